@@ -5,6 +5,6 @@ mod sockets;
 #[cfg(feature = "tokio-support")]
 mod tokio_sockets;
 
-pub use sockets::{BoundSocket, Socket};
+pub use sockets::{BoundSocket, Socket, Statistics};
 #[cfg(feature = "tokio-support")]
 pub use tokio_sockets::AsyncBoundSocket;
@@ -23,6 +23,12 @@ pub(crate) const SIOCGIFINDEX: libc::c_ulong = 0x8933;
 pub(crate) const SOL_PACKET: libc::c_int = 263;
 pub(crate) const PACKET_ADD_MEMBERSHIP: libc::c_int = 1;
 pub(crate) const PACKET_DROP_MEMBERSHIP: libc::c_int = 2;
+pub(crate) const PACKET_STATISTICS: libc::c_int = 6;
+pub(crate) const PACKET_FANOUT: libc::c_int = 18;
+
+pub(crate) const PACKET_FANOUT_HASH: libc::c_int = 0;
+/// Reassembles IP fragments before hashing, so every fragment lands on the same socket.
+pub(crate) const PACKET_FANOUT_FLAG_DEFRAG: libc::c_int = 0x8000;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -60,6 +66,12 @@ pub(crate) struct ifreq {
     pub(crate) ifr_ifru: ifru,
 }
 
+#[repr(C)]
+pub(crate) struct tpacket_stats {
+    pub(crate) tp_packets: libc::c_uint,
+    pub(crate) tp_drops: libc::c_uint,
+}
+
 #[repr(C)]
 pub(crate) struct tpacket_req {
     tp_block_size: libc::c_uint,
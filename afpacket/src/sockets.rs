@@ -6,7 +6,7 @@ use std::{
     ffi::CStr,
     io::{self, Read, Write},
     mem::{self, MaybeUninit},
-    os::unix::io::RawFd,
+    os::unix::io::{AsRawFd, IntoRawFd, RawFd},
     ptr,
 };
 
@@ -20,6 +20,17 @@ pub struct Addr {
     _len: libc::socklen_t,
 }
 
+/// What the kernel counted on a socket since its statistics were last read, as
+/// `BoundSocket::statistics` returns them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Statistics {
+    /// Frames the socket received, including those dropped.
+    pub packets: u32,
+    /// Frames dropped because the socket's receive buffer was full, as when the reader can't keep
+    /// up with the interface.
+    pub drops: u32,
+}
+
 /// Represents an unbound `AF_PACKET` socket.  At this phase of a socket's lifecycle, it can be
 /// configured.
 pub struct Socket {
@@ -186,6 +197,63 @@ impl BoundSocket {
         Ok(())
     }
 
+    /// Joins the `PACKET_FANOUT_HASH` fanout group `group_id`, creating it if this is the first
+    /// socket to join. The kernel then spreads the frames the interface receives over the sockets
+    /// of the group by a hash of their flow, so each socket sees whole flows, and sockets read on
+    /// different cores never hand frames to one another. IP fragments are reassembled before they
+    /// are hashed.
+    ///
+    /// Every socket of a group must be bound to the same interface, and group ids are shared by
+    /// every process on the host.
+    pub fn join_fanout(&mut self, group_id: u16) -> io::Result<()> {
+        // This block is unsafe because it uses FFI. We believe it to be safe, as setsockopt only
+        // reads the Rust-owned integer it is given the size of.
+        unsafe {
+            // Resources:
+            // man 7 packet regarding PACKET_FANOUT
+            let mode = linux::PACKET_FANOUT_HASH | linux::PACKET_FANOUT_FLAG_DEFRAG;
+            let arg: libc::c_int = libc::c_int::from(group_id) | (mode << 16);
+            let err = libc::setsockopt(
+                self.fd,
+                linux::SOL_PACKET,
+                linux::PACKET_FANOUT,
+                &arg as *const _ as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as u32,
+            );
+            if err < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the statistics of the socket. The kernel resets them as they are read, so each call
+    /// returns what was counted since the last, and whoever keeps totals must add them up.
+    pub fn statistics(&mut self) -> io::Result<Statistics> {
+        // This block is unsafe because it uses FFI. We believe it to be safe, as getsockopt only
+        // writes as many bytes as it is told the Rust-owned struct has.
+        unsafe {
+            // Resources:
+            // man 7 packet regarding PACKET_STATISTICS
+            let mut stats: linux::tpacket_stats = MaybeUninit::zeroed().assume_init();
+            let mut len = mem::size_of::<linux::tpacket_stats>() as libc::socklen_t;
+            let err = libc::getsockopt(
+                self.fd,
+                linux::SOL_PACKET,
+                linux::PACKET_STATISTICS,
+                &mut stats as *mut _ as *mut libc::c_void,
+                &mut len,
+            );
+            if err < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Statistics {
+                packets: stats.tp_packets,
+                drops: stats.tp_drops,
+            })
+        }
+    }
+
     /// Sends a frame to the NIC.
     pub fn send(&mut self, frame: &[u8]) -> io::Result<usize> {
         // This block is marked as unsafe because it uses FFI. We believe this code to be safe,
@@ -242,6 +310,56 @@ impl BoundSocket {
     }
 }
 
+impl BoundSocket {
+    /// Takes ownership of a socket that was bound elsewhere, such as one handed over by the
+    /// process this one replaces. The interface it is bound to is asked of the socket itself.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open, bound `AF_PACKET` socket, and not owned by anything else, as the
+    /// `BoundSocket` closes it when dropped.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> io::Result<Self> {
+        // Resources:
+        // man 2 getsockname
+        // man 7 packet regarding sockaddr_ll
+        let mut ll: libc::sockaddr_ll = MaybeUninit::zeroed().assume_init();
+        let mut len = mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+        let err = libc::getsockname(fd, &mut ll as *mut _ as *mut libc::sockaddr, &mut len);
+        if err < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if i32::from(ll.sll_family) != libc::AF_PACKET {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not an AF_PACKET socket",
+            ));
+        }
+        // Only the index of the interface is used once the socket is bound.
+        let mut iface: linux::ifreq = MaybeUninit::zeroed().assume_init();
+        iface.ifr_ifru.ifru_ivalue = ll.sll_ifindex;
+        Ok(BoundSocket {
+            fd,
+            iface,
+            send_addr: ll,
+        })
+    }
+}
+
+impl AsRawFd for BoundSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl IntoRawFd for BoundSocket {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.fd;
+        // As in `Socket::bind`, the descriptor now belongs to the caller, and mustn't be closed.
+        mem::forget(self);
+        fd
+    }
+}
+
 impl Read for BoundSocket {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self.recv(buf) {
@@ -16,10 +16,22 @@ impl AsyncBoundSocket {
         })
     }
 
+    /// Drives a socket that was bound elsewhere, such as one handed over by the process this one
+    /// replaces, which must not block.
+    pub fn from_socket(sock: sockets::BoundSocket) -> io::Result<Self> {
+        Ok(Self {
+            sock: PollEvented::new(sock)?,
+        })
+    }
+
     pub fn set_promiscuous(&mut self, p: bool) -> io::Result<()> {
         self.sock.get_mut().set_promiscuous(p)
     }
 
+    pub fn join_fanout(&mut self, group_id: u16) -> io::Result<()> {
+        self.sock.get_mut().join_fanout(group_id)
+    }
+
     pub async fn send(&mut self, frame: &[u8]) -> io::Result<usize> {
         self.sock.write(frame).await
     }
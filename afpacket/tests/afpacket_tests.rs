@@ -64,3 +64,74 @@ fn layer2_loopback() {
 
     thread_b.join().unwrap();
 }
+
+#[test]
+#[ignore]
+fn rebuilt_from_raw_fd() {
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+    let iface_name = CString::new("lo").unwrap();
+    let socket = afpacket::Socket::new().unwrap().bind(&iface_name).unwrap();
+    let fd = socket.into_raw_fd();
+
+    let mut socket = unsafe { afpacket::BoundSocket::from_raw_fd(fd) }.unwrap();
+    assert_eq!(socket.as_raw_fd(), fd);
+    let frame = vec![0xff; 64];
+    assert_eq!(socket.send(&frame).unwrap(), frame.len());
+}
+
+#[test]
+#[ignore]
+fn counts_received_frames() {
+    let iface_name = CString::new("lo").unwrap();
+    let mut sender = afpacket::Socket::new().unwrap().bind(&iface_name).unwrap();
+    let mut receiver = afpacket::Socket::new().unwrap().bind(&iface_name).unwrap();
+    // Anything else on the loopback so far is left behind.
+    receiver.statistics().unwrap();
+
+    let frame = vec![0xff; 64];
+    sender.send(&frame).unwrap();
+    let mut in_buffer = vec![0; 1500];
+    receiver.recv(&mut in_buffer).unwrap();
+    let statistics = receiver.statistics().unwrap();
+    assert!(statistics.packets >= 1);
+    assert_eq!(statistics.drops, 0);
+}
+
+#[test]
+#[ignore]
+fn fanout_group_splits_frames() {
+    let iface_name = CString::new("lo").unwrap();
+    let mut sender = afpacket::Socket::new().unwrap().bind(&iface_name).unwrap();
+    let mut members: Vec<afpacket::BoundSocket> = (0..2)
+        .map(|_| {
+            let mut socket = afpacket::Socket::new().unwrap();
+            socket.set_nonblocking(true).unwrap();
+            let mut socket = socket.bind(&iface_name).unwrap();
+            socket.join_fanout(0x5253).unwrap();
+            socket
+        })
+        .collect();
+
+    let frame = vec![0xff; 64];
+    sender.send(&frame).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    // The loopback may deliver the frame twice, as it is sent and as it is received, but always
+    // to the same member of the group, as both copies hash alike.
+    let mut in_buffer = vec![0; 1500];
+    let receivers = members
+        .iter_mut()
+        .map(|member| {
+            let mut received = 0;
+            while let Ok((len, _)) = member.recv(&mut in_buffer) {
+                if in_buffer[..len] == frame[..] {
+                    received += 1;
+                }
+            }
+            received
+        })
+        .filter(|received| *received > 0)
+        .count();
+    assert_eq!(receivers, 1);
+}
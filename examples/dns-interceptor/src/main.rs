@@ -1,12 +1,16 @@
 use crate::packets::SimplePacket;
 use crate::packets::{Interface, IpAndPort};
 use crossbeam::crossbeam_channel;
+use route_rs_bench::CountingAllocator;
 use route_rs_runtime::pipeline::Runner;
 
 mod packets;
 mod pipeline;
 mod processors;
 
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
 fn main() {
     let (input_sender, input_receiver) = crossbeam_channel::unbounded();
     let (output_sender, output_receiver) = crossbeam_channel::unbounded();
@@ -49,6 +53,20 @@ fn main() {
         ),
     ];
 
+    if let Some(packets) = route_rs_bench::self_test_arg() {
+        let packets = input_packets
+            .iter()
+            .cloned()
+            .cycle()
+            .take(packets)
+            .collect();
+        println!(
+            "{}",
+            route_rs_bench::self_test::<pipeline::Pipeline>("dns-interceptor", packets)
+        );
+        return;
+    }
+
     for p in input_packets {
         match input_sender.send(p.clone()) {
             Ok(_) => println!("Sent {:?}", p),
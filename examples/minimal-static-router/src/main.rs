@@ -1,9 +1,13 @@
 use route_rs_packets::EthernetFrame;
 use route_rs_runtime::link::{
+    composite::DropLink,
+    event::DropReason,
     primitive::{ClassifyLink, JoinLink, ProcessLink},
-    Link, LinkBuilder, PacketStream, ProcessLinkBuilder,
+    utils::drain::Drain,
+    Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder,
 };
-use route_rs_runtime::utils::{runner::runner, test::packet_generators::immediate_stream};
+use route_rs_runtime::pipeline::run_with_channels;
+use tokio::runtime;
 
 mod classifiers;
 mod processors;
@@ -22,34 +26,33 @@ fn main() {
     let test_frame1 = EthernetFrame::from_buffer(data_v4, 0).unwrap();
     let test_frame2 = EthernetFrame::from_buffer(data_v6, 0).unwrap();
 
-    let results = runner(router_runner);
+    let (input, input_channel) = crossbeam::crossbeam_channel::unbounded();
+    input.send(test_frame1.clone()).unwrap();
+    input.send(test_frame2.clone()).unwrap();
+    drop(input);
+    let (output_channels, interfaces): (Vec<_>, Vec<_>) = (0..3)
+        .map(|_| crossbeam::crossbeam_channel::unbounded())
+        .unzip();
+
+    let mut runtime = runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    run_with_channels(
+        &mut runtime,
+        Router::new(),
+        vec![input_channel],
+        output_channels,
+    );
     println!("It finished!");
 
+    let results: Vec<Vec<EthernetFrame>> = interfaces.iter().map(|i| i.iter().collect()).collect();
     assert_eq!(results[1][0], test_frame1);
     assert_eq!(results[2][0], test_frame2);
     println!("Got all packets on the expected interface!");
 }
 
-fn router_runner() -> Link<EthernetFrame> {
-    let data_v4: Vec<u8> = vec![
-        0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 8, 00, 0x45, 0, 0, 20, 0, 0, 0, 0,
-        64, 17, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
-    ];
-    let data_v6: Vec<u8> = vec![
-        0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x86, 0xDD, 0x60, 0, 0, 0, 0, 4, 17,
-        64, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad,
-        0xbe, 0xef, 0x20, 0x01, 0x0d, 0xb8, 0xbe, 0xef, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xa, 0xb,
-        0xc, 0xd,
-    ];
-    let frame1 = EthernetFrame::from_buffer(data_v4, 0).unwrap();
-    let frame2 = EthernetFrame::from_buffer(data_v6, 0).unwrap();
-    let packets = vec![frame1, frame2];
-    // Create our router
-    Router::new()
-        .ingressors(vec![immediate_stream(packets)])
-        .build_link()
-}
-
 // Note that Router is not Generic! This router only takes in EthernetFrames
 #[derive(Default)]
 pub struct Router {
@@ -65,34 +68,42 @@ impl Router {
 // Then we declare it is a link that take in EthernetFrames and outputs EthernetFrames
 // LinkBuilder is always generic, so we need to fill out EthernetFrame
 impl LinkBuilder<EthernetFrame, EthernetFrame> for Router {
-    fn ingressors(self, in_streams: Vec<PacketStream<EthernetFrame>>) -> Self {
-        assert!(
-            in_streams.len() == 1,
-            "Support only one input interface for now"
-        );
+    fn try_ingressors(
+        self,
+        in_streams: Vec<PacketStream<EthernetFrame>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "Support only one input interface for now",
+            ));
+        }
 
         if self.in_streams.is_some() {
-            panic!("Only support one input interface")
+            return Err(LinkBuildError::Ingressors(
+                "Only support one input interface",
+            ));
         }
 
-        Router {
+        Ok(Router {
             in_streams: Some(in_streams),
-        }
+        })
     }
 
-    fn ingressor(self, in_stream: PacketStream<EthernetFrame>) -> Self {
+    fn try_ingressor(self, in_stream: PacketStream<EthernetFrame>) -> Result<Self, LinkBuildError> {
         if self.in_streams.is_some() {
-            panic!("Only support one input interface")
+            return Err(LinkBuildError::Ingressors(
+                "Only support one input interface",
+            ));
         }
 
-        Router {
+        Ok(Router {
             in_streams: Some(vec![in_stream]),
-        }
+        })
     }
 
-    fn build_link(self) -> Link<EthernetFrame> {
+    fn try_build_link(self) -> Result<Link<EthernetFrame>, LinkBuildError> {
         if self.in_streams.is_none() {
-            panic!("Can not build link, missing input stream");
+            Err(LinkBuildError::Missing("input stream"))
         } else {
             // TODO: Build the router here
 
@@ -102,6 +113,7 @@ impl LinkBuilder<EthernetFrame, EthernetFrame> for Router {
             //               \                                          /--encap      \--Join--Interface 2
             //               \--Ipv6Dencap--Ipv6SubnetRouter(Classifier)<--encap
             //                                                          \--encap
+            //               \--Drop (neither IPv4 nor IPv6)
 
             //return an empty thing for now so it compiles.
             let mut all_runnables = vec![];
@@ -113,16 +125,24 @@ impl LinkBuilder<EthernetFrame, EthernetFrame> for Router {
 
             let (mut classify_runables, mut classify_egressors) = ClassifyLink::new()
                 .ingressors(self.in_streams.unwrap())
-                .num_egressors(2)
+                .num_egressors(3)
                 .classifier(classifiers::ClassifyIP)
                 .dispatcher(Box::new(|c| match c {
                     classifiers::ClassifyIPType::IPv4 => 0,
                     classifiers::ClassifyIPType::IPv6 => 1,
-                    classifiers::ClassifyIPType::None => 1, // we can't drop packets in a classify. Maybe we do need
-                })) // the DropLink back?
+                    classifiers::ClassifyIPType::None => 2,
+                }))
                 .build_link();
             all_runnables.append(&mut classify_runables);
 
+            // Frames that are neither IPv4 nor IPv6 end here. Nothing reads the DropLink's
+            // egressor, so it is drained to keep the classifier from filling its queue.
+            let (_, mut unclassified_egressors) = DropLink::new()
+                .ingressor(classify_egressors.remove(2))
+                .reason(DropReason::Unclassified)
+                .build_link();
+            all_runnables.push(Box::new(Drain::new(unclassified_egressors.remove(0))));
+
             //------------Ipv4 Subnet router--------------//
 
             // Reminder that process links don't have any runnables, so we can ignore that half of the tuple
@@ -219,7 +239,7 @@ impl LinkBuilder<EthernetFrame, EthernetFrame> for Router {
             interfaces.append(&mut interface2);
 
             //---------Return built Link!--------------//
-            (all_runnables, interfaces)
+            Ok((all_runnables, interfaces))
         }
     }
 }
@@ -1,11 +1,24 @@
 use crate::packets::IntegerPacket;
 use crossbeam::crossbeam_channel;
+use route_rs_bench::CountingAllocator;
 use route_rs_runtime::pipeline::Runner;
 
 mod packets;
 mod pipeline;
 
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
 fn main() {
+    if let Some(packets) = route_rs_bench::self_test_arg() {
+        let packets = (0..packets as u32).map(|id| IntegerPacket { id }).collect();
+        println!(
+            "{}",
+            route_rs_bench::self_test::<pipeline::Pipeline>("trivial-identity", packets)
+        );
+        return;
+    }
+
     let (input_sender, input_receiver) = crossbeam_channel::unbounded();
     let (output_sender, output_receiver) = crossbeam_channel::unbounded();
 
@@ -0,0 +1,104 @@
+//! Throughput, latency and allocations of each primitive link, and the latency of those that
+//! queue under a steady offered load, run with `cargo bench -p route-rs-bench`.
+
+use route_rs_bench::{CountingAllocator, LatencyBench, LinkBench};
+use route_rs_runtime::classifier::Even;
+use route_rs_runtime::link::primitive::{
+    ClassifyLink, FlowSchedulerLink, ForkLink, JoinLink, ProcessLink, QueueLink,
+};
+use route_rs_runtime::link::{LinkBuilder, PacketStream, ProcessLinkBuilder};
+use route_rs_runtime::processor::Identity;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const PACKETS: usize = 1_000_000;
+
+fn main() {
+    let process = LinkBench::new("ProcessLink").packets(PACKETS).run(
+        |input| {
+            ProcessLink::new()
+                .ingressor(input)
+                .processor(Identity::new())
+        },
+        |seq| seq as u64,
+    );
+    println!("{}", process);
+
+    let queue = LinkBench::new("QueueLink").packets(PACKETS).run(
+        |input| {
+            QueueLink::new()
+                .ingressor(input)
+                .processor(Identity::new())
+                .queue_capacity(256)
+        },
+        |seq| seq as u64,
+    );
+    println!("{}", queue);
+
+    let classify = LinkBench::new("ClassifyLink").packets(PACKETS).run(
+        |input| {
+            ClassifyLink::new()
+                .ingressor(input)
+                .classifier(Even::new())
+                .dispatcher(Box::new(|even| if even { 0 } else { 1 }))
+                .queue_capacity(256)
+                .num_egressors(2)
+        },
+        |seq| seq as i32,
+    );
+    println!("{}", classify);
+
+    // Joins the bench's input with a second stream of as many packets.
+    let join = LinkBench::new("JoinLink").packets(PACKETS).run(
+        |input| {
+            let other: PacketStream<u64> = Box::new(futures::stream::iter(0..PACKETS as u64));
+            JoinLink::new()
+                .ingressors(vec![input, other])
+                .queue_capacity(256)
+        },
+        |seq| seq as u64,
+    );
+    println!("{}", join);
+
+    let fork = LinkBench::new("ForkLink").packets(PACKETS).run(
+        |input| {
+            ForkLink::new()
+                .ingressor(input)
+                .num_egressors(2)
+                .queue_capacity(256)
+        },
+        |seq| seq as u64,
+    );
+    println!("{}", fork);
+
+    // Offered well under what either drains, so what queueing there is comes of scheduling.
+    let queue = LatencyBench::new("QueueLink at 1 Mpps")
+        .packets(PACKETS)
+        .rate(1_000_000)
+        .run(
+            |input| {
+                QueueLink::new()
+                    .ingressor(input)
+                    .processor(Identity::new())
+                    .queue_capacity(256)
+            },
+            |seq| seq as u64,
+            |seq| *seq as usize,
+        );
+    println!("{}", queue);
+
+    let flow_scheduler = LatencyBench::new("FlowSchedulerLink at 1 Mpps")
+        .packets(PACKETS)
+        .rate(1_000_000)
+        .run(
+            |input| {
+                FlowSchedulerLink::new()
+                    .ingressor(input)
+                    .flow_key(|seq: &u64| seq % 16)
+            },
+            |seq| seq as u64,
+            |seq| *seq as usize,
+        );
+    println!("{}", flow_scheduler);
+}
@@ -0,0 +1,213 @@
+use crate::alloc::CountingAllocator;
+use crate::report::{Latency, Report};
+use crossbeam::crossbeam_channel::{self, Sender};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_runtime::link::{LinkBuilder, PacketStream};
+use std::pin::Pin;
+use std::time::Instant;
+use std::vec;
+use tokio::runtime;
+
+/// Drives a link with generated traffic, as fast as it will take it.
+///
+/// Packets are generated before the clock starts, and taken from the input stream as fast as the
+/// link pulls them. The time each is taken, and the time each leaves an egressor, give the latency
+/// of links with a single egressor that neither drop nor reorder packets.
+pub struct LinkBench {
+    name: String,
+    packets: usize,
+    core_threads: Option<usize>,
+}
+
+impl LinkBench {
+    pub fn new(name: &str) -> Self {
+        LinkBench {
+            name: String::from(name),
+            packets: 1_000_000,
+            core_threads: None,
+        }
+    }
+
+    /// Number of packets to send through the link, defaults to 1000000.
+    pub fn packets(self, packets: usize) -> Self {
+        assert!(packets > 0, "packets must be > 0");
+        LinkBench {
+            name: self.name,
+            packets,
+            core_threads: self.core_threads,
+        }
+    }
+
+    /// Threads of the Tokio runtime running the link, defaults to one per core.
+    pub fn core_threads(self, core_threads: usize) -> Self {
+        assert!(core_threads > 0, "core_threads must be > 0");
+        LinkBench {
+            name: self.name,
+            packets: self.packets,
+            core_threads: Some(core_threads),
+        }
+    }
+
+    /// Builds the link with `build`, given a stream of the packets `generate` makes of each
+    /// sequence number, and runs it until all its egressors finish.
+    pub fn run<Input, Output, L, B, G>(self, build: B, generate: G) -> Report
+    where
+        Input: Send + 'static,
+        Output: Send + 'static,
+        L: LinkBuilder<Input, Output>,
+        B: FnOnce(PacketStream<Input>) -> L,
+        G: FnMut(usize) -> Input,
+    {
+        let packets: Vec<Input> = (0..self.packets).map(generate).collect();
+        let (stamps_sender, stamps_receiver) = crossbeam_channel::bounded(1);
+        let input = StampedStream {
+            packets: packets.into_iter(),
+            stamps: Vec::with_capacity(self.packets),
+            done: Some(stamps_sender),
+        };
+        let (runnables, egressors) = build(Box::new(input)).build_link();
+
+        let mut builder = runtime::Builder::new();
+        builder.threaded_scheduler().enable_all();
+        if let Some(core_threads) = self.core_threads {
+            builder.core_threads(core_threads);
+        }
+        let mut runtime = builder.build().unwrap();
+
+        let allocations = CountingAllocator::allocations();
+        let start = Instant::now();
+        let egress_stamps: Vec<Vec<Instant>> = runtime.block_on(async {
+            for runnable in runnables {
+                tokio::spawn(runnable);
+            }
+            let collectors: Vec<_> = egressors
+                .into_iter()
+                .map(|egressor| {
+                    tokio::spawn(egressor.fold(vec![], |mut stamps, _| {
+                        stamps.push(Instant::now());
+                        future::ready(stamps)
+                    }))
+                })
+                .collect();
+            let mut egress_stamps = vec![];
+            for collector in collectors {
+                egress_stamps.push(collector.await.unwrap());
+            }
+            egress_stamps
+        });
+        let elapsed = start.elapsed();
+        let allocations = CountingAllocator::allocations()
+            .and_then(|after| allocations.map(|before| after - before));
+
+        let ingress_stamps = stamps_receiver.recv().unwrap_or_default();
+        let latency = match egress_stamps.as_slice() {
+            [egress_stamps] if egress_stamps.len() == ingress_stamps.len() => {
+                Latency::from_samples(
+                    ingress_stamps
+                        .iter()
+                        .zip(egress_stamps)
+                        .map(|(ingress, egress)| egress.saturating_duration_since(*ingress))
+                        .collect(),
+                )
+            }
+            _ => None,
+        };
+
+        Report {
+            name: self.name,
+            packets_in: ingress_stamps.len() as u64,
+            packets_out: egress_stamps.iter().map(|stamps| stamps.len() as u64).sum(),
+            elapsed,
+            latency,
+            allocations,
+        }
+    }
+}
+
+/// Yields pregenerated packets, noting when each was taken. The times are handed over once the
+/// stream ends.
+struct StampedStream<Packet> {
+    packets: vec::IntoIter<Packet>,
+    stamps: Vec<Instant>,
+    done: Option<Sender<Vec<Instant>>>,
+}
+
+impl<Packet> Unpin for StampedStream<Packet> {}
+
+impl<Packet> Stream for StampedStream<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+        match self.packets.next() {
+            Some(packet) => {
+                self.stamps.push(Instant::now());
+                Poll::Ready(Some(packet))
+            }
+            None => {
+                if let Some(done) = self.done.take() {
+                    let stamps = std::mem::take(&mut self.stamps);
+                    let _ = done.send(stamps);
+                }
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_runtime::classifier::Even;
+    use route_rs_runtime::link::primitive::{ClassifyLink, ProcessLink, QueueLink};
+    use route_rs_runtime::link::ProcessLinkBuilder;
+    use route_rs_runtime::processor::Identity;
+
+    #[test]
+    fn measures_fifo_links() {
+        let report = LinkBench::new("QueueLink")
+            .packets(1000)
+            .core_threads(2)
+            .run(
+                |input| QueueLink::new().ingressor(input).processor(Identity::new()),
+                |seq| seq as u32,
+            );
+
+        assert_eq!(report.name, "QueueLink");
+        assert_eq!((report.packets_in, report.packets_out), (1000, 1000));
+        assert!(report.latency.is_some());
+        // The tests don't install the counting allocator.
+        assert_eq!(report.allocations, None);
+    }
+
+    #[test]
+    fn counts_every_egressor() {
+        let report = LinkBench::new("ClassifyLink").packets(1000).run(
+            |input| {
+                ClassifyLink::new()
+                    .ingressor(input)
+                    .classifier(Even::new())
+                    .dispatcher(Box::new(|even| if even { 0 } else { 1 }))
+                    .num_egressors(2)
+            },
+            |seq| seq as i32,
+        );
+
+        assert_eq!((report.packets_in, report.packets_out), (1000, 1000));
+        assert_eq!(report.latency, None);
+    }
+
+    #[test]
+    fn runs_links_without_tasks() {
+        let report = LinkBench::new("ProcessLink").packets(10).run(
+            |input| {
+                ProcessLink::new()
+                    .ingressor(input)
+                    .processor(Identity::new())
+            },
+            |seq| seq,
+        );
+
+        assert_eq!(report.packets_out, 10);
+    }
+}
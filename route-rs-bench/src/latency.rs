@@ -0,0 +1,353 @@
+use crate::alloc::CountingAllocator;
+use crate::report::{Latency, Report};
+use crossbeam::crossbeam_channel;
+use futures::prelude::*;
+use futures::ready;
+use futures::task::{Context, Poll};
+use route_rs_runtime::link::{LinkBuilder, PacketStream};
+use route_rs_runtime::pipeline::Runner;
+use std::pin::Pin;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::vec;
+use tokio::runtime;
+use tokio::time::{delay_until, Delay};
+
+/// Offers a link, or a whole pipeline, traffic at a steady rate, and measures how long each packet
+/// takes to get through it, to check that links that queue, such as schedulers and AQM, keep delay
+/// down under load, where `LinkBench` only tells how fast a link drains.
+///
+/// Packet `seq` is due `seq / rate` seconds after the start, and its latency runs from then until
+/// it leaves, so time spent waiting on a link that has stopped taking packets counts, as time in a
+/// NIC's receive queue would. Packets that leave are told apart by `identify`, which gives the
+/// sequence number each was generated from, so links that drop, reorder or have several egressors
+/// are measured too. A packet's latency is that of its first copy to leave, and jitter is between
+/// packets in the order they were sent.
+///
+/// Tokio's timer wakes a link's input at most once a millisecond, so above 1000 packets per second
+/// each millisecond's packets are sent together at its end, and latency has a floor of up to a
+/// millisecond. Compare links at the same rate, rather than reading latency as absolute.
+pub struct LatencyBench {
+    name: String,
+    packets: usize,
+    rate: u64,
+    core_threads: Option<usize>,
+}
+
+impl LatencyBench {
+    pub fn new(name: &str) -> Self {
+        LatencyBench {
+            name: String::from(name),
+            packets: 100_000,
+            rate: 100_000,
+            core_threads: None,
+        }
+    }
+
+    /// Number of packets to send, defaults to 100000.
+    pub fn packets(self, packets: usize) -> Self {
+        assert!(packets > 0, "packets must be > 0");
+        LatencyBench {
+            name: self.name,
+            packets,
+            rate: self.rate,
+            core_threads: self.core_threads,
+        }
+    }
+
+    /// Packets per second offered, defaults to 100000.
+    pub fn rate(self, rate: u64) -> Self {
+        assert!(rate > 0, "rate must be > 0");
+        LatencyBench {
+            name: self.name,
+            packets: self.packets,
+            rate,
+            core_threads: self.core_threads,
+        }
+    }
+
+    /// Threads of the Tokio runtime running a link, defaults to one per core.
+    pub fn core_threads(self, core_threads: usize) -> Self {
+        assert!(core_threads > 0, "core_threads must be > 0");
+        LatencyBench {
+            name: self.name,
+            packets: self.packets,
+            rate: self.rate,
+            core_threads: Some(core_threads),
+        }
+    }
+
+    /// Builds the link with `build`, given a stream of the packets `generate` makes of each
+    /// sequence number, sent at the rate, and runs it until all its egressors finish.
+    pub fn run<Input, Output, L, B, G, I>(self, build: B, generate: G, identify: I) -> Report
+    where
+        Input: Send + 'static,
+        Output: Send + 'static,
+        L: LinkBuilder<Input, Output>,
+        B: FnOnce(PacketStream<Input>) -> L,
+        G: FnMut(usize) -> Input,
+        I: FnMut(&Output) -> usize,
+    {
+        let packets: Vec<Input> = (0..self.packets).map(generate).collect();
+        let mut builder = runtime::Builder::new();
+        builder.threaded_scheduler().enable_all();
+        if let Some(core_threads) = self.core_threads {
+            builder.core_threads(core_threads);
+        }
+        let mut runtime = builder.build().unwrap();
+
+        let allocations = CountingAllocator::allocations();
+        let start = Instant::now();
+        let input = PacedStream {
+            packets: packets.into_iter(),
+            start,
+            rate: self.rate,
+            sent: 0,
+            delay: None,
+        };
+        let (runnables, egressors) = build(Box::new(input)).build_link();
+        let egressed: Vec<Vec<(Instant, Output)>> = runtime.block_on(async {
+            for runnable in runnables {
+                tokio::spawn(runnable);
+            }
+            let collectors: Vec<_> = egressors
+                .into_iter()
+                .map(|egressor| {
+                    tokio::spawn(egressor.fold(vec![], |mut egressed, packet| {
+                        egressed.push((Instant::now(), packet));
+                        future::ready(egressed)
+                    }))
+                })
+                .collect();
+            let mut egressed = vec![];
+            for collector in collectors {
+                egressed.push(collector.await.unwrap());
+            }
+            egressed
+        });
+        let elapsed = start.elapsed();
+        let allocations = CountingAllocator::allocations()
+            .and_then(|after| allocations.map(|before| after - before));
+
+        Report {
+            name: self.name.clone(),
+            packets_in: self.packets as u64,
+            packets_out: egressed.iter().map(|packets| packets.len() as u64).sum(),
+            elapsed,
+            latency: self.latency(start, egressed.iter().flatten(), identify),
+            allocations,
+        }
+    }
+
+    /// Sends the packets `generate` makes of each sequence number into a router's pipeline at the
+    /// rate, and runs it until it has drained, as a router can to check itself where it runs.
+    pub fn run_pipeline<R, G, I>(self, generate: G, identify: I) -> Report
+    where
+        R: Runner,
+        R::Input: Send + 'static,
+        R::Output: Send + 'static,
+        G: FnMut(usize) -> R::Input,
+        I: FnMut(&R::Output) -> usize,
+    {
+        let packets: Vec<R::Input> = (0..self.packets).map(generate).collect();
+        let (input_sender, input_receiver) = crossbeam_channel::unbounded();
+        let (output_sender, output_receiver) = crossbeam_channel::unbounded();
+
+        let allocations = CountingAllocator::allocations();
+        let start = Instant::now();
+        let rate = self.rate;
+        let sender = thread::spawn(move || {
+            for (seq, packet) in packets.into_iter().enumerate() {
+                let due = start + due_after(seq, rate);
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+                if input_sender.send(packet).is_err() {
+                    return;
+                }
+            }
+        });
+        let receiver = thread::spawn(move || {
+            output_receiver
+                .iter()
+                .map(|packet| (Instant::now(), packet))
+                .collect::<Vec<_>>()
+        });
+        R::run(input_receiver, output_sender);
+        sender.join().unwrap();
+        let egressed = receiver.join().unwrap();
+        let elapsed = start.elapsed();
+        let allocations = CountingAllocator::allocations()
+            .and_then(|after| allocations.map(|before| after - before));
+
+        Report {
+            name: self.name.clone(),
+            packets_in: self.packets as u64,
+            packets_out: egressed.len() as u64,
+            elapsed,
+            latency: self.latency(start, egressed.iter(), identify),
+            allocations,
+        }
+    }
+
+    /// The latency of the first copy of each packet that left, in the order they were sent.
+    fn latency<'a, Output: 'a, I>(
+        &self,
+        start: Instant,
+        egressed: impl Iterator<Item = &'a (Instant, Output)>,
+        mut identify: I,
+    ) -> Option<Latency>
+    where
+        I: FnMut(&Output) -> usize,
+    {
+        let mut first: Vec<Option<Instant>> = vec![None; self.packets];
+        for (at, packet) in egressed {
+            let seq = identify(packet);
+            assert!(
+                seq < self.packets,
+                "identify gave {} for a packet, but only {} were sent",
+                seq,
+                self.packets
+            );
+            first[seq] = Some(first[seq].map_or(*at, |first| first.min(*at)));
+        }
+        Latency::from_samples(
+            first
+                .into_iter()
+                .enumerate()
+                .filter_map(|(seq, at)| {
+                    at.map(|at| at.saturating_duration_since(start + due_after(seq, self.rate)))
+                })
+                .collect(),
+        )
+    }
+}
+
+/// When packet `seq` is due, from the start, at `rate` packets per second.
+fn due_after(seq: usize, rate: u64) -> Duration {
+    Duration::from_nanos((seq as u128 * 1_000_000_000 / u128::from(rate)) as u64)
+}
+
+/// Yields pregenerated packets, each no sooner than it is due.
+struct PacedStream<Packet> {
+    packets: vec::IntoIter<Packet>,
+    start: Instant,
+    rate: u64,
+    sent: usize,
+    delay: Option<Delay>,
+}
+
+impl<Packet> Unpin for PacedStream<Packet> {}
+
+impl<Packet> Stream for PacedStream<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let due = tokio::time::Instant::from_std(self.start + due_after(self.sent, self.rate));
+        if due > tokio::time::Instant::now() {
+            let delay = self.delay.get_or_insert_with(|| delay_until(due));
+            if delay.deadline() != due {
+                delay.reset(due);
+            }
+            ready!(Pin::new(delay).poll(cx));
+        }
+        self.sent += 1;
+        Poll::Ready(self.packets.next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_runtime::classifier::Even;
+    use route_rs_runtime::link::primitive::{ClassifyLink, ProcessLink, QueueLink};
+    use route_rs_runtime::link::ProcessLinkBuilder;
+    use route_rs_runtime::processor::{FnProcessor, Identity};
+
+    #[test]
+    fn measures_under_offered_load() {
+        let report = LatencyBench::new("QueueLink")
+            .packets(200)
+            .rate(20_000)
+            .core_threads(2)
+            .run(
+                |input| QueueLink::new().ingressor(input).processor(Identity::new()),
+                |seq| seq,
+                |seq| *seq,
+            );
+
+        assert_eq!((report.packets_in, report.packets_out), (200, 200));
+        // The last packet isn't due until 199 / 20000 seconds in.
+        assert!(report.elapsed >= Duration::from_micros(9950));
+        let latency = report.latency.unwrap();
+        assert!(latency.p50 <= latency.p99 && latency.p99 <= latency.p999);
+        assert!(latency.p999 <= latency.max);
+    }
+
+    #[test]
+    fn measures_links_that_drop_or_split() {
+        let dropping = LatencyBench::new("ProcessLink")
+            .packets(100)
+            .rate(50_000)
+            .run(
+                |input| {
+                    ProcessLink::new()
+                        .ingressor(input)
+                        .processor(FnProcessor::new(|seq: usize| {
+                            if seq.is_multiple_of(4) {
+                                None
+                            } else {
+                                Some(seq)
+                            }
+                        }))
+                },
+                |seq| seq,
+                |seq| *seq,
+            );
+        assert_eq!(dropping.packets_out, 75);
+        assert!(dropping.latency.is_some());
+
+        let split = LatencyBench::new("ClassifyLink")
+            .packets(100)
+            .rate(50_000)
+            .run(
+                |input| {
+                    ClassifyLink::new()
+                        .ingressor(input)
+                        .classifier(Even::new())
+                        .dispatcher(Box::new(|even| if even { 0 } else { 1 }))
+                        .num_egressors(2)
+                },
+                |seq| seq as i32,
+                |seq| *seq as usize,
+            );
+        assert_eq!(split.packets_out, 100);
+        assert!(split.latency.is_some());
+    }
+
+    struct Echo;
+
+    impl Runner for Echo {
+        type Input = usize;
+        type Output = usize;
+
+        fn run(input: crossbeam::Receiver<usize>, output: crossbeam::Sender<usize>) {
+            for packet in input.iter() {
+                output.send(packet).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn measures_pipelines() {
+        let report = LatencyBench::new("Echo")
+            .packets(100)
+            .rate(10_000)
+            .run_pipeline::<Echo, _, _>(|seq| seq, |seq| *seq);
+
+        assert_eq!((report.packets_in, report.packets_out), (100, 100));
+        assert!(report.elapsed >= Duration::from_micros(9900));
+        assert!(report.latency.is_some());
+    }
+}
@@ -0,0 +1,33 @@
+//! Measures how fast links and whole pipelines move packets, to give performance work on the
+//! primitives a baseline.
+//!
+//! A `LinkBench` drives any `LinkBuilder` with generated traffic, and `self_test` runs a whole
+//! `Runner` pipeline, as the example routers do with `--self-test`. Both return a `Report` of the
+//! packet rate, latency percentiles where they can be told, and allocations if the binary counts
+//! them with `CountingAllocator`:
+//!
+//! ```text
+//! QueueLink: 1000000 packets in, 1000000 out in 41.2ms, 24.27 Mpps, latency p50 3.1µs p90 4.8µs p99 12.9µs p999 48.2µs max 1.2ms, jitter 1.4µs, 4 allocations (0.00 per packet)
+//! ```
+//!
+//! A `LatencyBench` offers a link, or a `Runner` pipeline, traffic at a steady rate instead, for
+//! the latency and jitter of links that should control delay under load, such as schedulers.
+//!
+//! A `Soak` drives a pipeline for minutes with churn of its own choosing, and reports the gauges,
+//! such as the resident set size or the entries of a table, that grew over the run.
+//!
+//! `cargo bench -p route-rs-bench` runs the fixtures over each primitive link.
+
+mod alloc;
+mod fixture;
+mod latency;
+mod report;
+mod self_test;
+mod soak;
+
+pub use self::alloc::CountingAllocator;
+pub use self::fixture::LinkBench;
+pub use self::latency::LatencyBench;
+pub use self::report::{Latency, Report};
+pub use self::self_test::{self_test, self_test_arg};
+pub use self::soak::{Leak, Soak, SoakReport, SoakSample};
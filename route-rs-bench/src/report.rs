@@ -0,0 +1,126 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Percentiles of the time packets took to get through a link, and its jitter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Latency {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+    /// The mean difference between the latencies of packets sent one after the other, their
+    /// inter-packet delay variation, RFC 5481.
+    pub jitter: Duration,
+}
+
+impl Latency {
+    /// Percentiles by nearest rank, or none without any samples, given in the order the packets
+    /// were sent.
+    pub fn from_samples(mut samples: Vec<Duration>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let variation: Duration = samples
+            .windows(2)
+            .map(|pair| pair[1].abs_diff(pair[0]))
+            .sum();
+        let jitter = variation / (samples.len() as u32 - 1).max(1);
+        samples.sort();
+        let permille = |p: usize| samples[(samples.len() * p).div_ceil(1000).max(1) - 1];
+        Some(Latency {
+            p50: permille(500),
+            p90: permille(900),
+            p99: permille(990),
+            p999: permille(999),
+            max: samples[samples.len() - 1],
+            jitter,
+        })
+    }
+}
+
+/// What a benchmark measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub name: String,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    pub elapsed: Duration,
+    /// Only known where packets leave in the order they came in, one out for each in.
+    pub latency: Option<Latency>,
+    /// Only known where the binary installs `CountingAllocator`.
+    pub allocations: Option<u64>,
+}
+
+impl Report {
+    /// Packets out per second.
+    pub fn pps(&self) -> f64 {
+        self.packets_out as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} packets in, {} out in {:?}, {:.2} Mpps",
+            self.name,
+            self.packets_in,
+            self.packets_out,
+            self.elapsed,
+            self.pps() / 1e6
+        )?;
+        if let Some(latency) = &self.latency {
+            write!(
+                f,
+                ", latency p50 {:?} p90 {:?} p99 {:?} p999 {:?} max {:?}, jitter {:?}",
+                latency.p50, latency.p90, latency.p99, latency.p999, latency.max, latency.jitter
+            )?;
+        }
+        if let Some(allocations) = self.allocations {
+            write!(
+                f,
+                ", {} allocations ({:.2} per packet)",
+                allocations,
+                allocations as f64 / self.packets_in.max(1) as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_by_nearest_rank() {
+        let samples = (1..=200).rev().map(Duration::from_micros).collect();
+        let latency = Latency::from_samples(samples).unwrap();
+
+        assert_eq!(latency.p50, Duration::from_micros(100));
+        assert_eq!(latency.p90, Duration::from_micros(180));
+        assert_eq!(latency.p99, Duration::from_micros(198));
+        assert_eq!(latency.p999, Duration::from_micros(200));
+        assert_eq!(latency.max, Duration::from_micros(200));
+        assert_eq!(latency.jitter, Duration::from_micros(1));
+        assert_eq!(Latency::from_samples(vec![]), None);
+    }
+
+    #[test]
+    fn displays_what_is_known() {
+        let report = Report {
+            name: String::from("QueueLink"),
+            packets_in: 2_000_000,
+            packets_out: 1_000_000,
+            elapsed: Duration::from_secs(2),
+            latency: None,
+            allocations: Some(4),
+        };
+
+        assert_eq!(
+            report.to_string(),
+            "QueueLink: 2000000 packets in, 1000000 out in 2s, 0.50 Mpps, 4 allocations (0.00 per packet)"
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use crate::alloc::CountingAllocator;
+use crate::report::Report;
+use crossbeam::crossbeam_channel;
+use route_rs_runtime::pipeline::Runner;
+use std::env;
+use std::time::Instant;
+
+/// Runs a router's pipeline over `packets`, as fast as it will take them, to measure its
+/// forwarding rate. Example routers run this when started with `--self-test`.
+///
+/// Every packet is queued on the input channel before the clock starts, and the input channel is
+/// closed, so the run ends once the pipeline has drained. Packets may leave the pipeline in any
+/// order, or not at all, so no latency is reported.
+pub fn self_test<R: Runner>(name: &str, packets: Vec<R::Input>) -> Report {
+    let (input_sender, input_receiver) = crossbeam_channel::unbounded();
+    let (output_sender, output_receiver) = crossbeam_channel::unbounded();
+    let packets_in = packets.len() as u64;
+    for packet in packets {
+        input_sender.send(packet).unwrap();
+    }
+    drop(input_sender);
+
+    let allocations = CountingAllocator::allocations();
+    let start = Instant::now();
+    R::run(input_receiver, output_sender);
+    let elapsed = start.elapsed();
+    let allocations =
+        CountingAllocator::allocations().and_then(|after| allocations.map(|before| after - before));
+
+    Report {
+        name: String::from(name),
+        packets_in,
+        packets_out: output_receiver.try_iter().count() as u64,
+        elapsed,
+        latency: None,
+        allocations,
+    }
+}
+
+/// The number of packets to self-test with, if the binary was started with `--self-test [N]`.
+/// Defaults to 1000000 when no number is given.
+pub fn self_test_arg() -> Option<usize> {
+    let mut args = env::args().skip_while(|arg| arg != "--self-test");
+    args.next()?;
+    match args.next() {
+        Some(packets) => Some(
+            packets
+                .parse()
+                .unwrap_or_else(|_| panic!("--self-test takes a packet count, not {}", packets)),
+        ),
+        None => Some(1_000_000),
+    }
+}
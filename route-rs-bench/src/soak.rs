@@ -0,0 +1,383 @@
+use route_rs_runtime::metrics::Registry;
+use std::fmt;
+use std::fs;
+use std::time::{Duration, Instant};
+
+type Gauge = Box<dyn FnMut() -> u64>;
+
+/// Drives a pipeline for minutes on end, sampling what it holds as it goes, to catch the leaks of
+/// stateful links, such as a NAT table or a reassembly buffer that never lets go of an entry,
+/// that only show once far more flows have come and gone than a unit test sends.
+///
+/// The pipeline is driven by the closure given to `run`, called in a loop with the number of the
+/// round, which should send some traffic and churn whatever the pipeline keeps state for: flows
+/// starting and stopping, or its configuration swapped with `PipelineManager::load`. Every
+/// `sample_every`, the soak samples the resident set size and open file descriptors of the
+/// process, each `gauge`, and the packets each link named in the global `Registry` has sent.
+///
+/// Once `warmup` has passed, so caches and tables have filled, a gauge has leaked if the least it
+/// reads over the last third of the samples is more than the most it read over the first third,
+/// as it is when it grows from one sample to the next however it wobbles. The resident set size
+/// is allowed `rss_slack` of growth, for the allocator settling. Link counters only ever grow,
+/// and are reported, but not checked.
+pub struct Soak {
+    name: String,
+    duration: Duration,
+    sample_every: Duration,
+    warmup: Duration,
+    rss_slack: u64,
+    gauges: Vec<(String, Gauge)>,
+}
+
+impl Soak {
+    pub fn new(name: &str) -> Self {
+        Soak {
+            name: String::from(name),
+            duration: Duration::from_secs(300),
+            sample_every: Duration::from_secs(5),
+            warmup: Duration::from_secs(30),
+            rss_slack: 4 << 20,
+            gauges: vec![],
+        }
+    }
+
+    /// How long to drive the pipeline for, defaults to 5 minutes.
+    pub fn duration(self, duration: Duration) -> Self {
+        Soak {
+            name: self.name,
+            duration,
+            sample_every: self.sample_every,
+            warmup: self.warmup,
+            rss_slack: self.rss_slack,
+            gauges: self.gauges,
+        }
+    }
+
+    /// How often to sample, defaults to every 5 seconds.
+    pub fn sample_every(self, sample_every: Duration) -> Self {
+        assert!(
+            sample_every > Duration::from_secs(0),
+            "sample_every must be > 0"
+        );
+        Soak {
+            name: self.name,
+            duration: self.duration,
+            sample_every,
+            warmup: self.warmup,
+            rss_slack: self.rss_slack,
+            gauges: self.gauges,
+        }
+    }
+
+    /// How long to let the pipeline settle before checking for growth, defaults to 30 seconds.
+    pub fn warmup(self, warmup: Duration) -> Self {
+        Soak {
+            name: self.name,
+            duration: self.duration,
+            sample_every: self.sample_every,
+            warmup,
+            rss_slack: self.rss_slack,
+            gauges: self.gauges,
+        }
+    }
+
+    /// Bytes the resident set size may grow by without leaking, defaults to 4 MiB.
+    pub fn rss_slack(self, rss_slack: u64) -> Self {
+        Soak {
+            name: self.name,
+            duration: self.duration,
+            sample_every: self.sample_every,
+            warmup: self.warmup,
+            rss_slack,
+            gauges: self.gauges,
+        }
+    }
+
+    /// Samples and checks `gauge`, such as the number of entries in a table, alongside the
+    /// process.
+    pub fn gauge<F: FnMut() -> u64 + 'static>(self, name: &str, gauge: F) -> Self {
+        let mut gauges = self.gauges;
+        gauges.push((String::from(name), Box::new(gauge)));
+        Soak {
+            name: self.name,
+            duration: self.duration,
+            sample_every: self.sample_every,
+            warmup: self.warmup,
+            rss_slack: self.rss_slack,
+            gauges,
+        }
+    }
+
+    /// Calls `round` with the number of each round, until the duration has passed, sampling
+    /// between rounds.
+    pub fn run<F: FnMut(usize)>(mut self, mut round: F) -> SoakReport {
+        let start = Instant::now();
+        let mut samples = vec![];
+        let mut next_sample = start;
+        let mut rounds = 0;
+        while start.elapsed() < self.duration {
+            if Instant::now() >= next_sample {
+                samples.push(self.sample(start.elapsed(), rounds));
+                next_sample += self.sample_every;
+            }
+            round(rounds);
+            rounds += 1;
+        }
+        samples.push(self.sample(start.elapsed(), rounds));
+
+        let settled: Vec<&SoakSample> = samples
+            .iter()
+            .filter(|sample| sample.at >= self.warmup)
+            .collect();
+        let mut leaks = vec![];
+        for series in 0..samples[0].gauges.len() {
+            let slack = if samples[0].gauges[series].0 == "rss" {
+                self.rss_slack
+            } else {
+                0
+            };
+            let readings: Vec<u64> = settled
+                .iter()
+                .map(|sample| sample.gauges[series].1)
+                .collect();
+            if let Some((from, to)) = growth(&readings, slack) {
+                leaks.push(Leak {
+                    gauge: samples[0].gauges[series].0.clone(),
+                    from,
+                    to,
+                });
+            }
+        }
+
+        SoakReport {
+            name: self.name,
+            rounds,
+            elapsed: start.elapsed(),
+            samples,
+            leaks,
+        }
+    }
+
+    fn sample(&mut self, at: Duration, rounds: usize) -> SoakSample {
+        let mut gauges = vec![];
+        if let Some(rss) = resident_set_size() {
+            gauges.push((String::from("rss"), rss));
+        }
+        if let Some(fds) = open_fds() {
+            gauges.push((String::from("fds"), fds));
+        }
+        for (name, gauge) in &mut self.gauges {
+            gauges.push((name.clone(), gauge()));
+        }
+        SoakSample {
+            at,
+            rounds,
+            gauges,
+            links: Registry::global()
+                .links()
+                .iter()
+                .map(|link| (String::from(link.name()), link.packets()))
+                .collect(),
+        }
+    }
+}
+
+/// The least of the last third of `readings`, and the most of the first third, if the least is
+/// more than `slack` over the most. Too few readings to have thirds never grow.
+fn growth(readings: &[u64], slack: u64) -> Option<(u64, u64)> {
+    let third = readings.len() / 3;
+    if third == 0 {
+        return None;
+    }
+    let from = *readings[..third].iter().max().unwrap();
+    let to = *readings[readings.len() - third..].iter().min().unwrap();
+    if to > from.saturating_add(slack) {
+        Some((from, to))
+    } else {
+        None
+    }
+}
+
+/// The resident set size of the process in bytes, where `/proc` has it.
+fn resident_set_size() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// The file descriptors the process has open, where `/proc` has them.
+fn open_fds() -> Option<u64> {
+    // Reading the directory holds one descriptor open, which is counted every time alike.
+    Some(fs::read_dir("/proc/self/fd").ok()?.count() as u64)
+}
+
+/// What a soak read at one point of its run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoakSample {
+    pub at: Duration,
+    /// Rounds finished by then.
+    pub rounds: usize,
+    /// `rss` in bytes and `fds` where the platform has them, then each gauge, in the order added.
+    pub gauges: Vec<(String, u64)>,
+    /// Packets sent by each named link.
+    pub links: Vec<(String, u64)>,
+}
+
+/// A gauge that grew over a soak, from the most it read early on to the least it read late.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leak {
+    pub gauge: String,
+    pub from: u64,
+    pub to: u64,
+}
+
+/// What a soak sampled, and the gauges that leaked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoakReport {
+    pub name: String,
+    pub rounds: usize,
+    pub elapsed: Duration,
+    pub samples: Vec<SoakSample>,
+    pub leaks: Vec<Leak>,
+}
+
+impl SoakReport {
+    /// Panics with the report if any gauge leaked.
+    pub fn assert_no_leaks(&self) {
+        assert!(self.leaks.is_empty(), "{}", self);
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} rounds in {:?}, {} samples",
+            self.name,
+            self.rounds,
+            self.elapsed,
+            self.samples.len()
+        )?;
+        if let (Some(first), Some(last)) = (self.samples.first(), self.samples.last()) {
+            for ((name, from), (_, to)) in first.gauges.iter().zip(&last.gauges) {
+                write!(f, ", {} {} -> {}", name, from, to)?;
+            }
+        }
+        for leak in &self.leaks {
+            write!(
+                f,
+                "\n{} leaked, from {} to {}",
+                leak.gauge, leak.from, leak.to
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam::crossbeam_channel;
+    use route_rs_runtime::dynamic::DynamicNodeKind::{Processor, IO};
+    use route_rs_runtime::dynamic::{DynamicGraph, PipelineManager, ProcessorRegistry};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use tokio::runtime;
+
+    #[test]
+    fn grows_only_past_slack() {
+        assert_eq!(growth(&[10, 12, 11, 13, 15, 14], 0), Some((12, 14)));
+        assert_eq!(growth(&[10, 12, 11, 13, 15, 14], 2), None);
+        // Wobbling about a level is not growth.
+        assert_eq!(growth(&[10, 14, 9, 13, 11, 12], 0), None);
+        assert_eq!(growth(&[10, 20], 0), None);
+    }
+
+    #[test]
+    fn finds_leaking_gauges() {
+        let held = Rc::new(RefCell::new(vec![]));
+        let gauge = Rc::clone(&held);
+        let report = Soak::new("leaky")
+            .duration(Duration::from_millis(200))
+            .sample_every(Duration::from_millis(10))
+            .warmup(Duration::from_millis(20))
+            .gauge("held", move || gauge.borrow().len() as u64)
+            .gauge("steady", || 7)
+            .run(|round| {
+                held.borrow_mut().push(round);
+                std::thread::sleep(Duration::from_millis(1));
+            });
+
+        assert!(report.samples.len() >= 10);
+        let leaked: Vec<&str> = report.leaks.iter().map(|l| l.gauge.as_str()).collect();
+        assert!(leaked.contains(&"held"));
+        assert!(!leaked.contains(&"steady"));
+        assert!(report.to_string().contains("held leaked"));
+    }
+
+    struct Add(i32);
+
+    impl route_rs_runtime::processor::Processor for Add {
+        type Input = i32;
+        type Output = i32;
+
+        fn process(&mut self, packet: i32) -> Option<i32> {
+            Some(packet + self.0)
+        }
+    }
+
+    fn add(n: usize) -> DynamicGraph {
+        DynamicGraph::new()
+            .node("input", IO, "", vec![])
+            .node("add", Processor, "Add", vec![&n.to_string()])
+            .capacity(16)
+            .node("output", IO, "", vec![])
+            .edge("input", "add")
+            .edge("add", "output")
+    }
+
+    #[test]
+    fn soaks_a_reloading_pipeline() {
+        let mut registry = ProcessorRegistry::new();
+        registry.register_processor("Add", |args: &[String]| {
+            Ok(Add(args[0].parse().map_err(|_| "Bad number")?))
+        });
+        let (input, input_channel) = crossbeam_channel::unbounded();
+        let (output_channel, output) = crossbeam_channel::unbounded();
+        let mut manager = PipelineManager::new(registry, vec![input_channel], vec![output_channel]);
+        let mut runtime = runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(manager.load(&add(0))).unwrap();
+
+        // Flows start and stop as the pipeline's config is swapped under them.
+        let flows = Rc::new(RefCell::new(HashMap::new()));
+        let gauge = Rc::clone(&flows);
+        let report = Soak::new("reloading")
+            .duration(Duration::from_millis(300))
+            .sample_every(Duration::from_millis(20))
+            .warmup(Duration::from_millis(40))
+            .gauge("flows", move || gauge.borrow().len() as u64)
+            .run(|round| {
+                flows.borrow_mut().insert(round, ());
+                flows.borrow_mut().remove(&round.wrapping_sub(4));
+                for packet in 0..10 {
+                    input.send(packet).unwrap();
+                }
+                if round % 8 == 0 {
+                    runtime.block_on(manager.load(&add(round))).unwrap();
+                }
+                runtime.block_on(manager.barrier());
+                assert_eq!(output.try_iter().count(), 10);
+            });
+
+        assert!(report.rounds > 0);
+        assert!(report.leaks.iter().all(|leak| leak.gauge != "flows"));
+        drop(input);
+        runtime.block_on(manager.join());
+    }
+}
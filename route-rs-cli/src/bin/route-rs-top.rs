@@ -0,0 +1,197 @@
+use std::io;
+use std::net::SocketAddr;
+use std::process;
+use std::time::{Duration, Instant};
+
+extern crate clap;
+use clap::{App, Arg};
+
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use tui::backend::{Backend, CrosstermBackend};
+use tui::layout::Constraint;
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Block, Borders, Cell, Row, Table};
+use tui::{Frame, Terminal};
+
+use route_rs_cli::{format_rate, Client, LinkRates, Top};
+
+const DEFAULT_ADDR: &str = "127.0.0.1:9180";
+
+/// Queues at least this full are drawn in red, as the link behind them can't keep up.
+const FULL_QUEUE: f64 = 0.8;
+
+/// What was last read from the router, or why it couldn't be.
+struct Screen {
+    addr: SocketAddr,
+    interval: Duration,
+    links: Result<Vec<LinkRates>, String>,
+}
+
+fn draw<B: Backend>(f: &mut Frame<B>, screen: &Screen) {
+    let title = format!(
+        " route-rs-top  {}  every {:?}  q to quit ",
+        screen.addr, screen.interval
+    );
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let links = match &screen.links {
+        Ok(links) => links,
+        Err(err) => {
+            let row =
+                Row::new(vec![Cell::from(err.as_str())]).style(Style::default().fg(Color::Red));
+            let table = Table::new(vec![row])
+                .block(block)
+                .widths(&[Constraint::Percentage(100)]);
+            f.render_widget(table, f.size());
+            return;
+        }
+    };
+
+    let header = Row::new(vec![
+        "NAME",
+        "PPS",
+        "EGRESS PPS",
+        "DROPS/S",
+        "DROPPED",
+        "QUEUED",
+    ])
+    .style(Style::default().add_modifier(Modifier::BOLD));
+    let rows = links.iter().map(|link| {
+        let (depth, capacity) = link.queue_depth();
+        let queued = if capacity == 0 {
+            String::from("-")
+        } else {
+            format!("{}/{}", depth, capacity)
+        };
+        let style = if capacity > 0 && depth as f64 >= capacity as f64 * FULL_QUEUE {
+            Style::default().fg(Color::Red)
+        } else if link.drops_per_sec > 0.0 {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        };
+        Row::new(vec![
+            link.name.clone(),
+            format_rate(link.pps()),
+            link.egress_pps
+                .iter()
+                .map(|pps| format_rate(*pps))
+                .collect::<Vec<_>>()
+                .join(" "),
+            format_rate(link.drops_per_sec),
+            link.dropped.to_string(),
+            queued,
+        ])
+        .style(style)
+    });
+    let table = Table::new(rows).header(header).block(block).widths(&[
+        Constraint::Percentage(30),
+        Constraint::Percentage(10),
+        Constraint::Percentage(25),
+        Constraint::Percentage(10),
+        Constraint::Percentage(12),
+        Constraint::Percentage(13),
+    ]);
+    f.render_widget(table, f.size());
+}
+
+/// Reads the links every interval and redraws them, until q, Esc or Ctrl-C is pressed.
+fn run<B: Backend>(
+    terminal: &mut Terminal<B>,
+    client: &Client,
+    mut screen: Screen,
+) -> io::Result<()> {
+    let mut top = Top::new();
+    loop {
+        let read_at = Instant::now();
+        screen.links = client
+            .get("/links")
+            .map(|links| top.update(&links, read_at));
+        terminal.draw(|f| draw(f, &screen))?;
+
+        let next_read = read_at + screen.interval;
+        loop {
+            let now = Instant::now();
+            if now >= next_read {
+                break;
+            }
+            if !event::poll(next_read - now)? {
+                break;
+            }
+            match event::read()? {
+                Event::Key(key) => match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(())
+                    }
+                    _ => {}
+                },
+                Event::Resize(_, _) => {
+                    terminal.draw(|f| draw(f, &screen))?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn main() {
+    let matches = App::new("route-rs top")
+        .version("0.1.0")
+        .about("Shows the packets per second, drops and queue depths of a router's links as they change")
+        .arg(
+            Arg::with_name("addr")
+                .short("a")
+                .long("addr")
+                .value_name("ADDR")
+                .help("Address the management API of the router is served on")
+                .takes_value(true)
+                .default_value(DEFAULT_ADDR),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .short("i")
+                .long("interval")
+                .value_name("MS")
+                .help("Milliseconds between refreshes")
+                .takes_value(true)
+                .default_value("1000"),
+        )
+        .get_matches();
+    let addr: SocketAddr = match matches.value_of("addr").unwrap().parse() {
+        Ok(addr) => addr,
+        Err(_) => {
+            eprintln!("{} is not an address", matches.value_of("addr").unwrap());
+            process::exit(2);
+        }
+    };
+    let interval = match matches.value_of("interval").unwrap().parse::<u64>() {
+        Ok(interval) if interval > 0 => Duration::from_millis(interval),
+        _ => {
+            eprintln!("interval must be a number of milliseconds > 0");
+            process::exit(2);
+        }
+    };
+    let screen = Screen {
+        addr,
+        interval,
+        links: Ok(vec![]),
+    };
+
+    let result = terminal::enable_raw_mode().and_then(|_| {
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        let result = run(&mut terminal, &Client::new(addr), screen);
+        // Give the terminal back as it was, even if drawing failed.
+        let _ = execute!(terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = terminal.show_cursor();
+        result
+    });
+    let _ = terminal::disable_raw_mode();
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,154 @@
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// Makes requests of the management API of a router, one connection each.
+pub struct Client {
+    addr: SocketAddr,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(addr: SocketAddr) -> Self {
+        Client {
+            addr,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Result<Value, String> {
+        self.request("GET", path, None)
+    }
+
+    pub fn post(&self, path: &str, body: &Value) -> Result<Value, String> {
+        self.request("POST", path, Some(body))
+    }
+
+    /// The names of the tables the router serves, or none if it can't be reached.
+    pub fn table_names(&self) -> Vec<String> {
+        match self.get("/tables") {
+            Ok(Value::Array(names)) => names
+                .into_iter()
+                .filter_map(|name| name.as_str().map(String::from))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Sends a request and returns the body of the response. Responses other than 200 are errors,
+    /// described by the `error` the API put in their body.
+    fn request(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Value, String> {
+        let body = body.map(Value::to_string).unwrap_or_default();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            self.addr,
+            body.len(),
+            body
+        );
+
+        let mut stream = TcpStream::connect_timeout(&self.addr, self.timeout)
+            .map_err(|err| format!("Could not connect to {}: {}", self.addr, err))?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(|err| err.to_string())?;
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|err| format!("Could not send request: {}", err))?;
+        // Not every platform lets us shut down a socket the peer already closed.
+        let _ = stream.shutdown(Shutdown::Write);
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .map_err(|err| format!("Could not read response: {}", err))?;
+
+        parse_response(&response)
+    }
+}
+
+fn parse_response(response: &str) -> Result<Value, String> {
+    let status: u16 = response
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| String::from("Malformed response"))?;
+    let body = match response.find("\r\n\r\n") {
+        Some(header_end) => &response[header_end + 4..],
+        None => return Err(String::from("Malformed response")),
+    };
+    let body: Value = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(body).map_err(|err| format!("Malformed response: {}", err))?
+    };
+
+    if status == 200 {
+        Ok(body)
+    } else {
+        match body.get("error").and_then(Value::as_str) {
+            Some(error) => Err(String::from(error)),
+            None => Err(format!("Request failed with status {}", status)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answers a single request with a canned response, and returns the request it received.
+    fn serve_once(response: &'static str) -> (SocketAddr, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            stream.read_to_string(&mut request).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+            request
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn gets_json() {
+        let (addr, server) = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\n[\"arp\"]");
+
+        assert_eq!(Client::new(addr).table_names(), vec!["arp"]);
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /tables HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn posts_json() {
+        let (addr, server) = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\ntrue");
+
+        assert_eq!(
+            Client::new(addr).post("/controls/firewall", &json!(["drop"])),
+            Ok(json!(true))
+        );
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /controls/firewall HTTP/1.1\r\n"));
+        assert!(request.ends_with("\r\n\r\n[\"drop\"]"));
+    }
+
+    #[test]
+    fn returns_api_errors() {
+        assert_eq!(
+            parse_response("HTTP/1.1 404 Not Found\r\n\r\n{\"error\": \"Nothing is at /x\"}"),
+            Err(String::from("Nothing is at /x"))
+        );
+        assert_eq!(
+            parse_response("HTTP/1.1 500 Internal Server Error\r\n\r\n"),
+            Err(String::from("Request failed with status 500"))
+        );
+        assert!(parse_response("garbage").is_err());
+    }
+}
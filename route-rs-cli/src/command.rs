@@ -0,0 +1,146 @@
+use crate::client::Client;
+use serde_json::Value;
+
+/// The first word of every command the shell understands.
+pub const COMMANDS: &[&str] = &["show", "set", "help", "exit"];
+
+/// Tables `show` knows of before asking the router, as the tab completion of a router that can't
+/// be reached.
+const SHOW_TARGETS: &[&str] = &["links", "tables"];
+
+const HELP: &str = "\
+show links           Counters of every named link
+show tables          Names of the tables the router serves
+show <table>         Contents of a table, such as arp or flows
+set <control> [...]  Calls a control, such as firewall, with the rest of the line
+help                 This message
+exit                 Leaves the shell";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    ShowLinks,
+    ShowTables,
+    ShowTable(String),
+    Set(String, Vec<String>),
+    Help,
+    Exit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["show", "links"] => Ok(Command::ShowLinks),
+            ["show", "tables"] => Ok(Command::ShowTables),
+            ["show", table] => Ok(Command::ShowTable(String::from(*table))),
+            ["show"] => Err(String::from("show what? Try show tables")),
+            ["show", ..] => Err(String::from("show takes a single table")),
+            ["set", control, args @ ..] => Ok(Command::Set(
+                String::from(*control),
+                args.iter().map(|arg| String::from(*arg)).collect(),
+            )),
+            ["set"] => Err(String::from("set what?")),
+            ["help"] => Ok(Command::Help),
+            ["exit"] | ["quit"] => Ok(Command::Exit),
+            [] => Err(String::from("Empty command")),
+            [command, ..] => Err(format!("Unknown command {}, try help", command)),
+        }
+    }
+
+    /// Runs the command against a router. `Help` and `Exit` need no router, and return nothing to
+    /// render but the help text.
+    pub fn run(&self, client: &Client) -> Result<Value, String> {
+        match self {
+            Command::ShowLinks => client.get("/links"),
+            Command::ShowTables => client.get("/tables"),
+            Command::ShowTable(table) => client.get(&format!("/tables/{}", table)),
+            Command::Set(control, args) => client.post(
+                &format!("/controls/{}", control),
+                &Value::Array(args.iter().cloned().map(Value::String).collect()),
+            ),
+            Command::Help => Ok(Value::String(String::from(HELP))),
+            Command::Exit => Ok(Value::Null),
+        }
+    }
+}
+
+/// Candidates for the word being typed at the end of a line, given the tables the router serves.
+/// A line ending in whitespace completes a new word.
+pub fn complete(line: &str, tables: &[String]) -> Vec<String> {
+    let mut words: Vec<&str> = line.split_whitespace().collect();
+    if line.is_empty() || line.ends_with(char::is_whitespace) {
+        words.push("");
+    }
+
+    let candidates: Vec<&str> = match words.as_slice() {
+        [_] => COMMANDS.to_vec(),
+        ["show", _] => SHOW_TARGETS
+            .iter()
+            .cloned()
+            .chain(tables.iter().map(String::as_str))
+            .collect(),
+        _ => vec![],
+    };
+    let partial = words.last().cloned().unwrap_or("");
+    let mut completions: Vec<String> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(partial))
+        .map(String::from)
+        .collect();
+    completions.sort();
+    completions.dedup();
+    completions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse("show links"), Ok(Command::ShowLinks));
+        assert_eq!(Command::parse("  show   tables "), Ok(Command::ShowTables));
+        assert_eq!(
+            Command::parse("show arp"),
+            Ok(Command::ShowTable(String::from("arp")))
+        );
+        assert_eq!(
+            Command::parse("set firewall drop tcp 22"),
+            Ok(Command::Set(
+                String::from("firewall"),
+                vec![
+                    String::from("drop"),
+                    String::from("tcp"),
+                    String::from("22")
+                ]
+            ))
+        );
+        assert_eq!(Command::parse("quit"), Ok(Command::Exit));
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert!(Command::parse("").is_err());
+        assert!(Command::parse("show").is_err());
+        assert!(Command::parse("show arp flows").is_err());
+        assert_eq!(
+            Command::parse("reboot now"),
+            Err(String::from("Unknown command reboot, try help"))
+        );
+    }
+
+    #[test]
+    fn completes_commands_and_tables() {
+        let tables = vec![String::from("arp"), String::from("flows")];
+
+        assert_eq!(complete("", &tables), vec!["exit", "help", "set", "show"]);
+        assert_eq!(complete("sh", &tables), vec!["show"]);
+        assert_eq!(
+            complete("show ", &tables),
+            vec!["arp", "flows", "links", "tables"]
+        );
+        assert_eq!(complete("show f", &tables), vec!["flows"]);
+        assert_eq!(complete("show arp ", &tables), Vec::<String>::new());
+        assert_eq!(complete("show ", &[]), vec!["links", "tables"]);
+    }
+}
@@ -0,0 +1,29 @@
+//! A shell for routers that serve the route-rs management API.
+//!
+//! Each line typed into the shell, or given on the command line, is parsed into a `Command`, run
+//! against the API with a `Client`, and its result rendered as a table where it has the shape of
+//! one:
+//!
+//! ```text
+//! route-rs> show links
+//! NAME        EGRESS_PACKETS  PACKETS
+//! classifier  [512,512]       1024
+//! route-rs> set firewall drop tcp 22
+//! ```
+//!
+//! `show` reads any table the router has registered, such as `arp` or `flows`, and `set` calls
+//! the control of the same name with the rest of the words as a JSON array of strings.
+//!
+//! Built with the `top` feature, `route-rs-top` reads `GET /links` every second or so and shows
+//! the packets per second leaving each named link, its drops and how full its queues are, like
+//! `top` does for processes. `Top` turns the counters of successive reads into those rates.
+
+mod client;
+mod command;
+mod render;
+mod top;
+
+pub use self::client::Client;
+pub use self::command::{complete, Command, COMMANDS};
+pub use self::render::render;
+pub use self::top::{format_rate, LinkRates, Top};
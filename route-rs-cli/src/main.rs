@@ -0,0 +1,136 @@
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+use std::process;
+
+extern crate clap;
+use clap::{App, AppSettings, Arg};
+
+use route_rs_cli::{complete, render, Client, Command};
+
+const PROMPT: &str = "route-rs> ";
+const DEFAULT_ADDR: &str = "127.0.0.1:9180";
+
+/// Runs a command, printing what it returns. Returns whether it succeeded.
+fn run(client: &Client, line: &str) -> bool {
+    match Command::parse(line).and_then(|command| command.run(client)) {
+        Ok(result) => {
+            let output = render(&result);
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+            true
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        }
+    }
+}
+
+/// Drops the first word of a line, and the whitespace before it.
+fn skip_word(line: &str) -> &str {
+    let line = line.trim_start();
+    match line.find(char::is_whitespace) {
+        Some(end) => &line[end..],
+        None => "",
+    }
+}
+
+/// Splits the line bash is completing, when it runs us with `complete -C route-rs-cli
+/// route-rs-cli`, into the address given with `--addr`, if any, and the command after it.
+fn parse_comp_line(comp_line: &str) -> (Option<&str>, &str) {
+    let mut addr = None;
+    let mut rest = skip_word(comp_line);
+    loop {
+        match rest.split_whitespace().next() {
+            Some("-a") | Some("--addr") => {
+                rest = skip_word(rest);
+                addr = rest.split_whitespace().next();
+                rest = skip_word(rest);
+            }
+            _ => return (addr, rest.trim_start()),
+        }
+    }
+}
+
+fn parse_addr(addr: &str) -> SocketAddr {
+    match addr.parse() {
+        Ok(addr) => addr,
+        Err(_) => {
+            eprintln!("{} is not an address", addr);
+            process::exit(2);
+        }
+    }
+}
+
+fn shell(client: &Client) {
+    println!("Type help for the commands, or end a line with ? to list what can come next.");
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{}", PROMPT);
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+
+        if line.ends_with('?') {
+            let completions = complete(line.trim_end_matches('?'), &client.table_names());
+            println!("{}", completions.join("  "));
+        } else if Command::parse(&line) == Ok(Command::Exit) {
+            break;
+        } else if !line.trim().is_empty() {
+            run(client, &line);
+        }
+    }
+}
+
+fn main() {
+    // Bash passes the words around the cursor as arguments, which are no business of clap.
+    if let Ok(comp_line) = env::var("COMP_LINE") {
+        let comp_point = env::var("COMP_POINT")
+            .ok()
+            .and_then(|point| point.parse().ok())
+            .unwrap_or(comp_line.len())
+            .min(comp_line.len());
+        let (addr, line) = parse_comp_line(&comp_line[..comp_point]);
+        let client = Client::new(parse_addr(addr.unwrap_or(DEFAULT_ADDR)));
+        for completion in complete(line, &client.table_names()) {
+            println!("{}", completion);
+        }
+        return;
+    }
+
+    let app = App::new("route-rs cli")
+        .version("0.1.0")
+        .about("Inspects and reconfigures a router through its management API")
+        .setting(AppSettings::TrailingVarArg)
+        .arg(
+            Arg::with_name("addr")
+                .short("a")
+                .long("addr")
+                .value_name("ADDR")
+                .help("Address the management API of the router is served on")
+                .takes_value(true)
+                .default_value(DEFAULT_ADDR),
+        )
+        .arg(
+            Arg::with_name("command")
+                .value_name("COMMAND")
+                .help("Runs a single command, such as show links, rather than a shell")
+                .multiple(true),
+        );
+    let matches = app.get_matches();
+    let client = Client::new(parse_addr(matches.value_of("addr").unwrap()));
+
+    if let Some(command) = matches.values_of("command") {
+        let line = command.collect::<Vec<_>>().join(" ");
+        if !run(&client, &line) {
+            process::exit(1);
+        }
+    } else {
+        shell(&client);
+    }
+}
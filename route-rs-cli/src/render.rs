@@ -0,0 +1,114 @@
+use serde_json::Value;
+
+/// Renders the result of a command for a terminal. Arrays of objects, as most tables are, become
+/// columns named after the keys of the objects, with `name` first when rows have one. Strings are
+/// printed as they are, and anything else is pretty-printed JSON.
+pub fn render(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(rows) if !rows.is_empty() && rows.iter().all(Value::is_object) => {
+            render_table(rows)
+        }
+        Value::Array(items) if items.iter().all(Value::is_string) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => serde_json::to_string_pretty(value).unwrap(),
+    }
+}
+
+fn render_table(rows: &[Value]) -> String {
+    let mut columns: Vec<&str> = vec![];
+    for row in rows {
+        for key in row.as_object().unwrap().keys() {
+            if !columns.contains(&key.as_str()) {
+                columns.push(key);
+            }
+        }
+    }
+    if let Some(name) = columns.iter().position(|column| *column == "name") {
+        let name = columns.remove(name);
+        columns.insert(0, name);
+    }
+
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|column| match row.get(column) {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(Value::Null) | None => String::from("-"),
+                    Some(value) => value.to_string(),
+                })
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            cells
+                .iter()
+                .map(|row| row[i].len())
+                .chain(Some(column.len()))
+                .max()
+                .unwrap()
+        })
+        .collect();
+
+    let header: Vec<String> = columns.iter().map(|c| c.to_uppercase()).collect();
+    Some(header)
+        .into_iter()
+        .chain(cells)
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn renders_tables() {
+        let links = json!([
+            {"name": "classifier", "packets": 1024, "egress_packets": [512, 512]},
+            {"name": "out", "packets": 0, "egress_packets": []},
+        ]);
+        assert_eq!(
+            render(&links),
+            "NAME        EGRESS_PACKETS  PACKETS\n\
+             classifier  [512,512]       1024\n\
+             out         []              0"
+        );
+    }
+
+    #[test]
+    fn renders_missing_cells() {
+        let arp = json!([{"ip": "10.0.0.2", "mac": "de:ad:be:ef:00:01"}, {"ip": "10.0.0.3"}]);
+        assert_eq!(
+            render(&arp),
+            "IP        MAC\n10.0.0.2  de:ad:be:ef:00:01\n10.0.0.3  -"
+        );
+    }
+
+    #[test]
+    fn renders_other_values() {
+        assert_eq!(render(&json!(["arp", "flows"])), "arp\nflows");
+        assert_eq!(render(&json!("done")), "done");
+        assert_eq!(render(&json!(null)), "");
+        assert_eq!(render(&json!({"count": 2})), "{\n  \"count\": 2\n}");
+    }
+}
@@ -0,0 +1,208 @@
+use serde_json::Value;
+use std::time::Instant;
+
+/// The counters `GET /links` gives for one link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LinkCounters {
+    name: String,
+    egress_packets: Vec<u64>,
+    dropped: u64,
+    queue_depths: Vec<u64>,
+    queue_capacities: Vec<u64>,
+}
+
+impl LinkCounters {
+    /// Reads a row of `GET /links`. Fields a router too old to report them leaves out are taken
+    /// as zero, or as no queues.
+    fn parse(row: &Value) -> Option<Self> {
+        let numbers = |field: &str| -> Vec<u64> {
+            row.get(field)
+                .and_then(Value::as_array)
+                .map(|values| values.iter().filter_map(Value::as_u64).collect())
+                .unwrap_or_default()
+        };
+        Some(LinkCounters {
+            name: String::from(row.get("name")?.as_str()?),
+            egress_packets: numbers("egress_packets"),
+            dropped: row.get("dropped").and_then(Value::as_u64).unwrap_or(0),
+            queue_depths: numbers("queue_depths"),
+            queue_capacities: numbers("queue_capacities"),
+        })
+    }
+}
+
+/// What `route-rs-top` shows of a link: packets per second leaving each of its egressors and
+/// dropped, since the previous refresh, and how full its queues are now.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkRates {
+    pub name: String,
+    pub egress_pps: Vec<f64>,
+    pub drops_per_sec: f64,
+    pub dropped: u64,
+    pub queue_depths: Vec<(u64, u64)>,
+}
+
+impl LinkRates {
+    /// Packets per second leaving every egressor of the link.
+    pub fn pps(&self) -> f64 {
+        self.egress_pps.iter().sum()
+    }
+
+    /// Packets held in every queue of the link, and how many they can hold.
+    pub fn queue_depth(&self) -> (u64, u64) {
+        self.queue_depths
+            .iter()
+            .fold((0, 0), |(depth, capacity), (d, c)| {
+                (depth + d, capacity + c)
+            })
+    }
+}
+
+/// Turns successive reads of `GET /links` into rates, by the difference in each link's counters
+/// between reads. Links are matched by name, and a link whose counters went down was registered
+/// afresh, as when a pipeline is reloaded, so its counters are taken from zero.
+#[derive(Default)]
+pub struct Top {
+    previous: Vec<LinkCounters>,
+    at: Option<Instant>,
+}
+
+impl Top {
+    pub fn new() -> Self {
+        Top::default()
+    }
+
+    /// The rates of the links in `links`, read at `at`, in the order the router lists them. The
+    /// first read has nothing to compare with, so its rates are all zero.
+    pub fn update(&mut self, links: &Value, at: Instant) -> Vec<LinkRates> {
+        let current: Vec<LinkCounters> = links
+            .as_array()
+            .map(|rows| rows.iter().filter_map(LinkCounters::parse).collect())
+            .unwrap_or_default();
+        let elapsed = self
+            .at
+            .map(|previous| at.saturating_duration_since(previous).as_secs_f64());
+        let rate = |now: u64, before: Option<u64>| -> f64 {
+            match (elapsed, before) {
+                (Some(elapsed), Some(before)) if elapsed > 0.0 => {
+                    let delta = if now >= before { now - before } else { now };
+                    delta as f64 / elapsed
+                }
+                _ => 0.0,
+            }
+        };
+
+        let rates = current
+            .iter()
+            .map(|link| {
+                let previous = self.previous.iter().find(|p| p.name == link.name);
+                LinkRates {
+                    name: link.name.clone(),
+                    egress_pps: link
+                        .egress_packets
+                        .iter()
+                        .enumerate()
+                        .map(|(egressor, sent)| {
+                            let before = previous
+                                .map(|p| p.egress_packets.get(egressor).cloned().unwrap_or(0));
+                            rate(*sent, before)
+                        })
+                        .collect(),
+                    drops_per_sec: rate(link.dropped, previous.map(|p| p.dropped)),
+                    dropped: link.dropped,
+                    queue_depths: link
+                        .queue_depths
+                        .iter()
+                        .cloned()
+                        .zip(link.queue_capacities.iter().cloned())
+                        .collect(),
+                }
+            })
+            .collect();
+        self.previous = current;
+        self.at = Some(at);
+        rates
+    }
+}
+
+/// A rate, short enough for a narrow column, as in `1.50M`.
+pub fn format_rate(rate: f64) -> String {
+    if rate >= 1e9 {
+        format!("{:.2}G", rate / 1e9)
+    } else if rate >= 1e6 {
+        format!("{:.2}M", rate / 1e6)
+    } else if rate >= 1e3 {
+        format!("{:.2}k", rate / 1e3)
+    } else {
+        format!("{:.0}", rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn rates_are_differences_over_time() {
+        let mut top = Top::new();
+        let start = Instant::now();
+        let links = |sent: [u64; 2], dropped: u64, depth: u64| {
+            json!([{
+                "name": "classifier",
+                "packets": sent[0] + sent[1],
+                "egress_packets": sent,
+                "dropped": dropped,
+                "queue_depths": [depth, 0],
+                "queue_capacities": [10, 10],
+            }])
+        };
+
+        let first = top.update(&links([100, 50], 5, 3), start);
+        assert_eq!(first[0].pps(), 0.0);
+        assert_eq!(first[0].queue_depth(), (3, 20));
+
+        let second = top.update(
+            &links([1100, 550], 25, 7),
+            start + Duration::from_millis(500),
+        );
+        assert_eq!(second[0].egress_pps, vec![2000.0, 1000.0]);
+        assert_eq!(second[0].pps(), 3000.0);
+        assert_eq!(second[0].drops_per_sec, 40.0);
+        assert_eq!(second[0].dropped, 25);
+        assert_eq!(second[0].queue_depths, vec![(7, 10), (0, 10)]);
+    }
+
+    #[test]
+    fn counts_reregistered_links_from_zero() {
+        let mut top = Top::new();
+        let start = Instant::now();
+        top.update(
+            &json!([{"name": "filter", "egress_packets": [1000]}]),
+            start,
+        );
+        let rates = top.update(
+            &json!([
+                {"name": "filter", "egress_packets": [30]},
+                {"name": "added", "egress_packets": [500]},
+            ]),
+            start + Duration::from_secs(1),
+        );
+
+        assert_eq!(rates[0].egress_pps, vec![30.0]);
+        assert_eq!((rates[0].drops_per_sec, rates[0].dropped), (0.0, 0));
+        assert!(rates[0].queue_depths.is_empty());
+        // A link the previous read didn't have has nothing to compare with.
+        assert_eq!(rates[1].egress_pps, vec![0.0]);
+    }
+
+    #[test]
+    fn formats_rates() {
+        assert_eq!(format_rate(0.0), "0");
+        assert_eq!(format_rate(999.4), "999");
+        assert_eq!(format_rate(1500.0), "1.50k");
+        assert_eq!(format_rate(2_250_000.0), "2.25M");
+        assert_eq!(format_rate(1.2e9), "1.20G");
+    }
+}
@@ -0,0 +1,378 @@
+//! Derive macros for the boilerplate of Processors, Classifiers, and the classes a ClassifyLink
+//! dispatches on, so a two way classifier needs its classification and little else.
+//!
+//! ```
+//! use route_rs_derive::{Classifier, Dispatch};
+//!
+//! #[derive(Dispatch)]
+//! pub enum Parity {
+//!     Even,
+//!     Odd,
+//! }
+//!
+//! #[derive(Classifier, Default)]
+//! #[classifier(packet = "i32", class = "Parity", classify = "parity", new)]
+//! pub struct ByParity {}
+//!
+//! impl ByParity {
+//!     fn parity(&self, packet: &i32) -> Parity {
+//!         if packet % 2 == 0 {
+//!             Parity::Even
+//!         } else {
+//!             Parity::Odd
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! A `ClassifyLink` with a `ByParity` then only needs `.dispatch()` in place of a dispatcher and a
+//! number of egressors. The generated code refers to `route_rs_runtime` by name, so crates using
+//! the macros depend on it as that.
+//!
+//! The `pipeline!` macro declares a whole pipeline in Rust, as graphgen generates one from a graph.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use route_rs_graphgen::{generate, GenerateOptions, PipelineGraph, TypeIndex};
+use std::collections::HashMap;
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Lit, Meta, NestedMeta};
+
+/// The settings of a `#[processor(...)]` or `#[classifier(...)]` attribute, string values by
+/// name, and whether `new` was given.
+struct Settings {
+    values: HashMap<String, (String, proc_macro2::Span)>,
+    new: bool,
+}
+
+impl Settings {
+    fn parse(input: &DeriveInput, name: &str, keys: &[&str]) -> Result<Self, Error> {
+        let mut settings = Settings {
+            values: HashMap::new(),
+            new: false,
+        };
+        let mut found = false;
+        for attr in input.attrs.iter().filter(|attr| attr.path.is_ident(name)) {
+            found = true;
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => {
+                    return Err(Error::new(
+                        meta.span(),
+                        format!("expected #[{}(...)]", name),
+                    ))
+                }
+            };
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("new") => {
+                        settings.new = true
+                    }
+                    NestedMeta::Meta(Meta::NameValue(pair)) => {
+                        let key = match pair.path.get_ident() {
+                            Some(key) if keys.contains(&key.to_string().as_str()) => {
+                                key.to_string()
+                            }
+                            _ => {
+                                return Err(Error::new(
+                                    pair.path.span(),
+                                    format!("expected one of {}, or new", keys.join(", ")),
+                                ))
+                            }
+                        };
+                        match pair.lit {
+                            Lit::Str(value) => {
+                                settings.values.insert(key, (value.value(), value.span()))
+                            }
+                            lit => return Err(Error::new(lit.span(), "expected a string")),
+                        };
+                    }
+                    nested => {
+                        return Err(Error::new(
+                            nested.span(),
+                            format!("expected one of {}, or new", keys.join(", ")),
+                        ))
+                    }
+                }
+            }
+        }
+        if !found {
+            return Err(Error::new(
+                input.ident.span(),
+                format!("missing #[{}(...)] attribute", name),
+            ));
+        }
+        Ok(settings)
+    }
+
+    /// Parses the value of `key` as `T`, such as a type or a method name.
+    fn get<T: syn::parse::Parse>(&self, key: &str) -> Result<Option<T>, Error> {
+        match self.values.get(key) {
+            Some((value, span)) => syn::parse_str(value)
+                .map(Some)
+                .map_err(|err| Error::new(*span, format!("invalid {}: {}", key, err))),
+            None => Ok(None),
+        }
+    }
+
+    fn require<T: syn::parse::Parse>(&self, input: &DeriveInput, key: &str) -> Result<T, Error> {
+        self.get(key)?
+            .ok_or_else(|| Error::new(input.ident.span(), format!("missing {} = \"...\"", key)))
+    }
+}
+
+/// `pub fn new()`, building the struct with `Default`, as graphgen builds the nodes of a graph.
+fn gen_new(input: &DeriveInput, settings: &Settings) -> TokenStream2 {
+    if !settings.new {
+        return quote!();
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            pub fn new() -> Self {
+                ::std::default::Default::default()
+            }
+        }
+    }
+}
+
+/// Implements `route_rs_runtime::processor::Processor` by calling a method of the struct.
+///
+/// `#[processor(input = "Type", output = "Type", process = "method")]` gives the packet types,
+/// `output` defaulting to `input`, and the method processing each packet, which takes
+/// `&mut self` and an input, and returns an `Option` of an output. With `new`, a `new` building
+/// the struct with `Default` is generated too.
+#[proc_macro_derive(Processor, attributes(processor))]
+pub fn derive_processor(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    processor(&input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+fn processor(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let settings = Settings::parse(input, "processor", &["input", "output", "process"])?;
+    let input_type: syn::Type = settings.require(input, "input")?;
+    let output_type: syn::Type = settings
+        .get("output")?
+        .unwrap_or_else(|| input_type.clone());
+    let process: syn::Ident = settings.require(input, "process")?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let new = gen_new(input, &settings);
+    Ok(quote! {
+        impl #impl_generics route_rs_runtime::processor::Processor for #name #ty_generics #where_clause {
+            type Input = #input_type;
+            type Output = #output_type;
+
+            fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+                #name::#process(self, packet)
+            }
+        }
+
+        #new
+    })
+}
+
+/// Implements `route_rs_runtime::classifier::Classifier` by calling a method of the struct.
+///
+/// `#[classifier(packet = "Type", class = "Type", classify = "method")]` gives the packet and
+/// class types, and the method classifying each packet, which takes `&self` and a reference to a
+/// packet, and returns its class. With `new`, a `new` building the struct with `Default` is
+/// generated too.
+#[proc_macro_derive(Classifier, attributes(classifier))]
+pub fn derive_classifier(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    classifier(&input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+fn classifier(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let settings = Settings::parse(input, "classifier", &["packet", "class", "classify"])?;
+    let packet: syn::Type = settings.require(input, "packet")?;
+    let class: syn::Type = settings.require(input, "class")?;
+    let classify: syn::Ident = settings.require(input, "classify")?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let new = gen_new(input, &settings);
+    Ok(quote! {
+        impl #impl_generics route_rs_runtime::classifier::Classifier for #name #ty_generics #where_clause {
+            type Packet = #packet;
+            type Class = #class;
+
+            fn classify(&self, packet: &Self::Packet) -> Self::Class {
+                #name::#classify(self, packet)
+            }
+        }
+
+        #new
+    })
+}
+
+/// Implements `route_rs_runtime::classifier::Dispatch` for an enum, sending each variant to an
+/// egressor of its own, numbered in the order the variants are declared.
+///
+/// A variant with `#[dispatch(port = N)]` goes to egressor N instead, so several variants can
+/// share one. The enum has as many egressors as one more than the highest it sends to.
+#[proc_macro_derive(Dispatch, attributes(dispatch))]
+pub fn derive_dispatch(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    dispatch(&input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// The port of a `#[dispatch(port = N)]` attribute on a variant, if it has one.
+fn variant_port(variant: &syn::Variant) -> Result<Option<usize>, Error> {
+    let mut port = None;
+    for attr in variant
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("dispatch"))
+    {
+        let invalid = || Error::new(attr.span(), "expected #[dispatch(port = N)]");
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            _ => return Err(invalid()),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("port") => {
+                    match pair.lit {
+                        Lit::Int(int) => port = Some(int.base10_parse()?),
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+        }
+    }
+    Ok(port)
+}
+
+fn dispatch(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let data = match &input.data {
+        Data::Enum(data) if !data.variants.is_empty() => data,
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "Dispatch can only be derived for enums with variants",
+            ))
+        }
+    };
+    let mut arms = vec![];
+    let mut ports = 0;
+    for (index, variant) in data.variants.iter().enumerate() {
+        let port = variant_port(variant)?.unwrap_or(index);
+        ports = ports.max(port + 1);
+        let ident = &variant.ident;
+        let pattern = match variant.fields {
+            Fields::Named(_) => quote!(Self::#ident { .. }),
+            Fields::Unnamed(_) => quote!(Self::#ident(..)),
+            Fields::Unit => quote!(Self::#ident),
+        };
+        arms.push(quote!(#pattern => #port,));
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics route_rs_runtime::classifier::Dispatch for #name #ty_generics #where_clause {
+            const PORTS: usize = #ports;
+
+            fn port(&self) -> usize {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+/// The module a `pipeline!` is declared in, and the statements of its graph.
+struct PipelineInput {
+    vis: syn::Visibility,
+    name: syn::Ident,
+    statements: TokenStream2,
+}
+
+impl Parse for PipelineInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let vis = input.parse()?;
+        input.parse::<syn::Token![mod]>()?;
+        let name = input.parse()?;
+        input.parse::<syn::Token![;]>()?;
+        Ok(PipelineInput {
+            vis,
+            name,
+            statements: input.parse()?,
+        })
+    }
+}
+
+/// Declares a pipeline in Rust, for routers too small to be worth a graph file and a build script.
+/// It takes the name of a module, and the statements of a GraphViz digraph, as graphgen reads
+/// them, and expands to the module holding the `Pipeline` graphgen would generate from the graph:
+///
+/// ```
+/// use route_rs_runtime::classifier::Even;
+/// use route_rs_runtime::processor::Identity;
+///
+/// route_rs_derive::pipeline! {
+///     mod evens;
+///
+///     input [kind=io, label=i32];
+///     output [kind=io, label=i32];
+///     identity [label=Identity];
+///     even [kind=classifier, label=Even];
+///     odd [kind=drop, label=i32];
+///     input -> identity -> even;
+///     even -> output [label=true];
+///     even -> odd [label=false];
+/// }
+/// # fn main() {}
+/// ```
+///
+/// The graph is checked as graphgen checks it, failing to compile with each problem found, but
+/// without the types of its Processors and Classifiers, which are left to the compiler. The module
+/// glob imports its parent, so they are named as they are there. Like a generated pipeline, it
+/// needs `crossbeam` and `tokio` as dependencies of the crate.
+#[proc_macro]
+pub fn pipeline(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as PipelineInput);
+    let span = input.statements.span();
+    let graph = PipelineGraph::from_dot(&format!("digraph {{ {} }}", input.statements));
+    let diagnostics = graph.check(&TypeIndex::new());
+    if !diagnostics.is_empty() {
+        let errors = diagnostics.iter().map(|diagnostic| {
+            Error::new(span, format!("invalid pipeline: {}", diagnostic)).to_compile_error()
+        });
+        return quote!(#(#errors)*).into();
+    }
+
+    let source = generate(&graph, &GenerateOptions::new().local_modules(vec![]));
+    let pipeline: TokenStream2 = match source.parse() {
+        Ok(pipeline) => pipeline,
+        Err(err) => {
+            return Error::new(span, format!("could not generate pipeline: {}", err))
+                .to_compile_error()
+                .into()
+        }
+    };
+    let vis = &input.vis;
+    let name = &input.name;
+    quote!(
+        #vis mod #name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #pipeline
+        }
+    )
+    .into()
+}
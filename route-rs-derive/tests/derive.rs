@@ -0,0 +1,101 @@
+use route_rs_derive::{Classifier, Dispatch, Processor};
+use route_rs_runtime::classifier::{Classifier, Dispatch};
+use route_rs_runtime::link::primitive::ClassifyLink;
+use route_rs_runtime::link::LinkBuilder;
+use route_rs_runtime::processor::Processor;
+use route_rs_runtime::utils::test::harness::{initialize_runtime, run_link};
+use route_rs_runtime::utils::test::packet_generators::immediate_stream;
+
+#[derive(Processor, Default)]
+#[processor(input = "i32", output = "String", process = "describe", new)]
+struct Describe {
+    seen: usize,
+}
+
+impl Describe {
+    fn describe(&mut self, packet: i32) -> Option<String> {
+        self.seen += 1;
+        Some(format!("{}/{}", packet, self.seen))
+    }
+}
+
+#[derive(Processor)]
+#[processor(input = "T", process = "keep")]
+struct KeepSome<T: Send + Clone> {
+    every: usize,
+    seen: usize,
+    phantom: std::marker::PhantomData<T>,
+}
+
+impl<T: Send + Clone> KeepSome<T> {
+    fn keep(&mut self, packet: T) -> Option<T> {
+        self.seen += 1;
+        if self.seen.is_multiple_of(self.every) {
+            Some(packet)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Dispatch)]
+enum Size {
+    Small,
+    #[dispatch(port = 0)]
+    Tiny,
+    Large {
+        by: i32,
+    },
+    Negative(i32),
+}
+
+#[derive(Classifier, Default)]
+#[classifier(packet = "i32", class = "Size", classify = "size", new)]
+struct BySize {}
+
+impl BySize {
+    fn size(&self, packet: &i32) -> Size {
+        match *packet {
+            packet if packet < 0 => Size::Negative(packet),
+            packet if packet < 2 => Size::Tiny,
+            packet if packet < 10 => Size::Small,
+            packet => Size::Large { by: packet - 10 },
+        }
+    }
+}
+
+#[test]
+fn derives_processors() {
+    let mut describe = Describe::new();
+    assert_eq!(describe.process(7), Some(String::from("7/1")));
+    assert_eq!(describe.process(9), Some(String::from("9/2")));
+
+    let mut keep = KeepSome {
+        every: 2,
+        seen: 0,
+        phantom: std::marker::PhantomData,
+    };
+    assert_eq!(keep.process("a"), None);
+    assert_eq!(keep.process("b"), Some("b"));
+}
+
+#[test]
+fn derives_classifiers_and_dispatch() {
+    assert_eq!(BySize::new().classify(&12), Size::Large { by: 2 });
+    assert_eq!(Size::PORTS, 4);
+    assert_eq!(Size::Tiny.port(), 0);
+    assert_eq!(Size::Large { by: 0 }.port(), 2);
+    assert_eq!(Size::Negative(-1).port(), 3);
+
+    let mut runtime = initialize_runtime();
+    let results = runtime.block_on(async {
+        let link = ClassifyLink::new()
+            .ingressor(immediate_stream(vec![-3, 0, 5, 1, 20, 9]))
+            .classifier(BySize::new())
+            .dispatch()
+            .build_link();
+
+        run_link(link).await
+    });
+    assert_eq!(results, vec![vec![0, 5, 1, 9], vec![], vec![20], vec![-3]]);
+}
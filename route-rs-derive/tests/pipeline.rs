@@ -0,0 +1,76 @@
+use crossbeam::crossbeam_channel;
+use route_rs_runtime::classifier::Even;
+use route_rs_runtime::pipeline::{MultiRunner, Runner};
+use route_rs_runtime::processor::{FnProcessor, Identity};
+use std::sync::atomic::Ordering;
+
+/// `FnProcessor`s are built by factories, as graphgen builds them, so one per class.
+struct Double;
+
+impl Double {
+    #[allow(clippy::new_ret_no_self)]
+    fn new() -> FnProcessor<i32, i32, fn(i32) -> Option<i32>> {
+        FnProcessor::new(|packet| Some(packet * 2))
+    }
+}
+
+route_rs_derive::pipeline! {
+    mod evens;
+
+    input [kind=io, label=i32];
+    output [kind=io, label=i32];
+    identity [label=Identity];
+    even [kind=classifier, label=Even];
+    odd [kind=drop, label=i32];
+    input -> identity -> even;
+    even -> output [label=true];
+    even -> odd [label=false];
+}
+
+route_rs_derive::pipeline! {
+    pub(crate) mod doubled;
+
+    lan [kind=io, label=i32];
+    wan [kind=io, label=i32];
+    to_lan [kind=io, label=i32];
+    to_wan [kind=io, label=i32];
+    double [label=Double];
+    lan -> double -> to_wan;
+    wan -> to_lan;
+}
+
+#[test]
+fn runs_declared_pipeline() {
+    let (input, input_receiver) = crossbeam_channel::unbounded();
+    let (output_sender, output) = crossbeam_channel::unbounded();
+    for packet in 0..10 {
+        input.send(packet).unwrap();
+    }
+    drop(input);
+
+    evens::Pipeline::run(input_receiver, output_sender);
+
+    let mut received: Vec<i32> = output.try_iter().collect();
+    received.sort_unstable();
+    assert_eq!(received, vec![0, 2, 4, 6, 8]);
+    assert_eq!(evens::DROPPED_ODD.load(Ordering::Relaxed), 5);
+}
+
+#[test]
+fn runs_declared_multi_pipeline() {
+    let (lan, lan_receiver) = crossbeam_channel::unbounded();
+    let (wan, wan_receiver) = crossbeam_channel::unbounded();
+    let (to_lan_sender, to_lan) = crossbeam_channel::unbounded();
+    let (to_wan_sender, to_wan) = crossbeam_channel::unbounded();
+    lan.send(1).unwrap();
+    wan.send(7).unwrap();
+    drop((lan, wan));
+
+    doubled::Pipeline::run(
+        vec![lan_receiver, wan_receiver],
+        vec![to_lan_sender, to_wan_sender],
+    );
+
+    assert_eq!(to_wan.try_iter().collect::<Vec<_>>(), vec![2]);
+    assert_eq!(to_lan.try_iter().collect::<Vec<_>>(), vec![7]);
+}
@@ -0,0 +1,290 @@
+use crate::check::TypeIndex;
+use crate::generate::{generate, GenerateOptions};
+use crate::pipeline_graph::PipelineGraph;
+use std::path::{Path, PathBuf};
+
+/// Generates a pipeline from a build script, so the generated source never goes stale or needs
+/// to be checked in. The graph's format is taken from its extension, see
+/// `PipelineGraph::from_file`.
+///
+/// ```no_run
+/// // In the main of build.rs
+/// route_rs_graphgen::Build::new().graph("src/pipeline.dot").compile();
+/// ```
+///
+/// The pipeline is written to `OUT_DIR`, and included with
+/// `route_rs_runtime::include_pipeline!()`. Cargo reruns the build script whenever the graph or
+/// the local modules it is checked against change.
+pub struct Build {
+    graph: Option<PathBuf>,
+    src_dir: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
+    out_file: Option<String>,
+    local_modules: Option<Vec<String>>,
+    runtime_modules: Option<Vec<String>>,
+    instrument: bool,
+    factories: Vec<(String, String)>,
+}
+
+impl Default for Build {
+    fn default() -> Self {
+        Build::new()
+    }
+}
+
+impl Build {
+    pub fn new() -> Self {
+        Build {
+            graph: None,
+            src_dir: None,
+            out_dir: None,
+            out_file: None,
+            local_modules: None,
+            runtime_modules: None,
+            instrument: false,
+            factories: vec![],
+        }
+    }
+
+    /// Path of the graph, relative to the crate root. Required.
+    pub fn graph<P: AsRef<Path>>(self, graph: P) -> Self {
+        Build {
+            graph: Some(graph.as_ref().to_path_buf()),
+            src_dir: self.src_dir,
+            out_dir: self.out_dir,
+            out_file: self.out_file,
+            local_modules: self.local_modules,
+            runtime_modules: self.runtime_modules,
+            instrument: self.instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// Directory of the local modules the graph is checked against, by default the graph's.
+    pub fn src_dir<P: AsRef<Path>>(self, src_dir: P) -> Self {
+        Build {
+            graph: self.graph,
+            src_dir: Some(src_dir.as_ref().to_path_buf()),
+            out_dir: self.out_dir,
+            out_file: self.out_file,
+            local_modules: self.local_modules,
+            runtime_modules: self.runtime_modules,
+            instrument: self.instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// Directory the pipeline is written to, by default `OUT_DIR`.
+    pub fn out_dir<P: AsRef<Path>>(self, out_dir: P) -> Self {
+        Build {
+            graph: self.graph,
+            src_dir: self.src_dir,
+            out_dir: Some(out_dir.as_ref().to_path_buf()),
+            out_file: self.out_file,
+            local_modules: self.local_modules,
+            runtime_modules: self.runtime_modules,
+            instrument: self.instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// Name of the file the pipeline is written to, by default `pipeline.rs`. Crates with several
+    /// pipelines give each its own, and include it with `include_pipeline!("name.rs")`.
+    pub fn out_file(self, out_file: &str) -> Self {
+        Build {
+            graph: self.graph,
+            src_dir: self.src_dir,
+            out_dir: self.out_dir,
+            out_file: Some(String::from(out_file)),
+            local_modules: self.local_modules,
+            runtime_modules: self.runtime_modules,
+            instrument: self.instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// See `GenerateOptions::local_modules`.
+    pub fn local_modules(self, local_modules: Vec<&str>) -> Self {
+        Build {
+            graph: self.graph,
+            src_dir: self.src_dir,
+            out_dir: self.out_dir,
+            out_file: self.out_file,
+            local_modules: Some(local_modules.into_iter().map(String::from).collect()),
+            runtime_modules: self.runtime_modules,
+            instrument: self.instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// See `GenerateOptions::runtime_modules`.
+    pub fn runtime_modules(self, runtime_modules: Vec<&str>) -> Self {
+        Build {
+            graph: self.graph,
+            src_dir: self.src_dir,
+            out_dir: self.out_dir,
+            out_file: self.out_file,
+            local_modules: self.local_modules,
+            runtime_modules: Some(runtime_modules.into_iter().map(String::from).collect()),
+            instrument: self.instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// See `GenerateOptions::instrument`.
+    pub fn instrument(self, instrument: bool) -> Self {
+        Build {
+            graph: self.graph,
+            src_dir: self.src_dir,
+            out_dir: self.out_dir,
+            out_file: self.out_file,
+            local_modules: self.local_modules,
+            runtime_modules: self.runtime_modules,
+            instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// See `GenerateOptions::factory`.
+    pub fn factory(self, class: &str, factory: &str) -> Self {
+        let mut factories = self.factories;
+        factories.push((String::from(class), String::from(factory)));
+        Build {
+            graph: self.graph,
+            src_dir: self.src_dir,
+            out_dir: self.out_dir,
+            out_file: self.out_file,
+            local_modules: self.local_modules,
+            runtime_modules: self.runtime_modules,
+            instrument: self.instrument,
+            factories,
+        }
+    }
+
+    /// Checks the graph and writes the generated pipeline, returning its path. Panics with every
+    /// problem `PipelineGraph::check` finds, which Cargo shows as the build script's error.
+    pub fn compile(self) -> PathBuf {
+        let graph_path = match self.graph {
+            Some(graph) => graph,
+            None => panic!("Build has no graph"),
+        };
+        let src_dir = match self.src_dir {
+            Some(src_dir) => src_dir,
+            None => graph_path.parent().unwrap().to_path_buf(),
+        };
+        let out_dir = match self.out_dir {
+            Some(out_dir) => out_dir,
+            None => match std::env::var_os("OUT_DIR") {
+                Some(out_dir) => PathBuf::from(out_dir),
+                None => panic!("OUT_DIR is not set, Build must be used from a build script"),
+            },
+        };
+        let options = GenerateOptions::new()
+            .source_graph(&graph_path)
+            .instrument(self.instrument);
+        let options = match &self.local_modules {
+            Some(modules) => options.local_modules(modules.iter().map(String::as_str).collect()),
+            None => options,
+        };
+        let options = match &self.runtime_modules {
+            Some(modules) => options.runtime_modules(modules.iter().map(String::as_str).collect()),
+            None => options,
+        };
+        let options = self
+            .factories
+            .iter()
+            .fold(options, |options, (class, factory)| {
+                options.factory(class, factory)
+            });
+
+        println!("cargo:rerun-if-changed={}", graph_path.display());
+        let local_modules: Vec<&str> = options.local_module_names();
+        for module in &local_modules {
+            println!(
+                "cargo:rerun-if-changed={}",
+                src_dir.join(format!("{}.rs", module)).display()
+            );
+            println!(
+                "cargo:rerun-if-changed={}",
+                src_dir.join(module).join("mod.rs").display()
+            );
+        }
+
+        let graph = PipelineGraph::from_file(&graph_path);
+        let diagnostics = graph.check(&TypeIndex::from_modules(&src_dir, &local_modules));
+        if !diagnostics.is_empty() {
+            let messages: Vec<String> = diagnostics
+                .iter()
+                .map(|d| format!("{}: {}", graph_path.display(), d))
+                .collect();
+            panic!("Invalid pipeline graph\n{}", messages.join("\n"));
+        }
+
+        let out_path = out_dir.join(self.out_file.as_deref().unwrap_or("pipeline.rs"));
+        if let Err(err) = std::fs::write(&out_path, generate(&graph, &options)) {
+            panic!("Could not write {}: {}", out_path.display(), err);
+        }
+        out_path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_graph(example: &str, graph: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../examples")
+            .join(example)
+            .join("src")
+            .join(graph)
+    }
+
+    fn out_dir(name: &str) -> PathBuf {
+        let out_dir = std::env::temp_dir()
+            .join("route-rs-graphgen-build")
+            .join(name);
+        std::fs::create_dir_all(&out_dir).unwrap();
+        out_dir
+    }
+
+    #[test]
+    fn writes_pipeline() {
+        let out_path = Build::new()
+            .graph(example_graph("trivial-identity", "pipeline.yaml"))
+            .out_dir(out_dir("writes_pipeline"))
+            .out_file("identity.rs")
+            .local_modules(vec!["packets"])
+            .runtime_modules(vec!["processor"])
+            .compile();
+
+        assert!(out_path.ends_with("identity.rs"));
+        let source = std::fs::read_to_string(out_path).unwrap();
+        assert!(source.contains("Runner for Pipeline"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid pipeline graph")]
+    fn panics_on_invalid_graph() {
+        let graph = out_dir("panics_on_invalid_graph").join("pipeline.yaml");
+        std::fs::write(
+            &graph,
+            "nodes: [{ id: input, type: io, class: IntegerPacket }]",
+        )
+        .unwrap();
+
+        Build::new()
+            .graph(graph)
+            .out_dir(out_dir("panics_on_invalid_graph"))
+            .compile();
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown graph format")]
+    fn panics_on_unknown_format() {
+        Build::new()
+            .graph(example_graph("trivial-identity", "main.rs"))
+            .out_dir(out_dir("panics_on_unknown_format"))
+            .compile();
+    }
+}
@@ -0,0 +1,790 @@
+use crate::pipeline_graph::{EdgeData, NodeData, NodeKind, XmlNodeId};
+use petgraph::graph::NodeIndex;
+use petgraph::{Directed, Graph};
+use quote::ToTokens;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// The packet types a Processor or Classifier takes and gives. A type is None when it can't be
+/// resolved, for example because it depends on a generic parameter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeTypes {
+    pub input: Option<String>,
+    pub output: Option<String>,
+}
+
+/// The Input and Output types of the Processors and Classifiers implemented in a crate, read from
+/// the source of its local modules.
+#[derive(Default, Debug)]
+pub struct TypeIndex {
+    types: HashMap<String, NodeTypes>,
+    aliases: HashMap<String, String>,
+}
+
+impl TypeIndex {
+    pub fn new() -> Self {
+        TypeIndex::default()
+    }
+
+    /// Reads `<module>.rs` or `<module>/mod.rs` from `src_dir` for each module. Modules without
+    /// a source file of their own are skipped, their types just won't be checked.
+    pub fn from_modules(src_dir: &Path, modules: &[&str]) -> Self {
+        let mut index = TypeIndex::new();
+        for module in modules {
+            let candidates = [
+                src_dir.join(format!("{}.rs", module)),
+                src_dir.join(module).join("mod.rs"),
+            ];
+            if let Some(path) = candidates.iter().find(|p| p.is_file()) {
+                match std::fs::read_to_string(path) {
+                    Ok(source) => index.add_source(&source),
+                    Err(err) => panic!("Could not read {}: {}", path.display(), err),
+                }
+            }
+        }
+        index
+    }
+
+    /// Records the Processor and Classifier impls and the type aliases in a Rust source file,
+    /// including those in inline modules.
+    pub fn add_source(&mut self, source: &str) {
+        match syn::parse_file(source) {
+            Ok(file) => self.add_items(&file.items),
+            Err(err) => panic!("Could not parse local module: {}", err),
+        }
+    }
+
+    fn add_items(&mut self, items: &[syn::Item]) {
+        for item in items {
+            match item {
+                syn::Item::Impl(item_impl) => self.add_impl(item_impl),
+                syn::Item::Type(item_type) if item_type.generics.params.is_empty() => {
+                    self.aliases.insert(
+                        item_type.ident.to_string(),
+                        strip_paths(&item_type.ty.to_token_stream().to_string()),
+                    );
+                }
+                syn::Item::Mod(syn::ItemMod {
+                    content: Some((_, items)),
+                    ..
+                }) => self.add_items(items),
+                _ => {}
+            }
+        }
+    }
+
+    fn add_impl(&mut self, item_impl: &syn::ItemImpl) {
+        let trait_name = match &item_impl.trait_ {
+            Some((_, path, _)) => path.segments.last().unwrap().ident.to_string(),
+            None => return,
+        };
+        let class = match &*item_impl.self_ty {
+            syn::Type::Path(type_path) => type_path.path.segments.last().unwrap().ident.to_string(),
+            _ => return,
+        };
+        let generics: Vec<String> = item_impl
+            .generics
+            .type_params()
+            .map(|p| p.ident.to_string())
+            .collect();
+
+        let mut assoc_types = HashMap::new();
+        for impl_item in &item_impl.items {
+            if let syn::ImplItem::Type(assoc) = impl_item {
+                assoc_types.insert(
+                    assoc.ident.to_string(),
+                    compact(&assoc.ty.to_token_stream().to_string()),
+                );
+            }
+        }
+        let resolve = |name: &str| -> Option<String> {
+            let mut ty = assoc_types.get(name)?.to_owned();
+            // Follow references to the other associated types, like `type Output = Self::Input`.
+            for _ in 0..assoc_types.len() {
+                match ty.strip_prefix("Self::") {
+                    Some(other) => ty = assoc_types.get(other)?.to_owned(),
+                    None => break,
+                }
+            }
+            let ty = strip_paths(&ty);
+            let word = Regex::new(r"\w+").unwrap();
+            if word
+                .find_iter(&ty)
+                .any(|w| generics.contains(&w.as_str().to_owned()))
+            {
+                None
+            } else {
+                Some(ty)
+            }
+        };
+
+        let types = match trait_name.as_str() {
+            "Processor" => NodeTypes {
+                input: resolve("Input"),
+                output: resolve("Output"),
+            },
+            "Classifier" => NodeTypes {
+                input: resolve("Packet"),
+                output: resolve("Packet"),
+            },
+            _ => return,
+        };
+        self.types.insert(class, types);
+    }
+
+    /// Returns the types of a Processor or Classifier, or None if it wasn't found.
+    pub fn node_types(&self, class: &str) -> Option<NodeTypes> {
+        let types = self.types.get(class)?;
+        Some(NodeTypes {
+            input: types.input.as_ref().map(|t| self.unalias(t)),
+            output: types.output.as_ref().map(|t| self.unalias(t)),
+        })
+    }
+
+    /// Normalizes a type written in the graph, such as the packet type of an IO node, so that it
+    /// can be compared with the types of Processors.
+    pub fn graph_type(&self, ty: &str) -> Option<String> {
+        let ty = syn::parse_str::<syn::Type>(ty).ok()?;
+        Some(self.unalias(&strip_paths(&ty.to_token_stream().to_string())))
+    }
+
+    fn unalias(&self, ty: &str) -> String {
+        let mut ty = ty.to_owned();
+        for _ in 0..=self.aliases.len() {
+            match self.aliases.get(&ty) {
+                Some(aliased) => ty = aliased.to_owned(),
+                None => break,
+            }
+        }
+        ty
+    }
+}
+
+/// Writes a type's tokens the way they would usually be typed, `(Interface, Packet)` rather than
+/// `( Interface , Packet )`.
+fn compact(ty: &str) -> String {
+    let punctuation = Regex::new(r"\s*([^\w\s])\s*").unwrap();
+    punctuation.replace_all(ty, "$1").replace(",", ", ")
+}
+
+/// Compacts a type's tokens and removes module paths, so `crate :: packets :: Foo` and `Foo`
+/// compare equal.
+fn strip_paths(ty: &str) -> String {
+    let paths = Regex::new(r"\b(\w+::)+").unwrap();
+    paths.replace_all(&compact(ty), "").into_owned()
+}
+
+/// Where in the source graph a problem was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Graph,
+    Node(XmlNodeId),
+    Edge(XmlNodeId),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub location: Location,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Location::Graph => write!(f, "{}", self.message),
+            Location::Node(id) => write!(f, "node \"{}\": {}", id, self.message),
+            Location::Edge(id) => write!(f, "edge \"{}\": {}", id, self.message),
+        }
+    }
+}
+
+fn node_error<S: Into<String>>(node: &NodeData, message: S) -> Diagnostic {
+    Diagnostic {
+        location: Location::Node(node.xml_node_id.to_owned()),
+        message: format!("{} {}", node.node_class, message.into()),
+    }
+}
+
+fn edge_error<S: Into<String>>(edge: &EdgeData, message: S) -> Diagnostic {
+    Diagnostic {
+        location: Location::Edge(edge.xml_node_id.to_owned()),
+        message: format!(
+            "from \"{}\" to \"{}\" {}",
+            edge.source,
+            edge.target,
+            message.into()
+        ),
+    }
+}
+
+/// Checks a graph before any code is generated from it, so mistakes are reported against the
+/// nodes and edges they were made in rather than as compile errors in the generated pipeline.
+///
+/// Reports edges to missing nodes, nodes that aren't connected to both an input and an output,
+/// unlabeled classifier branches, cycles, and edges between nodes whose types don't match.
+pub fn check_graph(nodes: &[NodeData], edges: &[EdgeData], types: &TypeIndex) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    let node_map: HashMap<&str, &NodeData> =
+        nodes.iter().map(|n| (n.xml_node_id.as_str(), n)).collect();
+    let mut valid_edges = vec![];
+    for e in edges {
+        let mut valid = true;
+        for end in &[&e.source, &e.target] {
+            if !node_map.contains_key(end.as_str()) {
+                diagnostics.push(edge_error(e, format!("refers to missing node \"{}\"", end)));
+                valid = false;
+            }
+        }
+        if valid {
+            valid_edges.push(e);
+        }
+    }
+
+    let mut inputs = vec![];
+    let mut outputs = vec![];
+    for n in nodes {
+        let incoming: Vec<&&EdgeData> = valid_edges
+            .iter()
+            .filter(|e| e.target == n.xml_node_id)
+            .collect();
+        let outgoing: Vec<&&EdgeData> = valid_edges
+            .iter()
+            .filter(|e| e.source == n.xml_node_id)
+            .collect();
+        match n.node_kind {
+            NodeKind::IO => match (incoming.is_empty(), outgoing.is_empty()) {
+                (true, true) => diagnostics.push(node_error(n, "is not connected")),
+                (true, false) => inputs.push(n),
+                (false, true) => outputs.push(n),
+                (false, false) => diagnostics.push(node_error(
+                    n,
+                    "is an IO node with both incoming and outgoing edges",
+                )),
+            },
+            NodeKind::Processor | NodeKind::Classifier | NodeKind::Fork => {
+                if incoming.is_empty() {
+                    diagnostics.push(node_error(n, "has no incoming edges"));
+                }
+                if outgoing.is_empty() {
+                    diagnostics.push(node_error(n, "has no outgoing edges"));
+                }
+            }
+            NodeKind::Drop => {
+                if incoming.is_empty() {
+                    diagnostics.push(node_error(n, "has no incoming edges"));
+                }
+                for e in &outgoing {
+                    diagnostics.push(edge_error(
+                        e,
+                        format!("leaves drop {}, which discards every packet", n.node_class),
+                    ));
+                }
+            }
+            // Composites may well be sources or sinks of packets themselves.
+            NodeKind::Composite => {}
+        }
+        match n.node_kind {
+            NodeKind::Processor | NodeKind::Fork => {}
+            _ if n.capacity.is_some() => diagnostics.push(node_error(
+                n,
+                "has a capacity, which only processors and forks can have",
+            )),
+            _ => {}
+        }
+
+        match n.node_kind {
+            NodeKind::Composite => check_egressor_labels(n, &outgoing, &mut diagnostics),
+            // Any edge leaving a drop is already reported.
+            NodeKind::Drop => {}
+            NodeKind::Fork => {
+                for e in outgoing.iter().filter(|e| e.label.is_some()) {
+                    diagnostics.push(edge_error(
+                        e,
+                        format!(
+                            "has a label but leaves fork {}, which copies packets to every edge",
+                            n.node_class
+                        ),
+                    ));
+                }
+            }
+            _ => {
+                let classifier = n.node_kind == NodeKind::Classifier
+                    || (n.node_kind == NodeKind::Processor && outgoing.len() > 1);
+                for e in outgoing {
+                    match (&e.label, classifier) {
+                        (None, true) => diagnostics.push(edge_error(
+                            e,
+                            format!("leaves classifier {} without a branch label", n.node_class),
+                        )),
+                        (Some(label), false) => diagnostics.push(edge_error(
+                            e,
+                            format!(
+                                "has branch label {} but leaves {}, which is not a classifier",
+                                label, n.node_class
+                            ),
+                        )),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    for (io_nodes, direction, edges) in &[
+        (&inputs, "input", "outgoing"),
+        (&outputs, "output", "incoming"),
+    ] {
+        match io_nodes.split_first() {
+            None => diagnostics.push(Diagnostic {
+                location: Location::Graph,
+                message: format!(
+                    "expected at least one {} node, with only {} edges",
+                    direction, edges
+                ),
+            }),
+            Some((first, others)) => {
+                // Every channel of a pipeline carries the same packet type.
+                let first_type = types.graph_type(&first.node_class);
+                for n in others {
+                    if types.graph_type(&n.node_class) != first_type {
+                        diagnostics.push(node_error(
+                            n,
+                            format!(
+                                "is an {} of a different type than {} \"{}\"",
+                                direction, first.node_class, first.xml_node_id
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut graph = Graph::<&NodeData, (), Directed>::new();
+    let mut indices = HashMap::<&str, NodeIndex>::new();
+    for n in nodes {
+        indices.insert(&n.xml_node_id, graph.add_node(n));
+    }
+    for e in &valid_edges {
+        graph.add_edge(indices[e.source.as_str()], indices[e.target.as_str()], ());
+    }
+    for component in petgraph::algo::tarjan_scc(&graph) {
+        let self_loop = graph.contains_edge(component[0], component[0]);
+        if component.len() > 1 || self_loop {
+            let mut ids: Vec<&str> = component
+                .iter()
+                .map(|i| graph[*i].xml_node_id.as_str())
+                .collect();
+            ids.sort();
+            diagnostics.push(Diagnostic {
+                location: Location::Graph,
+                message: format!("nodes \"{}\" form a cycle", ids.join("\", \"")),
+            });
+        }
+    }
+
+    let node_types = |n: &NodeData| match n.node_kind {
+        NodeKind::IO | NodeKind::Fork | NodeKind::Drop => {
+            let ty = types.graph_type(&n.node_class);
+            NodeTypes {
+                input: ty.clone(),
+                output: ty,
+            }
+        }
+        NodeKind::Composite => NodeTypes {
+            input: None,
+            output: None,
+        },
+        _ => types.node_types(&n.node_class).unwrap_or(NodeTypes {
+            input: None,
+            output: None,
+        }),
+    };
+    for e in &valid_edges {
+        let source = node_map[e.source.as_str()];
+        let target = node_map[e.target.as_str()];
+        if let (Some(given), Some(taken)) = (node_types(source).output, node_types(target).input) {
+            if given != taken {
+                diagnostics.push(edge_error(
+                    e,
+                    format!(
+                        "connects mismatched types: {} gives {} but {} takes {}",
+                        source.node_class, given, target.node_class, taken
+                    ),
+                ));
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Edges leaving a composite are labeled with the index of the egressor they leave from, which
+/// may be left out when there is only one edge.
+fn check_egressor_labels(
+    node: &NodeData,
+    outgoing: &[&&EdgeData],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut used = vec![];
+    for e in outgoing {
+        match &e.label {
+            None if outgoing.len() > 1 => diagnostics.push(edge_error(
+                e,
+                format!(
+                    "leaves composite {} without an egressor index label",
+                    node.node_class
+                ),
+            )),
+            None => {}
+            Some(label) => match label.parse::<usize>() {
+                Ok(index) if used.contains(&index) => diagnostics.push(edge_error(
+                    e,
+                    format!(
+                        "leaves egressor {} of {}, which another edge already uses",
+                        index, node.node_class
+                    ),
+                )),
+                Ok(index) => used.push(index),
+                Err(_) => diagnostics.push(edge_error(
+                    e,
+                    format!(
+                        "has label {}, which is not an egressor index of {}",
+                        label, node.node_class
+                    ),
+                )),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROCESSORS: &str = r#"
+        use crate::packets::*;
+
+        pub type Tagged = (Interface, SimplePacket);
+
+        pub struct Tag;
+        impl Processor for Tag {
+            type Input = SimplePacket;
+            type Output = Tagged;
+        }
+
+        pub struct Untag;
+        impl route_rs_runtime::processor::Processor for Untag {
+            type Input = (Interface, packets::SimplePacket);
+            type Output = SimplePacket;
+        }
+
+        mod classifiers {
+            pub struct ByInterface;
+            impl Classifier for ByInterface {
+                type Packet = Tagged;
+                type Class = Interface;
+            }
+        }
+
+        pub struct Identity<A> {}
+        impl<A> Processor for Identity<A> {
+            type Input = A;
+            type Output = Self::Input;
+        }
+    "#;
+
+    fn node(id: &str, class: &str, node_kind: NodeKind) -> NodeData {
+        NodeData {
+            xml_node_id: String::from(id),
+            node_class: String::from(class),
+            node_kind,
+            args: vec![],
+            capacity: None,
+        }
+    }
+
+    fn edge(id: &str, source: &str, target: &str, label: Option<&str>) -> EdgeData {
+        EdgeData {
+            xml_node_id: String::from(id),
+            source: String::from(source),
+            target: String::from(target),
+            label: label.map(String::from),
+        }
+    }
+
+    fn index() -> TypeIndex {
+        let mut index = TypeIndex::new();
+        index.add_source(PROCESSORS);
+        index
+    }
+
+    #[test]
+    fn resolves_types() {
+        let index = index();
+
+        assert_eq!(
+            index.node_types("Tag"),
+            Some(NodeTypes {
+                input: Some(String::from("SimplePacket")),
+                output: Some(String::from("(Interface, SimplePacket)")),
+            })
+        );
+        assert_eq!(
+            index.node_types("Untag").unwrap().input,
+            index.node_types("Tag").unwrap().output
+        );
+        assert!(index.node_types("ByInterface").unwrap().output.is_some());
+        assert_eq!(
+            index.node_types("Identity"),
+            Some(NodeTypes {
+                input: None,
+                output: None,
+            })
+        );
+        assert_eq!(index.node_types("Missing"), None);
+    }
+
+    #[test]
+    fn valid_graph() {
+        let nodes = vec![
+            node("in", "SimplePacket", NodeKind::IO),
+            node("tag", "Tag", NodeKind::Processor),
+            node("classify", "ByInterface", NodeKind::Processor),
+            node("untag", "Untag", NodeKind::Processor),
+            node("identity", "Identity", NodeKind::Processor),
+            node("out", "crate::packets::SimplePacket", NodeKind::IO),
+        ];
+        let edges = vec![
+            edge("1", "in", "tag", None),
+            edge("2", "tag", "classify", None),
+            edge("3", "classify", "untag", Some("Interface::LAN")),
+            edge("4", "classify", "identity", Some("_")),
+            edge("5", "identity", "untag", None),
+            edge("6", "untag", "out", None),
+        ];
+
+        assert_eq!(check_graph(&nodes, &edges, &index()), vec![]);
+    }
+
+    #[test]
+    fn mismatched_types() {
+        let nodes = vec![
+            node("in", "SimplePacket", NodeKind::IO),
+            node("untag", "Untag", NodeKind::Processor),
+            node("out", "SimplePacket", NodeKind::IO),
+        ];
+        let edges = vec![
+            edge("1", "in", "untag", None),
+            edge("2", "untag", "out", None),
+        ];
+
+        let diagnostics = check_graph(&nodes, &edges, &index());
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].location, Location::Edge(String::from("1")));
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "edge \"1\": from \"in\" to \"untag\" connects mismatched types: \
+             SimplePacket gives SimplePacket but Untag takes (Interface, SimplePacket)"
+        );
+    }
+
+    #[test]
+    fn dangling_nodes_and_edges() {
+        let nodes = vec![
+            node("in", "SimplePacket", NodeKind::IO),
+            node("tag", "Tag", NodeKind::Processor),
+            node("stray", "Untag", NodeKind::Processor),
+            node("out", "Tagged", NodeKind::IO),
+        ];
+        let edges = vec![
+            edge("1", "in", "tag", None),
+            edge("2", "tag", "out", None),
+            edge("3", "tag", "missing", None),
+        ];
+
+        let diagnostics: Vec<String> = check_graph(&nodes, &edges, &index())
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                "edge \"3\": from \"tag\" to \"missing\" refers to missing node \"missing\"",
+                "node \"stray\": Untag has no incoming edges",
+                "node \"stray\": Untag has no outgoing edges",
+            ]
+        );
+    }
+
+    #[test]
+    fn branch_labels() {
+        let nodes = vec![
+            node("in", "Tagged", NodeKind::IO),
+            node("classify", "ByInterface", NodeKind::Processor),
+            node("identity", "Identity", NodeKind::Processor),
+            node("out", "Tagged", NodeKind::IO),
+        ];
+        let edges = vec![
+            edge("1", "in", "classify", Some("Interface::LAN")),
+            edge("2", "classify", "identity", None),
+            edge("3", "classify", "out", Some("_")),
+            edge("4", "identity", "out", None),
+        ];
+
+        let locations: Vec<Location> = check_graph(&nodes, &edges, &index())
+            .into_iter()
+            .map(|d| d.location)
+            .collect();
+
+        assert_eq!(
+            locations,
+            vec![
+                Location::Edge(String::from("1")),
+                Location::Edge(String::from("2")),
+            ]
+        );
+    }
+
+    #[test]
+    fn forks_and_composites() {
+        let mut queue = node("queue", "Tag", NodeKind::Processor);
+        queue.capacity = Some(20);
+        let mut classify = node("classify", "ByInterface", NodeKind::Classifier);
+        classify.capacity = Some(20);
+        let nodes = vec![
+            node("in", "SimplePacket", NodeKind::IO),
+            node("fork", "SimplePacket", NodeKind::Fork),
+            queue,
+            node("tee", "crate::links::Tee", NodeKind::Composite),
+            classify,
+            node("out", "Tagged", NodeKind::IO),
+        ];
+        let edges = vec![
+            edge("1", "in", "fork", None),
+            edge("2", "fork", "queue", None),
+            edge("3", "fork", "tee", Some("Interface::LAN")),
+            edge("4", "queue", "classify", None),
+            edge("5", "classify", "out", Some("_")),
+            edge("6", "tee", "out", Some("0")),
+            edge("7", "tee", "out", Some("0")),
+            edge("8", "tee", "out", Some("last")),
+        ];
+
+        let diagnostics: Vec<String> = check_graph(&nodes, &edges, &index())
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                "edge \"3\": from \"fork\" to \"tee\" has a label but leaves fork SimplePacket, \
+                 which copies packets to every edge",
+                "edge \"7\": from \"tee\" to \"out\" leaves egressor 0 of crate::links::Tee, \
+                 which another edge already uses",
+                "edge \"8\": from \"tee\" to \"out\" has label last, which is not an egressor \
+                 index of crate::links::Tee",
+                "node \"classify\": ByInterface has a capacity, which only processors and forks \
+                 can have",
+            ]
+        );
+    }
+
+    #[test]
+    fn drops() {
+        let nodes = vec![
+            node("in", "SimplePacket", NodeKind::IO),
+            node("tag", "Tag", NodeKind::Processor),
+            node("classify", "ByInterface", NodeKind::Classifier),
+            node("discard", "Tagged", NodeKind::Drop),
+            node("discard-untagged", "SimplePacket", NodeKind::Drop),
+            node("out", "Tagged", NodeKind::IO),
+        ];
+        let edges = vec![
+            edge("1", "in", "tag", None),
+            edge("2", "tag", "classify", None),
+            edge("3", "classify", "out", Some("Interface::LAN")),
+            edge("4", "classify", "discard", Some("_")),
+            edge("5", "discard", "out", None),
+            edge("6", "classify", "discard-untagged", Some("Interface::WAN")),
+        ];
+
+        let diagnostics: Vec<String> = check_graph(&nodes, &edges, &index())
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                "edge \"5\": from \"discard\" to \"out\" leaves drop Tagged, which discards every \
+                 packet",
+                "edge \"6\": from \"classify\" to \"discard-untagged\" connects mismatched types: \
+                 ByInterface gives (Interface, SimplePacket) but SimplePacket takes \
+                 SimplePacket",
+            ]
+        );
+    }
+
+    #[test]
+    fn io_nodes() {
+        let nodes = vec![
+            node("in-0", "SimplePacket", NodeKind::IO),
+            node("in-1", "packets::SimplePacket", NodeKind::IO),
+            node("tag", "Tag", NodeKind::Processor),
+            node("out-0", "SimplePacket", NodeKind::IO),
+            node("out-1", "Tagged", NodeKind::IO),
+        ];
+        let edges = vec![
+            edge("1", "in-0", "out-0", None),
+            edge("2", "in-1", "out-0", None),
+            edge("3", "tag", "out-1", None),
+        ];
+
+        let diagnostics: Vec<String> = check_graph(&nodes, &edges, &index())
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                "node \"tag\": Tag has no incoming edges",
+                "node \"out-1\": Tagged is an output of a different type than SimplePacket \"out-0\"",
+            ]
+        );
+
+        let diagnostics = check_graph(&nodes[..3], &[], &index());
+        assert!(diagnostics.contains(&Diagnostic {
+            location: Location::Graph,
+            message: String::from("expected at least one output node, with only incoming edges"),
+        }));
+    }
+
+    #[test]
+    fn cycles() {
+        let nodes = vec![
+            node("in", "SimplePacket", NodeKind::IO),
+            node("a", "Identity", NodeKind::Processor),
+            node("b", "Identity", NodeKind::Processor),
+            node("out", "SimplePacket", NodeKind::IO),
+        ];
+        let edges = vec![
+            edge("1", "in", "a", None),
+            edge("2", "a", "b", None),
+            edge("3", "b", "a", Some("Loop")),
+            edge("4", "b", "out", Some("_")),
+        ];
+
+        let diagnostics = check_graph(&nodes, &edges, &TypeIndex::new());
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].location, Location::Graph);
+        assert_eq!(diagnostics[0].message, "nodes \"a\", \"b\" form a cycle");
+    }
+}
@@ -364,16 +364,27 @@ pub fn expr_path_ident(id: &str) -> syn::Expr {
 }
 
 pub fn builder(base: syn::Ident, setters: Vec<(syn::Ident, Vec<syn::Expr>)>) -> syn::Expr {
-    let mut expr_accum = syn::Expr::Call(syn::ExprCall {
-        attrs: vec![],
-        func: Box::new(syn::Expr::Path(syn::ExprPath {
+    builder_from(
+        syn::Expr::Call(syn::ExprCall {
             attrs: vec![],
-            qself: None,
-            path: path(vec![(base, None), (ident("new"), None)]),
-        })),
-        paren_token: syn::token::Paren { span: fake_span() },
-        args: Default::default(),
-    });
+            func: Box::new(syn::Expr::Path(syn::ExprPath {
+                attrs: vec![],
+                qself: None,
+                path: path(vec![(base, None), (ident("new"), None)]),
+            })),
+            paren_token: syn::token::Paren { span: fake_span() },
+            args: Default::default(),
+        }),
+        setters,
+    )
+}
+
+/// Like `builder`, but starts from an arbitrary expression rather than `Base::new()`.
+pub fn builder_from(
+    constructor: syn::Expr,
+    setters: Vec<(syn::Ident, Vec<syn::Expr>)>,
+) -> syn::Expr {
+    let mut expr_accum = constructor;
 
     for (method, args) in setters {
         expr_accum = syn::Expr::MethodCall(syn::ExprMethodCall {
@@ -410,6 +421,11 @@ pub fn build_link(
     setters: Vec<(syn::Ident, Vec<syn::Expr>)>,
     num_egressors: usize,
 ) -> Vec<syn::Stmt> {
+    build_link_from(index, builder(ident(link_type), setters), num_egressors)
+}
+
+/// Like `build_link`, for a link built by an arbitrary builder expression, see `builder_from`.
+pub fn build_link_from(index: usize, builder: syn::Expr, num_egressors: usize) -> Vec<syn::Stmt> {
     let mut stmts = vec![];
 
     stmts.push(syn::Stmt::Local(syn::Local {
@@ -446,7 +462,7 @@ pub fn build_link(
             syn::token::Eq {
                 spans: [fake_span()],
             },
-            Box::new(builder(ident(link_type), setters)),
+            Box::new(builder),
         )),
         semi_token: syn::token::Semi {
             spans: [fake_span()],
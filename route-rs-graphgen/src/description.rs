@@ -0,0 +1,238 @@
+use crate::pipeline_graph::{config_ref, EdgeData, NodeData, NodeKind};
+use serde::Deserialize;
+
+/// A pipeline written out as plain data, for tools that would rather emit JSON or YAML than draw a
+/// diagram. `pipeline.schema.json` describes the same structure as a JSON Schema.
+///
+/// ```yaml
+/// nodes:
+///   - { id: input, type: io, class: IntegerPacket }
+///   - { id: identity, class: Identity }
+///   - { id: output, type: io, class: IntegerPacket }
+/// edges:
+///   - { source: input, target: identity }
+///   - { source: identity, target: output }
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineDescription {
+    pub nodes: Vec<NodeDescription>,
+    #[serde(default)]
+    pub edges: Vec<EdgeDescription>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NodeDescription {
+    pub id: String,
+    /// Defaults to a Processor.
+    #[serde(rename = "type")]
+    pub kind: Option<NodeKindDescription>,
+    /// The Processor or Classifier type, the packet type of an IO, fork or drop node, or the path
+    /// of a composite link.
+    pub class: String,
+    /// Arguments passed to the constructor of a Processor or Classifier. Booleans, numbers and
+    /// strings are passed as literals, `{config: name}` as a config reference, and
+    /// `{expr: source}` as a Rust expression.
+    #[serde(default)]
+    pub args: Vec<serde_json::Value>,
+    /// Queue capacity of a fork, or of a processor to run it in a QueueLink.
+    pub capacity: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKindDescription {
+    Io,
+    Processor,
+    Classifier,
+    Fork,
+    Composite,
+    Drop,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EdgeDescription {
+    pub id: Option<String>,
+    pub source: String,
+    pub target: String,
+    /// The classify branch this edge leaves from, as a match pattern, or the index of the egressor
+    /// it leaves a composite from.
+    pub label: Option<String>,
+}
+
+/// Renders a constructor argument as a Rust expression for a generated constructor call.
+fn arg_to_rust(node_id: &str, arg: &serde_json::Value) -> String {
+    match arg {
+        serde_json::Value::Bool(b) => b.to_string(),
+        serde_json::Value::Number(n) => n.to_string(),
+        // Debug formatting escapes a string the same way a Rust string literal would.
+        serde_json::Value::String(s) => format!("{:?}", s),
+        serde_json::Value::Object(object) if object.len() == 1 => {
+            match object.iter().next().unwrap() {
+                (key, serde_json::Value::String(name)) if key == "config" => config_ref(name),
+                (key, serde_json::Value::String(expr)) if key == "expr" => expr.to_owned(),
+                _ => panic!(
+                    "Node {} has an unsupported constructor argument {}",
+                    node_id, arg
+                ),
+            }
+        }
+        other => panic!(
+            "Node {} has an unsupported constructor argument {}",
+            node_id, other
+        ),
+    }
+}
+
+impl PipelineDescription {
+    pub fn from_json(source: &str) -> Self {
+        match serde_json::from_str(source) {
+            Ok(description) => description,
+            Err(err) => panic!("Invalid JSON pipeline description: {}", err),
+        }
+    }
+
+    pub fn from_yaml(source: &str) -> Self {
+        match serde_yaml::from_str(source) {
+            Ok(description) => description,
+            Err(err) => panic!("Invalid YAML pipeline description: {}", err),
+        }
+    }
+
+    /// Converts the description into the nodes and edges of a pipeline graph. Edges without an
+    /// ID are given one of the form `edge-N`, in the order they appear.
+    pub fn nodes_edges(self) -> (Vec<NodeData>, Vec<EdgeData>) {
+        let nodes = self
+            .nodes
+            .into_iter()
+            .map(|n| NodeData {
+                args: n.args.iter().map(|a| arg_to_rust(&n.id, a)).collect(),
+                capacity: match n.capacity {
+                    Some(0) => panic!("Node {} has capacity 0, must be positive", n.id),
+                    capacity => capacity,
+                },
+                xml_node_id: n.id,
+                node_class: n.class,
+                node_kind: match n.kind {
+                    Some(NodeKindDescription::Io) => NodeKind::IO,
+                    Some(NodeKindDescription::Processor) | None => NodeKind::Processor,
+                    Some(NodeKindDescription::Classifier) => NodeKind::Classifier,
+                    Some(NodeKindDescription::Fork) => NodeKind::Fork,
+                    Some(NodeKindDescription::Composite) => NodeKind::Composite,
+                    Some(NodeKindDescription::Drop) => NodeKind::Drop,
+                },
+            })
+            .collect();
+
+        let edges = self
+            .edges
+            .into_iter()
+            .enumerate()
+            .map(|(index, e)| EdgeData {
+                xml_node_id: e.id.unwrap_or_else(|| format!("edge-{}", index + 1)),
+                source: e.source,
+                target: e.target,
+                label: e.label,
+            })
+            .collect();
+
+        (nodes, edges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_description() {
+        let json = r#"{
+            "nodes": [
+                {"id": "input", "type": "io", "class": "IntegerPacket"},
+                {"id": "limit", "class": "RateLimiter", "args": [100, 2.5, true, "eth\"0"]},
+                {"id": "classify", "type": "classifier", "class": "Even"},
+                {"id": "queue", "class": "Identity", "capacity": 20},
+                {"id": "copy", "type": "fork", "class": "IntegerPacket"},
+                {"id": "composite", "type": "composite", "class": "crate::links::Tee"}
+            ],
+            "edges": [
+                {"source": "input", "target": "limit"},
+                {"id": "to-classify", "source": "limit", "target": "classify", "label": "true"}
+            ]
+        }"#;
+
+        let (nodes, edges) = PipelineDescription::from_json(json).nodes_edges();
+
+        assert_eq!(nodes.len(), 6);
+        assert_eq!(nodes[0].node_kind, NodeKind::IO);
+        assert_eq!(nodes[1].node_kind, NodeKind::Processor);
+        assert_eq!(nodes[1].args, vec!["100", "2.5", "true", r#""eth\"0""#]);
+        assert_eq!(nodes[2].node_kind, NodeKind::Classifier);
+        assert_eq!(nodes[3].capacity, Some(20));
+        assert_eq!(nodes[4].node_kind, NodeKind::Fork);
+        assert_eq!(nodes[5].node_kind, NodeKind::Composite);
+        assert_eq!(edges[0].xml_node_id, "edge-1");
+        assert_eq!(edges[1].xml_node_id, "to-classify");
+        assert_eq!(edges[1].label, Some(String::from("true")));
+    }
+
+    #[test]
+    fn yaml_description() {
+        let yaml = "
+            nodes:
+              - { id: input, type: io, class: IntegerPacket }
+              - id: identity
+                class: Identity
+            edges:
+              - source: input
+                target: identity
+        ";
+
+        let (nodes, edges) = PipelineDescription::from_yaml(yaml).nodes_edges();
+
+        assert_eq!(nodes[1].xml_node_id, "identity");
+        assert_eq!(nodes[1].node_class, "Identity");
+        assert!(nodes[1].args.is_empty());
+        assert_eq!(edges[0].target, "identity");
+    }
+
+    #[test]
+    fn config_and_expr_args() {
+        let yaml = r#"
+            nodes:
+              - id: route
+                class: SubnetRouter
+                args: [{ config: subnet }, { expr: "Duration::from_secs(1)" }]
+        "#;
+
+        let (nodes, _) = PipelineDescription::from_yaml(yaml).nodes_edges();
+
+        assert_eq!(
+            nodes[0].args,
+            vec!["crate::config::subnet()", "Duration::from_secs(1)"]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_unknown_args() {
+        PipelineDescription::from_json(
+            r#"{"nodes": [{"id": "a", "class": "A", "args": [{"env": "A"}]}]}"#,
+        )
+        .nodes_edges();
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_unknown_fields() {
+        PipelineDescription::from_json(r#"{"nodes": [{"id": "a", "class": "A", "shape": "box"}]}"#);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_unknown_node_types() {
+        PipelineDescription::from_yaml("nodes: [{ id: a, type: tee, class: A }]");
+    }
+}
@@ -0,0 +1,214 @@
+use crate::pipeline_graph::{EdgeData, NodeData, PipelineGraph};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A difference between two versions of a pipeline graph, found by `diff_graphs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    AddedNode(NodeData),
+    RemovedNode(NodeData),
+    /// A node with the same ID whose class, kind, arguments or capacity changed.
+    ChangedNode(NodeData, NodeData),
+    AddedEdge(EdgeData),
+    RemovedEdge(EdgeData),
+    /// An edge between the same nodes whose branch label changed.
+    RelabeledEdge(EdgeData, EdgeData),
+}
+
+fn describe_node(node: &NodeData) -> String {
+    let mut description = format!("{:?} {}", node.node_kind, node.node_class);
+    if !node.args.is_empty() {
+        description.push_str(&format!("({})", node.args.join(", ")));
+    }
+    if let Some(capacity) = node.capacity {
+        description.push_str(&format!(" capacity {}", capacity));
+    }
+    description
+}
+
+fn describe_edge(edge: &EdgeData) -> String {
+    match &edge.label {
+        Some(label) => format!("\"{}\" -> \"{}\" [{}]", edge.source, edge.target, label),
+        None => format!("\"{}\" -> \"{}\"", edge.source, edge.target),
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::AddedNode(n) => write!(f, "+ node \"{}\": {}", n.xml_node_id, describe_node(n)),
+            Change::RemovedNode(n) => {
+                write!(f, "- node \"{}\": {}", n.xml_node_id, describe_node(n))
+            }
+            Change::ChangedNode(old, new) => write!(
+                f,
+                "~ node \"{}\": {} => {}",
+                new.xml_node_id,
+                describe_node(old),
+                describe_node(new)
+            ),
+            Change::AddedEdge(e) => write!(f, "+ edge {}", describe_edge(e)),
+            Change::RemovedEdge(e) => write!(f, "- edge {}", describe_edge(e)),
+            Change::RelabeledEdge(old, new) => write!(
+                f,
+                "~ edge {} => [{}]",
+                describe_edge(old),
+                new.label.as_deref().unwrap_or("")
+            ),
+        }
+    }
+}
+
+/// Compares two graphs by what they generate rather than how they were drawn: nodes are matched by
+/// ID and edges by the nodes they connect, so moving a shape or renumbering an edge is no change.
+/// Changes are given with removed and changed nodes first, in the order of the old graph, then
+/// added nodes in the order of the new graph, then edges the same way.
+pub fn diff_graphs(old: &PipelineGraph, new: &PipelineGraph) -> Vec<Change> {
+    let mut changes = vec![];
+
+    let new_nodes: HashMap<&str, &NodeData> = new
+        .nodes()
+        .into_iter()
+        .map(|n| (n.xml_node_id.as_str(), n))
+        .collect();
+    let old_nodes: HashMap<&str, &NodeData> = old
+        .nodes()
+        .into_iter()
+        .map(|n| (n.xml_node_id.as_str(), n))
+        .collect();
+    for o in old.nodes() {
+        match new_nodes.get(o.xml_node_id.as_str()) {
+            None => changes.push(Change::RemovedNode(o.to_owned())),
+            Some(n) if !same_node(o, n) => {
+                changes.push(Change::ChangedNode(o.to_owned(), (*n).to_owned()))
+            }
+            Some(_) => {}
+        }
+    }
+    for n in new.nodes() {
+        if !old_nodes.contains_key(n.xml_node_id.as_str()) {
+            changes.push(Change::AddedNode(n.to_owned()));
+        }
+    }
+
+    // Edges with the same ends and label are the same edge. Of the rest, an edge that is removed
+    // and one that is added between the same nodes are a relabeling.
+    let mut added: Vec<&EdgeData> = new.edges();
+    let mut removed = vec![];
+    for o in old.edges() {
+        match added
+            .iter()
+            .position(|n| same_ends(o, n) && o.label == n.label)
+        {
+            Some(index) => {
+                added.remove(index);
+            }
+            None => removed.push(o),
+        }
+    }
+    for o in removed {
+        match added.iter().position(|n| same_ends(o, n)) {
+            Some(index) => changes.push(Change::RelabeledEdge(
+                o.to_owned(),
+                added.remove(index).to_owned(),
+            )),
+            None => changes.push(Change::RemovedEdge(o.to_owned())),
+        }
+    }
+    for n in added {
+        changes.push(Change::AddedEdge(n.to_owned()));
+    }
+
+    changes
+}
+
+fn same_node(a: &NodeData, b: &NodeData) -> bool {
+    a.node_class == b.node_class
+        && a.node_kind == b.node_kind
+        && a.args == b.args
+        && a.capacity == b.capacity
+}
+
+fn same_ends(a: &EdgeData, b: &EdgeData) -> bool {
+    a.source == b.source && a.target == b.target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = r#"digraph {
+        input [kind=io, label=Packet];
+        output [kind=io, label=Packet];
+        classify [kind=classifier, label=Even];
+        double [label=Double];
+        tag [label=Tag];
+        input -> classify;
+        classify -> double [label=true];
+        classify -> tag [label=false];
+        double -> output;
+        tag -> output;
+    }"#;
+
+    #[test]
+    fn same_graph() {
+        let old = PipelineGraph::from_dot(OLD);
+        let new = PipelineGraph::from_yaml(
+            "
+            nodes:
+              - { id: input, type: io, class: Packet }
+              - { id: output, type: io, class: Packet }
+              - { id: classify, type: classifier, class: Even }
+              - { id: double, class: Double }
+              - { id: tag, class: Tag }
+            edges:
+              - { source: tag, target: output }
+              - { source: input, target: classify }
+              - { source: classify, target: double, label: 'true' }
+              - { source: classify, target: tag, label: 'false' }
+              - { source: double, target: output }
+            ",
+        );
+
+        assert_eq!(diff_graphs(&old, &new), vec![]);
+    }
+
+    #[test]
+    fn changes() {
+        let old = PipelineGraph::from_dot(OLD);
+        let new = PipelineGraph::from_dot(
+            r#"digraph {
+                input [kind=io, label=Packet];
+                output [kind=io, label=Packet];
+                classify [kind=classifier, label=Odd];
+                double [label=Double, capacity=10];
+                log [label=Log];
+                input -> classify;
+                classify -> double [label=false];
+                classify -> log [label=true];
+                double -> output;
+                log -> output;
+            }"#,
+        );
+
+        let changes: Vec<String> = diff_graphs(&old, &new)
+            .iter()
+            .map(Change::to_string)
+            .collect();
+
+        assert_eq!(
+            changes,
+            vec![
+                "~ node \"classify\": Classifier Even => Classifier Odd",
+                "~ node \"double\": Processor Double => Processor Double capacity 10",
+                "- node \"tag\": Processor Tag",
+                "+ node \"log\": Processor Log",
+                "~ edge \"classify\" -> \"double\" [true] => [false]",
+                "- edge \"classify\" -> \"tag\" [false]",
+                "- edge \"tag\" -> \"output\"",
+                "+ edge \"classify\" -> \"log\" [true]",
+                "+ edge \"log\" -> \"output\"",
+            ]
+        );
+    }
+}
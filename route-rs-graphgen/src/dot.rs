@@ -0,0 +1,421 @@
+use crate::pipeline_graph::{parse_args, parse_capacity, EdgeData, NodeData, NodeKind};
+use std::collections::HashMap;
+
+/// The pieces of DOT syntax we care about. Keywords are left as identifiers and recognized by the
+/// parser, since DOT keywords are case insensitive and may also be quoted to use them as names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Id(String),
+    Quoted(String),
+    Edge,
+    Equals,
+    Separator,
+    OpenBracket,
+    CloseBracket,
+    OpenBrace,
+    CloseBrace,
+}
+
+/// Splits DOT source into tokens, dropping comments and whitespace.
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '#' => {
+                // Preprocessor style line, ignored by GraphViz as well.
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '-' if chars.peek() == Some(&'>') => {
+                chars.next();
+                tokens.push(Token::Edge);
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                panic!("Undirected edges are not supported, pipelines must be a digraph")
+            }
+            '=' => tokens.push(Token::Equals),
+            ';' | ',' => tokens.push(Token::Separator),
+            '[' => tokens.push(Token::OpenBracket),
+            ']' => tokens.push(Token::CloseBracket),
+            '{' => tokens.push(Token::OpenBrace),
+            '}' => tokens.push(Token::CloseBrace),
+            '"' => {
+                let mut value = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => match chars.next() {
+                            Some('"') => value.push('"'),
+                            Some('\n') => {}
+                            Some(other) => {
+                                value.push('\\');
+                                value.push(other);
+                            }
+                            None => break,
+                        },
+                        '"' => break,
+                        _ => value.push(c),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            '<' => panic!("HTML labels are not supported"),
+            c if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' => {
+                let mut value = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '.' {
+                        value.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Id(value));
+            }
+            c => panic!("Unexpected character {:?} in DOT source", c),
+        }
+    }
+
+    tokens
+}
+
+fn is_keyword(token: Option<&Token>, keyword: &str) -> bool {
+    match token {
+        Some(Token::Id(id)) => id.eq_ignore_ascii_case(keyword),
+        _ => false,
+    }
+}
+
+/// Walks the token stream of a single digraph, collecting nodes and edges in the order they first
+/// appear so generated code is stable across runs.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    node_order: Vec<String>,
+    node_attrs: HashMap<String, HashMap<String, String>>,
+    edges: Vec<(String, String, HashMap<String, String>)>,
+    node_defaults: HashMap<String, String>,
+    edge_defaults: HashMap<String, String>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) {
+        match self.next() {
+            Some(ref token) if *token == expected => {}
+            other => panic!("Expected {:?} in DOT source, found {:?}", expected, other),
+        }
+    }
+
+    fn id(&mut self) -> String {
+        match self.next() {
+            Some(Token::Id(id)) | Some(Token::Quoted(id)) => id,
+            other => panic!("Expected an ID in DOT source, found {:?}", other),
+        }
+    }
+
+    fn parse_graph(&mut self) {
+        if is_keyword(self.peek(), "strict") {
+            self.next();
+        }
+        if is_keyword(self.peek(), "graph") {
+            panic!("Undirected graphs are not supported, pipelines must be a digraph")
+        }
+        if !is_keyword(self.peek(), "digraph") {
+            panic!(
+                "DOT source must start with digraph, found {:?}",
+                self.peek()
+            )
+        }
+        self.next();
+        if self.peek() != Some(&Token::OpenBrace) {
+            self.id();
+        }
+        self.expect(Token::OpenBrace);
+
+        loop {
+            match self.peek() {
+                Some(Token::CloseBrace) => break,
+                Some(Token::Separator) => {
+                    self.next();
+                }
+                Some(_) => self.parse_stmt(),
+                None => panic!("Unexpected end of DOT source, missing closing brace"),
+            }
+        }
+        self.expect(Token::CloseBrace);
+    }
+
+    fn parse_stmt(&mut self) {
+        if is_keyword(self.peek(), "subgraph") || self.peek() == Some(&Token::OpenBrace) {
+            panic!("Subgraphs are not supported")
+        }
+
+        let first = self.id();
+        if first.eq_ignore_ascii_case("graph") && self.peek() == Some(&Token::OpenBracket) {
+            self.parse_attr_list();
+            return;
+        }
+        if first.eq_ignore_ascii_case("node") && self.peek() == Some(&Token::OpenBracket) {
+            let attrs = self.parse_attr_list();
+            self.node_defaults.extend(attrs);
+            return;
+        }
+        if first.eq_ignore_ascii_case("edge") && self.peek() == Some(&Token::OpenBracket) {
+            let attrs = self.parse_attr_list();
+            self.edge_defaults.extend(attrs);
+            return;
+        }
+        if self.peek() == Some(&Token::Equals) {
+            // Graph attribute, such as rankdir=LR, which has no meaning for a pipeline.
+            self.next();
+            self.id();
+            return;
+        }
+
+        let mut chain = vec![first];
+        while self.peek() == Some(&Token::Edge) {
+            self.next();
+            chain.push(self.id());
+        }
+        let attrs = if self.peek() == Some(&Token::OpenBracket) {
+            self.parse_attr_list()
+        } else {
+            HashMap::new()
+        };
+
+        if chain.len() == 1 {
+            self.declare_node(&chain[0]).extend(attrs);
+        } else {
+            for node in chain.iter() {
+                self.declare_node(node);
+            }
+            for pair in chain.windows(2) {
+                let mut edge_attrs = self.edge_defaults.clone();
+                edge_attrs.extend(attrs.clone());
+                self.edges
+                    .push((pair[0].to_owned(), pair[1].to_owned(), edge_attrs));
+            }
+        }
+    }
+
+    fn parse_attr_list(&mut self) -> HashMap<String, String> {
+        let mut attrs = HashMap::new();
+        while self.peek() == Some(&Token::OpenBracket) {
+            self.next();
+            loop {
+                match self.peek() {
+                    Some(Token::CloseBracket) => {
+                        self.next();
+                        break;
+                    }
+                    Some(Token::Separator) => {
+                        self.next();
+                    }
+                    _ => {
+                        let key = self.id();
+                        self.expect(Token::Equals);
+                        let value = self.id();
+                        attrs.insert(key, value);
+                    }
+                }
+            }
+        }
+        attrs
+    }
+
+    fn declare_node(&mut self, id: &str) -> &mut HashMap<String, String> {
+        if !self.node_attrs.contains_key(id) {
+            self.node_order.push(id.to_owned());
+            self.node_attrs
+                .insert(id.to_owned(), self.node_defaults.clone());
+        }
+        self.node_attrs.get_mut(id).unwrap()
+    }
+}
+
+/// Works out what a DOT node represents. An explicit `kind` attribute wins, see
+/// `NodeKind::from_name`, otherwise diamond shaped nodes are IO types and every other shape is a
+/// Processor, mirroring the rhombus convention of drawio graphs.
+fn node_kind(id: &str, attrs: &HashMap<String, String>) -> NodeKind {
+    match attrs.get("kind") {
+        Some(kind) => NodeKind::from_name(id, kind),
+        None => match attrs.get("shape").map(String::as_str) {
+            Some("diamond") | Some("Mdiamond") | Some("rhombus") => NodeKind::IO,
+            _ => NodeKind::Processor,
+        },
+    }
+}
+
+/// Given the source of a GraphViz digraph, returns a vector of nodes and a vector of edges
+/// extracted from that source.
+///
+/// A node's `label` names its type, and defaults to the node ID. Edge labels name the classify
+/// branch they leave from, just as edge values do in drawio graphs. Edges are given IDs of the form
+/// `edge-N` in the order they appear. A node's `args` attribute holds its constructor arguments,
+/// see `parse_args`, and its `capacity` attribute a queue capacity.
+pub fn nodes_edges_from_dot(source: &str) -> (Vec<NodeData>, Vec<EdgeData>) {
+    let mut parser = Parser {
+        tokens: tokenize(source),
+        position: 0,
+        node_order: vec![],
+        node_attrs: HashMap::new(),
+        edges: vec![],
+        node_defaults: HashMap::new(),
+        edge_defaults: HashMap::new(),
+    };
+    parser.parse_graph();
+
+    let nodes = parser
+        .node_order
+        .iter()
+        .map(|id| {
+            let attrs = &parser.node_attrs[id];
+            NodeData {
+                xml_node_id: id.to_owned(),
+                node_class: attrs.get("label").cloned().unwrap_or_else(|| id.to_owned()),
+                node_kind: node_kind(id, attrs),
+                args: match attrs.get("args") {
+                    Some(args) => parse_args(id, args),
+                    None => vec![],
+                },
+                capacity: attrs.get("capacity").map(|c| parse_capacity(id, c)),
+            }
+        })
+        .collect();
+
+    let edges = parser
+        .edges
+        .into_iter()
+        .enumerate()
+        .map(|(index, (source, target, attrs))| EdgeData {
+            xml_node_id: format!("edge-{}", index + 1),
+            source,
+            target,
+            label: attrs.get("label").cloned(),
+        })
+        .collect();
+
+    (nodes, edges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_and_labels() {
+        let dot = r#"
+            digraph pipeline {
+                rankdir=LR;
+                input [shape=diamond, label="(Interface, SimplePacket)"]
+                output [shape=Mdiamond label=Packet]
+                identity
+                classify [kind=classifier; label="ClassifyFoo"]
+                copy [kind=Fork, label=Packet, capacity=5]
+            }
+        "#;
+
+        let (nodes, edges) = nodes_edges_from_dot(dot);
+
+        assert!(edges.is_empty());
+        assert_eq!(nodes.len(), 5);
+        assert_eq!(nodes[0].xml_node_id, "input");
+        assert_eq!(nodes[0].node_class, "(Interface, SimplePacket)");
+        assert_eq!(nodes[0].node_kind, NodeKind::IO);
+        assert_eq!(nodes[1].node_kind, NodeKind::IO);
+        assert_eq!(nodes[2].node_class, "identity");
+        assert_eq!(nodes[2].node_kind, NodeKind::Processor);
+        assert_eq!(nodes[3].node_class, "ClassifyFoo");
+        assert_eq!(nodes[3].node_kind, NodeKind::Classifier);
+        assert_eq!(nodes[3].capacity, None);
+        assert_eq!(nodes[4].node_kind, NodeKind::Fork);
+        assert_eq!(nodes[4].capacity, Some(5));
+    }
+
+    #[test]
+    fn args() {
+        let dot =
+            r#"digraph { limit [label=RateLimiter, args="100, \"eth0\", $burst"]; a -> limit }"#;
+
+        let (nodes, _) = nodes_edges_from_dot(dot);
+
+        assert_eq!(
+            nodes[0].args,
+            vec!["100", r#""eth0""#, "crate :: config :: burst ()"]
+        );
+        assert!(nodes[1].args.is_empty());
+    }
+
+    #[test]
+    fn edge_chains_and_labels() {
+        let dot = r#"
+            // A classifier with two branches
+            digraph {
+                node [shape=box];
+                edge [color=blue];
+                in -> classify -> a [label="Class::A"];
+                /* the default branch */
+                classify -> "b" [label=_];
+            }
+        "#;
+
+        let (nodes, edges) = nodes_edges_from_dot(dot);
+
+        let ids: Vec<&str> = nodes.iter().map(|n| n.xml_node_id.as_str()).collect();
+        assert_eq!(ids, vec!["in", "classify", "a", "b"]);
+        assert_eq!(edges.len(), 3);
+        assert_eq!(edges[0].source, "in");
+        assert_eq!(edges[0].target, "classify");
+        assert_eq!(edges[0].xml_node_id, "edge-1");
+        assert_eq!(edges[1].label, Some(String::from("Class::A")));
+        assert_eq!(edges[2].target, "b");
+        assert_eq!(edges[2].label, Some(String::from("_")));
+    }
+
+    #[test]
+    fn later_attributes_apply() {
+        let dot = r#"digraph { a -> b; b [label="Foo\"Bar"]; }"#;
+
+        let (nodes, _) = nodes_edges_from_dot(dot);
+
+        assert_eq!(nodes[1].node_class, "Foo\"Bar");
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_undirected_graphs() {
+        nodes_edges_from_dot("graph { a -- b }");
+    }
+}
@@ -0,0 +1,1277 @@
+use crate::codegen;
+use crate::codegen::magic_newline_stmt;
+use crate::pipeline_graph::{EdgeData, NodeData, NodeKind, PipelineGraph, XmlNodeId};
+use quote::ToTokens;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+
+/// Options for `generate`, built like a link:
+///
+/// ```
+/// use route_rs_graphgen::GenerateOptions;
+///
+/// let options = GenerateOptions::new()
+///     .source_graph("src/pipeline.xml")
+///     .local_modules(vec!["packets"])
+///     .runtime_modules(vec!["processor"]);
+/// ```
+pub struct GenerateOptions {
+    source_graph: PathBuf,
+    local_modules: Vec<String>,
+    runtime_modules: Vec<String>,
+    instrument: bool,
+    factories: HashMap<String, String>,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions::new()
+    }
+}
+
+impl GenerateOptions {
+    pub fn new() -> Self {
+        GenerateOptions {
+            source_graph: PathBuf::new(),
+            local_modules: vec![String::from("packets"), String::from("processors")],
+            runtime_modules: vec![],
+            instrument: false,
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Path of the graph, named in the header comment of the generated source.
+    pub fn source_graph<P: AsRef<Path>>(self, source_graph: P) -> Self {
+        GenerateOptions {
+            source_graph: source_graph.as_ref().to_path_buf(),
+            local_modules: self.local_modules,
+            runtime_modules: self.runtime_modules,
+            instrument: self.instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// Modules of the crate the pipeline is generated into that are glob imported, by default
+    /// `packets` and `processors`.
+    pub fn local_modules(self, local_modules: Vec<&str>) -> Self {
+        GenerateOptions {
+            source_graph: self.source_graph,
+            local_modules: local_modules.into_iter().map(String::from).collect(),
+            runtime_modules: self.runtime_modules,
+            instrument: self.instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// Modules of `route_rs_runtime` that are glob imported, such as `processor`.
+    pub fn runtime_modules(self, runtime_modules: Vec<&str>) -> Self {
+        GenerateOptions {
+            source_graph: self.source_graph,
+            local_modules: self.local_modules,
+            runtime_modules: runtime_modules.into_iter().map(String::from).collect(),
+            instrument: self.instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// Names every link after the node it is generated from, registering it with
+    /// `route_rs_runtime::metrics::Registry` so its packets are counted while the router runs.
+    /// Links graphgen adds itself are named after the node they serve, like `join_<node>`.
+    pub fn instrument(self, instrument: bool) -> Self {
+        GenerateOptions {
+            source_graph: self.source_graph,
+            local_modules: self.local_modules,
+            runtime_modules: self.runtime_modules,
+            instrument,
+            factories: self.factories,
+        }
+    }
+
+    /// Builds the Processors and Classifiers of class `class` by calling `factory`, the path of a
+    /// function taking the node's arguments, rather than `<class>::new`. A factory can return an
+    /// `FnProcessor` or `FnClassifier` wrapping a closure, so they can be placed in a graph by name
+    /// like any other.
+    pub fn factory(self, class: &str, factory: &str) -> Self {
+        let mut factories = self.factories;
+        factories.insert(String::from(class), String::from(factory));
+        GenerateOptions {
+            source_graph: self.source_graph,
+            local_modules: self.local_modules,
+            runtime_modules: self.runtime_modules,
+            instrument: self.instrument,
+            factories,
+        }
+    }
+
+    pub(crate) fn local_module_names(&self) -> Vec<&str> {
+        self.local_modules.iter().map(String::as_str).collect()
+    }
+}
+
+/// Generates the Rust source of a pipeline implementing `Runner`, or `MultiRunner` for graphs with
+/// several IO nodes. The graph should have passed `PipelineGraph::check`, since codegen panics on
+/// graphs it can't make sense of.
+pub fn generate(graph: &PipelineGraph, options: &GenerateOptions) -> String {
+    let mut runtime_modules = options.runtime_modules.clone();
+    if options.instrument && !runtime_modules.iter().any(|m| m == "metrics") {
+        runtime_modules.push(String::from("metrics"));
+    }
+    let mut sections = vec![
+        codegen::comment(format!(
+            "Generated by route-rs-graphgen\n\
+             Source graph: {}",
+            options.source_graph.display()
+        )),
+        gen_source_imports(&options.local_modules, &runtime_modules),
+    ];
+    let drop_counters = gen_drop_counters(&pipeline_links(graph));
+    if !drop_counters.is_empty() {
+        sections.push(drop_counters.join("\n"));
+    }
+    sections.push(gen_source_pipeline(graph, options));
+    let source = sections.join("\n\n") + "\n";
+    codegen::unmagic_newlines(source)
+}
+
+pub(crate) enum Link {
+    Input(String),
+    Output((XmlNodeId, Option<String>), String),
+    Sync((XmlNodeId, Option<String>), XmlNodeId),
+    Queue((XmlNodeId, Option<String>), XmlNodeId, usize),
+    /// Classifiers without a `_` branch get an extra egressor for the classes no branch matches,
+    /// which feeds a Drop link.
+    Classify((XmlNodeId, Option<String>), XmlNodeId, Vec<String>, bool),
+    /// Egressors are keyed by the IDs of the edges leaving the fork, in order.
+    Fork((XmlNodeId, Option<String>), Vec<String>, Option<usize>),
+    /// Egressors are keyed by their index, or None for a composite with a single unlabeled edge.
+    Composite(
+        Vec<(XmlNodeId, Option<String>)>,
+        NodeData,
+        Vec<Option<String>>,
+    ),
+    Join(Vec<(XmlNodeId, Option<String>)>),
+    /// Discards packets, counting them in the static named by the second field.
+    Drop((XmlNodeId, Option<String>), String),
+}
+
+impl Link {
+    /// Name of the runtime link this generates, or the path of a composite.
+    pub(crate) fn link_type(&self) -> String {
+        String::from(match self {
+            Link::Input(_) => "InputChannelLink",
+            Link::Output(_, _) => "OutputChannelLink",
+            Link::Sync(_, _) => "ProcessLink",
+            Link::Queue(_, _, _) => "QueueLink",
+            Link::Classify(_, _, _, _) => "ClassifyLink",
+            Link::Fork(_, _, _) => "ForkLink",
+            Link::Composite(_, node, _) => return node.node_class.to_owned(),
+            Link::Join(_) => "JoinLink",
+            Link::Drop(_, _) => "DropLink",
+        })
+    }
+
+    /// The egressors this link is fed from, keyed as in `feeder_key`.
+    pub(crate) fn feeders(&self) -> Vec<&(XmlNodeId, Option<String>)> {
+        match self {
+            Link::Input(_) => vec![],
+            Link::Output(feeder, _)
+            | Link::Sync(feeder, _)
+            | Link::Queue(feeder, _, _)
+            | Link::Classify(feeder, _, _, _)
+            | Link::Fork(feeder, _, _)
+            | Link::Drop(feeder, _) => vec![feeder],
+            Link::Composite(feeders, _, _) | Link::Join(feeders) => feeders.iter().collect(),
+        }
+    }
+}
+
+fn gen_source_imports(local_modules: &[String], runtime_modules: &[String]) -> String {
+    let mut imports = vec![];
+    for lm in local_modules {
+        imports.push(syn::UseTree::Path(codegen::use_path(
+            "crate",
+            syn::UseTree::Path(codegen::use_path(
+                lm,
+                syn::UseTree::Glob(codegen::use_glob()),
+            )),
+        )))
+    }
+    imports.push(syn::UseTree::Path(codegen::use_path(
+        "route_rs_runtime",
+        syn::UseTree::Path(codegen::use_path(
+            "link",
+            syn::UseTree::Glob(codegen::use_glob()),
+        )),
+    )));
+    imports.push(syn::UseTree::Path(codegen::use_path(
+        "route_rs_runtime",
+        syn::UseTree::Path(codegen::use_path(
+            "link",
+            syn::UseTree::Path(codegen::use_path(
+                "primitive",
+                syn::UseTree::Glob(codegen::use_glob()),
+            )),
+        )),
+    )));
+    for rm in runtime_modules {
+        imports.push(syn::UseTree::Path(codegen::use_path(
+            "route_rs_runtime",
+            syn::UseTree::Path(codegen::use_path(
+                rm,
+                syn::UseTree::Glob(codegen::use_glob()),
+            )),
+        )))
+    }
+    imports.push(syn::UseTree::Path(codegen::use_path(
+        "tokio",
+        syn::UseTree::Name(syn::UseName {
+            ident: codegen::ident("runtime"),
+        }),
+    )));
+    imports.push(syn::UseTree::Path(codegen::use_path(
+        "tokio",
+        syn::UseTree::Path(codegen::use_path(
+            "task",
+            syn::UseTree::Name(syn::UseName {
+                ident: codegen::ident("JoinHandle"),
+            }),
+        )),
+    )));
+
+    codegen::import(&imports)
+}
+
+/// Returns the input and output IO nodes, each in the order they are declared in the graph, which
+/// is the order of their channels in a `MultiRunner`.
+fn get_io_nodes(nodes: &[&NodeData], edges: &[&EdgeData]) -> (Vec<NodeData>, Vec<NodeData>) {
+    let io_nodes: Vec<&NodeData> = nodes
+        .iter()
+        .cloned()
+        .filter(|n| n.node_kind == NodeKind::IO)
+        .collect();
+    let input_nodes: Vec<NodeData> = io_nodes
+        .iter()
+        .filter(|n| edges.iter().any(|e| e.source == n.xml_node_id))
+        .map(|n| n.to_owned().to_owned())
+        .collect();
+    assert!(!input_nodes.is_empty());
+    let output_nodes: Vec<NodeData> = io_nodes
+        .iter()
+        .filter(|n| edges.iter().any(|e| e.target == n.xml_node_id))
+        .map(|n| n.to_owned().to_owned())
+        .collect();
+    assert!(!output_nodes.is_empty());
+    (input_nodes, output_nodes)
+}
+
+/// Names the channel variable of each IO node. A pipeline with one input and one output takes
+/// `input_channel` and `output_channel`, otherwise they are numbered in declaration order.
+fn io_channel_names(io_nodes: &[NodeData], name: &str) -> HashMap<XmlNodeId, String> {
+    io_nodes
+        .iter()
+        .enumerate()
+        .map(|(index, n)| {
+            let channel = if io_nodes.len() == 1 {
+                format!("{}_channel", name)
+            } else {
+                format!("{}_channel_{}", name, index)
+            };
+            (n.xml_node_id.to_owned(), channel)
+        })
+        .collect()
+}
+
+/// Moves each channel out of the vector a `MultiRunner` is given into its own variable.
+fn gen_channel_unpacking(name: &str, count: usize) -> Vec<syn::Stmt> {
+    let mut stmts = vec![
+        syn::parse_str::<syn::Stmt>(&format!(
+            "assert_eq!({name}_channels.len(), {count}, \"Pipeline takes {count} {name} channels\");",
+            name = name,
+            count = count
+        ))
+        .unwrap(),
+        syn::parse_str::<syn::Stmt>(&format!(
+            "let mut {name}_channels = {name}_channels.into_iter();",
+            name = name
+        ))
+        .unwrap(),
+    ];
+    for index in 0..count {
+        stmts.push(
+            syn::parse_str::<syn::Stmt>(&format!(
+                "let {name}_channel_{index} = {name}_channels.next().unwrap();",
+                name = name,
+                index = index
+            ))
+            .unwrap(),
+        );
+    }
+    stmts
+}
+
+fn arg_exprs(node: &NodeData) -> Vec<syn::Expr> {
+    node.args
+        .iter()
+        .map(|a| match syn::parse_str::<syn::Expr>(a) {
+            Ok(expr) => expr,
+            Err(err) => panic!("Invalid argument {} for {}: {}", a, node.node_class, err),
+        })
+        .collect()
+}
+
+fn gen_processor_decls(
+    processors: &[&NodeData],
+    factories: &HashMap<String, String>,
+) -> (Vec<syn::Stmt>, HashMap<String, String>) {
+    let mut decl_idx: usize = 1;
+    let mut processor_decls_map = HashMap::new();
+    let decls: Vec<syn::Stmt> = processors
+        .iter()
+        .map(|e| {
+            let symbol = format!("elem_{}_{}", decl_idx, e.node_class.to_lowercase());
+            decl_idx += 1;
+            processor_decls_map.insert(e.xml_node_id.to_owned(), symbol.clone());
+            let constructor = match factories.get(&e.node_class) {
+                Some(factory) => match syn::parse_str::<syn::ExprPath>(factory) {
+                    Ok(path) => path,
+                    Err(err) => panic!("Invalid factory {} for {}: {}", factory, e.node_class, err),
+                },
+                None => syn::ExprPath {
+                    attrs: vec![],
+                    qself: None,
+                    path: codegen::path(vec![
+                        (codegen::ident(&e.node_class), None),
+                        (codegen::ident("new"), None),
+                    ]),
+                },
+            };
+            syn::Stmt::Local(codegen::let_simple(
+                codegen::ident(symbol.as_str()),
+                None,
+                codegen::call_function(syn::Expr::Path(constructor), arg_exprs(e)),
+                false,
+            ))
+        })
+        .collect();
+    (decls, processor_decls_map)
+}
+
+fn map_get_with_panic<'a, A, B>(map: &'a HashMap<A, B>, key: &A) -> &'a B
+where
+    A: Eq + Hash + Debug + 'a,
+    B: Debug + 'a,
+{
+    match map.get(key) {
+        Some(x) => x,
+        None => panic!("get({:?}) failed on {:?}", key, map),
+    }
+}
+
+fn gen_link_decls(
+    links: &[(XmlNodeId, Link)],
+    processor_decls: HashMap<String, String>,
+    instrument: bool,
+) -> Vec<syn::Stmt> {
+    let mut decl_idx: usize = 0;
+    let mut link_decls_map = HashMap::new();
+    let decls: Vec<Vec<syn::Stmt>> = links
+        .iter()
+        .map(|(id, el)| {
+            decl_idx += 1;
+            let mut decl = match el {
+                Link::Input(channel) => {
+                    link_decls_map.insert(
+                        (id.to_owned(), None),
+                        format!("link_{}_egress_{}", decl_idx, 0),
+                    );
+                    codegen::build_link(
+                        decl_idx,
+                        "InputChannelLink",
+                        vec![(
+                            codegen::ident("channel"),
+                            vec![codegen::expr_path_ident(channel)],
+                        )],
+                        1,
+                    )
+                }
+                Link::Output(feeder, channel) => codegen::build_link(
+                    decl_idx,
+                    "OutputChannelLink",
+                    vec![
+                        (
+                            codegen::ident("ingressor"),
+                            vec![codegen::expr_path_ident(
+                                map_get_with_panic(&link_decls_map, &feeder).as_str(),
+                            )],
+                        ),
+                        (
+                            codegen::ident("channel"),
+                            vec![codegen::expr_path_ident(channel)],
+                        ),
+                    ],
+                    0,
+                ),
+                Link::Sync(feeder, processor) => {
+                    link_decls_map.insert(
+                        (id.to_owned(), None),
+                        format!("link_{}_egress_{}", decl_idx, 0),
+                    );
+                    codegen::build_link(
+                        decl_idx,
+                        "ProcessLink",
+                        vec![
+                            (
+                                codegen::ident("ingressor"),
+                                vec![codegen::expr_path_ident(
+                                    map_get_with_panic(&link_decls_map, &feeder).as_str(),
+                                )],
+                            ),
+                            (
+                                codegen::ident("processor"),
+                                vec![codegen::expr_path_ident(
+                                    processor_decls.get(processor.as_str()).unwrap(),
+                                )],
+                            ),
+                        ],
+                        1,
+                    )
+                }
+                Link::Queue(feeder, processor, capacity) => {
+                    link_decls_map.insert(
+                        (id.to_owned(), None),
+                        format!("link_{}_egress_{}", decl_idx, 0),
+                    );
+                    codegen::build_link(
+                        decl_idx,
+                        "QueueLink",
+                        vec![
+                            (
+                                codegen::ident("ingressor"),
+                                vec![codegen::expr_path_ident(
+                                    map_get_with_panic(&link_decls_map, feeder).as_str(),
+                                )],
+                            ),
+                            (
+                                codegen::ident("processor"),
+                                vec![codegen::expr_path_ident(
+                                    processor_decls.get(processor.as_str()).unwrap(),
+                                )],
+                            ),
+                            (
+                                codegen::ident("queue_capacity"),
+                                vec![codegen::expr_lit_int(capacity)],
+                            ),
+                        ],
+                        1,
+                    )
+                }
+                Link::Fork(feeder, outlets, capacity) => {
+                    for (outlet_index, outlet) in outlets.iter().enumerate() {
+                        link_decls_map.insert(
+                            (id.to_owned(), Some(outlet.to_owned())),
+                            format!("link_{}_egress_{}", decl_idx, outlet_index),
+                        );
+                    }
+                    let mut setters = vec![
+                        (
+                            codegen::ident("ingressor"),
+                            vec![codegen::expr_path_ident(
+                                map_get_with_panic(&link_decls_map, feeder).as_str(),
+                            )],
+                        ),
+                        (
+                            codegen::ident("num_egressors"),
+                            vec![codegen::expr_lit_int(outlets.len())],
+                        ),
+                    ];
+                    if let Some(capacity) = capacity {
+                        setters.push((
+                            codegen::ident("queue_capacity"),
+                            vec![codegen::expr_lit_int(capacity)],
+                        ));
+                    }
+                    codegen::build_link(decl_idx, "ForkLink", setters, outlets.len())
+                }
+                Link::Composite(feeders, node, outlets) => {
+                    for (outlet_index, outlet) in outlets.iter().enumerate() {
+                        link_decls_map.insert(
+                            (id.to_owned(), outlet.to_owned()),
+                            format!("link_{}_egress_{}", decl_idx, outlet_index),
+                        );
+                    }
+                    let constructor =
+                        match syn::parse_str::<syn::Expr>(&format!("{}::new", node.node_class)) {
+                            Ok(constructor) => constructor,
+                            Err(err) => {
+                                panic!("Invalid composite link {}: {}", node.node_class, err)
+                            }
+                        };
+                    let feeders_decls = feeders
+                        .iter()
+                        .map(|f| {
+                            codegen::expr_path_ident(
+                                map_get_with_panic(&link_decls_map, f).as_str(),
+                            )
+                        })
+                        .collect::<Vec<syn::Expr>>();
+                    codegen::build_link_from(
+                        decl_idx,
+                        codegen::builder_from(
+                            codegen::call_function(constructor, arg_exprs(node)),
+                            vec![(
+                                codegen::ident("ingressors"),
+                                vec![codegen::vec(feeders_decls)],
+                            )],
+                        ),
+                        outlets.len(),
+                    )
+                }
+                Link::Classify(feeder, processor, branches, default_drop) => {
+                    let num_egressors = branches.len() + *default_drop as usize;
+                    let mut match_branches = vec![];
+                    for branch_index in 0..(branches.len()) {
+                        match_branches.push((
+                            branches.get(branch_index).unwrap(),
+                            branch_index.to_string(),
+                        ));
+                        link_decls_map.insert(
+                            (
+                                id.to_owned(),
+                                Some(branches.get(branch_index).unwrap().to_owned()),
+                            ),
+                            format!("link_{}_egress_{}", decl_idx, branch_index),
+                        );
+                    }
+                    let mut dispatch = codegen::expr_match(
+                        codegen::expr_path_ident("c"),
+                        match_branches
+                            .into_iter()
+                            .map(|(p, b)| {
+                                (
+                                    syn::parse_str::<syn::Pat>(p).unwrap(),
+                                    codegen::expr_lit_int(b),
+                                )
+                            })
+                            .collect(),
+                    );
+                    if *default_drop {
+                        link_decls_map.insert(
+                            (id.to_owned(), Some(String::from("_"))),
+                            format!("link_{}_egress_{}", decl_idx, branches.len()),
+                        );
+                        // The branches may cover every class already, which we can't tell here.
+                        if let syn::Expr::Match(m) = &mut dispatch {
+                            m.arms.push(
+                                syn::parse_str::<syn::Arm>(&format!(
+                                    "#[allow(unreachable_patterns)] _ => {},",
+                                    branches.len()
+                                ))
+                                .unwrap(),
+                            );
+                        }
+                    }
+                    codegen::build_link(
+                        decl_idx,
+                        "ClassifyLink",
+                        vec![
+                            (
+                                codegen::ident("ingressor"),
+                                vec![codegen::expr_path_ident(
+                                    map_get_with_panic(&link_decls_map, &feeder).as_str(),
+                                )],
+                            ),
+                            (
+                                codegen::ident("classifier"),
+                                vec![codegen::expr_path_ident(
+                                    processor_decls.get(processor.as_str()).unwrap(),
+                                )],
+                            ),
+                            (
+                                codegen::ident("dispatcher"),
+                                vec![codegen::call_function(
+                                    syn::Expr::Path(syn::ExprPath {
+                                        attrs: vec![],
+                                        qself: None,
+                                        path: codegen::path(vec![
+                                            (codegen::ident("Box"), None),
+                                            (codegen::ident("new"), None),
+                                        ]),
+                                    }),
+                                    vec![codegen::closure(
+                                        false,
+                                        false,
+                                        false,
+                                        vec![syn::Pat::Ident(syn::PatIdent {
+                                            attrs: vec![],
+                                            by_ref: None,
+                                            mutability: None,
+                                            ident: codegen::ident("c"),
+                                            subpat: None,
+                                        })],
+                                        syn::ReturnType::Default,
+                                        vec![syn::Stmt::Expr(dispatch)],
+                                    )],
+                                )],
+                            ),
+                            (
+                                codegen::ident("num_egressors"),
+                                vec![codegen::expr_lit_int(num_egressors)],
+                            ),
+                        ],
+                        num_egressors,
+                    )
+                }
+                Link::Join(feeders) => {
+                    let egressor_symbol = format!("link_{}_egress_{}", decl_idx, 0);
+                    link_decls_map.insert((id.to_owned(), None), egressor_symbol);
+                    let mut feeders_decls = vec![];
+                    for feeder_index in 0..(feeders.len()) {
+                        feeders_decls.push(map_get_with_panic(
+                            &link_decls_map,
+                            &feeders.get(feeder_index).unwrap(),
+                        ));
+                    }
+                    codegen::build_link(
+                        decl_idx,
+                        "JoinLink",
+                        vec![(
+                            codegen::ident("ingressors"),
+                            vec![codegen::vec(
+                                feeders_decls
+                                    .into_iter()
+                                    .map(|d| codegen::expr_path_ident(d))
+                                    .collect::<Vec<syn::Expr>>(),
+                            )],
+                        )],
+                        1,
+                    )
+                }
+                Link::Drop(feeder, counter) => {
+                    let counter_ref = syn::parse_str::<syn::Expr>(&format!("&{}", counter));
+                    let mut decl = codegen::build_link_from(
+                        decl_idx,
+                        codegen::builder_from(
+                            syn::parse_str::<syn::Expr>(
+                                "route_rs_runtime::link::composite::DropLink::new()",
+                            )
+                            .unwrap(),
+                            vec![
+                                (
+                                    codegen::ident("ingressor"),
+                                    vec![codegen::expr_path_ident(
+                                        map_get_with_panic(&link_decls_map, feeder).as_str(),
+                                    )],
+                                ),
+                                (codegen::ident("counter"), vec![counter_ref.unwrap()]),
+                            ],
+                        ),
+                        1,
+                    );
+                    // Nothing reads a drop link, so drain it to keep the packets flowing.
+                    let drain = format!(
+                        "all_runnables.push(Box::new(route_rs_runtime::link::utils::drain::Drain::new(link_{}_egress_0)));",
+                        decl_idx
+                    );
+                    decl.push(syn::parse_str::<syn::Stmt>(&drain).unwrap());
+                    decl
+                }
+            };
+            if instrument {
+                name_link(&mut decl, id);
+            }
+            decl
+        })
+        .collect();
+    decls
+        .into_iter()
+        .map(|mut ss| {
+            // Add magic newlines between each link section. These will be replaced with real newlines
+            // right before we write out the source, since syn doesn't have a way to generate newlines.
+            ss.push(magic_newline_stmt());
+            ss
+        })
+        .flatten()
+        .collect()
+}
+
+/// Names the link built by the first statement of a declaration from `codegen::build_link`, by
+/// calling `name` right before `build_link`, so it registers with the metrics registry.
+fn name_link(decl: &mut [syn::Stmt], name: &str) {
+    if let Some(syn::Stmt::Local(syn::Local {
+        init: Some((_, builder)),
+        ..
+    })) = decl.first_mut()
+    {
+        if let syn::Expr::MethodCall(build_link) = builder.as_mut() {
+            let receiver = build_link.receiver.clone();
+            *build_link.receiver = syn::parse_quote!(#receiver.name(#name));
+            return;
+        }
+    }
+    panic!("Link {} is not declared by build_link", name)
+}
+
+fn gen_tokio_run() -> Vec<syn::Stmt> {
+    vec![
+        syn::Stmt::Local(codegen::let_simple(
+            codegen::ident("rt"),
+            None,
+            codegen::call_chain(
+                codegen::call_function(
+                    syn::Expr::Path(syn::ExprPath {
+                        attrs: vec![],
+                        qself: None,
+                        path: codegen::path(vec![
+                            (codegen::ident("runtime"), None),
+                            (codegen::ident("Builder"), None),
+                            (codegen::ident("new"), None),
+                        ]),
+                    }),
+                    vec![],
+                ),
+                vec![
+                    ("threaded_scheduler", vec![]),
+                    ("enable_all", vec![]),
+                    ("build", vec![]),
+                    ("unwrap", vec![]),
+                ],
+            ),
+            true,
+        )),
+        codegen::stmt_expr_semi(codegen::call_function(
+            codegen::expr_field(codegen::expr_path_ident("rt"), "block_on"),
+            vec![codegen::expr_async(vec![
+                syn::Stmt::Local(codegen::let_simple(
+                    codegen::ident("handles"),
+                    Some(syn::Type::Path(syn::TypePath {
+                        qself: None,
+                        path: codegen::path(vec![(
+                            codegen::ident("Vec"),
+                            Some(vec![syn::GenericArgument::Type(syn::Type::Path(
+                                syn::TypePath {
+                                    qself: None,
+                                    path: codegen::path(vec![(
+                                        codegen::ident("JoinHandle"),
+                                        Some(vec![syn::GenericArgument::Type(
+                                            codegen::type_tuple(vec![]),
+                                        )]),
+                                    )]),
+                                },
+                            ))]),
+                        )]),
+                    })),
+                    codegen::call_chain(
+                        codegen::expr_path_ident("all_runnables"),
+                        vec![
+                            ("into_iter", vec![]),
+                            (
+                                "map",
+                                vec![syn::Expr::Path(syn::ExprPath {
+                                    attrs: vec![],
+                                    qself: None,
+                                    path: codegen::path(vec![
+                                        (codegen::ident("tokio"), None),
+                                        (codegen::ident("spawn"), None),
+                                    ]),
+                                })],
+                            ),
+                            ("collect", vec![]),
+                        ],
+                    ),
+                    false,
+                )),
+                codegen::for_loop(
+                    syn::Pat::Ident(syn::PatIdent {
+                        attrs: vec![],
+                        by_ref: None,
+                        mutability: None,
+                        ident: codegen::ident("handle"),
+                        subpat: None,
+                    }),
+                    codegen::expr_path_ident("handles"),
+                    vec![codegen::stmt_expr_semi(codegen::call_function(
+                        codegen::expr_field(
+                            codegen::expr_field(codegen::expr_path_ident("handle"), "await"),
+                            "unwrap",
+                        ),
+                        vec![],
+                    ))],
+                ),
+            ])],
+        )),
+    ]
+}
+
+/// Name of the static counting the packets dropped by a Drop node, or by a classifier for the
+/// classes none of its branches match.
+fn drop_counter_name(node_id: &str) -> String {
+    let name: String = node_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("DROPPED_{}", name)
+}
+
+/// Declares the counters of every Drop link, which users read to find out how many packets the
+/// pipeline discarded where.
+fn gen_drop_counters(links: &[(XmlNodeId, Link)]) -> Vec<String> {
+    links
+        .iter()
+        .filter_map(|(id, link)| match link {
+            Link::Drop(_, counter) => Some(
+                syn::parse_str::<syn::Item>(&format!(
+                    "#[doc = {:?}] \
+                     pub static {}: std::sync::atomic::AtomicU64 = \
+                     std::sync::atomic::AtomicU64::new(0);",
+                    format!("Packets dropped by `{}`.", id),
+                    counter
+                ))
+                .unwrap()
+                .to_token_stream()
+                .to_string(),
+            ),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The key of the egressor an edge leaves from, under which `gen_link_decls` records it. Forks
+/// have a distinct egressor for each edge, and other links one for each branch label.
+fn feeder_key(edge: &EdgeData, nodes: &[&NodeData]) -> (XmlNodeId, Option<String>) {
+    let fork = nodes
+        .iter()
+        .any(|n| n.xml_node_id == edge.source && n.node_kind == NodeKind::Fork);
+    if fork {
+        (edge.source.to_owned(), Some(edge.xml_node_id.to_owned()))
+    } else {
+        (edge.source.to_owned(), edge.label.to_owned())
+    }
+}
+
+fn expand_join_link<'a>(
+    feeders: &[(XmlNodeId, Option<String>)],
+    links: &mut Vec<(String, Link)>,
+    orig_xml_node_id: &str,
+    link_builder: Box<dyn Fn(XmlNodeId, Option<String>) -> Link + 'a>,
+) {
+    if feeders.len() == 1 {
+        links.push((
+            orig_xml_node_id.to_owned(),
+            link_builder(feeders[0].0.to_owned(), feeders[0].1.to_owned()),
+        ))
+    } else {
+        let join_xml_node_id = ["join", &orig_xml_node_id].join("_");
+        links.push((join_xml_node_id.to_owned(), Link::Join(feeders.to_vec())));
+        links.push((
+            orig_xml_node_id.to_owned(),
+            link_builder(join_xml_node_id, None),
+        ));
+    }
+}
+
+/// Lays the graph out as the links of the pipeline, in the order they are declared, along with
+/// the nodes that need a Processor or Classifier. Nodes with several incoming edges are fed by an
+/// extra JoinLink named `join_<node>`.
+fn gen_links<'a>(
+    nodes: &[&'a NodeData],
+    edges: &[&EdgeData],
+    input_nodes: &[NodeData],
+    output_nodes: &[NodeData],
+) -> (Vec<&'a NodeData>, Vec<(XmlNodeId, Link)>) {
+    let mut processors = vec![];
+    let mut links = vec![];
+    let input_channels = io_channel_names(input_nodes, "input");
+    let output_channels = io_channel_names(output_nodes, "output");
+
+    for nd in nodes {
+        let feeders: Vec<(XmlNodeId, Option<String>)> = edges
+            .iter()
+            .filter(|e| e.target == nd.xml_node_id)
+            .map(|e| feeder_key(e, nodes))
+            .collect();
+        let outgoing: Vec<&&EdgeData> = edges
+            .iter()
+            .filter(|e| e.source == nd.xml_node_id)
+            .collect();
+        match &nd.node_kind {
+            NodeKind::IO => {
+                if let Some(channel) = input_channels.get(&nd.xml_node_id) {
+                    links.push((nd.xml_node_id.to_owned(), Link::Input(channel.to_owned())));
+                } else if let Some(channel) = output_channels.get(&nd.xml_node_id) {
+                    expand_join_link(
+                        &feeders,
+                        &mut links,
+                        &nd.xml_node_id,
+                        Box::new(move |xni, label| Link::Output((xni, label), channel.to_owned())),
+                    );
+                } else {
+                    panic!("{:?} is IO but not an input or output node", nd)
+                }
+            }
+            NodeKind::Processor => {
+                processors.push(*nd);
+                expand_join_link(
+                    &feeders,
+                    &mut links,
+                    &nd.xml_node_id,
+                    Box::new(|xni, label| match nd.capacity {
+                        Some(capacity) => {
+                            Link::Queue((xni, label), nd.xml_node_id.to_owned(), capacity)
+                        }
+                        None => Link::Sync((xni, label), nd.xml_node_id.to_owned()),
+                    }),
+                );
+            }
+            NodeKind::Classifier => {
+                let outlets: Vec<String> =
+                    outgoing.iter().map(|e| e.label.clone().unwrap()).collect();
+                let default_drop = !outlets.iter().any(|o| o == "_");
+                processors.push(*nd);
+                expand_join_link(
+                    &feeders,
+                    &mut links,
+                    &nd.xml_node_id,
+                    Box::new(|xni, label| {
+                        Link::Classify(
+                            (xni, label),
+                            nd.xml_node_id.to_owned(),
+                            outlets.to_owned(),
+                            default_drop,
+                        )
+                    }),
+                );
+                if default_drop {
+                    links.push((
+                        ["drop", &nd.xml_node_id].join("_"),
+                        Link::Drop(
+                            (nd.xml_node_id.to_owned(), Some(String::from("_"))),
+                            drop_counter_name(&nd.xml_node_id),
+                        ),
+                    ));
+                }
+            }
+            NodeKind::Drop => {
+                let counter = drop_counter_name(&nd.xml_node_id);
+                expand_join_link(
+                    &feeders,
+                    &mut links,
+                    &nd.xml_node_id,
+                    Box::new(move |xni, label| Link::Drop((xni, label), counter.to_owned())),
+                );
+            }
+            NodeKind::Fork => {
+                let outlets: Vec<String> = outgoing.iter().map(|e| e.xml_node_id.clone()).collect();
+                expand_join_link(
+                    &feeders,
+                    &mut links,
+                    &nd.xml_node_id,
+                    Box::new(|xni, label| {
+                        Link::Fork((xni, label), outlets.to_owned(), nd.capacity)
+                    }),
+                );
+            }
+            NodeKind::Composite => {
+                let outlets: Vec<Option<String>> = match outgoing.as_slice() {
+                    [e] if e.label.is_none() => vec![None],
+                    _ => {
+                        let num_egressors = outgoing
+                            .iter()
+                            .map(|e| e.label.as_ref().unwrap().parse::<usize>().unwrap() + 1)
+                            .max()
+                            .unwrap_or(0);
+                        (0..num_egressors).map(|i| Some(i.to_string())).collect()
+                    }
+                };
+                links.push((
+                    nd.xml_node_id.to_owned(),
+                    Link::Composite(feeders, (*nd).to_owned(), outlets),
+                ));
+            }
+        }
+    }
+    (processors, links)
+}
+
+/// The links of the pipeline generated from a graph, see `gen_links`.
+pub(crate) fn pipeline_links(graph: &PipelineGraph) -> Vec<(XmlNodeId, Link)> {
+    let nodes = graph.ordered_nodes();
+    let edges = graph.edges();
+    let (input_nodes, output_nodes) = get_io_nodes(&graph.nodes(), &edges);
+    gen_links(&nodes, &edges, &input_nodes, &output_nodes).1
+}
+
+fn gen_run_body(
+    nodes: &[&NodeData],
+    edges: &[&EdgeData],
+    input_nodes: &[NodeData],
+    output_nodes: &[NodeData],
+    options: &GenerateOptions,
+) -> Vec<syn::Stmt> {
+    let (processors, links) = gen_links(nodes, edges, input_nodes, output_nodes);
+    let all_runnables_stmt = syn::Stmt::Local(codegen::let_simple(
+        codegen::ident("all_runnables"),
+        Some(syn::Type::Path(syn::TypePath {
+            qself: None,
+            path: codegen::path(vec![(
+                codegen::ident("Vec"),
+                Some(vec![syn::GenericArgument::Type(syn::Type::Path(
+                    syn::TypePath {
+                        qself: None,
+                        path: codegen::path(vec![(codegen::ident("TokioRunnable"), None)]),
+                    },
+                ))]),
+            )]),
+        })),
+        codegen::vec(vec![]),
+        true,
+    ));
+    let (mut processor_decls_stmts, processor_decls_map) =
+        gen_processor_decls(&processors, &options.factories);
+    processor_decls_stmts.push(magic_newline_stmt());
+    let mut stmts = vec![];
+    if input_nodes.len() > 1 || output_nodes.len() > 1 {
+        stmts.append(&mut gen_channel_unpacking("input", input_nodes.len()));
+        stmts.append(&mut gen_channel_unpacking("output", output_nodes.len()));
+        stmts.push(magic_newline_stmt());
+    }
+    stmts.push(all_runnables_stmt);
+    stmts.push(magic_newline_stmt());
+    stmts.append(&mut processor_decls_stmts);
+    stmts.append(&mut gen_link_decls(
+        &links,
+        processor_decls_map,
+        options.instrument,
+    ));
+    stmts.append(&mut gen_tokio_run());
+    stmts
+}
+
+/// The type of the channel parameter of `run`, e.g. `crossbeam::Receiver<Self::Input>`, wrapped in
+/// a Vec for a `MultiRunner`.
+fn channel_type(channel: &str, packet: &str, multi: bool) -> syn::Type {
+    let channel_type = syn::Type::Path(syn::TypePath {
+        qself: None,
+        path: codegen::path(vec![
+            (codegen::ident("crossbeam"), None),
+            (
+                codegen::ident(channel),
+                Some(vec![syn::GenericArgument::Type(syn::Type::Path(
+                    syn::TypePath {
+                        qself: None,
+                        path: codegen::path(vec![
+                            (codegen::ident("Self"), None),
+                            (codegen::ident(packet), None),
+                        ]),
+                    },
+                ))]),
+            ),
+        ]),
+    });
+    if !multi {
+        return channel_type;
+    }
+    syn::Type::Path(syn::TypePath {
+        qself: None,
+        path: codegen::path(vec![(
+            codegen::ident("Vec"),
+            Some(vec![syn::GenericArgument::Type(channel_type)]),
+        )]),
+    })
+}
+
+/// Generates the Pipeline struct, implementing `Runner` for graphs with one input and one output
+/// node, and `MultiRunner` otherwise. All input nodes must share a packet type, as must all output
+/// nodes.
+fn gen_source_pipeline(graph: &PipelineGraph, options: &GenerateOptions) -> String {
+    let nodes = graph.ordered_nodes();
+    let edges = graph.edges();
+    let (input_nodes, output_nodes) = get_io_nodes(&graph.nodes(), &edges);
+    let multi = input_nodes.len() > 1 || output_nodes.len() > 1;
+    let (runner, input_param, output_param) = if multi {
+        ("MultiRunner", "input_channels", "output_channels")
+    } else {
+        ("Runner", "input_channel", "output_channel")
+    };
+    [
+        String::from("pub struct Pipeline {}"),
+        codegen::impl_struct(
+            format!("route_rs_runtime::pipeline::{}", runner),
+            "Pipeline",
+            [
+                codegen::typedef(vec![
+                    (
+                        codegen::ident("Input"),
+                        syn::parse_str::<syn::Type>(&input_nodes[0].node_class).unwrap(),
+                    ),
+                    (
+                        codegen::ident("Output"),
+                        syn::parse_str::<syn::Type>(&output_nodes[0].node_class).unwrap(),
+                    ),
+                ]),
+                codegen::function_def(
+                    codegen::ident("run"),
+                    vec![
+                        (input_param, channel_type("Receiver", "Input", multi)),
+                        (output_param, channel_type("Sender", "Output", multi)),
+                    ],
+                    gen_run_body(&nodes, &edges, &input_nodes, &output_nodes, options),
+                    syn::ReturnType::Default,
+                )
+                .to_token_stream()
+                .to_string(),
+            ]
+            .join("\n\n"),
+        ),
+    ]
+    .join("\n\n")
+}
+
+#[cfg(test)]
+mod gen_source_pipeline {
+    use super::*;
+    use crate::dot::nodes_edges_from_dot;
+
+    #[test]
+    fn queue_fork_and_composite_links() {
+        let (nodes, edges) = nodes_edges_from_dot(
+            r#"digraph {
+                input [kind=io, label=Packet];
+                output [kind=io, label=Packet];
+                fork [kind=fork, label=Packet, capacity=5];
+                queue [label=Identity, capacity=20];
+                tee [kind=composite, label="crate::links::Tee", args="2"];
+                input -> fork;
+                fork -> queue -> tee;
+                fork -> output;
+                tee -> output [label=1];
+            }"#,
+        );
+        let graph = PipelineGraph::from_nodes_edges(nodes, edges);
+
+        let source = gen_source_pipeline(&graph, &GenerateOptions::new());
+
+        assert!(source.contains(
+            "ForkLink :: new () . ingressor (link_1_egress_0) . num_egressors (2) . queue_capacity (5)"
+        ));
+        assert!(source.contains(
+            "QueueLink :: new () . ingressor (link_2_egress_0) . processor (elem_1_identity) . queue_capacity (20)"
+        ));
+        assert!(source
+            .contains("crate :: links :: Tee :: new (2) . ingressors (vec ! [link_3_egress_0])"));
+        assert!(source.contains("let link_4_egress_1 = egressors_4 . remove (0)"));
+        assert!(source.contains(
+            "JoinLink :: new () . ingressors (vec ! [link_2_egress_1 , link_4_egress_1])"
+        ));
+    }
+
+    #[test]
+    fn multiple_io_nodes() {
+        let (nodes, edges) = nodes_edges_from_dot(
+            r#"digraph {
+                lan [kind=io, label=Packet];
+                wan [kind=io, label=Packet];
+                to_lan [kind=io, label=Packet];
+                to_wan [kind=io, label=Packet];
+                lan -> to_wan;
+                wan -> to_lan;
+            }"#,
+        );
+        let graph = PipelineGraph::from_nodes_edges(nodes, edges);
+
+        let source = gen_source_pipeline(&graph, &GenerateOptions::new());
+
+        assert!(source.contains("route_rs_runtime::pipeline::MultiRunner for Pipeline"));
+        assert!(source.contains("input_channels : Vec < crossbeam :: Receiver < Self :: Input > >"));
+        assert!(source.contains("let input_channel_1 = input_channels . next () . unwrap ()"));
+        assert!(source.contains("InputChannelLink :: new () . channel (input_channel_0)"));
+        assert!(source.contains(". channel (output_channel_1)"));
+    }
+
+    #[test]
+    fn drops() {
+        let graph = PipelineGraph::from_dot(
+            r#"digraph {
+                input [kind=io, label=Packet];
+                output [kind=io, label=Packet];
+                even [kind=classifier, label=Even];
+                odd [kind=drop, label=Packet];
+                input -> even;
+                even -> output [label=true];
+                even -> odd [label=false];
+            }"#,
+        );
+
+        let source = generate(&graph, &GenerateOptions::new());
+
+        assert!(source.contains("pub static DROPPED_EVEN : std :: sync :: atomic :: AtomicU64"));
+        assert!(source.contains("pub static DROPPED_ODD : std :: sync :: atomic :: AtomicU64"));
+        assert!(
+            source.contains("true => 0 , false => 1 , # [allow (unreachable_patterns)] _ => 2 ,")
+        );
+        assert!(source.contains(". num_egressors (3)"));
+        assert!(source.contains(
+            "DropLink :: new () . ingressor (link_2_egress_2) . counter (& DROPPED_EVEN)"
+        ));
+        assert!(source.contains(
+            "DropLink :: new () . ingressor (link_2_egress_1) . counter (& DROPPED_ODD)"
+        ));
+        assert!(source.contains("Drain :: new (link_3_egress_0)"));
+        assert!(source.contains("Drain :: new (link_5_egress_0)"));
+    }
+
+    #[test]
+    fn instrument() {
+        let graph = PipelineGraph::from_dot(
+            r#"digraph {
+                input [kind=io, label=Packet];
+                output [kind=io, label=Packet];
+                identity [label=Identity];
+                input -> identity -> output;
+                input -> output;
+            }"#,
+        );
+
+        let source = generate(&graph, &GenerateOptions::new().instrument(true));
+
+        assert!(source.contains("use route_rs_runtime :: metrics :: * ;"));
+        assert!(source.contains(". channel (input_channel) . name (\"input\") . build_link ()"));
+        assert!(
+            source.contains(". processor (elem_1_identity) . name (\"identity\") . build_link ()")
+        );
+        assert!(source.contains(". name (\"join_output\") . build_link ()"));
+        assert!(source.contains(". channel (output_channel) . name (\"output\") . build_link ()"));
+    }
+
+    #[test]
+    fn factories() {
+        let graph = PipelineGraph::from_dot(
+            r#"digraph {
+                input [kind=io, label=Packet];
+                output [kind=io, label=Packet];
+                add [label=AddN, args="3"];
+                small [kind=classifier, label=Small];
+                input -> add -> small;
+                small -> output [label=true];
+                small -> output [label=false];
+            }"#,
+        );
+
+        let source = generate(
+            &graph,
+            &GenerateOptions::new()
+                .factory("AddN", "crate::fixtures::add_n")
+                .factory("Small", "crate::fixtures::small"),
+        );
+
+        assert!(source.contains("let elem_1_addn = crate :: fixtures :: add_n (3) ;"));
+        assert!(source.contains("let elem_2_small = crate :: fixtures :: small () ;"));
+    }
+}
@@ -0,0 +1,38 @@
+//! Generates route-rs pipelines from graphs.
+//!
+//! A graph can be drawn in drawio, written as a GraphViz digraph, or described in JSON or YAML.
+//! Each becomes a `PipelineGraph`, which is checked against the Processors of the crate it is
+//! generated into before `generate` turns it into Rust source:
+//!
+//! ```no_run
+//! use route_rs_graphgen::{generate, GenerateOptions, PipelineGraph, TypeIndex};
+//! use std::path::Path;
+//!
+//! let graph = PipelineGraph::from_dot(&std::fs::read_to_string("src/pipeline.dot").unwrap());
+//! let types = TypeIndex::from_modules(Path::new("src"), &["packets", "processors"]);
+//! for diagnostic in graph.check(&types) {
+//!     panic!("src/pipeline.dot: {}", diagnostic);
+//! }
+//! let source = generate(&graph, &GenerateOptions::new().source_graph("src/pipeline.dot"));
+//! ```
+//!
+//! Crates that generate their pipeline at build time use `Build` from their build script instead.
+
+mod build;
+mod check;
+mod codegen;
+mod description;
+mod diff;
+mod dot;
+mod generate;
+mod pipeline_graph;
+mod viz;
+
+pub use self::build::Build;
+pub use self::check::{check_graph, Diagnostic, Location, NodeTypes, TypeIndex};
+pub use self::description::*;
+pub use self::diff::{diff_graphs, Change};
+pub use self::dot::nodes_edges_from_dot;
+pub use self::generate::{generate, GenerateOptions};
+pub use self::pipeline_graph::*;
+pub use self::viz::{visualize, VizFormat};
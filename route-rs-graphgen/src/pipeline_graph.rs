@@ -1,7 +1,12 @@
+use crate::check::{check_graph, Diagnostic, TypeIndex};
+use crate::description::PipelineDescription;
+use crate::dot::nodes_edges_from_dot;
 use petgraph::graph::NodeIndex;
 use petgraph::{Directed, Graph};
+use quote::ToTokens;
 use std::collections::HashMap;
-use std::io::Read;
+use std::io::{Cursor, Read};
+use std::path::Path;
 use xml::attribute::OwnedAttribute;
 use xml::name::OwnedName;
 use xml::reader::{EventReader, XmlEvent};
@@ -13,6 +18,30 @@ pub enum NodeKind {
     Classifier,
     Processor,
     IO,
+    /// A ForkLink, which copies each packet to every outgoing edge. Like an IO node, its class is
+    /// the type of packet it forks.
+    Fork,
+    /// A composite link, whose class is the path of a type implementing `LinkBuilder`. Outgoing
+    /// edges are labeled with the index of the egressor they leave from.
+    Composite,
+    /// A DropLink, which discards every packet it receives and counts them in a static the
+    /// generated pipeline exports. Like a Fork, its class is the type of packet it drops.
+    Drop,
+}
+
+impl NodeKind {
+    /// Parses the `kind` declared on a node in any graph format.
+    pub fn from_name(node_id: &str, name: &str) -> NodeKind {
+        match name.to_lowercase().as_str() {
+            "io" => NodeKind::IO,
+            "processor" => NodeKind::Processor,
+            "classifier" => NodeKind::Classifier,
+            "fork" => NodeKind::Fork,
+            "composite" => NodeKind::Composite,
+            "drop" => NodeKind::Drop,
+            _ => panic!("Node {} has unknown kind {}", node_id, name),
+        }
+    }
 }
 
 impl Default for NodeKind {
@@ -26,6 +55,122 @@ pub struct NodeData {
     pub xml_node_id: XmlNodeId,
     pub node_class: String,
     pub node_kind: NodeKind,
+    /// Rust expressions passed to the constructor of a Processor, Classifier or composite link.
+    pub args: Vec<String>,
+    /// Queue capacity of a Fork, or of a Processor to run it in a QueueLink rather than a
+    /// ProcessLink.
+    pub capacity: Option<usize>,
+}
+
+/// Parses the `capacity` declared on a node.
+pub fn parse_capacity(node_id: &str, capacity: &str) -> usize {
+    match capacity.trim().parse::<usize>() {
+        Ok(capacity) if capacity > 0 => capacity,
+        _ => panic!(
+            "Node {} has invalid capacity {}, must be a positive integer",
+            node_id, capacity
+        ),
+    }
+}
+
+/// Returns the expression a config reference compiles to. Generated pipelines read their
+/// configuration from functions in the `config` module of the crate they are built into, so the
+/// reference `rate_limit` becomes the call `crate::config::rate_limit()`.
+pub fn config_ref(name: &str) -> String {
+    if syn::parse_str::<syn::Ident>(name).is_err() {
+        panic!("Invalid config reference {}", name);
+    }
+    format!("crate::config::{}()", name)
+}
+
+/// Splits the comma separated constructor arguments declared on a node into Rust expressions.
+/// Outside of string literals, `$name` is a config reference, see `config_ref`.
+///
+/// ```text
+/// 100, "eth0", $rate_limit  =>  ["100", "\"eth0\"", "crate::config::rate_limit()"]
+/// ```
+pub fn parse_args(node_id: &str, source: &str) -> Vec<String> {
+    let mut expanded = String::new();
+    let mut chars = source.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            expanded.push(c);
+            match c {
+                '\\' => expanded.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '$' {
+            let mut name = String::new();
+            while let Some(&n) = chars.peek() {
+                if !(n.is_alphanumeric() || n == '_') {
+                    break;
+                }
+                name.push(n);
+                chars.next();
+            }
+            expanded.push_str(&config_ref(&name));
+        } else {
+            in_string = c == '"';
+            expanded.push(c);
+        }
+    }
+
+    let expanded = expanded.trim().trim_end_matches(',');
+    if expanded.is_empty() {
+        return vec![];
+    }
+    match syn::parse_str::<syn::ExprTuple>(&format!("({},)", expanded)) {
+        Ok(tuple) => tuple
+            .elems
+            .iter()
+            .map(|e| e.to_token_stream().to_string())
+            .collect(),
+        Err(err) => panic!("Node {} has invalid arguments {}: {}", node_id, source, err),
+    }
+}
+
+#[cfg(test)]
+mod parse_args {
+    use super::*;
+
+    #[test]
+    fn literals() {
+        assert_eq!(
+            parse_args("limit", r#"100, -2.5, true, "a, b""#),
+            vec!["100", "- 2.5", "true", r#""a, b""#]
+        );
+        assert!(parse_args("identity", " ").is_empty());
+        assert_eq!(parse_args("limit", "1,"), vec!["1"]);
+    }
+
+    #[test]
+    fn config_references() {
+        assert_eq!(
+            parse_args(
+                "route",
+                r#"$subnet, Duration::from_secs($timeout), "$not_config""#
+            ),
+            vec![
+                "crate :: config :: subnet ()",
+                "Duration :: from_secs (crate :: config :: timeout ())",
+                r#""$not_config""#,
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_config_reference() {
+        parse_args("limit", "$, 1");
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_expression() {
+        parse_args("limit", "1 +");
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
@@ -38,47 +183,108 @@ pub struct EdgeData {
 
 pub struct PipelineGraph {
     graph: Graph<NodeData, EdgeData, Directed>,
+    /// The nodes and edges as they were declared, for `check` to report problems against.
+    declared: (Vec<NodeData>, Vec<EdgeData>),
 }
 
 impl PipelineGraph {
     pub fn new<R: Read>(xml_source: EventReader<R>) -> Self {
         let (nodes, edges) = nodes_edges_from_xml(xml_source);
+        PipelineGraph::from_nodes_edges(nodes, edges)
+    }
+
+    /// Builds a graph from the source of a drawio diagram, see `nodes_edges_from_xml`.
+    pub fn from_drawio(xml_source: &str) -> Self {
+        PipelineGraph::new(EventReader::new(Cursor::new(xml_source)))
+    }
+
+    /// Builds a graph from the source of a GraphViz digraph, see `nodes_edges_from_dot`.
+    pub fn from_dot(dot_source: &str) -> Self {
+        let (nodes, edges) = nodes_edges_from_dot(dot_source);
+        PipelineGraph::from_nodes_edges(nodes, edges)
+    }
+
+    /// Builds a graph from a JSON pipeline description, see `PipelineDescription`.
+    pub fn from_json(json_source: &str) -> Self {
+        PipelineGraph::from_description(PipelineDescription::from_json(json_source))
+    }
+
+    /// Builds a graph from a YAML pipeline description, see `PipelineDescription`.
+    pub fn from_yaml(yaml_source: &str) -> Self {
+        PipelineGraph::from_description(PipelineDescription::from_yaml(yaml_source))
+    }
 
+    /// Reads a graph in the format given by its extension: `.xml` or `.drawio`, `.dot` or `.gv`,
+    /// `.json`, and `.yaml` or `.yml`.
+    pub fn from_file(graph_path: &Path) -> Self {
+        let source = match std::fs::read_to_string(graph_path) {
+            Ok(source) => source,
+            Err(err) => panic!("Could not read {}: {}", graph_path.display(), err),
+        };
+        match graph_path.extension().and_then(|e| e.to_str()) {
+            Some("xml") | Some("drawio") => PipelineGraph::from_drawio(&source),
+            Some("dot") | Some("gv") => PipelineGraph::from_dot(&source),
+            Some("json") => PipelineGraph::from_json(&source),
+            Some("yaml") | Some("yml") => PipelineGraph::from_yaml(&source),
+            _ => panic!(
+                "Unknown graph format of {}, expected .xml, .dot, .json or .yaml",
+                graph_path.display()
+            ),
+        }
+    }
+
+    pub fn from_description(description: PipelineDescription) -> Self {
+        let (nodes, edges) = description.nodes_edges();
+        PipelineGraph::from_nodes_edges(nodes, edges)
+    }
+
+    /// Builds a graph from nodes and edges in any format. Edges between missing nodes are left out
+    /// of the graph, and reported by `check`.
+    pub fn from_nodes_edges(nodes: Vec<NodeData>, edges: Vec<EdgeData>) -> Self {
         let mut graph = Graph::<NodeData, EdgeData, Directed>::new();
 
         let mut node_map = HashMap::<XmlNodeId, NodeIndex>::new();
 
-        for n in nodes {
+        for n in &nodes {
             let node_name = n.xml_node_id.clone();
-            let index = graph.add_node(n);
+            let index = graph.add_node(n.clone());
             node_map.insert(node_name, index);
         }
 
-        for e in edges {
-            let source_index = node_map[&e.source];
-            let target_index = node_map[&e.target];
-            graph.extend_with_edges(&[(source_index, target_index, e)]);
+        for e in &edges {
+            if let (Some(source_index), Some(target_index)) =
+                (node_map.get(&e.source), node_map.get(&e.target))
+            {
+                graph.extend_with_edges(&[(*source_index, *target_index, e.clone())]);
+            }
         }
 
-        let mut g = PipelineGraph { graph };
+        let mut g = PipelineGraph {
+            graph,
+            declared: (nodes, edges),
+        };
         g.mark_classifiers();
         g
     }
 
-    /// Converts processors that have multiple output edges into Classifiers. In the future we'll
-    /// want to distinguish between Classifiers and Tees based on whether they have labels, but for
-    /// now we only have a Classifier example.
+    /// Checks the graph for mistakes that would otherwise surface as panics or compile errors in
+    /// the generated pipeline, see `check_graph`.
+    pub fn check(&self, types: &TypeIndex) -> Vec<Diagnostic> {
+        check_graph(&self.declared.0, &self.declared.1, types)
+    }
+
+    /// Converts processors that have multiple output edges into Classifiers. Copying packets to
+    /// several edges is done with an explicit Fork node instead.
     pub fn mark_classifiers(&mut self) {
         self.graph.node_indices().for_each(|ni| {
-            if self.graph.edges(ni).count() > 1 {
+            if self.graph[ni].node_kind == NodeKind::Processor && self.graph.edges(ni).count() > 1 {
                 let mut weight = self.graph.node_weight_mut(ni).unwrap();
                 weight.node_kind = NodeKind::Classifier;
             }
         })
     }
 
-    /// Provides a vector of all nodes in the graph, in arbitrary order.
-    #[allow(dead_code)] // This method isn't used but it feels ridiculous to not implement it
+    /// Provides a vector of all nodes in the graph, in the order they were declared.
     pub fn nodes(&self) -> Vec<&NodeData> {
         self.graph
             .node_indices()
@@ -153,8 +359,10 @@ mod PipelineGraph_ordered_nodes {
 /// extracted from that source.
 ///
 /// Nodes with the rhombus shape are considered IO types. Nodes with the default shape are
-/// considered Processor types.
-fn nodes_edges_from_xml<R: Read>(xml_source: EventReader<R>) -> (Vec<NodeData>, Vec<EdgeData>) {
+/// considered Processor types, unless a `kind` style says otherwise, see `NodeKind::from_name`.
+/// Constructor arguments are declared with an `args` style, see `parse_args`, and queue capacity
+/// with a `capacity` style.
+pub fn nodes_edges_from_xml<R: Read>(xml_source: EventReader<R>) -> (Vec<NodeData>, Vec<EdgeData>) {
     let mut nodes = vec![];
     let mut edges = vec![];
 
@@ -172,14 +380,22 @@ fn nodes_edges_from_xml<R: Read>(xml_source: EventReader<R>) -> (Vec<NodeData>,
             if xml_node_name == "mxCell" {
                 if has_attr(&attrs, "vertex") {
                     let styles = get_styles(&attrs);
+                    let xml_node_id = get_attr(&attrs, "id").unwrap();
                     nodes.push(NodeData {
-                        xml_node_id: get_attr(&attrs, "id").unwrap(),
+                        args: match styles.get("args") {
+                            Some(args) => parse_args(&xml_node_id, args),
+                            None => vec![],
+                        },
+                        capacity: styles
+                            .get("capacity")
+                            .map(|c| parse_capacity(&xml_node_id, c)),
                         node_class: get_attr(&attrs, "value").unwrap(),
-                        node_kind: if styles.contains_key("rhombus") {
-                            NodeKind::IO
-                        } else {
-                            NodeKind::Processor
+                        node_kind: match styles.get("kind") {
+                            Some(kind) => NodeKind::from_name(&xml_node_id, kind),
+                            None if styles.contains_key("rhombus") => NodeKind::IO,
+                            None => NodeKind::Processor,
                         },
+                        xml_node_id,
                     });
                 } else if has_attr(&attrs, "edge") {
                     edges.push(EdgeData {
@@ -204,8 +420,7 @@ mod nodes_edges_from_xml {
 
     #[test]
     fn rhombus_xml() {
-        let xml = r#"
-            <?xml version="1.0" encoding="UTF-8"?>
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
             <mxGraphModel>
                 <root>
                     <mxCell id="fooasdfbar-1" style="rhombus" vertex="1" value="FooAsdfBar">
@@ -223,8 +438,7 @@ mod nodes_edges_from_xml {
 
     #[test]
     fn rect_xml() {
-        let xml = r#"
-            <?xml version="1.0" encoding="UTF-8"?>
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
             <mxGraphModel>
                 <root>
                     <mxCell id="fooasdfbar-1" style="" vertex="1" value="FooAsdfBar">
@@ -239,6 +453,30 @@ mod nodes_edges_from_xml {
         assert_eq!(nodes.len(), 1);
         assert_eq!(nodes[0].node_kind, NodeKind::Processor);
     }
+
+    #[test]
+    fn args_xml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <mxGraphModel>
+                <root>
+                    <mxCell id="limit-1" style="rounded=1;args=100, &quot;eth0&quot;, $burst" vertex="1" value="RateLimiter"/>
+                    <mxCell id="identity-1" style="" vertex="1" value="Identity"/>
+                    <mxCell id="fork-1" style="kind=fork;capacity=20" vertex="1" value="Packet"/>
+                </root>
+            </mxGraphModel>
+        "#;
+
+        let (nodes, _) = nodes_edges_from_xml(EventReader::new(Cursor::new(xml)));
+
+        assert_eq!(
+            nodes[0].args,
+            vec!["100", r#""eth0""#, "crate :: config :: burst ()"]
+        );
+        assert!(nodes[1].args.is_empty());
+        assert_eq!(nodes[1].capacity, None);
+        assert_eq!(nodes[2].node_kind, NodeKind::Fork);
+        assert_eq!(nodes[2].capacity, Some(20));
+    }
 }
 
 /// Helper method to extract an attribute from the attributes vector.
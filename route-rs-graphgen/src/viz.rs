@@ -0,0 +1,149 @@
+use crate::generate::{pipeline_links, Link};
+use crate::pipeline_graph::PipelineGraph;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VizFormat {
+    Dot,
+    Mermaid,
+}
+
+/// Renders the links graphgen lays a graph out as, including the JoinLinks it inserts in front of
+/// nodes with several incoming edges, so the pipeline can be compared against the graph it was
+/// drawn as. Each link is labeled with the ID of its node and the link it is generated as.
+pub fn visualize(graph: &PipelineGraph, format: VizFormat) -> String {
+    let classes: HashMap<&str, &str> = graph
+        .nodes()
+        .into_iter()
+        .map(|n| (n.xml_node_id.as_str(), n.node_class.as_str()))
+        .collect();
+    let links = pipeline_links(graph);
+    let indices: HashMap<&str, usize> = links
+        .iter()
+        .enumerate()
+        .map(|(index, (id, _))| (id.as_str(), index + 1))
+        .collect();
+
+    let mut lines = vec![];
+    for (index, (id, link)) in links.iter().enumerate() {
+        let label = format!("{}\n{}", id, describe(link, &classes));
+        lines.push(match format {
+            VizFormat::Dot => format!("link_{} [label=\"{}\"];", index + 1, escape_dot(&label)),
+            VizFormat::Mermaid => format!("link_{}[\"{}\"]", index + 1, escape_mermaid(&label)),
+        });
+    }
+    for (index, (_, link)) in links.iter().enumerate() {
+        for (source, label) in link.feeders() {
+            let source_index = indices[source.as_str()];
+            // Fork egressors are keyed by edge ID, which says nothing the drawing doesn't.
+            let label = match &links[source_index - 1].1 {
+                Link::Fork(_, _, _) => None,
+                _ => label.as_ref(),
+            };
+            lines.push(match (format, label) {
+                (VizFormat::Dot, None) => format!("link_{} -> link_{};", source_index, index + 1),
+                (VizFormat::Dot, Some(label)) => format!(
+                    "link_{} -> link_{} [label=\"{}\"];",
+                    source_index,
+                    index + 1,
+                    escape_dot(label)
+                ),
+                (VizFormat::Mermaid, None) => {
+                    format!("link_{} --> link_{}", source_index, index + 1)
+                }
+                (VizFormat::Mermaid, Some(label)) => format!(
+                    "link_{} -->|\"{}\"| link_{}",
+                    source_index,
+                    escape_mermaid(label),
+                    index + 1
+                ),
+            });
+        }
+    }
+
+    let body: Vec<String> = lines.iter().map(|l| format!("    {}", l)).collect();
+    match format {
+        VizFormat::Dot => format!("digraph pipeline {{\n{}\n}}\n", body.join("\n")),
+        VizFormat::Mermaid => format!("graph LR\n{}\n", body.join("\n")),
+    }
+}
+
+/// The link type, with the Processor, channel or capacity it is built with.
+fn describe(link: &Link, classes: &HashMap<&str, &str>) -> String {
+    let params = match link {
+        Link::Input(channel) | Link::Output(_, channel) => vec![channel.to_owned()],
+        Link::Sync(_, processor) | Link::Classify(_, processor, _, _) => {
+            vec![classes[processor.as_str()].to_owned()]
+        }
+        Link::Queue(_, processor, capacity) => {
+            vec![classes[processor.as_str()].to_owned(), capacity.to_string()]
+        }
+        Link::Fork(_, _, capacity) => capacity.iter().map(|c| c.to_string()).collect(),
+        Link::Composite(_, node, _) => node.args.to_owned(),
+        Link::Join(_) => vec![],
+        Link::Drop(_, counter) => vec![counter.to_owned()],
+    };
+    if params.is_empty() {
+        link.link_type()
+    } else {
+        format!("{}({})", link.link_type(), params.join(", "))
+    }
+}
+
+fn escape_dot(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_mermaid(label: &str) -> String {
+    label.replace('"', "#quot;").replace('\n', "<br/>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> PipelineGraph {
+        PipelineGraph::from_dot(
+            r#"digraph {
+                input [kind=io, label=Packet];
+                output [kind=io, label=Packet];
+                copy [kind=fork, label=Packet];
+                even [label=Even];
+                tag [label=Tag, args="\"odd\"", capacity=10];
+                input -> copy;
+                copy -> even;
+                copy -> output;
+                even -> tag [label=false];
+                even -> output [label=true];
+                tag -> output;
+            }"#,
+        )
+    }
+
+    #[test]
+    fn dot() {
+        let viz = visualize(&graph(), VizFormat::Dot);
+
+        assert!(viz.starts_with("digraph pipeline {\n"));
+        assert!(viz.contains(r#"link_1 [label="input\nInputChannelLink(input_channel)"];"#));
+        assert!(viz.contains(r#"[label="tag\nQueueLink(Tag, 10)"];"#));
+        assert!(viz.contains(r#"[label="join_output\nJoinLink"];"#));
+        assert!(viz.contains(r#"[label="output\nOutputChannelLink(output_channel)"];"#));
+        assert!(viz.contains("link_1 -> link_2;"));
+        assert!(viz.contains(r#" [label="false"];"#));
+        assert!(!viz.contains("edge-"));
+    }
+
+    #[test]
+    fn mermaid() {
+        let viz = visualize(&graph(), VizFormat::Mermaid);
+
+        assert!(viz.starts_with("graph LR\n"));
+        assert!(viz.contains(r#"link_1["input<br/>InputChannelLink(input_channel)"]"#));
+        assert!(viz.contains("link_1 --> link_2"));
+        assert!(viz.contains(r#"-->|"true"|"#));
+    }
+}
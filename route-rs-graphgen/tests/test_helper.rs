@@ -6,6 +6,7 @@ pub struct TestHelper {
     pub tmpdir: PathBuf,
     pub example_crate: String,
     pub test_name: String,
+    pub graph_file_name: String,
     pub extra_args: Vec<String>,
 }
 
@@ -14,6 +15,21 @@ impl TestHelper {
     where
         S: Into<String>,
         T: Into<String>,
+    {
+        TestHelper::with_graph_file(example_crate, "pipeline.xml", extra_args)
+    }
+
+    /// Like `new`, but reads the graph from another file in the example crate's `src`, to test the
+    /// other input formats against the same expected pipeline.
+    pub fn with_graph_file<S, G, T>(
+        example_crate: S,
+        graph_file_name: G,
+        extra_args: Vec<T>,
+    ) -> Self
+    where
+        S: Into<String>,
+        G: Into<String>,
+        T: Into<String>,
     {
         let root = Path::new(env!("CARGO_MANIFEST_DIR"))
             .join(Path::new(".."))
@@ -24,16 +40,24 @@ impl TestHelper {
         let mut test_name = example_crate_string.clone();
         test_name.retain(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
         test_name = test_name.replace('-', "_");
+        let graph_file_name = graph_file_name.into();
+        if graph_file_name != "pipeline.xml" {
+            // Keep a separate tmpdir from the drawio test of the same example.
+            test_name.push('_');
+            test_name.push_str(&graph_file_name.replace('.', "_"));
+        }
+        let extra_args: Vec<String> = extra_args.into_iter().map(|s| s.into()).collect();
+        if extra_args.iter().any(|a| a == "--check") {
+            test_name.push_str("_check");
+        }
 
         TestHelper {
             root,
             tmpdir: global_tmpdir.join("integration-test-route-rs-graphgen"),
             example_crate: example_crate_string,
             test_name,
-            extra_args: extra_args
-                .into_iter()
-                .map(|s| s.into())
-                .collect::<Vec<String>>(),
+            graph_file_name,
+            extra_args,
         }
         .initialize()
     }
@@ -75,7 +99,7 @@ impl TestHelper {
     }
 
     pub fn graph_file(&self) -> PathBuf {
-        self.crate_dir().join("src/pipeline.xml")
+        self.crate_dir().join("src").join(&self.graph_file_name)
     }
 
     pub fn pipeline_file(&self) -> PathBuf {
@@ -97,6 +121,23 @@ impl TestHelper {
         );
     }
 
+    /// Runs graphgen without an output file, for tests passing `--check`.
+    pub fn run_check(&self) {
+        let graphgen_cmd = Command::new(self.graphgen_binary())
+            .arg("--graph")
+            .arg(self.graph_file())
+            .args(&self.extra_args)
+            .output()
+            .expect("Failed to execute graphgen");
+        assert!(
+            graphgen_cmd.status.success(),
+            "Output:\n{}\n\nError:\n{}",
+            String::from_utf8(graphgen_cmd.stdout).unwrap(),
+            String::from_utf8(graphgen_cmd.stderr).unwrap(),
+        );
+        assert!(!self.output_file().exists());
+    }
+
     pub fn run_diff(&self) {
         let diff_cmd = Command::new("diff")
             .arg("-u")
@@ -25,3 +25,75 @@ fn dns_interceptor() {
     test_helper.run_graphgen();
     test_helper.run_diff();
 }
+
+#[test]
+fn dns_interceptor_dot() {
+    let test_helper = test_helper::TestHelper::with_graph_file(
+        "dns-interceptor",
+        "pipeline.dot",
+        vec!["--rustfmt", "--format", "dot"],
+    );
+
+    test_helper.run_graphgen();
+    test_helper.run_diff();
+}
+
+#[test]
+fn trivial_identity_yaml() {
+    let test_helper = test_helper::TestHelper::with_graph_file(
+        "trivial-identity",
+        "pipeline.yaml",
+        vec![
+            "--rustfmt",
+            "--format",
+            "yaml",
+            "--local-modules",
+            "packets",
+            "--runtime-modules",
+            "processor",
+        ],
+    );
+
+    test_helper.run_graphgen();
+    test_helper.run_diff();
+}
+
+#[test]
+fn dns_interceptor_json() {
+    let test_helper = test_helper::TestHelper::with_graph_file(
+        "dns-interceptor",
+        "pipeline.json",
+        vec!["--rustfmt", "--format", "json"],
+    );
+
+    test_helper.run_graphgen();
+    test_helper.run_diff();
+}
+
+#[test]
+fn dns_interceptor_check() {
+    let test_helper = test_helper::TestHelper::with_graph_file(
+        "dns-interceptor",
+        "pipeline.dot",
+        vec!["--check", "--format", "dot"],
+    );
+
+    test_helper.run_check();
+}
+
+#[test]
+fn trivial_identity_library() {
+    let yaml = std::fs::read_to_string("../examples/trivial-identity/src/pipeline.yaml").unwrap();
+    let graph = route_rs_graphgen::PipelineGraph::from_yaml(&yaml);
+    let source = route_rs_graphgen::generate(
+        &graph,
+        &route_rs_graphgen::GenerateOptions::new()
+            .source_graph("pipeline.yaml")
+            .local_modules(vec!["packets"])
+            .runtime_modules(vec!["processor"]),
+    );
+
+    assert!(source.contains("Source graph: pipeline.yaml"));
+    assert!(source.contains("packets"));
+    assert!(source.contains("Runner for Pipeline"));
+}
@@ -0,0 +1,14 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use route_rs_packets::{ArpFrame, EthernetFrame, ARP_ETHER_TYPE};
+use std::convert::TryFrom;
+
+// The input is the ARP payload, behind an Ethernet header with the ARP ether type.
+fuzz_target!(|data: &[u8]| {
+    let mut frame = EthernetFrame::empty();
+    frame.set_ether_type(ARP_ETHER_TYPE);
+    frame.set_payload(data);
+    if let Ok(arp_frame) = ArpFrame::try_from(frame) {
+        route_rs_packets_fuzz::arp(arp_frame);
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use route_rs_packets::EthernetFrame;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(frame) = EthernetFrame::from_buffer(data.to_vec(), 0) {
+        route_rs_packets_fuzz::ethernet(frame);
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use route_rs_packets::Ipv6Packet;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(packet) = Ipv6Packet::from_buffer(data.to_vec(), None, 0) {
+        route_rs_packets_fuzz::ipv6(packet);
+    }
+});
@@ -0,0 +1,35 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use route_rs_packets::{EthernetFrame, IPV4_ETHER_TYPE, IPV6_ETHER_TYPE};
+
+// Frames whose first byte picks the protocol of their payload, so the fuzzer reaches the parsers
+// beneath IPv4 and IPv6 without having to find their ether type, header length and protocol.
+fuzz_target!(|data: &[u8]| {
+    let (selector, payload) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let mut frame = EthernetFrame::empty();
+    match selector % 3 {
+        0 => {
+            frame.set_ether_type(IPV4_ETHER_TYPE);
+            let mut header = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, selector / 3, 0, 0];
+            header.resize(20, 0);
+            let total_len = (header.len() + payload.len()).min(usize::from(u16::MAX)) as u16;
+            header[2..4].copy_from_slice(&total_len.to_be_bytes());
+            header.extend_from_slice(&payload[..usize::from(total_len) - 20]);
+            frame.set_payload(&header);
+        }
+        1 => {
+            frame.set_ether_type(IPV6_ETHER_TYPE);
+            let mut header = vec![0x60, 0, 0, 0, 0, 0, selector / 3, 64];
+            header.resize(40, 0);
+            let payload_len = payload.len().min(usize::from(u16::MAX));
+            header[4..6].copy_from_slice(&(payload_len as u16).to_be_bytes());
+            header.extend_from_slice(&payload[..payload_len]);
+            frame.set_payload(&header);
+        }
+        _ => frame.set_payload(payload),
+    }
+    route_rs_packets_fuzz::ethernet(frame);
+});
@@ -0,0 +1,131 @@
+#![no_main]
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use route_rs_packets::{EthernetFrame, IpProtocol, Ipv4Packet, TcpSegment, UdpSegment};
+use std::convert::TryFrom;
+use std::net::Ipv4Addr;
+
+/// A change to a field of a parsed IPv4 packet, or of the TCP or UDP segment it carries.
+#[derive(Arbitrary, Debug)]
+enum Edit {
+    SrcAddr([u8; 4]),
+    DestAddr([u8; 4]),
+    Ttl(u8),
+    Dscp(u8),
+    Ecn(u8),
+    Identification(u16),
+    FragmentOffset(u16),
+    Flags(bool, bool),
+    Payload(Vec<u8>),
+    SrcPort(u16),
+    DestPort(u16),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    frame: Vec<u8>,
+    edits: Vec<Edit>,
+}
+
+/// The fields of an IPv4 packet a router reads and writes.
+#[derive(Debug, PartialEq)]
+struct Fields {
+    src_addr: Ipv4Addr,
+    dest_addr: Ipv4Addr,
+    protocol: IpProtocol,
+    ttl: u8,
+    dscp: u8,
+    ecn: u8,
+    identification: u16,
+    fragment_offset: u16,
+    flags: (bool, bool),
+    payload: Vec<u8>,
+}
+
+impl Fields {
+    fn of(packet: &Ipv4Packet) -> Self {
+        Fields {
+            src_addr: packet.src_addr(),
+            dest_addr: packet.dest_addr(),
+            protocol: packet.protocol(),
+            ttl: packet.ttl(),
+            dscp: packet.dscp(),
+            ecn: packet.ecn(),
+            identification: packet.indentification(),
+            fragment_offset: packet.fragment_offset(),
+            flags: packet.flags(),
+            payload: packet.payload().into_owned(),
+        }
+    }
+}
+
+// Parses a frame, edits the IPv4 packet it carries, serializes it, and parses it again, which must
+// give back the packet as edited. The ports of a TCP or UDP segment it carries are then edited and
+// checked the same way.
+fuzz_target!(|input: Input| {
+    let frame = match EthernetFrame::from_buffer(input.frame, 0) {
+        Ok(frame) => frame,
+        Err(_) => return,
+    };
+    let mut packet = match Ipv4Packet::try_from(frame) {
+        Ok(packet) => packet,
+        Err(_) => return,
+    };
+
+    let mut ports = (None, None);
+    for edit in &input.edits {
+        match edit {
+            Edit::SrcAddr(addr) => packet.set_src_addr(Ipv4Addr::from(*addr)),
+            Edit::DestAddr(addr) => packet.set_dest_addr(Ipv4Addr::from(*addr)),
+            Edit::Ttl(ttl) => packet.set_ttl(*ttl),
+            Edit::Dscp(dscp) => packet.set_dscp(dscp & 0x3f),
+            Edit::Ecn(ecn) => packet.set_ecn(*ecn),
+            Edit::Identification(id) => packet.set_identification(*id),
+            Edit::FragmentOffset(offset) => packet.set_fragment_offset(offset & 0x1fff),
+            Edit::Flags(df, mf) => packet.set_flags(*df, *mf),
+            // Payloads that fit in a packet, as a router never sends larger.
+            Edit::Payload(payload) => packet.set_payload(&payload[..payload.len().min(1480)]),
+            Edit::SrcPort(port) => ports.0 = Some(*port),
+            Edit::DestPort(port) => ports.1 = Some(*port),
+        }
+    }
+    packet.set_checksum();
+    let edited = Fields::of(&packet);
+
+    let frame = EthernetFrame::from_buffer(packet.data, 0).expect("edited frame doesn't parse");
+    let mut packet = Ipv4Packet::try_from(frame).expect("edited packet doesn't parse");
+    assert_eq!(Fields::of(&packet), edited);
+    assert!(packet.validate_checksum());
+
+    match packet.protocol() {
+        IpProtocol::TCP => {
+            if let Ok(mut segment) = TcpSegment::try_from(packet) {
+                let expected = (
+                    ports.0.unwrap_or_else(|| segment.src_port()),
+                    ports.1.unwrap_or_else(|| segment.dest_port()),
+                );
+                segment.set_src_port(expected.0);
+                segment.set_dest_port(expected.1);
+                let frame = EthernetFrame::from_buffer(segment.data, 0).unwrap();
+                let packet = Ipv4Packet::try_from(frame).unwrap();
+                let segment = TcpSegment::try_from(packet).expect("edited segment doesn't parse");
+                assert_eq!((segment.src_port(), segment.dest_port()), expected);
+            }
+        }
+        IpProtocol::UDP => {
+            if let Ok(mut segment) = UdpSegment::try_from(packet) {
+                let expected = (
+                    ports.0.unwrap_or_else(|| segment.src_port()),
+                    ports.1.unwrap_or_else(|| segment.dest_port()),
+                );
+                segment.set_src_port(expected.0);
+                segment.set_dest_port(expected.1);
+                let frame = EthernetFrame::from_buffer(segment.data, 0).unwrap();
+                let packet = Ipv4Packet::try_from(frame).unwrap();
+                let segment = UdpSegment::try_from(packet).expect("edited segment doesn't parse");
+                assert_eq!((segment.src_port(), segment.dest_port()), expected);
+            }
+        }
+        _ => {}
+    }
+});
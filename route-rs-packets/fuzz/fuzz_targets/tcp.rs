@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use route_rs_packets::TcpSegment;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(segment) = TcpSegment::from_buffer(data.to_vec(), None, None, 0) {
+        route_rs_packets_fuzz::tcp(segment);
+    }
+});
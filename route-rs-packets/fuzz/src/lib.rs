@@ -0,0 +1,178 @@
+//! Reads every field of each packet the fuzz targets parse, so a parser that accepts a packet its
+//! accessors then index out of bounds of fails the target, as it would fail a router.
+//!
+//! Each function reads the fields of one layer and parses the layers it can carry, so a target for
+//! the outermost layer a router parses covers every layer beneath it.
+
+use route_rs_packets::*;
+use std::convert::TryFrom;
+use std::hint::black_box;
+
+pub fn ethernet(frame: EthernetFrame) {
+    black_box((frame.dest_mac(), frame.src_mac(), frame.ether_type()));
+    black_box(frame.payload());
+
+    if let Ok(arp_frame) = ArpFrame::try_from(frame.clone()) {
+        arp(arp_frame);
+    }
+    if let Ok(eapol) = EapolFrame::try_from(frame.clone()) {
+        black_box((eapol.version(), eapol.packet_type(), eapol.body_len()));
+        black_box(eapol.body());
+    }
+    if let Ok(lldp) = LldpFrame::try_from(frame.clone()) {
+        black_box((lldp.chassis_id(), lldp.port_id(), lldp.ttl()));
+        black_box(lldp.tlvs().count());
+    }
+    if let Ok(macsec) = MacsecFrame::try_from(frame.clone()) {
+        black_box((
+            macsec.tci(),
+            macsec.association_number(),
+            macsec.short_length(),
+        ));
+        black_box((macsec.packet_number(), macsec.sci(), macsec.sectag_len()));
+        black_box((macsec.header(), macsec.secure_data()));
+    }
+    if let Ok(pppoe) = PppoeFrame::try_from(frame.clone()) {
+        black_box((pppoe.is_discovery(), pppoe.code(), pppoe.session_id()));
+        black_box((pppoe.length(), pppoe.pppoe_payload(), pppoe.tags()));
+        black_box((pppoe.ppp_protocol(), pppoe.ppp_payload()));
+        ppp(pppoe.ppp_payload());
+    }
+    if let Ok(packet) = Ipv4Packet::try_from(frame.clone()) {
+        ipv4(packet);
+    }
+    if let Ok(packet) = Ipv6Packet::try_from(frame) {
+        ipv6(packet);
+    }
+}
+
+pub fn arp(frame: ArpFrame) {
+    black_box((frame.hardware_type(), frame.protocol_type(), frame.opcode()));
+    black_box((frame.hardware_addr_len(), frame.protocol_addr_len()));
+    black_box((frame.sender_hardware_addr(), frame.sender_protocol_addr()));
+    black_box((frame.target_hardware_addr(), frame.target_protocol_addr()));
+}
+
+pub fn ppp(data: &[u8]) {
+    if let Ok(packet) = PppControlPacket::try_from(data) {
+        black_box(packet.options());
+        black_box(packet.to_bytes());
+    }
+}
+
+pub fn dns(data: Vec<u8>) {
+    if let Ok(message) = DnsMessage::from_bytes(data) {
+        black_box((message.id(), message.is_response(), message.is_truncated()));
+        black_box((message.rcode(), message.question()));
+        black_box((message.questions(), message.records()));
+        black_box(message.response(0));
+    }
+}
+
+pub fn ipv4(packet: Ipv4Packet) {
+    black_box((packet.src_addr(), packet.dest_addr(), packet.ihl()));
+    black_box((packet.payload(), packet.options()));
+    black_box((packet.protocol(), packet.total_len(), packet.ttl()));
+    black_box((packet.checksum(), packet.caclulate_checksum()));
+    black_box((packet.dscp(), packet.ecn(), packet.indentification()));
+    black_box((packet.fragment_offset(), packet.flags()));
+    black_box(FlowKey::from_ipv4(&packet).reverse());
+
+    if let Ok(segment) = TcpSegment::try_from(packet.clone()) {
+        tcp(segment);
+    }
+    if let Ok(segment) = UdpSegment::try_from(packet.clone()) {
+        udp(segment);
+    }
+    if let Ok(sctp_packet) = SctpPacket::try_from(packet.clone()) {
+        sctp(sctp_packet);
+    }
+    if let Ok(igmp) = IgmpPacket::try_from(packet.clone()) {
+        black_box((igmp.message_type(), igmp.max_resp_time()));
+        black_box((igmp.checksum(), igmp.group_addr()));
+        for record in igmp.group_records() {
+            black_box((record.is_join(), record.is_leave()));
+        }
+    }
+    if let Ok(esp) = EspPacket::try_from(packet.clone()) {
+        black_box((esp.spi(), esp.sequence(), esp.header(), esp.esp_payload()));
+    }
+    if let Ok(gre) = GrePacket::try_from(packet.clone()) {
+        black_box((gre.protocol_type(), gre.key(), gre.sequence()));
+        black_box((gre.header_len(), gre.gre_payload()));
+        if let Ok(erspan) = ErspanPacket::try_from(gre) {
+            black_box((erspan.erspan_type(), erspan.vlan(), erspan.cos()));
+            black_box((erspan.session_id(), erspan.index(), erspan.timestamp()));
+            black_box(erspan.mirrored());
+        }
+    }
+    if let Ok(geneve) = GenevePacket::try_from(packet.clone()) {
+        black_box((geneve.src_port(), geneve.oam(), geneve.critical()));
+        black_box((geneve.protocol_type(), geneve.vni(), geneve.options()));
+        black_box((geneve.header_len(), geneve.inner()));
+    }
+    if let Ok(bfd) = BfdPacket::try_from(packet.clone()) {
+        black_box((bfd.src_port(), bfd.version(), bfd.diagnostic(), bfd.state()));
+        black_box((bfd.poll(), bfd.final_bit(), bfd.detect_mult()));
+        black_box((bfd.my_discriminator(), bfd.your_discriminator()));
+        black_box((bfd.desired_min_tx(), bfd.required_min_rx()));
+        black_box(bfd.required_min_echo_rx());
+    }
+    if let Ok(vrrp) = VrrpPacket::try_from(packet) {
+        black_box((vrrp.version(), vrrp.vrid(), vrrp.priority()));
+        black_box((vrrp.max_advert_interval(), vrrp.validate_checksum()));
+        black_box(vrrp.addresses());
+    }
+}
+
+pub fn ipv6(packet: Ipv6Packet) {
+    black_box((packet.traffic_class(), packet.flow_label(), packet.ecn()));
+    black_box((
+        packet.payload_length(),
+        packet.next_header(),
+        packet.hop_limit(),
+    ));
+    black_box((packet.src_addr(), packet.dest_addr()));
+    black_box((packet.payload(), packet.extension_headers()));
+    black_box(FlowKey::from_ipv6(&packet).reverse());
+
+    if let Ok(segment) = TcpSegment::try_from(packet.clone()) {
+        tcp(segment);
+    }
+    if let Ok(segment) = UdpSegment::try_from(packet.clone()) {
+        udp(segment);
+    }
+    if let Ok(sctp_packet) = SctpPacket::try_from(packet.clone()) {
+        sctp(sctp_packet);
+    }
+    if let Ok(mld) = MldPacket::try_from(packet) {
+        black_box((mld.message_type(), mld.max_resp_delay()));
+        black_box(mld.multicast_addr());
+        for record in mld.group_records() {
+            black_box((record.is_join(), record.is_leave()));
+        }
+    }
+}
+
+pub fn tcp(segment: TcpSegment) {
+    black_box((segment.src_port(), segment.dest_port()));
+    black_box((segment.sequence_number(), segment.acknowledgment_number()));
+    black_box((segment.data_offset(), segment.control_bits()));
+    black_box((segment.window_size(), segment.checksum()));
+    black_box((segment.urgent_pointer(), segment.options()));
+    black_box(segment.payload());
+}
+
+pub fn udp(segment: UdpSegment) {
+    black_box((segment.src_port(), segment.dest_port()));
+    black_box((segment.length(), segment.checksum()));
+    black_box(segment.payload());
+    dns(segment.payload().into_owned());
+}
+
+pub fn sctp(packet: SctpPacket) {
+    black_box((packet.src_port(), packet.dest_port()));
+    black_box((packet.verification_tag(), packet.checksum()));
+    black_box(packet.validate_checksum());
+    black_box(packet.chunks().count());
+}
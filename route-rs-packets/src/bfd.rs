@@ -0,0 +1,335 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+/// The UDP port single hop BFD control packets are sent to, RFC 5881 section 4.
+pub const BFD_CONTROL_PORT: u16 = 3784;
+
+const UDP_PROTOCOL: u8 = 17;
+const UDP_HEADER_LEN: usize = 8;
+const BFD_HEADER_LEN: usize = 24;
+const BFD_VERSION: u8 = 1;
+const BFD_POLL: u8 = 0x20;
+const BFD_FINAL: u8 = 0x10;
+
+/// The state of a BFD session, as its end sends it, RFC 5880 section 4.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BfdState {
+    AdminDown,
+    Down,
+    Init,
+    Up,
+}
+
+impl From<u8> for BfdState {
+    fn from(state: u8) -> Self {
+        match state & 0x03 {
+            0 => BfdState::AdminDown,
+            1 => BfdState::Down,
+            2 => BfdState::Init,
+            _ => BfdState::Up,
+        }
+    }
+}
+
+impl From<BfdState> for u8 {
+    fn from(state: BfdState) -> Self {
+        match state {
+            BfdState::AdminDown => 0,
+            BfdState::Down => 1,
+            BfdState::Init => 2,
+            BfdState::Up => 3,
+        }
+    }
+}
+
+///
+/// Ipv4Packet wrapper with getters/setters for the BFD control packets of single hop sessions, in
+/// asynchronous mode and without authentication. The UDP checksum is left at 0.
+/// https://tools.ietf.org/html/rfc5880
+/// https://tools.ietf.org/html/rfc5881
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BfdPacket {
+    packet: Ipv4Packet,
+}
+
+impl BfdPacket {
+    /// Constructs a control packet to the BFD control port, with a TTL of 255 as RFC 5881
+    /// requires, in the Down state, with a detect multiplier of 1 and every interval 0.
+    pub fn new() -> Self {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(UDP_PROTOCOL);
+        packet.set_ttl(255);
+        packet.set_payload(&[0; UDP_HEADER_LEN + BFD_HEADER_LEN]);
+        let mut bfd = BfdPacket { packet };
+        let length = ((UDP_HEADER_LEN + BFD_HEADER_LEN) as u16).to_be_bytes();
+        let udp = bfd.udp_data_mut();
+        udp[0..2].copy_from_slice(&49152u16.to_be_bytes());
+        udp[2..4].copy_from_slice(&BFD_CONTROL_PORT.to_be_bytes());
+        udp[4..6].copy_from_slice(&length);
+        let data = bfd.bfd_data_mut();
+        data[0] = BFD_VERSION << 5;
+        data[3] = BFD_HEADER_LEN as u8;
+        bfd.set_state(BfdState::Down).set_detect_mult(1);
+        bfd
+    }
+
+    /// The UDP source port, which is from 49152 to 65535, and the same for all the packets of a
+    /// session.
+    pub fn src_port(&self) -> u16 {
+        u16::from_be_bytes(self.udp_data()[0..2].try_into().unwrap())
+    }
+
+    pub fn set_src_port(&mut self, src_port: u16) -> &mut Self {
+        self.udp_data_mut()[0..2].copy_from_slice(&src_port.to_be_bytes());
+        self
+    }
+
+    pub fn version(&self) -> u8 {
+        self.bfd_data()[0] >> 5
+    }
+
+    /// Why the sender's session last went down, 5 bits, RFC 5880 section 4.1.
+    pub fn diagnostic(&self) -> u8 {
+        self.bfd_data()[0] & 0x1f
+    }
+
+    pub fn set_diagnostic(&mut self, diagnostic: u8) -> &mut Self {
+        let data = self.bfd_data_mut();
+        data[0] = (data[0] & 0xe0) | (diagnostic & 0x1f);
+        self
+    }
+
+    pub fn state(&self) -> BfdState {
+        BfdState::from(self.bfd_data()[1] >> 6)
+    }
+
+    pub fn set_state(&mut self, state: BfdState) -> &mut Self {
+        let data = self.bfd_data_mut();
+        data[1] = (data[1] & 0x3f) | (u8::from(state) << 6);
+        self
+    }
+
+    /// Whether the sender asks for a packet with the final bit set, to confirm a change of
+    /// parameters.
+    pub fn poll(&self) -> bool {
+        self.bfd_data()[1] & BFD_POLL != 0
+    }
+
+    pub fn set_poll(&mut self, poll: bool) -> &mut Self {
+        let data = self.bfd_data_mut();
+        data[1] = if poll {
+            data[1] | BFD_POLL
+        } else {
+            data[1] & !BFD_POLL
+        };
+        self
+    }
+
+    /// Whether the packet answers one with the poll bit set.
+    pub fn final_bit(&self) -> bool {
+        self.bfd_data()[1] & BFD_FINAL != 0
+    }
+
+    pub fn set_final_bit(&mut self, final_bit: bool) -> &mut Self {
+        let data = self.bfd_data_mut();
+        data[1] = if final_bit {
+            data[1] | BFD_FINAL
+        } else {
+            data[1] & !BFD_FINAL
+        };
+        self
+    }
+
+    /// The number of the sender's intervals the receiver waits for before it declares the session
+    /// down.
+    pub fn detect_mult(&self) -> u8 {
+        self.bfd_data()[2]
+    }
+
+    pub fn set_detect_mult(&mut self, detect_mult: u8) -> &mut Self {
+        self.bfd_data_mut()[2] = detect_mult;
+        self
+    }
+
+    /// The discriminator the sender gave the session, never 0.
+    pub fn my_discriminator(&self) -> u32 {
+        self.word(4)
+    }
+
+    pub fn set_my_discriminator(&mut self, discriminator: u32) -> &mut Self {
+        self.set_word(4, discriminator)
+    }
+
+    /// The discriminator the receiver gave the session, or 0 if the sender doesn't know it yet.
+    pub fn your_discriminator(&self) -> u32 {
+        self.word(8)
+    }
+
+    pub fn set_your_discriminator(&mut self, discriminator: u32) -> &mut Self {
+        self.set_word(8, discriminator)
+    }
+
+    /// The shortest interval the sender wants to send packets at, in microseconds.
+    pub fn desired_min_tx(&self) -> u32 {
+        self.word(12)
+    }
+
+    pub fn set_desired_min_tx(&mut self, interval: u32) -> &mut Self {
+        self.set_word(12, interval)
+    }
+
+    /// The shortest interval the sender can receive packets at, in microseconds.
+    pub fn required_min_rx(&self) -> u32 {
+        self.word(16)
+    }
+
+    pub fn set_required_min_rx(&mut self, interval: u32) -> &mut Self {
+        self.set_word(16, interval)
+    }
+
+    /// The shortest interval the sender can receive echo packets at, in microseconds, 0 if it
+    /// doesn't take them.
+    pub fn required_min_echo_rx(&self) -> u32 {
+        self.word(20)
+    }
+
+    pub fn set_required_min_echo_rx(&mut self, interval: u32) -> &mut Self {
+        self.set_word(20, interval)
+    }
+
+    pub fn ipv4(&self) -> &Ipv4Packet {
+        &self.packet
+    }
+
+    pub fn ipv4_mut(&mut self) -> &mut Ipv4Packet {
+        &mut self.packet
+    }
+
+    // Move ownership of the packet back to the caller
+    pub fn packet(self) -> Ipv4Packet {
+        self.packet
+    }
+
+    fn word(&self, offset: usize) -> u32 {
+        u32::from_be_bytes(self.bfd_data()[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn set_word(&mut self, offset: usize, word: u32) -> &mut Self {
+        self.bfd_data_mut()[offset..offset + 4].copy_from_slice(&word.to_be_bytes());
+        self
+    }
+
+    fn udp_data(&self) -> &[u8] {
+        &self.packet.data[self.packet.payload_offset..]
+    }
+
+    fn udp_data_mut(&mut self) -> &mut [u8] {
+        &mut self.packet.data[self.packet.payload_offset..]
+    }
+
+    fn bfd_data(&self) -> &[u8] {
+        &self.udp_data()[UDP_HEADER_LEN..]
+    }
+
+    fn bfd_data_mut(&mut self) -> &mut [u8] {
+        &mut self.udp_data_mut()[UDP_HEADER_LEN..]
+    }
+}
+
+impl Default for BfdPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<Ipv4Packet> for BfdPacket {
+    type Error = &'static str;
+
+    ///
+    /// Decorates the given Ipv4Packet with BfdPacket getters/setters.
+    /// Validates
+    /// - The packet is UDP, to the BFD control port
+    /// - The control packet is of version 1, fits within the packet, and is unauthenticated
+    /// - The detect multiplier and the sender's discriminator are not 0
+    ///
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        if packet.protocol() != IpProtocol::UDP {
+            return Err("Packet protocol is not UDP");
+        }
+        if packet.payload().len() < UDP_HEADER_LEN + BFD_HEADER_LEN {
+            return Err("Packet payload is too short to be a BFD control packet");
+        }
+        let bfd = BfdPacket { packet };
+        if u16::from_be_bytes(bfd.udp_data()[2..4].try_into().unwrap()) != BFD_CONTROL_PORT {
+            return Err("Packet is not to the BFD control port");
+        }
+        if bfd.version() != BFD_VERSION {
+            return Err("BFD control packet is not of version 1");
+        }
+        let length = usize::from(bfd.bfd_data()[3]);
+        if length < BFD_HEADER_LEN || length > bfd.bfd_data().len() {
+            return Err("BFD control packet length is invalid");
+        }
+        if bfd.bfd_data()[1] & 0x04 != 0 {
+            return Err("BFD control packet is authenticated");
+        }
+        if bfd.detect_mult() == 0 || bfd.my_discriminator() == 0 {
+            return Err("BFD control packet has a detect multiplier or discriminator of 0");
+        }
+        Ok(bfd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_packet_fields() {
+        let mut bfd = BfdPacket::new();
+        bfd.set_state(BfdState::Init)
+            .set_diagnostic(1)
+            .set_poll(true)
+            .set_detect_mult(3)
+            .set_my_discriminator(7)
+            .set_your_discriminator(0x1234_5678)
+            .set_desired_min_tx(300_000)
+            .set_required_min_rx(100_000)
+            .set_src_port(49200);
+
+        let bfd = BfdPacket::try_from(bfd.packet()).unwrap();
+        assert_eq!(bfd.version(), 1);
+        assert_eq!(bfd.state(), BfdState::Init);
+        assert_eq!(bfd.diagnostic(), 1);
+        assert!(bfd.poll());
+        assert!(!bfd.final_bit());
+        assert_eq!(bfd.detect_mult(), 3);
+        assert_eq!(bfd.my_discriminator(), 7);
+        assert_eq!(bfd.your_discriminator(), 0x1234_5678);
+        assert_eq!(bfd.desired_min_tx(), 300_000);
+        assert_eq!(bfd.required_min_rx(), 100_000);
+        assert_eq!(bfd.required_min_echo_rx(), 0);
+        assert_eq!(bfd.ipv4().ttl(), 255);
+
+        let udp = UdpSegment::try_from(bfd.packet()).unwrap();
+        assert_eq!(udp.src_port(), 49200);
+        assert_eq!(udp.dest_port(), BFD_CONTROL_PORT);
+        assert_eq!(usize::from(udp.length()), 8 + 24);
+    }
+
+    #[test]
+    fn rejects_invalid_control_packets() {
+        // A discriminator of 0.
+        let mut bfd = BfdPacket::new();
+        assert!(BfdPacket::try_from(bfd.clone().packet()).is_err());
+
+        bfd.set_my_discriminator(1);
+        assert!(BfdPacket::try_from(bfd.clone().packet()).is_ok());
+
+        let mut packet = bfd.packet();
+        let offset = packet.payload_offset;
+        packet.data[offset + 3] = 53;
+        assert!(BfdPacket::try_from(packet).is_err());
+    }
+}
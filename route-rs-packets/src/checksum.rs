@@ -0,0 +1,58 @@
+/// Adds the 16 bit words of `data` to the one's complement sum `sum`, as the Internet checksum is
+/// made of, RFC 1071. An odd last byte is padded with zero. Sums are carried in 32 bits, so a sum
+/// may take the words of many headers before it is folded by `fold_checksum`.
+pub fn sum_words(data: &[u8], mut sum: u32) -> u32 {
+    for word in data.chunks(2) {
+        let word = match word {
+            [high, low] => u16::from_be_bytes([*high, *low]),
+            [high] => u16::from_be_bytes([*high, 0]),
+            _ => unreachable!(),
+        };
+        sum += u32::from(word);
+    }
+    sum
+}
+
+/// The checksum of a sum made by `sum_words`, its carries folded back in and its bits inverted.
+pub fn fold_checksum(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The Internet checksum of `data`, as IPv4, ICMP, IGMP and VRRP use. Summed over data that
+/// includes a valid checksum, it is 0.
+pub fn internet_checksum(data: &[u8]) -> u16 {
+    fold_checksum(sum_words(data, 0))
+}
+
+/// `checksum` updated for a 16 bit word of what it covers changing from `old` to `new`, without
+/// summing the rest again, RFC 1624.
+pub fn update_checksum(checksum: u16, old: u16, new: u16) -> u16 {
+    fold_checksum(u32::from(!checksum) + u32::from(!old) + u32::from(new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_rfc_1071_example() {
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(sum_words(&data, 0), 0x2_ddf0);
+        assert_eq!(internet_checksum(&data), !0xddf2);
+        assert_eq!(internet_checksum(&[0xab]), !0xab00);
+    }
+
+    #[test]
+    fn updates_checksum_as_if_summed_again() {
+        let mut data = [0x45, 0x00, 0x00, 0x54, 0x12, 0x34];
+        let checksum = internet_checksum(&data);
+        data[1] = 0x03;
+        assert_eq!(
+            update_checksum(checksum, 0x4500, 0x4503),
+            internet_checksum(&data)
+        );
+    }
+}
@@ -0,0 +1,410 @@
+use std::convert::TryInto;
+
+pub const DNS_PORT: u16 = 53;
+
+/// Record types, RFC 1035 section 3.2.2, RFC 3596 and RFC 6891
+pub const DNS_TYPE_A: u16 = 1;
+pub const DNS_TYPE_CNAME: u16 = 5;
+pub const DNS_TYPE_SOA: u16 = 6;
+pub const DNS_TYPE_AAAA: u16 = 28;
+pub const DNS_TYPE_OPT: u16 = 41;
+
+pub const DNS_CLASS_IN: u16 = 1;
+
+/// Response codes, RFC 1035 section 4.1.1
+pub const DNS_RCODE_NOERROR: u8 = 0;
+pub const DNS_RCODE_SERVFAIL: u8 = 2;
+pub const DNS_RCODE_NXDOMAIN: u8 = 3;
+pub const DNS_RCODE_REFUSED: u8 = 5;
+
+const DNS_HEADER_LEN: usize = 12;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const FLAG_RECURSION_AVAILABLE: u16 = 0x0080;
+
+/// Compression pointers a name may follow before it is taken to be a loop.
+const MAX_POINTERS: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsQuestion {
+    /// The name asked about, as labels joined by dots, without the trailing dot of the root.
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DnsSection {
+    Answer,
+    Authority,
+    Additional,
+}
+
+/// A resource record of a `DnsMessage`. Its data is kept as it was sent, so names within it may
+/// be compressed against the rest of the message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsRecord {
+    pub section: DnsSection,
+    pub name: String,
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: Vec<u8>,
+    ttl_offset: usize,
+}
+
+///
+/// A DNS message, as carried in a UDP datagram, with getters/setters for its header, and
+/// parsers for its questions and records
+/// https://tools.ietf.org/html/rfc1035#section-4
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsMessage {
+    data: Vec<u8>,
+}
+
+impl DnsMessage {
+    /// Takes the message in a datagram, which must hold a whole header, and questions and records
+    /// to match its counts.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, &'static str> {
+        if data.len() < DNS_HEADER_LEN {
+            return Err("Message is too short to be a DnsMessage");
+        }
+        let message = DnsMessage { data };
+        message.parse()?;
+        Ok(message)
+    }
+
+    /// Constructs a query asking recursively for the answer to one question.
+    pub fn query(id: u16, question: &DnsQuestion) -> Self {
+        let mut data = vec![0; DNS_HEADER_LEN];
+        data[0..2].copy_from_slice(&id.to_be_bytes());
+        data[2..4].copy_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+        data[4..6].copy_from_slice(&1u16.to_be_bytes());
+        write_name(&mut data, &question.name);
+        data.extend_from_slice(&question.qtype.to_be_bytes());
+        data.extend_from_slice(&question.qclass.to_be_bytes());
+        DnsMessage { data }
+    }
+
+    /// Constructs an empty response to this query, which repeats its ID, first question, and
+    /// whether recursion was desired.
+    pub fn response(&self, rcode: u8) -> Self {
+        let flags = FLAG_RESPONSE
+            | FLAG_RECURSION_AVAILABLE
+            | (self.flags() & FLAG_RECURSION_DESIRED)
+            | u16::from(rcode & 0x0f);
+        let mut data = vec![0; DNS_HEADER_LEN];
+        data[0..2].copy_from_slice(&self.id().to_be_bytes());
+        data[2..4].copy_from_slice(&flags.to_be_bytes());
+        if let Some(question) = self.question() {
+            data[4..6].copy_from_slice(&1u16.to_be_bytes());
+            write_name(&mut data, &question.name);
+            data.extend_from_slice(&question.qtype.to_be_bytes());
+            data.extend_from_slice(&question.qclass.to_be_bytes());
+        }
+        DnsMessage { data }
+    }
+
+    /// Appends a record about the name of the first question to the answer section. Must be
+    /// called before any authority or additional records are added.
+    pub fn add_answer(&mut self, rtype: u16, class: u16, ttl: u32, data: &[u8]) -> &mut Self {
+        // A pointer to the name of the first question, right after the header.
+        self.data.extend_from_slice(&[0xc0, DNS_HEADER_LEN as u8]);
+        self.data.extend_from_slice(&rtype.to_be_bytes());
+        self.data.extend_from_slice(&class.to_be_bytes());
+        self.data.extend_from_slice(&ttl.to_be_bytes());
+        self.data
+            .extend_from_slice(&(data.len() as u16).to_be_bytes());
+        self.data.extend_from_slice(data);
+        let answers = (self.count(6) + 1) as u16;
+        self.data[6..8].copy_from_slice(&answers.to_be_bytes());
+        self
+    }
+
+    pub fn id(&self) -> u16 {
+        u16::from_be_bytes(self.data[0..2].try_into().unwrap())
+    }
+
+    pub fn set_id(&mut self, id: u16) -> &mut Self {
+        self.data[0..2].copy_from_slice(&id.to_be_bytes());
+        self
+    }
+
+    fn flags(&self) -> u16 {
+        u16::from_be_bytes(self.data[2..4].try_into().unwrap())
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags() & FLAG_RESPONSE != 0
+    }
+
+    /// Whether the response was cut short to fit its datagram, so its records are incomplete.
+    pub fn is_truncated(&self) -> bool {
+        self.flags() & FLAG_TRUNCATED != 0
+    }
+
+    pub fn rcode(&self) -> u8 {
+        (self.flags() & 0x000f) as u8
+    }
+
+    pub fn set_rcode(&mut self, rcode: u8) -> &mut Self {
+        let flags = (self.flags() & !0x000f) | u16::from(rcode & 0x0f);
+        self.data[2..4].copy_from_slice(&flags.to_be_bytes());
+        self
+    }
+
+    /// The first question of the message. Queries in practice only ever ask one.
+    pub fn question(&self) -> Option<DnsQuestion> {
+        self.parse().ok()?.0.into_iter().next()
+    }
+
+    pub fn questions(&self) -> Vec<DnsQuestion> {
+        self.parse()
+            .map(|(questions, _)| questions)
+            .unwrap_or_default()
+    }
+
+    /// The records of the answer, authority and additional sections, in order.
+    pub fn records(&self) -> Vec<DnsRecord> {
+        self.parse().map(|(_, records)| records).unwrap_or_default()
+    }
+
+    /// Changes the TTL of one of the message's records, as returned by `records`.
+    pub fn set_ttl(&mut self, record: &DnsRecord, ttl: u32) -> &mut Self {
+        self.data[record.ttl_offset..record.ttl_offset + 4].copy_from_slice(&ttl.to_be_bytes());
+        self
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    fn count(&self, offset: usize) -> usize {
+        usize::from(u16::from_be_bytes(
+            self.data[offset..offset + 2].try_into().unwrap(),
+        ))
+    }
+
+    fn parse(&self) -> Result<(Vec<DnsQuestion>, Vec<DnsRecord>), &'static str> {
+        let data = &self.data;
+        let mut offset = DNS_HEADER_LEN;
+        let mut questions = vec![];
+        for _ in 0..self.count(4) {
+            let (name, end) = read_name(data, offset)?;
+            let fixed = data
+                .get(end..end + 4)
+                .ok_or("Question runs past the end of the message")?;
+            questions.push(DnsQuestion {
+                name,
+                qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+                qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+            });
+            offset = end + 4;
+        }
+
+        let sections = [
+            (DnsSection::Answer, self.count(6)),
+            (DnsSection::Authority, self.count(8)),
+            (DnsSection::Additional, self.count(10)),
+        ];
+        let mut records = vec![];
+        for (section, count) in sections.iter() {
+            for _ in 0..*count {
+                let (name, end) = read_name(data, offset)?;
+                let fixed = data
+                    .get(end..end + 10)
+                    .ok_or("Record runs past the end of the message")?;
+                let len = usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+                let record_data = data
+                    .get(end + 10..end + 10 + len)
+                    .ok_or("Record data runs past the end of the message")?;
+                records.push(DnsRecord {
+                    section: *section,
+                    name,
+                    rtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+                    class: u16::from_be_bytes([fixed[2], fixed[3]]),
+                    ttl: u32::from_be_bytes(fixed[4..8].try_into().unwrap()),
+                    data: record_data.to_vec(),
+                    ttl_offset: end + 4,
+                });
+                offset = end + 10 + len;
+            }
+        }
+        Ok((questions, records))
+    }
+}
+
+/// Reads the name at `offset`, following compression pointers, RFC 1035 section 4.1.4. Returns
+/// the name and the offset right after it.
+fn read_name(data: &[u8], mut offset: usize) -> Result<(String, usize), &'static str> {
+    let mut labels = vec![];
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *data
+            .get(offset)
+            .ok_or("Name runs past the end of the message")?;
+        match len & 0xc0 {
+            0xc0 => {
+                let low = *data
+                    .get(offset + 1)
+                    .ok_or("Name runs past the end of the message")?;
+                end.get_or_insert(offset + 2);
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err("Name has too many compression pointers");
+                }
+                offset = usize::from(len & 0x3f) << 8 | usize::from(low);
+            }
+            0x00 if len == 0 => {
+                end.get_or_insert(offset + 1);
+                break;
+            }
+            0x00 => {
+                let len = usize::from(len);
+                let label = data
+                    .get(offset + 1..offset + 1 + len)
+                    .ok_or("Name runs past the end of the message")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + len;
+            }
+            _ => return Err("Name has a label of an unknown type"),
+        }
+    }
+    Ok((labels.join("."), end.unwrap()))
+}
+
+fn write_name(data: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        data.push(label.len() as u8);
+        data.extend_from_slice(label.as_bytes());
+    }
+    data.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A response for the A records of www.example.com, which is a CNAME for example.com, with
+    /// names compressed against the question.
+    fn response() -> Vec<u8> {
+        let mut data = vec![
+            0x12, 0x34, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0, // Header
+            3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm',
+            0, 0, 1, 0, 1, // Question
+        ];
+        // www.example.com CNAME example.com
+        data.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0x0e, 0x10, 0, 2, 0xc0, 16]);
+        // example.com A 93.184.216.34
+        data.extend_from_slice(&[0xc0, 16, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 216, 34]);
+        data
+    }
+
+    #[test]
+    fn parses_compressed_response() {
+        let message = DnsMessage::from_bytes(response()).unwrap();
+        assert_eq!(message.id(), 0x1234);
+        assert!(message.is_response());
+        assert!(!message.is_truncated());
+        assert_eq!(message.rcode(), DNS_RCODE_NOERROR);
+        assert_eq!(
+            message.question(),
+            Some(DnsQuestion {
+                name: String::from("www.example.com"),
+                qtype: DNS_TYPE_A,
+                qclass: DNS_CLASS_IN,
+            })
+        );
+
+        let records = message.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name, "www.example.com");
+        assert_eq!(records[0].rtype, DNS_TYPE_CNAME);
+        assert_eq!(records[0].ttl, 3600);
+        assert_eq!(records[1].name, "example.com");
+        assert_eq!(records[1].section, DnsSection::Answer);
+        assert_eq!(records[1].data, vec![93, 184, 216, 34]);
+    }
+
+    #[test]
+    fn sets_header_and_ttls() {
+        let mut message = DnsMessage::from_bytes(response()).unwrap();
+        let record = message.records().remove(1);
+        message
+            .set_id(7)
+            .set_rcode(DNS_RCODE_SERVFAIL)
+            .set_ttl(&record, 30);
+
+        assert_eq!(message.id(), 7);
+        assert_eq!(message.rcode(), DNS_RCODE_SERVFAIL);
+        assert!(message.is_response());
+        assert_eq!(message.records()[1].ttl, 30);
+        assert_eq!(message.records()[0].ttl, 3600);
+    }
+
+    #[test]
+    fn builds_query() {
+        let question = DnsQuestion {
+            name: String::from("example.com"),
+            qtype: DNS_TYPE_AAAA,
+            qclass: DNS_CLASS_IN,
+        };
+        let query = DnsMessage::query(9, &question);
+        let parsed = DnsMessage::from_bytes(query.into_bytes()).unwrap();
+
+        assert_eq!(parsed.id(), 9);
+        assert!(!parsed.is_response());
+        assert_eq!(parsed.questions(), vec![question]);
+        assert!(parsed.records().is_empty());
+    }
+
+    #[test]
+    fn builds_response() {
+        let query = DnsMessage::query(
+            3,
+            &DnsQuestion {
+                name: String::from("router.lan"),
+                qtype: DNS_TYPE_A,
+                qclass: DNS_CLASS_IN,
+            },
+        );
+        let mut response = query.response(DNS_RCODE_NOERROR);
+        response.add_answer(DNS_TYPE_A, DNS_CLASS_IN, 60, &[192, 168, 1, 1]);
+        let parsed = DnsMessage::from_bytes(response.into_bytes()).unwrap();
+
+        assert_eq!(parsed.id(), 3);
+        assert!(parsed.is_response());
+        assert_eq!(parsed.question(), query.question());
+        let records = parsed.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, "router.lan");
+        assert_eq!(records[0].ttl, 60);
+        assert_eq!(records[0].data, vec![192, 168, 1, 1]);
+
+        let refused = query.response(DNS_RCODE_REFUSED);
+        assert_eq!(refused.rcode(), DNS_RCODE_REFUSED);
+        assert!(refused.records().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert!(DnsMessage::from_bytes(vec![0; 11]).is_err());
+
+        let mut truncated = response();
+        truncated.truncate(truncated.len() - 2);
+        assert!(DnsMessage::from_bytes(truncated).is_err());
+
+        // A name that points at itself.
+        let mut looped = response();
+        looped[12..14].copy_from_slice(&[0xc0, 12]);
+        assert!(DnsMessage::from_bytes(looped).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+/// EAPOL packet types, IEEE 802.1X-2010 section 11.3.2
+pub const EAPOL_EAP: u8 = 0;
+pub const EAPOL_START: u8 = 1;
+pub const EAPOL_LOGOFF: u8 = 2;
+pub const EAPOL_KEY: u8 = 3;
+pub const EAPOL_ENCAPSULATED_ASF_ALERT: u8 = 4;
+
+/// Group address that supplicants send EAPOL frames to. Bridges never forward it, which is why a
+/// router sitting between supplicant and authenticator has to pass EAPOL along itself.
+pub const EAPOL_PAE_GROUP_ADDR: MacAddr = MacAddr {
+    bytes: [0x01, 0x80, 0xc2, 0x00, 0x00, 0x03],
+};
+
+const EAPOL_HEADER_LEN: usize = 4;
+
+///
+/// EthernetFrame wrapper with getters/setters for 802.1X EAP over LAN frames
+/// https://standards.ieee.org/standard/802_1X-2010.html
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EapolFrame {
+    frame: EthernetFrame,
+}
+
+impl EapolFrame {
+    /// Constructs a version 2 EAPOL frame of the given type with an empty body, addressed to the
+    /// PAE group address.
+    pub fn new(packet_type: u8) -> Self {
+        let mut frame = EthernetFrame::empty();
+        frame.set_dest_mac(EAPOL_PAE_GROUP_ADDR);
+        frame.set_ether_type(EAPOL_ETHER_TYPE);
+        frame.set_payload(&[2, packet_type, 0, 0]);
+        EapolFrame { frame }
+    }
+
+    pub fn version(&self) -> u8 {
+        self.eapol_data()[0]
+    }
+
+    pub fn set_version(&mut self, version: u8) -> &mut Self {
+        let offset = self.frame.payload_offset;
+        self.frame.data[offset] = version;
+        self
+    }
+
+    pub fn packet_type(&self) -> u8 {
+        self.eapol_data()[1]
+    }
+
+    pub fn set_packet_type(&mut self, packet_type: u8) -> &mut Self {
+        let offset = self.frame.payload_offset + 1;
+        self.frame.data[offset] = packet_type;
+        self
+    }
+
+    pub fn body_len(&self) -> u16 {
+        u16::from_be_bytes(self.eapol_data()[2..4].try_into().unwrap())
+    }
+
+    /// The packet body, without any Ethernet padding that follows it.
+    pub fn body(&self) -> &[u8] {
+        &self.eapol_data()[EAPOL_HEADER_LEN..EAPOL_HEADER_LEN + self.body_len() as usize]
+    }
+
+    /// Replaces the packet body and updates the body length to match.
+    pub fn set_body(&mut self, body: &[u8]) -> &mut Self {
+        let start = self.frame.payload_offset + EAPOL_HEADER_LEN;
+        self.frame.data.truncate(start);
+        self.frame.data.extend_from_slice(body);
+        self.frame.data[start - 2..start].copy_from_slice(&(body.len() as u16).to_be_bytes());
+        self
+    }
+
+    // Move ownership of the frame back to the caller
+    pub fn frame(self) -> EthernetFrame {
+        self.frame
+    }
+
+    fn eapol_data(&self) -> &[u8] {
+        &self.frame.data[self.frame.payload_offset..]
+    }
+}
+
+impl TryFrom<EthernetFrame> for EapolFrame {
+    type Error = &'static str;
+
+    ///
+    /// Decorates the given EthernetFrame with EapolFrame getters/setters.
+    /// Validates
+    /// - The frame has the EAPOL ether type
+    /// - The body length field fits within the frame
+    ///
+    fn try_from(frame: EthernetFrame) -> Result<Self, Self::Error> {
+        if frame.ether_type() != EAPOL_ETHER_TYPE {
+            return Err("Frame does not have EAPOL ether type");
+        }
+
+        let payload_len = frame.data.len() - frame.payload_offset;
+        if payload_len < EAPOL_HEADER_LEN {
+            return Err("Frame payload is too small");
+        }
+
+        let eapol = EapolFrame { frame };
+        if eapol.body_len() as usize > payload_len - EAPOL_HEADER_LEN {
+            return Err("Frame payload doesn't match body length field");
+        }
+
+        Ok(eapol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_frame() {
+        let eapol = EapolFrame::new(EAPOL_START);
+        assert_eq!(eapol.version(), 2);
+        assert_eq!(eapol.packet_type(), EAPOL_START);
+        assert_eq!(eapol.body_len(), 0);
+        assert!(eapol.body().is_empty());
+
+        let frame = eapol.frame();
+        assert_eq!(frame.dest_mac(), EAPOL_PAE_GROUP_ADDR);
+        assert_eq!(frame.ether_type(), EAPOL_ETHER_TYPE);
+    }
+
+    #[test]
+    fn body_ignores_padding() {
+        let mut eapol = EapolFrame::new(EAPOL_EAP);
+        eapol.set_version(1).set_body(&[1, 1, 0, 5, 1]);
+        let mut frame = eapol.frame();
+        frame.data.extend_from_slice(&[0; 32]);
+
+        let eapol = EapolFrame::try_from(frame).unwrap();
+        assert_eq!(eapol.version(), 1);
+        assert_eq!(eapol.body_len(), 5);
+        assert_eq!(eapol.body(), &[1, 1, 0, 5, 1]);
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(IPV4_ETHER_TYPE);
+        frame.set_payload(&[2, 0, 0, 0]);
+        assert!(EapolFrame::try_from(frame).is_err());
+
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(EAPOL_ETHER_TYPE);
+        frame.set_payload(&[2, 0, 0]);
+        assert!(EapolFrame::try_from(frame).is_err());
+
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(EAPOL_ETHER_TYPE);
+        frame.set_payload(&[2, 0, 0, 8, 1]);
+        assert!(EapolFrame::try_from(frame).is_err());
+    }
+}
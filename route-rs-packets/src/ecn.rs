@@ -0,0 +1,109 @@
+use crate::*;
+
+/// The ECN codepoints of the low 2 bits of the IPv4 TOS and IPv6 traffic class, RFC 3168 section
+/// 5.
+pub const ECN_NOT_ECT: u8 = 0;
+pub const ECN_ECT1: u8 = 1;
+pub const ECN_ECT0: u8 = 2;
+pub const ECN_CE: u8 = 3;
+
+/// Marks the IPv4 header starting `data` Congestion Experienced, if it is ECN capable,
+/// updating its checksum for the changed word as RFC 1624 does, rather than summing the header
+/// again.
+fn mark_ipv4_header(data: &mut [u8]) -> bool {
+    if data.len() < 20 || data[1] & 0x03 == ECN_NOT_ECT {
+        return false;
+    }
+    let old = u16::from_be_bytes([data[0], data[1]]);
+    data[1] |= ECN_CE;
+    let new = u16::from_be_bytes([data[0], data[1]]);
+    let checksum = u16::from_be_bytes([data[10], data[11]]);
+    data[10..12].copy_from_slice(&update_checksum(checksum, old, new).to_be_bytes());
+    true
+}
+
+/// Marks the IPv6 header starting `data` Congestion Experienced, if it is ECN capable. The ECN
+/// bits are the low bits of the traffic class, which straddles the first two bytes.
+fn mark_ipv6_header(data: &mut [u8]) -> bool {
+    if data.len() < 40 || (data[1] >> 4) & 0x03 == ECN_NOT_ECT {
+        return false;
+    }
+    data[1] |= ECN_CE << 4;
+    true
+}
+
+impl Ipv4Packet {
+    /// Marks the packet Congestion Experienced, returning whether it was ECN capable, ECT(0),
+    /// ECT(1) or already CE. Packets that aren't are left alone, to be dropped instead.
+    pub fn mark_ce(&mut self) -> bool {
+        mark_ipv4_header(&mut self.data[self.layer3_offset..])
+    }
+}
+
+impl Ipv6Packet {
+    /// The ECN codepoint, the low 2 bits of the traffic class.
+    pub fn ecn(&self) -> u8 {
+        self.traffic_class() & 0x03
+    }
+
+    /// Marks the packet Congestion Experienced, returning whether it was ECN capable, ECT(0),
+    /// ECT(1) or already CE. Packets that aren't are left alone, to be dropped instead.
+    pub fn mark_ce(&mut self) -> bool {
+        mark_ipv6_header(&mut self.data[self.layer3_offset..])
+    }
+}
+
+impl EthernetFrame {
+    /// Marks the IPv4 or IPv6 packet the frame carries Congestion Experienced, returning whether
+    /// it was ECN capable. Frames carrying anything else aren't.
+    pub fn mark_ce(&mut self) -> bool {
+        let payload_offset = self.payload_offset;
+        match self.ether_type() {
+            IPV4_ETHER_TYPE => mark_ipv4_header(&mut self.data[payload_offset..]),
+            IPV6_ETHER_TYPE => mark_ipv6_header(&mut self.data[payload_offset..]),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn marks_ipv4_and_keeps_checksum() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_dscp(46);
+        packet.set_ecn(ECN_ECT0);
+        packet.set_checksum();
+        let mut frame = EthernetFrame::encap_ipv4(packet.clone());
+
+        assert!(packet.mark_ce());
+        assert_eq!(packet.ecn(), ECN_CE);
+        assert_eq!(packet.dscp(), 46);
+        assert!(packet.validate_checksum());
+
+        assert!(frame.mark_ce());
+        assert_eq!(Ipv4Packet::try_from(frame).unwrap(), packet);
+
+        let mut not_ect = Ipv4Packet::empty();
+        assert!(!not_ect.mark_ce());
+        assert_eq!(not_ect.ecn(), ECN_NOT_ECT);
+    }
+
+    #[test]
+    fn marks_ipv6_traffic_class() {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_traffic_class((46 << 2) | ECN_ECT1);
+        packet.set_flow_label(0xabcde);
+
+        assert!(packet.mark_ce());
+        assert_eq!(packet.ecn(), ECN_CE);
+        assert_eq!(packet.traffic_class() >> 2, 46);
+        assert_eq!(packet.flow_label(), 0xabcde);
+
+        let mut frame = EthernetFrame::encap_ipv6(Ipv6Packet::empty());
+        assert!(!frame.mark_ce());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+/// The version of an ERSPAN header, which sets its length and the fields after the session ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErspanType {
+    /// 8 bytes, with the index of the port the frame was mirrored from.
+    II,
+    /// 12 bytes, with a timestamp and the security group tag, without the platform specific
+    /// subheader.
+    III,
+}
+
+impl ErspanType {
+    fn header_len(self) -> usize {
+        match self {
+            ErspanType::II => 8,
+            ErspanType::III => 12,
+        }
+    }
+
+    fn protocol_type(self) -> u16 {
+        match self {
+            ErspanType::II => ERSPAN_II_ETHER_TYPE,
+            ErspanType::III => ERSPAN_III_ETHER_TYPE,
+        }
+    }
+}
+
+///
+/// GrePacket wrapper with getters/setters for the header of ERSPAN Type II and III packets,
+/// which carry frames mirrored to a remote analyzer.
+/// https://tools.ietf.org/html/draft-foschiano-erspan-03
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErspanPacket {
+    gre: GrePacket,
+    erspan_type: ErspanType,
+}
+
+impl ErspanPacket {
+    /// Constructs an ERSPAN packet of `erspan_type`, with a GRE sequence number of 0, as ERSPAN
+    /// always has one, and no mirrored frame.
+    pub fn new(erspan_type: ErspanType) -> Self {
+        let mut gre = GrePacket::new(erspan_type.protocol_type());
+        let mut header = vec![0; erspan_type.header_len()];
+        header[0] = match erspan_type {
+            ErspanType::II => 0x10,
+            ErspanType::III => 0x20,
+        };
+        gre.set_sequence(Some(0)).set_gre_payload(&header);
+        ErspanPacket { gre, erspan_type }
+    }
+
+    pub fn erspan_type(&self) -> ErspanType {
+        self.erspan_type
+    }
+
+    /// The VLAN the mirrored frame was on.
+    pub fn vlan(&self) -> u16 {
+        u16::from_be_bytes(self.erspan_data()[0..2].try_into().unwrap()) & 0x0fff
+    }
+
+    pub fn set_vlan(&mut self, vlan: u16) -> &mut Self {
+        let version = self.erspan_data()[0] & 0xf0;
+        let bytes = (vlan & 0x0fff).to_be_bytes();
+        self.erspan_data_mut()[0] = version | bytes[0];
+        self.erspan_data_mut()[1] = bytes[1];
+        self
+    }
+
+    pub fn cos(&self) -> u8 {
+        self.erspan_data()[2] >> 5
+    }
+
+    pub fn set_cos(&mut self, cos: u8) -> &mut Self {
+        let data = self.erspan_data_mut();
+        data[2] = (data[2] & 0x1f) | ((cos & 0x07) << 5);
+        self
+    }
+
+    pub fn session_id(&self) -> u16 {
+        u16::from_be_bytes(self.erspan_data()[2..4].try_into().unwrap()) & 0x03ff
+    }
+
+    pub fn set_session_id(&mut self, session_id: u16) -> &mut Self {
+        let data = self.erspan_data_mut();
+        let bytes = (session_id & 0x03ff).to_be_bytes();
+        data[2] = (data[2] & 0xfc) | bytes[0];
+        data[3] = bytes[1];
+        self
+    }
+
+    /// The index of the port the frame was mirrored from, of Type II packets.
+    pub fn index(&self) -> Option<u32> {
+        match self.erspan_type {
+            ErspanType::II => {
+                Some(u32::from_be_bytes(self.erspan_data()[4..8].try_into().unwrap()) & 0x000f_ffff)
+            }
+            ErspanType::III => None,
+        }
+    }
+
+    /// Sets the index of Type II packets, and does nothing to those of Type III.
+    pub fn set_index(&mut self, index: u32) -> &mut Self {
+        if self.erspan_type == ErspanType::II {
+            self.erspan_data_mut()[4..8].copy_from_slice(&(index & 0x000f_ffff).to_be_bytes());
+        }
+        self
+    }
+
+    /// The time the frame was mirrored, of Type III packets, in units the analyzer agrees on
+    /// with the sender, usually 100 microseconds.
+    pub fn timestamp(&self) -> Option<u32> {
+        match self.erspan_type {
+            ErspanType::II => None,
+            ErspanType::III => Some(u32::from_be_bytes(
+                self.erspan_data()[4..8].try_into().unwrap(),
+            )),
+        }
+    }
+
+    /// Sets the timestamp of Type III packets, and does nothing to those of Type II.
+    pub fn set_timestamp(&mut self, timestamp: u32) -> &mut Self {
+        if self.erspan_type == ErspanType::III {
+            self.erspan_data_mut()[4..8].copy_from_slice(&timestamp.to_be_bytes());
+        }
+        self
+    }
+
+    /// The mirrored frame, everything after the ERSPAN header.
+    pub fn mirrored(&self) -> &[u8] {
+        &self.erspan_data()[self.erspan_type.header_len()..]
+    }
+
+    pub fn set_mirrored(&mut self, mirrored: &[u8]) -> &mut Self {
+        let mut data = self.erspan_data()[..self.erspan_type.header_len()].to_vec();
+        data.extend_from_slice(mirrored);
+        self.gre.set_gre_payload(&data);
+        self
+    }
+
+    pub fn gre(&self) -> &GrePacket {
+        &self.gre
+    }
+
+    pub fn gre_mut(&mut self) -> &mut GrePacket {
+        &mut self.gre
+    }
+
+    // Move ownership of the packet back to the caller
+    pub fn packet(self) -> Ipv4Packet {
+        self.gre.packet()
+    }
+
+    fn erspan_data(&self) -> &[u8] {
+        self.gre.gre_payload()
+    }
+
+    fn erspan_data_mut(&mut self) -> &mut [u8] {
+        let offset = self.gre.ipv4().payload_offset + self.gre.header_len();
+        &mut self.gre.ipv4_mut().data[offset..]
+    }
+}
+
+impl TryFrom<GrePacket> for ErspanPacket {
+    type Error = &'static str;
+
+    ///
+    /// Decorates the given GrePacket with ErspanPacket getters/setters.
+    /// Validates
+    /// - The GRE protocol type is that of ERSPAN Type II or III
+    /// - The ERSPAN header fits within the packet, and its version matches its protocol type
+    ///
+    fn try_from(gre: GrePacket) -> Result<Self, Self::Error> {
+        let erspan_type = match gre.protocol_type() {
+            ERSPAN_II_ETHER_TYPE => ErspanType::II,
+            ERSPAN_III_ETHER_TYPE => ErspanType::III,
+            _ => return Err("GRE protocol type is not ERSPAN"),
+        };
+        if gre.gre_payload().len() < erspan_type.header_len() {
+            return Err("GRE payload is too short for its ERSPAN header");
+        }
+        let version = gre.gre_payload()[0] >> 4;
+        match (erspan_type, version) {
+            (ErspanType::II, 1) | (ErspanType::III, 2) => Ok(ErspanPacket { gre, erspan_type }),
+            _ => Err("ERSPAN version does not match its GRE protocol type"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_ii_header() {
+        let mut erspan = ErspanPacket::new(ErspanType::II);
+        erspan
+            .set_vlan(100)
+            .set_cos(5)
+            .set_session_id(0x3ff)
+            .set_index(42)
+            .set_timestamp(7)
+            .set_mirrored(&[0xaa; 20]);
+        erspan.gre_mut().set_sequence(Some(3));
+
+        let erspan = ErspanPacket::try_from(GrePacket::try_from(erspan.packet()).unwrap()).unwrap();
+        assert_eq!(erspan.erspan_type(), ErspanType::II);
+        assert_eq!(erspan.vlan(), 100);
+        assert_eq!(erspan.cos(), 5);
+        assert_eq!(erspan.session_id(), 0x3ff);
+        assert_eq!(erspan.index(), Some(42));
+        assert_eq!(erspan.timestamp(), None);
+        assert_eq!(erspan.gre().sequence(), Some(3));
+        assert_eq!(erspan.mirrored(), &[0xaa; 20][..]);
+    }
+
+    #[test]
+    fn type_iii_header() {
+        let mut erspan = ErspanPacket::new(ErspanType::III);
+        erspan
+            .set_session_id(12)
+            .set_timestamp(0xdead_beef)
+            .set_mirrored(&[1, 2, 3]);
+        assert_eq!(erspan.gre().protocol_type(), ERSPAN_III_ETHER_TYPE);
+        assert_eq!(erspan.session_id(), 12);
+        assert_eq!(erspan.timestamp(), Some(0xdead_beef));
+        assert_eq!(erspan.index(), None);
+        assert_eq!(erspan.mirrored(), &[1, 2, 3]);
+
+        let mut gre = GrePacket::new(ERSPAN_III_ETHER_TYPE);
+        gre.set_gre_payload(&[0x10; 12]);
+        assert!(ErspanPacket::try_from(gre).is_err());
+    }
+}
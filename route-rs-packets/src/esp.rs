@@ -0,0 +1,139 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+const ESP_PROTOCOL: u8 = 50;
+const ESP_HEADER_LEN: usize = 8;
+
+///
+/// Ipv4Packet wrapper with getters/setters for the header of ESP packets. What follows the header,
+/// the IV, the encrypted payload and the ICV, depends on the cipher of the security association.
+/// https://tools.ietf.org/html/rfc4303
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EspPacket {
+    packet: Ipv4Packet,
+}
+
+impl EspPacket {
+    /// Constructs an ESP packet with a zeroed header, and nothing after it.
+    pub fn new() -> Self {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(ESP_PROTOCOL);
+        packet.set_ttl(64);
+        packet.set_payload(&[0; ESP_HEADER_LEN]);
+        EspPacket { packet }
+    }
+
+    /// Security Parameters Index, which names the security association of the packet at its
+    /// receiver.
+    pub fn spi(&self) -> u32 {
+        u32::from_be_bytes(self.esp_data()[0..4].try_into().unwrap())
+    }
+
+    pub fn set_spi(&mut self, spi: u32) -> &mut Self {
+        self.esp_data_mut()[0..4].copy_from_slice(&spi.to_be_bytes());
+        self
+    }
+
+    pub fn sequence(&self) -> u32 {
+        u32::from_be_bytes(self.esp_data()[4..8].try_into().unwrap())
+    }
+
+    pub fn set_sequence(&mut self, sequence: u32) -> &mut Self {
+        self.esp_data_mut()[4..8].copy_from_slice(&sequence.to_be_bytes());
+        self
+    }
+
+    /// The header, SPI and sequence number, as authenticated with the payload.
+    pub fn header(&self) -> &[u8] {
+        &self.esp_data()[..ESP_HEADER_LEN]
+    }
+
+    /// Everything after the header.
+    pub fn esp_payload(&self) -> &[u8] {
+        &self.esp_data()[ESP_HEADER_LEN..]
+    }
+
+    pub fn set_esp_payload(&mut self, payload: &[u8]) -> &mut Self {
+        let mut data = self.header().to_vec();
+        data.extend_from_slice(payload);
+        self.packet.set_payload(&data);
+        self
+    }
+
+    pub fn ipv4(&self) -> &Ipv4Packet {
+        &self.packet
+    }
+
+    pub fn ipv4_mut(&mut self) -> &mut Ipv4Packet {
+        &mut self.packet
+    }
+
+    // Move ownership of the packet back to the caller
+    pub fn packet(self) -> Ipv4Packet {
+        self.packet
+    }
+
+    fn esp_data(&self) -> &[u8] {
+        &self.packet.data[self.packet.payload_offset..]
+    }
+
+    fn esp_data_mut(&mut self) -> &mut [u8] {
+        &mut self.packet.data[self.packet.payload_offset..]
+    }
+}
+
+impl Default for EspPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<Ipv4Packet> for EspPacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        if packet.protocol() != IpProtocol::ESP {
+            return Err("Packet protocol is not ESP");
+        }
+        if packet.payload().len() < ESP_HEADER_LEN {
+            return Err("Packet payload is too short to be an ESP packet");
+        }
+        Ok(EspPacket { packet })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_and_payload() {
+        let mut esp = EspPacket::new();
+        esp.set_spi(0x1000_0001)
+            .set_sequence(7)
+            .set_esp_payload(&[1, 2, 3, 4]);
+
+        assert_eq!(esp.spi(), 0x1000_0001);
+        assert_eq!(esp.sequence(), 7);
+        assert_eq!(esp.header(), &[0x10, 0, 0, 1, 0, 0, 0, 7]);
+        assert_eq!(esp.esp_payload(), &[1, 2, 3, 4]);
+
+        let packet = esp.packet();
+        assert_eq!(packet.protocol(), IpProtocol::ESP);
+        assert_eq!(packet.payload().len(), 12);
+    }
+
+    #[test]
+    fn rejects_non_esp() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(17);
+        packet.set_payload(&[0; 8]);
+        assert!(EspPacket::try_from(packet).is_err());
+
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(50);
+        packet.set_payload(&[0; 4]);
+        assert!(EspPacket::try_from(packet).is_err());
+    }
+}
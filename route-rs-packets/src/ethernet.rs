@@ -23,11 +23,14 @@ impl EthernetFrame {
             return Err("Frame is less than the minimum of 14 bytes");
         }
 
-        Ok(EthernetFrame {
+        let mut frame = EthernetFrame {
             data: frame,
             layer2_offset,
-            payload_offset: 14 + layer2_offset, // To support 802.1Q VLAN Tagging, this number may be different.
-        })
+            payload_offset: 14 + layer2_offset,
+        };
+        // The ether type follows any 802.1Q and 802.1ad tags, and the payload follows it.
+        frame.parse_vlan_tags(&DEFAULT_VLAN_TPIDS);
+        Ok(frame)
     }
 
     /// Returns an empty EthernetFrame where all values all populated to zero. This function allocates a
@@ -56,12 +59,18 @@ impl EthernetFrame {
         self.data[6..12].copy_from_slice(&mac.bytes[..6]);
     }
 
+    /// The ether type of the payload, after any VLAN tags.
     pub fn ether_type(&self) -> u16 {
-        u16::from_be_bytes(self.data[12..=13].try_into().unwrap())
+        u16::from_be_bytes(
+            self.data[self.payload_offset - 2..self.payload_offset]
+                .try_into()
+                .unwrap(),
+        )
     }
 
     pub fn set_ether_type(&mut self, ether_type: u16) {
-        self.data[12..=13].copy_from_slice(&ether_type.to_be_bytes());
+        self.data[self.payload_offset - 2..self.payload_offset]
+            .copy_from_slice(&ether_type.to_be_bytes());
     }
 
     // This gives you a cow of a slice of the payload.
@@ -0,0 +1,145 @@
+use crate::*;
+use std::convert::TryInto;
+use std::net::IpAddr;
+
+/// Identifies the flow a packet belongs to by its addresses, IP protocol number and, for TCP,
+/// UDP and SCTP, its ports. Packets of other protocols have both ports set to 0.
+#[derive(Eq, Clone, Copy, Hash, PartialEq, Debug)]
+pub struct FlowKey {
+    pub src_addr: IpAddr,
+    pub dest_addr: IpAddr,
+    pub protocol: u8,
+    pub src_port: u16,
+    pub dest_port: u16,
+}
+
+impl FlowKey {
+    pub fn from_ipv4(packet: &Ipv4Packet) -> FlowKey {
+        let protocol = packet.data[packet.layer3_offset + 9];
+        let (src_port, dest_port) =
+            transport_ports(protocol, &packet.data[packet.payload_offset..]);
+        FlowKey {
+            src_addr: IpAddr::V4(packet.src_addr()),
+            dest_addr: IpAddr::V4(packet.dest_addr()),
+            protocol,
+            src_port,
+            dest_port,
+        }
+    }
+
+    /// Extension headers are not walked, so a packet that carries any is keyed by its first next
+    /// header value and no ports.
+    pub fn from_ipv6(packet: &Ipv6Packet) -> FlowKey {
+        let protocol = packet.data[packet.layer3_offset + 6];
+        let (src_port, dest_port) =
+            transport_ports(protocol, &packet.data[packet.payload_offset..]);
+        FlowKey {
+            src_addr: IpAddr::V6(packet.src_addr()),
+            dest_addr: IpAddr::V6(packet.dest_addr()),
+            protocol,
+            src_port,
+            dest_port,
+        }
+    }
+
+    /// The key of the traffic flowing the opposite way.
+    pub fn reverse(&self) -> FlowKey {
+        FlowKey {
+            src_addr: self.dest_addr,
+            dest_addr: self.src_addr,
+            protocol: self.protocol,
+            src_port: self.dest_port,
+            dest_port: self.src_port,
+        }
+    }
+}
+
+fn transport_ports(protocol: u8, payload: &[u8]) -> (u16, u16) {
+    match IpProtocol::from(protocol) {
+        IpProtocol::TCP | IpProtocol::UDP | IpProtocol::SCTP if payload.len() >= 4 => (
+            u16::from_be_bytes(payload[0..2].try_into().unwrap()),
+            u16::from_be_bytes(payload[2..4].try_into().unwrap()),
+        ),
+        _ => (0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn ipv4_udp_key() {
+        let mut segment = UdpSegment::empty();
+        segment.set_src_port(5353);
+        segment.set_dest_port(53);
+        let mut packet = Ipv4Packet::encap_udp(segment);
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 2));
+        packet.set_dest_addr(Ipv4Addr::new(1, 1, 1, 1));
+
+        let key = FlowKey::from_ipv4(&packet);
+        assert_eq!(key.src_addr, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(key.dest_addr, IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)));
+        assert_eq!(key.protocol, 17);
+        assert_eq!(key.src_port, 5353);
+        assert_eq!(key.dest_port, 53);
+    }
+
+    #[test]
+    fn ipv6_tcp_key() {
+        let mut segment = TcpSegment::empty();
+        segment.set_src_port(40000);
+        segment.set_dest_port(443);
+        let mut packet = Ipv6Packet::encap_tcp(segment);
+        packet.set_dest_addr(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+
+        let key = FlowKey::from_ipv6(&packet);
+        assert_eq!(
+            key.dest_addr,
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+        );
+        assert_eq!(key.protocol, 6);
+        assert_eq!(key.src_port, 40000);
+        assert_eq!(key.dest_port, 443);
+    }
+
+    #[test]
+    fn ipv4_sctp_key() {
+        let mut sctp = SctpPacket::empty();
+        sctp.set_src_port(36412);
+        sctp.set_dest_port(38412);
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(132);
+        packet.set_payload(&sctp.data);
+
+        let key = FlowKey::from_ipv4(&packet);
+        assert_eq!(key.protocol, 132);
+        assert_eq!((key.src_port, key.dest_port), (36412, 38412));
+    }
+
+    #[test]
+    fn portless_protocol() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(1);
+        packet.set_payload(&[8, 0, 0xf7, 0xff]);
+        let key = FlowKey::from_ipv4(&packet);
+        assert_eq!(key.protocol, 1);
+        assert_eq!((key.src_port, key.dest_port), (0, 0));
+    }
+
+    #[test]
+    fn reverse_key() {
+        let mut segment = UdpSegment::empty();
+        segment.set_src_port(1000);
+        segment.set_dest_port(2000);
+        let mut packet = Ipv4Packet::encap_udp(segment);
+        packet.set_src_addr(Ipv4Addr::new(192, 168, 0, 1));
+
+        let key = FlowKey::from_ipv4(&packet);
+        let reverse = key.reverse();
+        assert_eq!(reverse.src_addr, key.dest_addr);
+        assert_eq!(reverse.dest_port, 1000);
+        assert_eq!(reverse.reverse(), key);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+pub const GENEVE_PORT: u16 = 6081;
+
+const UDP_PROTOCOL: u8 = 17;
+const UDP_HEADER_LEN: usize = 8;
+const GENEVE_HEADER_LEN: usize = 8;
+const GENEVE_OAM: u8 = 0x80;
+const GENEVE_CRITICAL: u8 = 0x40;
+
+///
+/// Ipv4Packet wrapper with getters/setters for the UDP and Geneve headers of Geneve packets. The
+/// UDP checksum is left at 0, as RFC 8926 section 3.3 allows over IPv4.
+/// https://tools.ietf.org/html/rfc8926
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenevePacket {
+    packet: Ipv4Packet,
+}
+
+impl GenevePacket {
+    /// Constructs a Geneve packet to the Geneve port, of VNI 0, with no options, carrying
+    /// Ethernet frames, though there is no frame yet.
+    pub fn new() -> Self {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(UDP_PROTOCOL);
+        packet.set_ttl(64);
+        packet.set_payload(&[0; UDP_HEADER_LEN + GENEVE_HEADER_LEN]);
+        let mut geneve = GenevePacket { packet };
+        geneve.udp_data_mut()[2..4].copy_from_slice(&GENEVE_PORT.to_be_bytes());
+        geneve.set_protocol_type(TRANSPARENT_ETHER_BRIDGING);
+        geneve.set_udp_length();
+        geneve
+    }
+
+    /// The UDP source port, which senders set from a hash of the inner flow, so that the paths
+    /// between them spread the overlay's flows over their links.
+    pub fn src_port(&self) -> u16 {
+        u16::from_be_bytes(self.udp_data()[0..2].try_into().unwrap())
+    }
+
+    pub fn set_src_port(&mut self, src_port: u16) -> &mut Self {
+        self.udp_data_mut()[0..2].copy_from_slice(&src_port.to_be_bytes());
+        self
+    }
+
+    /// Whether the packet carries control messages, rather than data.
+    pub fn oam(&self) -> bool {
+        self.geneve_data()[1] & GENEVE_OAM != 0
+    }
+
+    pub fn set_oam(&mut self, oam: bool) -> &mut Self {
+        let data = self.geneve_data_mut();
+        data[1] = if oam {
+            data[1] | GENEVE_OAM
+        } else {
+            data[1] & !GENEVE_OAM
+        };
+        self
+    }
+
+    /// Whether the options include any a receiver must drop the packet for, if it doesn't
+    /// understand them.
+    pub fn critical(&self) -> bool {
+        self.geneve_data()[1] & GENEVE_CRITICAL != 0
+    }
+
+    pub fn set_critical(&mut self, critical: bool) -> &mut Self {
+        let data = self.geneve_data_mut();
+        data[1] = if critical {
+            data[1] | GENEVE_CRITICAL
+        } else {
+            data[1] & !GENEVE_CRITICAL
+        };
+        self
+    }
+
+    /// The ether type of the inner packet.
+    pub fn protocol_type(&self) -> u16 {
+        u16::from_be_bytes(self.geneve_data()[2..4].try_into().unwrap())
+    }
+
+    pub fn set_protocol_type(&mut self, protocol_type: u16) -> &mut Self {
+        self.geneve_data_mut()[2..4].copy_from_slice(&protocol_type.to_be_bytes());
+        self
+    }
+
+    /// The Virtual Network Identifier, 24 bits.
+    pub fn vni(&self) -> u32 {
+        u32::from_be_bytes(self.geneve_data()[4..8].try_into().unwrap()) >> 8
+    }
+
+    pub fn set_vni(&mut self, vni: u32) -> &mut Self {
+        self.geneve_data_mut()[4..8].copy_from_slice(&(vni << 8).to_be_bytes());
+        self
+    }
+
+    /// The options, as they are on the wire, a multiple of 4 bytes long.
+    pub fn options(&self) -> &[u8] {
+        &self.geneve_data()[GENEVE_HEADER_LEN..self.header_len()]
+    }
+
+    pub fn set_options(&mut self, options: &[u8]) -> &mut Self {
+        assert!(
+            options.len().is_multiple_of(4) && options.len() <= 252,
+            "Geneve options must be a multiple of 4 bytes, up to 252"
+        );
+        let inner = self.inner().to_vec();
+        let mut data = self.packet.data
+            [self.packet.payload_offset..self.packet.payload_offset + UDP_HEADER_LEN + 8]
+            .to_vec();
+        data[UDP_HEADER_LEN] = (data[UDP_HEADER_LEN] & 0xc0) | (options.len() / 4) as u8;
+        data.extend_from_slice(options);
+        data.extend_from_slice(&inner);
+        self.packet.set_payload(&data);
+        self.set_udp_length();
+        self
+    }
+
+    /// The length of the Geneve header, with its options.
+    pub fn header_len(&self) -> usize {
+        GENEVE_HEADER_LEN + usize::from(self.geneve_data()[0] & 0x3f) * 4
+    }
+
+    /// The tunneled packet, everything after the Geneve header.
+    pub fn inner(&self) -> &[u8] {
+        &self.geneve_data()[self.header_len()..]
+    }
+
+    pub fn set_inner(&mut self, inner: &[u8]) -> &mut Self {
+        let end = self.packet.payload_offset + UDP_HEADER_LEN + self.header_len();
+        let mut data = self.packet.data[self.packet.payload_offset..end].to_vec();
+        data.extend_from_slice(inner);
+        self.packet.set_payload(&data);
+        self.set_udp_length();
+        self
+    }
+
+    pub fn ipv4(&self) -> &Ipv4Packet {
+        &self.packet
+    }
+
+    pub fn ipv4_mut(&mut self) -> &mut Ipv4Packet {
+        &mut self.packet
+    }
+
+    // Move ownership of the packet back to the caller
+    pub fn packet(self) -> Ipv4Packet {
+        self.packet
+    }
+
+    fn set_udp_length(&mut self) {
+        let length = (self.udp_data().len() as u16).to_be_bytes();
+        self.udp_data_mut()[4..6].copy_from_slice(&length);
+    }
+
+    fn udp_data(&self) -> &[u8] {
+        &self.packet.data[self.packet.payload_offset..]
+    }
+
+    fn udp_data_mut(&mut self) -> &mut [u8] {
+        &mut self.packet.data[self.packet.payload_offset..]
+    }
+
+    fn geneve_data(&self) -> &[u8] {
+        &self.udp_data()[UDP_HEADER_LEN..]
+    }
+
+    fn geneve_data_mut(&mut self) -> &mut [u8] {
+        &mut self.udp_data_mut()[UDP_HEADER_LEN..]
+    }
+}
+
+impl Default for GenevePacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<Ipv4Packet> for GenevePacket {
+    type Error = &'static str;
+
+    ///
+    /// Decorates the given Ipv4Packet with GenevePacket getters/setters.
+    /// Validates
+    /// - The packet is UDP, to the Geneve port
+    /// - The Geneve header is of version 0, and it and its options fit within the packet
+    ///
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        if packet.protocol() != IpProtocol::UDP {
+            return Err("Packet protocol is not UDP");
+        }
+        if packet.payload().len() < UDP_HEADER_LEN + GENEVE_HEADER_LEN {
+            return Err("Packet payload is too short to be a Geneve packet");
+        }
+        let geneve = GenevePacket { packet };
+        if u16::from_be_bytes(geneve.udp_data()[2..4].try_into().unwrap()) != GENEVE_PORT {
+            return Err("Packet is not to the Geneve port");
+        }
+        if geneve.geneve_data()[0] >> 6 != 0 {
+            return Err("Geneve header is not of version 0");
+        }
+        if geneve.geneve_data().len() < geneve.header_len() {
+            return Err("Packet payload is too short for its Geneve options");
+        }
+        Ok(geneve)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_options_and_inner() {
+        let mut geneve = GenevePacket::new();
+        geneve
+            .set_vni(0x00ab_cdef)
+            .set_src_port(50000)
+            .set_inner(&[7; 30])
+            .set_options(&[0, 0x80, 1, 1, 9, 9, 9, 9])
+            .set_critical(true);
+
+        let geneve = GenevePacket::try_from(geneve.packet()).unwrap();
+        assert_eq!(geneve.vni(), 0x00ab_cdef);
+        assert_eq!(geneve.src_port(), 50000);
+        assert_eq!(geneve.protocol_type(), TRANSPARENT_ETHER_BRIDGING);
+        assert!(geneve.critical());
+        assert!(!geneve.oam());
+        assert_eq!(geneve.header_len(), 16);
+        assert_eq!(geneve.options(), &[0, 0x80, 1, 1, 9, 9, 9, 9]);
+        assert_eq!(geneve.inner(), &[7; 30][..]);
+
+        let udp = UdpSegment::try_from(geneve.packet()).unwrap();
+        assert_eq!(udp.dest_port(), GENEVE_PORT);
+        assert_eq!(usize::from(udp.length()), 8 + 16 + 30);
+    }
+
+    #[test]
+    fn rejects_other_udp() {
+        let mut geneve = GenevePacket::new();
+        geneve.set_inner(&[0; 14]);
+        let mut packet = geneve.packet();
+        let offset = packet.payload_offset;
+        packet.data[offset + 3] = 53;
+        assert!(GenevePacket::try_from(packet).is_err());
+    }
+}
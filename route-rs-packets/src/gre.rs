@@ -0,0 +1,221 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+const GRE_PROTOCOL: u8 = 47;
+const GRE_CHECKSUM_PRESENT: u16 = 0x8000;
+const GRE_KEY_PRESENT: u16 = 0x2000;
+const GRE_SEQUENCE_PRESENT: u16 = 0x1000;
+const GRE_VERSION: u16 = 0x0007;
+
+///
+/// Ipv4Packet wrapper with getters/setters for the header of GRE packets, with the key and
+/// sequence number extensions. Checksums are read past, but never set.
+/// https://tools.ietf.org/html/rfc2784
+/// https://tools.ietf.org/html/rfc2890
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrePacket {
+    packet: Ipv4Packet,
+}
+
+impl GrePacket {
+    /// Constructs a GRE packet carrying `protocol_type`, an ether type, with no key, sequence
+    /// number, or payload.
+    pub fn new(protocol_type: u16) -> Self {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(GRE_PROTOCOL);
+        packet.set_ttl(64);
+        let mut header = vec![0, 0];
+        header.extend_from_slice(&protocol_type.to_be_bytes());
+        packet.set_payload(&header);
+        GrePacket { packet }
+    }
+
+    fn flags(&self) -> u16 {
+        u16::from_be_bytes(self.gre_data()[0..2].try_into().unwrap())
+    }
+
+    pub fn protocol_type(&self) -> u16 {
+        u16::from_be_bytes(self.gre_data()[2..4].try_into().unwrap())
+    }
+
+    pub fn set_protocol_type(&mut self, protocol_type: u16) -> &mut Self {
+        self.gre_data_mut()[2..4].copy_from_slice(&protocol_type.to_be_bytes());
+        self
+    }
+
+    pub fn key(&self) -> Option<u32> {
+        self.field(GRE_KEY_PRESENT)
+    }
+
+    pub fn set_key(&mut self, key: Option<u32>) -> &mut Self {
+        self.set_field(GRE_KEY_PRESENT, key)
+    }
+
+    pub fn sequence(&self) -> Option<u32> {
+        self.field(GRE_SEQUENCE_PRESENT)
+    }
+
+    pub fn set_sequence(&mut self, sequence: Option<u32>) -> &mut Self {
+        self.set_field(GRE_SEQUENCE_PRESENT, sequence)
+    }
+
+    /// The length of the header, with the optional fields its flags say it has.
+    pub fn header_len(&self) -> usize {
+        let flags = self.flags();
+        [GRE_CHECKSUM_PRESENT, GRE_KEY_PRESENT, GRE_SEQUENCE_PRESENT]
+            .iter()
+            .filter(|flag| flags & **flag != 0)
+            .count()
+            * 4
+            + 4
+    }
+
+    /// Everything after the header, the packet of `protocol_type`.
+    pub fn gre_payload(&self) -> &[u8] {
+        &self.gre_data()[self.header_len()..]
+    }
+
+    pub fn set_gre_payload(&mut self, payload: &[u8]) -> &mut Self {
+        let mut data = self.gre_data()[..self.header_len()].to_vec();
+        data.extend_from_slice(payload);
+        self.packet.set_payload(&data);
+        self
+    }
+
+    pub fn ipv4(&self) -> &Ipv4Packet {
+        &self.packet
+    }
+
+    pub fn ipv4_mut(&mut self) -> &mut Ipv4Packet {
+        &mut self.packet
+    }
+
+    // Move ownership of the packet back to the caller
+    pub fn packet(self) -> Ipv4Packet {
+        self.packet
+    }
+
+    /// The offset of the optional field of `flag` within the header, the fields being in the
+    /// order of their flags.
+    fn field_offset(&self, flag: u16) -> usize {
+        let flags = self.flags();
+        [GRE_CHECKSUM_PRESENT, GRE_KEY_PRESENT, GRE_SEQUENCE_PRESENT]
+            .iter()
+            .take_while(|f| **f != flag)
+            .filter(|f| flags & **f != 0)
+            .count()
+            * 4
+            + 4
+    }
+
+    fn field(&self, flag: u16) -> Option<u32> {
+        if self.flags() & flag == 0 {
+            return None;
+        }
+        let offset = self.field_offset(flag);
+        Some(u32::from_be_bytes(
+            self.gre_data()[offset..offset + 4].try_into().unwrap(),
+        ))
+    }
+
+    fn set_field(&mut self, flag: u16, value: Option<u32>) -> &mut Self {
+        let offset = self.field_offset(flag);
+        let present = self.flags() & flag != 0;
+        let mut data = self.gre_data().to_vec();
+        match (present, value) {
+            (true, Some(value)) => data[offset..offset + 4].copy_from_slice(&value.to_be_bytes()),
+            (true, None) => {
+                data.drain(offset..offset + 4);
+            }
+            (false, Some(value)) => {
+                data.splice(offset..offset, value.to_be_bytes().iter().cloned());
+            }
+            (false, None) => return self,
+        }
+        let flags = if value.is_some() {
+            self.flags() | flag
+        } else {
+            self.flags() & !flag
+        };
+        data[0..2].copy_from_slice(&flags.to_be_bytes());
+        self.packet.set_payload(&data);
+        self
+    }
+
+    fn gre_data(&self) -> &[u8] {
+        &self.packet.data[self.packet.payload_offset..]
+    }
+
+    fn gre_data_mut(&mut self) -> &mut [u8] {
+        &mut self.packet.data[self.packet.payload_offset..]
+    }
+}
+
+impl TryFrom<Ipv4Packet> for GrePacket {
+    type Error = &'static str;
+
+    ///
+    /// Decorates the given Ipv4Packet with GrePacket getters/setters.
+    /// Validates
+    /// - The packet's protocol is GRE
+    /// - The header is of version 0
+    /// - The header, with the optional fields its flags say it has, fits within the packet
+    ///
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        if packet.protocol() != IpProtocol::GREs {
+            return Err("Packet protocol is not GRE");
+        }
+        if packet.payload().len() < 4 {
+            return Err("Packet payload is too short to be a GRE packet");
+        }
+        let gre = GrePacket { packet };
+        if gre.flags() & GRE_VERSION != 0 {
+            return Err("GRE header is not of version 0");
+        }
+        if gre.gre_data().len() < gre.header_len() {
+            return Err("Packet payload is too short for its GRE header");
+        }
+        Ok(gre)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn optional_fields() {
+        let mut gre = GrePacket::new(ERSPAN_II_ETHER_TYPE);
+        gre.set_gre_payload(&[1, 2, 3]);
+        assert_eq!(gre.header_len(), 4);
+        assert_eq!((gre.key(), gre.sequence()), (None, None));
+
+        gre.set_sequence(Some(9)).set_key(Some(0xabcd));
+        assert_eq!(gre.header_len(), 12);
+        assert_eq!(gre.key(), Some(0xabcd));
+        assert_eq!(gre.sequence(), Some(9));
+        assert_eq!(gre.protocol_type(), ERSPAN_II_ETHER_TYPE);
+        assert_eq!(gre.gre_payload(), &[1, 2, 3]);
+
+        gre.set_key(None);
+        assert_eq!((gre.key(), gre.sequence()), (None, Some(9)));
+        assert_eq!(gre.gre_payload(), &[1, 2, 3]);
+
+        let gre = GrePacket::try_from(gre.packet()).unwrap();
+        assert_eq!(gre.sequence(), Some(9));
+    }
+
+    #[test]
+    fn rejects_invalid_packets() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(17);
+        packet.set_payload(&[0; 4]);
+        assert!(GrePacket::try_from(packet).is_err());
+
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(GRE_PROTOCOL);
+        packet.set_payload(&[0x10, 0, 0x88, 0xbe, 0, 0]);
+        assert!(GrePacket::try_from(packet).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use crate::*;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// ICMP Destination Unreachable, and its Fragmentation Needed code, RFC 792 and RFC 1191.
+pub const ICMP_DEST_UNREACHABLE: u8 = 3;
+pub const ICMP_FRAGMENTATION_NEEDED: u8 = 4;
+
+/// ICMPv6 Packet Too Big, RFC 4443 section 3.2.
+pub const ICMPV6_PACKET_TOO_BIG: u8 = 2;
+
+/// The smallest MTU of an IPv6 link, which an ICMPv6 error must fit in, RFC 8200 section 5.
+pub const IPV6_MIN_MTU: usize = 1280;
+
+const ICMP_PROTOCOL: u8 = 1;
+const ICMPV6_NEXT_HEADER: u8 = 58;
+
+/// The Fragmentation Needed error to send, from `src_addr`, to the source of `packet`, which is
+/// too big for the next hop's `mtu` and has Don't Fragment set, RFC 1191 section 4. It quotes the
+/// packet's header and the first 8 bytes of its payload, and has no layer 2 header.
+pub fn icmp_fragmentation_needed(packet: &Ipv4Packet, mtu: u16, src_addr: Ipv4Addr) -> Ipv4Packet {
+    let quoted_len = (packet.payload_offset + 8).min(packet.data.len());
+    let mut message = vec![ICMP_DEST_UNREACHABLE, ICMP_FRAGMENTATION_NEEDED, 0, 0, 0, 0];
+    message.extend_from_slice(&mtu.to_be_bytes());
+    message.extend_from_slice(&packet.data[packet.layer3_offset..quoted_len]);
+    let checksum = internet_checksum(&message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    let mut error = Ipv4Packet::empty();
+    error.set_protocol(ICMP_PROTOCOL);
+    error.set_ttl(64);
+    error.set_src_addr(src_addr);
+    error.set_dest_addr(packet.src_addr());
+    error.set_payload(&message);
+    error.set_checksum();
+    error
+}
+
+/// The Packet Too Big error to send, from `src_addr`, to the source of `packet`, which is too big
+/// for the next hop's `mtu`, RFC 4443 section 3.2. It quotes as much of the packet as fits in the
+/// minimum IPv6 MTU, and has no layer 2 header.
+pub fn icmpv6_packet_too_big(packet: &Ipv6Packet, mtu: u32, src_addr: Ipv6Addr) -> Ipv6Packet {
+    let quoted = &packet.data[packet.layer3_offset..];
+    let quoted = &quoted[..quoted.len().min(IPV6_MIN_MTU - 40 - 8)];
+    let mut message = vec![ICMPV6_PACKET_TOO_BIG, 0, 0, 0];
+    message.extend_from_slice(&mtu.to_be_bytes());
+    message.extend_from_slice(quoted);
+
+    let dest_addr = packet.src_addr();
+    let checksum = ipv6_pseudo_header_checksum(src_addr, dest_addr, ICMPV6_NEXT_HEADER, &message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    let mut error = Ipv6Packet::empty();
+    error.set_next_header(ICMPV6_NEXT_HEADER);
+    error.set_hop_limit(64);
+    error.set_src_addr(src_addr);
+    error.set_dest_addr(dest_addr);
+    error.set_payload(&message);
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fragmentation_needed() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 2));
+        packet.set_protocol(17);
+        packet.set_payload(&[7; 1472]);
+
+        let router = Ipv4Addr::new(192, 0, 2, 1);
+        let mut error = icmp_fragmentation_needed(&packet, 1400, router);
+        assert!(error.validate_checksum());
+        assert_eq!(error.protocol(), IpProtocol::ICMP);
+        assert_eq!(error.src_addr(), router);
+        assert_eq!(error.dest_addr(), Ipv4Addr::new(10, 0, 0, 2));
+        let message = error.payload();
+        assert_eq!(
+            message[..2],
+            [ICMP_DEST_UNREACHABLE, ICMP_FRAGMENTATION_NEEDED]
+        );
+        assert_eq!(message[6..8], 1400u16.to_be_bytes());
+        assert_eq!(message.len(), 8 + 20 + 8);
+        assert_eq!(internet_checksum(&message), 0);
+    }
+
+    #[test]
+    fn packet_too_big() {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_src_addr("2001:db8::2".parse().unwrap());
+        packet.set_payload(&[7; 1600]);
+
+        let router: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let error = icmpv6_packet_too_big(&packet, 1500, router);
+        assert_eq!(error.next_header(), IpProtocol::IPv6_ICMP);
+        assert_eq!(error.dest_addr(), packet.src_addr());
+        assert_eq!(error.data.len(), IPV6_MIN_MTU);
+        let message = error.payload();
+        assert_eq!(message[0], ICMPV6_PACKET_TOO_BIG);
+        assert_eq!(message[4..8], 1500u32.to_be_bytes());
+    }
+}
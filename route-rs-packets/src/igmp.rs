@@ -0,0 +1,318 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+use std::net::{IpAddr, Ipv4Addr};
+
+pub const IGMP_MEMBERSHIP_QUERY: u8 = 0x11;
+pub const IGMP_V1_MEMBERSHIP_REPORT: u8 = 0x12;
+pub const IGMP_V2_MEMBERSHIP_REPORT: u8 = 0x16;
+pub const IGMP_LEAVE_GROUP: u8 = 0x17;
+pub const IGMP_V3_MEMBERSHIP_REPORT: u8 = 0x22;
+
+/// Group record types shared by IGMPv3 (RFC 3376) and MLDv2 (RFC 3810) reports.
+pub const MODE_IS_INCLUDE: u8 = 1;
+pub const MODE_IS_EXCLUDE: u8 = 2;
+pub const CHANGE_TO_INCLUDE_MODE: u8 = 3;
+pub const CHANGE_TO_EXCLUDE_MODE: u8 = 4;
+pub const ALLOW_NEW_SOURCES: u8 = 5;
+pub const BLOCK_OLD_SOURCES: u8 = 6;
+
+const IGMP_PROTOCOL: u8 = 2;
+const IGMP_HEADER_LEN: usize = 8;
+
+/// A change or statement of membership for one multicast group, as carried by IGMP and MLD reports.
+/// Reports from older protocol versions are translated into their version 3 equivalent: a report is
+/// `MODE_IS_EXCLUDE` with no sources, and a leave is `CHANGE_TO_INCLUDE_MODE` with no sources.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MulticastGroupRecord {
+    pub record_type: u8,
+    pub group: IpAddr,
+    pub num_sources: u16,
+}
+
+impl MulticastGroupRecord {
+    /// Whether the sender wants to receive traffic for the group after this record.
+    pub fn is_join(&self) -> bool {
+        match self.record_type {
+            MODE_IS_EXCLUDE | CHANGE_TO_EXCLUDE_MODE => true,
+            MODE_IS_INCLUDE | ALLOW_NEW_SOURCES | CHANGE_TO_INCLUDE_MODE => self.num_sources > 0,
+            _ => false,
+        }
+    }
+
+    /// Whether the sender no longer wants any traffic for the group.
+    pub fn is_leave(&self) -> bool {
+        match self.record_type {
+            MODE_IS_INCLUDE | CHANGE_TO_INCLUDE_MODE => self.num_sources == 0,
+            _ => false,
+        }
+    }
+}
+
+/// Walks the group records of an IGMPv3 or MLDv2 report. `records` starts at the first record, and
+/// `addr_len` is 4 or 16 depending on the protocol.
+pub(crate) fn parse_group_records(
+    records: &[u8],
+    num_records: usize,
+    addr_len: usize,
+) -> Vec<MulticastGroupRecord> {
+    let mut parsed = vec![];
+    let mut offset = 0;
+    for _ in 0..num_records {
+        if records.len() < offset + 4 + addr_len {
+            break;
+        }
+        let record_type = records[offset];
+        let aux_len = records[offset + 1] as usize;
+        let num_sources = u16::from_be_bytes(records[offset + 2..offset + 4].try_into().unwrap());
+        let group_bytes = &records[offset + 4..offset + 4 + addr_len];
+        let group = if addr_len == 4 {
+            IpAddr::from(<[u8; 4]>::try_from(group_bytes).unwrap())
+        } else {
+            IpAddr::from(<[u8; 16]>::try_from(group_bytes).unwrap())
+        };
+        parsed.push(MulticastGroupRecord {
+            record_type,
+            group,
+            num_sources,
+        });
+        offset += 4 + addr_len + (num_sources as usize * addr_len) + (aux_len * 4);
+    }
+    parsed
+}
+
+///
+/// Ipv4Packet wrapper with getters/setters for IGMP messages, versions 1 through 3
+/// https://tools.ietf.org/html/rfc2236
+/// https://tools.ietf.org/html/rfc3376
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgmpPacket {
+    packet: Ipv4Packet,
+}
+
+impl IgmpPacket {
+    /// Constructs an IGMP message with a zeroed 8 byte header, in an Ipv4Packet with a TTL of 1
+    /// as IGMP requires.
+    pub fn new() -> Self {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(IGMP_PROTOCOL);
+        packet.set_ttl(1);
+        packet.set_payload(&[0; IGMP_HEADER_LEN]);
+        IgmpPacket { packet }
+    }
+
+    pub fn message_type(&self) -> u8 {
+        self.igmp_data()[0]
+    }
+
+    pub fn set_message_type(&mut self, message_type: u8) -> &mut Self {
+        self.igmp_data_mut()[0] = message_type;
+        self
+    }
+
+    /// Max response time, in tenths of a second. Only meaningful in queries.
+    pub fn max_resp_time(&self) -> u8 {
+        self.igmp_data()[1]
+    }
+
+    pub fn set_max_resp_time(&mut self, max_resp_time: u8) -> &mut Self {
+        self.igmp_data_mut()[1] = max_resp_time;
+        self
+    }
+
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes(self.igmp_data()[2..4].try_into().unwrap())
+    }
+
+    /// Recalculates the checksum over the whole IGMP message.
+    pub fn set_checksum(&mut self) -> &mut Self {
+        self.igmp_data_mut()[2..4].copy_from_slice(&[0, 0]);
+        let checksum = internet_checksum(self.igmp_data());
+        self.igmp_data_mut()[2..4].copy_from_slice(&checksum.to_be_bytes());
+        self
+    }
+
+    /// The group being queried, reported or left. Version 3 reports carry their groups in group
+    /// records instead, see `group_records`.
+    pub fn group_addr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(<[u8; 4]>::try_from(&self.igmp_data()[4..8]).unwrap())
+    }
+
+    pub fn set_group_addr(&mut self, addr: Ipv4Addr) -> &mut Self {
+        self.igmp_data_mut()[4..8].copy_from_slice(&addr.octets());
+        self
+    }
+
+    /// Membership changes announced by this message. Empty for queries.
+    pub fn group_records(&self) -> Vec<MulticastGroupRecord> {
+        let group = IpAddr::V4(self.group_addr());
+        match self.message_type() {
+            IGMP_V1_MEMBERSHIP_REPORT | IGMP_V2_MEMBERSHIP_REPORT => vec![MulticastGroupRecord {
+                record_type: MODE_IS_EXCLUDE,
+                group,
+                num_sources: 0,
+            }],
+            IGMP_LEAVE_GROUP => vec![MulticastGroupRecord {
+                record_type: CHANGE_TO_INCLUDE_MODE,
+                group,
+                num_sources: 0,
+            }],
+            IGMP_V3_MEMBERSHIP_REPORT => {
+                let data = self.igmp_data();
+                let num_records = u16::from_be_bytes(data[6..8].try_into().unwrap()) as usize;
+                parse_group_records(&data[IGMP_HEADER_LEN..], num_records, 4)
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Makes the message a version 3 report of `records`. Sources are not carried, so each
+    /// record is written with none, whatever its `num_sources`, and IPv6 groups are left out. The checksum must be set after.
+    pub fn set_group_records(&mut self, records: &[MulticastGroupRecord]) -> &mut Self {
+        let records: Vec<(u8, Ipv4Addr)> = records
+            .iter()
+            .filter_map(|record| match record.group {
+                IpAddr::V4(group) => Some((record.record_type, group)),
+                IpAddr::V6(_) => None,
+            })
+            .collect();
+        let mut data = vec![IGMP_V3_MEMBERSHIP_REPORT, 0, 0, 0, 0, 0];
+        data.extend_from_slice(&(records.len() as u16).to_be_bytes());
+        for (record_type, group) in records {
+            data.extend_from_slice(&[record_type, 0, 0, 0]);
+            data.extend_from_slice(&group.octets());
+        }
+        self.packet.set_payload(&data);
+        self
+    }
+
+    // Move ownership of the packet back to the caller
+    pub fn packet(self) -> Ipv4Packet {
+        self.packet
+    }
+
+    fn igmp_data(&self) -> &[u8] {
+        &self.packet.data[self.packet.payload_offset..]
+    }
+
+    fn igmp_data_mut(&mut self) -> &mut [u8] {
+        &mut self.packet.data[self.packet.payload_offset..]
+    }
+}
+
+impl Default for IgmpPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<Ipv4Packet> for IgmpPacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        if packet.protocol() != IpProtocol::IGMP {
+            return Err("Packet protocol is not IGMP");
+        }
+        if packet.payload().len() < IGMP_HEADER_LEN {
+            return Err("Packet payload is too short to be an IGMP message");
+        }
+        Ok(IgmpPacket { packet })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v2_report() {
+        let mut igmp = IgmpPacket::new();
+        igmp.set_message_type(IGMP_V2_MEMBERSHIP_REPORT)
+            .set_group_addr(Ipv4Addr::new(239, 1, 2, 3))
+            .set_checksum();
+
+        assert_eq!(igmp.message_type(), IGMP_V2_MEMBERSHIP_REPORT);
+        assert_eq!(igmp.group_addr(), Ipv4Addr::new(239, 1, 2, 3));
+        assert_eq!(internet_checksum(igmp.igmp_data()), 0);
+
+        let records = igmp.group_records();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].is_join());
+        assert_eq!(records[0].group, IpAddr::V4(Ipv4Addr::new(239, 1, 2, 3)));
+
+        let packet = igmp.packet();
+        assert_eq!(packet.ttl(), 1);
+        assert_eq!(packet.protocol(), IpProtocol::IGMP);
+    }
+
+    #[test]
+    fn leave() {
+        let mut igmp = IgmpPacket::new();
+        igmp.set_message_type(IGMP_LEAVE_GROUP)
+            .set_group_addr(Ipv4Addr::new(239, 1, 2, 3));
+        let records = igmp.group_records();
+        assert!(records[0].is_leave());
+        assert!(!records[0].is_join());
+    }
+
+    #[test]
+    fn v3_report() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(2);
+        packet.set_payload(&[
+            0x22, 0, 0, 0, 0, 0, 0, 2, // header with 2 group records
+            4, 0, 0, 0, 239, 0, 0, 1, // CHANGE_TO_EXCLUDE 239.0.0.1
+            3, 1, 0, 1, 239, 0, 0, 2, 10, 0, 0, 1, 0, 0, 0,
+            0, // CHANGE_TO_INCLUDE with a source
+        ]);
+        let igmp = IgmpPacket::try_from(packet).unwrap();
+
+        let records = igmp.group_records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].group, IpAddr::V4(Ipv4Addr::new(239, 0, 0, 1)));
+        assert!(records[0].is_join());
+        assert_eq!(records[1].group, IpAddr::V4(Ipv4Addr::new(239, 0, 0, 2)));
+        assert_eq!(records[1].num_sources, 1);
+        assert!(records[1].is_join());
+    }
+
+    #[test]
+    fn builds_v3_report() {
+        let mut igmp = IgmpPacket::new();
+        igmp.set_group_records(&[MulticastGroupRecord {
+            record_type: CHANGE_TO_EXCLUDE_MODE,
+            group: IpAddr::V4(Ipv4Addr::new(239, 0, 0, 9)),
+            num_sources: 0,
+        }])
+        .set_checksum();
+
+        assert_eq!(igmp.message_type(), IGMP_V3_MEMBERSHIP_REPORT);
+        assert_eq!(internet_checksum(igmp.igmp_data()), 0);
+        let records = igmp.group_records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].group, IpAddr::V4(Ipv4Addr::new(239, 0, 0, 9)));
+        assert!(records[0].is_join());
+    }
+
+    #[test]
+    fn truncated_v3_report() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(2);
+        packet.set_payload(&[0x22, 0, 0, 0, 0, 0, 0, 3, 4, 0, 0, 0, 239, 0, 0, 1, 4, 0]);
+        let igmp = IgmpPacket::try_from(packet).unwrap();
+        assert_eq!(igmp.group_records().len(), 1);
+    }
+
+    #[test]
+    fn rejects_non_igmp() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(17);
+        packet.set_payload(&[0; 8]);
+        assert!(IgmpPacket::try_from(packet).is_err());
+
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(2);
+        packet.set_payload(&[0x16, 0, 0]);
+        assert!(IgmpPacket::try_from(packet).is_err());
+    }
+}
@@ -42,6 +42,9 @@ impl Ipv4Packet {
         // This is the header length in 32bit words
         let ihl = (data[layer3_offset] & 0x0F) as usize;
         let payload_offset = layer3_offset + (ihl * 4);
+        if ihl < 5 || payload_offset > data.len() {
+            return Err("Packet has invalid header length field");
+        }
 
         Ok(Ipv4Packet {
             data,
@@ -226,7 +229,8 @@ impl Ipv4Packet {
             (true, false) => bits = 2,
             (true, true) => bits = 3,
         }
-        self.data[self.layer3_offset + 6] &= 0xE0;
+        // Keep the reserved bit and the top of the fragment offset.
+        self.data[self.layer3_offset + 6] &= 0x9F;
         self.data[self.layer3_offset + 6] |= bits << 5;
     }
 
@@ -268,6 +272,66 @@ impl Ipv4Packet {
         self.data[self.layer3_offset + 11] = (new_checksum & 0x00FF) as u8;
     }
 
+    /// Splits the packet into fragments of at most `mtu` bytes from the IP header on, RFC 791
+    /// section 3.2, each with whatever came before the IP header, such as an Ethernet header.
+    /// Only the first fragment has every option, the rest only those with the copied flag set.
+    /// Returns the packet itself if it fits, and nothing if it doesn't and has Don't Fragment
+    /// set, or if `mtu` can't fit 8 bytes of payload after the header.
+    pub fn fragment(&self, mtu: usize) -> Vec<Ipv4Packet> {
+        let header = &self.data[self.layer3_offset..self.payload_offset];
+        let payload = &self.data[self.payload_offset..];
+        if header.len() + payload.len() <= mtu {
+            return vec![self.clone()];
+        }
+        let (df, mf) = self.flags();
+        if df {
+            return vec![];
+        }
+
+        let mut later_header = header[..20].to_vec();
+        let mut options = &header[20..];
+        while let Some(&option_type) = options.first() {
+            let len = match option_type {
+                0 => break,
+                1 => 1,
+                _ => usize::from(*options.get(1).unwrap_or(&0)).max(2),
+            };
+            let option = &options[..len.min(options.len())];
+            if option_type & 0x80 != 0 {
+                later_header.extend_from_slice(option);
+            }
+            options = &options[option.len()..];
+        }
+        later_header.resize(later_header.len().div_ceil(4) * 4, 0);
+        later_header[0] = 0x40 | (later_header.len() / 4) as u8;
+
+        let mut fragments = vec![];
+        let mut offset = 0;
+        while offset < payload.len() {
+            let header = if offset == 0 { header } else { &later_header };
+            let room = mtu.saturating_sub(header.len()) / 8 * 8;
+            if room == 0 {
+                return vec![];
+            }
+            let end = (offset + room).min(payload.len());
+            let mut data = self.data[..self.layer3_offset].to_vec();
+            data.extend_from_slice(header);
+            data.extend_from_slice(&payload[offset..end]);
+            let total_len = (header.len() + end - offset) as u16;
+            data[self.layer3_offset + 2..self.layer3_offset + 4]
+                .copy_from_slice(&total_len.to_be_bytes());
+
+            let mut fragment =
+                Ipv4Packet::from_buffer(data, self.layer2_offset, self.layer3_offset).unwrap();
+            fragment.set_fragment_offset(self.fragment_offset() + (offset / 8) as u16);
+            fragment.set_flags(false, mf || end < payload.len());
+            fragment.set_checksum();
+            fragments.push(fragment);
+            offset = end;
+        }
+        fragments
+    }
+
     /// Takes a UdpSegment, and returns an Ipv6Packet with the
     /// segment as payload. Does not set checksums
     pub fn encap_udp(udp: UdpSegment) -> Ipv4Packet {
@@ -427,6 +491,29 @@ mod tests {
         assert_eq!(packet.ihl(), 6);
     }
 
+    #[test]
+    fn rejects_invalid_header_length() {
+        let mut data: Vec<u8> = vec![
+            0x44, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
+        ];
+        assert!(Ipv4Packet::from_buffer(data.clone(), None, 0).is_err());
+        data[0] = 0x46;
+        assert!(Ipv4Packet::from_buffer(data, None, 0).is_err());
+    }
+
+    #[test]
+    fn set_flags() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_fragment_offset(0x1abc);
+        packet.set_flags(true, true);
+        assert_eq!(packet.flags(), (true, true));
+        packet.set_flags(false, true);
+        assert_eq!(packet.flags(), (false, true));
+        packet.set_flags(false, false);
+        assert_eq!(packet.flags(), (false, false));
+        assert_eq!(packet.fragment_offset(), 0x1abc);
+    }
+
     #[test]
     fn empty() {
         let empty_packet = Ipv4Packet::empty();
@@ -460,4 +547,36 @@ mod tests {
         assert_eq!(new_segment.layer3_offset, Some(0));
         assert_eq!(new_segment.layer4_offset, 20);
     }
+
+    #[test]
+    fn fragment() {
+        // A copied option, and a record route option which isn't copied.
+        let mut data = vec![
+            0x47, 0, 0, 0, 0x12, 0x34, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+        ];
+        data.extend_from_slice(&[0x82, 4, 0, 0, 0x07, 4, 4, 0]);
+        data.extend((0..100).map(|i| i as u8));
+        data[2..4].copy_from_slice(&128u16.to_be_bytes());
+        let packet = Ipv4Packet::from_buffer(data, None, 0).unwrap();
+
+        let fragments = packet.fragment(68);
+        let lens: Vec<usize> = fragments.iter().map(|f| f.data.len()).collect();
+        assert_eq!(lens, vec![68, 64, 44]);
+        let offsets: Vec<u16> = fragments.iter().map(|f| f.fragment_offset()).collect();
+        assert_eq!(offsets, vec![0, 5, 10]);
+        let more: Vec<bool> = fragments.iter().map(|f| f.flags().1).collect();
+        assert_eq!(more, vec![true, true, false]);
+        assert_eq!(fragments[1].options().unwrap()[..], [0x82, 4, 0, 0]);
+        assert_eq!(fragments[1].indentification(), 0x1234);
+        let mut reassembled = fragments[0].payload().to_vec();
+        reassembled.extend_from_slice(&fragments[1].payload());
+        reassembled.extend_from_slice(&fragments[2].payload());
+        assert_eq!(reassembled[..], packet.payload()[..]);
+        assert!(fragments.iter().all(|f| f.clone().validate_checksum()));
+
+        assert_eq!(packet.fragment(1500), vec![packet.clone()]);
+        let mut df = packet;
+        df.set_flags(true, false);
+        assert!(df.fragment(68).is_empty());
+    }
 }
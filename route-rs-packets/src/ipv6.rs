@@ -61,7 +61,7 @@ impl Ipv6Packet {
 
     pub fn traffic_class(&self) -> u8 {
         ((self.data[self.layer3_offset] & 0x0F) << 4)
-            + (self.data[self.layer3_offset + 1] & 0xF0 >> 4)
+            + ((self.data[self.layer3_offset + 1] & 0xF0) >> 4)
     }
 
     pub fn set_traffic_class(&mut self, traffic_class: u8) {
@@ -171,12 +171,19 @@ impl Ipv6Packet {
                 | IpProtocol::HIP
                 | IpProtocol::Shim6
                 | IpProtocol::Use_for_experimentation_and_testing => {
+                    if offset + 2 > self.data.len() {
+                        return headers;
+                    }
                     header_ext_len = self.data[offset + 1];
                     if header_ext_len == 0 {
                         // Fragments have the minimum of 8, but it set to zero for some dumb reason
                         // https://en.wikipedia.org/wiki/IPv6_packet#Fragment
                         header_ext_len = 8;
                     }
+                    // A header running past the end of the packet ends the chain.
+                    if offset + header_ext_len as usize > self.data.len() {
+                        return headers;
+                    }
                     headers.push(Cow::from(
                         &self.data[offset..offset + header_ext_len as usize],
                     ));
@@ -285,6 +292,23 @@ pub fn get_ipv6_payload_type(
     }
 }
 
+/// The checksum of an upper-layer message over IPv6, such as an ICMPv6 message or a UDP datagram,
+/// with its checksum field zeroed, summed with the pseudo-header of RFC 8200 section 8.1.
+/// `next_header` is the protocol number of the message. UDP sends a zero checksum as all ones,
+/// which is left to the caller.
+pub fn ipv6_pseudo_header_checksum(
+    src_addr: Ipv6Addr,
+    dest_addr: Ipv6Addr,
+    next_header: u8,
+    message: &[u8],
+) -> u16 {
+    let mut sum = sum_words(&src_addr.octets(), 0);
+    sum = sum_words(&dest_addr.octets(), sum);
+    sum = sum_words(&(message.len() as u32).to_be_bytes(), sum);
+    sum += u32::from(next_header);
+    fold_checksum(sum_words(message, sum))
+}
+
 impl TryFrom<EthernetFrame> for Ipv6Packet {
     type Error = &'static str;
 
@@ -353,6 +377,17 @@ mod tests {
         assert_eq!(packet.dest_addr(), dest_addr);
     }
 
+    #[test]
+    fn truncated_extension_headers() {
+        let mut data = vec![0x60, 0, 0, 0, 0, 4, 0, 64];
+        data.resize(40, 0);
+        // A hop-by-hop header claiming 16 bytes, of which only 4 are there.
+        data.extend_from_slice(&[17, 16, 0, 0]);
+
+        let packet = Ipv6Packet::from_buffer(data, None, 0).unwrap();
+        assert!(packet.extension_headers().is_empty());
+    }
+
     #[test]
     fn set_src_addr() {
         let data: Vec<u8> = vec![
@@ -1,15 +1,24 @@
 mod types;
 pub use self::types::*;
 
+mod checksum;
+pub use self::checksum::*;
+
 mod ethernet;
 pub use self::ethernet::*;
 
+mod vlan;
+pub use self::vlan::*;
+
 mod ipv4;
 pub use self::ipv4::*;
 
 mod ipv6;
 pub use self::ipv6::*;
 
+mod ecn;
+pub use self::ecn::*;
+
 mod arp;
 pub use self::arp::*;
 
@@ -18,3 +27,51 @@ pub use self::udp::*;
 
 mod tcp;
 pub use self::tcp::*;
+
+mod sctp;
+pub use self::sctp::*;
+
+mod flow;
+pub use self::flow::*;
+
+mod icmp;
+pub use self::icmp::*;
+
+mod igmp;
+pub use self::igmp::*;
+
+mod mld;
+pub use self::mld::*;
+
+mod pppoe;
+pub use self::pppoe::*;
+
+mod eapol;
+pub use self::eapol::*;
+
+mod dns;
+pub use self::dns::*;
+
+mod esp;
+pub use self::esp::*;
+
+mod macsec;
+pub use self::macsec::*;
+
+mod gre;
+pub use self::gre::*;
+
+mod erspan;
+pub use self::erspan::*;
+
+mod geneve;
+pub use self::geneve::*;
+
+mod bfd;
+pub use self::bfd::*;
+
+mod vrrp;
+pub use self::vrrp::*;
+
+mod lldp;
+pub use self::lldp::*;
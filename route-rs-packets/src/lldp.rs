@@ -0,0 +1,225 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+/// The group LLDP frames are sent to, which no bridge forwards, IEEE 802.1AB-2016 section 7.1.
+pub const LLDP_NEAREST_BRIDGE_MAC: MacAddr = MacAddr {
+    bytes: [0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e],
+};
+
+/// TLV types, IEEE 802.1AB-2016 section 8.4.
+pub const LLDP_TLV_END: u8 = 0;
+pub const LLDP_TLV_CHASSIS_ID: u8 = 1;
+pub const LLDP_TLV_PORT_ID: u8 = 2;
+pub const LLDP_TLV_TTL: u8 = 3;
+pub const LLDP_TLV_PORT_DESCRIPTION: u8 = 4;
+pub const LLDP_TLV_SYSTEM_NAME: u8 = 5;
+pub const LLDP_TLV_SYSTEM_DESCRIPTION: u8 = 6;
+pub const LLDP_TLV_SYSTEM_CAPABILITIES: u8 = 7;
+pub const LLDP_TLV_MANAGEMENT_ADDRESS: u8 = 8;
+
+/// Chassis ID and port ID subtypes, IEEE 802.1AB-2016 sections 8.5.2.2 and 8.5.3.2.
+pub const LLDP_CHASSIS_ID_MAC_ADDRESS: u8 = 4;
+pub const LLDP_PORT_ID_MAC_ADDRESS: u8 = 3;
+pub const LLDP_PORT_ID_INTERFACE_NAME: u8 = 5;
+
+const TLV_HEADER_LEN: usize = 2;
+
+/// A TLV of an LLDPDU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LldpTlv<'frame> {
+    pub tlv_type: u8,
+    pub value: &'frame [u8],
+}
+
+/// Iterates over the TLVs of an LLDPDU, stopping at the End TLV, or the first that doesn't fit.
+pub struct LldpTlvs<'frame> {
+    data: &'frame [u8],
+}
+
+impl<'frame> Iterator for LldpTlvs<'frame> {
+    type Item = LldpTlv<'frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < TLV_HEADER_LEN {
+            return None;
+        }
+        let header = u16::from_be_bytes(self.data[0..2].try_into().unwrap());
+        let tlv_type = (header >> 9) as u8;
+        let length = usize::from(header & 0x01ff);
+        if tlv_type == LLDP_TLV_END || TLV_HEADER_LEN + length > self.data.len() {
+            self.data = &[];
+            return None;
+        }
+        let tlv = LldpTlv {
+            tlv_type,
+            value: &self.data[TLV_HEADER_LEN..TLV_HEADER_LEN + length],
+        };
+        self.data = &self.data[TLV_HEADER_LEN + length..];
+        Some(tlv)
+    }
+}
+
+///
+/// EthernetFrame wrapper with getters/setters for the TLVs of LLDP frames.
+/// https://standards.ieee.org/standard/802_1AB-2016.html
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LldpFrame {
+    frame: EthernetFrame,
+}
+
+impl LldpFrame {
+    /// Constructs an LLDP frame to the nearest bridge group, with only the End TLV. The Chassis ID,
+    /// Port ID and TTL TLVs must be added first, in that order.
+    pub fn new() -> Self {
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(LLDP_ETHER_TYPE);
+        frame.set_dest_mac(LLDP_NEAREST_BRIDGE_MAC);
+        frame.set_payload(&[0; TLV_HEADER_LEN]);
+        LldpFrame { frame }
+    }
+
+    pub fn tlvs(&self) -> LldpTlvs<'_> {
+        LldpTlvs {
+            data: &self.frame.data[self.frame.payload_offset..],
+        }
+    }
+
+    /// The value of the first TLV of a type.
+    pub fn tlv(&self, tlv_type: u8) -> Option<&[u8]> {
+        self.tlvs()
+            .find(|tlv| tlv.tlv_type == tlv_type)
+            .map(|tlv| tlv.value)
+    }
+
+    /// Adds a TLV before the End TLV.
+    pub fn add_tlv(&mut self, tlv_type: u8, value: &[u8]) -> &mut Self {
+        assert!(tlv_type < 128, "LLDP TLV types are 7 bits");
+        assert!(value.len() < 512, "LLDP TLV values are at most 511 bytes");
+        let end = self.frame.data.len() - TLV_HEADER_LEN;
+        let header = (u16::from(tlv_type) << 9) | value.len() as u16;
+        let mut tlv = header.to_be_bytes().to_vec();
+        tlv.extend_from_slice(value);
+        self.frame.data.splice(end..end, tlv);
+        self
+    }
+
+    /// The subtype and ID of the sending chassis.
+    pub fn chassis_id(&self) -> Option<(u8, &[u8])> {
+        let value = self.tlv(LLDP_TLV_CHASSIS_ID)?;
+        Some((*value.first()?, &value[1..]))
+    }
+
+    /// The subtype and ID of the port the frame was sent from.
+    pub fn port_id(&self) -> Option<(u8, &[u8])> {
+        let value = self.tlv(LLDP_TLV_PORT_ID)?;
+        Some((*value.first()?, &value[1..]))
+    }
+
+    /// How long, in seconds, the receiver keeps what the frame says. 0 means the sender is going
+    /// away, and the receiver should forget it at once.
+    pub fn ttl(&self) -> Option<u16> {
+        let value = self.tlv(LLDP_TLV_TTL)?;
+        Some(u16::from_be_bytes(value.get(0..2)?.try_into().unwrap()))
+    }
+
+    pub fn ethernet(&self) -> &EthernetFrame {
+        &self.frame
+    }
+
+    pub fn ethernet_mut(&mut self) -> &mut EthernetFrame {
+        &mut self.frame
+    }
+
+    // Move ownership of the frame back to the caller
+    pub fn frame(self) -> EthernetFrame {
+        self.frame
+    }
+}
+
+impl Default for LldpFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<EthernetFrame> for LldpFrame {
+    type Error = &'static str;
+
+    ///
+    /// Decorates the given EthernetFrame with LldpFrame getters/setters.
+    /// Validates
+    /// - The frame has the LLDP ether type
+    /// - The LLDPDU starts with the Chassis ID, Port ID and TTL TLVs, in that order
+    ///
+    fn try_from(frame: EthernetFrame) -> Result<Self, Self::Error> {
+        if frame.ether_type() != LLDP_ETHER_TYPE {
+            return Err("Frame does not have LLDP ether type");
+        }
+
+        let lldp = LldpFrame { frame };
+        let types: Vec<u8> = lldp.tlvs().take(3).map(|tlv| tlv.tlv_type).collect();
+        if types != [LLDP_TLV_CHASSIS_ID, LLDP_TLV_PORT_ID, LLDP_TLV_TTL] {
+            return Err("LLDPDU does not start with the Chassis ID, Port ID and TTL TLVs");
+        }
+        if lldp.chassis_id().is_none() || lldp.port_id().is_none() || lldp.ttl().is_none() {
+            return Err("LLDPDU has an empty Chassis ID, Port ID or TTL");
+        }
+        Ok(lldp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lldpdu() -> LldpFrame {
+        let mut lldp = LldpFrame::new();
+        lldp.add_tlv(
+            LLDP_TLV_CHASSIS_ID,
+            &[LLDP_CHASSIS_ID_MAC_ADDRESS, 2, 0, 0, 0, 0, 1],
+        )
+        .add_tlv(LLDP_TLV_PORT_ID, b"\x05eth0")
+        .add_tlv(LLDP_TLV_TTL, &120u16.to_be_bytes())
+        .add_tlv(LLDP_TLV_SYSTEM_NAME, b"edge-router");
+        lldp
+    }
+
+    #[test]
+    fn tlvs_round_trip() {
+        let lldp = LldpFrame::try_from(lldpdu().frame()).unwrap();
+        assert_eq!(lldp.ethernet().dest_mac(), LLDP_NEAREST_BRIDGE_MAC);
+        assert_eq!(
+            lldp.chassis_id(),
+            Some((LLDP_CHASSIS_ID_MAC_ADDRESS, &[2, 0, 0, 0, 0, 1][..]))
+        );
+        assert_eq!(
+            lldp.port_id(),
+            Some((LLDP_PORT_ID_INTERFACE_NAME, &b"eth0"[..]))
+        );
+        assert_eq!(lldp.ttl(), Some(120));
+        assert_eq!(lldp.tlv(LLDP_TLV_SYSTEM_NAME), Some(&b"edge-router"[..]));
+        assert_eq!(lldp.tlvs().count(), 4);
+        // The End TLV stays last.
+        assert_eq!(
+            lldp.ethernet().payload()[lldp.ethernet().payload().len() - 2..],
+            [0, 0]
+        );
+    }
+
+    #[test]
+    fn rejects_missing_mandatory_tlvs() {
+        let mut lldp = LldpFrame::new();
+        lldp.add_tlv(LLDP_TLV_PORT_ID, b"\x05eth0")
+            .add_tlv(
+                LLDP_TLV_CHASSIS_ID,
+                &[LLDP_CHASSIS_ID_MAC_ADDRESS, 2, 0, 0, 0, 0, 1],
+            )
+            .add_tlv(LLDP_TLV_TTL, &120u16.to_be_bytes());
+        assert!(LldpFrame::try_from(lldp.frame()).is_err());
+
+        let mut truncated = lldpdu().frame();
+        truncated.data.truncate(truncated.payload_offset + 12);
+        assert!(LldpFrame::try_from(truncated).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+/// TCI bits of the SecTAG, IEEE 802.1AE-2018 section 9.5. The low 2 bits are the association
+/// number.
+pub const MACSEC_TCI_ES: u8 = 0x40;
+pub const MACSEC_TCI_SC: u8 = 0x20;
+pub const MACSEC_TCI_SCB: u8 = 0x10;
+pub const MACSEC_TCI_E: u8 = 0x08;
+pub const MACSEC_TCI_C: u8 = 0x04;
+
+const MACSEC_TCI_VERSION: u8 = 0x80;
+const SECTAG_LEN: usize = 6;
+const SCI_LEN: usize = 8;
+
+///
+/// EthernetFrame wrapper with getters/setters for the SecTAG of MACsec frames. What follows it,
+/// the secure data and the ICV, depends on the cipher suite of the secure association.
+/// https://standards.ieee.org/standard/802_1AE-2018.html
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacsecFrame {
+    frame: EthernetFrame,
+}
+
+impl MacsecFrame {
+    /// Constructs a MACsec frame with a zeroed SecTAG, without an SCI, and no secure data.
+    pub fn new() -> Self {
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(MACSEC_ETHER_TYPE);
+        frame.set_payload(&[0; SECTAG_LEN]);
+        MacsecFrame { frame }
+    }
+
+    /// The TCI and association number, the first byte of the SecTAG.
+    pub fn tci(&self) -> u8 {
+        self.macsec_data()[0]
+    }
+
+    /// Sets the TCI and association number, other than the SC bit, which says whether the frame
+    /// has an SCI, see `set_sci`.
+    pub fn set_tci(&mut self, tci: u8) -> &mut Self {
+        let sc = self.tci() & MACSEC_TCI_SC;
+        self.macsec_data_mut()[0] = (tci & !MACSEC_TCI_SC & !MACSEC_TCI_VERSION) | sc;
+        self
+    }
+
+    pub fn association_number(&self) -> u8 {
+        self.tci() & 0x03
+    }
+
+    /// The length of the secure data, without the ICV, if it is less than 48 bytes, else 0.
+    pub fn short_length(&self) -> u8 {
+        self.macsec_data()[1] & 0x3f
+    }
+
+    pub fn set_short_length(&mut self, short_length: u8) -> &mut Self {
+        self.macsec_data_mut()[1] = short_length & 0x3f;
+        self
+    }
+
+    pub fn packet_number(&self) -> u32 {
+        u32::from_be_bytes(self.macsec_data()[2..6].try_into().unwrap())
+    }
+
+    pub fn set_packet_number(&mut self, packet_number: u32) -> &mut Self {
+        self.macsec_data_mut()[2..6].copy_from_slice(&packet_number.to_be_bytes());
+        self
+    }
+
+    /// The Secure Channel Identifier, the MAC address and port of the sender, if the frame
+    /// carries it.
+    pub fn sci(&self) -> Option<u64> {
+        if self.tci() & MACSEC_TCI_SC == 0 {
+            return None;
+        }
+        let sci = &self.macsec_data()[SECTAG_LEN..SECTAG_LEN + SCI_LEN];
+        Some(u64::from_be_bytes(sci.try_into().unwrap()))
+    }
+
+    /// Sets the SCI, or removes it, moving the secure data after it along.
+    pub fn set_sci(&mut self, sci: Option<u64>) -> &mut Self {
+        let secure_data = self.secure_data().to_vec();
+        let mut data = self.macsec_data()[..SECTAG_LEN].to_vec();
+        match sci {
+            Some(sci) => {
+                data[0] |= MACSEC_TCI_SC;
+                data.extend_from_slice(&sci.to_be_bytes());
+            }
+            None => data[0] &= !MACSEC_TCI_SC,
+        }
+        data.extend_from_slice(&secure_data);
+        self.frame.set_payload(&data);
+        self
+    }
+
+    /// The length of the SecTAG, with the SCI if the frame carries it.
+    pub fn sectag_len(&self) -> usize {
+        if self.tci() & MACSEC_TCI_SC == 0 {
+            SECTAG_LEN
+        } else {
+            SECTAG_LEN + SCI_LEN
+        }
+    }
+
+    /// The Ethernet header and SecTAG, which are authenticated with the secure data.
+    pub fn header(&self) -> &[u8] {
+        &self.frame.data[self.frame.layer2_offset..self.frame.payload_offset + self.sectag_len()]
+    }
+
+    /// Everything after the SecTAG, the secure data followed by the ICV.
+    pub fn secure_data(&self) -> &[u8] {
+        &self.macsec_data()[self.sectag_len()..]
+    }
+
+    pub fn set_secure_data(&mut self, secure_data: &[u8]) -> &mut Self {
+        let mut data = self.macsec_data()[..self.sectag_len()].to_vec();
+        data.extend_from_slice(secure_data);
+        self.frame.set_payload(&data);
+        self
+    }
+
+    pub fn ethernet(&self) -> &EthernetFrame {
+        &self.frame
+    }
+
+    pub fn ethernet_mut(&mut self) -> &mut EthernetFrame {
+        &mut self.frame
+    }
+
+    // Move ownership of the frame back to the caller
+    pub fn frame(self) -> EthernetFrame {
+        self.frame
+    }
+
+    fn macsec_data(&self) -> &[u8] {
+        &self.frame.data[self.frame.payload_offset..]
+    }
+
+    fn macsec_data_mut(&mut self) -> &mut [u8] {
+        &mut self.frame.data[self.frame.payload_offset..]
+    }
+}
+
+impl Default for MacsecFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<EthernetFrame> for MacsecFrame {
+    type Error = &'static str;
+
+    ///
+    /// Decorates the given EthernetFrame with MacsecFrame getters/setters.
+    /// Validates
+    /// - The frame has the MACsec ether type
+    /// - The SecTAG, and the SCI if the TCI says there is one, fit within the frame
+    /// - The SecTAG is of version 0
+    ///
+    fn try_from(frame: EthernetFrame) -> Result<Self, Self::Error> {
+        if frame.ether_type() != MACSEC_ETHER_TYPE {
+            return Err("Frame does not have MACsec ether type");
+        }
+
+        let payload_len = frame.data.len() - frame.payload_offset;
+        if payload_len < SECTAG_LEN {
+            return Err("Frame payload is too small");
+        }
+
+        let macsec = MacsecFrame { frame };
+        if macsec.tci() & MACSEC_TCI_VERSION != 0 {
+            return Err("Frame has an unknown SecTAG version");
+        }
+        if payload_len < macsec.sectag_len() {
+            return Err("Frame payload is too small for its SCI");
+        }
+
+        Ok(macsec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sectag_with_sci() {
+        let mut macsec = MacsecFrame::new();
+        macsec
+            .set_tci(MACSEC_TCI_E | MACSEC_TCI_C | 2)
+            .set_short_length(20)
+            .set_packet_number(0x0102_0304)
+            .set_secure_data(&[9; 36])
+            .set_sci(Some(0x0200_0000_000a_0001));
+
+        assert_eq!(macsec.association_number(), 2);
+        assert_eq!(
+            macsec.tci(),
+            MACSEC_TCI_SC | MACSEC_TCI_E | MACSEC_TCI_C | 2
+        );
+        assert_eq!(macsec.short_length(), 20);
+        assert_eq!(macsec.packet_number(), 0x0102_0304);
+        assert_eq!(macsec.sci(), Some(0x0200_0000_000a_0001));
+        assert_eq!(macsec.sectag_len(), 14);
+        assert_eq!(macsec.header().len(), 28);
+        assert_eq!(macsec.secure_data(), &[9; 36][..]);
+
+        macsec.set_sci(None);
+        assert_eq!(macsec.sci(), None);
+        assert_eq!(macsec.secure_data(), &[9; 36][..]);
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(IPV4_ETHER_TYPE);
+        frame.set_payload(&[0; 6]);
+        assert!(MacsecFrame::try_from(frame).is_err());
+
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(MACSEC_ETHER_TYPE);
+        frame.set_payload(&[MACSEC_TCI_SC, 0, 0, 0, 0, 1, 0x02]);
+        assert!(MacsecFrame::try_from(frame).is_err());
+
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(MACSEC_ETHER_TYPE);
+        frame.set_payload(&[MACSEC_TCI_VERSION, 0, 0, 0, 0, 1]);
+        assert!(MacsecFrame::try_from(frame).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+use std::net::{IpAddr, Ipv6Addr};
+
+pub const MLD_LISTENER_QUERY: u8 = 130;
+pub const MLD_V1_LISTENER_REPORT: u8 = 131;
+pub const MLD_LISTENER_DONE: u8 = 132;
+pub const MLD_V2_LISTENER_REPORT: u8 = 143;
+
+const ICMPV6_NEXT_HEADER: u8 = 58;
+const MLD_HEADER_LEN: usize = 24;
+const MLD_V2_REPORT_HEADER_LEN: usize = 8;
+
+///
+/// Ipv6Packet wrapper with getters/setters for MLD messages, versions 1 and 2
+/// https://tools.ietf.org/html/rfc2710
+/// https://tools.ietf.org/html/rfc3810
+///
+/// MLD is carried in ICMPv6, whose checksum covers an IPv6 pseudo header. It is not calculated here.
+/// Messages behind IPv6 extension headers, such as the Router Alert option, are not recognised yet.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MldPacket {
+    packet: Ipv6Packet,
+}
+
+impl MldPacket {
+    /// Constructs an MLD message with a zeroed 24 byte header, in an Ipv6Packet with a hop limit
+    /// of 1 as MLD requires.
+    pub fn new() -> Self {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(ICMPV6_NEXT_HEADER);
+        packet.set_hop_limit(1);
+        packet.set_payload(&[0; MLD_HEADER_LEN]);
+        MldPacket { packet }
+    }
+
+    pub fn message_type(&self) -> u8 {
+        self.mld_data()[0]
+    }
+
+    pub fn set_message_type(&mut self, message_type: u8) -> &mut Self {
+        self.mld_data_mut()[0] = message_type;
+        self
+    }
+
+    /// Maximum response delay in milliseconds. Only meaningful in queries.
+    pub fn max_resp_delay(&self) -> u16 {
+        u16::from_be_bytes(self.mld_data()[4..6].try_into().unwrap())
+    }
+
+    pub fn set_max_resp_delay(&mut self, delay: u16) -> &mut Self {
+        self.mld_data_mut()[4..6].copy_from_slice(&delay.to_be_bytes());
+        self
+    }
+
+    /// The address being queried, reported or left. Version 2 reports carry their addresses in
+    /// group records instead, see `group_records`.
+    pub fn multicast_addr(&self) -> Ipv6Addr {
+        Ipv6Addr::from(<[u8; 16]>::try_from(&self.mld_data()[8..24]).unwrap())
+    }
+
+    pub fn set_multicast_addr(&mut self, addr: Ipv6Addr) -> &mut Self {
+        self.mld_data_mut()[8..24].copy_from_slice(&addr.octets());
+        self
+    }
+
+    /// Membership changes announced by this message. Empty for queries.
+    pub fn group_records(&self) -> Vec<MulticastGroupRecord> {
+        match self.message_type() {
+            MLD_V1_LISTENER_REPORT => vec![MulticastGroupRecord {
+                record_type: MODE_IS_EXCLUDE,
+                group: IpAddr::V6(self.multicast_addr()),
+                num_sources: 0,
+            }],
+            MLD_LISTENER_DONE => vec![MulticastGroupRecord {
+                record_type: CHANGE_TO_INCLUDE_MODE,
+                group: IpAddr::V6(self.multicast_addr()),
+                num_sources: 0,
+            }],
+            MLD_V2_LISTENER_REPORT => {
+                let data = self.mld_data();
+                let num_records = u16::from_be_bytes(data[6..8].try_into().unwrap()) as usize;
+                parse_group_records(&data[MLD_V2_REPORT_HEADER_LEN..], num_records, 16)
+            }
+            _ => vec![],
+        }
+    }
+
+    // Move ownership of the packet back to the caller
+    pub fn packet(self) -> Ipv6Packet {
+        self.packet
+    }
+
+    fn mld_data(&self) -> &[u8] {
+        &self.packet.data[self.packet.payload_offset..]
+    }
+
+    fn mld_data_mut(&mut self) -> &mut [u8] {
+        &mut self.packet.data[self.packet.payload_offset..]
+    }
+}
+
+impl Default for MldPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<Ipv6Packet> for MldPacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv6Packet) -> Result<Self, Self::Error> {
+        if packet.next_header() != IpProtocol::IPv6_ICMP {
+            return Err("Packet next header is not ICMPv6");
+        }
+        let payload = packet.payload();
+        let min_len = match payload.first() {
+            Some(&MLD_V2_LISTENER_REPORT) => MLD_V2_REPORT_HEADER_LEN,
+            Some(&MLD_LISTENER_QUERY)
+            | Some(&MLD_V1_LISTENER_REPORT)
+            | Some(&MLD_LISTENER_DONE) => MLD_HEADER_LEN,
+            _ => return Err("ICMPv6 message is not an MLD message"),
+        };
+        if payload.len() < min_len {
+            return Err("Packet payload is too short to be an MLD message");
+        }
+        Ok(MldPacket { packet })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_report_and_done() {
+        let group = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+        let mut mld = MldPacket::new();
+        mld.set_message_type(MLD_V1_LISTENER_REPORT)
+            .set_multicast_addr(group);
+
+        assert_eq!(mld.multicast_addr(), group);
+        let records = mld.group_records();
+        assert!(records[0].is_join());
+        assert_eq!(records[0].group, IpAddr::V6(group));
+
+        mld.set_message_type(MLD_LISTENER_DONE);
+        assert!(mld.group_records()[0].is_leave());
+
+        let packet = mld.packet();
+        assert_eq!(packet.hop_limit(), 1);
+        assert_eq!(packet.next_header(), IpProtocol::IPv6_ICMP);
+    }
+
+    #[test]
+    fn v2_report() {
+        let mut payload = vec![143, 0, 0, 0, 0, 0, 0, 1, 3, 0, 0, 0];
+        payload.extend_from_slice(&Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 2).octets());
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(58);
+        packet.set_payload(&payload);
+
+        let mld = MldPacket::try_from(packet).unwrap();
+        let records = mld.group_records();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].is_leave());
+        assert_eq!(
+            records[0].group,
+            IpAddr::V6(Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 2))
+        );
+    }
+
+    #[test]
+    fn rejects_other_icmpv6() {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(58);
+        packet.set_payload(&[128, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(MldPacket::try_from(packet).is_err());
+
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(17);
+        packet.set_payload(&[131; 24]);
+        assert!(MldPacket::try_from(packet).is_err());
+    }
+}
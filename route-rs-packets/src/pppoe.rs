@@ -0,0 +1,398 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+/// PPPoE codes, RFC 2516 section 5
+pub const PPPOE_SESSION_DATA: u8 = 0x00;
+pub const PADO: u8 = 0x07;
+pub const PADI: u8 = 0x09;
+pub const PADR: u8 = 0x19;
+pub const PADS: u8 = 0x65;
+pub const PADT: u8 = 0xa7;
+
+/// Discovery tag types, RFC 2516 appendix A
+pub const TAG_END_OF_LIST: u16 = 0x0000;
+pub const TAG_SERVICE_NAME: u16 = 0x0101;
+pub const TAG_AC_NAME: u16 = 0x0102;
+pub const TAG_HOST_UNIQ: u16 = 0x0103;
+pub const TAG_AC_COOKIE: u16 = 0x0104;
+
+/// PPP protocol numbers carried in session frames
+pub const PPP_IPV4: u16 = 0x0021;
+pub const PPP_IPCP: u16 = 0x8021;
+pub const PPP_LCP: u16 = 0xc021;
+
+/// LCP and IPCP packet codes, RFC 1661 section 5
+pub const PPP_CONFIGURE_REQUEST: u8 = 1;
+pub const PPP_CONFIGURE_ACK: u8 = 2;
+pub const PPP_CONFIGURE_NAK: u8 = 3;
+pub const PPP_CONFIGURE_REJECT: u8 = 4;
+pub const PPP_TERMINATE_REQUEST: u8 = 5;
+pub const PPP_TERMINATE_ACK: u8 = 6;
+pub const PPP_ECHO_REQUEST: u8 = 9;
+pub const PPP_ECHO_REPLY: u8 = 10;
+
+const PPPOE_VER_TYPE: u8 = 0x11;
+const PPPOE_HEADER_LEN: usize = 6;
+const PPP_CONTROL_HEADER_LEN: usize = 4;
+
+/// A type-length-value element, as found in PPPoE discovery payloads and PPP configure packets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PppoeTag {
+    pub tag_type: u16,
+    pub value: Vec<u8>,
+}
+
+///
+/// EthernetFrame wrapper with getters/setters for PPPoE discovery and session frames
+/// https://tools.ietf.org/html/rfc2516
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PppoeFrame {
+    frame: EthernetFrame,
+}
+
+impl PppoeFrame {
+    /// Constructs a discovery stage frame with the given code and no tags.
+    pub fn discovery(code: u8) -> Self {
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(PPPOE_DISCOVERY_ETHER_TYPE);
+        frame.set_payload(&[PPPOE_VER_TYPE, code, 0, 0, 0, 0]);
+        PppoeFrame { frame }
+    }
+
+    /// Constructs a session stage frame carrying `payload` for the given PPP protocol.
+    pub fn session(session_id: u16, protocol: u16, payload: &[u8]) -> Self {
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(PPPOE_SESSION_ETHER_TYPE);
+        frame.set_payload(&[PPPOE_VER_TYPE, PPPOE_SESSION_DATA, 0, 0, 0, 0]);
+        let mut pppoe = PppoeFrame { frame };
+        pppoe.set_session_id(session_id);
+
+        let mut ppp = Vec::with_capacity(2 + payload.len());
+        ppp.extend_from_slice(&protocol.to_be_bytes());
+        ppp.extend_from_slice(payload);
+        pppoe.set_pppoe_payload(&ppp);
+        pppoe
+    }
+
+    pub fn is_discovery(&self) -> bool {
+        self.frame.ether_type() == PPPOE_DISCOVERY_ETHER_TYPE
+    }
+
+    pub fn code(&self) -> u8 {
+        self.header()[1]
+    }
+
+    pub fn set_code(&mut self, code: u8) -> &mut Self {
+        let offset = self.frame.payload_offset + 1;
+        self.frame.data[offset] = code;
+        self
+    }
+
+    pub fn session_id(&self) -> u16 {
+        u16::from_be_bytes(self.header()[2..4].try_into().unwrap())
+    }
+
+    pub fn set_session_id(&mut self, session_id: u16) -> &mut Self {
+        let offset = self.frame.payload_offset + 2;
+        self.frame.data[offset..offset + 2].copy_from_slice(&session_id.to_be_bytes());
+        self
+    }
+
+    /// Length of the PPPoE payload, as claimed by the header.
+    pub fn length(&self) -> u16 {
+        u16::from_be_bytes(self.header()[4..6].try_into().unwrap())
+    }
+
+    /// The PPPoE payload, without any Ethernet padding that follows it.
+    pub fn pppoe_payload(&self) -> &[u8] {
+        let start = self.frame.payload_offset + PPPOE_HEADER_LEN;
+        &self.frame.data[start..start + self.length() as usize]
+    }
+
+    /// Replaces the PPPoE payload and updates the length field to match.
+    pub fn set_pppoe_payload(&mut self, payload: &[u8]) -> &mut Self {
+        let start = self.frame.payload_offset + PPPOE_HEADER_LEN;
+        self.frame.data.truncate(start);
+        self.frame.data.extend_from_slice(payload);
+        self.frame.data[start - 2..start].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+        self
+    }
+
+    /// Tags of a discovery frame, up to the End-Of-List tag or the first truncated tag.
+    pub fn tags(&self) -> Vec<PppoeTag> {
+        parse_tlvs(self.pppoe_payload(), 2, 4)
+            .into_iter()
+            .take_while(|tag| tag.tag_type != TAG_END_OF_LIST)
+            .collect()
+    }
+
+    /// The value of the first tag of the given type.
+    pub fn tag(&self, tag_type: u16) -> Option<Vec<u8>> {
+        self.tags()
+            .into_iter()
+            .find(|tag| tag.tag_type == tag_type)
+            .map(|tag| tag.value)
+    }
+
+    /// Appends a tag to the payload of a discovery frame.
+    pub fn add_tag(&mut self, tag_type: u16, value: &[u8]) -> &mut Self {
+        let mut payload = self.pppoe_payload().to_vec();
+        payload.extend_from_slice(&tag_type.to_be_bytes());
+        payload.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        payload.extend_from_slice(value);
+        self.set_pppoe_payload(&payload)
+    }
+
+    /// The PPP protocol of a session frame.
+    pub fn ppp_protocol(&self) -> Option<u16> {
+        let payload = self.pppoe_payload();
+        if self.is_discovery() || payload.len() < 2 {
+            return None;
+        }
+        Some(u16::from_be_bytes(payload[0..2].try_into().unwrap()))
+    }
+
+    /// The payload of a session frame, after the PPP protocol field.
+    pub fn ppp_payload(&self) -> &[u8] {
+        let payload = self.pppoe_payload();
+        if self.is_discovery() || payload.len() < 2 {
+            return &[];
+        }
+        &payload[2..]
+    }
+
+    // Move ownership of the frame back to the caller
+    pub fn frame(self) -> EthernetFrame {
+        self.frame
+    }
+
+    fn header(&self) -> &[u8] {
+        let start = self.frame.payload_offset;
+        &self.frame.data[start..start + PPPOE_HEADER_LEN]
+    }
+}
+
+impl TryFrom<EthernetFrame> for PppoeFrame {
+    type Error = &'static str;
+
+    ///
+    /// Decorates the given EthernetFrame with PppoeFrame getters/setters.
+    /// Validates
+    /// - The frame has a PPPoE discovery or session ether type
+    /// - The version and type fields are both 1
+    /// - The length field fits within the frame
+    ///
+    fn try_from(frame: EthernetFrame) -> Result<Self, Self::Error> {
+        let ether_type = frame.ether_type();
+        if ether_type != PPPOE_DISCOVERY_ETHER_TYPE && ether_type != PPPOE_SESSION_ETHER_TYPE {
+            return Err("Frame does not have a PPPoE ether type");
+        }
+
+        let payload_len = frame.data.len() - frame.payload_offset;
+        if payload_len < PPPOE_HEADER_LEN {
+            return Err("Frame payload is too small");
+        }
+
+        let pppoe = PppoeFrame { frame };
+        if pppoe.header()[0] != PPPOE_VER_TYPE {
+            return Err("Frame has an unsupported PPPoE version or type");
+        }
+        if pppoe.length() as usize > payload_len - PPPOE_HEADER_LEN {
+            return Err("Frame payload doesn't match length field");
+        }
+
+        Ok(pppoe)
+    }
+}
+
+/// A Link Control Protocol or IPv4 Control Protocol packet, RFC 1661 section 5 and RFC 1332.
+/// Both protocols share the same packet format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PppControlPacket {
+    pub code: u8,
+    pub identifier: u8,
+    /// Configuration options for the configure codes, or the magic number and data of echoes.
+    pub data: Vec<u8>,
+}
+
+impl PppControlPacket {
+    pub fn new(code: u8, identifier: u8, data: &[u8]) -> Self {
+        PppControlPacket {
+            code,
+            identifier,
+            data: data.to_vec(),
+        }
+    }
+
+    /// Builds a configure packet out of a list of options.
+    pub fn with_options(code: u8, identifier: u8, options: &[PppoeTag]) -> Self {
+        let mut data = vec![];
+        for option in options {
+            data.push(option.tag_type as u8);
+            data.push((option.value.len() + 2) as u8);
+            data.extend_from_slice(&option.value);
+        }
+        PppControlPacket {
+            code,
+            identifier,
+            data,
+        }
+    }
+
+    /// Configuration options, for Configure-Request, Ack, Nak and Reject packets.
+    pub fn options(&self) -> Vec<PppoeTag> {
+        parse_tlvs(&self.data, 1, 2)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = (PPP_CONTROL_HEADER_LEN + self.data.len()) as u16;
+        let mut bytes = vec![self.code, self.identifier];
+        bytes.extend_from_slice(&len.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for PppControlPacket {
+    type Error = &'static str;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < PPP_CONTROL_HEADER_LEN {
+            return Err("Control packet is too short");
+        }
+        let len = u16::from_be_bytes(bytes[2..4].try_into().unwrap()) as usize;
+        if len < PPP_CONTROL_HEADER_LEN || len > bytes.len() {
+            return Err("Control packet has invalid length field");
+        }
+        Ok(PppControlPacket {
+            code: bytes[0],
+            identifier: bytes[1],
+            data: bytes[PPP_CONTROL_HEADER_LEN..len].to_vec(),
+        })
+    }
+}
+
+/// Walks TLVs with `type_len` byte types and `header_len` byte headers. PPPoE tags give the length of
+/// the value alone, while PPP options count their own 2 byte header, which is how the two are told apart.
+fn parse_tlvs(data: &[u8], type_len: usize, header_len: usize) -> Vec<PppoeTag> {
+    let mut tlvs = vec![];
+    let mut offset = 0;
+    while data.len() >= offset + header_len {
+        let (tag_type, value_len) = if type_len == 2 {
+            let tag_type = u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap());
+            let len = u16::from_be_bytes(data[offset + 2..offset + 4].try_into().unwrap());
+            (tag_type, len as usize)
+        } else {
+            let len = data[offset + 1] as usize;
+            if len < header_len {
+                break;
+            }
+            (u16::from(data[offset]), len - header_len)
+        };
+        let start = offset + header_len;
+        if data.len() < start + value_len {
+            break;
+        }
+        tlvs.push(PppoeTag {
+            tag_type,
+            value: data[start..start + value_len].to_vec(),
+        });
+        offset = start + value_len;
+    }
+    tlvs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery_tags() {
+        let mut padi = PppoeFrame::discovery(PADI);
+        padi.add_tag(TAG_SERVICE_NAME, b"")
+            .add_tag(TAG_HOST_UNIQ, &[1, 2, 3, 4]);
+
+        assert!(padi.is_discovery());
+        assert_eq!(padi.code(), PADI);
+        assert_eq!(padi.session_id(), 0);
+        assert_eq!(padi.length(), 12);
+        assert_eq!(padi.tag(TAG_SERVICE_NAME), Some(vec![]));
+        assert_eq!(padi.tag(TAG_HOST_UNIQ), Some(vec![1, 2, 3, 4]));
+        assert_eq!(padi.tag(TAG_AC_COOKIE), None);
+        assert_eq!(padi.ppp_protocol(), None);
+
+        let frame = padi.frame();
+        assert_eq!(frame.ether_type(), PPPOE_DISCOVERY_ETHER_TYPE);
+    }
+
+    #[test]
+    fn session_payload() {
+        let mut pppoe = PppoeFrame::session(0x1234, PPP_IPV4, &[0x45, 0, 0, 20]);
+        assert!(!pppoe.is_discovery());
+        assert_eq!(pppoe.session_id(), 0x1234);
+        assert_eq!(pppoe.length(), 6);
+        assert_eq!(pppoe.ppp_protocol(), Some(PPP_IPV4));
+        assert_eq!(pppoe.ppp_payload(), &[0x45, 0, 0, 20]);
+
+        pppoe.set_code(PADT).set_session_id(7);
+        assert_eq!(pppoe.code(), PADT);
+        assert_eq!(pppoe.session_id(), 7);
+    }
+
+    #[test]
+    fn ignores_ethernet_padding() {
+        let mut frame = PppoeFrame::session(1, PPP_LCP, &[9, 1, 0, 8, 0, 0, 0, 0]).frame();
+        frame.data.extend_from_slice(&[0; 20]);
+
+        let pppoe = PppoeFrame::try_from(frame).unwrap();
+        assert_eq!(pppoe.ppp_payload().len(), 8);
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(ARP_ETHER_TYPE);
+        frame.set_payload(&[0x11, 0, 0, 0, 0, 0]);
+        assert!(PppoeFrame::try_from(frame).is_err());
+
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(PPPOE_DISCOVERY_ETHER_TYPE);
+        frame.set_payload(&[0x11, PADI, 0, 0]);
+        assert!(PppoeFrame::try_from(frame).is_err());
+
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(PPPOE_DISCOVERY_ETHER_TYPE);
+        frame.set_payload(&[0x11, PADI, 0, 0, 0, 4, 1, 1]);
+        assert!(PppoeFrame::try_from(frame).is_err());
+    }
+
+    #[test]
+    fn control_packet_round_trip() {
+        let request = PppControlPacket::with_options(
+            PPP_CONFIGURE_REQUEST,
+            3,
+            &[
+                PppoeTag {
+                    tag_type: 1,
+                    value: 1492u16.to_be_bytes().to_vec(),
+                },
+                PppoeTag {
+                    tag_type: 5,
+                    value: vec![0xde, 0xad, 0xbe, 0xef],
+                },
+            ],
+        );
+        let bytes = request.to_bytes();
+        assert_eq!(&bytes[..4], &[1, 3, 0, 14]);
+
+        let parsed = PppControlPacket::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(parsed, request);
+        let options = parsed.options();
+        assert_eq!(options.len(), 2);
+        assert_eq!(options[0].value, vec![0x05, 0xd4]);
+        assert_eq!(options[1].tag_type, 5);
+
+        assert!(PppControlPacket::try_from(&[1, 3, 0, 20][..]).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+pub const SCTP_DATA: u8 = 0;
+pub const SCTP_INIT: u8 = 1;
+pub const SCTP_INIT_ACK: u8 = 2;
+pub const SCTP_SACK: u8 = 3;
+pub const SCTP_HEARTBEAT: u8 = 4;
+pub const SCTP_HEARTBEAT_ACK: u8 = 5;
+pub const SCTP_ABORT: u8 = 6;
+pub const SCTP_SHUTDOWN: u8 = 7;
+pub const SCTP_SHUTDOWN_ACK: u8 = 8;
+pub const SCTP_COOKIE_ECHO: u8 = 10;
+pub const SCTP_COOKIE_ACK: u8 = 11;
+pub const SCTP_SHUTDOWN_COMPLETE: u8 = 14;
+
+const SCTP_HEADER_LEN: usize = 12;
+const CHUNK_HEADER_LEN: usize = 4;
+
+/// A chunk of an SCTP packet, without its padding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SctpChunk<'packet> {
+    pub chunk_type: u8,
+    pub flags: u8,
+    pub value: &'packet [u8],
+}
+
+/// Iterates over the chunks of an SCTP packet, stopping at the first that doesn't fit in it.
+pub struct SctpChunks<'packet> {
+    data: &'packet [u8],
+}
+
+impl<'packet> Iterator for SctpChunks<'packet> {
+    type Item = SctpChunk<'packet>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < CHUNK_HEADER_LEN {
+            return None;
+        }
+        let length = usize::from(u16::from_be_bytes(self.data[2..4].try_into().unwrap()));
+        if length < CHUNK_HEADER_LEN || length > self.data.len() {
+            self.data = &[];
+            return None;
+        }
+        let chunk = SctpChunk {
+            chunk_type: self.data[0],
+            flags: self.data[1],
+            value: &self.data[CHUNK_HEADER_LEN..length],
+        };
+        let padded = (length + 3) & !3;
+        self.data = &self.data[padded.min(self.data.len())..];
+        Some(chunk)
+    }
+}
+
+/// The CRC32c of `data`, as SCTP checksums packets with, RFC 4960 appendix B.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// An SCTP packet, its common header and the chunks after it.
+/// https://tools.ietf.org/html/rfc4960
+#[derive(Clone, Debug)]
+pub struct SctpPacket {
+    pub data: PacketData,
+    pub layer2_offset: Option<usize>,
+    pub layer3_offset: Option<usize>,
+    pub layer4_offset: usize,
+    pub payload_offset: usize,
+}
+
+impl SctpPacket {
+    pub fn from_buffer(
+        data: PacketData,
+        layer2_offset: Option<usize>,
+        layer3_offset: Option<usize>,
+        layer4_offset: usize,
+    ) -> Result<SctpPacket, &'static str> {
+        if data.len() < layer4_offset + SCTP_HEADER_LEN {
+            return Err("Packet to short to contain valid SCTP Header");
+        }
+
+        if let Some(layer3_offset) = layer3_offset {
+            let protocol = match (data[layer3_offset] & 0xF0) >> 4 {
+                4 => get_ipv4_payload_type(&data, layer3_offset)
+                    .expect("Malformed IPv4 Header in SctpPacket"),
+                6 => get_ipv6_payload_type(&data, layer3_offset)
+                    .expect("Malformed IPv6 Header in SctpPacket"),
+                _ => return Err("IP Header has invalid version number"),
+            };
+            if protocol != IpProtocol::SCTP {
+                return Err("Protocol is incorrect, since it isn't SCTP");
+            }
+        }
+
+        Ok(SctpPacket {
+            data,
+            layer2_offset,
+            layer3_offset,
+            layer4_offset,
+            payload_offset: layer4_offset + SCTP_HEADER_LEN,
+        })
+    }
+
+    /// Make an empty SctpPacket, with no layer 3 header nor chunks.
+    pub fn empty() -> SctpPacket {
+        SctpPacket::from_buffer(vec![0; SCTP_HEADER_LEN], None, None, 0).unwrap()
+    }
+
+    pub fn src_port(&self) -> u16 {
+        u16::from_be_bytes(self.header()[0..2].try_into().unwrap())
+    }
+
+    pub fn set_src_port(&mut self, port: u16) {
+        self.header_mut()[0..2].copy_from_slice(&port.to_be_bytes());
+    }
+
+    pub fn dest_port(&self) -> u16 {
+        u16::from_be_bytes(self.header()[2..4].try_into().unwrap())
+    }
+
+    pub fn set_dest_port(&mut self, port: u16) {
+        self.header_mut()[2..4].copy_from_slice(&port.to_be_bytes());
+    }
+
+    /// The tag the receiver of the packet gave its association with the sender, 0 in INIT
+    /// packets.
+    pub fn verification_tag(&self) -> u32 {
+        u32::from_be_bytes(self.header()[4..8].try_into().unwrap())
+    }
+
+    pub fn set_verification_tag(&mut self, verification_tag: u32) {
+        self.header_mut()[4..8].copy_from_slice(&verification_tag.to_be_bytes());
+    }
+
+    pub fn checksum(&self) -> u32 {
+        u32::from_le_bytes(self.header()[8..12].try_into().unwrap())
+    }
+
+    pub fn calculate_checksum(&self) -> u32 {
+        let mut packet = self.data[self.layer4_offset..].to_vec();
+        packet[8..12].copy_from_slice(&[0; 4]);
+        crc32c(&packet)
+    }
+
+    /// Sets the checksum from the header and chunks, which must be set first.
+    pub fn set_checksum(&mut self) {
+        let checksum = self.calculate_checksum();
+        self.header_mut()[8..12].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    pub fn validate_checksum(&self) -> bool {
+        self.checksum() == self.calculate_checksum()
+    }
+
+    pub fn chunks(&self) -> SctpChunks<'_> {
+        SctpChunks {
+            data: &self.data[self.payload_offset..],
+        }
+    }
+
+    /// Appends a chunk, padded to a multiple of 4 bytes. Does not change the checksum.
+    pub fn add_chunk(&mut self, chunk_type: u8, flags: u8, value: &[u8]) {
+        let length = (CHUNK_HEADER_LEN + value.len()) as u16;
+        self.data.push(chunk_type);
+        self.data.push(flags);
+        self.data.extend_from_slice(&length.to_be_bytes());
+        self.data.extend_from_slice(value);
+        let padding = (4 - value.len() % 4) % 4;
+        self.data.extend_from_slice(&[0; 3][..padding]);
+    }
+
+    fn header(&self) -> &[u8] {
+        &self.data[self.layer4_offset..self.payload_offset]
+    }
+
+    fn header_mut(&mut self) -> &mut [u8] {
+        &mut self.data[self.layer4_offset..self.payload_offset]
+    }
+}
+
+/// SctpPackets are considered the same if they have the same data from the layer 4
+/// header and onward. This function does not consider the data before the start of
+/// the SCTP header.
+impl PartialEq for SctpPacket {
+    fn eq(&self, other: &Self) -> bool {
+        self.data[self.layer4_offset..] == other.data[other.layer4_offset..]
+    }
+}
+
+impl Eq for SctpPacket {}
+
+impl TryFrom<Ipv4Packet> for SctpPacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        SctpPacket::from_buffer(
+            packet.data,
+            packet.layer2_offset,
+            Some(packet.layer3_offset),
+            packet.payload_offset,
+        )
+    }
+}
+
+impl TryFrom<Ipv6Packet> for SctpPacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv6Packet) -> Result<Self, Self::Error> {
+        SctpPacket::from_buffer(
+            packet.data,
+            packet.layer2_offset,
+            Some(packet.layer3_offset),
+            packet.payload_offset,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_and_chunks() {
+        let mut sctp = SctpPacket::empty();
+        sctp.set_src_port(2905);
+        sctp.set_dest_port(2905);
+        sctp.set_verification_tag(0xdead_beef);
+        sctp.add_chunk(SCTP_DATA, 0x03, &[1, 2, 3, 4, 5]);
+        sctp.add_chunk(SCTP_SACK, 0, &[0; 12]);
+        sctp.set_checksum();
+
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(132);
+        packet.set_payload(&sctp.data);
+        let sctp = SctpPacket::try_from(packet).unwrap();
+
+        assert_eq!(sctp.src_port(), 2905);
+        assert_eq!(sctp.verification_tag(), 0xdead_beef);
+        assert!(sctp.validate_checksum());
+        let chunks: Vec<SctpChunk> = sctp.chunks().collect();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].chunk_type, SCTP_DATA);
+        assert_eq!(chunks[0].flags, 0x03);
+        assert_eq!(chunks[0].value, &[1, 2, 3, 4, 5]);
+        assert_eq!(chunks[1].chunk_type, SCTP_SACK);
+        assert_eq!(chunks[1].value.len(), 12);
+    }
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn truncated_chunk_ends_iteration() {
+        let mut sctp = SctpPacket::empty();
+        sctp.add_chunk(SCTP_INIT, 0, &[0; 16]);
+        sctp.data.extend_from_slice(&[SCTP_DATA, 0, 0, 40, 1, 2]);
+        assert_eq!(sctp.chunks().count(), 1);
+
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(17);
+        packet.set_payload(&sctp.data);
+        assert!(SctpPacket::try_from(packet).is_err());
+    }
+}
@@ -44,8 +44,11 @@ impl TcpSegment {
             }
         }
 
-        let payload_offset =
-            layer4_offset + (((data[layer4_offset + 12] & 0xF0) >> 4) as usize * 4);
+        let data_offset = ((data[layer4_offset + 12] & 0xF0) >> 4) as usize;
+        let payload_offset = layer4_offset + (data_offset * 4);
+        if data_offset < 5 || payload_offset > data.len() {
+            return Err("Segment has invalid data offset field");
+        }
 
         Ok(TcpSegment {
             data,
@@ -110,7 +113,7 @@ impl TcpSegment {
     }
 
     pub fn set_acknowledgment_number(&mut self, acknowledgment_number: u32) {
-        self.data[self.layer4_offset + 4..=self.layer4_offset + 7]
+        self.data[self.layer4_offset + 8..=self.layer4_offset + 11]
             .copy_from_slice(&acknowledgment_number.to_be_bytes());
     }
 
@@ -294,6 +297,15 @@ mod tests {
         assert_eq!(segment.payload()[0], 0);
     }
 
+    #[test]
+    fn rejects_invalid_data_offset() {
+        let mut data = vec![0; 20];
+        data[12] = 0x40;
+        assert!(TcpSegment::from_buffer(data.clone(), None, None, 0).is_err());
+        data[12] = 0x60;
+        assert!(TcpSegment::from_buffer(data, None, None, 0).is_err());
+    }
+
     #[test]
     fn empty() {
         let empty_segment = TcpSegment::empty();
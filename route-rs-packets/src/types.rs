@@ -4,6 +4,18 @@ use std::fmt;
 pub const IPV4_ETHER_TYPE: u16 = 0x0800;
 pub const IPV6_ETHER_TYPE: u16 = 0x86DD;
 pub const ARP_ETHER_TYPE: u16 = 0x0806;
+pub const PPPOE_DISCOVERY_ETHER_TYPE: u16 = 0x8863;
+pub const PPPOE_SESSION_ETHER_TYPE: u16 = 0x8864;
+pub const EAPOL_ETHER_TYPE: u16 = 0x888E;
+pub const MACSEC_ETHER_TYPE: u16 = 0x88E5;
+pub const TRANSPARENT_ETHER_BRIDGING: u16 = 0x6558;
+pub const ERSPAN_II_ETHER_TYPE: u16 = 0x88BE;
+pub const ERSPAN_III_ETHER_TYPE: u16 = 0x22EB;
+pub const LLDP_ETHER_TYPE: u16 = 0x88CC;
+pub const VLAN_CTAG_TPID: u16 = 0x8100;
+pub const VLAN_STAG_TPID: u16 = 0x88A8;
+/// The TPID S-tags had before 802.1ad, which some switches still use.
+pub const VLAN_QINQ_TPID: u16 = 0x9100;
 
 /// The common datatype that all packet structures share to repreasent their data
 pub type PacketData = Vec<u8>;
@@ -0,0 +1,146 @@
+use crate::*;
+use std::convert::TryInto;
+
+/// The TPIDs of the tags an `EthernetFrame` recognizes as it is parsed.
+pub const DEFAULT_VLAN_TPIDS: [u16; 3] = [VLAN_CTAG_TPID, VLAN_STAG_TPID, VLAN_QINQ_TPID];
+
+const VLAN_TAG_LEN: usize = 4;
+
+/// An 802.1Q C-tag or 802.1ad S-tag, which goes after the source MAC address of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VlanTag {
+    /// The Tag Protocol Identifier, 0x8100 for C-tags and 0x88a8 for S-tags, unless configured
+    /// otherwise.
+    pub tpid: u16,
+    /// The Priority Code Point, 3 bits.
+    pub pcp: u8,
+    /// The Drop Eligible Indicator.
+    pub dei: bool,
+    /// The VLAN Identifier, 12 bits.
+    pub vid: u16,
+}
+
+impl VlanTag {
+    /// A tag of VLAN `vid`, with priority 0.
+    pub fn new(tpid: u16, vid: u16) -> Self {
+        assert!(vid < 0x1000, "VLAN IDs are 12 bits");
+        VlanTag {
+            tpid,
+            pcp: 0,
+            dei: false,
+            vid,
+        }
+    }
+
+    /// The Tag Control Information, the PCP, DEI and VID.
+    pub fn tci(&self) -> u16 {
+        (u16::from(self.pcp & 0x07) << 13) | (u16::from(self.dei) << 12) | (self.vid & 0x0fff)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let tci = u16::from_be_bytes(bytes[2..4].try_into().unwrap());
+        VlanTag {
+            tpid: u16::from_be_bytes(bytes[0..2].try_into().unwrap()),
+            pcp: (tci >> 13) as u8,
+            dei: tci & 0x1000 != 0,
+            vid: tci & 0x0fff,
+        }
+    }
+}
+
+impl EthernetFrame {
+    /// The VLAN tags of the frame, outermost first.
+    pub fn vlan_tags(&self) -> Vec<VlanTag> {
+        self.data[self.layer2_offset + 12..self.payload_offset - 2]
+            .chunks(VLAN_TAG_LEN)
+            .map(VlanTag::from_bytes)
+            .collect()
+    }
+
+    /// Recognizes more tags, after those already recognized, whose TPIDs are among `tpids`. Frames
+    /// are parsed with the `DEFAULT_VLAN_TPIDS`, so this is only needed for other TPIDs, of
+    /// switches configured with their own.
+    pub fn parse_vlan_tags(&mut self, tpids: &[u16]) {
+        while self.payload_offset + VLAN_TAG_LEN <= self.data.len() {
+            let tpid = self.ether_type();
+            if !tpids.contains(&tpid) {
+                break;
+            }
+            self.payload_offset += VLAN_TAG_LEN;
+        }
+    }
+
+    /// Adds a tag outside the others, as an 802.1ad S-tag goes outside the C-tag.
+    pub fn push_vlan_tag(&mut self, tag: VlanTag) {
+        let at = self.layer2_offset + 12;
+        let mut bytes = tag.tpid.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&tag.tci().to_be_bytes());
+        self.data.splice(at..at, bytes);
+        self.payload_offset += VLAN_TAG_LEN;
+    }
+
+    /// Removes the outermost tag, if the frame has any.
+    pub fn pop_vlan_tag(&mut self) -> Option<VlanTag> {
+        let at = self.layer2_offset + 12;
+        if self.payload_offset - 2 == at {
+            return None;
+        }
+        let tag = VlanTag::from_bytes(&self.data[at..at + VLAN_TAG_LEN]);
+        self.data.drain(at..at + VLAN_TAG_LEN);
+        self.payload_offset -= VLAN_TAG_LEN;
+        Some(tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn push_and_pop_tags() {
+        let mut frame = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
+        frame.set_dest_mac(MacAddr::new([2, 0, 0, 0, 0, 1]));
+        let untagged = frame.clone();
+
+        frame.push_vlan_tag(VlanTag::new(VLAN_CTAG_TPID, 10));
+        let mut s_tag = VlanTag::new(VLAN_STAG_TPID, 200);
+        s_tag.pcp = 5;
+        frame.push_vlan_tag(s_tag);
+        assert_eq!(
+            frame.vlan_tags(),
+            vec![s_tag, VlanTag::new(VLAN_CTAG_TPID, 10)]
+        );
+        assert_eq!(frame.ether_type(), IPV4_ETHER_TYPE);
+        assert_eq!(frame.dest_mac(), MacAddr::new([2, 0, 0, 0, 0, 1]));
+        assert_eq!(frame.payload(), untagged.payload());
+
+        // The tags are found again when the frame is parsed, and carried through an Ipv4Packet.
+        let reparsed = EthernetFrame::from_buffer(frame.data.clone(), 0).unwrap();
+        assert_eq!(reparsed.payload_offset, 22);
+        let packet = Ipv4Packet::try_from(reparsed).unwrap();
+        let reparsed = EthernetFrame::try_from(packet).unwrap();
+        assert_eq!(reparsed.vlan_tags().len(), 2);
+
+        assert_eq!(frame.pop_vlan_tag(), Some(s_tag));
+        assert_eq!(frame.pop_vlan_tag(), Some(VlanTag::new(VLAN_CTAG_TPID, 10)));
+        assert_eq!(frame.pop_vlan_tag(), None);
+        assert_eq!(frame, untagged);
+    }
+
+    #[test]
+    fn configured_tpids() {
+        let mut frame = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
+        frame.push_vlan_tag(VlanTag::new(VLAN_CTAG_TPID, 10));
+        frame.push_vlan_tag(VlanTag::new(0x9200, 300));
+
+        let mut frame = EthernetFrame::from_buffer(frame.data, 0).unwrap();
+        assert_eq!(frame.vlan_tags(), vec![]);
+        assert_eq!(frame.ether_type(), 0x9200);
+
+        frame.parse_vlan_tags(&[0x9200, VLAN_CTAG_TPID]);
+        assert_eq!(frame.vlan_tags()[0].vid, 300);
+        assert_eq!(frame.vlan_tags()[1].vid, 10);
+        assert_eq!(frame.ether_type(), IPV4_ETHER_TYPE);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+use std::net::Ipv4Addr;
+
+/// The group VRRP advertisements are sent to, RFC 5798 section 5.1.1.2.
+pub const VRRP_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 18);
+
+/// The priority of the router that owns the virtual addresses, and of a master giving up.
+pub const VRRP_OWNER_PRIORITY: u8 = 255;
+pub const VRRP_RESIGN_PRIORITY: u8 = 0;
+
+const VRRP_PROTOCOL: u8 = 112;
+const VRRP_HEADER_LEN: usize = 8;
+const VRRP_VERSION: u8 = 3;
+const VRRP_ADVERTISEMENT: u8 = 1;
+
+/// The MAC address of the virtual router with `vrid`, which its master answers for,
+/// RFC 5798 section 7.3.
+pub fn vrrp_virtual_mac(vrid: u8) -> MacAddr {
+    MacAddr::new([0x00, 0x00, 0x5e, 0x00, 0x01, vrid])
+}
+
+///
+/// Ipv4Packet wrapper with getters/setters for VRRP version 3 advertisements of IPv4 addresses.
+/// https://tools.ietf.org/html/rfc5798
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VrrpPacket {
+    packet: Ipv4Packet,
+}
+
+impl VrrpPacket {
+    /// Constructs an advertisement of VRID 0 and priority 0, with no addresses, to the VRRP group
+    /// with a TTL of 255 as VRRP requires.
+    pub fn new() -> Self {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(VRRP_PROTOCOL);
+        packet.set_ttl(255);
+        packet.set_dest_addr(VRRP_MULTICAST_ADDR);
+        packet.set_payload(&[0; VRRP_HEADER_LEN]);
+        let mut vrrp = VrrpPacket { packet };
+        vrrp.vrrp_data_mut()[0] = (VRRP_VERSION << 4) | VRRP_ADVERTISEMENT;
+        vrrp
+    }
+
+    pub fn version(&self) -> u8 {
+        self.vrrp_data()[0] >> 4
+    }
+
+    /// The Virtual Router Identifier.
+    pub fn vrid(&self) -> u8 {
+        self.vrrp_data()[1]
+    }
+
+    pub fn set_vrid(&mut self, vrid: u8) -> &mut Self {
+        self.vrrp_data_mut()[1] = vrid;
+        self
+    }
+
+    /// The sender's priority, 255 if it owns the addresses, and 0 if it is giving up being master.
+    pub fn priority(&self) -> u8 {
+        self.vrrp_data()[2]
+    }
+
+    pub fn set_priority(&mut self, priority: u8) -> &mut Self {
+        self.vrrp_data_mut()[2] = priority;
+        self
+    }
+
+    /// The interval between the master's advertisements, in centiseconds, 12 bits.
+    pub fn max_advert_interval(&self) -> u16 {
+        u16::from_be_bytes(self.vrrp_data()[4..6].try_into().unwrap()) & 0x0fff
+    }
+
+    pub fn set_max_advert_interval(&mut self, interval: u16) -> &mut Self {
+        self.vrrp_data_mut()[4..6].copy_from_slice(&(interval & 0x0fff).to_be_bytes());
+        self
+    }
+
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes(self.vrrp_data()[6..8].try_into().unwrap())
+    }
+
+    /// The checksum of the advertisement, with the IPv4 pseudo-header as version 3 sums it.
+    pub fn calculate_checksum(&self) -> u16 {
+        let message = self.vrrp_data();
+        let mut data = Vec::with_capacity(12 + message.len());
+        data.extend_from_slice(&self.packet.src_addr().octets());
+        data.extend_from_slice(&self.packet.dest_addr().octets());
+        data.extend_from_slice(&[0, VRRP_PROTOCOL]);
+        data.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        data.extend_from_slice(&message[..6]);
+        data.extend_from_slice(&message[8..]);
+        internet_checksum(&data)
+    }
+
+    /// Sets the checksum from the addresses of the packet and the advertisement, which must be set
+    /// first.
+    pub fn set_checksum(&mut self) -> &mut Self {
+        let checksum = self.calculate_checksum();
+        self.vrrp_data_mut()[6..8].copy_from_slice(&checksum.to_be_bytes());
+        self
+    }
+
+    pub fn validate_checksum(&self) -> bool {
+        self.checksum() == self.calculate_checksum()
+    }
+
+    /// The virtual addresses the virtual router answers for.
+    pub fn addresses(&self) -> Vec<Ipv4Addr> {
+        let count = usize::from(self.vrrp_data()[3]);
+        self.vrrp_data()[VRRP_HEADER_LEN..VRRP_HEADER_LEN + count * 4]
+            .chunks(4)
+            .map(|addr| Ipv4Addr::from(<[u8; 4]>::try_from(addr).unwrap()))
+            .collect()
+    }
+
+    pub fn set_addresses(&mut self, addresses: &[Ipv4Addr]) -> &mut Self {
+        assert!(addresses.len() <= 255, "VRRP carries at most 255 addresses");
+        let mut data = self.vrrp_data()[..VRRP_HEADER_LEN].to_vec();
+        data[3] = addresses.len() as u8;
+        for addr in addresses {
+            data.extend_from_slice(&addr.octets());
+        }
+        self.packet.set_payload(&data);
+        self
+    }
+
+    pub fn ipv4(&self) -> &Ipv4Packet {
+        &self.packet
+    }
+
+    pub fn ipv4_mut(&mut self) -> &mut Ipv4Packet {
+        &mut self.packet
+    }
+
+    // Move ownership of the packet back to the caller
+    pub fn packet(self) -> Ipv4Packet {
+        self.packet
+    }
+
+    fn vrrp_data(&self) -> &[u8] {
+        &self.packet.data[self.packet.payload_offset..]
+    }
+
+    fn vrrp_data_mut(&mut self) -> &mut [u8] {
+        &mut self.packet.data[self.packet.payload_offset..]
+    }
+}
+
+impl Default for VrrpPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<Ipv4Packet> for VrrpPacket {
+    type Error = &'static str;
+
+    ///
+    /// Decorates the given Ipv4Packet with VrrpPacket getters/setters.
+    /// Validates
+    /// - The packet is VRRP
+    /// - The message is a version 3 advertisement, with all its addresses within the packet
+    ///
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        if packet.protocol() != IpProtocol::VRRP {
+            return Err("Packet protocol is not VRRP");
+        }
+        if packet.payload().len() < VRRP_HEADER_LEN {
+            return Err("Packet payload is too short to be a VRRP advertisement");
+        }
+        let vrrp = VrrpPacket { packet };
+        if vrrp.version() != VRRP_VERSION || vrrp.vrrp_data()[0] & 0x0f != VRRP_ADVERTISEMENT {
+            return Err("VRRP message is not a version 3 advertisement");
+        }
+        if vrrp.vrrp_data().len() < VRRP_HEADER_LEN + usize::from(vrrp.vrrp_data()[3]) * 4 {
+            return Err("Packet payload is too short for its VRRP addresses");
+        }
+        Ok(vrrp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advertisement_fields() {
+        let addresses = [Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2)];
+        let mut vrrp = VrrpPacket::new();
+        vrrp.ipv4_mut().set_src_addr(Ipv4Addr::new(192, 0, 2, 10));
+        vrrp.set_vrid(7)
+            .set_priority(200)
+            .set_max_advert_interval(100)
+            .set_addresses(&addresses)
+            .set_checksum();
+
+        let vrrp = VrrpPacket::try_from(vrrp.packet()).unwrap();
+        assert_eq!(vrrp.version(), 3);
+        assert_eq!(vrrp.vrid(), 7);
+        assert_eq!(vrrp.priority(), 200);
+        assert_eq!(vrrp.max_advert_interval(), 100);
+        assert_eq!(vrrp.addresses(), addresses.to_vec());
+        assert!(vrrp.validate_checksum());
+        assert_eq!(vrrp.ipv4().dest_addr(), VRRP_MULTICAST_ADDR);
+        assert_eq!(vrrp.ipv4().ttl(), 255);
+        assert_eq!(vrrp_virtual_mac(7).bytes, [0, 0, 0x5e, 0, 1, 7]);
+    }
+
+    #[test]
+    fn rejects_truncated_addresses() {
+        let mut vrrp = VrrpPacket::new();
+        vrrp.set_addresses(&[Ipv4Addr::new(192, 0, 2, 1)]);
+        let mut packet = vrrp.packet();
+        let payload = packet.payload()[..10].to_vec();
+        packet.set_payload(&payload);
+        assert!(VrrpPacket::try_from(packet).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::classifier::Classifier;
+use crate::link::message::RouterMessage;
+
+/// Runs a classifier on a stream of `RouterMessage`s. Packets are classified by the wrapped
+/// classifier, so a `ClassifyLink` can keep its dispatcher, and control messages are broadcast to
+/// every egressor.
+pub struct ControlAwareClassifier<C: Classifier> {
+    classifier: C,
+}
+
+impl<C: Classifier> ControlAwareClassifier<C> {
+    pub fn new(classifier: C) -> Self {
+        ControlAwareClassifier { classifier }
+    }
+}
+
+impl<C: Classifier> Classifier for ControlAwareClassifier<C> {
+    type Packet = RouterMessage<C::Packet>;
+    type Class = C::Class;
+
+    fn classify(&self, message: &Self::Packet) -> Self::Class {
+        match message {
+            RouterMessage::Data(packet) => self.classifier.classify(packet),
+            RouterMessage::Control(_) => {
+                unreachable!("Control messages are broadcast without being classified")
+            }
+        }
+    }
+
+    fn broadcast(&self, message: &Self::Packet) -> bool {
+        message.is_control()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::Even;
+    use crate::link::message::ControlMsg;
+    use crate::link::primitive::ClassifyLink;
+    use crate::link::LinkBuilder;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    fn broadcasts_control_messages() {
+        let messages = vec![
+            RouterMessage::Data(1),
+            RouterMessage::Data(2),
+            RouterMessage::Control(ControlMsg::Barrier(1)),
+            RouterMessage::Data(4),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ClassifyLink::new()
+                .ingressor(immediate_stream(messages))
+                .num_egressors(2)
+                .classifier(ControlAwareClassifier::new(Even::new()))
+                .dispatcher(Box::new(|is_even| if is_even { 0 } else { 1 }))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(
+            results[0],
+            vec![
+                RouterMessage::Data(2),
+                RouterMessage::Control(ControlMsg::Barrier(1)),
+                RouterMessage::Data(4),
+            ]
+        );
+        assert_eq!(
+            results[1],
+            vec![
+                RouterMessage::Data(1),
+                RouterMessage::Control(ControlMsg::Barrier(1)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use crate::classifier::Classifier;
+use crate::link::{primitive::ClassifyLink, Link, LinkBuilder, PacketStream};
+use route_rs_packets::{EthernetFrame, EAPOL_ETHER_TYPE, EAPOL_PAE_GROUP_ADDR};
+
+pub enum EapolClass {
+    Eapol,
+    Other,
+}
+
+/// Picks out 802.1X EAPOL frames, so they can be diverted to the host rather than forwarded
+/// or dropped with the rest of the traffic. Frames sent to the PAE group address are always
+/// treated as EAPOL, since nothing else may be sent there.
+#[derive(Default)]
+pub struct ClassifyEapol {}
+
+impl ClassifyEapol {
+    pub fn new() -> Self {
+        ClassifyEapol {}
+    }
+}
+
+impl Classifier for ClassifyEapol {
+    type Packet = EthernetFrame;
+    type Class = EapolClass;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        if frame.ether_type() == EAPOL_ETHER_TYPE || frame.dest_mac() == EAPOL_PAE_GROUP_ADDR {
+            EapolClass::Eapol
+        } else {
+            EapolClass::Other
+        }
+    }
+}
+
+/// EAPOL frames leave on egressor 0, and everything else on egressor 1.
+pub fn eapol_link(stream: PacketStream<EthernetFrame>) -> Link<EthernetFrame> {
+    ClassifyLink::new()
+        .ingressor(stream)
+        .num_egressors(2)
+        .classifier(ClassifyEapol::new())
+        .dispatcher(Box::new(|class| match class {
+            EapolClass::Eapol => 0,
+            EapolClass::Other => 1,
+        }))
+        .build_link()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::{EapolFrame, Ipv4Packet, UdpSegment, EAPOL_START};
+
+    #[test]
+    fn diverts_eapol() {
+        let eapol = EapolFrame::new(EAPOL_START).frame();
+        let ipv4 = EthernetFrame::encap_ipv4(Ipv4Packet::encap_udp(UdpSegment::empty()));
+        let packets = vec![ipv4.clone(), eapol.clone(), ipv4.clone()];
+
+        let mut runtime = initialize_runtime();
+        let results =
+            runtime.block_on(async { run_link(eapol_link(immediate_stream(packets))).await });
+
+        assert_eq!(results[0], vec![eapol]);
+        assert_eq!(results[1], vec![ipv4.clone(), ipv4]);
+    }
+}
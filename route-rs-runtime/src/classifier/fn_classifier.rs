@@ -0,0 +1,77 @@
+use crate::classifier::Classifier;
+use std::marker::PhantomData;
+
+/// Classifier that classifies each packet with a closure, for classifications and test fixtures
+/// too small to be worth a struct and impl of their own.
+///
+/// ```
+/// use route_rs_runtime::classifier::{Classifier, FnClassifier};
+///
+/// let small = FnClassifier::new(|packet: &i32| *packet < 10);
+/// assert!(small.classify(&7));
+/// ```
+///
+/// Like an `FnProcessor`, it can be placed in a dynamic pipeline or a graphgen pipeline by the
+/// name of a factory building it.
+pub struct FnClassifier<P, C, F> {
+    classify: F,
+    phantom: PhantomData<fn(&P) -> C>,
+}
+
+impl<P, C, F: Fn(&P) -> C> FnClassifier<P, C, F> {
+    pub fn new(classify: F) -> Self {
+        FnClassifier {
+            classify,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<P: Send + Clone, C, F: Fn(&P) -> C> Classifier for FnClassifier<P, C, F> {
+    type Packet = P;
+    type Class = C;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        (self.classify)(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic::ProcessorRegistry;
+    use crate::link::primitive::ClassifyLink;
+    use crate::link::LinkBuilder;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    fn dispatches_by_closure() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ClassifyLink::new()
+                .ingressor(immediate_stream(0..10))
+                .num_egressors(3)
+                .classifier(FnClassifier::new(|packet: &i32| packet % 3))
+                .dispatcher(Box::new(|class| class as usize))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 3, 6, 9]);
+        assert_eq!(results[1], vec![1, 4, 7]);
+        assert_eq!(results[2], vec![2, 5, 8]);
+    }
+
+    #[test]
+    fn registers_by_class_name() {
+        let mut registry = ProcessorRegistry::new();
+        registry.register_classifier("Small", |_| {
+            Ok(FnClassifier::new(|packet: &i32| *packet < 10))
+        });
+
+        let small = registry.classifier("Small", &[]).unwrap();
+        assert_eq!(small.classify(&7), "true");
+        assert_eq!(small.classify(&70), "false");
+    }
+}
@@ -10,6 +10,27 @@ pub use self::even::*;
 mod fizz_buzz;
 pub use self::fizz_buzz::*;
 
+mod fn_classifier;
+pub use self::fn_classifier::*;
+
+mod eapol;
+pub use self::eapol::*;
+
+mod control_aware;
+pub use self::control_aware::*;
+
+mod policy_router;
+pub use self::policy_router::*;
+
+mod transport;
+pub use self::transport::*;
+
+mod quic;
+pub use self::quic::*;
+
+mod vlan;
+pub use self::vlan::*;
+
 /// Used by a ClassifyLink to determine the kind of packet we have. Classifier::Class is then
 /// consumed by the dispatcher on the ClassifyLink to send it down the appropriate path.
 pub trait Classifier {
@@ -17,4 +38,21 @@ pub trait Classifier {
     type Class: Sized;
 
     fn classify(&self, packet: &Self::Packet) -> Self::Class;
+
+    /// Whether a packet should be sent to every egressor of the ClassifyLink, rather than
+    /// classified and dispatched, as control messages are.
+    fn broadcast(&self, _packet: &Self::Packet) -> bool {
+        false
+    }
+}
+
+/// A Class whose values each go to one egressor of a ClassifyLink, which `ClassifyLink::dispatch`
+/// uses in place of a dispatcher and a number of egressors. `#[derive(Dispatch)]`, from
+/// route-rs-derive, implements it for enums, with an egressor for each variant.
+pub trait Dispatch {
+    /// The number of egressors the classes go to.
+    const PORTS: usize;
+
+    /// The egressor a class goes to, less than PORTS.
+    fn port(&self) -> usize;
 }
@@ -0,0 +1,133 @@
+use crate::classifier::Classifier;
+use crate::config::Subnet;
+use route_rs_packets::Ipv4Packet;
+use std::net::IpAddr;
+
+/// Sends the packets it matches to a branch, whatever their destination. Each condition left
+/// unset matches every packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyRule {
+    pub ingress: Option<usize>,
+    pub source: Option<Subnet>,
+    pub dscp: Option<u8>,
+    pub branch: usize,
+}
+
+impl PolicyRule {
+    /// A rule sending every packet to `branch`, until conditions are added.
+    pub fn to(branch: usize) -> Self {
+        PolicyRule {
+            ingress: None,
+            source: None,
+            dscp: None,
+            branch,
+        }
+    }
+
+    /// Matches only packets that arrived on the ingressor at `ingress`.
+    pub fn ingress(self, ingress: usize) -> Self {
+        PolicyRule {
+            ingress: Some(ingress),
+            source: self.source,
+            dscp: self.dscp,
+            branch: self.branch,
+        }
+    }
+
+    /// Matches only packets from an address in `source`.
+    pub fn source(self, source: Subnet) -> Self {
+        PolicyRule {
+            ingress: self.ingress,
+            source: Some(source),
+            dscp: self.dscp,
+            branch: self.branch,
+        }
+    }
+
+    /// Matches only packets marked with `dscp`.
+    pub fn dscp(self, dscp: u8) -> Self {
+        assert!(dscp < 64, "dscp must be < 64");
+        PolicyRule {
+            ingress: self.ingress,
+            source: self.source,
+            dscp: Some(dscp),
+            branch: self.branch,
+        }
+    }
+
+    /// Whether the rule matches `packet`, arrived on the ingressor at `ingress`.
+    pub fn matches(&self, ingress: usize, packet: &Ipv4Packet) -> bool {
+        self.ingress.is_none_or(|rule| rule == ingress)
+            && self.dscp.is_none_or(|dscp| dscp == packet.dscp())
+            && self
+                .source
+                .as_ref()
+                .is_none_or(|source| source.contains(IpAddr::V4(packet.src_addr())))
+    }
+}
+
+/// Routes IPv4 packets by policy, ahead of the routing table: by the interface they arrived on,
+/// their source, or their DSCP, as when keeping a guest VLAN off the LAN, or steering some hosts
+/// out of a second WAN.
+///
+/// The router classifies the packets of one ingress interface, `ingress`. The first of its rules
+/// to match a packet gives its branch, and a packet none match is classified as `None`, to be
+/// looked up in the routing table as usual.
+pub struct PolicyRouter {
+    ingress: usize,
+    rules: Vec<PolicyRule>,
+}
+
+impl PolicyRouter {
+    pub fn new(ingress: usize, rules: Vec<PolicyRule>) -> Self {
+        PolicyRouter { ingress, rules }
+    }
+}
+
+impl Classifier for PolicyRouter {
+    type Packet = Ipv4Packet;
+    type Class = Option<usize>;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(self.ingress, packet))
+            .map(|rule| rule.branch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn packet(src_addr: Ipv4Addr, dscp: u8) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(src_addr);
+        packet.set_dest_addr(Ipv4Addr::new(198, 51, 100, 1));
+        packet.set_dscp(dscp);
+        packet
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = vec![
+            PolicyRule::to(0).ingress(1),
+            PolicyRule::to(1)
+                .source("10.0.2.0/24".parse().unwrap())
+                .dscp(46),
+            PolicyRule::to(2).source("10.0.0.0/16".parse().unwrap()),
+        ];
+        let lan = PolicyRouter::new(0, rules.clone());
+        let guest = PolicyRouter::new(1, rules);
+
+        let voice = packet(Ipv4Addr::new(10, 0, 2, 5), 46);
+        assert_eq!(lan.classify(&voice), Some(1));
+        assert_eq!(guest.classify(&voice), Some(0));
+        assert_eq!(
+            lan.classify(&packet(Ipv4Addr::new(10, 0, 2, 5), 0)),
+            Some(2)
+        );
+        assert_eq!(lan.classify(&packet(Ipv4Addr::new(10, 1, 0, 1), 46)), None);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::classifier::Classifier;
+use route_rs_packets::{FlowKey, IpProtocol, Ipv4Packet};
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryInto;
+use std::hash::{Hash, Hasher};
+
+/// The longest connection ID of QUIC version 1, RFC 9000 section 17.2.
+const MAX_CONNECTION_ID_LEN: usize = 20;
+
+/// What a QUIC packet's header says of it, RFC 9000 section 17.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuicClass {
+    /// A long header, sent while a connection is set up, with its version, the packet type of the
+    /// first byte, and destination connection ID. Version negotiation packets have version 0.
+    Long {
+        version: u32,
+        packet_type: u8,
+        dcid: Vec<u8>,
+    },
+    /// A short header, sent once a connection is set up, with its destination connection ID.
+    Short {
+        dcid: Vec<u8>,
+    },
+    NotQuic,
+}
+
+impl QuicClass {
+    pub fn dcid(&self) -> Option<&[u8]> {
+        match self {
+            QuicClass::Long { dcid, .. } | QuicClass::Short { dcid } => Some(dcid),
+            QuicClass::NotQuic => None,
+        }
+    }
+}
+
+/// Recognizes QUIC packets, UDP to or from one of `ports`, 443 by default, whose first byte has
+/// the fixed bit set. The length of connection IDs isn't given in short headers, so the classifier
+/// is told the length the servers behind it choose, `short_dcid_len`, 8 bytes by default.
+#[derive(Debug, Clone)]
+pub struct ClassifyQuic {
+    ports: Vec<u16>,
+    short_dcid_len: usize,
+}
+
+impl Default for ClassifyQuic {
+    fn default() -> Self {
+        ClassifyQuic::new()
+    }
+}
+
+impl ClassifyQuic {
+    pub fn new() -> Self {
+        ClassifyQuic {
+            ports: vec![443],
+            short_dcid_len: 8,
+        }
+    }
+
+    /// Changes ports, the UDP ports QUIC is recognized on, default value is 443.
+    pub fn ports(self, ports: Vec<u16>) -> Self {
+        ClassifyQuic {
+            ports,
+            short_dcid_len: self.short_dcid_len,
+        }
+    }
+
+    /// Changes short_dcid_len, the length of the connection IDs of short headers, default value
+    /// is 8.
+    pub fn short_dcid_len(self, short_dcid_len: usize) -> Self {
+        assert!(
+            short_dcid_len <= MAX_CONNECTION_ID_LEN,
+            "short_dcid_len must be <= 20"
+        );
+        ClassifyQuic {
+            ports: self.ports,
+            short_dcid_len,
+        }
+    }
+
+    /// The header of the QUIC packet carried in a UDP payload.
+    fn parse(&self, payload: &[u8]) -> QuicClass {
+        let first = match payload.first() {
+            Some(first) => *first,
+            None => return QuicClass::NotQuic,
+        };
+        if first & 0x80 == 0 {
+            if first & 0x40 == 0 || payload.len() < 1 + self.short_dcid_len {
+                return QuicClass::NotQuic;
+            }
+            return QuicClass::Short {
+                dcid: payload[1..1 + self.short_dcid_len].to_vec(),
+            };
+        }
+
+        if payload.len() < 6 {
+            return QuicClass::NotQuic;
+        }
+        let version = u32::from_be_bytes(payload[1..5].try_into().unwrap());
+        let dcid_len = usize::from(payload[5]);
+        // The fixed bit is unset only in version negotiation packets.
+        if (version != 0 && first & 0x40 == 0)
+            || dcid_len > MAX_CONNECTION_ID_LEN
+            || payload.len() < 6 + dcid_len
+        {
+            return QuicClass::NotQuic;
+        }
+        QuicClass::Long {
+            version,
+            packet_type: (first >> 4) & 0x03,
+            dcid: payload[6..6 + dcid_len].to_vec(),
+        }
+    }
+}
+
+impl Classifier for ClassifyQuic {
+    type Packet = Ipv4Packet;
+    type Class = QuicClass;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        let segment = &packet.data[packet.payload_offset..];
+        if packet.protocol() != IpProtocol::UDP || segment.len() < 8 {
+            return QuicClass::NotQuic;
+        }
+        let src_port = u16::from_be_bytes(segment[0..2].try_into().unwrap());
+        let dest_port = u16::from_be_bytes(segment[2..4].try_into().unwrap());
+        if !self.ports.contains(&src_port) && !self.ports.contains(&dest_port) {
+            return QuicClass::NotQuic;
+        }
+        self.parse(&segment[8..])
+    }
+}
+
+/// The backend, of `num_backends`, the packets of a connection ID are sent to by a
+/// `ClassifyBackend` balancing by connection ID. Servers behind it choose connection IDs this
+/// gives their own index for, so their connections stay with them.
+pub fn connection_backend(dcid: &[u8], num_backends: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    dcid.hash(&mut hasher);
+    (hasher.finish() % num_backends as u64) as usize
+}
+
+/// What a `ClassifyBackend` hashes to pick a packet's backend.
+#[derive(Debug, Clone)]
+pub enum BalanceMode {
+    /// The packet's addresses, protocol and ports.
+    FiveTuple,
+    /// The destination connection ID of QUIC packets, as recognized by the `ClassifyQuic`, and
+    /// the five tuple of anything else. A client's connection keeps its backend when a NAT in
+    /// front of it gives it a new address or port, where it would move with the five tuple.
+    ConnectionId(ClassifyQuic),
+}
+
+/// Spreads IPv4 packets over `num_backends` backends, classifying each by the index of its
+/// backend, from a hash of its five tuple or of its QUIC connection ID, as `mode` says.
+///
+/// Balancing by connection ID relies on the servers choosing connection IDs that
+/// `connection_backend` maps to themselves. The client picks the connection ID of its first
+/// packets, so those land on whichever backend it hashes to, which must then answer them, as QUIC
+/// servers do with a Retry or by taking the connection on.
+pub struct ClassifyBackend {
+    num_backends: usize,
+    mode: BalanceMode,
+}
+
+impl ClassifyBackend {
+    pub fn new(num_backends: usize, mode: BalanceMode) -> Self {
+        assert!(num_backends > 0, "num_backends must be > 0");
+        ClassifyBackend { num_backends, mode }
+    }
+}
+
+impl Classifier for ClassifyBackend {
+    type Packet = Ipv4Packet;
+    type Class = usize;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        if let BalanceMode::ConnectionId(quic) = &self.mode {
+            if let Some(dcid) = quic.classify(packet).dcid() {
+                return connection_backend(dcid, self.num_backends);
+            }
+        }
+        let mut hasher = DefaultHasher::new();
+        FlowKey::from_ipv4(packet).hash(&mut hasher);
+        (hasher.finish() % self.num_backends as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::UdpSegment;
+    use std::net::Ipv4Addr;
+
+    fn udp(src_addr: Ipv4Addr, src_port: u16, dest_port: u16, payload: &[u8]) -> Ipv4Packet {
+        let mut segment = UdpSegment::empty();
+        segment.set_src_port(src_port);
+        segment.set_dest_port(dest_port);
+        segment.set_payload(payload);
+        let mut packet = Ipv4Packet::encap_udp(segment);
+        packet.set_src_addr(src_addr);
+        packet.set_dest_addr(Ipv4Addr::new(198, 51, 100, 10));
+        packet
+    }
+
+    fn short_header(dcid: &[u8]) -> Vec<u8> {
+        let mut header = vec![0x43];
+        header.extend_from_slice(dcid);
+        header.extend_from_slice(&[0xaa; 20]);
+        header
+    }
+
+    #[test]
+    fn parses_long_and_short_headers() {
+        let quic = ClassifyQuic::new();
+        let client = Ipv4Addr::new(10, 0, 0, 2);
+
+        let mut initial = vec![0xc3, 0, 0, 0, 1, 4, 1, 2, 3, 4, 0];
+        initial.extend_from_slice(&[0; 20]);
+        assert_eq!(
+            quic.classify(&udp(client, 50000, 443, &initial)),
+            QuicClass::Long {
+                version: 1,
+                packet_type: 0,
+                dcid: vec![1, 2, 3, 4],
+            }
+        );
+
+        let dcid = [9, 8, 7, 6, 5, 4, 3, 2];
+        assert_eq!(
+            quic.classify(&udp(client, 50000, 443, &short_header(&dcid))),
+            QuicClass::Short {
+                dcid: dcid.to_vec()
+            }
+        );
+
+        // Without the fixed bit, or on another port, it isn't QUIC.
+        assert_eq!(
+            quic.classify(&udp(client, 50000, 443, &[0x03; 20])),
+            QuicClass::NotQuic
+        );
+        assert_eq!(
+            quic.classify(&udp(client, 50000, 53, &short_header(&dcid))),
+            QuicClass::NotQuic
+        );
+    }
+
+    #[test]
+    fn connection_id_survives_nat_rebinding() {
+        let balancer = ClassifyBackend::new(4, BalanceMode::ConnectionId(ClassifyQuic::new()));
+        let dcid = [1, 1, 2, 3, 5, 8, 13, 21];
+        let header = short_header(&dcid);
+
+        let before = udp(Ipv4Addr::new(203, 0, 113, 5), 40000, 443, &header);
+        let after = udp(Ipv4Addr::new(203, 0, 113, 77), 61000, 443, &header);
+        assert_eq!(balancer.classify(&before), connection_backend(&dcid, 4));
+        assert_eq!(balancer.classify(&after), balancer.classify(&before));
+
+        // Anything else is balanced by five tuple, the same way every time.
+        let dns = udp(Ipv4Addr::new(203, 0, 113, 5), 40000, 53, b"query");
+        let five_tuple = ClassifyBackend::new(4, BalanceMode::FiveTuple);
+        assert_eq!(balancer.classify(&dns), five_tuple.classify(&dns));
+        assert!(five_tuple.classify(&dns) < 4);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::classifier::Classifier;
+use route_rs_packets::{IpProtocol, Ipv4Packet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportClass {
+    Tcp,
+    Udp,
+    Sctp,
+    Icmp,
+    Other,
+}
+
+impl From<IpProtocol> for TransportClass {
+    fn from(protocol: IpProtocol) -> Self {
+        match protocol {
+            IpProtocol::TCP => TransportClass::Tcp,
+            IpProtocol::UDP => TransportClass::Udp,
+            IpProtocol::SCTP => TransportClass::Sctp,
+            IpProtocol::ICMP => TransportClass::Icmp,
+            _ => TransportClass::Other,
+        }
+    }
+}
+
+/// Sorts IPv4 packets by their transport protocol, so that each can be given the processors that
+/// understand it, such as SCTP signalling kept apart from the TCP and UDP the rest of the router
+/// handles.
+#[derive(Default)]
+pub struct ClassifyTransport {}
+
+impl ClassifyTransport {
+    pub fn new() -> Self {
+        ClassifyTransport {}
+    }
+}
+
+impl Classifier for ClassifyTransport {
+    type Packet = Ipv4Packet;
+    type Class = TransportClass;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        TransportClass::from(packet.protocol())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{SctpPacket, UdpSegment};
+
+    #[test]
+    fn classifies_sctp() {
+        let classifier = ClassifyTransport::new();
+
+        let mut sctp = Ipv4Packet::empty();
+        sctp.set_protocol(132);
+        sctp.set_payload(&SctpPacket::empty().data);
+        assert_eq!(classifier.classify(&sctp), TransportClass::Sctp);
+
+        let udp = Ipv4Packet::encap_udp(UdpSegment::empty());
+        assert_eq!(classifier.classify(&udp), TransportClass::Udp);
+
+        let mut gre = Ipv4Packet::empty();
+        gre.set_protocol(47);
+        assert_eq!(classifier.classify(&gre), TransportClass::Other);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::classifier::Classifier;
+use crate::link::{primitive::ClassifyLink, Link, LinkBuilder, PacketStream};
+use route_rs_packets::{EthernetFrame, VLAN_CTAG_TPID, VLAN_QINQ_TPID, VLAN_STAG_TPID};
+use std::convert::TryInto;
+
+/// The VLANs of a frame: the service VLAN of its S-tag and the customer VLAN of its C-tag, either
+/// of which it may lack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VlanClass {
+    pub s_vid: Option<u16>,
+    pub c_vid: Option<u16>,
+}
+
+/// Classifies frames by their (S-VID, C-VID) pair. An outer tag whose TPID is one of `s_tpids`,
+/// 0x88a8 and 0x9100 by default, is the S-tag, and a tag with the 802.1Q TPID after it, or
+/// alone, is the C-tag.
+#[derive(Debug, Clone)]
+pub struct ClassifyVlan {
+    s_tpids: Vec<u16>,
+}
+
+impl Default for ClassifyVlan {
+    fn default() -> Self {
+        ClassifyVlan::new()
+    }
+}
+
+impl ClassifyVlan {
+    pub fn new() -> Self {
+        ClassifyVlan {
+            s_tpids: vec![VLAN_STAG_TPID, VLAN_QINQ_TPID],
+        }
+    }
+
+    /// Changes s_tpids, the TPIDs of S-tags, default value is 0x88a8 and 0x9100.
+    pub fn s_tpids(self, s_tpids: Vec<u16>) -> Self {
+        ClassifyVlan { s_tpids }
+    }
+}
+
+impl Classifier for ClassifyVlan {
+    type Packet = EthernetFrame;
+    type Class = VlanClass;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        // The frame may have been parsed without the S-tag's TPID, so its tags are read here.
+        let tag = |at: usize| -> Option<(u16, u16)> {
+            let bytes = frame.data.get(at..at + 4)?;
+            let tpid = u16::from_be_bytes(bytes[0..2].try_into().unwrap());
+            let tci = u16::from_be_bytes(bytes[2..4].try_into().unwrap());
+            Some((tpid, tci & 0x0fff))
+        };
+        let mut at = frame.layer2_offset + 12;
+        let mut class = VlanClass {
+            s_vid: None,
+            c_vid: None,
+        };
+        if let Some((tpid, vid)) = tag(at) {
+            if self.s_tpids.contains(&tpid) {
+                class.s_vid = Some(vid);
+                at += 4;
+            }
+        }
+        if let Some((tpid, vid)) = tag(at) {
+            if tpid == VLAN_CTAG_TPID {
+                class.c_vid = Some(vid);
+            }
+        }
+        class
+    }
+}
+
+/// Frames of the (S-VID, C-VID) pair `pairs[i]` leave on egressor `i`, and all others on the
+/// egressor after the last pair.
+pub fn vlan_pair_link(
+    stream: PacketStream<EthernetFrame>,
+    classifier: ClassifyVlan,
+    pairs: Vec<(u16, u16)>,
+) -> Link<EthernetFrame> {
+    let other = pairs.len();
+    ClassifyLink::new()
+        .ingressor(stream)
+        .num_egressors(other + 1)
+        .classifier(classifier)
+        .dispatcher(Box::new(move |class| match (class.s_vid, class.c_vid) {
+            (Some(s_vid), Some(c_vid)) => pairs
+                .iter()
+                .position(|pair| *pair == (s_vid, c_vid))
+                .unwrap_or(other),
+            _ => other,
+        }))
+        .build_link()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::{Ipv4Packet, VlanTag};
+
+    fn frame(tags: &[VlanTag]) -> EthernetFrame {
+        let mut frame = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
+        for tag in tags.iter().rev() {
+            frame.push_vlan_tag(*tag);
+        }
+        frame
+    }
+
+    #[test]
+    fn classifies_tag_pairs() {
+        let classifier = ClassifyVlan::new();
+        let s_tag = VlanTag::new(VLAN_STAG_TPID, 200);
+        let c_tag = VlanTag::new(VLAN_CTAG_TPID, 10);
+        let pair = |s_vid, c_vid| VlanClass { s_vid, c_vid };
+
+        assert_eq!(
+            classifier.classify(&frame(&[s_tag, c_tag])),
+            pair(Some(200), Some(10))
+        );
+        assert_eq!(classifier.classify(&frame(&[s_tag])), pair(Some(200), None));
+        assert_eq!(classifier.classify(&frame(&[c_tag])), pair(None, Some(10)));
+        assert_eq!(classifier.classify(&frame(&[])), pair(None, None));
+
+        let custom = frame(&[VlanTag::new(0x9200, 300), c_tag]);
+        assert_eq!(classifier.classify(&custom), pair(None, None));
+        assert_eq!(
+            ClassifyVlan::new().s_tpids(vec![0x9200]).classify(&custom),
+            pair(Some(300), Some(10))
+        );
+    }
+
+    #[test]
+    fn dispatches_by_pair() {
+        let s_tag = |vid| VlanTag::new(VLAN_STAG_TPID, vid);
+        let c_tag = |vid| VlanTag::new(VLAN_CTAG_TPID, vid);
+        let frames = vec![
+            frame(&[s_tag(200), c_tag(10)]),
+            frame(&[s_tag(200), c_tag(20)]),
+            frame(&[s_tag(300), c_tag(10)]),
+            frame(&[c_tag(10)]),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = vlan_pair_link(
+                immediate_stream(frames.clone()),
+                ClassifyVlan::new(),
+                vec![(200, 10), (300, 10)],
+            );
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![frames[0].clone()]);
+        assert_eq!(results[1], vec![frames[2].clone()]);
+        assert_eq!(results[2], vec![frames[1].clone(), frames[3].clone()]);
+    }
+}
@@ -0,0 +1,559 @@
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Why a `RouterConfig` could not be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file is not valid TOML, or does not have the shape of a `RouterConfig`.
+    Parse(String),
+    /// The file parsed, but describes a router that can't exist.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "Could not read config: {}", err),
+            ConfigError::Parse(message) => write!(f, "Could not parse config: {}", message),
+            ConfigError::Invalid(message) => write!(f, "Invalid config: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// An IP network in CIDR notation, such as `192.168.1.1/24`. The address is kept as written, so it
+/// can name both the network and the router's own address on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    pub address: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl Subnet {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '/');
+        let address: IpAddr = parts
+            .next()
+            .unwrap()
+            .parse()
+            .map_err(|_| format!("{} is not a subnet, bad address", s))?;
+        let prefix_len: u8 = match parts.next() {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .map_err(|_| format!("{} is not a subnet, bad prefix length", s))?,
+            None => return Err(format!("{} is not a subnet, missing prefix length", s)),
+        };
+        let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max_prefix_len {
+            return Err(format!(
+                "{} is not a subnet, prefix length must be <= {}",
+                s, max_prefix_len
+            ));
+        }
+        Ok(Subnet {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+impl<'de> Deserialize<'de> for Subnet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterfaceConfig {
+    /// Name of the interface in the operating system, such as `eth0`.
+    pub name: String,
+    /// Subnets the router has an address on through this interface.
+    #[serde(default)]
+    pub subnets: Vec<Subnet>,
+    pub mtu: Option<u16>,
+}
+
+/// A named token bucket, for whichever links of a router limit a kind of traffic.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub name: String,
+    pub packets_per_second: u64,
+    /// Packets that may pass at once after a quiet period, defaults to `packets_per_second`.
+    pub burst: Option<u64>,
+}
+
+/// Settings of a `FlowExporterComposite`, see `FlowExporterComposite::config`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlowExportConfig {
+    pub collector: SocketAddr,
+    pub active_timeout_secs: Option<u64>,
+    pub inactive_timeout_secs: Option<u64>,
+    pub export_interval_secs: Option<u64>,
+    pub observation_domain_id: Option<u32>,
+}
+
+/// Settings of an `SFlowExporterLink`, see `SFlowExporterLink::config`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SFlowConfig {
+    pub collector: SocketAddr,
+    pub agent_address: Option<IpAddr>,
+    pub if_index: Option<u32>,
+    pub sampling_rate: Option<u32>,
+}
+
+/// How a `SyslogExporter` reaches its server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogTransport {
+    Udp,
+    Tcp,
+}
+
+/// The facility a `SyslogExporter` sends messages as, with its code from RFC 5424 section 6.2.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    User = 1,
+    Daemon = 3,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+/// Settings of a `SyslogExporter`, see `SyslogExporter::config`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyslogConfig {
+    pub server: SocketAddr,
+    /// Defaults to UDP.
+    pub transport: Option<SyslogTransport>,
+    /// Defaults to daemon.
+    pub facility: Option<SyslogFacility>,
+    pub hostname: Option<String>,
+    /// Whether each packet dropped is sent, defaults to false, as a busy router drops many.
+    pub drops: Option<bool>,
+    /// Messages sent per second at most, others are counted and left out.
+    pub max_rate: Option<u64>,
+}
+
+/// Which way the packets of a security association go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SaDirection {
+    Inbound,
+    Outbound,
+}
+
+/// Whether a security association protects the payload of packets between its ends, or whole
+/// packets between the networks behind them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SaMode {
+    Transport,
+    Tunnel,
+}
+
+/// A security association with a static key, for an `IpsecComposite`, see
+/// `IpsecComposite::config`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SaConfig {
+    pub spi: u32,
+    pub direction: SaDirection,
+    /// Defaults to transport.
+    pub mode: Option<SaMode>,
+    /// The router's end of the association.
+    pub local: Ipv4Addr,
+    pub remote: Ipv4Addr,
+    /// The AES-GCM key followed by its 4 byte salt, RFC 4106 section 8.1, in hex: 20 bytes for
+    /// AES-128, or 36 for AES-256.
+    pub key: String,
+    /// The destinations whose packets an outbound association protects. Defaults to `remote` in
+    /// transport mode, and everything in tunnel mode.
+    pub traffic: Option<Subnet>,
+    /// How far behind the highest sequence number received an inbound association accepts
+    /// packets, up to 64, which is the default.
+    pub replay_window: Option<u32>,
+}
+
+impl SaConfig {
+    /// The bytes of `key`, or none if it is not hex of a length AES-GCM takes.
+    pub fn key_bytes(&self) -> Option<Vec<u8>> {
+        if !matches!(self.key.len(), 40 | 72) {
+            return None;
+        }
+        (0..self.key.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(self.key.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+}
+
+/// Everything about a router that is decided where it is deployed rather than when it is built,
+/// read from a TOML file such as:
+///
+/// ```toml
+/// [[interfaces]]
+/// name = "eth0"
+/// subnets = ["192.168.1.1/24", "fd00::1/64"]
+///
+/// [[interfaces]]
+/// name = "eth1"
+/// mtu = 1492
+///
+/// [[rate_limits]]
+/// name = "icmp"
+/// packets_per_second = 100
+///
+/// [features]
+/// dns_interception = true
+///
+/// [flow_export]
+/// collector = "192.168.1.10:4739"
+///
+/// [syslog]
+/// server = "192.168.1.10:514"
+/// transport = "tcp"
+/// ```
+///
+/// Every section is optional. Composites take the section that concerns them in their builders.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouterConfig {
+    pub interfaces: Vec<InterfaceConfig>,
+    pub rate_limits: Vec<RateLimitConfig>,
+    /// Parts of the router that can be switched on or off, by name.
+    pub features: BTreeMap<String, bool>,
+    pub flow_export: Option<FlowExportConfig>,
+    pub sflow: Option<SFlowConfig>,
+    pub syslog: Option<SyslogConfig>,
+    pub security_associations: Vec<SaConfig>,
+}
+
+impl RouterConfig {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        fs::read_to_string(path).map_err(ConfigError::Io)?.parse()
+    }
+
+    pub fn interface(&self, name: &str) -> Option<&InterfaceConfig> {
+        self.interfaces
+            .iter()
+            .find(|interface| interface.name == name)
+    }
+
+    pub fn rate_limit(&self, name: &str) -> Option<&RateLimitConfig> {
+        self.rate_limits
+            .iter()
+            .find(|rate_limit| rate_limit.name == name)
+    }
+
+    /// Features not mentioned in the config are off.
+    pub fn feature_enabled(&self, feature: &str) -> bool {
+        self.features.get(feature).cloned().unwrap_or(false)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut interfaces = HashSet::new();
+        for interface in &self.interfaces {
+            if !interfaces.insert(&interface.name) {
+                return Err(ConfigError::Invalid(format!(
+                    "Interface {} is configured more than once",
+                    interface.name
+                )));
+            }
+        }
+
+        let mut rate_limits = HashSet::new();
+        for rate_limit in &self.rate_limits {
+            if !rate_limits.insert(&rate_limit.name) {
+                return Err(ConfigError::Invalid(format!(
+                    "Rate limit {} is configured more than once",
+                    rate_limit.name
+                )));
+            }
+            if rate_limit.packets_per_second == 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "Rate limit {} must allow some packets per second",
+                    rate_limit.name
+                )));
+            }
+        }
+
+        if let Some(sflow) = &self.sflow {
+            if sflow.sampling_rate == Some(0) {
+                return Err(ConfigError::Invalid(String::from(
+                    "sFlow sampling_rate must be > 0",
+                )));
+            }
+        }
+        if let Some(syslog) = &self.syslog {
+            if syslog.max_rate == Some(0) {
+                return Err(ConfigError::Invalid(String::from(
+                    "Syslog max_rate must be > 0",
+                )));
+            }
+        }
+        if let Some(flow_export) = &self.flow_export {
+            if flow_export.export_interval_secs == Some(0) {
+                return Err(ConfigError::Invalid(String::from(
+                    "Flow export_interval_secs must be > 0",
+                )));
+            }
+        }
+
+        let mut security_associations = HashSet::new();
+        for sa in &self.security_associations {
+            if !security_associations.insert((sa.spi, sa.direction)) {
+                return Err(ConfigError::Invalid(format!(
+                    "Security association {:#x} is configured more than once",
+                    sa.spi
+                )));
+            }
+            if sa.spi < 256 {
+                return Err(ConfigError::Invalid(format!(
+                    "Security association {:#x} must have an SPI >= 256",
+                    sa.spi
+                )));
+            }
+            if sa.key_bytes().is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "Security association {:#x} must have a key of 20 or 36 bytes in hex",
+                    sa.spi
+                )));
+            }
+            if sa.replay_window.is_some_and(|window| window > 64) {
+                return Err(ConfigError::Invalid(format!(
+                    "Security association {:#x} must have a replay_window <= 64",
+                    sa.spi
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for RouterConfig {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: RouterConfig =
+            toml::from_str(s).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn parses_full_config() {
+        let config: RouterConfig = r#"
+            [[interfaces]]
+            name = "eth0"
+            subnets = ["192.168.1.1/24", "fd00::1/64"]
+
+            [[interfaces]]
+            name = "eth1"
+            mtu = 1492
+
+            [[rate_limits]]
+            name = "icmp"
+            packets_per_second = 100
+            burst = 20
+
+            [features]
+            dns_interception = true
+            flow_export = false
+
+            [flow_export]
+            collector = "192.168.1.10:4739"
+            active_timeout_secs = 120
+
+            [sflow]
+            collector = "192.168.1.10:6343"
+            sampling_rate = 512
+
+            [syslog]
+            server = "192.168.1.10:514"
+            transport = "tcp"
+            facility = "local3"
+        "#
+        .parse()
+        .unwrap();
+
+        let eth0 = config.interface("eth0").unwrap();
+        assert_eq!(
+            eth0.subnets,
+            vec![
+                Subnet {
+                    address: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+                    prefix_len: 24,
+                },
+                Subnet {
+                    address: IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)),
+                    prefix_len: 64,
+                },
+            ]
+        );
+        assert_eq!(eth0.mtu, None);
+        assert_eq!(config.interface("eth1").unwrap().mtu, Some(1492));
+        assert!(config.interface("eth2").is_none());
+
+        let icmp = config.rate_limit("icmp").unwrap();
+        assert_eq!(icmp.packets_per_second, 100);
+        assert_eq!(icmp.burst, Some(20));
+
+        assert!(config.feature_enabled("dns_interception"));
+        assert!(!config.feature_enabled("flow_export"));
+        assert!(!config.feature_enabled("not_mentioned"));
+
+        let flow_export = config.flow_export.unwrap();
+        assert_eq!(flow_export.collector, "192.168.1.10:4739".parse().unwrap());
+        assert_eq!(flow_export.active_timeout_secs, Some(120));
+        assert_eq!(flow_export.inactive_timeout_secs, None);
+        assert_eq!(config.sflow.unwrap().sampling_rate, Some(512));
+
+        let syslog = config.syslog.unwrap();
+        assert_eq!(syslog.transport, Some(SyslogTransport::Tcp));
+        assert_eq!(syslog.facility, Some(SyslogFacility::Local3));
+        assert_eq!(syslog.drops, None);
+    }
+
+    #[test]
+    fn parses_security_associations() {
+        let config: RouterConfig = r#"
+            [[security_associations]]
+            spi = 0x1001
+            direction = "outbound"
+            mode = "tunnel"
+            local = "203.0.113.2"
+            remote = "198.51.100.7"
+            key = "000102030405060708090a0b0c0d0e0f10111213"
+            traffic = "10.20.0.0/16"
+        "#
+        .parse()
+        .unwrap();
+        let sa = &config.security_associations[0];
+        assert_eq!(sa.direction, SaDirection::Outbound);
+        assert_eq!(sa.mode, Some(SaMode::Tunnel));
+        assert_eq!(sa.key_bytes().unwrap(), (0..20).collect::<Vec<u8>>());
+
+        let short_key = "[[security_associations]]\nspi = 0x1001\ndirection = \"inbound\"\n\
+            local = \"203.0.113.2\"\nremote = \"198.51.100.7\"\nkey = \"0001\"";
+        assert!(matches!(
+            short_key.parse::<RouterConfig>(),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn empty_config_is_default() {
+        assert_eq!("".parse::<RouterConfig>().unwrap(), RouterConfig::default());
+    }
+
+    #[test]
+    fn subnet_contains() {
+        let subnet: Subnet = "10.1.2.3/16".parse().unwrap();
+        assert!(subnet.contains("10.1.200.1".parse().unwrap()));
+        assert!(!subnet.contains("10.2.0.1".parse().unwrap()));
+        assert!(!subnet.contains("::1".parse().unwrap()));
+
+        let everything: Subnet = "::/0".parse().unwrap();
+        assert!(everything.contains("2001:db8::1".parse().unwrap()));
+        assert_eq!(everything.to_string(), "::/0");
+    }
+
+    #[test]
+    fn rejects_bad_subnets() {
+        assert!("10.0.0.1".parse::<Subnet>().is_err());
+        assert!("10.0.0.1/33".parse::<Subnet>().is_err());
+        assert!("eth0/24".parse::<Subnet>().is_err());
+
+        match "[[interfaces]]\nname = \"eth0\"\nsubnets = [\"10.0.0.1/40\"]".parse::<RouterConfig>()
+        {
+            Err(ConfigError::Parse(message)) => assert!(message.contains("10.0.0.1/40")),
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(matches!(
+            "[[interfaces]]\nname = \"eth0\"\nspeed = 1000".parse::<RouterConfig>(),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_duplicate_interfaces() {
+        match "[[interfaces]]\nname = \"eth0\"\n[[interfaces]]\nname = \"eth0\""
+            .parse::<RouterConfig>()
+        {
+            Err(ConfigError::Invalid(message)) => {
+                assert_eq!(message, "Interface eth0 is configured more than once")
+            }
+            other => panic!("Expected an invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_missing_file() {
+        assert!(matches!(
+            RouterConfig::from_file("/nonexistent/router.toml"),
+            Err(ConfigError::Io(_))
+        ));
+    }
+}
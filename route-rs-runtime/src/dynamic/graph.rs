@@ -0,0 +1,92 @@
+/// What a node of a `DynamicGraph` is built as, mirroring the node kinds of graphgen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DynamicNodeKind {
+    /// An input channel if the node has no incoming edges, or else an output channel.
+    IO,
+    /// A Processor, in a QueueLink if the node has a capacity or else a ProcessLink.
+    Processor,
+    /// A Classifier, whose outgoing edges are labeled with the classes they carry. A `_` label
+    /// carries every class no other edge does, and without one those packets are dropped.
+    Classifier,
+    /// A ForkLink, copying each packet to every outgoing edge.
+    Fork,
+    /// A DropLink, dropping every packet.
+    Drop,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynamicNode {
+    pub id: String,
+    pub kind: DynamicNodeKind,
+    /// The name a Processor or Classifier is registered under in the `ProcessorRegistry`. Unused
+    /// by other kinds of node.
+    pub class: String,
+    /// Arguments given to the factory of the Processor or Classifier.
+    pub args: Vec<String>,
+    /// Queue capacity of a Processor, Classifier or fork.
+    pub capacity: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynamicEdge {
+    pub source: String,
+    pub target: String,
+    /// The class a classifier sends down the edge.
+    pub label: Option<String>,
+}
+
+/// A pipeline graph to be built at runtime, see `build_pipeline`. Graphs can be assembled in code,
+/// or filled in from whatever configuration a router reads.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DynamicGraph {
+    pub nodes: Vec<DynamicNode>,
+    pub edges: Vec<DynamicEdge>,
+}
+
+impl DynamicGraph {
+    pub fn new() -> Self {
+        DynamicGraph {
+            nodes: vec![],
+            edges: vec![],
+        }
+    }
+
+    pub fn node(mut self, id: &str, kind: DynamicNodeKind, class: &str, args: Vec<&str>) -> Self {
+        self.nodes.push(DynamicNode {
+            id: String::from(id),
+            kind,
+            class: String::from(class),
+            args: args.into_iter().map(String::from).collect(),
+            capacity: None,
+        });
+        self
+    }
+
+    /// Sets the capacity of the last node added.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        match self.nodes.last_mut() {
+            Some(node) => node.capacity = Some(capacity),
+            None => panic!("DynamicGraph has no node to set the capacity of"),
+        }
+        self
+    }
+
+    pub fn edge(mut self, source: &str, target: &str) -> Self {
+        self.edges.push(DynamicEdge {
+            source: String::from(source),
+            target: String::from(target),
+            label: None,
+        });
+        self
+    }
+
+    /// Adds an edge leaving a classifier, carrying the packets of one class.
+    pub fn branch(mut self, source: &str, target: &str, label: &str) -> Self {
+        self.edges.push(DynamicEdge {
+            source: String::from(source),
+            target: String::from(target),
+            label: Some(String::from(label)),
+        });
+        self
+    }
+}
@@ -0,0 +1,391 @@
+use crate::dynamic::pipeline::{build_links, count_paths};
+use crate::dynamic::{DynamicGraph, ProcessorRegistry};
+use crate::link::message::{ControlMsg, RouterMessage};
+use crossbeam::crossbeam_channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// Where the packets of one input of a `PipelineManager` go: the input channel of the section
+/// running now, or nowhere once the input has closed.
+struct Gate<P> {
+    sender: Option<crossbeam::Sender<RouterMessage<P>>>,
+    control: crossbeam::Sender<ControlMsg>,
+    closed: bool,
+}
+
+/// A barrier sent through the section running now, with the number of its copies each output has
+/// yet to see.
+struct PendingBarrier {
+    id: u64,
+    remaining: Vec<usize>,
+    done: oneshot::Sender<()>,
+}
+
+/// Runs a dynamic pipeline, and replaces it with another built from a new graph while the router
+/// keeps running, as when its configuration changes.
+///
+/// Each input of the manager is forwarded to the section running now through a gate. `load`
+/// builds the new section before touching the old, so a graph that fails to build leaves the old
+/// running. It then points the gates at the new section, lets the old one drain every packet it
+/// holds and finish, and only then starts the new one. Packets are neither dropped nor reordered
+/// by a reload, but are held for as long as the old section takes to drain, in the queue of the
+/// new section and then in the input channels of the manager.
+///
+/// Sections carry the packets of the manager as `RouterMessage`s, so that `barrier` can send a
+/// marker through them in order with the packets.
+pub struct PipelineManager<P> {
+    registry: ProcessorRegistry<P>,
+    input_channels: Vec<crossbeam::Receiver<P>>,
+    output_channels: Vec<crossbeam::Sender<P>>,
+    gates: Vec<Arc<Mutex<Gate<P>>>>,
+    outputs: Vec<crossbeam::Sender<RouterMessage<P>>>,
+    paths: Vec<Vec<usize>>,
+    barriers: Arc<Mutex<Vec<PendingBarrier>>>,
+    next_barrier: u64,
+    queue_capacity: usize,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl<P: Send + Clone + 'static> PipelineManager<P> {
+    /// As with `build_pipeline`, the channels are those of the IO nodes of every graph loaded, in
+    /// the order they appear.
+    pub fn new(
+        registry: ProcessorRegistry<P>,
+        input_channels: Vec<crossbeam::Receiver<P>>,
+        output_channels: Vec<crossbeam::Sender<P>>,
+    ) -> Self {
+        PipelineManager {
+            registry,
+            input_channels,
+            output_channels,
+            gates: vec![],
+            outputs: vec![],
+            paths: vec![],
+            barriers: Arc::new(Mutex::new(vec![])),
+            next_barrier: 0,
+            queue_capacity: 10,
+            handles: vec![],
+        }
+    }
+
+    /// Capacity of the queue between each gate and the section it feeds, which holds the packets
+    /// arriving while a reload waits for the old section to drain, and of the queue between the
+    /// section and each output channel.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(queue_capacity > 0, "queue_capacity must be > 0");
+        PipelineManager {
+            registry: self.registry,
+            input_channels: self.input_channels,
+            output_channels: self.output_channels,
+            gates: self.gates,
+            outputs: self.outputs,
+            paths: self.paths,
+            barriers: self.barriers,
+            next_barrier: self.next_barrier,
+            queue_capacity,
+            handles: self.handles,
+        }
+    }
+
+    /// Builds a graph and swaps it in for the section running now, if any, returning once it runs.
+    /// Must be called from within a Tokio runtime, which the section is spawned onto.
+    pub async fn load(&mut self, graph: &DynamicGraph) -> Result<(), String> {
+        let num_inputs = self.input_channels.len().max(self.gates.len());
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..num_inputs)
+            .map(|_| crossbeam_channel::bounded(self.queue_capacity))
+            .unzip();
+        self.open_outputs();
+        let runnables = build_links(graph, &self.registry, receivers, self.outputs.clone())?;
+
+        self.open_gates();
+        self.paths = count_paths(graph);
+        // Replacing the sender of a gate drops the old one, which the old section sees as the end
+        // of its input once it has taken every packet sent before.
+        for (gate, sender) in self.gates.iter().zip(senders) {
+            let mut gate = gate.lock().unwrap();
+            if !gate.closed {
+                gate.sender = Some(sender);
+            }
+        }
+        for handle in self.handles.drain(..) {
+            handle.await.unwrap();
+        }
+
+        self.handles = runnables.into_iter().map(tokio::spawn).collect();
+        Ok(())
+    }
+
+    /// Sends a barrier into every open input of the section running now, returning once each
+    /// copy of it has come out of the outputs the section sends it to. By then, every packet that
+    /// reached the inputs of the manager before the call has left the pipeline or been dropped, so
+    /// none is handled by the section under a change to the router's configuration made
+    /// afterwards, such as swapping the rules of a NAT or firewall.
+    pub async fn barrier(&mut self) {
+        let id = self.next_barrier;
+        self.next_barrier += 1;
+
+        // Gates are held until the barrier is sent, so none closes without passing it on.
+        let gates: Vec<_> = self.gates.iter().map(|gate| gate.lock().unwrap()).collect();
+        let mut remaining = vec![0; self.outputs.len()];
+        for (gate, paths) in gates.iter().zip(&self.paths) {
+            if !gate.closed {
+                for (remaining, paths) in remaining.iter_mut().zip(paths) {
+                    *remaining += paths;
+                }
+            }
+        }
+        if remaining.iter().all(|r| *r == 0) {
+            return;
+        }
+        let (done, resolved) = oneshot::channel();
+        self.barriers.lock().unwrap().push(PendingBarrier {
+            id,
+            remaining,
+            done,
+        });
+        for gate in gates.iter().filter(|gate| !gate.closed) {
+            gate.control.send(ControlMsg::Barrier(id)).unwrap();
+        }
+        drop(gates);
+        let _ = resolved.await;
+    }
+
+    /// Waits for the section running now to finish, which it does once every input of the manager
+    /// has closed and it has drained.
+    pub async fn join(mut self) {
+        for handle in self.handles.drain(..) {
+            handle.await.unwrap();
+        }
+    }
+
+    /// Starts forwarding the packets sections send each output to its output channel, on a thread
+    /// of its own, and noting the barriers that arrive.
+    fn open_outputs(&mut self) {
+        for (index, output_channel) in self.output_channels.drain(..).enumerate() {
+            let (sender, receiver) = crossbeam_channel::bounded(self.queue_capacity);
+            self.outputs.push(sender);
+            let barriers = Arc::clone(&self.barriers);
+            thread::spawn(move || {
+                for message in receiver.iter() {
+                    match message {
+                        RouterMessage::Data(packet) => {
+                            let _ = output_channel.send(packet);
+                        }
+                        RouterMessage::Control(ControlMsg::Barrier(id)) => {
+                            let mut barriers = barriers.lock().unwrap();
+                            if let Some(position) = barriers.iter().position(|b| b.id == id) {
+                                let remaining = &mut barriers[position].remaining[index];
+                                *remaining = remaining.saturating_sub(1);
+                                if barriers[position].remaining.iter().all(|r| *r == 0) {
+                                    let _ = barriers.remove(position).done.send(());
+                                }
+                            }
+                        }
+                        RouterMessage::Control(_) => {}
+                    }
+                }
+            });
+        }
+    }
+
+    /// Starts forwarding each input channel to its gate, on a thread of its own. Control messages
+    /// sent to the gate are forwarded behind the packets already waiting in the input channel.
+    fn open_gates(&mut self) {
+        for input_channel in self.input_channels.drain(..) {
+            let (control, control_channel) = crossbeam_channel::unbounded();
+            let gate = Arc::new(Mutex::new(Gate {
+                sender: None,
+                control,
+                closed: false,
+            }));
+            self.gates.push(Arc::clone(&gate));
+            thread::spawn(move || {
+                let forward = |message| {
+                    if let Some(sender) = &gate.lock().unwrap().sender {
+                        // Only fails if the section has stopped early, taking its packets with it.
+                        let _ = sender.send(message);
+                    }
+                };
+                loop {
+                    crossbeam_channel::select! {
+                        recv(input_channel) -> packet => match packet {
+                            Ok(packet) => forward(RouterMessage::Data(packet)),
+                            Err(_) => break,
+                        },
+                        recv(control_channel) -> msg => {
+                            let waiting = input_channel.len();
+                            for packet in input_channel.try_iter().take(waiting) {
+                                forward(RouterMessage::Data(packet));
+                            }
+                            forward(RouterMessage::Control(msg.unwrap()));
+                        }
+                    }
+                }
+                let mut gate = gate.lock().unwrap();
+                if let Some(sender) = &gate.sender {
+                    for msg in control_channel.try_iter() {
+                        let _ = sender.send(RouterMessage::Control(msg));
+                    }
+                }
+                gate.sender = None;
+                gate.closed = true;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::Even;
+    use crate::dynamic::DynamicNodeKind::{Classifier, Fork, Processor, IO};
+    use crate::utils::test::harness::initialize_runtime;
+    use std::time::Duration;
+
+    struct Add(i32);
+
+    impl crate::processor::Processor for Add {
+        type Input = i32;
+        type Output = i32;
+
+        fn process(&mut self, packet: i32) -> Option<i32> {
+            Some(packet + self.0)
+        }
+    }
+
+    /// Takes a while over each packet.
+    struct Slow;
+
+    impl crate::processor::Processor for Slow {
+        type Input = i32;
+        type Output = i32;
+
+        fn process(&mut self, packet: i32) -> Option<i32> {
+            thread::sleep(Duration::from_micros(500));
+            Some(packet)
+        }
+    }
+
+    fn registry() -> ProcessorRegistry<i32> {
+        let mut registry = ProcessorRegistry::new();
+        registry.register_processor("Add", |args: &[String]| {
+            Ok(Add(args[0].parse().map_err(|_| "Bad number")?))
+        });
+        registry.register_processor("Slow", |_: &[String]| Ok(Slow));
+        registry.register_classifier("Even", |_: &[String]| Ok(Even::new()));
+        registry
+    }
+
+    fn add(n: &str) -> DynamicGraph {
+        DynamicGraph::new()
+            .node("input", IO, "", vec![])
+            .node("add", Processor, "Add", vec![n])
+            .capacity(5)
+            .node("output", IO, "", vec![])
+            .edge("input", "add")
+            .edge("add", "output")
+    }
+
+    #[test]
+    fn reloads_without_losing_packets() {
+        let (input, input_channel) = crossbeam_channel::unbounded();
+        let (output_channel, output) = crossbeam_channel::unbounded();
+        let mut manager =
+            PipelineManager::new(registry(), vec![input_channel], vec![output_channel]);
+
+        let mut runtime = initialize_runtime();
+        runtime.block_on(manager.load(&add("1000"))).unwrap();
+        let sender = thread::spawn(move || {
+            for packet in 0..200 {
+                thread::sleep(Duration::from_micros(200));
+                input.send(packet).unwrap();
+            }
+        });
+        thread::sleep(Duration::from_millis(20));
+        runtime.block_on(manager.load(&add("2000"))).unwrap();
+        sender.join().unwrap();
+        runtime.block_on(manager.join());
+
+        let output: Vec<i32> = output.iter().collect();
+        assert_eq!(output.len(), 200);
+        assert_eq!(
+            output.iter().map(|p| p % 1000).collect::<Vec<_>>(),
+            (0..200).collect::<Vec<_>>()
+        );
+        let reloaded_at = output.iter().position(|p| *p >= 2000).unwrap();
+        assert!(reloaded_at > 0);
+        assert!(output[reloaded_at..].iter().all(|p| *p >= 2000));
+    }
+
+    #[test]
+    fn keeps_running_when_a_graph_fails_to_build() {
+        let (input, input_channel) = crossbeam_channel::unbounded();
+        let (output_channel, output) = crossbeam_channel::unbounded();
+        let mut manager =
+            PipelineManager::new(registry(), vec![input_channel], vec![output_channel])
+                .queue_capacity(1);
+
+        let mut runtime = initialize_runtime();
+        runtime.block_on(manager.load(&add("1000"))).unwrap();
+        input.send(1).unwrap();
+        assert_eq!(
+            runtime.block_on(manager.load(&add("one"))),
+            Err(String::from("Node \"add\": Bad number"))
+        );
+        input.send(2).unwrap();
+        drop(input);
+        runtime.block_on(manager.join());
+
+        assert_eq!(output.iter().collect::<Vec<_>>(), vec![1001, 1002]);
+    }
+
+    #[test]
+    fn barrier_waits_for_packets_in_flight() {
+        let graph = DynamicGraph::new()
+            .node("input", IO, "", vec![])
+            .node("copy", Fork, "", vec![])
+            .node("slow", Processor, "Slow", vec![])
+            .capacity(100)
+            .node("even", Classifier, "Even", vec![])
+            .node("all", IO, "", vec![])
+            .node("evens", IO, "", vec![])
+            .edge("input", "copy")
+            .edge("copy", "slow")
+            .edge("slow", "all")
+            .edge("copy", "even")
+            .branch("even", "evens", "true");
+        let (input, input_channel) = crossbeam_channel::unbounded();
+        let (all_channel, all) = crossbeam_channel::unbounded();
+        let (evens_channel, evens) = crossbeam_channel::unbounded();
+        let mut manager = PipelineManager::new(
+            registry(),
+            vec![input_channel],
+            vec![all_channel, evens_channel],
+        )
+        .queue_capacity(100);
+
+        let mut runtime = initialize_runtime();
+        runtime.block_on(manager.load(&graph)).unwrap();
+        for packet in 0..100 {
+            input.send(packet).unwrap();
+        }
+        runtime.block_on(manager.barrier());
+
+        assert_eq!(
+            all.try_iter().collect::<Vec<_>>(),
+            (0..100).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            evens.try_iter().collect::<Vec<_>>(),
+            (0..100).filter(|p| p % 2 == 0).collect::<Vec<_>>()
+        );
+
+        input.send(100).unwrap();
+        runtime.block_on(manager.barrier());
+        assert_eq!(all.try_iter().collect::<Vec<_>>(), vec![100]);
+        drop(input);
+        runtime.block_on(manager.join());
+    }
+}
@@ -0,0 +1,23 @@
+//! # What is it for?
+//!
+//! Dynamic pipelines are built from a graph at runtime, rather than generated from one by graphgen
+//! and compiled into the router. Processors and Classifiers are registered with a
+//! `ProcessorRegistry` under the class names the graph uses, and boxed as they are built, so a
+//! router can take its pipeline from configuration and build it again when that changes. Boxing
+//! costs a virtual call for each packet in each link, and every link must carry the same type of
+//! packet.
+//!
+//! A `PipelineManager` runs a dynamic pipeline and swaps in another when the graph changes, without
+//! restarting the router or losing the packets passing through.
+
+mod registry;
+pub use self::registry::*;
+
+mod graph;
+pub use self::graph::*;
+
+mod pipeline;
+pub use self::pipeline::*;
+
+mod manager;
+pub use self::manager::*;
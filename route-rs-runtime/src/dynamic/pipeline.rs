@@ -0,0 +1,540 @@
+use crate::classifier::{Classifier, ControlAwareClassifier};
+use crate::dynamic::{
+    DynClassifier, DynProcessor, DynamicGraph, DynamicNode, DynamicNodeKind, ProcessorRegistry,
+};
+use crate::link::composite::DropLink;
+use crate::link::message::RouterMessage;
+use crate::link::primitive::*;
+use crate::link::utils::drain::Drain;
+use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder, TokioRunnable};
+use crate::processor::{ControlAware, Processor, ProcessorContext};
+use std::collections::HashMap;
+use tokio::runtime;
+use tokio::task::JoinHandle;
+
+/// Builds the links of a graph, returning the runnables that drive them. As with a generated
+/// `MultiRunner`, input and output channels are given in the order their IO nodes appear in the
+/// graph. Fails with the first problem found in the graph, or with the error of a factory of the
+/// registry.
+pub fn build_pipeline<P: Send + Clone + 'static>(
+    graph: &DynamicGraph,
+    registry: &ProcessorRegistry<P>,
+    input_channels: Vec<crossbeam::Receiver<P>>,
+    output_channels: Vec<crossbeam::Sender<P>>,
+) -> Result<Vec<TokioRunnable>, String> {
+    build_links(graph, registry, input_channels, output_channels)
+}
+
+/// What the links of a dynamic pipeline carry: its packets, or, in the sections a
+/// `PipelineManager` runs, `RouterMessage`s of them, with its processors and classifiers wrapped to
+/// forward control messages.
+pub(super) trait Carried<P>: Send + Clone + 'static {
+    type Processor: Processor<Input = Self, Output = Self> + Send + 'static;
+    type Classifier: Classifier<Packet = Self, Class = String> + Send + 'static;
+
+    fn processor(processor: DynProcessor<P>) -> Self::Processor;
+    fn classifier(classifier: DynClassifier<P>) -> Self::Classifier;
+}
+
+impl<P: Send + Clone + 'static> Carried<P> for P {
+    type Processor = DynProcessor<P>;
+    type Classifier = DynClassifier<P>;
+
+    fn processor(processor: DynProcessor<P>) -> Self::Processor {
+        processor
+    }
+
+    fn classifier(classifier: DynClassifier<P>) -> Self::Classifier {
+        classifier
+    }
+}
+
+impl<P: Send + Clone + 'static> Carried<P> for RouterMessage<P> {
+    type Processor = ControlAware<DynProcessor<P>>;
+    type Classifier = ControlAwareClassifier<DynClassifier<P>>;
+
+    fn processor(processor: DynProcessor<P>) -> Self::Processor {
+        ControlAware::new(processor)
+    }
+
+    fn classifier(classifier: DynClassifier<P>) -> Self::Classifier {
+        ControlAwareClassifier::new(classifier)
+    }
+}
+
+/// Builds the links of a graph as `build_pipeline` does, carrying `M`.
+pub(super) fn build_links<P: Send + Clone + 'static, M: Carried<P>>(
+    graph: &DynamicGraph,
+    registry: &ProcessorRegistry<P>,
+    input_channels: Vec<crossbeam::Receiver<M>>,
+    output_channels: Vec<crossbeam::Sender<M>>,
+) -> Result<Vec<TokioRunnable>, String> {
+    let mut incoming: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut outgoing: HashMap<&str, Vec<usize>> = HashMap::new();
+    for node in &graph.nodes {
+        if incoming.insert(&node.id, vec![]).is_some() {
+            return Err(format!("Node \"{}\" is declared twice", node.id));
+        }
+        outgoing.insert(&node.id, vec![]);
+    }
+    for (index, edge) in graph.edges.iter().enumerate() {
+        match (
+            outgoing.get_mut(edge.source.as_str()),
+            incoming.contains_key(edge.target.as_str()),
+        ) {
+            (Some(sources), true) => sources.push(index),
+            _ => {
+                return Err(format!(
+                    "Edge \"{}\" -> \"{}\" connects a missing node",
+                    edge.source, edge.target
+                ))
+            }
+        }
+        incoming.get_mut(edge.target.as_str()).unwrap().push(index);
+    }
+
+    let num_inputs = graph
+        .nodes
+        .iter()
+        .filter(|n| n.kind == DynamicNodeKind::IO && incoming[n.id.as_str()].is_empty())
+        .count();
+    let num_outputs = graph
+        .nodes
+        .iter()
+        .filter(|n| n.kind == DynamicNodeKind::IO)
+        .count()
+        - num_inputs;
+    if num_inputs != input_channels.len() || num_outputs != output_channels.len() {
+        return Err(format!(
+            "Graph has {} inputs and {} outputs, but was given {} input and {} output channels",
+            num_inputs,
+            num_outputs,
+            input_channels.len(),
+            output_channels.len()
+        ));
+    }
+    let mut input_channels = input_channels.into_iter();
+    let mut output_channels = output_channels.into_iter();
+
+    // Links are built once every link feeding them has been, each taking the streams of its
+    // incoming edges and leaving one for each outgoing edge.
+    let mut all_runnables: Vec<TokioRunnable> = vec![];
+    let mut streams: HashMap<usize, PacketStream<M>> = HashMap::new();
+    let mut unbuilt: Vec<&DynamicNode> = graph.nodes.iter().collect();
+    while !unbuilt.is_empty() {
+        let ready = unbuilt.iter().position(|n| {
+            incoming[n.id.as_str()]
+                .iter()
+                .all(|e| streams.contains_key(e))
+        });
+        let node = match ready {
+            Some(index) => unbuilt.remove(index),
+            None => {
+                let ids: Vec<&str> = unbuilt.iter().map(|n| n.id.as_str()).collect();
+                return Err(format!("Nodes \"{}\" are in a cycle", ids.join("\", \"")));
+            }
+        };
+        let edges_in = &incoming[node.id.as_str()];
+        let edges_out = &outgoing[node.id.as_str()];
+
+        let mut in_streams: Vec<PacketStream<M>> = edges_in
+            .iter()
+            .map(|e| streams.remove(e).unwrap())
+            .collect();
+        let in_stream = if in_streams.len() > 1 {
+            let (mut runnables, mut egressors) =
+                JoinLink::new().ingressors(in_streams).build_link();
+            all_runnables.append(&mut runnables);
+            Some(egressors.remove(0))
+        } else {
+            in_streams.pop()
+        };
+
+        let (mut runnables, egressors) = match (node.kind, in_stream) {
+            (DynamicNodeKind::IO, None) => InputChannelLink::new()
+                .channel(input_channels.next().unwrap())
+                .build_link(),
+            (DynamicNodeKind::IO, Some(in_stream)) => {
+                let (runnables, _) = OutputChannelLink::new()
+                    .ingressor(in_stream)
+                    .channel(output_channels.next().unwrap())
+                    .build_link();
+                (runnables, vec![])
+            }
+            (_, None) => return Err(format!("Node \"{}\" has no incoming edges", node.id)),
+            (DynamicNodeKind::Processor, Some(in_stream)) => {
+                let processor = registry
+                    .processor(&node.class, &node.args)
+                    .map(M::processor)
+                    .map_err(|err| format!("Node \"{}\": {}", node.id, err))?;
+                let context = ProcessorContext::new(&node.id);
+                match node.capacity {
+                    Some(capacity) => QueueLink::new()
+                        .ingressor(in_stream)
+                        .processor(processor)
+                        .queue_capacity(capacity)
+                        .context(context)
+                        .build_link(),
+                    None => ProcessLink::new()
+                        .ingressor(in_stream)
+                        .processor(processor)
+                        .context(context)
+                        .build_link(),
+                }
+            }
+            (DynamicNodeKind::Classifier, Some(in_stream)) => {
+                classify(node, registry, in_stream, graph, edges_out)?
+            }
+            (DynamicNodeKind::Fork, Some(in_stream)) => {
+                let link = ForkLink::new()
+                    .ingressor(in_stream)
+                    .num_egressors(edges_out.len().max(1));
+                match node.capacity {
+                    Some(capacity) => link.queue_capacity(capacity).build_link(),
+                    None => link.build_link(),
+                }
+            }
+            (DynamicNodeKind::Drop, Some(in_stream)) => {
+                let (mut runnables, mut egressors) =
+                    DropLink::new().ingressor(in_stream).build_link();
+                runnables.push(Box::new(Drain::new(egressors.remove(0))));
+                (runnables, vec![])
+            }
+        };
+        all_runnables.append(&mut runnables);
+
+        if egressors.len() != edges_out.len() {
+            return Err(match node.kind {
+                _ if egressors.is_empty() => {
+                    format!("Node \"{}\" can not have outgoing edges", node.id)
+                }
+                DynamicNodeKind::IO | DynamicNodeKind::Processor => format!(
+                    "Node \"{}\" must have exactly 1 outgoing edge, fork it to send packets down several",
+                    node.id
+                ),
+                _ => format!("Node \"{}\" must have outgoing edges", node.id),
+            });
+        }
+        for (edge, egressor) in edges_out.iter().zip(egressors) {
+            streams.insert(*edge, egressor);
+        }
+    }
+
+    Ok(all_runnables)
+}
+
+/// Builds the ClassifyLink of a classifier node, with an egressor for each outgoing edge. Packets
+/// of a class no edge is labeled with go down the `_` edge, or are drained if there is none.
+fn classify<P: Send + Clone + 'static, M: Carried<P>>(
+    node: &DynamicNode,
+    registry: &ProcessorRegistry<P>,
+    in_stream: PacketStream<M>,
+    graph: &DynamicGraph,
+    edges_out: &[usize],
+) -> Result<Link<M>, String> {
+    let classifier = registry
+        .classifier(&node.class, &node.args)
+        .map(M::classifier)
+        .map_err(|err| format!("Node \"{}\": {}", node.id, err))?;
+    if edges_out.is_empty() {
+        return Err(format!("Node \"{}\" must have outgoing edges", node.id));
+    }
+    let mut labels = vec![];
+    for edge in edges_out {
+        match &graph.edges[*edge].label {
+            Some(label) => labels.push(label.to_owned()),
+            None => {
+                return Err(format!(
+                    "Edge \"{}\" -> \"{}\" leaves a classifier, but has no label",
+                    node.id, graph.edges[*edge].target
+                ))
+            }
+        }
+    }
+
+    let default = labels.iter().position(|l| l == "_");
+    let num_egressors = edges_out.len() + default.is_none() as usize;
+    let dispatch = move |class: String| {
+        // Labels may name the class with its enum, as in generated pipelines.
+        let suffix = format!("::{}", class);
+        labels
+            .iter()
+            .position(|l| *l == class || l.ends_with(&suffix))
+            .or(default)
+            .unwrap_or(num_egressors - 1)
+    };
+    let link = ClassifyLink::new()
+        .ingressor(in_stream)
+        .classifier(classifier)
+        .dispatcher(Box::new(dispatch))
+        .num_egressors(num_egressors);
+    let (mut runnables, mut egressors) = match node.capacity {
+        Some(capacity) => link.queue_capacity(capacity).build_link(),
+        None => link.build_link(),
+    };
+    if default.is_none() {
+        runnables.push(Box::new(Drain::new(egressors.pop().unwrap())));
+    }
+    Ok((runnables, egressors))
+}
+
+/// How many paths lead from each input of a graph to each of its outputs, in the order of their IO
+/// nodes, which is how many copies of a control message sent into an input each output sees. The
+/// graph must be one that builds.
+pub(super) fn count_paths(graph: &DynamicGraph) -> Vec<Vec<usize>> {
+    let is_target = |id: &str| graph.edges.iter().any(|e| e.target == id);
+    let io = graph.nodes.iter().filter(|n| n.kind == DynamicNodeKind::IO);
+    let (outputs, inputs): (Vec<&DynamicNode>, Vec<&DynamicNode>) =
+        io.partition(|n| is_target(&n.id));
+
+    inputs
+        .iter()
+        .map(|input| {
+            let mut memo = HashMap::new();
+            outputs
+                .iter()
+                .map(|output| paths_between(graph, &input.id, &output.id, &mut memo))
+                .collect()
+        })
+        .collect()
+}
+
+fn paths_between<'a>(
+    graph: &'a DynamicGraph,
+    from: &str,
+    to: &'a str,
+    memo: &mut HashMap<&'a str, usize>,
+) -> usize {
+    if to == from {
+        return 1;
+    }
+    if let Some(paths) = memo.get(to) {
+        return *paths;
+    }
+    let paths = graph
+        .edges
+        .iter()
+        .filter(|e| e.target == to)
+        .map(|e| paths_between(graph, from, &e.source, memo))
+        .sum();
+    memo.insert(to, paths);
+    paths
+}
+
+/// Builds a graph and runs it to completion on a new Tokio runtime, as `Runner::run` does a
+/// generated pipeline.
+pub fn run_pipeline<P: Send + Clone + 'static>(
+    graph: &DynamicGraph,
+    registry: &ProcessorRegistry<P>,
+    input_channels: Vec<crossbeam::Receiver<P>>,
+    output_channels: Vec<crossbeam::Sender<P>>,
+) -> Result<(), String> {
+    let all_runnables = build_pipeline(graph, registry, input_channels, output_channels)?;
+
+    let mut rt = runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let handles: Vec<JoinHandle<()>> = all_runnables.into_iter().map(tokio::spawn).collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::Even;
+    use crate::processor::Identity;
+    use crossbeam::crossbeam_channel;
+    use DynamicNodeKind::{Classifier, Drop, Fork, Processor, IO};
+
+    struct Add(i32);
+
+    impl crate::processor::Processor for Add {
+        type Input = i32;
+        type Output = i32;
+
+        fn process(&mut self, packet: i32) -> Option<i32> {
+            Some(packet + self.0)
+        }
+    }
+
+    fn registry() -> ProcessorRegistry<i32> {
+        let mut registry = ProcessorRegistry::new();
+        registry.register_processor("Identity", |_| Ok(Identity::new()));
+        registry.register_processor("Add", |args| match args {
+            [n] => n.parse().map(Add).map_err(|_| format!("Bad number {}", n)),
+            _ => Err(String::from("Add takes a number")),
+        });
+        registry.register_classifier("Even", |_| Ok(Even::new()));
+        registry
+    }
+
+    /// Runs a graph with one input and the given number of outputs.
+    fn run(graph: &DynamicGraph, packets: Vec<i32>, num_outputs: usize) -> Vec<Vec<i32>> {
+        let (input, input_channel) = crossbeam_channel::unbounded();
+        for packet in packets {
+            input.send(packet).unwrap();
+        }
+        drop(input);
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..num_outputs)
+            .map(|_| crossbeam_channel::unbounded())
+            .unzip();
+
+        run_pipeline(graph, &registry(), vec![input_channel], senders).unwrap();
+        receivers.iter().map(|r| r.iter().collect()).collect()
+    }
+
+    #[test]
+    fn identity() {
+        let graph = DynamicGraph::new()
+            .node("input", IO, "", vec![])
+            .node("identity", Processor, "Identity", vec![])
+            .node("output", IO, "", vec![])
+            .edge("input", "identity")
+            .edge("identity", "output");
+
+        assert_eq!(run(&graph, vec![1, 2, 3], 1), vec![vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn classify_and_join() {
+        let graph = DynamicGraph::new()
+            .node("input", IO, "", vec![])
+            .node("even", Classifier, "Even", vec![])
+            .node("add", Processor, "Add", vec!["100"])
+            .capacity(5)
+            .node("output", IO, "", vec![])
+            .edge("input", "even")
+            .branch("even", "add", "false")
+            .branch("even", "output", "true")
+            .edge("add", "output");
+
+        let mut output = run(&graph, (0..10).collect(), 1).remove(0);
+        output.sort();
+        assert_eq!(output, vec![0, 2, 4, 6, 8, 101, 103, 105, 107, 109]);
+    }
+
+    #[test]
+    fn drops_unmatched_classes() {
+        let graph = DynamicGraph::new()
+            .node("input", IO, "", vec![])
+            .node("even", Classifier, "Even", vec![])
+            .node("output", IO, "", vec![])
+            .edge("input", "even")
+            .branch("even", "output", "Even::true");
+
+        assert_eq!(
+            run(&graph, (0..100).collect(), 1),
+            vec![(0..100).filter(|p| p % 2 == 0).collect::<Vec<_>>()]
+        );
+    }
+
+    #[test]
+    fn fork_and_drop() {
+        let graph = DynamicGraph::new()
+            .node("input", IO, "", vec![])
+            .node("copy", Fork, "", vec![])
+            .node("first", IO, "", vec![])
+            .node("second", IO, "", vec![])
+            .node("copy_again", Fork, "", vec![])
+            .node("drop", Drop, "", vec![])
+            .edge("input", "copy")
+            .edge("copy", "first")
+            .edge("copy", "copy_again")
+            .edge("copy_again", "second")
+            .edge("copy_again", "drop");
+
+        assert_eq!(
+            run(&graph, vec![1, 2, 3], 2),
+            vec![vec![1, 2, 3], vec![1, 2, 3]]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_graphs() {
+        let build = |graph: DynamicGraph| {
+            let (_, input_channel) = crossbeam_channel::unbounded();
+            let (output_channel, _) = crossbeam_channel::unbounded();
+            build_pipeline(
+                &graph,
+                &registry(),
+                vec![input_channel],
+                vec![output_channel],
+            )
+            .err()
+            .unwrap()
+        };
+        let io = DynamicGraph::new()
+            .node("input", IO, "", vec![])
+            .node("output", IO, "", vec![]);
+
+        assert_eq!(
+            build(
+                io.clone()
+                    .node("add", Processor, "Add", vec!["one"])
+                    .edge("input", "add")
+                    .edge("add", "output")
+            ),
+            "Node \"add\": Bad number one"
+        );
+        assert_eq!(
+            build(
+                io.clone()
+                    .node("log", Processor, "Log", vec![])
+                    .edge("input", "log")
+                    .edge("log", "output")
+            ),
+            "Node \"log\": No Processor is registered as Log"
+        );
+        assert_eq!(
+            build(
+                io.clone()
+                    .node("a", Processor, "Identity", vec![])
+                    .node("b", Processor, "Identity", vec![])
+                    .edge("input", "output")
+                    .edge("a", "b")
+                    .edge("b", "a")
+            ),
+            "Nodes \"a\", \"b\" are in a cycle"
+        );
+        assert_eq!(
+            build(
+                io.clone()
+                    .edge("input", "output")
+                    .edge("input", "output")
+            ),
+            "Node \"input\" must have exactly 1 outgoing edge, fork it to send packets down several"
+        );
+        assert_eq!(
+            build(io.clone().edge("input", "nowhere")),
+            "Edge \"input\" -> \"nowhere\" connects a missing node"
+        );
+        assert_eq!(
+            build(io.node("input", IO, "", vec![])),
+            "Node \"input\" is declared twice"
+        );
+    }
+
+    #[test]
+    fn counts_paths_from_inputs_to_outputs() {
+        let graph = DynamicGraph::new()
+            .node("input", IO, "", vec![])
+            .node("other_input", IO, "", vec![])
+            .node("copy", Fork, "", vec![])
+            .node("first", IO, "", vec![])
+            .node("second", IO, "", vec![])
+            .edge("input", "copy")
+            .edge("copy", "first")
+            .edge("copy", "first")
+            .edge("copy", "second")
+            .edge("other_input", "second");
+
+        assert_eq!(count_paths(&graph), vec![vec![2, 1], vec![0, 1]]);
+    }
+}
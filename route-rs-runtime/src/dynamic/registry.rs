@@ -0,0 +1,180 @@
+use crate::classifier::Classifier;
+use crate::link::message::ControlMsg;
+use crate::processor::{Processor, ProcessorContext};
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+/// A Processor of any type, as built by a `ProcessorRegistry`.
+pub struct DynProcessor<P> {
+    processor: Box<dyn Processor<Input = P, Output = P> + Send>,
+}
+
+impl<P: Send + Clone> Processor for DynProcessor<P> {
+    type Input = P;
+    type Output = P;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        self.processor.process(packet)
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.processor.setup(context)
+    }
+
+    fn control(&mut self, msg: &ControlMsg) {
+        self.processor.control(msg)
+    }
+}
+
+/// A Classifier of any type, as built by a `ProcessorRegistry`. Its class is the `Debug`
+/// representation of the wrapped Classifier's, which is what the branches of a dynamic pipeline
+/// are labeled with.
+pub struct DynClassifier<P> {
+    classify: Box<dyn Fn(&P) -> String + Send>,
+}
+
+impl<P: Send + Clone> Classifier for DynClassifier<P> {
+    type Packet = P;
+    type Class = String;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        (self.classify)(packet)
+    }
+}
+
+type ProcessorFactory<P> = Box<dyn Fn(&[String]) -> Result<DynProcessor<P>, String> + Send + Sync>;
+type ClassifierFactory<P> =
+    Box<dyn Fn(&[String]) -> Result<DynClassifier<P>, String> + Send + Sync>;
+
+/// The Processors and Classifiers a dynamic pipeline can be built from, by the class names the
+/// nodes of its graph refer to them by. Each is registered with a factory, which builds it from the
+/// arguments of a node, or says why it can't.
+///
+/// Every link of a dynamic pipeline carries the same type of packet, so only Processors whose
+/// Input and Output are both `P` can be registered.
+pub struct ProcessorRegistry<P> {
+    processors: HashMap<String, ProcessorFactory<P>>,
+    classifiers: HashMap<String, ClassifierFactory<P>>,
+}
+
+impl<P: Send + Clone + 'static> Default for ProcessorRegistry<P> {
+    fn default() -> Self {
+        ProcessorRegistry::new()
+    }
+}
+
+impl<P: Send + Clone + 'static> ProcessorRegistry<P> {
+    pub fn new() -> Self {
+        ProcessorRegistry {
+            processors: HashMap::new(),
+            classifiers: HashMap::new(),
+        }
+    }
+
+    /// Registers a Processor under a class name, replacing any registered before under the same.
+    pub fn register_processor<T, F>(&mut self, class: &str, factory: F)
+    where
+        T: Processor<Input = P, Output = P> + Send + 'static,
+        F: Fn(&[String]) -> Result<T, String> + Send + Sync + 'static,
+    {
+        self.processors.insert(
+            String::from(class),
+            Box::new(move |args| {
+                factory(args).map(|processor| DynProcessor {
+                    processor: Box::new(processor),
+                })
+            }),
+        );
+    }
+
+    /// Registers a Classifier under a class name, replacing any registered before under the same.
+    pub fn register_classifier<C, F>(&mut self, class: &str, factory: F)
+    where
+        C: Classifier<Packet = P> + Send + 'static,
+        C::Class: Debug,
+        F: Fn(&[String]) -> Result<C, String> + Send + Sync + 'static,
+    {
+        self.classifiers.insert(
+            String::from(class),
+            Box::new(move |args| {
+                factory(args).map(|classifier| DynClassifier {
+                    classify: Box::new(move |packet| format!("{:?}", classifier.classify(packet))),
+                })
+            }),
+        );
+    }
+
+    pub fn has_processor(&self, class: &str) -> bool {
+        self.processors.contains_key(class)
+    }
+
+    pub fn has_classifier(&self, class: &str) -> bool {
+        self.classifiers.contains_key(class)
+    }
+
+    /// Builds the Processor registered under a class name.
+    pub fn processor(&self, class: &str, args: &[String]) -> Result<DynProcessor<P>, String> {
+        match self.processors.get(class) {
+            Some(factory) => factory(args),
+            None => Err(format!("No Processor is registered as {}", class)),
+        }
+    }
+
+    /// Builds the Classifier registered under a class name.
+    pub fn classifier(&self, class: &str, args: &[String]) -> Result<DynClassifier<P>, String> {
+        match self.classifiers.get(class) {
+            Some(factory) => factory(args),
+            None => Err(format!("No Classifier is registered as {}", class)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::Even;
+    use crate::processor::Identity;
+
+    #[test]
+    fn builds_registered() {
+        let mut registry = ProcessorRegistry::new();
+        registry.register_processor("Identity", |_| Ok(Identity::new()));
+        registry.register_classifier("Even", |_| Ok(Even::new()));
+
+        let mut identity = registry.processor("Identity", &[]).unwrap();
+        assert_eq!(identity.process(7), Some(7));
+        let even = registry.classifier("Even", &[]).unwrap();
+        assert_eq!(even.classify(&7), "false");
+        assert_eq!(even.classify(&8), "true");
+    }
+
+    #[test]
+    fn passes_args() {
+        let mut registry: ProcessorRegistry<i32> = ProcessorRegistry::new();
+        registry.register_processor("Identity", |args| match args {
+            [] => Ok(Identity::new()),
+            _ => Err(String::from("Identity takes no arguments")),
+        });
+
+        assert!(registry.processor("Identity", &[]).is_ok());
+        assert_eq!(
+            registry.processor("Identity", &[String::from("1")]).err(),
+            Some(String::from("Identity takes no arguments"))
+        );
+    }
+
+    #[test]
+    fn rejects_unregistered() {
+        let registry: ProcessorRegistry<i32> = ProcessorRegistry::new();
+
+        assert!(!registry.has_processor("Identity"));
+        assert_eq!(
+            registry.processor("Identity", &[]).err(),
+            Some(String::from("No Processor is registered as Identity"))
+        );
+        assert_eq!(
+            registry.classifier("Even", &[]).err(),
+            Some(String::from("No Classifier is registered as Even"))
+        );
+    }
+}
@@ -14,6 +14,30 @@ pub mod classifier;
 /// Wrappers around Processors and Classfiers, and implement all the movement of Packets through the Router.
 pub mod link;
 
+/// Pipelines built at runtime from a graph and a registry of Processors and Classifiers.
+pub mod dynamic;
+
+/// Settings of a router read from a TOML file when it starts, rather than compiled into it.
+pub mod config;
+
+/// Counters of named links, which graphgen names after the nodes of the graph with `--instrument`.
+pub mod metrics;
+
+/// State shared by name between the processors of a router.
+pub mod state;
+
+/// Inspect and reconfigure a running router over a local HTTP endpoint.
+#[cfg(feature = "mgmt")]
+pub mod mgmt;
+
+/// Answer SNMP polls of a running router's interface and link counters.
+#[cfg(feature = "snmp")]
+pub mod snmp;
+
+/// A single threaded runtime on virtual time, for reproducible tests of links that keep time.
+#[cfg(feature = "sim")]
+pub mod sim;
+
 /// Structure meant to encapsulate a router as and input and output channel. Used by graphgen.
 pub mod pipeline;
 
@@ -0,0 +1,260 @@
+use crate::classifier::Classifier;
+use crate::link::primitive::{ClassifyLink, JoinLink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, TokioRunnable};
+
+/// A branch of a `Chain::split`, continuing the chain of the packets dispatched to it.
+pub type Branch<Input, Output> = Box<dyn FnOnce(Chain<Input>) -> Chain<Output>>;
+
+/// Links composed so far, with the runnables of every one of them, and the egressors of the last,
+/// so links can be wired one after the other without the `remove(0)`s and `append`s of building
+/// each by hand:
+///
+/// ```
+/// use route_rs_runtime::classifier::Even;
+/// use route_rs_runtime::link::chain::Chain;
+/// use route_rs_runtime::link::primitive::ProcessLink;
+/// use route_rs_runtime::link::ProcessLinkBuilder;
+/// use route_rs_runtime::processor::{Drop, Identity};
+/// use route_rs_runtime::utils::test::packet_generators::immediate_stream;
+///
+/// let (runnables, egressors) = Chain::from_stream(immediate_stream(0..10))
+///     .split(
+///         Even::new(),
+///         Box::new(|even| if even { 0 } else { 1 }),
+///         vec![
+///             Box::new(|evens| evens.then(ProcessLink::new().processor(Identity::new()))),
+///             Box::new(|odds| odds.then(ProcessLink::new().processor(Drop::new()))),
+///         ],
+///     )
+///     .merge()
+///     .into_link();
+/// ```
+///
+/// `then` feeds every egressor of the chain to the next link as its ingressors, so a link taking a
+/// single ingressor follows a chain with one egressor, and `merge` joins several into one. Each
+/// has a `try_` version, returning the `LinkBuildError` of a link that can't be built as asked,
+/// where the other panics with it, as `LinkBuilder`'s methods do.
+pub struct Chain<Packet> {
+    runnables: Vec<TokioRunnable>,
+    egressors: Vec<PacketStream<Packet>>,
+}
+
+impl<Packet: Send + Clone + 'static> Chain<Packet> {
+    /// A chain continuing from a built link.
+    pub fn new(link: Link<Packet>) -> Self {
+        let (runnables, egressors) = link;
+        Chain {
+            runnables,
+            egressors,
+        }
+    }
+
+    /// A chain starting with a stream of packets.
+    pub fn from_stream(stream: PacketStream<Packet>) -> Self {
+        Chain {
+            runnables: vec![],
+            egressors: vec![stream],
+        }
+    }
+
+    /// A chain starting with a link that takes no ingressors, such as an `InputChannelLink`.
+    pub fn start<B: LinkBuilder<(), Packet>>(builder: B) -> Self {
+        Chain::new(builder.build_link())
+    }
+
+    /// `then`, returning the error of a link that can't be built.
+    pub fn try_then<Output, B>(self, builder: B) -> Result<Chain<Output>, LinkBuildError>
+    where
+        Output: Send + Clone + 'static,
+        B: LinkBuilder<Packet, Output>,
+    {
+        let (mut runnables, egressors) =
+            builder.try_ingressors(self.egressors)?.try_build_link()?;
+        let mut all_runnables = self.runnables;
+        all_runnables.append(&mut runnables);
+        Ok(Chain {
+            runnables: all_runnables,
+            egressors,
+        })
+    }
+
+    /// Feeds the egressors of the chain to the link `builder` builds, continuing the chain from
+    /// its egressors.
+    pub fn then<Output, B>(self, builder: B) -> Chain<Output>
+    where
+        Output: Send + Clone + 'static,
+        B: LinkBuilder<Packet, Output>,
+    {
+        self.try_then(builder)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// `split`, returning the error of a link that can't be built.
+    #[allow(clippy::type_complexity)]
+    pub fn try_split<C, Output>(
+        self,
+        classifier: C,
+        dispatcher: Box<dyn Fn(C::Class) -> usize + Send + Sync + 'static>,
+        branches: Vec<Branch<Packet, Output>>,
+    ) -> Result<Chain<Output>, LinkBuildError>
+    where
+        C: Classifier<Packet = Packet> + Send + 'static,
+        Output: Send + Clone + 'static,
+    {
+        let (runnables, egressors) = ClassifyLink::new()
+            .try_ingressors(self.egressors)?
+            .classifier(classifier)
+            .dispatcher(dispatcher)
+            .num_egressors(branches.len())
+            .try_build_link()?;
+        let mut all_runnables = self.runnables;
+        all_runnables.extend(runnables);
+        let mut all_egressors = vec![];
+        for (branch, egressor) in branches.into_iter().zip(egressors) {
+            let (mut runnables, mut egressors) = branch(Chain::from_stream(egressor)).into_link();
+            all_runnables.append(&mut runnables);
+            all_egressors.append(&mut egressors);
+        }
+        Ok(Chain {
+            runnables: all_runnables,
+            egressors: all_egressors,
+        })
+    }
+
+    /// Classifies the packets of the chain, and dispatches them to the branch of the index
+    /// `dispatcher` gives their class, each continuing the chain as it likes. The chain continues
+    /// from the egressors of every branch, in order.
+    #[allow(clippy::type_complexity)]
+    pub fn split<C, Output>(
+        self,
+        classifier: C,
+        dispatcher: Box<dyn Fn(C::Class) -> usize + Send + Sync + 'static>,
+        branches: Vec<Branch<Packet, Output>>,
+    ) -> Chain<Output>
+    where
+        C: Classifier<Packet = Packet> + Send + 'static,
+        Output: Send + Clone + 'static,
+    {
+        self.try_split(classifier, dispatcher, branches)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Joins the egressors of the chain into one. A chain with one egressor is left as it is.
+    pub fn merge(self) -> Self {
+        if self.egressors.len() == 1 {
+            return self;
+        }
+        self.then(JoinLink::new())
+    }
+
+    /// Joins several chains into one, with the runnables of each, and an egressor joining all of
+    /// theirs.
+    pub fn merge_all(chains: Vec<Chain<Packet>>) -> Self {
+        let mut runnables = vec![];
+        let mut egressors = vec![];
+        for mut chain in chains {
+            runnables.append(&mut chain.runnables);
+            egressors.append(&mut chain.egressors);
+        }
+        Chain {
+            runnables,
+            egressors,
+        }
+        .merge()
+    }
+
+    /// The number of egressors the chain continues from.
+    pub fn len(&self) -> usize {
+        self.egressors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.egressors.is_empty()
+    }
+
+    /// The runnables of every link of the chain, and the egressors of the last.
+    pub fn into_link(self) -> Link<Packet> {
+        (self.runnables, self.egressors)
+    }
+}
+
+impl<Packet: Send + Clone + 'static> From<Link<Packet>> for Chain<Packet> {
+    fn from(link: Link<Packet>) -> Self {
+        Chain::new(link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::{Even, FizzBuzz, FizzBuzzVariant};
+    use crate::link::composite::DropLink;
+    use crate::link::primitive::{ProcessLink, QueueLink};
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::{FnProcessor, Identity};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    fn chains_links() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = Chain::from_stream(immediate_stream(0..5))
+                .then(ProcessLink::new().processor(FnProcessor::new(|p: i32| Some(p * 2))))
+                .then(QueueLink::new().processor(Identity::new()))
+                .then(ProcessLink::new().processor(FnProcessor::new(|p: i32| Some(p + 1))))
+                .into_link();
+            assert_eq!(link.0.len(), 1);
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![1, 3, 5, 7, 9]);
+    }
+
+    #[test]
+    fn splits_and_merges() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let fizz_buzz = Chain::from_stream(immediate_stream(1..16)).split(
+                FizzBuzz::new(),
+                Box::new(|class| match class {
+                    FizzBuzzVariant::FizzBuzz | FizzBuzzVariant::Fizz => 0,
+                    FizzBuzzVariant::Buzz => 1,
+                    FizzBuzzVariant::None => 2,
+                }),
+                vec![
+                    Box::new(|fizz| fizz),
+                    Box::new(|buzz| {
+                        buzz.then(ProcessLink::new().processor(FnProcessor::new(|p: i32| Some(-p))))
+                    }),
+                    Box::new(|other| other.then(DropLink::new())),
+                ],
+            );
+            assert_eq!(fizz_buzz.len(), 3);
+            let evens = Chain::from_stream(immediate_stream(vec![100, 101])).split(
+                Even::new(),
+                Box::new(|even| if even { 0 } else { 1 }),
+                vec![
+                    Box::new(|evens| evens),
+                    Box::new(|odds| odds.then(DropLink::new())),
+                ],
+            );
+
+            run_link(Chain::merge_all(vec![fizz_buzz, evens]).into_link()).await
+        });
+        let mut merged = results[0].clone();
+        merged.sort_unstable();
+        assert_eq!(merged, vec![-10, -5, 3, 6, 9, 12, 15, 100]);
+    }
+
+    #[test]
+    fn reports_links_that_cant_be_built() {
+        let chain = Chain::from_stream(immediate_stream(0..5)).split(
+            Even::new(),
+            Box::new(|even| if even { 0 } else { 1 }),
+            vec![Box::new(|evens| evens), Box::new(|odds| odds)],
+        );
+        let then = chain.try_then(ProcessLink::new().processor(Identity::new()));
+        assert!(matches!(then, Err(LinkBuildError::Ingressors(_))));
+    }
+}
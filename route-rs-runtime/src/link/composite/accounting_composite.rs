@@ -0,0 +1,700 @@
+use crate::config::Subnet;
+use crate::link::event::{DropAccounting, DropCounters, DropReason, EventSink};
+use crate::link::primitive::ProcessLink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::{Processor, ProcessorContext};
+use crate::state::StateStore;
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::{Ipv4Packet, MacAddr};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::{delay_until, Delay};
+
+/// The name the `AccountingTable` of an `AccountingComposite` is kept under in the `StateStore`,
+/// unless it is given one of its own.
+pub const ACCOUNTING_STATE: &str = "accounting";
+
+/// DSCP class selector 1, the low priority class over quota traffic is remarked to by default,
+/// RFC 8622.
+const DSCP_CS1: u8 = 8;
+
+/// The month of `time`, in UTC, as `YYYY-MM`, from the days since the epoch, as in
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+pub fn month_of(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86400;
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!("{:04}-{:02}", year, month)
+}
+
+/// What a subscriber has sent in a month.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedSubscriber {
+    name: String,
+    packets: u64,
+    bytes: u64,
+}
+
+/// An `AccountingTable` as it is saved to a file.
+#[derive(Serialize, Deserialize)]
+struct SavedTable {
+    month: String,
+    #[serde(default)]
+    subscriber: Vec<SavedSubscriber>,
+}
+
+struct Period {
+    month: String,
+    usage: HashMap<String, Usage>,
+}
+
+/// What each subscriber has sent this month, shared by the `AccountingComposite` counting it and
+/// whatever reports it, or bills for it.
+pub struct AccountingTable {
+    period: Mutex<Period>,
+}
+
+impl Default for AccountingTable {
+    fn default() -> Self {
+        AccountingTable::new()
+    }
+}
+
+impl AccountingTable {
+    pub fn new() -> Self {
+        AccountingTable {
+            period: Mutex::new(Period {
+                month: month_of(SystemTime::now()),
+                usage: HashMap::new(),
+            }),
+        }
+    }
+
+    /// The month counted, as `YYYY-MM`.
+    pub fn month(&self) -> String {
+        self.period.lock().unwrap().month.clone()
+    }
+
+    pub fn usage(&self, subscriber: &str) -> Usage {
+        let period = self.period.lock().unwrap();
+        period.usage.get(subscriber).copied().unwrap_or_default()
+    }
+
+    /// Every subscriber that has sent anything this month, by name.
+    pub fn subscribers(&self) -> Vec<(String, Usage)> {
+        let period = self.period.lock().unwrap();
+        let mut subscribers: Vec<(String, Usage)> = period
+            .usage
+            .iter()
+            .map(|(name, usage)| (name.clone(), *usage))
+            .collect();
+        subscribers.sort_by(|a, b| a.0.cmp(&b.0));
+        subscribers
+    }
+
+    /// Counts a packet of `bytes` against a subscriber, returning what it has sent since.
+    fn add(&self, subscriber: &str, bytes: u64) -> Usage {
+        let mut period = self.period.lock().unwrap();
+        let usage = period.usage.entry(String::from(subscriber)).or_default();
+        usage.packets += 1;
+        usage.bytes += bytes;
+        *usage
+    }
+
+    /// Starts counting `month` from nothing, unless it is already the month counted, returning
+    /// whether it was not.
+    pub fn roll_over(&self, month: &str) -> bool {
+        let mut period = self.period.lock().unwrap();
+        if period.month == month {
+            return false;
+        }
+        period.month = String::from(month);
+        period.usage.clear();
+        true
+    }
+
+    /// Saves the table to a TOML file, replacing it whole, so a reader never sees it half written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let saved = {
+            let period = self.period.lock().unwrap();
+            SavedTable {
+                month: period.month.clone(),
+                subscriber: period
+                    .usage
+                    .iter()
+                    .map(|(name, usage)| SavedSubscriber {
+                        name: name.clone(),
+                        packets: usage.packets,
+                        bytes: usage.bytes,
+                    })
+                    .collect(),
+            }
+        };
+        let contents = toml::to_string(&saved)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let path = path.as_ref();
+        let partial = path.with_extension("partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, path)
+    }
+
+    /// Replaces the table with one saved to a file.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let saved: SavedTable = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let mut period = self.period.lock().unwrap();
+        period.month = saved.month;
+        period.usage = saved
+            .subscriber
+            .into_iter()
+            .map(|s| {
+                let usage = Usage {
+                    packets: s.packets,
+                    bytes: s.bytes,
+                };
+                (s.name, usage)
+            })
+            .collect();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Identity {
+    Mac(MacAddr),
+    Prefix(Subnet),
+}
+
+/// A subscriber of the router, the packets it sends known by their source MAC address, or their
+/// source address being in its prefix, with how many bytes it may send a month.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscriber {
+    name: String,
+    identity: Identity,
+    quota: Option<u64>,
+}
+
+impl Subscriber {
+    /// A subscriber sending from the MAC address `mac`, the packets of which must still have
+    /// their Ethernet header to be counted.
+    pub fn mac(name: &str, mac: MacAddr) -> Self {
+        Subscriber {
+            name: String::from(name),
+            identity: Identity::Mac(mac),
+            quota: None,
+        }
+    }
+
+    /// A subscriber sending from the addresses of `prefix`.
+    pub fn prefix(name: &str, prefix: Subnet) -> Self {
+        Subscriber {
+            name: String::from(name),
+            identity: Identity::Prefix(prefix),
+            quota: None,
+        }
+    }
+
+    /// The bytes the subscriber may send a month, with no limit by default.
+    pub fn quota(self, bytes: u64) -> Self {
+        Subscriber {
+            name: self.name,
+            identity: self.identity,
+            quota: Some(bytes),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn matches(&self, packet: &Ipv4Packet) -> bool {
+        match self.identity {
+            Identity::Mac(mac) => packet.layer2_offset.is_some_and(|offset| {
+                packet.data.get(offset + 6..offset + 12) == Some(&mac.bytes[..])
+            }),
+            Identity::Prefix(prefix) => prefix.contains(IpAddr::V4(packet.src_addr())),
+        }
+    }
+}
+
+/// What becomes of the packets of a subscriber over its quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaAction {
+    Drop,
+    /// Remarks them to a DSCP, so the queues downstream send them after everyone else's.
+    Remark(u8),
+}
+
+impl Default for QuotaAction {
+    fn default() -> Self {
+        QuotaAction::Remark(DSCP_CS1)
+    }
+}
+
+/// Counts the packets of each subscriber in the table, and enforces their quotas.
+struct Accountant {
+    subscribers: Vec<Subscriber>,
+    table: Arc<AccountingTable>,
+    action: QuotaAction,
+    over_quota: Arc<AtomicU64>,
+    drops: DropAccounting,
+}
+
+impl Processor for Accountant {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, mut packet: Self::Input) -> Option<Self::Output> {
+        let subscriber = match self.subscribers.iter().find(|s| s.matches(&packet)) {
+            Some(subscriber) => subscriber,
+            None => return Some(packet),
+        };
+        let usage = self
+            .table
+            .add(&subscriber.name, u64::from(packet.total_len()));
+        if subscriber.quota.is_none_or(|quota| usage.bytes <= quota) {
+            return Some(packet);
+        }
+
+        self.over_quota.fetch_add(1, Ordering::Relaxed);
+        match self.action {
+            QuotaAction::Drop => {
+                self.drops.dropped(DropReason::RateLimited);
+                None
+            }
+            QuotaAction::Remark(dscp) => {
+                packet.set_dscp(dscp);
+                packet.set_checksum();
+                Some(packet)
+            }
+        }
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.over_quota = context.counter("over_quota");
+    }
+}
+
+/// Passes packets through, rolling the table over to each new month, and saving it, every
+/// interval, and once more as its ingressor ends.
+struct Checkpointer {
+    in_stream: PacketStream<Ipv4Packet>,
+    table: Arc<AccountingTable>,
+    path: Option<PathBuf>,
+    interval: Duration,
+    next_checkpoint: Instant,
+    timer: Delay,
+    event_sink: Option<EventSink>,
+}
+
+impl Checkpointer {
+    fn checkpoint(&self) {
+        self.table.roll_over(&month_of(SystemTime::now()));
+        if let Some(path) = &self.path {
+            if let Err(err) = self.table.save(path) {
+                if let Some(event_sink) = &self.event_sink {
+                    event_sink.alert(format!("could not save {}, {}", path.display(), err));
+                }
+            }
+        }
+    }
+}
+
+impl Unpin for Checkpointer {}
+
+impl Stream for Checkpointer {
+    type Item = Ipv4Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let checkpointer = &mut *self;
+        loop {
+            if clock::now() >= checkpointer.next_checkpoint {
+                checkpointer.checkpoint();
+                checkpointer.next_checkpoint += checkpointer.interval;
+            }
+            match Pin::new(&mut checkpointer.in_stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => return Poll::Ready(Some(packet)),
+                Poll::Ready(None) => {
+                    checkpointer.checkpoint();
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => {}
+            }
+
+            checkpointer
+                .timer
+                .reset(tokio::time::Instant::from_std(checkpointer.next_checkpoint));
+            if Pin::new(&mut checkpointer.timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Counts the packets and bytes each subscriber sends, by source MAC address or prefix, in an
+/// `AccountingTable`, and holds them to monthly quotas.
+///
+/// A packet is counted against the first subscriber it belongs to, and passes uncounted if it
+/// belongs to none. Once a subscriber has sent more than its quota in a month, its packets are
+/// remarked to DSCP class selector 1, or dropped, as `over_quota` says, until the next month.
+/// They are still counted in the table, and as `over_quota` under `accounting` in the metrics.
+/// Those dropped are counted in the composite's `drop_counters` too, if it has them, as
+/// `DropReason::RateLimited`, and reported as drops on the `event_sink`. Months are UTC.
+///
+/// Every `checkpoint_interval` the table rolls over to the next month, if one has begun, and is
+/// saved to `persist` if it is given, so counts survive a restart: a table saved there is loaded
+/// when the link is built. Failures to save are reported as alerts on the `event_sink`.
+pub struct AccountingComposite {
+    in_stream: Option<PacketStream<Ipv4Packet>>,
+    subscribers: Vec<Subscriber>,
+    over_quota: QuotaAction,
+    table: Option<Arc<AccountingTable>>,
+    persist: Option<PathBuf>,
+    checkpoint_interval: Duration,
+    event_sink: Option<EventSink>,
+    drop_counters: Option<Arc<DropCounters>>,
+}
+
+impl Default for AccountingComposite {
+    fn default() -> Self {
+        AccountingComposite::new()
+    }
+}
+
+impl AccountingComposite {
+    pub fn new() -> Self {
+        AccountingComposite {
+            in_stream: None,
+            subscribers: vec![],
+            over_quota: QuotaAction::default(),
+            table: None,
+            persist: None,
+            checkpoint_interval: Duration::from_secs(300),
+            event_sink: None,
+            drop_counters: None,
+        }
+    }
+
+    /// Adds a subscriber, tried after those already added.
+    pub fn subscriber(self, subscriber: Subscriber) -> Self {
+        let mut subscribers = self.subscribers;
+        subscribers.push(subscriber);
+        AccountingComposite {
+            in_stream: self.in_stream,
+            subscribers,
+            over_quota: self.over_quota,
+            table: self.table,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes what becomes of packets over quota, default value is
+    /// `QuotaAction::Remark(8)`.
+    pub fn over_quota(self, over_quota: QuotaAction) -> Self {
+        AccountingComposite {
+            in_stream: self.in_stream,
+            subscribers: self.subscribers,
+            over_quota,
+            table: self.table,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Counts into `table`, rather than the one under `ACCOUNTING_STATE` in the global
+    /// `StateStore`.
+    pub fn table(self, table: Arc<AccountingTable>) -> Self {
+        AccountingComposite {
+            in_stream: self.in_stream,
+            subscribers: self.subscribers,
+            over_quota: self.over_quota,
+            table: Some(table),
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// The file the table is loaded from, and saved to.
+    pub fn persist<P: AsRef<Path>>(self, path: P) -> Self {
+        AccountingComposite {
+            in_stream: self.in_stream,
+            subscribers: self.subscribers,
+            over_quota: self.over_quota,
+            table: self.table,
+            persist: Some(path.as_ref().to_path_buf()),
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes checkpoint_interval, default value is 5 minutes.
+    pub fn checkpoint_interval(self, checkpoint_interval: Duration) -> Self {
+        assert!(
+            checkpoint_interval > Duration::from_secs(0),
+            "checkpoint_interval must be > 0"
+        );
+        AccountingComposite {
+            in_stream: self.in_stream,
+            subscribers: self.subscribers,
+            over_quota: self.over_quota,
+            table: self.table,
+            persist: self.persist,
+            checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Counts the packets the composite drops over quota in `drop_counters`.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        AccountingComposite {
+            in_stream: self.in_stream,
+            subscribers: self.subscribers,
+            over_quota: self.over_quota,
+            table: self.table,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: Some(drop_counters),
+        }
+    }
+
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        AccountingComposite {
+            in_stream: self.in_stream,
+            subscribers: self.subscribers,
+            over_quota: self.over_quota,
+            table: self.table,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: Some(event_sink),
+            drop_counters: self.drop_counters,
+        }
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for AccountingComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "AccountingComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "AccountingComposite may only take 1 input stream",
+            ));
+        }
+        Ok(AccountingComposite {
+            in_stream: Some(in_stream),
+            subscribers: self.subscribers,
+            over_quota: self.over_quota,
+            table: self.table,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        let in_stream = match self.in_stream {
+            Some(in_stream) => in_stream,
+            None => return Err(LinkBuildError::Missing("input stream")),
+        };
+        let table = self.table.unwrap_or_else(|| {
+            StateStore::global().get_or_insert_with(ACCOUNTING_STATE, AccountingTable::new)
+        });
+        if let Some(path) = self.persist.as_ref().filter(|path| path.exists()) {
+            table.load(path).map_err(|err| {
+                LinkBuildError::Invalid(format!("could not load {}, {}", path.display(), err))
+            })?;
+        }
+        table.roll_over(&month_of(SystemTime::now()));
+
+        let next_checkpoint = clock::now() + self.checkpoint_interval;
+        let checkpointer = Checkpointer {
+            in_stream,
+            table: Arc::clone(&table),
+            path: self.persist,
+            interval: self.checkpoint_interval,
+            next_checkpoint,
+            timer: delay_until(tokio::time::Instant::from_std(next_checkpoint)),
+            event_sink: self.event_sink.clone(),
+        };
+        let accountant = Accountant {
+            subscribers: self.subscribers,
+            table,
+            action: self.over_quota,
+            over_quota: Arc::new(AtomicU64::new(0)),
+            drops: DropAccounting::new(self.drop_counters, self.event_sink),
+        };
+        ProcessLink::new()
+            .try_ingressor(Box::new(checkpointer))?
+            .processor(accountant)
+            .context(ProcessorContext::new("accounting"))
+            .try_build_link()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::EthernetFrame;
+    use std::convert::TryFrom;
+    use std::net::Ipv4Addr;
+    use uuid::Uuid;
+
+    const ALICE: MacAddr = MacAddr {
+        bytes: [0x02, 0, 0, 0, 0, 0x0a],
+    };
+
+    fn packet(src_addr: Ipv4Addr, payload_len: usize) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(src_addr);
+        packet.set_payload(&vec![0; payload_len]);
+        packet
+    }
+
+    fn from_mac(mac: MacAddr, payload_len: usize) -> Ipv4Packet {
+        let mut frame = EthernetFrame::encap_ipv4(packet(Ipv4Addr::new(10, 0, 0, 7), payload_len));
+        frame.set_src_mac(mac);
+        Ipv4Packet::try_from(frame).unwrap()
+    }
+
+    #[test]
+    fn counts_subscribers_and_enforces_quotas() {
+        let table = Arc::new(AccountingTable::new());
+        let drop_counters = Arc::new(DropCounters::new());
+        let packets = vec![
+            from_mac(ALICE, 80),
+            packet(Ipv4Addr::new(10, 1, 0, 9), 80),
+            from_mac(ALICE, 80),
+            packet(Ipv4Addr::new(10, 1, 0, 9), 80),
+            packet(Ipv4Addr::new(10, 2, 0, 1), 80),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = AccountingComposite::new()
+                .subscriber(Subscriber::mac("alice", ALICE).quota(150))
+                .subscriber(Subscriber::prefix("bob", "10.1.0.0/16".parse().unwrap()).quota(150))
+                .over_quota(QuotaAction::Drop)
+                .table(Arc::clone(&table))
+                .drop_counters(Arc::clone(&drop_counters))
+                .ingressor(immediate_stream(packets))
+                .build_link();
+
+            run_link(link).await
+        });
+        // The second packets of both put them over quota, and are dropped, though still counted.
+        assert_eq!(results[0].len(), 3);
+        assert_eq!(drop_counters.get(DropReason::RateLimited), 2);
+        assert_eq!(
+            table.usage("alice"),
+            Usage {
+                packets: 2,
+                bytes: 200
+            }
+        );
+        assert_eq!(
+            table.usage("bob"),
+            Usage {
+                packets: 2,
+                bytes: 200
+            }
+        );
+        assert_eq!(table.subscribers().len(), 2);
+
+        let mut accountant = Accountant {
+            subscribers: vec![Subscriber::mac("alice", ALICE).quota(150)],
+            table: Arc::clone(&table),
+            action: QuotaAction::default(),
+            over_quota: Arc::new(AtomicU64::new(0)),
+            drops: DropAccounting::default(),
+        };
+        let mut remarked = accountant.process(from_mac(ALICE, 80)).unwrap();
+        assert_eq!(remarked.dscp(), DSCP_CS1);
+        assert!(remarked.validate_checksum());
+        assert_eq!(accountant.over_quota.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn saves_and_rolls_over() {
+        let path = std::env::temp_dir().join(format!("accounting-{}.toml", Uuid::new_v4()));
+        let table = AccountingTable::new();
+        table.roll_over("2020-01");
+        table.add("alice", 1500);
+        table.add("alice", 40);
+        table.save(&path).unwrap();
+
+        let loaded = AccountingTable::new();
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.month(), "2020-01");
+        assert_eq!(
+            loaded.usage("alice"),
+            Usage {
+                packets: 2,
+                bytes: 1540
+            }
+        );
+
+        assert!(!loaded.roll_over("2020-01"));
+        assert!(loaded.roll_over("2020-02"));
+        assert_eq!(loaded.usage("alice"), Usage::default());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn months_are_utc() {
+        let at = |secs| month_of(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01");
+        assert_eq!(at(951_782_400), "2000-02");
+        assert_eq!(at(951_868_799), "2000-02");
+        assert_eq!(at(951_868_800), "2000-03");
+        assert_eq!(at(1_700_000_000), "2023-11");
+    }
+}
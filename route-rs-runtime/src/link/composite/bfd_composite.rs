@@ -0,0 +1,726 @@
+use crate::link::event::{DropAccounting, DropCounters, DropReason, EventSink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::state::StateStore;
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use rand::Rng;
+use route_rs_packets::{BfdPacket, BfdState, Ipv4Packet};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::net::Ipv4Addr;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::time::{delay_until, Delay};
+
+/// The name the BFD status is shared under in the `StateStore`, unless the composite is given its
+/// own `BfdStatus`.
+pub const BFD_STATUS_STATE: &str = "bfd-status";
+
+/// Diagnostic codes, RFC 5880 section 4.1.
+const DIAG_NONE: u8 = 0;
+const DIAG_DETECTION_TIME_EXPIRED: u8 = 1;
+const DIAG_NEIGHBOR_SIGNALED_DOWN: u8 = 3;
+
+/// The slowest a session may send at while it isn't up, RFC 5880 section 6.8.3.
+const SLOW_TX_INTERVAL: Duration = Duration::from_secs(1);
+
+fn micros(interval: Duration) -> u32 {
+    interval.as_micros().min(u128::from(u32::MAX)) as u32
+}
+
+struct BfdSessions {
+    states: Vec<BfdState>,
+    changes: u64,
+}
+
+/// The state of each session of a `BfdComposite`, which failover and routing watch to stop using a
+/// next hop as soon as its session goes down.
+pub struct BfdStatus {
+    sessions: RwLock<BfdSessions>,
+}
+
+impl BfdStatus {
+    pub fn new() -> Self {
+        BfdStatus {
+            sessions: RwLock::new(BfdSessions {
+                states: vec![],
+                changes: 0,
+            }),
+        }
+    }
+
+    /// The state of the session at `session`, Down if there is none.
+    pub fn state(&self, session: usize) -> BfdState {
+        self.sessions
+            .read()
+            .unwrap()
+            .states
+            .get(session)
+            .copied()
+            .unwrap_or(BfdState::Down)
+    }
+
+    /// Whether the session at `session` is up, so its peer is reachable.
+    pub fn is_up(&self, session: usize) -> bool {
+        self.state(session) == BfdState::Up
+    }
+
+    /// How many times a session has changed state, so a watcher can tell when to look again.
+    pub fn changes(&self) -> u64 {
+        self.sessions.read().unwrap().changes
+    }
+
+    pub(crate) fn set(&self, session: usize, state: BfdState) {
+        let mut sessions = self.sessions.write().unwrap();
+        if sessions.states.len() <= session {
+            sessions.states.resize(session + 1, BfdState::Down);
+        }
+        if sessions.states[session] != state {
+            sessions.states[session] = state;
+            sessions.changes += 1;
+        }
+    }
+}
+
+impl Default for BfdStatus {
+    fn default() -> Self {
+        BfdStatus::new()
+    }
+}
+
+/// One BFD session, with its state variables, RFC 5880 section 6.8.1.
+struct Session {
+    local: Ipv4Addr,
+    remote: Ipv4Addr,
+    src_port: u16,
+    state: BfdState,
+    diagnostic: u8,
+    local_discriminator: u32,
+    remote_discriminator: u32,
+    remote_min_tx: Duration,
+    remote_min_rx: Duration,
+    remote_detect_mult: u8,
+    next_tx: Instant,
+    /// When the session goes down if no packet arrives before, while it is Init or Up.
+    detect_at: Option<Instant>,
+}
+
+struct BfdEngine {
+    sessions: Vec<Session>,
+    desired_min_tx: Duration,
+    required_min_rx: Duration,
+    detect_mult: u8,
+    status: Arc<BfdStatus>,
+    event_sink: Option<EventSink>,
+    drops: DropAccounting,
+}
+
+impl BfdEngine {
+    fn new(
+        peers: Vec<(Ipv4Addr, Ipv4Addr)>,
+        desired_min_tx: Duration,
+        required_min_rx: Duration,
+        detect_mult: u8,
+        status: Arc<BfdStatus>,
+        now: Instant,
+    ) -> Self {
+        let mut rng = rand::thread_rng();
+        let sessions = peers
+            .into_iter()
+            .enumerate()
+            .map(|(index, (local, remote))| Session {
+                local,
+                remote,
+                src_port: 49152 + index as u16,
+                state: BfdState::Down,
+                diagnostic: DIAG_NONE,
+                local_discriminator: rng.gen_range(1, u32::MAX),
+                remote_discriminator: 0,
+                remote_min_tx: Duration::from_micros(0),
+                remote_min_rx: Duration::from_micros(1),
+                remote_detect_mult: 0,
+                next_tx: now,
+                detect_at: None,
+            })
+            .collect::<Vec<Session>>();
+        for session in 0..sessions.len() {
+            status.set(session, BfdState::Down);
+        }
+        BfdEngine {
+            sessions,
+            desired_min_tx,
+            required_min_rx,
+            detect_mult,
+            status,
+            event_sink: None,
+            drops: DropAccounting::default(),
+        }
+    }
+
+    /// The interval between the packets of a session, before jitter, RFC 5880 section 6.8.7.
+    fn tx_interval(&self, session: usize) -> Duration {
+        let session = &self.sessions[session];
+        let desired = if session.state == BfdState::Up {
+            self.desired_min_tx
+        } else {
+            self.desired_min_tx.max(SLOW_TX_INTERVAL)
+        };
+        desired.max(session.remote_min_rx)
+    }
+
+    fn detection_time(&self, session: usize) -> Duration {
+        let session = &self.sessions[session];
+        self.required_min_rx.max(session.remote_min_tx) * u32::from(session.remote_detect_mult)
+    }
+
+    fn control(&self, session: usize, final_bit: bool) -> Ipv4Packet {
+        let session = &self.sessions[session];
+        let desired_min_tx = if session.state == BfdState::Up {
+            self.desired_min_tx
+        } else {
+            self.desired_min_tx.max(SLOW_TX_INTERVAL)
+        };
+        let mut bfd = BfdPacket::new();
+        bfd.set_src_port(session.src_port)
+            .set_state(session.state)
+            .set_diagnostic(session.diagnostic)
+            .set_final_bit(final_bit)
+            .set_detect_mult(self.detect_mult)
+            .set_my_discriminator(session.local_discriminator)
+            .set_your_discriminator(session.remote_discriminator)
+            .set_desired_min_tx(micros(desired_min_tx))
+            .set_required_min_rx(micros(self.required_min_rx));
+        let mut packet = bfd.packet();
+        packet.set_src_addr(session.local);
+        packet.set_dest_addr(session.remote);
+        packet.set_checksum();
+        packet
+    }
+
+    fn transition(&mut self, session: usize, state: BfdState, diagnostic: u8) {
+        if self.sessions[session].state == state {
+            return;
+        }
+        let remote = self.sessions[session].remote;
+        self.sessions[session].state = state;
+        self.sessions[session].diagnostic = diagnostic;
+        if state == BfdState::Down {
+            self.sessions[session].detect_at = None;
+        }
+        self.status.set(session, state);
+        if let Some(event_sink) = &self.event_sink {
+            event_sink.alert(format!("BFD session {} to {} {:?}", session, remote, state));
+        }
+    }
+
+    /// Runs a received control packet through its session, RFC 5880 section 6.8.6, returning the
+    /// packet with the final bit set to send back if it was a poll.
+    fn receive(&mut self, packet: Ipv4Packet, now: Instant) -> Option<Ipv4Packet> {
+        // Single hop sessions only take packets no router has forwarded, RFC 5881 section 5.
+        if packet.ttl() != 255 {
+            self.drops.dropped(DropReason::Filtered);
+            return None;
+        }
+        let (src_addr, dest_addr) = (packet.src_addr(), packet.dest_addr());
+        let bfd = match BfdPacket::try_from(packet) {
+            Ok(bfd) => bfd,
+            Err(err) => {
+                self.drops.parse_error(err);
+                return None;
+            }
+        };
+        let session = if bfd.your_discriminator() != 0 {
+            self.sessions
+                .iter()
+                .position(|s| s.local_discriminator == bfd.your_discriminator())
+        } else {
+            self.sessions
+                .iter()
+                .position(|s| s.remote == src_addr && s.local == dest_addr)
+                .filter(|session| {
+                    self.sessions[*session].state != BfdState::Up
+                        && self.sessions[*session].state != BfdState::Init
+                })
+        };
+        let session = match session {
+            Some(session) => session,
+            None => {
+                self.drops.dropped(DropReason::Unclassified);
+                return None;
+            }
+        };
+
+        let peer = &mut self.sessions[session];
+        peer.remote_discriminator = bfd.my_discriminator();
+        peer.remote_min_tx = Duration::from_micros(u64::from(bfd.desired_min_tx()));
+        peer.remote_min_rx = Duration::from_micros(u64::from(bfd.required_min_rx()));
+        peer.remote_detect_mult = bfd.detect_mult();
+        if peer.state == BfdState::AdminDown {
+            self.drops.dropped(DropReason::Discarded);
+            return None;
+        }
+
+        let state = self.sessions[session].state;
+        if bfd.state() == BfdState::AdminDown {
+            self.transition(session, BfdState::Down, DIAG_NEIGHBOR_SIGNALED_DOWN);
+        } else {
+            match (state, bfd.state()) {
+                (BfdState::Down, BfdState::Down) => {
+                    self.transition(session, BfdState::Init, DIAG_NONE)
+                }
+                (BfdState::Down, BfdState::Init)
+                | (BfdState::Init, BfdState::Init)
+                | (BfdState::Init, BfdState::Up) => {
+                    self.transition(session, BfdState::Up, DIAG_NONE)
+                }
+                (BfdState::Up, BfdState::Down) => {
+                    self.transition(session, BfdState::Down, DIAG_NEIGHBOR_SIGNALED_DOWN)
+                }
+                _ => {}
+            }
+        }
+
+        if self.sessions[session].state != BfdState::Down {
+            let detect_at = now + self.detection_time(session);
+            self.sessions[session].detect_at = Some(detect_at);
+        }
+        let next_tx = now + self.tx_interval(session);
+        if next_tx < self.sessions[session].next_tx {
+            self.sessions[session].next_tx = next_tx;
+        }
+        if bfd.poll() {
+            Some(self.control(session, true))
+        } else {
+            None
+        }
+    }
+
+    /// Takes down the sessions whose peers have gone quiet, and sends the packets that are due.
+    fn expire(&mut self, now: Instant) -> Vec<Ipv4Packet> {
+        let mut packets = vec![];
+        let mut rng = rand::thread_rng();
+        for session in 0..self.sessions.len() {
+            if self.sessions[session].detect_at.is_some_and(|at| now >= at) {
+                self.transition(session, BfdState::Down, DIAG_DETECTION_TIME_EXPIRED);
+                self.sessions[session].remote_discriminator = 0;
+            }
+            if now >= self.sessions[session].next_tx {
+                packets.push(self.control(session, false));
+                // Jitter of up to 25%, so sessions don't send in lockstep, RFC 5880 section 6.8.7.
+                let interval = self.tx_interval(session).mul_f64(rng.gen_range(0.75, 1.0));
+                self.sessions[session].next_tx = now + interval;
+            }
+        }
+        packets
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.sessions
+            .iter()
+            .flat_map(|session| session.detect_at.into_iter().chain(Some(session.next_tx)))
+            .min()
+    }
+}
+
+struct BfdRunner {
+    in_stream: PacketStream<Ipv4Packet>,
+    engine: BfdEngine,
+    packets: VecDeque<Ipv4Packet>,
+    timer: Delay,
+}
+
+impl Unpin for BfdRunner {}
+
+impl Stream for BfdRunner {
+    type Item = Ipv4Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let runner = &mut *self;
+        loop {
+            if let Some(packet) = runner.packets.pop_front() {
+                return Poll::Ready(Some(packet));
+            }
+            let now = clock::now();
+            let deadline = runner.engine.next_deadline();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                runner.packets.extend(runner.engine.expire(now));
+                continue;
+            }
+            match Pin::new(&mut runner.in_stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    runner.packets.extend(runner.engine.receive(packet, now));
+                    continue;
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => {}
+            }
+
+            match deadline {
+                Some(deadline) => runner.timer.reset(tokio::time::Instant::from_std(deadline)),
+                None => return Poll::Pending,
+            }
+            if Pin::new(&mut runner.timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Runs asynchronous mode BFD sessions with next hops and tunnel endpoints, to learn they have
+/// gone within a fraction of a second, where probing them with pings takes several.
+///
+/// The composite takes the BFD control packets received for the router, IPv4 UDP to
+/// `BFD_CONTROL_PORT` as a `UdpLink` or classifier picks them out, and sends the control packets
+/// of its sessions out of its egressor, to be routed to their peers. Each session added with
+/// `session` sends a packet every `desired_min_tx`, or as slowly as its peer asks, and goes down
+/// when `detect_mult` of the peer's intervals pass without a packet from it. Sessions send at most
+/// one a second until they are up, and answer polls at once.
+///
+/// The state of the sessions, numbered in the order they were added, is published in a
+/// `BfdStatus`, shared in the `StateStore` under `BFD_STATUS_STATE` unless one is given, which a
+/// `WanFailoverComposite` can follow with `Probe::Bfd`. Each change of state is also reported as
+/// an alert to `event_sink`, if it is given. Control packets that no session takes, or that a
+/// router has forwarded, are dropped, and counted in the composite's `drop_counters` if it has
+/// them. The composite ends when its ingressor does.
+pub struct BfdComposite {
+    in_stream: Option<PacketStream<Ipv4Packet>>,
+    peers: Vec<(Ipv4Addr, Ipv4Addr)>,
+    desired_min_tx: Duration,
+    required_min_rx: Duration,
+    detect_mult: u8,
+    status: Option<Arc<BfdStatus>>,
+    event_sink: Option<EventSink>,
+    drop_counters: Option<Arc<DropCounters>>,
+}
+
+impl Default for BfdComposite {
+    fn default() -> Self {
+        BfdComposite::new()
+    }
+}
+
+impl BfdComposite {
+    pub fn new() -> Self {
+        BfdComposite {
+            in_stream: None,
+            peers: vec![],
+            desired_min_tx: Duration::from_millis(300),
+            required_min_rx: Duration::from_millis(300),
+            detect_mult: 3,
+            status: None,
+            event_sink: None,
+            drop_counters: None,
+        }
+    }
+
+    /// Adds a session, after those already added, from the router's address `local` to the peer
+    /// at `remote`.
+    pub fn session(self, local: Ipv4Addr, remote: Ipv4Addr) -> Self {
+        let mut peers = self.peers;
+        peers.push((local, remote));
+        BfdComposite {
+            in_stream: self.in_stream,
+            peers,
+            desired_min_tx: self.desired_min_tx,
+            required_min_rx: self.required_min_rx,
+            detect_mult: self.detect_mult,
+            status: self.status,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes desired_min_tx, the interval the sessions send at once they are up, default value
+    /// is 300 milliseconds.
+    pub fn desired_min_tx(self, desired_min_tx: Duration) -> Self {
+        assert!(
+            desired_min_tx > Duration::from_secs(0),
+            "desired_min_tx must be > 0"
+        );
+        BfdComposite {
+            in_stream: self.in_stream,
+            peers: self.peers,
+            desired_min_tx,
+            required_min_rx: self.required_min_rx,
+            detect_mult: self.detect_mult,
+            status: self.status,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes required_min_rx, the shortest interval peers may send at, default value is 300
+    /// milliseconds.
+    pub fn required_min_rx(self, required_min_rx: Duration) -> Self {
+        assert!(
+            required_min_rx > Duration::from_secs(0),
+            "required_min_rx must be > 0"
+        );
+        BfdComposite {
+            in_stream: self.in_stream,
+            peers: self.peers,
+            desired_min_tx: self.desired_min_tx,
+            required_min_rx,
+            detect_mult: self.detect_mult,
+            status: self.status,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes detect_mult, the number of intervals peers wait for a packet before they take a
+    /// session down, default value is 3.
+    pub fn detect_mult(self, detect_mult: u8) -> Self {
+        assert!(detect_mult > 0, "detect_mult must be > 0");
+        BfdComposite {
+            in_stream: self.in_stream,
+            peers: self.peers,
+            desired_min_tx: self.desired_min_tx,
+            required_min_rx: self.required_min_rx,
+            detect_mult,
+            status: self.status,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Publishes the state of the sessions here rather than in the `StateStore`.
+    pub fn bfd_status(self, status: Arc<BfdStatus>) -> Self {
+        BfdComposite {
+            in_stream: self.in_stream,
+            peers: self.peers,
+            desired_min_tx: self.desired_min_tx,
+            required_min_rx: self.required_min_rx,
+            detect_mult: self.detect_mult,
+            status: Some(status),
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Counts the control packets the composite drops in `drop_counters`, by reason.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        BfdComposite {
+            in_stream: self.in_stream,
+            peers: self.peers,
+            desired_min_tx: self.desired_min_tx,
+            required_min_rx: self.required_min_rx,
+            detect_mult: self.detect_mult,
+            status: self.status,
+            event_sink: self.event_sink,
+            drop_counters: Some(drop_counters),
+        }
+    }
+
+    /// Reports each change of a session's state to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        BfdComposite {
+            in_stream: self.in_stream,
+            peers: self.peers,
+            desired_min_tx: self.desired_min_tx,
+            required_min_rx: self.required_min_rx,
+            detect_mult: self.detect_mult,
+            status: self.status,
+            event_sink: Some(event_sink),
+            drop_counters: self.drop_counters,
+        }
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for BfdComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "BfdComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "BfdComposite may only take 1 input stream",
+            ));
+        }
+        Ok(BfdComposite {
+            in_stream: Some(in_stream),
+            peers: self.peers,
+            desired_min_tx: self.desired_min_tx,
+            required_min_rx: self.required_min_rx,
+            detect_mult: self.detect_mult,
+            status: self.status,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        let in_stream = self
+            .in_stream
+            .ok_or(LinkBuildError::Missing("input stream"))?;
+        if self.peers.is_empty() {
+            return Err(LinkBuildError::Missing("session"));
+        }
+        let status = self.status.unwrap_or_else(|| {
+            StateStore::global().get_or_insert_with(BFD_STATUS_STATE, BfdStatus::new)
+        });
+        let now = clock::now();
+        let mut engine = BfdEngine::new(
+            self.peers,
+            self.desired_min_tx,
+            self.required_min_rx,
+            self.detect_mult,
+            status,
+            now,
+        );
+        engine.drops = DropAccounting::new(self.drop_counters, self.event_sink.clone());
+        engine.event_sink = self.event_sink;
+        Ok((
+            vec![],
+            vec![Box::new(BfdRunner {
+                in_stream,
+                engine,
+                packets: VecDeque::new(),
+                timer: delay_until(tokio::time::Instant::from_std(now)),
+            })],
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    const LOCAL: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const PEER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 2);
+
+    fn engine(now: Instant) -> BfdEngine {
+        BfdEngine::new(
+            vec![(LOCAL, PEER)],
+            Duration::from_millis(50),
+            Duration::from_millis(50),
+            3,
+            Arc::new(BfdStatus::new()),
+            now,
+        )
+    }
+
+    /// What the peer sends, in `state`, knowing our discriminator or not.
+    fn from_peer(state: BfdState, your_discriminator: u32, poll: bool) -> Ipv4Packet {
+        let mut bfd = BfdPacket::new();
+        bfd.set_state(state)
+            .set_poll(poll)
+            .set_detect_mult(3)
+            .set_my_discriminator(0xabcd)
+            .set_your_discriminator(your_discriminator)
+            .set_desired_min_tx(100_000)
+            .set_required_min_rx(50_000);
+        let mut packet = bfd.packet();
+        packet.set_src_addr(PEER);
+        packet.set_dest_addr(LOCAL);
+        packet
+    }
+
+    #[test]
+    fn three_way_handshake_and_detection() {
+        let start = Instant::now();
+        let mut engine = engine(start);
+        let status = Arc::clone(&engine.status);
+        let discriminator = engine.sessions[0].local_discriminator;
+
+        // Until it is up, the session sends once a second.
+        let sent = engine.expire(start);
+        let bfd = BfdPacket::try_from(sent[0].clone()).unwrap();
+        assert_eq!(bfd.state(), BfdState::Down);
+        assert_eq!(bfd.your_discriminator(), 0);
+        assert_eq!(bfd.desired_min_tx(), 1_000_000);
+        assert!(engine.next_deadline().unwrap() >= start + Duration::from_millis(750));
+
+        engine.receive(from_peer(BfdState::Down, 0, false), start);
+        assert_eq!(status.state(0), BfdState::Init);
+        engine.receive(from_peer(BfdState::Init, discriminator, false), start);
+        assert!(status.is_up(0));
+
+        // Once up, it sends at the faster interval both ends agree on.
+        let last = start + Duration::from_millis(100);
+        let sent = engine.expire(last);
+        let bfd = BfdPacket::try_from(sent[0].clone()).unwrap();
+        assert_eq!(bfd.state(), BfdState::Up);
+        assert_eq!(bfd.your_discriminator(), 0xabcd);
+        assert_eq!(bfd.desired_min_tx(), 50_000);
+
+        // The peer sends every 100ms, so 3 of them pass before the session goes down.
+        engine.receive(from_peer(BfdState::Up, discriminator, false), last);
+        engine.expire(last + Duration::from_millis(299));
+        assert!(status.is_up(0));
+        engine.expire(last + Duration::from_millis(300));
+        assert_eq!(status.state(0), BfdState::Down);
+        assert_eq!(engine.sessions[0].diagnostic, DIAG_DETECTION_TIME_EXPIRED);
+        assert_eq!(status.changes(), 3);
+    }
+
+    #[test]
+    fn answers_polls_and_drops_forwarded_packets() {
+        let now = Instant::now();
+        let drop_counters = Arc::new(DropCounters::new());
+        let mut engine = BfdEngine {
+            drops: DropAccounting::new(Some(Arc::clone(&drop_counters)), None),
+            ..engine(now)
+        };
+        let discriminator = engine.sessions[0].local_discriminator;
+
+        let mut forwarded = from_peer(BfdState::Down, 0, false);
+        forwarded.set_ttl(254);
+        assert!(engine.receive(forwarded, now).is_none());
+        assert_eq!(engine.sessions[0].state, BfdState::Down);
+        assert!(engine
+            .receive(from_peer(BfdState::Down, discriminator + 1, false), now)
+            .is_none());
+        assert_eq!(
+            drop_counters.snapshot(),
+            vec![(DropReason::Filtered, 1), (DropReason::Unclassified, 1)]
+        );
+
+        engine.receive(from_peer(BfdState::Init, 0, false), now);
+        let answer = engine
+            .receive(from_peer(BfdState::Up, discriminator, true), now)
+            .unwrap();
+        let bfd = BfdPacket::try_from(answer).unwrap();
+        assert!(bfd.final_bit());
+        assert_eq!(bfd.state(), BfdState::Up);
+
+        // The peer taking the session down takes ours down with it.
+        engine.receive(from_peer(BfdState::Down, discriminator, false), now);
+        assert_eq!(engine.sessions[0].state, BfdState::Down);
+        assert_eq!(engine.sessions[0].diagnostic, DIAG_NEIGHBOR_SIGNALED_DOWN);
+    }
+
+    #[test]
+    fn sends_control_packets() {
+        let status = Arc::new(BfdStatus::new());
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = BfdComposite::new()
+                .session(LOCAL, PEER)
+                .bfd_status(Arc::clone(&status))
+                .ingressor(immediate_stream(vec![from_peer(BfdState::Down, 0, false)]))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results.len(), 1);
+        let bfd = BfdPacket::try_from(results[0][0].clone()).unwrap();
+        assert_eq!(bfd.ipv4().src_addr(), LOCAL);
+        assert_eq!(bfd.ipv4().dest_addr(), PEER);
+        assert_eq!(bfd.ipv4().ttl(), 255);
+        assert_eq!(status.state(0), BfdState::Init);
+    }
+}
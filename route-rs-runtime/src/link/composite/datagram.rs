@@ -0,0 +1,37 @@
+use route_rs_packets::{IpProtocol, Ipv4Packet};
+use std::net::SocketAddrV4;
+
+/// The ports and payload of a UDP datagram.
+pub(crate) fn udp(packet: &Ipv4Packet) -> Option<(u16, u16, Vec<u8>)> {
+    if packet.protocol() != IpProtocol::UDP {
+        return None;
+    }
+    let segment = packet.payload();
+    if segment.len() < 8 {
+        return None;
+    }
+    let src_port = u16::from_be_bytes([segment[0], segment[1]]);
+    let dest_port = u16::from_be_bytes([segment[2], segment[3]]);
+    let len = usize::from(u16::from_be_bytes([segment[4], segment[5]]));
+    let payload = segment.get(8..len)?.to_vec();
+    Some((src_port, dest_port, payload))
+}
+
+/// A datagram from `src` to `dest`, without a UDP checksum, which IPv4 allows.
+pub(crate) fn datagram(src: SocketAddrV4, dest: SocketAddrV4, message: &[u8]) -> Ipv4Packet {
+    let mut segment = vec![];
+    segment.extend_from_slice(&src.port().to_be_bytes());
+    segment.extend_from_slice(&dest.port().to_be_bytes());
+    segment.extend_from_slice(&((8 + message.len()) as u16).to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    segment.extend_from_slice(message);
+
+    let mut packet = Ipv4Packet::empty();
+    packet.set_protocol(17);
+    packet.set_ttl(64);
+    packet.set_src_addr(*src.ip());
+    packet.set_dest_addr(*dest.ip());
+    packet.set_payload(&segment);
+    packet.set_checksum();
+    packet
+}
@@ -0,0 +1,863 @@
+use crate::config::Subnet;
+use crate::link::event::EventSink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::state::StateStore;
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::{ipv6_pseudo_header_checksum, IpProtocol, Ipv6Packet, MacAddr};
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv6Addr};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::time::{delay_until, Delay};
+
+pub const DHCPV6_CLIENT_PORT: u16 = 546;
+pub const DHCPV6_SERVER_PORT: u16 = 547;
+
+/// The name the delegated prefix is shared under in the `StateStore`, unless the composite is
+/// given its own `DelegatedPrefix`.
+pub const DELEGATED_PREFIX_STATE: &str = "delegated-prefix";
+
+const ALL_DHCP_RELAY_AGENTS_AND_SERVERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+
+/// RFC 8415 section 7.3.
+const SOLICIT: u8 = 1;
+const ADVERTISE: u8 = 2;
+const REQUEST: u8 = 3;
+const RENEW: u8 = 5;
+const REBIND: u8 = 6;
+const REPLY: u8 = 7;
+
+/// RFC 8415 section 21.
+const OPTION_CLIENTID: u16 = 1;
+const OPTION_SERVERID: u16 = 2;
+const OPTION_ELAPSED_TIME: u16 = 8;
+const OPTION_STATUS_CODE: u16 = 13;
+const OPTION_IA_PD: u16 = 25;
+const OPTION_IAPREFIX: u16 = 26;
+
+const STATUS_SUCCESS: u16 = 0;
+
+/// A DUID based on the link-layer address, of an Ethernet interface, RFC 8415 section 11.4.
+const DUID_LL: u16 = 3;
+const HARDWARE_TYPE_ETHERNET: u16 = 1;
+
+/// Retransmission parameters, RFC 8415 section 7.6.
+const SOL_TIMEOUT: Duration = Duration::from_secs(1);
+const SOL_MAX_RT: Duration = Duration::from_secs(3600);
+const REQ_TIMEOUT: Duration = Duration::from_secs(1);
+const REQ_MAX_RT: Duration = Duration::from_secs(30);
+const REQ_MAX_RC: u32 = 10;
+const REN_TIMEOUT: Duration = Duration::from_secs(10);
+const REN_MAX_RT: Duration = Duration::from_secs(600);
+const REB_TIMEOUT: Duration = Duration::from_secs(10);
+const REB_MAX_RT: Duration = Duration::from_secs(600);
+
+/// The options of a DHCPv6 message, or of an option that encapsulates others, as code and data.
+/// A truncated option ends them.
+fn options(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut options = vec![];
+    while data.len() >= 4 {
+        let code = u16::from_be_bytes([data[0], data[1]]);
+        let len = usize::from(u16::from_be_bytes([data[2], data[3]]));
+        if data.len() < 4 + len {
+            break;
+        }
+        options.push((code, &data[4..4 + len]));
+        data = &data[4 + len..];
+    }
+    options
+}
+
+fn push_option(message: &mut Vec<u8>, code: u16, data: &[u8]) {
+    message.extend_from_slice(&code.to_be_bytes());
+    message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    message.extend_from_slice(data);
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes(data[..4].try_into().unwrap())
+}
+
+/// A prefix delegated to the router, and the /64 taken from it for each LAN, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delegation {
+    pub prefix: Subnet,
+    pub lan_prefixes: Vec<Subnet>,
+    pub valid_until: Instant,
+}
+
+/// The prefix a `Dhcpv6PdClientComposite` holds, for the links that number the LANs from it, such
+/// as the `RaDaemonComposite` of each. There is none while no prefix is delegated, or once it
+/// expires without being renewed.
+#[derive(Default)]
+pub struct DelegatedPrefix {
+    delegation: RwLock<Option<Delegation>>,
+}
+
+impl DelegatedPrefix {
+    pub fn new() -> Self {
+        DelegatedPrefix {
+            delegation: RwLock::new(None),
+        }
+    }
+
+    pub fn delegation(&self) -> Option<Delegation> {
+        self.delegation.read().unwrap().clone()
+    }
+
+    /// The /64 of the LAN at `index`, if the delegated prefix has one for it.
+    pub fn lan_prefix(&self, index: usize) -> Option<Subnet> {
+        self.delegation
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|delegation| delegation.lan_prefixes.get(index).copied())
+    }
+
+    fn set(&self, delegation: Option<Delegation>) {
+        *self.delegation.write().unwrap() = delegation;
+    }
+}
+
+/// The /64s of a prefix, the first `count` of them, or as many as it holds.
+fn lan_prefixes(prefix: Ipv6Addr, prefix_len: u8, count: usize) -> Vec<Subnet> {
+    if prefix_len > 64 {
+        return vec![];
+    }
+    let mask = u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0);
+    let network = u128::from(prefix) & mask;
+    let available = 1u128 << (64 - prefix_len);
+    (0..count as u128)
+        .take_while(|index| *index < available)
+        .map(|index| Subnet {
+            address: IpAddr::V6(Ipv6Addr::from(network | index << 64)),
+            prefix_len: 64,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Soliciting,
+    Requesting,
+    Bound,
+    Renewing,
+    Rebinding,
+}
+
+/// The prefix a server offered or delegated, and when to renew it.
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    prefix: Ipv6Addr,
+    prefix_len: u8,
+    t1: Duration,
+    t2: Duration,
+    valid: Duration,
+}
+
+/// The prefix of the IA_PD of a message, if it has one the client may use, RFC 8415 section 18.2.
+fn offered_lease(message: &[(u16, &[u8])], iaid: u32) -> Option<Lease> {
+    let (_, ia_pd) = message
+        .iter()
+        .find(|(code, data)| *code == OPTION_IA_PD && data.len() >= 12 && read_u32(data) == iaid)?;
+    let ia_options = options(&ia_pd[12..]);
+    let failed = ia_options.iter().any(|(code, data)| {
+        *code == OPTION_STATUS_CODE
+            && data.len() >= 2
+            && u16::from_be_bytes([data[0], data[1]]) != STATUS_SUCCESS
+    });
+    if failed {
+        return None;
+    }
+    let (_, prefix) = ia_options.iter().find(|(code, data)| {
+        *code == OPTION_IAPREFIX && data.len() >= 25 && read_u32(&data[4..]) > 0
+    })?;
+    let preferred = read_u32(prefix);
+    let valid = read_u32(&prefix[4..]);
+    let prefix_len = prefix[8];
+    let address: [u8; 16] = prefix[9..25].try_into().unwrap();
+
+    // Servers leave T1 and T2 to the client by sending zero, RFC 8415 section 21.21.
+    let t1 = match read_u32(&ia_pd[4..]) {
+        0 => preferred / 2,
+        t1 => t1,
+    };
+    let t2 = match read_u32(&ia_pd[8..]) {
+        0 => preferred / 5 * 4,
+        t2 => t2,
+    };
+    Some(Lease {
+        prefix: Ipv6Addr::from(address),
+        prefix_len,
+        t1: Duration::from_secs(u64::from(t1)),
+        t2: Duration::from_secs(u64::from(t2.max(t1))),
+        valid: Duration::from_secs(u64::from(valid)),
+    })
+}
+
+/// The client side of DHCPv6 prefix delegation, RFC 8415. It solicits a prefix, requests it from
+/// the first server to advertise one, and renews it with that server at T1, or any server at T2,
+/// until it expires, when it starts over.
+struct Dhcpv6Client {
+    client_addr: Ipv6Addr,
+    duid: Vec<u8>,
+    iaid: u32,
+    prefix_len_hint: Option<u8>,
+    lan_count: usize,
+    delegated: Arc<DelegatedPrefix>,
+    state: State,
+    transaction_id: [u8; 3],
+    server_id: Option<Vec<u8>>,
+    offered: Option<Lease>,
+    bound: Option<(Lease, Instant)>,
+    exchange_started: Instant,
+    next_send: Instant,
+    timeout: Duration,
+    attempts: u32,
+    event_sink: Option<EventSink>,
+}
+
+impl Dhcpv6Client {
+    fn new(
+        client_addr: Ipv6Addr,
+        mac_addr: MacAddr,
+        prefix_len_hint: Option<u8>,
+        lan_count: usize,
+        delegated: Arc<DelegatedPrefix>,
+        now: Instant,
+    ) -> Self {
+        let mut duid = vec![];
+        duid.extend_from_slice(&DUID_LL.to_be_bytes());
+        duid.extend_from_slice(&HARDWARE_TYPE_ETHERNET.to_be_bytes());
+        duid.extend_from_slice(&mac_addr.bytes);
+        let mut client = Dhcpv6Client {
+            client_addr,
+            duid,
+            iaid: 1,
+            prefix_len_hint,
+            lan_count,
+            delegated,
+            state: State::Soliciting,
+            transaction_id: [0; 3],
+            server_id: None,
+            offered: None,
+            bound: None,
+            exchange_started: now,
+            next_send: now,
+            timeout: SOL_TIMEOUT,
+            attempts: 0,
+            event_sink: None,
+        };
+        client.begin(State::Soliciting, now);
+        client
+    }
+
+    /// Starts a new exchange, whose first message is sent right away.
+    fn begin(&mut self, state: State, now: Instant) {
+        self.state = state;
+        self.transaction_id = rand::random();
+        self.exchange_started = now;
+        self.next_send = now;
+        self.attempts = 0;
+        self.timeout = match state {
+            State::Soliciting => SOL_TIMEOUT,
+            State::Requesting => REQ_TIMEOUT,
+            State::Renewing => REN_TIMEOUT,
+            State::Rebinding => REB_TIMEOUT,
+            State::Bound => Duration::from_secs(0),
+        };
+        if state == State::Soliciting {
+            self.server_id = None;
+            self.offered = None;
+        }
+    }
+
+    /// When the client next has something to do, should nothing arrive before then.
+    fn deadline(&self) -> Instant {
+        let expiry = self.bound.map(|(lease, bound_at)| bound_at + lease.valid);
+        let next = match (self.state, self.bound) {
+            (State::Bound, Some((lease, bound_at))) => bound_at + lease.t1,
+            (State::Renewing, Some((lease, bound_at))) => self.next_send.min(bound_at + lease.t2),
+            _ => self.next_send,
+        };
+        expiry.map_or(next, |expiry| next.min(expiry))
+    }
+
+    /// Moves the client on as time passes, returning the message it sends, if any.
+    fn poll_timers(&mut self, now: Instant) -> Option<Ipv6Packet> {
+        if let Some((lease, bound_at)) = self.bound {
+            if now >= bound_at + lease.valid {
+                self.bound = None;
+                self.delegated.set(None);
+                self.report(format!(
+                    "delegated prefix {}/{} expired",
+                    lease.prefix, lease.prefix_len
+                ));
+                self.begin(State::Soliciting, now);
+            } else if self.state == State::Bound && now >= bound_at + lease.t1 {
+                self.begin(State::Renewing, now);
+            } else if self.state == State::Renewing && now >= bound_at + lease.t2 {
+                self.begin(State::Rebinding, now);
+            }
+        }
+        if self.state == State::Bound || now < self.next_send {
+            return None;
+        }
+
+        self.attempts += 1;
+        if self.state == State::Requesting && self.attempts > REQ_MAX_RC {
+            self.begin(State::Soliciting, now);
+            self.attempts = 1;
+        }
+        let max_timeout = match self.state {
+            State::Soliciting => SOL_MAX_RT,
+            State::Requesting => REQ_MAX_RT,
+            State::Renewing => REN_MAX_RT,
+            _ => REB_MAX_RT,
+        };
+        self.next_send = now + self.timeout;
+        self.timeout = (self.timeout * 2).min(max_timeout);
+        Some(self.message(now))
+    }
+
+    fn message(&self, now: Instant) -> Ipv6Packet {
+        let message_type = match self.state {
+            State::Soliciting => SOLICIT,
+            State::Requesting => REQUEST,
+            State::Renewing => RENEW,
+            _ => REBIND,
+        };
+        let mut message = vec![message_type];
+        message.extend_from_slice(&self.transaction_id);
+        push_option(&mut message, OPTION_CLIENTID, &self.duid);
+        if let (State::Requesting, Some(server_id)) | (State::Renewing, Some(server_id)) =
+            (self.state, &self.server_id)
+        {
+            push_option(&mut message, OPTION_SERVERID, server_id);
+        }
+        let elapsed = now
+            .saturating_duration_since(self.exchange_started)
+            .as_millis()
+            / 10;
+        let elapsed = elapsed.min(0xffff) as u16;
+        push_option(&mut message, OPTION_ELAPSED_TIME, &elapsed.to_be_bytes());
+
+        // The IA_PD names the prefix being requested or renewed, or hints at the length wanted.
+        let mut ia_pd = vec![];
+        ia_pd.extend_from_slice(&self.iaid.to_be_bytes());
+        ia_pd.extend_from_slice(&[0; 8]);
+        let lease = match self.state {
+            State::Requesting => self.offered,
+            State::Renewing | State::Rebinding => self.bound.map(|(lease, _)| lease),
+            _ => None,
+        };
+        let hint = match (lease, self.prefix_len_hint) {
+            (Some(lease), _) => Some((lease.prefix_len, lease.prefix)),
+            (None, Some(prefix_len)) => Some((prefix_len, Ipv6Addr::UNSPECIFIED)),
+            (None, None) => None,
+        };
+        if let Some((prefix_len, prefix)) = hint {
+            let mut ia_prefix = vec![0; 8];
+            ia_prefix.push(prefix_len);
+            ia_prefix.extend_from_slice(&prefix.octets());
+            push_option(&mut ia_pd, OPTION_IAPREFIX, &ia_prefix);
+        }
+        push_option(&mut message, OPTION_IA_PD, &ia_pd);
+
+        let mut datagram = vec![];
+        datagram.extend_from_slice(&DHCPV6_CLIENT_PORT.to_be_bytes());
+        datagram.extend_from_slice(&DHCPV6_SERVER_PORT.to_be_bytes());
+        datagram.extend_from_slice(&((8 + message.len()) as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(&message);
+        let checksum = match ipv6_pseudo_header_checksum(
+            self.client_addr,
+            ALL_DHCP_RELAY_AGENTS_AND_SERVERS,
+            17,
+            &datagram,
+        ) {
+            // A zero checksum is sent as all ones, RFC 768.
+            0 => 0xffff,
+            checksum => checksum,
+        };
+        datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(17);
+        packet.set_hop_limit(1);
+        packet.set_src_addr(self.client_addr);
+        packet.set_dest_addr(ALL_DHCP_RELAY_AGENTS_AND_SERVERS);
+        packet.set_payload(&datagram);
+        packet
+    }
+
+    /// Handles a packet from the WAN. Anything but a reply to the client's current exchange is
+    /// ignored.
+    fn receive(&mut self, packet: &Ipv6Packet, now: Instant) {
+        if packet.next_header() != IpProtocol::UDP {
+            return;
+        }
+        let datagram = packet.payload();
+        if datagram.len() < 12
+            || u16::from_be_bytes([datagram[2], datagram[3]]) != DHCPV6_CLIENT_PORT
+            || datagram[9..12] != self.transaction_id
+        {
+            return;
+        }
+        let message_type = datagram[8];
+        let message = options(&datagram[12..]);
+        let for_us = message
+            .iter()
+            .any(|(code, data)| *code == OPTION_CLIENTID && *data == &self.duid[..]);
+        let server_id = message
+            .iter()
+            .find(|(code, _)| *code == OPTION_SERVERID)
+            .map(|(_, data)| data.to_vec());
+        if !for_us || server_id.is_none() {
+            return;
+        }
+
+        match (self.state, message_type) {
+            (State::Soliciting, ADVERTISE) => {
+                if let Some(lease) = offered_lease(&message, self.iaid) {
+                    self.begin(State::Requesting, now);
+                    self.server_id = server_id;
+                    self.offered = Some(lease);
+                }
+            }
+            (State::Requesting, REPLY) | (State::Renewing, REPLY) | (State::Rebinding, REPLY) => {
+                match offered_lease(&message, self.iaid) {
+                    Some(lease) => {
+                        self.server_id = server_id;
+                        self.bind(lease, now);
+                    }
+                    // The server has no prefix for the client after all.
+                    None if self.state == State::Requesting => self.begin(State::Soliciting, now),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn bind(&mut self, lease: Lease, now: Instant) {
+        let renewed = self.bound.as_ref().is_some_and(|(bound, _)| {
+            (bound.prefix, bound.prefix_len) == (lease.prefix, lease.prefix_len)
+        });
+        self.report(format!(
+            "{} prefix {}/{} for {}s",
+            if renewed { "renewed" } else { "delegated" },
+            lease.prefix,
+            lease.prefix_len,
+            lease.valid.as_secs()
+        ));
+        self.state = State::Bound;
+        self.offered = None;
+        self.bound = Some((lease, now));
+        self.delegated.set(Some(Delegation {
+            prefix: Subnet {
+                address: IpAddr::V6(lease.prefix),
+                prefix_len: lease.prefix_len,
+            },
+            lan_prefixes: lan_prefixes(lease.prefix, lease.prefix_len, self.lan_count),
+            valid_until: now + lease.valid,
+        }));
+    }
+}
+
+impl Dhcpv6Client {
+    fn report(&self, activity: String) {
+        if let Some(event_sink) = &self.event_sink {
+            event_sink.activity(activity);
+        }
+    }
+}
+
+struct Dhcpv6PdRunner {
+    in_stream: PacketStream<Ipv6Packet>,
+    client: Dhcpv6Client,
+    timer: Delay,
+}
+
+impl Unpin for Dhcpv6PdRunner {}
+
+impl Stream for Dhcpv6PdRunner {
+    type Item = Ipv6Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let runner = &mut *self;
+        loop {
+            if let Some(packet) = runner.client.poll_timers(clock::now()) {
+                return Poll::Ready(Some(packet));
+            }
+            match Pin::new(&mut runner.in_stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    runner.client.receive(&packet, clock::now());
+                    continue;
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => {}
+            }
+
+            let deadline = tokio::time::Instant::from_std(runner.client.deadline());
+            runner.timer.reset(deadline);
+            if Pin::new(&mut runner.timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Acquires an IPv6 prefix for the LANs with DHCPv6 prefix delegation, RFC 8415, on the WAN.
+///
+/// The composite takes the IPv6 packets the router receives for DHCPv6 clients on the WAN, UDP
+/// port 546, and sends its own messages to all DHCPv6 servers. It solicits a prefix as soon as it
+/// starts, and keeps the first one delegated renewed until its ingressor ends.
+///
+/// The delegated prefix is published in a `DelegatedPrefix`, shared in the `StateStore` under
+/// `DELEGATED_PREFIX_STATE` unless one is given, along with a /64 from it for each of
+/// `lan_prefixes` LANs, for their Router Advertisements.
+pub struct Dhcpv6PdClientComposite {
+    in_stream: Option<PacketStream<Ipv6Packet>>,
+    client_addr: Option<Ipv6Addr>,
+    mac_addr: Option<MacAddr>,
+    prefix_len_hint: Option<u8>,
+    lan_prefixes: usize,
+    delegated: Option<Arc<DelegatedPrefix>>,
+    event_sink: Option<EventSink>,
+}
+
+impl Default for Dhcpv6PdClientComposite {
+    fn default() -> Self {
+        Dhcpv6PdClientComposite::new()
+    }
+}
+
+impl Dhcpv6PdClientComposite {
+    pub fn new() -> Self {
+        Dhcpv6PdClientComposite {
+            in_stream: None,
+            client_addr: None,
+            mac_addr: None,
+            prefix_len_hint: None,
+            lan_prefixes: 1,
+            delegated: None,
+            event_sink: None,
+        }
+    }
+
+    /// The router's link-local address on the WAN, which messages are sent from.
+    pub fn client_addr(self, client_addr: Ipv6Addr) -> Self {
+        Dhcpv6PdClientComposite {
+            in_stream: self.in_stream,
+            client_addr: Some(client_addr),
+            mac_addr: self.mac_addr,
+            prefix_len_hint: self.prefix_len_hint,
+            lan_prefixes: self.lan_prefixes,
+            delegated: self.delegated,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// The router's MAC address on the WAN, which identifies it to servers.
+    pub fn mac_addr(self, mac_addr: MacAddr) -> Self {
+        Dhcpv6PdClientComposite {
+            in_stream: self.in_stream,
+            client_addr: self.client_addr,
+            mac_addr: Some(mac_addr),
+            prefix_len_hint: self.prefix_len_hint,
+            lan_prefixes: self.lan_prefixes,
+            delegated: self.delegated,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// The prefix length to ask servers for, such as 56. Servers may delegate another.
+    pub fn prefix_len_hint(self, prefix_len: u8) -> Self {
+        assert!(prefix_len <= 64, "prefix_len must be <= 64");
+        Dhcpv6PdClientComposite {
+            in_stream: self.in_stream,
+            client_addr: self.client_addr,
+            mac_addr: self.mac_addr,
+            prefix_len_hint: Some(prefix_len),
+            lan_prefixes: self.lan_prefixes,
+            delegated: self.delegated,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes lan_prefixes, the number of /64s to take from the delegated prefix, one for each
+    /// LAN, default value is 1.
+    pub fn lan_prefixes(self, lan_prefixes: usize) -> Self {
+        Dhcpv6PdClientComposite {
+            in_stream: self.in_stream,
+            client_addr: self.client_addr,
+            mac_addr: self.mac_addr,
+            prefix_len_hint: self.prefix_len_hint,
+            lan_prefixes,
+            delegated: self.delegated,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Publishes the delegated prefix here rather than in the `StateStore`.
+    pub fn delegated_prefix(self, delegated: Arc<DelegatedPrefix>) -> Self {
+        Dhcpv6PdClientComposite {
+            in_stream: self.in_stream,
+            client_addr: self.client_addr,
+            mac_addr: self.mac_addr,
+            prefix_len_hint: self.prefix_len_hint,
+            lan_prefixes: self.lan_prefixes,
+            delegated: Some(delegated),
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Reports each prefix delegated, renewed or lost to `event_sink`, as a
+    /// `LinkEvent::Activity`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        Dhcpv6PdClientComposite {
+            in_stream: self.in_stream,
+            client_addr: self.client_addr,
+            mac_addr: self.mac_addr,
+            prefix_len_hint: self.prefix_len_hint,
+            lan_prefixes: self.lan_prefixes,
+            delegated: self.delegated,
+            event_sink: Some(event_sink),
+        }
+    }
+}
+
+impl LinkBuilder<Ipv6Packet, Ipv6Packet> for Dhcpv6PdClientComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv6Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "Dhcpv6PdClientComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv6Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "Dhcpv6PdClientComposite may only take 1 input stream",
+            ));
+        }
+        Ok(Dhcpv6PdClientComposite {
+            in_stream: Some(in_stream),
+            client_addr: self.client_addr,
+            mac_addr: self.mac_addr,
+            prefix_len_hint: self.prefix_len_hint,
+            lan_prefixes: self.lan_prefixes,
+            delegated: self.delegated,
+            event_sink: self.event_sink,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv6Packet>, LinkBuildError> {
+        match (self.in_stream, self.client_addr, self.mac_addr) {
+            (None, _, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None, _) => Err(LinkBuildError::Missing("client_addr")),
+            (_, _, None) => Err(LinkBuildError::Missing("mac_addr")),
+            (Some(in_stream), Some(client_addr), Some(mac_addr)) => {
+                let delegated = self.delegated.unwrap_or_else(|| {
+                    StateStore::global()
+                        .get_or_insert_with(DELEGATED_PREFIX_STATE, DelegatedPrefix::new)
+                });
+                let now = clock::now();
+                Ok((
+                    vec![],
+                    vec![Box::new(Dhcpv6PdRunner {
+                        in_stream,
+                        client: Dhcpv6Client {
+                            event_sink: self.event_sink,
+                            ..Dhcpv6Client::new(
+                                client_addr,
+                                mac_addr,
+                                self.prefix_len_hint,
+                                self.lan_prefixes,
+                                delegated,
+                                now,
+                            )
+                        },
+                        timer: delay_until(tokio::time::Instant::from_std(now)),
+                    })],
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    const CLIENT_ADDR: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0x10);
+    const MAC_ADDR: MacAddr = MacAddr {
+        bytes: [0x02, 0, 0, 0, 0, 0x10],
+    };
+    const SERVER_DUID: &[u8] = &[0, 3, 0, 1, 0x02, 0, 0, 0, 0, 0x01];
+
+    fn client(delegated: &Arc<DelegatedPrefix>, now: Instant) -> Dhcpv6Client {
+        Dhcpv6Client::new(
+            CLIENT_ADDR,
+            MAC_ADDR,
+            Some(56),
+            2,
+            Arc::clone(delegated),
+            now,
+        )
+    }
+
+    /// A message from the server, answering `request`, that delegates a /56 with a T1 of 100
+    /// seconds, a T2 of 160 and a valid lifetime of 200.
+    fn answer(request: &Ipv6Packet, message_type: u8) -> Ipv6Packet {
+        let request = request.payload();
+        let mut message = vec![message_type];
+        message.extend_from_slice(&request[9..12]);
+        let client_id = options(&request[12..])
+            .into_iter()
+            .find(|(code, _)| *code == OPTION_CLIENTID)
+            .unwrap()
+            .1;
+        push_option(&mut message, OPTION_CLIENTID, client_id);
+        push_option(&mut message, OPTION_SERVERID, SERVER_DUID);
+        let mut ia_pd = vec![0, 0, 0, 1, 0, 0, 0, 100, 0, 0, 0, 160];
+        let mut ia_prefix = vec![0, 0, 0, 150, 0, 0, 0, 200, 56];
+        ia_prefix.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0x1200, 0, 0, 0, 0, 0).octets());
+        push_option(&mut ia_pd, OPTION_IAPREFIX, &ia_prefix);
+        push_option(&mut message, OPTION_IA_PD, &ia_pd);
+
+        let mut datagram = vec![];
+        datagram.extend_from_slice(&DHCPV6_SERVER_PORT.to_be_bytes());
+        datagram.extend_from_slice(&DHCPV6_CLIENT_PORT.to_be_bytes());
+        datagram.extend_from_slice(&((8 + message.len()) as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(&message);
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(17);
+        packet.set_src_addr(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+        packet.set_dest_addr(CLIENT_ADDR);
+        packet.set_payload(&datagram);
+        packet
+    }
+
+    fn message_type(packet: &Ipv6Packet) -> u8 {
+        packet.payload()[8]
+    }
+
+    fn has_server_id(packet: &Ipv6Packet) -> bool {
+        options(&packet.payload()[12..])
+            .iter()
+            .any(|(code, data)| *code == OPTION_SERVERID && *data == SERVER_DUID)
+    }
+
+    #[test]
+    fn delegates_prefix() {
+        let delegated = Arc::new(DelegatedPrefix::new());
+        let now = Instant::now();
+        let mut client = client(&delegated, now);
+
+        let solicit = client.poll_timers(now).unwrap();
+        assert_eq!(message_type(&solicit), SOLICIT);
+        assert_eq!(
+            solicit.dest_addr(),
+            Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2)
+        );
+        let datagram = solicit.payload();
+        assert_eq!(
+            ipv6_pseudo_header_checksum(CLIENT_ADDR, solicit.dest_addr(), 17, &datagram),
+            0
+        );
+
+        client.receive(&answer(&solicit, ADVERTISE), now);
+        let request = client.poll_timers(now).unwrap();
+        assert_eq!(message_type(&request), REQUEST);
+        assert!(has_server_id(&request));
+        assert!(delegated.delegation().is_none());
+
+        client.receive(&answer(&request, REPLY), now);
+        let delegation = delegated.delegation().unwrap();
+        assert_eq!(delegation.prefix, "2001:db8:1200::/56".parse().unwrap());
+        assert_eq!(
+            delegation.lan_prefixes,
+            vec![
+                "2001:db8:1200::/64".parse().unwrap(),
+                "2001:db8:1200:1::/64".parse().unwrap(),
+            ]
+        );
+        assert_eq!(delegated.lan_prefix(2), None);
+        assert!(client.poll_timers(now + Duration::from_secs(99)).is_none());
+    }
+
+    #[test]
+    fn ignores_other_transactions() {
+        let delegated = Arc::new(DelegatedPrefix::new());
+        let now = Instant::now();
+        let mut client = client(&delegated, now);
+
+        let mut solicit = client.poll_timers(now).unwrap();
+        let mut other = solicit.payload().to_vec();
+        other[9] ^= 0xff;
+        solicit.set_payload(&other);
+        client.receive(&answer(&solicit, ADVERTISE), now);
+
+        assert_eq!(client.state, State::Soliciting);
+        assert!(client.poll_timers(now).is_none());
+        let again = client.poll_timers(now + Duration::from_secs(1)).unwrap();
+        assert_eq!(message_type(&again), SOLICIT);
+    }
+
+    #[test]
+    fn renews_then_rebinds_then_starts_over() {
+        let delegated = Arc::new(DelegatedPrefix::new());
+        let now = Instant::now();
+        let mut client = client(&delegated, now);
+        let solicit = client.poll_timers(now).unwrap();
+        client.receive(&answer(&solicit, ADVERTISE), now);
+        let request = client.poll_timers(now).unwrap();
+        client.receive(&answer(&request, REPLY), now);
+        assert_eq!(client.deadline(), now + Duration::from_secs(100));
+
+        let renew = client.poll_timers(now + Duration::from_secs(100)).unwrap();
+        assert_eq!(message_type(&renew), RENEW);
+        assert!(has_server_id(&renew));
+
+        let rebind = client.poll_timers(now + Duration::from_secs(160)).unwrap();
+        assert_eq!(message_type(&rebind), REBIND);
+        assert!(!has_server_id(&rebind));
+
+        let solicit = client.poll_timers(now + Duration::from_secs(200)).unwrap();
+        assert_eq!(message_type(&solicit), SOLICIT);
+        assert!(delegated.delegation().is_none());
+    }
+
+    #[test]
+    fn solicits_when_started() {
+        let delegated = Arc::new(DelegatedPrefix::new());
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = Dhcpv6PdClientComposite::new()
+                .ingressor(immediate_stream(vec![]))
+                .client_addr(CLIENT_ADDR)
+                .mac_addr(MAC_ADDR)
+                .delegated_prefix(Arc::clone(&delegated))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 1);
+        assert_eq!(message_type(&results[0][0]), SOLICIT);
+    }
+}
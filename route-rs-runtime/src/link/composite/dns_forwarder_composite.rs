@@ -0,0 +1,1609 @@
+use crate::classifier::Classifier;
+use crate::config::Subnet;
+use crate::link::composite::datagram::{datagram, udp};
+use crate::link::event::{DropAccounting, DropCounters, DropReason, EventSink};
+use crate::link::primitive::{ClassifyLink, ProcessLink};
+use crate::link::{
+    Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder, TokioRunnable,
+};
+use crate::processor::{Processor, ProcessorContext};
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::{
+    DnsMessage, DnsQuestion, DnsSection, Ipv4Packet, DNS_CLASS_IN, DNS_PORT, DNS_RCODE_NOERROR,
+    DNS_RCODE_NXDOMAIN, DNS_TYPE_A, DNS_TYPE_AAAA, DNS_TYPE_OPT, DNS_TYPE_SOA,
+};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::timeout;
+
+/// How long a query forwarded upstream waits for its response before it is forgotten.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a query sent over TLS or HTTPS may take before it is sent again over UDP.
+const SECURE_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Connections to an upstream server kept open between queries over TLS or HTTPS.
+#[cfg(any(feature = "dns-over-tls", feature = "dns-over-https"))]
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+/// Queries are forwarded upstream from a random port in the dynamic range, 49152 and up, so
+/// responses are harder to spoof, RFC 5452.
+const QUERY_PORT_BASE: u16 = 0xc000;
+
+/// The TTL of answers given by a `DnsPolicy`, short so clients see changes to its rules soon.
+const POLICY_TTL: u32 = 60;
+
+type CacheKey = (String, u16);
+
+struct CachedResponse {
+    response: DnsMessage,
+    stored: Instant,
+    ttl: Duration,
+    last_used: u64,
+}
+
+/// Responses to questions, kept for as long as their records live, RFC 1035 section 7.4, or for
+/// negative responses, as long as their zone's SOA says, RFC 2308. Truncated responses and
+/// failures are not kept. When the cache is full, the response used longest ago is evicted.
+pub struct DnsCache {
+    capacity: usize,
+    max_ttl: Duration,
+    max_negative_ttl: Duration,
+    entries: HashMap<CacheKey, CachedResponse>,
+    recency: BTreeMap<u64, CacheKey>,
+    uses: u64,
+}
+
+impl DnsCache {
+    pub fn new(capacity: usize, max_ttl: Duration, max_negative_ttl: Duration) -> Self {
+        assert!(capacity > 0, "capacity must be > 0");
+        DnsCache {
+            capacity,
+            max_ttl,
+            max_negative_ttl,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            uses: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// A copy of the response cached for a question, with the TTLs of its records counted down
+    /// by the time it has been cached. Its ID is that of the query that was cached.
+    pub fn get(&mut self, question: &DnsQuestion, now: Instant) -> Option<DnsMessage> {
+        let key = cache_key(question);
+        let entry = self.entries.get_mut(&key)?;
+        let age = now.saturating_duration_since(entry.stored);
+        if age >= entry.ttl {
+            self.recency.remove(&entry.last_used);
+            self.entries.remove(&key);
+            return None;
+        }
+
+        self.uses += 1;
+        self.recency.remove(&entry.last_used);
+        self.recency.insert(self.uses, key);
+        entry.last_used = self.uses;
+
+        let mut response = entry.response.clone();
+        let age = age.as_secs() as u32;
+        for record in entry.response.records() {
+            if record.rtype != DNS_TYPE_OPT {
+                response.set_ttl(&record, record.ttl.saturating_sub(age));
+            }
+        }
+        Some(response)
+    }
+
+    /// Caches a response, if it may be. Returns the number of responses evicted to make room for
+    /// it, or none if it was not cached.
+    pub fn insert(&mut self, response: &DnsMessage, now: Instant) -> Option<usize> {
+        let question = response.question()?;
+        let ttl = self.ttl(response)?;
+        let key = cache_key(&question);
+        if let Some(replaced) = self.entries.remove(&key) {
+            self.recency.remove(&replaced.last_used);
+        }
+
+        let mut evicted = 0;
+        while self.entries.len() >= self.capacity {
+            let (&oldest, _) = self.recency.iter().next()?;
+            let key = self.recency.remove(&oldest).unwrap();
+            self.entries.remove(&key);
+            evicted += 1;
+        }
+
+        self.uses += 1;
+        self.recency.insert(self.uses, key.clone());
+        self.entries.insert(
+            key,
+            CachedResponse {
+                response: response.clone(),
+                stored: now,
+                ttl,
+                last_used: self.uses,
+            },
+        );
+        Some(evicted)
+    }
+
+    /// How long a response may be cached, if it may be at all.
+    fn ttl(&self, response: &DnsMessage) -> Option<Duration> {
+        if !response.is_response() || response.is_truncated() {
+            return None;
+        }
+        let records = response.records();
+        let min_answer_ttl = records
+            .iter()
+            .filter(|record| record.section == DnsSection::Answer)
+            .map(|record| record.ttl)
+            .min();
+        let ttl = match (response.rcode(), min_answer_ttl) {
+            (DNS_RCODE_NOERROR, Some(ttl)) => Duration::from_secs(u64::from(ttl)).min(self.max_ttl),
+            (DNS_RCODE_NOERROR, None) | (DNS_RCODE_NXDOMAIN, _) => {
+                // Negative responses live as long as the smaller of the SOA's TTL and its
+                // minimum field, RFC 2308 section 5. Without a SOA they are not cached.
+                let soa = records.iter().find(|record| {
+                    record.section == DnsSection::Authority
+                        && record.rtype == DNS_TYPE_SOA
+                        && record.data.len() >= 20
+                })?;
+                let minimum = &soa.data[soa.data.len() - 4..];
+                let minimum = u32::from_be_bytes([minimum[0], minimum[1], minimum[2], minimum[3]]);
+                Duration::from_secs(u64::from(soa.ttl.min(minimum))).min(self.max_negative_ttl)
+            }
+            _ => return None,
+        };
+        if ttl == Duration::from_secs(0) {
+            None
+        } else {
+            Some(ttl)
+        }
+    }
+}
+
+/// What a `DnsPolicy` does with a query for a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsAction {
+    /// Answers that the name does not exist.
+    Block,
+    /// Answers with these addresses, those of the family asked for, in place of whatever the
+    /// upstream server would. Lists sinkhole names by answering with an unroutable address, such
+    /// as 0.0.0.0.
+    Rewrite(Vec<IpAddr>),
+    /// Forwards the query, whatever a rule for a parent domain says.
+    Allow,
+}
+
+#[derive(Debug, Clone)]
+struct DnsRule {
+    action: DnsAction,
+    subdomains: bool,
+}
+
+/// Rules for names, which may cover a name alone or its subdomains too. The rule for a name
+/// itself wins over rules for its parents, and those for nearer parents over those for further.
+///
+/// Lists are read from hosts files, where an entry rewrites the names on its line, Adblock Plus
+/// filters of the form `||example.com^`, which block a domain and its subdomains, or
+/// `@@||example.com^`, which allow them, and bare names, which are blocked. Other lines, and
+/// comments, are skipped, so lists written for other blockers can be read as they are.
+#[derive(Debug, Clone, Default)]
+pub struct DnsRuleList {
+    rules: HashMap<String, DnsRule>,
+}
+
+impl DnsRuleList {
+    pub fn new() -> Self {
+        DnsRuleList {
+            rules: HashMap::new(),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(DnsRuleList::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(list: &str) -> Self {
+        let mut rules = DnsRuleList::new();
+        for line in list.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+                continue;
+            }
+            if let Some(filter) = line.strip_prefix("@@||") {
+                if let Some(name) = filter.strip_suffix('^') {
+                    rules.insert(name, DnsAction::Allow, true);
+                }
+            } else if let Some(filter) = line.strip_prefix("||") {
+                if let Some(name) = filter.strip_suffix('^') {
+                    rules.insert(name, DnsAction::Block, true);
+                }
+            } else {
+                let mut fields = line.split_whitespace();
+                let first = fields.next().unwrap();
+                match first.parse::<IpAddr>() {
+                    Ok(addr) => {
+                        for name in fields {
+                            rules.rewrite(name, addr);
+                        }
+                    }
+                    Err(_) if fields.next().is_none() && first.contains('.') => {
+                        rules.insert(first, DnsAction::Block, false)
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+        rules
+    }
+
+    /// Blocks a name, along with its subdomains if `subdomains` is set.
+    pub fn block(&mut self, name: &str, subdomains: bool) {
+        self.insert(name, DnsAction::Block, subdomains)
+    }
+
+    /// Allows a name and its subdomains, whatever rules for its parents say.
+    pub fn allow(&mut self, name: &str) {
+        self.insert(name, DnsAction::Allow, true)
+    }
+
+    /// Adds an address to answer queries for a name with.
+    pub fn rewrite(&mut self, name: &str, addr: IpAddr) {
+        let name = normalize(name);
+        match self.rules.get_mut(&name) {
+            Some(DnsRule {
+                action: DnsAction::Rewrite(addrs),
+                ..
+            }) => {
+                if !addrs.contains(&addr) {
+                    addrs.push(addr)
+                }
+            }
+            _ => {
+                self.rules.insert(
+                    name,
+                    DnsRule {
+                        action: DnsAction::Rewrite(vec![addr]),
+                        subdomains: false,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Adds the rules of another list, which win over rules of this one for the same names.
+    pub fn extend(&mut self, other: DnsRuleList) {
+        self.rules.extend(other.rules)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The action of the rule that covers a name, if any does.
+    pub fn action(&self, name: &str) -> Option<&DnsAction> {
+        let name = normalize(name);
+        if let Some(rule) = self.rules.get(&name) {
+            return Some(&rule.action);
+        }
+        name.match_indices('.')
+            .filter_map(|(dot, _)| self.rules.get(&name[dot + 1..]))
+            .find(|rule| rule.subdomains)
+            .map(|rule| &rule.action)
+    }
+
+    fn insert(&mut self, name: &str, action: DnsAction, subdomains: bool) {
+        self.rules
+            .insert(normalize(name), DnsRule { action, subdomains });
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// The rules a `DnsForwarderComposite` applies to queries, shared so that they can be replaced
+/// while the router runs, as when a list is downloaded again. Clients in a subnet given rules of
+/// their own get those in place of the default ones, from the most specific subnet that holds
+/// them.
+#[derive(Default)]
+pub struct DnsPolicy {
+    rules: RwLock<Arc<DnsRuleList>>,
+    client_rules: RwLock<Vec<(Subnet, Arc<DnsRuleList>)>>,
+}
+
+impl DnsPolicy {
+    pub fn new(rules: DnsRuleList) -> Self {
+        DnsPolicy {
+            rules: RwLock::new(Arc::new(rules)),
+            client_rules: RwLock::new(vec![]),
+        }
+    }
+
+    /// Replaces the default rules. Queries are held to the new rules as soon as this returns.
+    pub fn set_rules(&self, rules: DnsRuleList) {
+        *self.rules.write().unwrap() = Arc::new(rules);
+    }
+
+    /// Gives the clients of a subnet rules of their own, replacing any they had.
+    pub fn set_client_rules(&self, clients: Subnet, rules: DnsRuleList) {
+        let mut client_rules = self.client_rules.write().unwrap();
+        client_rules.retain(|(subnet, _)| *subnet != clients);
+        client_rules.push((clients, Arc::new(rules)));
+    }
+
+    /// Puts the clients of a subnet back under the default rules.
+    pub fn remove_client_rules(&self, clients: &Subnet) {
+        self.client_rules
+            .write()
+            .unwrap()
+            .retain(|(subnet, _)| subnet != clients);
+    }
+
+    /// What to do with a client's query for a name, or none to forward it.
+    pub fn action(&self, client: Ipv4Addr, name: &str) -> Option<DnsAction> {
+        let rules = self
+            .client_rules
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(subnet, _)| subnet.contains(IpAddr::V4(client)))
+            .max_by_key(|(subnet, _)| subnet.prefix_len)
+            .map(|(_, rules)| Arc::clone(rules))
+            .unwrap_or_else(|| Arc::clone(&self.rules.read().unwrap()));
+        rules.action(name).cloned()
+    }
+}
+
+/// The response a policy gives to a query in place of the upstream server's.
+fn policy_response(query: &DnsMessage, question: &DnsQuestion, action: &DnsAction) -> DnsMessage {
+    match action {
+        DnsAction::Rewrite(addrs) => {
+            let mut response = query.response(DNS_RCODE_NOERROR);
+            for addr in addrs {
+                match (addr, question.qtype) {
+                    (IpAddr::V4(addr), DNS_TYPE_A) => {
+                        response.add_answer(DNS_TYPE_A, DNS_CLASS_IN, POLICY_TTL, &addr.octets())
+                    }
+                    (IpAddr::V6(addr), DNS_TYPE_AAAA) => {
+                        response.add_answer(DNS_TYPE_AAAA, DNS_CLASS_IN, POLICY_TTL, &addr.octets())
+                    }
+                    _ => continue,
+                };
+            }
+            response
+        }
+        _ => query.response(DNS_RCODE_NXDOMAIN),
+    }
+}
+
+/// Names are compared without regard to case, RFC 4343.
+fn cache_key(question: &DnsQuestion) -> CacheKey {
+    (question.name.to_ascii_lowercase(), question.qtype)
+}
+
+/// How a `DnsForwarderComposite` sends queries to its upstream server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsTransport {
+    /// Plain DNS, over UDP port 53, out of the composite's first egressor.
+    Udp,
+    /// DNS over TLS, RFC 7858, to port 853 of the upstream server, whose certificate must be
+    /// valid for `server_name`.
+    #[cfg(feature = "dns-over-tls")]
+    Tls { server_name: String },
+    /// DNS over HTTPS, RFC 8484, posting queries to `url`, such as
+    /// `https://cloudflare-dns.com/dns-query`.
+    #[cfg(feature = "dns-over-https")]
+    Https { url: String },
+}
+
+type ExchangeFuture = Pin<Box<dyn Future<Output = io::Result<Vec<u8>>> + Send>>;
+
+/// Sends a query to the upstream server over a connection of the router's own, rather than as
+/// packets through the composite, and returns the response.
+type Exchange = Arc<dyn Fn(Vec<u8>) -> ExchangeFuture + Send + Sync>;
+
+#[cfg(feature = "dns-over-tls")]
+async fn tls_query(
+    stream: &mut tokio_rustls::client::TlsStream<tokio::net::TcpStream>,
+    framed: &[u8],
+) -> io::Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    stream.write_all(framed).await?;
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).await?;
+    let mut response = vec![0; usize::from(u16::from_be_bytes(len))];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+#[cfg(feature = "dns-over-tls")]
+fn tls_exchange(upstream: Ipv4Addr, server_name: &str) -> Result<Exchange, LinkBuildError> {
+    use std::sync::Mutex;
+    use tokio::net::TcpStream;
+    use tokio_rustls::client::TlsStream;
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::TlsConnector;
+
+    let server_name = webpki::DNSNameRef::try_from_ascii_str(server_name)
+        .map_err(|_| LinkBuildError::Invalid(format!("Invalid server name {}", server_name)))?
+        .to_owned();
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    let connector = TlsConnector::from(Arc::new(config));
+    let idle: Arc<Mutex<Vec<TlsStream<TcpStream>>>> = Arc::new(Mutex::new(vec![]));
+
+    Ok(Arc::new(move |query: Vec<u8>| {
+        let connector = connector.clone();
+        let server_name = server_name.clone();
+        let idle = Arc::clone(&idle);
+        Box::pin(async move {
+            // Each query is prefixed by its length, RFC 1035 section 4.2.2.
+            let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+            framed.extend_from_slice(&query);
+            let mut reused = idle.lock().unwrap().pop();
+            loop {
+                let fresh = reused.is_none();
+                let mut stream = match reused.take() {
+                    Some(stream) => stream,
+                    None => {
+                        let tcp = TcpStream::connect((upstream, 853)).await?;
+                        connector.connect(server_name.as_ref(), tcp).await?
+                    }
+                };
+                match tls_query(&mut stream, &framed).await {
+                    Ok(response) => {
+                        let mut idle = idle.lock().unwrap();
+                        if idle.len() < MAX_IDLE_CONNECTIONS {
+                            idle.push(stream);
+                        }
+                        return Ok(response);
+                    }
+                    Err(err) if fresh => return Err(err),
+                    // A connection left idle may have been closed by the server, in which case
+                    // the query is sent again over a new one.
+                    Err(_) => {}
+                }
+            }
+        }) as ExchangeFuture
+    }))
+}
+
+#[cfg(feature = "dns-over-https")]
+fn https_exchange(url: &str) -> Result<Exchange, LinkBuildError> {
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(MAX_IDLE_CONNECTIONS)
+        .build()
+        .map_err(|err| LinkBuildError::Invalid(err.to_string()))?;
+    let url = reqwest::Url::parse(url).map_err(|err| LinkBuildError::Invalid(err.to_string()))?;
+
+    Ok(Arc::new(move |query: Vec<u8>| {
+        let request = client
+            .post(url.clone())
+            .header("content-type", "application/dns-message")
+            .header("accept", "application/dns-message")
+            .body(query);
+        Box::pin(async move {
+            let to_io = |err: reqwest::Error| io::Error::other(err);
+            let response = request.send().await.map_err(to_io)?;
+            let response = response.error_for_status().map_err(to_io)?;
+            Ok(response.bytes().await.map_err(to_io)?.to_vec())
+        }) as ExchangeFuture
+    }))
+}
+
+/// Sends the queries given it over a secure transport, each in a task of its own so that a slow
+/// answer doesn't hold up the rest, and hands back their responses, or none if they failed.
+fn secure_upstream(
+    exchange: Exchange,
+    mut queries: UnboundedReceiver<(u16, DnsMessage)>,
+    responses: UnboundedSender<(u16, Option<DnsMessage>)>,
+) -> TokioRunnable {
+    Box::new(Box::pin(async move {
+        while let Some((id, query)) = queries.recv().await {
+            let exchange = Arc::clone(&exchange);
+            let responses = responses.clone();
+            tokio::spawn(async move {
+                let response = timeout(SECURE_QUERY_TIMEOUT, exchange(query.into_bytes()))
+                    .await
+                    .ok()
+                    .and_then(Result::ok)
+                    .and_then(|response| DnsMessage::from_bytes(response).ok());
+                // The forwarder may have stopped in the meantime.
+                let _ = responses.send((id, response));
+            });
+        }
+    }))
+}
+
+/// Whether `response` carries `question`, and no other, with the name compared regardless of
+/// case, as servers may echo it back in another.
+fn answers(response: &DnsMessage, question: &DnsQuestion) -> bool {
+    match response.questions().as_slice() {
+        [asked] => {
+            asked.name.eq_ignore_ascii_case(&question.name)
+                && asked.qtype == question.qtype
+                && asked.qclass == question.qclass
+        }
+        _ => false,
+    }
+}
+
+#[derive(Clone)]
+enum ForwarderInput {
+    Packet(Ipv4Packet),
+    /// The response to a query sent over a secure transport, by the ID it was sent with, or none
+    /// if it failed.
+    Upstream(u16, Option<DnsMessage>),
+}
+
+/// The packets the composite takes, along with responses from a secure transport, if it has one.
+/// Ends when the packets do.
+struct ForwarderIngress {
+    in_stream: PacketStream<Ipv4Packet>,
+    responses: Option<UnboundedReceiver<(u16, Option<DnsMessage>)>>,
+}
+
+impl Unpin for ForwarderIngress {}
+
+impl Stream for ForwarderIngress {
+    type Item = ForwarderInput;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let ingress = &mut *self;
+        if let Some(responses) = ingress.responses.as_mut() {
+            if let Poll::Ready(Some((id, response))) = responses.poll_recv(cx) {
+                return Poll::Ready(Some(ForwarderInput::Upstream(id, response)));
+            }
+        }
+        Pin::new(&mut ingress.in_stream)
+            .poll_next(cx)
+            .map(|packet| packet.map(ForwarderInput::Packet))
+    }
+}
+
+/// A query forwarded upstream, waiting for its response.
+struct PendingQuery {
+    client: SocketAddrV4,
+    /// The address the client sent its query to, which answers come from.
+    server_addr: Ipv4Addr,
+    id: u16,
+    /// The question forwarded, which the response must carry.
+    question: DnsQuestion,
+    port: u16,
+    sent: Instant,
+    /// The query as forwarded, kept to send over UDP should a secure transport fail.
+    query: Option<DnsMessage>,
+}
+
+struct DnsForwarder {
+    addr: Ipv4Addr,
+    upstream: Ipv4Addr,
+    cache: DnsCache,
+    policy: Option<Arc<DnsPolicy>>,
+    secure: Option<UnboundedSender<(u16, DnsMessage)>>,
+    pending: HashMap<u16, PendingQuery>,
+    sent: VecDeque<(Instant, u16)>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    evictions: Arc<AtomicU64>,
+    blocked: Arc<AtomicU64>,
+    rewritten: Arc<AtomicU64>,
+    event_sink: Option<EventSink>,
+    drops: DropAccounting,
+}
+
+impl DnsForwarder {
+    fn query(
+        &mut self,
+        packet: &Ipv4Packet,
+        src_port: u16,
+        message: DnsMessage,
+    ) -> Option<Ipv4Packet> {
+        let now = clock::now();
+        let question = match message.question() {
+            Some(question) => question,
+            None => {
+                self.drops.dropped(DropReason::Malformed);
+                return None;
+            }
+        };
+        let client = SocketAddrV4::new(packet.src_addr(), src_port);
+        let server = SocketAddrV4::new(packet.dest_addr(), DNS_PORT);
+        let action = self
+            .policy
+            .as_ref()
+            .and_then(|policy| policy.action(packet.src_addr(), &question.name));
+        match action {
+            Some(DnsAction::Allow) | None => {}
+            Some(action) => {
+                let (counter, verb) = match action {
+                    DnsAction::Block => (&self.blocked, "blocked"),
+                    _ => (&self.rewritten, "rewrote"),
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                if let Some(event_sink) = &self.event_sink {
+                    event_sink.activity(format!(
+                        "{} query for {} from {}",
+                        verb,
+                        question.name,
+                        packet.src_addr()
+                    ));
+                }
+                let answer = policy_response(&message, &question, &action);
+                return Some(datagram(server, client, answer.bytes()));
+            }
+        }
+
+        if let Some(mut answer) = self.cache.get(&question, now) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            answer.set_id(message.id());
+            return Some(datagram(server, client, answer.bytes()));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        while let Some(&(sent, id)) = self.sent.front() {
+            if now.saturating_duration_since(sent) < QUERY_TIMEOUT {
+                break;
+            }
+            self.sent.pop_front();
+            if self
+                .pending
+                .get(&id)
+                .is_some_and(|query| query.sent == sent)
+            {
+                self.pending.remove(&id);
+            }
+        }
+        if self.pending.len() > usize::from(u16::MAX) {
+            self.drops.dropped(DropReason::QueueFull);
+            return None;
+        }
+        let id = loop {
+            let id: u16 = rand::random();
+            if !self.pending.contains_key(&id) {
+                break id;
+            }
+        };
+        let port = rand::random::<u16>() | QUERY_PORT_BASE;
+        self.pending.insert(
+            id,
+            PendingQuery {
+                client,
+                server_addr: packet.dest_addr(),
+                id: message.id(),
+                question,
+                port,
+                sent: now,
+                query: None,
+            },
+        );
+        self.sent.push_back((now, id));
+
+        let mut forwarded = message;
+        forwarded.set_id(id);
+        if let Some(secure) = &self.secure {
+            self.pending.get_mut(&id).unwrap().query = Some(forwarded.clone());
+            if secure.send((id, forwarded.clone())).is_ok() {
+                return None;
+            }
+        }
+        Some(datagram(
+            SocketAddrV4::new(self.addr, port),
+            SocketAddrV4::new(self.upstream, DNS_PORT),
+            forwarded.bytes(),
+        ))
+    }
+
+    fn response(&mut self, dest_port: u16, message: DnsMessage) -> Option<Ipv4Packet> {
+        match self.pending.get(&message.id()) {
+            Some(query) if query.port == dest_port => self.answer(message.id(), message),
+            // Responses to queries never sent, or sent from another port, may be spoofed.
+            _ => {
+                self.drops.dropped(DropReason::Filtered);
+                None
+            }
+        }
+    }
+
+    /// Falls back to UDP for a query a secure transport failed to get a response to.
+    fn fallback(&mut self, id: u16) -> Option<Ipv4Packet> {
+        let query = self.pending.get_mut(&id)?;
+        let forwarded = query.query.take()?;
+        Some(datagram(
+            SocketAddrV4::new(self.addr, query.port),
+            SocketAddrV4::new(self.upstream, DNS_PORT),
+            forwarded.bytes(),
+        ))
+    }
+
+    /// Answers the query forwarded with `id` with its response. A secure transport may have
+    /// answered with another ID, as DoH servers may answer with 0, RFC 8484 section 4.1, so the
+    /// response is taken as having `id`.
+    fn answer(&mut self, id: u16, mut message: DnsMessage) -> Option<Ipv4Packet> {
+        message.set_id(id);
+        let query = match self.pending.get(&id) {
+            Some(query) => query,
+            // The query timed out before a secure transport got its response.
+            None => {
+                self.drops.dropped(DropReason::Discarded);
+                return None;
+            }
+        };
+        // A response to another question would be cached under it, RFC 5452 section 9.1. The
+        // query stays pending, for the response that does answer it.
+        if !answers(&message, &query.question) {
+            self.drops.dropped(DropReason::Filtered);
+            return None;
+        }
+        let query = self.pending.remove(&id)?;
+        if let Some(evicted) = self.cache.insert(&message, clock::now()) {
+            self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+        }
+
+        let mut answer = message;
+        answer.set_id(query.id);
+        Some(datagram(
+            SocketAddrV4::new(query.server_addr, DNS_PORT),
+            query.client,
+            answer.bytes(),
+        ))
+    }
+}
+
+impl Processor for DnsForwarder {
+    type Input = ForwarderInput;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        let packet = match input {
+            ForwarderInput::Packet(packet) => packet,
+            ForwarderInput::Upstream(id, Some(response)) => return self.answer(id, response),
+            ForwarderInput::Upstream(id, None) => return self.fallback(id),
+        };
+        let (src_port, dest_port, payload) = match udp(&packet) {
+            Some(datagram) => datagram,
+            None => {
+                self.drops.dropped(DropReason::Unclassified);
+                return None;
+            }
+        };
+        let message = match DnsMessage::from_bytes(payload) {
+            Ok(message) => message,
+            Err(err) => {
+                self.drops.parse_error(err);
+                return None;
+            }
+        };
+        if packet.src_addr() == self.upstream && src_port == DNS_PORT {
+            if message.is_response() && packet.dest_addr() == self.addr {
+                return self.response(dest_port, message);
+            }
+        } else if dest_port == DNS_PORT && !message.is_response() {
+            return self.query(&packet, src_port, message);
+        }
+        self.drops.dropped(DropReason::Unclassified);
+        None
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.hits = context.counter("cache_hits");
+        self.misses = context.counter("cache_misses");
+        self.evictions = context.counter("cache_evictions");
+        self.blocked = context.counter("policy_blocked");
+        self.rewritten = context.counter("policy_rewritten");
+    }
+}
+
+/// Sends packets to the upstream server out of the first egressor, and to clients out of the
+/// second.
+struct ByDestination {
+    upstream: Ipv4Addr,
+}
+
+impl Classifier for ByDestination {
+    type Packet = Ipv4Packet;
+    type Class = usize;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        if packet.dest_addr() == self.upstream {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// Answers the DNS queries of clients on the LAN, forwarding them to an upstream server unless
+/// the answer is cached.
+///
+/// The composite takes IPv4 DNS traffic: queries from clients to the router, UDP port 53, and
+/// responses from the upstream server to the router's address `addr`. Queries that miss the
+/// cache leave the first egressor, readdressed to the upstream server from `addr`, with a random
+/// ID and source port. Answers leave the second, to the clients that asked, from the address they
+/// asked. Any other packet, and any response to a query not forwarded, or that doesn't carry the
+/// question forwarded, is dropped, and counted by reason in the composite's `drop_counters`, if it
+/// has them.
+///
+/// Responses are cached for `cache_capacity` questions, each for as long as its records live, up
+/// to `max_ttl`, or if negative, as long as its zone allows, up to `max_negative_ttl`. Cache hits,
+/// misses and evictions are counted in the `Registry` as `dns-forwarder.cache_hits` and so on.
+///
+/// Given a `DnsPolicy`, the composite answers queries its rules block or rewrite itself, counting
+/// them as `dns-forwarder.policy_blocked` and `dns-forwarder.policy_rewritten`, and reporting
+/// each to its event sink, if it has one.
+///
+/// With the `dns-over-tls` or `dns-over-https` features, queries can be sent to the upstream
+/// server over TLS or HTTPS instead, from the router itself, over connections kept open between
+/// queries. A query that gets no response that way within 2 seconds is sent over UDP after all.
+pub struct DnsForwarderComposite {
+    in_stream: Option<PacketStream<Ipv4Packet>>,
+    addr: Option<Ipv4Addr>,
+    upstream: Option<Ipv4Addr>,
+    cache_capacity: usize,
+    max_ttl: Duration,
+    max_negative_ttl: Duration,
+    policy: Option<Arc<DnsPolicy>>,
+    transport: DnsTransport,
+    event_sink: Option<EventSink>,
+    drop_counters: Option<Arc<DropCounters>>,
+}
+
+impl Default for DnsForwarderComposite {
+    fn default() -> Self {
+        DnsForwarderComposite::new()
+    }
+}
+
+impl DnsForwarderComposite {
+    pub fn new() -> Self {
+        DnsForwarderComposite {
+            in_stream: None,
+            addr: None,
+            upstream: None,
+            cache_capacity: 1024,
+            max_ttl: Duration::from_secs(24 * 60 * 60),
+            max_negative_ttl: Duration::from_secs(15 * 60),
+            policy: None,
+            transport: DnsTransport::Udp,
+            event_sink: None,
+            drop_counters: None,
+        }
+    }
+
+    /// The router's address on the WAN, which queries are forwarded from.
+    pub fn addr(self, addr: Ipv4Addr) -> Self {
+        DnsForwarderComposite {
+            in_stream: self.in_stream,
+            addr: Some(addr),
+            upstream: self.upstream,
+            cache_capacity: self.cache_capacity,
+            max_ttl: self.max_ttl,
+            max_negative_ttl: self.max_negative_ttl,
+            policy: self.policy,
+            transport: self.transport,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// The server queries are forwarded to.
+    pub fn upstream(self, upstream: Ipv4Addr) -> Self {
+        DnsForwarderComposite {
+            in_stream: self.in_stream,
+            addr: self.addr,
+            upstream: Some(upstream),
+            cache_capacity: self.cache_capacity,
+            max_ttl: self.max_ttl,
+            max_negative_ttl: self.max_negative_ttl,
+            policy: self.policy,
+            transport: self.transport,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes cache_capacity, the number of questions whose answers are cached, default value
+    /// is 1024.
+    pub fn cache_capacity(self, cache_capacity: usize) -> Self {
+        assert!(cache_capacity > 0, "cache_capacity must be > 0");
+        DnsForwarderComposite {
+            in_stream: self.in_stream,
+            addr: self.addr,
+            upstream: self.upstream,
+            cache_capacity,
+            max_ttl: self.max_ttl,
+            max_negative_ttl: self.max_negative_ttl,
+            policy: self.policy,
+            transport: self.transport,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes max_ttl, the longest an answer is cached whatever its TTL, default value is a day.
+    pub fn max_ttl(self, max_ttl: Duration) -> Self {
+        DnsForwarderComposite {
+            in_stream: self.in_stream,
+            addr: self.addr,
+            upstream: self.upstream,
+            cache_capacity: self.cache_capacity,
+            max_ttl,
+            max_negative_ttl: self.max_negative_ttl,
+            policy: self.policy,
+            transport: self.transport,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes max_negative_ttl, the longest that a name or record not existing is cached,
+    /// default value is 15 minutes.
+    pub fn max_negative_ttl(self, max_negative_ttl: Duration) -> Self {
+        DnsForwarderComposite {
+            in_stream: self.in_stream,
+            addr: self.addr,
+            upstream: self.upstream,
+            cache_capacity: self.cache_capacity,
+            max_ttl: self.max_ttl,
+            max_negative_ttl,
+            policy: self.policy,
+            transport: self.transport,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Blocks or rewrites the answers to queries by the rules of a policy, before the cache or
+    /// upstream server are asked.
+    pub fn policy(self, policy: Arc<DnsPolicy>) -> Self {
+        DnsForwarderComposite {
+            in_stream: self.in_stream,
+            addr: self.addr,
+            upstream: self.upstream,
+            cache_capacity: self.cache_capacity,
+            max_ttl: self.max_ttl,
+            max_negative_ttl: self.max_negative_ttl,
+            policy: Some(policy),
+            transport: self.transport,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes transport, how queries are sent to the upstream server, default value is
+    /// `DnsTransport::Udp`.
+    pub fn transport(self, transport: DnsTransport) -> Self {
+        DnsForwarderComposite {
+            in_stream: self.in_stream,
+            addr: self.addr,
+            upstream: self.upstream,
+            cache_capacity: self.cache_capacity,
+            max_ttl: self.max_ttl,
+            max_negative_ttl: self.max_negative_ttl,
+            policy: self.policy,
+            transport,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Reports each query the policy blocks or rewrites to `event_sink`, as a
+    /// `LinkEvent::Activity`, and the packets the composite drops.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        DnsForwarderComposite {
+            in_stream: self.in_stream,
+            addr: self.addr,
+            upstream: self.upstream,
+            cache_capacity: self.cache_capacity,
+            max_ttl: self.max_ttl,
+            max_negative_ttl: self.max_negative_ttl,
+            policy: self.policy,
+            transport: self.transport,
+            event_sink: Some(event_sink),
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Counts the packets the composite drops in `drop_counters`, by reason.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        DnsForwarderComposite {
+            in_stream: self.in_stream,
+            addr: self.addr,
+            upstream: self.upstream,
+            cache_capacity: self.cache_capacity,
+            max_ttl: self.max_ttl,
+            max_negative_ttl: self.max_negative_ttl,
+            policy: self.policy,
+            transport: self.transport,
+            event_sink: self.event_sink,
+            drop_counters: Some(drop_counters),
+        }
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for DnsForwarderComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "DnsForwarderComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "DnsForwarderComposite may only take 1 input stream",
+            ));
+        }
+        Ok(DnsForwarderComposite {
+            in_stream: Some(in_stream),
+            addr: self.addr,
+            upstream: self.upstream,
+            cache_capacity: self.cache_capacity,
+            max_ttl: self.max_ttl,
+            max_negative_ttl: self.max_negative_ttl,
+            policy: self.policy,
+            transport: self.transport,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        match (self.in_stream, self.addr, self.upstream) {
+            (None, _, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None, _) => Err(LinkBuildError::Missing("addr")),
+            (_, _, None) => Err(LinkBuildError::Missing("upstream")),
+            (Some(in_stream), Some(addr), Some(upstream)) => {
+                let exchange = match &self.transport {
+                    DnsTransport::Udp => None,
+                    #[cfg(feature = "dns-over-tls")]
+                    DnsTransport::Tls { server_name } => Some(tls_exchange(upstream, server_name)?),
+                    #[cfg(feature = "dns-over-https")]
+                    DnsTransport::Https { url } => Some(https_exchange(url)?),
+                };
+                build_forwarder(
+                    in_stream,
+                    DnsForwarder {
+                        addr,
+                        upstream,
+                        cache: DnsCache::new(
+                            self.cache_capacity,
+                            self.max_ttl,
+                            self.max_negative_ttl,
+                        ),
+                        policy: self.policy,
+                        secure: None,
+                        pending: HashMap::new(),
+                        sent: VecDeque::new(),
+                        hits: Arc::new(AtomicU64::new(0)),
+                        misses: Arc::new(AtomicU64::new(0)),
+                        evictions: Arc::new(AtomicU64::new(0)),
+                        blocked: Arc::new(AtomicU64::new(0)),
+                        rewritten: Arc::new(AtomicU64::new(0)),
+                        drops: DropAccounting::new(self.drop_counters, self.event_sink.clone()),
+                        event_sink: self.event_sink,
+                    },
+                    exchange,
+                )
+            }
+        }
+    }
+}
+
+fn build_forwarder(
+    in_stream: PacketStream<Ipv4Packet>,
+    mut forwarder: DnsForwarder,
+    exchange: Option<Exchange>,
+) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+    let upstream = forwarder.upstream;
+    let mut runnables = vec![];
+    let mut ingress = ForwarderIngress {
+        in_stream,
+        responses: None,
+    };
+    if let Some(exchange) = exchange {
+        let (queries_tx, queries_rx) = unbounded_channel();
+        let (responses_tx, responses_rx) = unbounded_channel();
+        runnables.push(secure_upstream(exchange, queries_rx, responses_tx));
+        forwarder.secure = Some(queries_tx);
+        ingress.responses = Some(responses_rx);
+    }
+
+    let (mut process_runnables, mut egressors) = ProcessLink::new()
+        .try_ingressor(Box::new(ingress))?
+        .processor(forwarder)
+        .context(ProcessorContext::new("dns-forwarder"))
+        .try_build_link()?;
+    let (mut classify_runnables, classify_egressors) = ClassifyLink::new()
+        .try_ingressor(egressors.remove(0))?
+        .num_egressors(2)
+        .classifier(ByDestination { upstream })
+        .dispatcher(Box::new(|class| class))
+        .try_build_link()?;
+    runnables.append(&mut process_runnables);
+    runnables.append(&mut classify_runnables);
+    Ok((runnables, classify_egressors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use crossbeam::crossbeam_channel;
+
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 2);
+    const UPSTREAM: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 53);
+    const LAN_ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 20);
+
+    fn question(name: &str) -> DnsQuestion {
+        DnsQuestion {
+            name: String::from(name),
+            qtype: DNS_TYPE_A,
+            qclass: DNS_CLASS_IN,
+        }
+    }
+
+    /// A response answering `query` with one A record of `ttl` seconds.
+    fn response(query: &DnsMessage, ttl: u32) -> DnsMessage {
+        let mut data = query.bytes().to_vec();
+        data[2] |= 0x80;
+        data[7] = 1;
+        data.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+        data.extend_from_slice(&ttl.to_be_bytes());
+        data.extend_from_slice(&[0, 4, 93, 184, 216, 34]);
+        DnsMessage::from_bytes(data).unwrap()
+    }
+
+    /// An NXDOMAIN response to `query`, whose zone's SOA has a TTL of `soa_ttl` and a minimum of
+    /// `minimum`.
+    fn nxdomain(query: &DnsMessage, soa_ttl: u32, minimum: u32) -> DnsMessage {
+        let mut data = query.bytes().to_vec();
+        data[2] |= 0x80;
+        data[3] = DNS_RCODE_NXDOMAIN;
+        data[9] = 1;
+        data.extend_from_slice(&[0, 0, 6, 0, 1]);
+        data.extend_from_slice(&soa_ttl.to_be_bytes());
+        data.extend_from_slice(&[0, 22, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&minimum.to_be_bytes());
+        DnsMessage::from_bytes(data).unwrap()
+    }
+
+    fn cache(capacity: usize) -> DnsCache {
+        DnsCache::new(capacity, Duration::from_secs(3600), Duration::from_secs(60))
+    }
+
+    fn forwarder() -> DnsForwarder {
+        DnsForwarder {
+            addr: ROUTER,
+            upstream: UPSTREAM,
+            cache: cache(4),
+            policy: None,
+            secure: None,
+            pending: HashMap::new(),
+            sent: VecDeque::new(),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+            evictions: Arc::new(AtomicU64::new(0)),
+            blocked: Arc::new(AtomicU64::new(0)),
+            rewritten: Arc::new(AtomicU64::new(0)),
+            event_sink: None,
+            drops: DropAccounting::default(),
+        }
+    }
+
+    #[test]
+    fn cache_counts_down_ttls() {
+        let mut cache = cache(4);
+        let now = Instant::now();
+        let query = DnsMessage::query(1, &question("Example.com"));
+        assert_eq!(cache.insert(&response(&query, 300), now), Some(0));
+
+        let cached = cache
+            .get(&question("example.COM"), now + Duration::from_secs(100))
+            .unwrap();
+        assert_eq!(cached.records()[0].ttl, 200);
+        assert!(cache
+            .get(&question("example.com"), now + Duration::from_secs(300))
+            .is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn cache_keeps_negative_responses_for_soa_minimum() {
+        let mut cache = cache(4);
+        let now = Instant::now();
+        let query = DnsMessage::query(1, &question("nope.example.com"));
+        cache.insert(&nxdomain(&query, 3600, 30), now);
+
+        let cached = cache.get(&question("nope.example.com"), now).unwrap();
+        assert_eq!(cached.rcode(), DNS_RCODE_NXDOMAIN);
+        assert!(cache
+            .get(&question("nope.example.com"), now + Duration::from_secs(30))
+            .is_none());
+
+        // Negative responses are not kept for longer than max_negative_ttl.
+        cache.insert(&nxdomain(&query, 3600, 3600), now);
+        assert!(cache
+            .get(&question("nope.example.com"), now + Duration::from_secs(60))
+            .is_none());
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let mut cache = cache(2);
+        let now = Instant::now();
+        for name in &["a.example.com", "b.example.com"] {
+            let query = DnsMessage::query(1, &question(name));
+            assert_eq!(cache.insert(&response(&query, 300), now), Some(0));
+        }
+        assert!(cache.get(&question("a.example.com"), now).is_some());
+
+        let query = DnsMessage::query(1, &question("c.example.com"));
+        assert_eq!(cache.insert(&response(&query, 300), now), Some(1));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&question("a.example.com"), now).is_some());
+        assert!(cache.get(&question("b.example.com"), now).is_none());
+
+        let mut truncated = response(&query, 300).into_bytes();
+        truncated[2] |= 0x02;
+        let truncated = DnsMessage::from_bytes(truncated).unwrap();
+        assert_eq!(cache.insert(&truncated, now), None);
+    }
+
+    #[test]
+    fn forwards_and_answers_from_cache() {
+        let query = DnsMessage::query(0x4242, &question("example.com"));
+        let client_query = datagram(
+            SocketAddrV4::new(CLIENT, 40000),
+            SocketAddrV4::new(LAN_ADDR, DNS_PORT),
+            query.bytes(),
+        );
+
+        let drop_counters = Arc::new(DropCounters::new());
+        let mut forwarder = DnsForwarder {
+            drops: DropAccounting::new(Some(Arc::clone(&drop_counters)), None),
+            ..forwarder()
+        };
+        let forwarded = forwarder
+            .process(ForwarderInput::Packet(client_query.clone()))
+            .unwrap();
+        assert_eq!(forwarded.src_addr(), ROUTER);
+        assert_eq!(forwarded.dest_addr(), UPSTREAM);
+        let (port, dest_port, payload) = udp(&forwarded).unwrap();
+        assert_eq!(dest_port, DNS_PORT);
+        let upstream_query = DnsMessage::from_bytes(payload).unwrap();
+
+        // A response to another port is not taken.
+        let upstream_response = response(&upstream_query, 300);
+        let spoofed = datagram(
+            SocketAddrV4::new(UPSTREAM, DNS_PORT),
+            SocketAddrV4::new(ROUTER, port.wrapping_add(1)),
+            upstream_response.bytes(),
+        );
+        assert!(forwarder.process(ForwarderInput::Packet(spoofed)).is_none());
+        assert_eq!(drop_counters.snapshot(), vec![(DropReason::Filtered, 1)]);
+
+        let answer = forwarder
+            .process(ForwarderInput::Packet(datagram(
+                SocketAddrV4::new(UPSTREAM, DNS_PORT),
+                SocketAddrV4::new(ROUTER, port),
+                upstream_response.bytes(),
+            )))
+            .unwrap();
+        assert_eq!(answer.src_addr(), LAN_ADDR);
+        assert_eq!(answer.dest_addr(), CLIENT);
+        let (src_port, dest_port, payload) = udp(&answer).unwrap();
+        assert_eq!((src_port, dest_port), (DNS_PORT, 40000));
+        assert_eq!(DnsMessage::from_bytes(payload).unwrap().id(), 0x4242);
+
+        let cached = forwarder
+            .process(ForwarderInput::Packet(client_query))
+            .unwrap();
+        assert_eq!(cached.dest_addr(), CLIENT);
+        assert_eq!(forwarder.hits.load(Ordering::Relaxed), 1);
+        assert_eq!(forwarder.misses.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn drops_responses_to_other_questions() {
+        let query = DnsMessage::query(0x4242, &question("Example.com"));
+        let client_query = datagram(
+            SocketAddrV4::new(CLIENT, 40000),
+            SocketAddrV4::new(LAN_ADDR, DNS_PORT),
+            query.bytes(),
+        );
+        let drop_counters = Arc::new(DropCounters::new());
+        let mut forwarder = DnsForwarder {
+            drops: DropAccounting::new(Some(Arc::clone(&drop_counters)), None),
+            ..forwarder()
+        };
+        let forwarded = forwarder
+            .process(ForwarderInput::Packet(client_query))
+            .unwrap();
+        let (port, _, payload) = udp(&forwarded).unwrap();
+        let id = DnsMessage::from_bytes(payload).unwrap().id();
+        let from_upstream = |response: DnsMessage| {
+            ForwarderInput::Packet(datagram(
+                SocketAddrV4::new(UPSTREAM, DNS_PORT),
+                SocketAddrV4::new(ROUTER, port),
+                response.bytes(),
+            ))
+        };
+
+        let poisoned = response(&DnsMessage::query(id, &question("bank.example")), 300);
+        assert!(forwarder.process(from_upstream(poisoned)).is_none());
+        assert_eq!(drop_counters.snapshot(), vec![(DropReason::Filtered, 1)]);
+        assert!(forwarder
+            .cache
+            .get(&question("bank.example"), clock::now())
+            .is_none());
+
+        // The query is still answered by the response that does carry its question.
+        let genuine = response(&DnsMessage::query(id, &question("example.COM")), 300);
+        let answer = forwarder.process(from_upstream(genuine)).unwrap();
+        assert_eq!(answer.dest_addr(), CLIENT);
+    }
+
+    #[test]
+    fn parses_hosts_and_adblock_lists() {
+        let rules = DnsRuleList::parse(
+            "# hosts\n\
+             0.0.0.0 ads.example.com tracker.example.com\n\
+             192.168.1.5 nas.lan # the NAS\n\
+             fd00::5 nas.lan\n\
+             ! adblock\n\
+             [Adblock Plus 2.0]\n\
+             ||doubleclick.net^\n\
+             @@||ok.doubleclick.net^\n\
+             malware.example.org\n\
+             ||example.com^$third-party\n",
+        );
+
+        assert_eq!(
+            rules.action("Ads.Example.com."),
+            Some(&DnsAction::Rewrite(vec!["0.0.0.0".parse().unwrap()]))
+        );
+        assert_eq!(rules.action("www.ads.example.com"), None);
+        assert_eq!(
+            rules.action("nas.lan"),
+            Some(&DnsAction::Rewrite(vec![
+                "192.168.1.5".parse().unwrap(),
+                "fd00::5".parse().unwrap()
+            ]))
+        );
+        assert_eq!(rules.action("doubleclick.net"), Some(&DnsAction::Block));
+        assert_eq!(
+            rules.action("ad.g.doubleclick.net"),
+            Some(&DnsAction::Block)
+        );
+        assert_eq!(
+            rules.action("x.ok.doubleclick.net"),
+            Some(&DnsAction::Allow)
+        );
+        assert_eq!(rules.action("malware.example.org"), Some(&DnsAction::Block));
+        assert_eq!(rules.action("www.malware.example.org"), None);
+        assert_eq!(rules.action("example.com"), None);
+        assert_eq!(rules.len(), 6);
+    }
+
+    #[test]
+    fn policy_applies_client_rules() {
+        let mut rules = DnsRuleList::new();
+        rules.block("ads.example.com", true);
+        let policy = DnsPolicy::new(rules);
+        let mut kids = DnsRuleList::new();
+        kids.block("games.example.com", true);
+        policy.set_client_rules("192.168.1.128/25".parse().unwrap(), kids);
+        policy.set_client_rules("192.168.1.200/32".parse().unwrap(), DnsRuleList::new());
+
+        let adult = Ipv4Addr::new(192, 168, 1, 20);
+        let kid = Ipv4Addr::new(192, 168, 1, 130);
+        let exempt = Ipv4Addr::new(192, 168, 1, 200);
+        assert_eq!(
+            policy.action(adult, "ads.example.com"),
+            Some(DnsAction::Block)
+        );
+        assert_eq!(policy.action(adult, "games.example.com"), None);
+        assert_eq!(
+            policy.action(kid, "games.example.com"),
+            Some(DnsAction::Block)
+        );
+        assert_eq!(policy.action(kid, "ads.example.com"), None);
+        assert_eq!(policy.action(exempt, "games.example.com"), None);
+
+        policy.set_rules(DnsRuleList::parse("||example.com^"));
+        assert_eq!(
+            policy.action(adult, "games.example.com"),
+            Some(DnsAction::Block)
+        );
+        // Without rules of its own, a client falls under those of the next subnet it is in.
+        policy.remove_client_rules(&"192.168.1.200/32".parse().unwrap());
+        assert_eq!(
+            policy.action(exempt, "games.example.com"),
+            Some(DnsAction::Block)
+        );
+    }
+
+    #[test]
+    fn answers_from_policy() {
+        let policy = Arc::new(DnsPolicy::new(DnsRuleList::parse(
+            "10.0.0.9 printer.lan\n||ads.example.com^\n",
+        )));
+        let queries = vec![
+            ("printer.lan", DNS_TYPE_A),
+            ("printer.lan", DNS_TYPE_AAAA),
+            ("x.ads.example.com", DNS_TYPE_A),
+        ];
+        let packets: Vec<Ipv4Packet> = queries
+            .into_iter()
+            .map(|(name, qtype)| {
+                let question = DnsQuestion {
+                    name: String::from(name),
+                    qtype,
+                    qclass: DNS_CLASS_IN,
+                };
+                datagram(
+                    SocketAddrV4::new(CLIENT, 40000),
+                    SocketAddrV4::new(LAN_ADDR, DNS_PORT),
+                    DnsMessage::query(1, &question).bytes(),
+                )
+            })
+            .collect();
+
+        let (sender, events) = crossbeam_channel::unbounded();
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DnsForwarderComposite::new()
+                .ingressor(immediate_stream(packets))
+                .addr(ROUTER)
+                .upstream(UPSTREAM)
+                .policy(policy)
+                .event_sink(EventSink::new("dns", sender))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert!(results[0].is_empty());
+        let answers: Vec<DnsMessage> = results[1]
+            .iter()
+            .map(|packet| DnsMessage::from_bytes(udp(packet).unwrap().2).unwrap())
+            .collect();
+        assert_eq!(answers.len(), 3);
+        assert_eq!(answers[0].records()[0].data, vec![10, 0, 0, 9]);
+        assert_eq!(answers[1].rcode(), DNS_RCODE_NOERROR);
+        assert!(answers[1].records().is_empty());
+        assert_eq!(answers[2].rcode(), DNS_RCODE_NXDOMAIN);
+
+        let events: Vec<String> = events.try_iter().map(|event| event.to_string()).collect();
+        assert_eq!(
+            events,
+            vec![
+                format!("dns: rewrote query for printer.lan from {}", CLIENT),
+                format!("dns: rewrote query for printer.lan from {}", CLIENT),
+                format!("dns: blocked query for x.ads.example.com from {}", CLIENT),
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_udp_when_secure_transport_fails() {
+        // Answers queries for example.com, and fails any other.
+        let exchange: Exchange = Arc::new(|query: Vec<u8>| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            Box::pin(async move {
+                if query.question().unwrap().name == "example.com" {
+                    // As DoH servers may, RFC 8484 section 4.1.
+                    let mut response = response(&query, 300);
+                    response.set_id(0);
+                    Ok(response.into_bytes())
+                } else {
+                    Err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"))
+                }
+            }) as ExchangeFuture
+        });
+        let packets: Vec<Ipv4Packet> = ["example.com", "fail.example.com", "", ""]
+            .iter()
+            .map(|name| {
+                let query = DnsMessage::query(1, &question(name));
+                datagram(
+                    SocketAddrV4::new(CLIENT, 40000),
+                    SocketAddrV4::new(LAN_ADDR, DNS_PORT),
+                    query.bytes(),
+                )
+            })
+            .collect();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = build_forwarder(
+                Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(50),
+                    packets.into_iter(),
+                )),
+                forwarder(),
+                Some(exchange),
+            )
+            .unwrap();
+
+            run_link(link).await
+        });
+        let fallbacks: Vec<DnsMessage> = results[0]
+            .iter()
+            .map(|packet| DnsMessage::from_bytes(udp(packet).unwrap().2).unwrap())
+            .filter(|query| !query.question().unwrap().name.is_empty())
+            .collect();
+        assert_eq!(fallbacks.len(), 1);
+        assert_eq!(fallbacks[0].question().unwrap().name, "fail.example.com");
+        assert_eq!(results[1].len(), 1);
+        assert_eq!(results[1][0].dest_addr(), CLIENT);
+        let answer = DnsMessage::from_bytes(udp(&results[1][0]).unwrap().2).unwrap();
+        assert_eq!(answer.id(), 1);
+        assert_eq!(answer.records()[0].data, vec![93, 184, 216, 34]);
+    }
+
+    #[test]
+    fn sends_misses_upstream() {
+        let query = DnsMessage::query(7, &question("example.com"));
+        let packets = vec![
+            datagram(
+                SocketAddrV4::new(CLIENT, 40000),
+                SocketAddrV4::new(LAN_ADDR, DNS_PORT),
+                query.bytes(),
+            ),
+            // Not DNS.
+            datagram(
+                SocketAddrV4::new(CLIENT, 40000),
+                SocketAddrV4::new(LAN_ADDR, 80),
+                b"GET /",
+            ),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DnsForwarderComposite::new()
+                .ingressor(immediate_stream(packets))
+                .addr(ROUTER)
+                .upstream(UPSTREAM)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 1);
+        assert_eq!(results[0][0].dest_addr(), UPSTREAM);
+        assert!(results[1].is_empty());
+    }
+}
@@ -1,15 +1,37 @@
+use crate::link::event::{DropAccounting, DropCounters, DropReason, EventSink};
 use crate::link::primitive::ProcessLink;
-use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
-use crate::processor::Drop;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::{Drop, Processor};
+use crossbeam::crossbeam_channel::Sender;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
 
 /// Link that drops packets.
 /// Can specify a weighted uniform distribution for dropping,
 /// otherwise, will drop all incoming packets.
-#[derive(Default)]
+///
+/// A DropLink is how a branch of a router ends, for packets it has no use for, such as those a
+/// classifier can't place. Each is dropped for the link's `reason`, counted in its `counters`
+/// under that reason, shared by every DropLink of the router so its drops are all accounted for
+/// in one place, and reported to its `event_sink`. Composites that drop packets inside their own
+/// streams take the same `DropCounters`, as `drop_counters`, and count their drops in them too.
+/// With `sample`, some of the dropped packets are sent on to a channel, such as one an
+/// `InputChannelLink` reads into a `CaptureRingLink`, so what is being dropped can be looked at.
 pub struct DropLink<I> {
     in_stream: Option<PacketStream<I>>,
     drop_chance: Option<f64>,
     seed: Option<u64>,
+    counter: Option<&'static AtomicU64>,
+    reason: DropReason,
+    counters: Option<Arc<DropCounters>>,
+    sample: Option<(usize, Sender<I>)>,
+    event_sink: Option<EventSink>,
+}
+
+impl<I> Default for DropLink<I> {
+    fn default() -> Self {
+        DropLink::new()
+    }
 }
 
 impl<I> DropLink<I> {
@@ -18,6 +40,11 @@ impl<I> DropLink<I> {
             in_stream: None,
             drop_chance: None,
             seed: None,
+            counter: None,
+            reason: DropReason::Discarded,
+            counters: None,
+            sample: None,
+            event_sink: None,
         }
     }
 
@@ -26,6 +53,11 @@ impl<I> DropLink<I> {
             in_stream: self.in_stream,
             drop_chance: Some(chance),
             seed: self.seed,
+            counter: self.counter,
+            reason: self.reason,
+            counters: self.counters,
+            sample: self.sample,
+            event_sink: self.event_sink,
         }
     }
 
@@ -34,60 +66,176 @@ impl<I> DropLink<I> {
             in_stream: self.in_stream,
             drop_chance: self.drop_chance,
             seed: Some(int_seed),
+            counter: self.counter,
+            reason: self.reason,
+            counters: self.counters,
+            sample: self.sample,
+            event_sink: self.event_sink,
         }
     }
-}
 
-impl<I: Send + Clone + 'static> LinkBuilder<I, I> for DropLink<I> {
-    fn ingressors(self, mut ingress_streams: Vec<PacketStream<I>>) -> Self {
-        assert_eq!(
-            ingress_streams.len(),
-            1,
-            "DropLink can only take 1 ingress stream"
-        );
+    /// Counts the dropped packets, see `Drop::counter`.
+    pub fn counter(self, counter: &'static AtomicU64) -> Self {
+        DropLink {
+            in_stream: self.in_stream,
+            drop_chance: self.drop_chance,
+            seed: self.seed,
+            counter: Some(counter),
+            reason: self.reason,
+            counters: self.counters,
+            sample: self.sample,
+            event_sink: self.event_sink,
+        }
+    }
 
-        if self.in_stream.is_some() {
-            panic!("DropLink can only take 1 input stream")
+    /// Changes reason, what the packets are dropped for, default value is `DropReason::Discarded`.
+    pub fn reason(self, reason: DropReason) -> Self {
+        DropLink {
+            in_stream: self.in_stream,
+            drop_chance: self.drop_chance,
+            seed: self.seed,
+            counter: self.counter,
+            reason,
+            counters: self.counters,
+            sample: self.sample,
+            event_sink: self.event_sink,
         }
+    }
 
+    /// Counts the dropped packets in `counters`, under the link's reason.
+    pub fn counters(self, counters: Arc<DropCounters>) -> Self {
         DropLink {
-            in_stream: Some(ingress_streams.remove(0)),
+            in_stream: self.in_stream,
             drop_chance: self.drop_chance,
             seed: self.seed,
+            counter: self.counter,
+            reason: self.reason,
+            counters: Some(counters),
+            sample: self.sample,
+            event_sink: self.event_sink,
         }
     }
 
-    fn ingressor(self, in_stream: PacketStream<I>) -> Self {
-        if self.in_stream.is_some() {
-            panic!("DropLink can only take 1 input stream")
+    /// Sends the first of every `one_in` dropped packets to `sender`, without blocking, so a full
+    /// channel loses samples rather than holding up the link.
+    pub fn sample(self, one_in: usize, sender: Sender<I>) -> Self {
+        assert!(one_in > 0, "one_in must be > 0");
+        DropLink {
+            in_stream: self.in_stream,
+            drop_chance: self.drop_chance,
+            seed: self.seed,
+            counter: self.counter,
+            reason: self.reason,
+            counters: self.counters,
+            sample: Some((one_in, sender)),
+            event_sink: self.event_sink,
         }
+    }
 
+    /// Reports the dropped packets to `event_sink`, with the link's reason.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
         DropLink {
-            in_stream: Some(in_stream),
+            in_stream: self.in_stream,
             drop_chance: self.drop_chance,
             seed: self.seed,
+            counter: self.counter,
+            reason: self.reason,
+            counters: self.counters,
+            sample: self.sample,
+            event_sink: Some(event_sink),
         }
     }
+}
 
-    fn build_link(self) -> Link<I> {
-        if self.in_stream.is_none() {
-            panic!("Cannot build link! Missing input streams");
-        } else {
-            let mut dropper: Drop<I> = Drop::new();
+/// The `Drop` processor, doing the accounting of a `DropLink` for each packet it drops.
+struct Discard<I: Send + Clone> {
+    dropper: Drop<I>,
+    reason: DropReason,
+    accounting: DropAccounting,
+    sample: Option<(usize, Sender<I>)>,
+    dropped: usize,
+}
 
-            if let Some(dc) = self.drop_chance {
-                dropper = dropper.drop_chance(dc);
-            }
+impl<I: Send + Clone> Processor for Discard<I> {
+    type Input = I;
+    type Output = I;
 
-            if let Some(s) = self.seed {
-                dropper = dropper.seed(s);
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        if !self.dropper.drops_next() {
+            return Some(packet);
+        }
+        self.accounting.dropped(self.reason);
+        if let Some((one_in, sender)) = &self.sample {
+            if self.dropped.is_multiple_of(*one_in) {
+                let _ = sender.try_send(packet);
             }
+        }
+        self.dropped = self.dropped.wrapping_add(1);
+        None
+    }
+}
 
-            ProcessLink::new()
-                .ingressor(self.in_stream.unwrap())
-                .processor(dropper)
-                .build_link()
+impl<I: Send + Clone + 'static> LinkBuilder<I, I> for DropLink<I> {
+    fn try_ingressors(
+        self,
+        mut ingress_streams: Vec<PacketStream<I>>,
+    ) -> Result<Self, LinkBuildError> {
+        if ingress_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "DropLink can only take 1 ingress stream",
+            ));
         }
+        self.try_ingressor(ingress_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<I>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "DropLink can only take 1 input stream",
+            ));
+        }
+
+        Ok(DropLink {
+            in_stream: Some(in_stream),
+            drop_chance: self.drop_chance,
+            seed: self.seed,
+            counter: self.counter,
+            reason: self.reason,
+            counters: self.counters,
+            sample: self.sample,
+            event_sink: self.event_sink,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<I>, LinkBuildError> {
+        let in_stream = match self.in_stream {
+            Some(in_stream) => in_stream,
+            None => return Err(LinkBuildError::Missing("input streams")),
+        };
+        let mut dropper: Drop<I> = Drop::new();
+
+        if let Some(dc) = self.drop_chance {
+            dropper = dropper.drop_chance(dc);
+        }
+
+        if let Some(s) = self.seed {
+            dropper = dropper.seed(s);
+        }
+
+        if let Some(c) = self.counter {
+            dropper = dropper.counter(c);
+        }
+
+        ProcessLink::new()
+            .try_ingressor(in_stream)?
+            .processor(Discard {
+                dropper,
+                reason: self.reason,
+                accounting: DropAccounting::new(self.counters, self.event_sink),
+                sample: self.sample,
+                dropped: 0,
+            })
+            .try_build_link()
     }
 }
 
@@ -95,9 +243,12 @@ impl<I: Send + Clone + 'static> LinkBuilder<I, I> for DropLink<I> {
 mod tests {
     use super::*;
     use crate::classifier::even_link;
+    use crate::link::event::LinkEvent;
     use crate::utils::test::harness::{initialize_runtime, run_link};
     use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
     use core::time;
+    use crossbeam::crossbeam_channel;
+    use std::sync::atomic::Ordering;
 
     #[test]
     #[should_panic]
@@ -117,7 +268,7 @@ mod tests {
 
             run_link(link).await
         });
-        assert_eq!(results[0], vec![]);
+        assert_eq!(results[0], Vec::<i32>::new());
     }
 
     #[test]
@@ -135,7 +286,7 @@ mod tests {
 
             run_link(link).await
         });
-        assert_eq!(results[0], vec![]);
+        assert_eq!(results[0], Vec::<i32>::new());
     }
 
     #[test]
@@ -177,4 +328,57 @@ mod tests {
         });
         assert_eq!(results[0], vec![1, 2, 1337, 7]);
     }
+
+    #[test]
+    fn counts_dropped_packets() {
+        static DROPPED: AtomicU64 = AtomicU64::new(0);
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link: Link<i32> = DropLink::new()
+                .ingressor(immediate_stream(packets))
+                .drop_chance(0.7)
+                .seed(0)
+                .counter(&DROPPED)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 4);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn accounts_for_drops_by_reason() {
+        let counters = Arc::new(DropCounters::new());
+        let (events, event_receiver) = crossbeam_channel::unbounded();
+        let (samples, sample_receiver) = crossbeam_channel::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DropLink::new()
+                .ingressor(immediate_stream(0..10))
+                .reason(DropReason::Unclassified)
+                .counters(Arc::clone(&counters))
+                .sample(4, samples)
+                .event_sink(EventSink::new("unclassified", events))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], Vec::<i32>::new());
+        assert_eq!(counters.get(DropReason::Unclassified), 10);
+        assert_eq!(counters.total(), 10);
+        assert_eq!(
+            sample_receiver.try_iter().collect::<Vec<_>>(),
+            vec![0, 4, 8]
+        );
+        let events: Vec<LinkEvent> = event_receiver.try_iter().collect();
+        assert_eq!(events.len(), 10);
+        assert_eq!(
+            events[0].to_string(),
+            "unclassified: dropped packet, unclassified"
+        );
+    }
 }
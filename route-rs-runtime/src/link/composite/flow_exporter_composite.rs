@@ -0,0 +1,670 @@
+use crate::config::FlowExportConfig;
+use crate::link::primitive::ProcessLink;
+use crate::link::{
+    Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder, TokioRunnable,
+};
+use crate::processor::Processor;
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::ready;
+use futures::task::{Context, Poll};
+use route_rs_packets::{FlowKey, Ipv4Packet};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::{interval, Interval};
+
+/// IPFIX message header length, RFC 7011 section 3.1
+const IPFIX_HEADER_LEN: usize = 16;
+const IPFIX_VERSION: u16 = 10;
+const IPFIX_TEMPLATE_SET_ID: u16 = 2;
+/// Template IDs below 256 are reserved for set IDs.
+const IPFIX_TEMPLATE_ID: u16 = 256;
+/// Keeps messages under a typical path MTU once IP and UDP headers are added.
+const IPFIX_MAX_MESSAGE_LEN: usize = 1400;
+
+/// (Information Element ID, length) of every field in a data record, in order.
+const IPFIX_TEMPLATE_FIELDS: [(u16, u16); 10] = [
+    (8, 4),   // sourceIPv4Address
+    (12, 4),  // destinationIPv4Address
+    (4, 1),   // protocolIdentifier
+    (7, 2),   // sourceTransportPort
+    (11, 2),  // destinationTransportPort
+    (2, 8),   // packetDeltaCount
+    (1, 8),   // octetDeltaCount
+    (150, 4), // flowStartSeconds
+    (151, 4), // flowEndSeconds
+    (136, 1), // flowEndReason
+];
+const IPFIX_RECORD_LEN: usize = 38;
+const IPFIX_TEMPLATE_SET_LEN: usize = 8 + 4 * IPFIX_TEMPLATE_FIELDS.len();
+
+/// Why a flow record was exported, values are from the IPFIX flowEndReason element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowEndReason {
+    IdleTimeout = 1,
+    ActiveTimeout = 2,
+    ForcedEnd = 4,
+}
+
+/// Traffic seen for a single `FlowKey` since the last time it was exported.
+#[derive(Debug, Clone)]
+pub struct FlowRecord {
+    pub key: FlowKey,
+    pub packets: u64,
+    pub octets: u64,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    first_seen: Instant,
+    last_seen: Instant,
+}
+
+/// Flows that are currently being metered. Shared between the `FlowMeter` in the data path and the
+/// `FlowExportRunner` that expires and exports them.
+#[derive(Default)]
+struct FlowTable {
+    flows: HashMap<FlowKey, FlowRecord>,
+    /// Set once the meter is dropped, ie. the input stream has finished.
+    closed: bool,
+}
+
+impl FlowTable {
+    fn update(&mut self, key: FlowKey, octets: u64, now: Instant, wall_clock: SystemTime) {
+        let record = self.flows.entry(key).or_insert(FlowRecord {
+            key,
+            packets: 0,
+            octets: 0,
+            start: wall_clock,
+            end: wall_clock,
+            first_seen: now,
+            last_seen: now,
+        });
+        record.packets += 1;
+        record.octets += octets;
+        record.end = wall_clock;
+        record.last_seen = now;
+    }
+
+    /// Removes and returns every flow that has been idle for `inactive_timeout`, or has been
+    /// active for longer than `active_timeout`.
+    fn expire(
+        &mut self,
+        now: Instant,
+        active_timeout: Duration,
+        inactive_timeout: Duration,
+    ) -> Vec<(FlowRecord, FlowEndReason)> {
+        let expired: Vec<(FlowKey, FlowEndReason)> = self
+            .flows
+            .values()
+            .filter_map(|record| {
+                if now.duration_since(record.last_seen) >= inactive_timeout {
+                    Some((record.key, FlowEndReason::IdleTimeout))
+                } else if now.duration_since(record.first_seen) >= active_timeout {
+                    Some((record.key, FlowEndReason::ActiveTimeout))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        expired
+            .into_iter()
+            .map(|(key, reason)| (self.flows.remove(&key).unwrap(), reason))
+            .collect()
+    }
+
+    fn drain(&mut self) -> Vec<(FlowRecord, FlowEndReason)> {
+        self.flows
+            .drain()
+            .map(|(_, record)| (record, FlowEndReason::ForcedEnd))
+            .collect()
+    }
+}
+
+/// Serializes flow records into IPFIX messages (RFC 7011). Every message carries the template,
+/// since UDP transport gives no guarantee the collector saw an earlier one.
+struct IpfixEncoder {
+    observation_domain_id: u32,
+    /// Number of data records sent before the current message, as the header requires.
+    sequence_number: u32,
+}
+
+impl IpfixEncoder {
+    fn new(observation_domain_id: u32) -> Self {
+        IpfixEncoder {
+            observation_domain_id,
+            sequence_number: 0,
+        }
+    }
+
+    fn encode(
+        &mut self,
+        records: &[(FlowRecord, FlowEndReason)],
+        export_time: SystemTime,
+    ) -> Vec<Vec<u8>> {
+        let ipv4_records: Vec<&(FlowRecord, FlowEndReason)> = records
+            .iter()
+            .filter(|(record, _)| record.key.src_addr.is_ipv4() && record.key.dest_addr.is_ipv4())
+            .collect();
+        let records_per_message =
+            (IPFIX_MAX_MESSAGE_LEN - IPFIX_HEADER_LEN - IPFIX_TEMPLATE_SET_LEN - 4)
+                / IPFIX_RECORD_LEN;
+
+        ipv4_records
+            .chunks(records_per_message)
+            .map(|chunk| self.encode_message(chunk, export_time))
+            .collect()
+    }
+
+    fn encode_message(
+        &mut self,
+        records: &[&(FlowRecord, FlowEndReason)],
+        export_time: SystemTime,
+    ) -> Vec<u8> {
+        let data_set_len = 4 + records.len() * IPFIX_RECORD_LEN;
+        let message_len = IPFIX_HEADER_LEN + IPFIX_TEMPLATE_SET_LEN + data_set_len;
+        let mut message = Vec::with_capacity(message_len);
+
+        message.extend_from_slice(&IPFIX_VERSION.to_be_bytes());
+        message.extend_from_slice(&(message_len as u16).to_be_bytes());
+        message.extend_from_slice(&epoch_seconds(export_time).to_be_bytes());
+        message.extend_from_slice(&self.sequence_number.to_be_bytes());
+        message.extend_from_slice(&self.observation_domain_id.to_be_bytes());
+
+        message.extend_from_slice(&IPFIX_TEMPLATE_SET_ID.to_be_bytes());
+        message.extend_from_slice(&(IPFIX_TEMPLATE_SET_LEN as u16).to_be_bytes());
+        message.extend_from_slice(&IPFIX_TEMPLATE_ID.to_be_bytes());
+        message.extend_from_slice(&(IPFIX_TEMPLATE_FIELDS.len() as u16).to_be_bytes());
+        for (element_id, length) in IPFIX_TEMPLATE_FIELDS.iter() {
+            message.extend_from_slice(&element_id.to_be_bytes());
+            message.extend_from_slice(&length.to_be_bytes());
+        }
+
+        message.extend_from_slice(&IPFIX_TEMPLATE_ID.to_be_bytes());
+        message.extend_from_slice(&(data_set_len as u16).to_be_bytes());
+        for (record, reason) in records {
+            for addr in [record.key.src_addr, record.key.dest_addr].iter() {
+                if let IpAddr::V4(addr) = addr {
+                    message.extend_from_slice(&addr.octets());
+                }
+            }
+            message.push(record.key.protocol);
+            message.extend_from_slice(&record.key.src_port.to_be_bytes());
+            message.extend_from_slice(&record.key.dest_port.to_be_bytes());
+            message.extend_from_slice(&record.packets.to_be_bytes());
+            message.extend_from_slice(&record.octets.to_be_bytes());
+            message.extend_from_slice(&epoch_seconds(record.start).to_be_bytes());
+            message.extend_from_slice(&epoch_seconds(record.end).to_be_bytes());
+            message.push(*reason as u8);
+        }
+
+        self.sequence_number = self.sequence_number.wrapping_add(records.len() as u32);
+        message
+    }
+}
+
+fn epoch_seconds(time: SystemTime) -> u32 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+/// Accounts every packet that passes through against its flow.
+struct FlowMeter {
+    table: Arc<Mutex<FlowTable>>,
+}
+
+impl Processor for FlowMeter {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let key = FlowKey::from_ipv4(&packet);
+        let octets = u64::from(packet.total_len());
+        self.table
+            .lock()
+            .unwrap()
+            .update(key, octets, clock::now(), SystemTime::now());
+        Some(packet)
+    }
+}
+
+impl Drop for FlowMeter {
+    fn drop(&mut self) {
+        if let Ok(mut table) = self.table.lock() {
+            table.closed = true;
+        }
+    }
+}
+
+/// Periodically expires flows from the table and sends them to the collector. Once the meter has
+/// gone away, all remaining flows are exported and the runner finishes.
+struct FlowExportRunner {
+    table: Arc<Mutex<FlowTable>>,
+    encoder: IpfixEncoder,
+    socket: UdpSocket,
+    collector: SocketAddr,
+    active_timeout: Duration,
+    inactive_timeout: Duration,
+    interval: Interval,
+}
+
+impl FlowExportRunner {
+    fn export(&mut self, records: Vec<(FlowRecord, FlowEndReason)>) {
+        for message in self.encoder.encode(&records, SystemTime::now()) {
+            // Export is best effort, the same as the UDP transport it rides on.
+            let _ = self.socket.send_to(&message, self.collector);
+        }
+    }
+}
+
+impl Future for FlowExportRunner {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let runner = Pin::into_inner(self.as_mut());
+        loop {
+            ready!(Pin::new(&mut runner.interval).poll_next(cx));
+
+            let (records, closed) = {
+                let mut table = runner.table.lock().unwrap();
+                if table.closed {
+                    (table.drain(), true)
+                } else {
+                    (
+                        table.expire(clock::now(), runner.active_timeout, runner.inactive_timeout),
+                        false,
+                    )
+                }
+            };
+
+            if !records.is_empty() {
+                runner.export(records);
+            }
+            if closed {
+                return Poll::Ready(());
+            }
+        }
+    }
+}
+
+/// Meters IPv4 traffic into flow records and exports them as IPFIX over UDP.
+///
+/// Packets pass through unchanged on the single egressor. A flow is exported once it has been idle
+/// for the inactive timeout, or has been active for the active timeout, in which case the next
+/// packet starts a new record. The table is checked every export interval, and all remaining flows
+/// are exported when the input stream finishes.
+#[derive(Default)]
+pub struct FlowExporterComposite {
+    in_stream: Option<PacketStream<Ipv4Packet>>,
+    collector: Option<SocketAddr>,
+    active_timeout: Option<Duration>,
+    inactive_timeout: Option<Duration>,
+    export_interval: Option<Duration>,
+    observation_domain_id: Option<u32>,
+}
+
+impl FlowExporterComposite {
+    pub fn new() -> Self {
+        FlowExporterComposite {
+            in_stream: None,
+            collector: None,
+            active_timeout: None,
+            inactive_timeout: None,
+            export_interval: None,
+            observation_domain_id: None,
+        }
+    }
+
+    /// Address of the IPFIX collector, required.
+    pub fn collector(self, collector: SocketAddr) -> Self {
+        FlowExporterComposite {
+            in_stream: self.in_stream,
+            collector: Some(collector),
+            active_timeout: self.active_timeout,
+            inactive_timeout: self.inactive_timeout,
+            export_interval: self.export_interval,
+            observation_domain_id: self.observation_domain_id,
+        }
+    }
+
+    /// Defaults to 60 seconds.
+    pub fn active_timeout(self, timeout: Duration) -> Self {
+        FlowExporterComposite {
+            in_stream: self.in_stream,
+            collector: self.collector,
+            active_timeout: Some(timeout),
+            inactive_timeout: self.inactive_timeout,
+            export_interval: self.export_interval,
+            observation_domain_id: self.observation_domain_id,
+        }
+    }
+
+    /// Defaults to 15 seconds.
+    pub fn inactive_timeout(self, timeout: Duration) -> Self {
+        FlowExporterComposite {
+            in_stream: self.in_stream,
+            collector: self.collector,
+            active_timeout: self.active_timeout,
+            inactive_timeout: Some(timeout),
+            export_interval: self.export_interval,
+            observation_domain_id: self.observation_domain_id,
+        }
+    }
+
+    /// How often the table is checked for expired flows, defaults to 1 second.
+    pub fn export_interval(self, export_interval: Duration) -> Self {
+        assert!(
+            export_interval > Duration::from_secs(0),
+            "export_interval must be > 0"
+        );
+        FlowExporterComposite {
+            in_stream: self.in_stream,
+            collector: self.collector,
+            active_timeout: self.active_timeout,
+            inactive_timeout: self.inactive_timeout,
+            export_interval: Some(export_interval),
+            observation_domain_id: self.observation_domain_id,
+        }
+    }
+
+    /// Defaults to 0.
+    pub fn observation_domain_id(self, id: u32) -> Self {
+        FlowExporterComposite {
+            in_stream: self.in_stream,
+            collector: self.collector,
+            active_timeout: self.active_timeout,
+            inactive_timeout: self.inactive_timeout,
+            export_interval: self.export_interval,
+            observation_domain_id: Some(id),
+        }
+    }
+
+    /// Applies every setting given in the `flow_export` section of a `RouterConfig`.
+    pub fn config(self, config: &FlowExportConfig) -> Self {
+        let mut composite = self.collector(config.collector);
+        if let Some(secs) = config.active_timeout_secs {
+            composite = composite.active_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = config.inactive_timeout_secs {
+            composite = composite.inactive_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = config.export_interval_secs {
+            composite = composite.export_interval(Duration::from_secs(secs));
+        }
+        if let Some(id) = config.observation_domain_id {
+            composite = composite.observation_domain_id(id);
+        }
+        composite
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for FlowExporterComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "FlowExporterComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "FlowExporterComposite may only take 1 input stream",
+            ));
+        }
+
+        Ok(FlowExporterComposite {
+            in_stream: Some(in_stream),
+            collector: self.collector,
+            active_timeout: self.active_timeout,
+            inactive_timeout: self.inactive_timeout,
+            export_interval: self.export_interval,
+            observation_domain_id: self.observation_domain_id,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        match (self.in_stream, self.collector) {
+            (None, _) => Err(LinkBuildError::Missing("input streams")),
+            (_, None) => Err(LinkBuildError::Missing("collector")),
+            (Some(in_stream), Some(collector)) => {
+                let bind_addr: SocketAddr = if collector.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let socket = UdpSocket::bind(bind_addr).map_err(|err| {
+                    LinkBuildError::Invalid(format!(
+                        "FlowExporterComposite could not bind socket, {}",
+                        err
+                    ))
+                })?;
+                socket.set_nonblocking(true).map_err(|err| {
+                    LinkBuildError::Invalid(format!(
+                        "FlowExporterComposite could not configure socket, {}",
+                        err
+                    ))
+                })?;
+
+                let table = Arc::new(Mutex::new(FlowTable::default()));
+                let meter = FlowMeter {
+                    table: Arc::clone(&table),
+                };
+                let exporter = FlowExportRunner {
+                    table,
+                    encoder: IpfixEncoder::new(self.observation_domain_id.unwrap_or(0)),
+                    socket,
+                    collector,
+                    active_timeout: self.active_timeout.unwrap_or(Duration::from_secs(60)),
+                    inactive_timeout: self.inactive_timeout.unwrap_or(Duration::from_secs(15)),
+                    interval: interval(self.export_interval.unwrap_or(Duration::from_secs(1))),
+                };
+
+                let (mut runnables, egressors) = ProcessLink::new()
+                    .try_ingressor(in_stream)?
+                    .processor(meter)
+                    .try_build_link()?;
+                let exporter: TokioRunnable = Box::new(exporter);
+                runnables.push(exporter);
+                Ok((runnables, egressors))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::UdpSegment;
+    use std::convert::TryInto;
+    use std::net::Ipv4Addr;
+
+    fn udp_packet(src: [u8; 4], src_port: u16, dest_port: u16) -> Ipv4Packet {
+        let mut segment = UdpSegment::empty();
+        segment.set_src_port(src_port);
+        segment.set_dest_port(dest_port);
+        let mut packet = Ipv4Packet::encap_udp(segment);
+        packet.set_src_addr(Ipv4Addr::from(src));
+        packet.set_dest_addr(Ipv4Addr::new(10, 0, 0, 1));
+        packet
+    }
+
+    fn read_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(data: &[u8], offset: usize) -> u64 {
+        u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_if_no_input_stream_provided() {
+        FlowExporterComposite::new()
+            .collector(([127, 0, 0, 1], 4739).into())
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_if_no_collector_provided() {
+        FlowExporterComposite::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn table_expires_idle_and_active_flows() {
+        let start = Instant::now();
+        let idle_key = FlowKey::from_ipv4(&udp_packet([10, 0, 0, 2], 1000, 53));
+        let busy_key = FlowKey::from_ipv4(&udp_packet([10, 0, 0, 3], 1000, 53));
+
+        let mut table = FlowTable::default();
+        table.update(idle_key, 28, start, SystemTime::now());
+        table.update(busy_key, 28, start, SystemTime::now());
+        table.update(
+            busy_key,
+            28,
+            start + Duration::from_secs(9),
+            SystemTime::now(),
+        );
+
+        let active = Duration::from_secs(30);
+        let inactive = Duration::from_secs(5);
+        let expired = table.expire(start + Duration::from_secs(10), active, inactive);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0.key, idle_key);
+        assert_eq!(expired[0].1, FlowEndReason::IdleTimeout);
+
+        let expired = table.expire(start + Duration::from_secs(12), active, inactive);
+        assert!(expired.is_empty());
+
+        table.update(
+            busy_key,
+            28,
+            start + Duration::from_secs(29),
+            SystemTime::now(),
+        );
+        let expired = table.expire(start + Duration::from_secs(30), active, inactive);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0.packets, 3);
+        assert_eq!(expired[0].0.octets, 84);
+        assert_eq!(expired[0].1, FlowEndReason::ActiveTimeout);
+    }
+
+    #[test]
+    fn encoder_splits_messages_and_counts_sequence() {
+        let mut table = FlowTable::default();
+        for port in 0..40 {
+            let key = FlowKey::from_ipv4(&udp_packet([10, 0, 0, 2], port, 53));
+            table.update(key, 28, Instant::now(), SystemTime::now());
+        }
+
+        let mut encoder = IpfixEncoder::new(7);
+        let messages = encoder.encode(&table.drain(), SystemTime::now());
+        assert_eq!(messages.len(), 2);
+        for message in messages.iter() {
+            assert!(message.len() <= IPFIX_MAX_MESSAGE_LEN);
+            assert_eq!(read_u16(message, 0), IPFIX_VERSION);
+            assert_eq!(read_u16(message, 2) as usize, message.len());
+            assert_eq!(&message[12..16], &7u32.to_be_bytes());
+        }
+        assert_eq!(&messages[0][8..12], &0u32.to_be_bytes());
+        let first_message_records =
+            (messages[0].len() - IPFIX_HEADER_LEN - IPFIX_TEMPLATE_SET_LEN - 4) / IPFIX_RECORD_LEN;
+        assert_eq!(
+            &messages[1][8..12],
+            &(first_message_records as u32).to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn exports_flows_to_collector() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let collector_addr = collector.local_addr().unwrap();
+
+        let packets = vec![
+            udp_packet([10, 0, 0, 2], 5000, 53),
+            udp_packet([10, 0, 0, 2], 5000, 53),
+            udp_packet([10, 0, 0, 3], 6000, 80),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = FlowExporterComposite::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .collector(collector_addr)
+                .export_interval(Duration::from_millis(10))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+
+        let mut buf = [0; 1500];
+        let len = collector.recv(&mut buf).unwrap();
+        let message = &buf[..len];
+        assert_eq!(read_u16(message, 0), IPFIX_VERSION);
+        assert_eq!(read_u16(message, 2) as usize, len);
+        assert_eq!(read_u16(message, IPFIX_HEADER_LEN), IPFIX_TEMPLATE_SET_ID);
+
+        let data_set = IPFIX_HEADER_LEN + IPFIX_TEMPLATE_SET_LEN;
+        assert_eq!(read_u16(message, data_set), IPFIX_TEMPLATE_ID);
+        assert_eq!(
+            read_u16(message, data_set + 2) as usize,
+            4 + 2 * IPFIX_RECORD_LEN
+        );
+
+        let mut packet_counts: Vec<u64> = (0..2)
+            .map(|i| read_u64(message, data_set + 4 + i * IPFIX_RECORD_LEN + 13))
+            .collect();
+        packet_counts.sort();
+        assert_eq!(packet_counts, vec![1, 2]);
+    }
+
+    #[test]
+    fn takes_settings_from_config() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let config = FlowExportConfig {
+            collector: collector.local_addr().unwrap(),
+            active_timeout_secs: None,
+            inactive_timeout_secs: None,
+            export_interval_secs: Some(1),
+            observation_domain_id: Some(7),
+        };
+
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let link = FlowExporterComposite::new()
+                .config(&config)
+                .ingressor(immediate_stream(vec![udp_packet([10, 0, 0, 2], 5000, 53)]))
+                .build_link();
+
+            run_link(link).await
+        });
+
+        let mut buf = [0; 1500];
+        let len = collector.recv(&mut buf).unwrap();
+        assert_eq!(read_u32(&buf[..len], 12), 7);
+    }
+}
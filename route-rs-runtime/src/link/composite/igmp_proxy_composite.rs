@@ -0,0 +1,427 @@
+use crate::link::composite::multicast_composite::{is_link_local_group, multicast_group};
+use crate::link::composite::MulticastMembership;
+use crate::link::primitive::{ForkLink, JoinLink, ProcessLink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::Processor;
+use route_rs_packets::{
+    EthernetFrame, IgmpPacket, Ipv4Packet, MacAddr, MulticastGroupRecord, CHANGE_TO_EXCLUDE_MODE,
+    CHANGE_TO_INCLUDE_MODE, IGMP_MEMBERSHIP_QUERY, IPV4_ETHER_TYPE, MODE_IS_EXCLUDE,
+};
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+
+/// Where IGMPv3 reports are sent, RFC 3376 section 4.2.14.
+const ALL_IGMPV3_ROUTERS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 22);
+
+const IGMP_PROTOCOL: u8 = 2;
+
+/// An IGMPv3 report of `records`, from `src_addr`, to the upstream routers.
+fn upstream_report(records: &[MulticastGroupRecord], src_addr: Ipv4Addr) -> EthernetFrame {
+    let mut igmp = IgmpPacket::new();
+    igmp.set_group_records(records).set_checksum();
+    let mut packet = igmp.packet();
+    packet.set_src_addr(src_addr);
+    packet.set_dest_addr(ALL_IGMPV3_ROUTERS);
+    packet.set_checksum();
+    let mut frame = EthernetFrame::encap_ipv4(packet);
+    frame.set_dest_mac(MacAddr::new([0x01, 0x00, 0x5e, 0x00, 0x00, 0x16]));
+    frame
+}
+
+/// The IGMP message of a frame, if it is one.
+fn igmp_message(frame: &EthernetFrame) -> Option<IgmpPacket> {
+    let payload = &frame.data[frame.payload_offset..];
+    if frame.ether_type() != IPV4_ETHER_TYPE || payload.len() <= 9 || payload[9] != IGMP_PROTOCOL {
+        return None;
+    }
+    Ipv4Packet::try_from(frame.clone())
+        .and_then(IgmpPacket::try_from)
+        .ok()
+}
+
+/// Learns the membership of the downstream ports from their reports, and reports upstream each
+/// group the first port joins, or the last port leaves.
+struct ReportAggregator {
+    membership: Arc<MulticastMembership>,
+    upstream_addr: Ipv4Addr,
+}
+
+impl Processor for ReportAggregator {
+    type Input = (usize, EthernetFrame);
+    type Output = EthernetFrame;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let (port, frame) = packet;
+        let igmp = igmp_message(&frame)?;
+
+        let mut changes = vec![];
+        for record in igmp.group_records() {
+            if is_link_local_group(record.group) {
+                continue;
+            }
+            let had_members = !self.membership.members(record.group).is_empty();
+            if record.is_join() {
+                self.membership.join(record.group, port);
+            } else if record.is_leave() {
+                self.membership.leave(record.group, port);
+            }
+            let has_members = !self.membership.members(record.group).is_empty();
+            let record_type = match (had_members, has_members) {
+                (false, true) => CHANGE_TO_EXCLUDE_MODE,
+                (true, false) => CHANGE_TO_INCLUDE_MODE,
+                _ => continue,
+            };
+            changes.push(MulticastGroupRecord {
+                record_type,
+                group: record.group,
+                num_sources: 0,
+            });
+        }
+
+        if changes.is_empty() {
+            None
+        } else {
+            Some(upstream_report(&changes, self.upstream_addr))
+        }
+    }
+}
+
+/// Answers the queries of the upstream routers with the groups the downstream ports have joined.
+struct QueryResponder {
+    membership: Arc<MulticastMembership>,
+    upstream_addr: Ipv4Addr,
+}
+
+impl Processor for QueryResponder {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        let igmp = igmp_message(&frame)?;
+        if igmp.message_type() != IGMP_MEMBERSHIP_QUERY {
+            return None;
+        }
+        let queried = igmp.group_addr();
+        let mut groups: Vec<IpAddr> = if queried.is_unspecified() {
+            self.membership
+                .groups()
+                .into_iter()
+                .filter(|group| group.is_ipv4() && !is_link_local_group(*group))
+                .collect()
+        } else if !self.membership.members(IpAddr::V4(queried)).is_empty() {
+            vec![IpAddr::V4(queried)]
+        } else {
+            vec![]
+        };
+        if groups.is_empty() {
+            return None;
+        }
+        groups.sort();
+
+        let records: Vec<MulticastGroupRecord> = groups
+            .into_iter()
+            .map(|group| MulticastGroupRecord {
+                record_type: MODE_IS_EXCLUDE,
+                group,
+                num_sources: 0,
+            })
+            .collect();
+        Some(upstream_report(&records, self.upstream_addr))
+    }
+}
+
+/// Passes the copy of an upstream frame meant for one downstream port, if the frame is multicast
+/// data for a group the port has joined.
+struct DownstreamFilter {
+    port: usize,
+    membership: Arc<MulticastMembership>,
+}
+
+impl Processor for DownstreamFilter {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        match multicast_group(&frame) {
+            Some(group)
+                if !is_link_local_group(group)
+                    && igmp_message(&frame).is_none()
+                    && self.membership.members(group).contains(&self.port) =>
+            {
+                Some(frame)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Proxies IGMP between the downstream ports of a router and its upstream interface, RFC 4605,
+/// as for IPTV, where the set-top boxes on the LAN join channels that the ISP multicasts on the
+/// WAN.
+///
+/// The composite's ingressor takes the IGMP reports of the downstream ports, tagged with the
+/// port they arrived on, and its `upstream_ingressor` the frames of the upstream interface. The
+/// first `num_ports` egressors carry the multicast data to send out of each downstream port, and
+/// the last the IGMPv3 reports to send upstream. The router joins a group upstream when the first
+/// port joins it, and leaves it when the last one leaves, and answers upstream queries with every
+/// group it has joined. Multicast data from upstream is sent only to the ports that joined its
+/// group, and everything else from upstream, link local groups and IGMP included, is dropped.
+///
+/// The proxy does not query the downstream ports itself, so a port keeps its membership until it
+/// leaves. The membership table may be shared with `membership`, to inspect it, or add static
+/// members.
+#[derive(Default)]
+pub struct IgmpProxyComposite {
+    in_stream: Option<PacketStream<(usize, EthernetFrame)>>,
+    upstream_stream: Option<PacketStream<EthernetFrame>>,
+    num_ports: Option<usize>,
+    upstream_addr: Option<Ipv4Addr>,
+    membership: Option<Arc<MulticastMembership>>,
+}
+
+impl IgmpProxyComposite {
+    pub fn new() -> Self {
+        IgmpProxyComposite {
+            in_stream: None,
+            upstream_stream: None,
+            num_ports: None,
+            upstream_addr: None,
+            membership: None,
+        }
+    }
+
+    /// The frames received on the upstream interface.
+    pub fn upstream_ingressor(self, upstream_stream: PacketStream<EthernetFrame>) -> Self {
+        IgmpProxyComposite {
+            in_stream: self.in_stream,
+            upstream_stream: Some(upstream_stream),
+            num_ports: self.num_ports,
+            upstream_addr: self.upstream_addr,
+            membership: self.membership,
+        }
+    }
+
+    pub fn num_ports(self, num_ports: usize) -> Self {
+        assert!(num_ports > 0, "num_ports: {}, must be > 0", num_ports);
+
+        IgmpProxyComposite {
+            in_stream: self.in_stream,
+            upstream_stream: self.upstream_stream,
+            num_ports: Some(num_ports),
+            upstream_addr: self.upstream_addr,
+            membership: self.membership,
+        }
+    }
+
+    /// The address of the upstream interface, which reports are sent from. Defaults to 0.0.0.0,
+    /// which RFC 3376 allows reports to be sent from.
+    pub fn upstream_addr(self, upstream_addr: Ipv4Addr) -> Self {
+        IgmpProxyComposite {
+            in_stream: self.in_stream,
+            upstream_stream: self.upstream_stream,
+            num_ports: self.num_ports,
+            upstream_addr: Some(upstream_addr),
+            membership: self.membership,
+        }
+    }
+
+    /// Shares the membership table, for instance to inspect it or to add static members.
+    pub fn membership(self, membership: Arc<MulticastMembership>) -> Self {
+        IgmpProxyComposite {
+            in_stream: self.in_stream,
+            upstream_stream: self.upstream_stream,
+            num_ports: self.num_ports,
+            upstream_addr: self.upstream_addr,
+            membership: Some(membership),
+        }
+    }
+}
+
+impl LinkBuilder<(usize, EthernetFrame), EthernetFrame> for IgmpProxyComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<(usize, EthernetFrame)>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "IgmpProxyComposite may only take 1 input stream, and 1 upstream input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(
+        self,
+        in_stream: PacketStream<(usize, EthernetFrame)>,
+    ) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "IgmpProxyComposite may only take 1 input stream, and 1 upstream input stream",
+            ));
+        }
+        Ok(IgmpProxyComposite {
+            in_stream: Some(in_stream),
+            upstream_stream: self.upstream_stream,
+            num_ports: self.num_ports,
+            upstream_addr: self.upstream_addr,
+            membership: self.membership,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<EthernetFrame>, LinkBuildError> {
+        let (in_stream, upstream_stream, num_ports) =
+            match (self.in_stream, self.upstream_stream, self.num_ports) {
+                (None, _, _) => return Err(LinkBuildError::Missing("input stream")),
+                (_, None, _) => return Err(LinkBuildError::Missing("upstream input stream")),
+                (_, _, None) => return Err(LinkBuildError::Missing("num_ports")),
+                (Some(in_stream), Some(upstream_stream), Some(num_ports)) => {
+                    (in_stream, upstream_stream, num_ports)
+                }
+            };
+        let membership = self.membership.unwrap_or_default();
+        let upstream_addr = self.upstream_addr.unwrap_or(Ipv4Addr::UNSPECIFIED);
+
+        let (mut runnables, mut reports) = ProcessLink::new()
+            .try_ingressor(in_stream)?
+            .processor(ReportAggregator {
+                membership: Arc::clone(&membership),
+                upstream_addr,
+            })
+            .try_build_link()?;
+
+        let (mut fork_runnables, mut fork_egressors) = ForkLink::new()
+            .try_ingressor(upstream_stream)?
+            .num_egressors(num_ports + 1)
+            .try_build_link()?;
+        runnables.append(&mut fork_runnables);
+
+        let (mut responder_runnables, mut responses) = ProcessLink::new()
+            .try_ingressor(fork_egressors.pop().unwrap())?
+            .processor(QueryResponder {
+                membership: Arc::clone(&membership),
+                upstream_addr,
+            })
+            .try_build_link()?;
+        runnables.append(&mut responder_runnables);
+
+        let mut egressors = vec![];
+        for (port, fork_egressor) in fork_egressors.into_iter().enumerate() {
+            let (mut filter_runnables, mut filter_egressors) = ProcessLink::new()
+                .try_ingressor(fork_egressor)?
+                .processor(DownstreamFilter {
+                    port,
+                    membership: Arc::clone(&membership),
+                })
+                .try_build_link()?;
+            runnables.append(&mut filter_runnables);
+            egressors.append(&mut filter_egressors);
+        }
+
+        reports.append(&mut responses);
+        let (mut join_runnables, mut upstream) =
+            JoinLink::new().try_ingressors(reports)?.try_build_link()?;
+        runnables.append(&mut join_runnables);
+        egressors.append(&mut upstream);
+
+        Ok((runnables, egressors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::{UdpSegment, IGMP_LEAVE_GROUP, IGMP_V2_MEMBERSHIP_REPORT};
+
+    fn igmp_frame(message_type: u8, group: Ipv4Addr) -> EthernetFrame {
+        let mut igmp = IgmpPacket::new();
+        igmp.set_message_type(message_type)
+            .set_group_addr(group)
+            .set_checksum();
+        EthernetFrame::encap_ipv4(igmp.packet())
+    }
+
+    fn udp_frame(dest: Ipv4Addr) -> EthernetFrame {
+        let mut packet = Ipv4Packet::encap_udp(UdpSegment::empty());
+        packet.set_dest_addr(dest);
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    fn report_records(frame: &EthernetFrame) -> Vec<MulticastGroupRecord> {
+        igmp_message(frame).unwrap().group_records()
+    }
+
+    #[test]
+    fn reports_first_join_and_last_leave() {
+        let group = Ipv4Addr::new(239, 10, 0, 1);
+        let mut aggregator = ReportAggregator {
+            membership: Arc::new(MulticastMembership::new()),
+            upstream_addr: Ipv4Addr::new(100, 64, 0, 2),
+        };
+
+        let join = aggregator
+            .process((0, igmp_frame(IGMP_V2_MEMBERSHIP_REPORT, group)))
+            .unwrap();
+        let records = report_records(&join);
+        assert_eq!(records[0].group, IpAddr::V4(group));
+        assert_eq!(records[0].record_type, CHANGE_TO_EXCLUDE_MODE);
+        let packet = Ipv4Packet::try_from(join).unwrap();
+        assert_eq!(packet.dest_addr(), ALL_IGMPV3_ROUTERS);
+        assert_eq!(packet.src_addr(), Ipv4Addr::new(100, 64, 0, 2));
+
+        assert!(aggregator
+            .process((1, igmp_frame(IGMP_V2_MEMBERSHIP_REPORT, group)))
+            .is_none());
+        assert!(aggregator
+            .process((0, igmp_frame(IGMP_LEAVE_GROUP, group)))
+            .is_none());
+        let leave = aggregator
+            .process((1, igmp_frame(IGMP_LEAVE_GROUP, group)))
+            .unwrap();
+        assert!(report_records(&leave)[0].is_leave());
+
+        // Link local groups are never proxied.
+        assert!(aggregator
+            .process((
+                0,
+                igmp_frame(IGMP_V2_MEMBERSHIP_REPORT, Ipv4Addr::new(224, 0, 0, 251))
+            ))
+            .is_none());
+    }
+
+    #[test]
+    fn forwards_joined_groups_and_answers_queries() {
+        let channel = Ipv4Addr::new(239, 10, 0, 1);
+        let other = Ipv4Addr::new(239, 10, 0, 2);
+        let membership = Arc::new(MulticastMembership::new());
+        membership.join(IpAddr::V4(channel), 1);
+
+        let upstream = vec![
+            udp_frame(channel),
+            udp_frame(other),
+            igmp_frame(IGMP_MEMBERSHIP_QUERY, Ipv4Addr::UNSPECIFIED),
+            igmp_frame(IGMP_MEMBERSHIP_QUERY, other),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = IgmpProxyComposite::new()
+                .ingressor(immediate_stream(vec![]))
+                .upstream_ingressor(immediate_stream(upstream))
+                .num_ports(2)
+                .membership(membership)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert!(results[0].is_empty());
+        assert_eq!(results[1], vec![udp_frame(channel)]);
+        assert_eq!(results[2].len(), 1);
+        let records = report_records(&results[2][0]);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].group, IpAddr::V4(channel));
+        assert_eq!(records[0].record_type, MODE_IS_EXCLUDE);
+    }
+}
@@ -0,0 +1,1053 @@
+use crate::config::{RouterConfig, SaConfig, SaDirection, SaMode, Subnet};
+use crate::link::event::{DropAccounting, DropCounters, DropReason};
+use crate::link::primitive::QueueLink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::{Processor, ProcessorContext};
+use crate::state::StateStore;
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{Aes128Gcm, Aes256Gcm, KeyInit, Nonce, Tag};
+use route_rs_packets::{EspPacket, IpProtocol, Ipv4Packet};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The name the sequence numbers of IPsec security associations are kept under in the
+/// `StateStore`, so that a composite built again carries on from those used before.
+pub const IPSEC_SEQUENCES_STATE: &str = "ipsec-sequences";
+
+const ESP_PROTOCOL: u8 = 50;
+
+/// The next header of a packet tunneled whole, IP in IP.
+const IPV4_IN_IPV4: u8 = 4;
+
+/// The explicit IV and ICV of AES-GCM in ESP, RFC 4106 sections 3.1 and 6.
+const IV_LEN: usize = 8;
+const ICV_LEN: usize = 16;
+
+/// How many sequence numbers an outbound association takes at once, recording the last of them
+/// before it sends the first, and how far the replay window of an inbound association moves on
+/// between recording where it is.
+const SEQUENCE_BLOCK: u64 = 1 << 16;
+
+/// A security association, by its direction and SPI.
+type AssociationKey = (SaDirection, u32);
+
+#[derive(Serialize, Deserialize)]
+struct SavedAssociation {
+    direction: SaDirection,
+    spi: u32,
+    sequence: u64,
+}
+
+/// `Sequences` as they are saved to a file.
+#[derive(Serialize, Deserialize)]
+struct SavedSequences {
+    #[serde(default)]
+    association: Vec<SavedAssociation>,
+}
+
+/// The last sequence number each outbound security association may have sent, and the highest
+/// each inbound security association has accepted. The IVs of a static key are its sequence
+/// numbers, which must never repeat, nor be accepted twice, and static keys outlive the
+/// composite, so these do too.
+#[derive(Default)]
+struct Sequences {
+    sequences: Mutex<HashMap<AssociationKey, u64>>,
+}
+
+impl Sequences {
+    fn get(&self, key: AssociationKey) -> Option<u64> {
+        self.sequences.lock().unwrap().get(&key).copied()
+    }
+
+    /// Moves the sequence number of an association on to `sequence`, never back, and saves them
+    /// all to `persist`, if it is given, replacing it whole.
+    fn record(&self, key: AssociationKey, sequence: u64, persist: Option<&Path>) -> io::Result<()> {
+        let mut sequences = self.sequences.lock().unwrap();
+        let entry = sequences.entry(key).or_insert(sequence);
+        *entry = (*entry).max(sequence);
+        let path = match persist {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let saved = SavedSequences {
+            association: sequences
+                .iter()
+                .map(|(&(direction, spi), &sequence)| SavedAssociation {
+                    direction,
+                    spi,
+                    sequence,
+                })
+                .collect(),
+        };
+        let contents = toml::to_string(&saved)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let partial = path.with_extension("partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, path)
+    }
+
+    /// Moves the sequence numbers on to those saved to a file, if they are further on.
+    fn load(&self, path: &Path) -> io::Result<()> {
+        let saved: SavedSequences = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let mut sequences = self.sequences.lock().unwrap();
+        for association in saved.association {
+            let entry = sequences
+                .entry((association.direction, association.spi))
+                .or_insert(association.sequence);
+            *entry = (*entry).max(association.sequence);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+enum Cipher {
+    Aes128(Box<Aes128Gcm>),
+    Aes256(Box<Aes256Gcm>),
+}
+
+/// Packets received ahead of those behind them, RFC 4303 section 3.4.3: the highest sequence
+/// number received, and which of the `size` before it have been.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ReplayWindow {
+    size: u32,
+    top: u32,
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn new(size: u32) -> Self {
+        ReplayWindow {
+            size: size.clamp(1, 64),
+            top: 0,
+            seen: 0,
+        }
+    }
+
+    /// A window that has received everything up to `top`, for an association carrying on from
+    /// where it was recorded.
+    fn resume(size: u32, top: u32) -> Self {
+        ReplayWindow {
+            top,
+            seen: u64::MAX,
+            ..ReplayWindow::new(size)
+        }
+    }
+
+    /// Whether a packet of `sequence` may be new, to be checked before it is authenticated.
+    fn check(&self, sequence: u32) -> bool {
+        if sequence == 0 {
+            return false;
+        }
+        if sequence > self.top {
+            return true;
+        }
+        let behind = self.top - sequence;
+        behind < self.size && self.seen & (1 << behind) == 0
+    }
+
+    /// Records a packet of `sequence` as received, once it is authenticated.
+    fn update(&mut self, sequence: u32) {
+        if sequence > self.top {
+            let ahead = sequence - self.top;
+            self.seen = if ahead >= 64 { 0 } else { self.seen << ahead };
+            self.seen |= 1;
+            self.top = sequence;
+        } else {
+            self.seen |= 1 << (self.top - sequence);
+        }
+    }
+}
+
+/// A security association with a static key, AES-GCM with an explicit IV, RFC 4106, one way
+/// between the router and a peer.
+#[derive(Clone)]
+pub struct SecurityAssociation {
+    spi: u32,
+    mode: SaMode,
+    local: Ipv4Addr,
+    remote: Ipv4Addr,
+    cipher: Cipher,
+    salt: [u8; 4],
+    traffic: Option<Subnet>,
+    replay_window: u32,
+}
+
+impl SecurityAssociation {
+    /// An association of `key`, the AES-GCM key followed by its 4 byte salt, 20 bytes for AES-128,
+    /// or 36 for AES-256.
+    pub fn new(
+        spi: u32,
+        mode: SaMode,
+        local: Ipv4Addr,
+        remote: Ipv4Addr,
+        key: &[u8],
+    ) -> Result<Self, String> {
+        let (key, salt) = match key.len() {
+            20 | 36 => key.split_at(key.len() - 4),
+            len => return Err(format!("SA {:#x} has a key of {} bytes", spi, len)),
+        };
+        let cipher = match key.len() {
+            16 => Cipher::Aes128(Box::new(Aes128Gcm::new_from_slice(key).unwrap())),
+            _ => Cipher::Aes256(Box::new(Aes256Gcm::new_from_slice(key).unwrap())),
+        };
+        let mut salt_bytes = [0; 4];
+        salt_bytes.copy_from_slice(salt);
+        Ok(SecurityAssociation {
+            spi,
+            mode,
+            local,
+            remote,
+            cipher,
+            salt: salt_bytes,
+            traffic: None,
+            replay_window: 64,
+        })
+    }
+
+    pub fn from_config(config: &SaConfig) -> Result<Self, String> {
+        let key = config
+            .key_bytes()
+            .ok_or_else(|| format!("SA {:#x} has a bad key", config.spi))?;
+        let sa = SecurityAssociation::new(
+            config.spi,
+            config.mode.unwrap_or(SaMode::Transport),
+            config.local,
+            config.remote,
+            &key,
+        )?;
+        let sa = match config.traffic {
+            Some(traffic) => sa.traffic(traffic),
+            None => sa,
+        };
+        Ok(sa.replay_window(config.replay_window.unwrap_or(64)))
+    }
+
+    /// The destinations whose packets the association protects, outbound. Defaults to the peer
+    /// in transport mode, and everything in tunnel mode.
+    pub fn traffic(self, traffic: Subnet) -> Self {
+        SecurityAssociation {
+            traffic: Some(traffic),
+            ..self
+        }
+    }
+
+    /// Changes replay_window, up to 64, default value is 64.
+    pub fn replay_window(self, replay_window: u32) -> Self {
+        assert!(
+            (1..=64).contains(&replay_window),
+            "replay_window must be between 1 and 64"
+        );
+        SecurityAssociation {
+            replay_window,
+            ..self
+        }
+    }
+
+    pub fn spi(&self) -> u32 {
+        self.spi
+    }
+
+    /// Whether the association protects a packet the router sends to `dest`.
+    fn protects(&self, dest: Ipv4Addr) -> bool {
+        match (self.traffic, self.mode) {
+            (Some(traffic), _) => traffic.contains(IpAddr::V4(dest)),
+            (None, SaMode::Transport) => dest == self.remote,
+            (None, SaMode::Tunnel) => true,
+        }
+    }
+
+    /// The nonce of a packet, the salt followed by its explicit IV.
+    fn nonce(&self, iv: &[u8]) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[..4].copy_from_slice(&self.salt);
+        nonce[4..].copy_from_slice(iv);
+        nonce
+    }
+
+    fn seal(&self, iv: &[u8], aad: &[u8], data: &mut [u8]) -> [u8; ICV_LEN] {
+        let nonce = self.nonce(iv);
+        let nonce = Nonce::from_slice(&nonce);
+        let tag = match &self.cipher {
+            Cipher::Aes128(cipher) => cipher.encrypt_in_place_detached(nonce, aad, data),
+            Cipher::Aes256(cipher) => cipher.encrypt_in_place_detached(nonce, aad, data),
+        };
+        tag.expect("ESP payloads are never too long for AES-GCM")
+            .into()
+    }
+
+    fn open(&self, iv: &[u8], aad: &[u8], data: &mut [u8], icv: &[u8]) -> bool {
+        let nonce = self.nonce(iv);
+        let nonce = Nonce::from_slice(&nonce);
+        let tag = Tag::from_slice(icv);
+        match &self.cipher {
+            Cipher::Aes128(cipher) => cipher.decrypt_in_place_detached(nonce, aad, data, tag),
+            Cipher::Aes256(cipher) => cipher.decrypt_in_place_detached(nonce, aad, data, tag),
+        }
+        .is_ok()
+    }
+}
+
+/// The counters of a security association, under the name of the processor using it, as
+/// `<spi>.packets` and `<spi>.bytes`, in hex, and `<spi>.replayed` and `<spi>.auth_failed` for
+/// those inbound.
+struct SaCounters {
+    packets: Arc<AtomicU64>,
+    bytes: Arc<AtomicU64>,
+    replayed: Arc<AtomicU64>,
+    auth_failed: Arc<AtomicU64>,
+}
+
+impl SaCounters {
+    fn new(context: Option<&ProcessorContext>, spi: u32) -> Self {
+        let counter = |name: &str| match context {
+            Some(context) => context.counter(&format!("{:08x}.{}", spi, name)),
+            None => Arc::new(AtomicU64::new(0)),
+        };
+        SaCounters {
+            packets: counter("packets"),
+            bytes: counter("bytes"),
+            replayed: counter("replayed"),
+            auth_failed: counter("auth_failed"),
+        }
+    }
+}
+
+fn count(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+/// The explicit IV of the packet of `sequence`, RFC 4106 section 3.1, which is the sequence
+/// number itself, so it never repeats under a key as long as the sequence numbers don't.
+fn iv(sequence: u64) -> [u8; IV_LEN] {
+    sequence.to_be_bytes()
+}
+
+/// An outbound association, with the sequence number it last sent, and the last of those
+/// recorded as taken.
+struct OutboundSa {
+    sa: SecurityAssociation,
+    sequence: u64,
+    reserved: u64,
+    counters: SaCounters,
+}
+
+/// Encapsulates the packets the outbound security associations protect in ESP, and passes the
+/// rest through.
+struct EspEncapsulator {
+    sas: Vec<OutboundSa>,
+    sequences: Arc<Sequences>,
+    persist: Option<PathBuf>,
+    exhausted: Arc<AtomicU64>,
+    unsaved: Arc<AtomicU64>,
+    drops: DropAccounting,
+}
+
+impl EspEncapsulator {
+    /// Encapsulates with `sas`, carrying on from the sequence numbers recorded in `sequences`.
+    fn new(sas: Vec<SecurityAssociation>, sequences: Arc<Sequences>) -> Self {
+        EspEncapsulator {
+            sas: sas
+                .into_iter()
+                .map(|sa| {
+                    let sequence = sequences.get((SaDirection::Outbound, sa.spi)).unwrap_or(0);
+                    OutboundSa {
+                        counters: SaCounters::new(None, sa.spi),
+                        sa,
+                        sequence,
+                        reserved: sequence,
+                    }
+                })
+                .collect(),
+            sequences,
+            persist: None,
+            exhausted: Arc::new(AtomicU64::new(0)),
+            unsaved: Arc::new(AtomicU64::new(0)),
+            drops: DropAccounting::default(),
+        }
+    }
+}
+
+impl Processor for EspEncapsulator {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let dest = packet.dest_addr();
+        let outbound = match self.sas.iter_mut().find(|out| out.sa.protects(dest)) {
+            Some(outbound) => outbound,
+            None => return Some(packet),
+        };
+        // The sequence number must never cycle, without extended sequence numbers, RFC 4303
+        // section 3.3.3, so an association that has used them all up sends nothing more.
+        if outbound.sequence >= u64::from(u32::MAX) {
+            count(&self.exhausted, 1);
+            self.drops.dropped(DropReason::Discarded);
+            return None;
+        }
+        // The packets sent after a restart must not reuse the IVs of those sent before it, so no
+        // sequence number is sent before it is recorded as taken.
+        if outbound.sequence >= outbound.reserved {
+            let reserved = outbound.sequence + SEQUENCE_BLOCK;
+            let key = (SaDirection::Outbound, outbound.sa.spi);
+            if self
+                .sequences
+                .record(key, reserved, self.persist.as_deref())
+                .is_err()
+            {
+                count(&self.unsaved, 1);
+                self.drops.dropped(DropReason::Discarded);
+                return None;
+            }
+            outbound.reserved = reserved;
+        }
+        outbound.sequence += 1;
+        let sequence = outbound.sequence;
+        let iv = iv(sequence);
+        let (sa, counters) = (&outbound.sa, &outbound.counters);
+
+        let (mut plaintext, next_header) = match sa.mode {
+            SaMode::Transport => (
+                packet.payload().to_vec(),
+                packet.data[packet.layer3_offset + 9],
+            ),
+            SaMode::Tunnel => (packet.data[packet.layer3_offset..].to_vec(), IPV4_IN_IPV4),
+        };
+        // Pads the payload and trailer to a multiple of 4 bytes, with padding 1, 2, 3, RFC 4303
+        // section 2.4.
+        let pad_len = (4 - (plaintext.len() + 2) % 4) % 4;
+        plaintext.extend((1..=pad_len as u8).collect::<Vec<u8>>());
+        plaintext.push(pad_len as u8);
+        plaintext.push(next_header);
+
+        let mut esp = EspPacket::new();
+        esp.set_spi(sa.spi).set_sequence(sequence as u32);
+        let icv = sa.seal(&iv, esp.header(), &mut plaintext);
+        let mut esp_payload = iv.to_vec();
+        esp_payload.extend_from_slice(&plaintext);
+        esp_payload.extend_from_slice(&icv);
+        esp.set_esp_payload(&esp_payload);
+
+        let mut out = match sa.mode {
+            SaMode::Transport => {
+                let mut out = packet;
+                out.set_protocol(ESP_PROTOCOL);
+                out.set_payload(&esp.packet().payload());
+                out
+            }
+            SaMode::Tunnel => {
+                let mut out = esp.packet();
+                out.set_src_addr(sa.local);
+                out.set_dest_addr(sa.remote);
+                out
+            }
+        };
+        out.set_checksum();
+        count(&counters.packets, 1);
+        count(&counters.bytes, u64::from(out.total_len()));
+        Some(out)
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        for outbound in self.sas.iter_mut() {
+            outbound.counters = SaCounters::new(Some(context), outbound.sa.spi);
+        }
+        self.exhausted = context.counter("exhausted");
+        self.unsaved = context.counter("unsaved");
+    }
+}
+
+/// An inbound association, with its replay window, and the top of the window last recorded.
+struct InboundSa {
+    sa: SecurityAssociation,
+    window: ReplayWindow,
+    recorded: u32,
+    counters: SaCounters,
+}
+
+/// Decapsulates the ESP packets of the inbound security associations, dropping those that are
+/// replayed, or fail to authenticate, and passes everything else through.
+struct EspDecapsulator {
+    sas: HashMap<u32, InboundSa>,
+    sequences: Arc<Sequences>,
+    persist: Option<PathBuf>,
+    unknown_spi: Arc<AtomicU64>,
+    malformed: Arc<AtomicU64>,
+    unsaved: Arc<AtomicU64>,
+    drops: DropAccounting,
+}
+
+impl EspDecapsulator {
+    /// Decapsulates with `sas`, refusing the sequence numbers recorded as accepted in
+    /// `sequences`.
+    fn new(sas: Vec<SecurityAssociation>, sequences: Arc<Sequences>) -> Self {
+        EspDecapsulator {
+            sas: sas
+                .into_iter()
+                .map(|sa| {
+                    let top = sequences
+                        .get((SaDirection::Inbound, sa.spi))
+                        .map(|top| u32::try_from(top).unwrap_or(u32::MAX));
+                    let window = match top {
+                        Some(top) => ReplayWindow::resume(sa.replay_window, top),
+                        None => ReplayWindow::new(sa.replay_window),
+                    };
+                    let inbound = InboundSa {
+                        counters: SaCounters::new(None, sa.spi),
+                        sa,
+                        window,
+                        recorded: top.unwrap_or(0),
+                    };
+                    (inbound.sa.spi, inbound)
+                })
+                .collect(),
+            sequences,
+            persist: None,
+            unknown_spi: Arc::new(AtomicU64::new(0)),
+            malformed: Arc::new(AtomicU64::new(0)),
+            unsaved: Arc::new(AtomicU64::new(0)),
+            drops: DropAccounting::default(),
+        }
+    }
+}
+
+impl Processor for EspDecapsulator {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        if packet.protocol() != IpProtocol::ESP {
+            return Some(packet);
+        }
+        let esp = match EspPacket::try_from(packet) {
+            Ok(esp) => esp,
+            Err(err) => {
+                count(&self.malformed, 1);
+                self.drops.parse_error(err);
+                return None;
+            }
+        };
+        let inbound = match self.sas.get_mut(&esp.spi()) {
+            Some(inbound) if esp.ipv4().dest_addr() == inbound.sa.local => inbound,
+            _ => {
+                count(&self.unknown_spi, 1);
+                self.drops.dropped(DropReason::Unclassified);
+                return None;
+            }
+        };
+        let (sa, counters) = (&inbound.sa, &inbound.counters);
+        let sequence = esp.sequence();
+        if !inbound.window.check(sequence) {
+            count(&counters.replayed, 1);
+            self.drops.dropped(DropReason::Unauthenticated);
+            return None;
+        }
+        let payload = esp.esp_payload();
+        if payload.len() < IV_LEN + 2 + ICV_LEN {
+            count(&self.malformed, 1);
+            self.drops.dropped(DropReason::Malformed);
+            return None;
+        }
+        let (iv, rest) = payload.split_at(IV_LEN);
+        let (ciphertext, icv) = rest.split_at(rest.len() - ICV_LEN);
+        let mut plaintext = ciphertext.to_vec();
+        if !sa.open(iv, esp.header(), &mut plaintext, icv) {
+            count(&counters.auth_failed, 1);
+            self.drops.dropped(DropReason::Unauthenticated);
+            return None;
+        }
+        inbound.window.update(sequence);
+        // Where the window is is recorded every block of sequence numbers, so that packets
+        // captured before a restart are refused after it, bar those of the last block.
+        let top = inbound.window.top;
+        if u64::from(top) >= u64::from(inbound.recorded) + SEQUENCE_BLOCK {
+            let key = (SaDirection::Inbound, sa.spi);
+            if self
+                .sequences
+                .record(key, u64::from(top), self.persist.as_deref())
+                .is_err()
+            {
+                count(&self.unsaved, 1);
+            }
+            inbound.recorded = top;
+        }
+        count(&counters.packets, 1);
+        count(&counters.bytes, u64::from(esp.ipv4().total_len()));
+
+        let next_header = plaintext.pop()?;
+        let pad_len = usize::from(plaintext.pop()?);
+        if pad_len > plaintext.len() {
+            count(&self.malformed, 1);
+            self.drops.dropped(DropReason::Malformed);
+            return None;
+        }
+        plaintext.truncate(plaintext.len() - pad_len);
+
+        match (sa.mode, next_header) {
+            (SaMode::Tunnel, IPV4_IN_IPV4) => match Ipv4Packet::from_buffer(plaintext, None, 0) {
+                Ok(inner) => Some(inner),
+                Err(_) => {
+                    count(&self.malformed, 1);
+                    self.drops.dropped(DropReason::Malformed);
+                    None
+                }
+            },
+            (SaMode::Transport, _) => {
+                let mut out = esp.packet();
+                out.set_protocol(next_header);
+                out.set_payload(&plaintext);
+                out.set_checksum();
+                Some(out)
+            }
+            _ => {
+                count(&self.malformed, 1);
+                self.drops.dropped(DropReason::Malformed);
+                None
+            }
+        }
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        for inbound in self.sas.values_mut() {
+            inbound.counters = SaCounters::new(Some(context), inbound.sa.spi);
+        }
+        self.unknown_spi = context.counter("unknown_spi");
+        self.malformed = context.counter("malformed");
+        self.unsaved = context.counter("unsaved");
+    }
+}
+
+/// Protects traffic between the router and its peers with IPsec ESP, RFC 4303, in transport or
+/// tunnel mode, with statically keyed security associations using AES-GCM, RFC 4106. There is no
+/// IKE yet, so keys are configured by hand, as `[[security_associations]]` of the `RouterConfig`,
+/// or with `outbound` and `inbound`.
+///
+/// The composite's ingressor takes the packets the router sends toward its peers, and its
+/// `inbound_ingressor` those it receives from them. Its first egressor sends on the outbound
+/// packets, encapsulated by the first outbound association that protects them, and its second the
+/// inbound packets, decapsulated by the association of their SPI. Packets no association protects,
+/// and inbound packets other than ESP, pass through unchanged, so any policy requiring protection
+/// belongs in front of the composite.
+///
+/// Inbound ESP packets replayed, outside the replay window, failing authentication, or of an SPI
+/// the router doesn't know, are dropped. Encryption and decryption each run in a queue of their
+/// own, so the cost of the cipher is kept off the task forwarding the rest of the router's
+/// traffic. Each association counts its packets and bytes, and those dropped, under
+/// `ipsec-outbound` or `ipsec-inbound` in the metrics, as `<spi>.packets` and so on, the SPI in
+/// hex. The packets dropped are counted in the composite's `drop_counters` too, if it has them:
+/// those replayed or failing authentication as `DropReason::Unauthenticated`, and those of an
+/// unknown SPI as `DropReason::Unclassified`.
+///
+/// The IV of each packet is its sequence number, so the sequence numbers an outbound association
+/// sends under its static key must never repeat, and those an inbound association accepts must
+/// never be accepted again. The composite carries on from the sequence numbers of the
+/// associations of the same direction and SPI used before, kept in the `StateStore` under
+/// `IPSEC_SEQUENCES_STATE`, and saved to `persist` if it is given, to be loaded when the link is
+/// built. Outbound associations take their sequence numbers a block at a time, recording the
+/// block before sending any of it, and drop packets, counted as `unsaved`, if it can't be saved.
+/// Inbound associations record the top of their replay window after each block, and refuse
+/// everything up to it once built again, bar the packets of the last block received before a
+/// restart. Without `persist`, sequence numbers start over when the router restarts, so the
+/// associations must be rekeyed, with new keys and SPIs, each time it does.
+pub struct IpsecComposite {
+    in_stream: Option<PacketStream<Ipv4Packet>>,
+    inbound_stream: Option<PacketStream<Ipv4Packet>>,
+    outbound: Vec<SecurityAssociation>,
+    inbound: Vec<SecurityAssociation>,
+    configs: Vec<SaConfig>,
+    queue_capacity: usize,
+    drop_counters: Option<Arc<DropCounters>>,
+    persist: Option<PathBuf>,
+}
+
+impl Default for IpsecComposite {
+    fn default() -> Self {
+        IpsecComposite::new()
+    }
+}
+
+impl IpsecComposite {
+    pub fn new() -> Self {
+        IpsecComposite {
+            in_stream: None,
+            inbound_stream: None,
+            outbound: vec![],
+            inbound: vec![],
+            configs: vec![],
+            queue_capacity: 256,
+            drop_counters: None,
+            persist: None,
+        }
+    }
+
+    /// The packets received from the composite's peers.
+    pub fn inbound_ingressor(self, inbound_stream: PacketStream<Ipv4Packet>) -> Self {
+        IpsecComposite {
+            in_stream: self.in_stream,
+            inbound_stream: Some(inbound_stream),
+            outbound: self.outbound,
+            inbound: self.inbound,
+            configs: self.configs,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        }
+    }
+
+    /// Adds an outbound association, tried after those already added.
+    pub fn outbound(self, sa: SecurityAssociation) -> Self {
+        let mut outbound = self.outbound;
+        outbound.push(sa);
+        IpsecComposite {
+            in_stream: self.in_stream,
+            inbound_stream: self.inbound_stream,
+            outbound,
+            inbound: self.inbound,
+            configs: self.configs,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        }
+    }
+
+    pub fn inbound(self, sa: SecurityAssociation) -> Self {
+        let mut inbound = self.inbound;
+        inbound.push(sa);
+        IpsecComposite {
+            in_stream: self.in_stream,
+            inbound_stream: self.inbound_stream,
+            outbound: self.outbound,
+            inbound,
+            configs: self.configs,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        }
+    }
+
+    /// Adds the security associations of the `RouterConfig`.
+    pub fn config(self, config: &RouterConfig) -> Self {
+        let mut configs = self.configs;
+        configs.extend(config.security_associations.iter().cloned());
+        IpsecComposite {
+            in_stream: self.in_stream,
+            inbound_stream: self.inbound_stream,
+            outbound: self.outbound,
+            inbound: self.inbound,
+            configs,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        }
+    }
+
+    /// Changes queue_capacity, of each of the queues the ciphers run in, default value is 256.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        IpsecComposite {
+            in_stream: self.in_stream,
+            inbound_stream: self.inbound_stream,
+            outbound: self.outbound,
+            inbound: self.inbound,
+            configs: self.configs,
+            queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        }
+    }
+
+    /// Counts the packets the composite drops in `drop_counters`, by reason.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        IpsecComposite {
+            in_stream: self.in_stream,
+            inbound_stream: self.inbound_stream,
+            outbound: self.outbound,
+            inbound: self.inbound,
+            configs: self.configs,
+            queue_capacity: self.queue_capacity,
+            drop_counters: Some(drop_counters),
+            persist: self.persist,
+        }
+    }
+
+    /// The file the sequence numbers are loaded from, and saved to.
+    pub fn persist<P: AsRef<Path>>(self, path: P) -> Self {
+        IpsecComposite {
+            in_stream: self.in_stream,
+            inbound_stream: self.inbound_stream,
+            outbound: self.outbound,
+            inbound: self.inbound,
+            configs: self.configs,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: Some(path.as_ref().to_path_buf()),
+        }
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for IpsecComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "IpsecComposite may only take 1 input stream, and 1 inbound input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "IpsecComposite may only take 1 input stream, and 1 inbound input stream",
+            ));
+        }
+        Ok(IpsecComposite {
+            in_stream: Some(in_stream),
+            inbound_stream: self.inbound_stream,
+            outbound: self.outbound,
+            inbound: self.inbound,
+            configs: self.configs,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        match (self.in_stream, self.inbound_stream) {
+            (None, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None) => Err(LinkBuildError::Missing("inbound input stream")),
+            (Some(in_stream), Some(inbound_stream)) => {
+                let mut outbound = self.outbound;
+                let mut inbound = self.inbound;
+                for config in &self.configs {
+                    let sa = SecurityAssociation::from_config(config)
+                        .map_err(LinkBuildError::Invalid)?;
+                    match config.direction {
+                        SaDirection::Outbound => outbound.push(sa),
+                        SaDirection::Inbound => inbound.push(sa),
+                    }
+                }
+                let mut spis = HashMap::new();
+                if let Some(sa) = inbound.iter().find(|sa| spis.insert(sa.spi, ()).is_some()) {
+                    return Err(LinkBuildError::Invalid(format!(
+                        "inbound SA {:#x} is configured more than once",
+                        sa.spi
+                    )));
+                }
+
+                let sequences = StateStore::global()
+                    .get_or_insert_with(IPSEC_SEQUENCES_STATE, Sequences::default);
+                if let Some(path) = self.persist.as_ref().filter(|path| path.exists()) {
+                    sequences.load(path).map_err(|err| {
+                        LinkBuildError::Invalid(format!(
+                            "could not load {}, {}",
+                            path.display(),
+                            err
+                        ))
+                    })?;
+                }
+
+                let (mut runnables, mut egressors) = QueueLink::new()
+                    .try_ingressor(in_stream)?
+                    .processor(EspEncapsulator {
+                        persist: self.persist.clone(),
+                        drops: DropAccounting::new(self.drop_counters.clone(), None),
+                        ..EspEncapsulator::new(outbound, Arc::clone(&sequences))
+                    })
+                    .queue_capacity(self.queue_capacity)
+                    .context(ProcessorContext::new("ipsec-outbound"))
+                    .try_build_link()?;
+                let (mut inbound_runnables, mut inbound_egressors) = QueueLink::new()
+                    .try_ingressor(inbound_stream)?
+                    .processor(EspDecapsulator {
+                        persist: self.persist,
+                        drops: DropAccounting::new(self.drop_counters, None),
+                        ..EspDecapsulator::new(inbound, sequences)
+                    })
+                    .queue_capacity(self.queue_capacity)
+                    .context(ProcessorContext::new("ipsec-inbound"))
+                    .try_build_link()?;
+                runnables.append(&mut inbound_runnables);
+                egressors.append(&mut inbound_egressors);
+                Ok((runnables, egressors))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use uuid::Uuid;
+
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 2);
+    const PEER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+
+    fn key(len: u8) -> Vec<u8> {
+        (0..len).collect()
+    }
+
+    fn packet(src_addr: Ipv4Addr, dest_addr: Ipv4Addr, payload: &[u8]) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(17);
+        packet.set_src_addr(src_addr);
+        packet.set_dest_addr(dest_addr);
+        packet.set_payload(payload);
+        packet.set_checksum();
+        packet
+    }
+
+    #[test]
+    fn round_trips_through_a_tunnel() {
+        let lan_host = Ipv4Addr::new(10, 1, 0, 5);
+        let remote_host = Ipv4Addr::new(10, 20, 0, 9);
+        let sa = |local, remote| {
+            SecurityAssociation::new(0x1001, SaMode::Tunnel, local, remote, &key(36))
+                .unwrap()
+                .traffic("10.20.0.0/16".parse().unwrap())
+        };
+        let mut encapsulator = EspEncapsulator::new(vec![sa(ROUTER, PEER)], Arc::default());
+        let drop_counters = Arc::new(DropCounters::new());
+        let mut decapsulator = EspDecapsulator {
+            drops: DropAccounting::new(Some(Arc::clone(&drop_counters)), None),
+            ..EspDecapsulator::new(vec![sa(PEER, ROUTER)], Arc::default())
+        };
+
+        let inner = packet(lan_host, remote_host, b"hello");
+        let esp = encapsulator.process(inner.clone()).unwrap();
+        assert_eq!(esp.protocol(), IpProtocol::ESP);
+        assert_eq!((esp.src_addr(), esp.dest_addr()), (ROUTER, PEER));
+        assert!(!esp.payload().windows(5).any(|w| w == b"hello"));
+
+        let decrypted = decapsulator.process(esp.clone()).unwrap();
+        assert_eq!(decrypted.data, inner.data[inner.layer3_offset..].to_vec());
+
+        // A replay, and a tampered packet, are dropped.
+        assert!(decapsulator.process(esp).is_none());
+        let mut tampered = encapsulator.process(inner).unwrap();
+        let mut payload = tampered.payload().to_vec();
+        payload[20] ^= 1;
+        tampered.set_payload(&payload);
+        assert!(decapsulator.process(tampered).is_none());
+        let counters = &decapsulator.sas[&0x1001].counters;
+        assert_eq!(counters.replayed.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.auth_failed.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.packets.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            drop_counters.snapshot(),
+            vec![(DropReason::Unauthenticated, 2)]
+        );
+
+        // Traffic to elsewhere passes untouched.
+        let other = packet(lan_host, Ipv4Addr::new(192, 0, 2, 1), b"hi");
+        assert_eq!(encapsulator.process(other.clone()), Some(other));
+    }
+
+    #[test]
+    fn sequences_outlive_the_composite() {
+        let path = std::env::temp_dir().join(format!("ipsec-sequences-{}.toml", Uuid::new_v4()));
+        let sa = |local, remote| {
+            SecurityAssociation::new(0x3003, SaMode::Transport, local, remote, &key(20)).unwrap()
+        };
+        let build = |from_peer| {
+            let mut runtime = initialize_runtime();
+            runtime.block_on(async {
+                let link = IpsecComposite::new()
+                    .outbound(sa(ROUTER, PEER))
+                    .inbound(sa(ROUTER, PEER))
+                    .persist(&path)
+                    .ingressor(immediate_stream(vec![packet(ROUTER, PEER, b"out")]))
+                    .inbound_ingressor(immediate_stream(from_peer))
+                    .build_link();
+                run_link(link).await
+            })
+        };
+        let iv = |esp: &Ipv4Packet| esp.payload()[8..8 + IV_LEN].to_vec();
+
+        // The peer has sent a block of packets, so where the router's window is gets recorded.
+        let mut peer = EspEncapsulator::new(vec![sa(PEER, ROUTER)], Arc::default());
+        peer.sas[0].sequence = SEQUENCE_BLOCK;
+        peer.sas[0].reserved = 2 * SEQUENCE_BLOCK;
+        let old = peer.process(packet(PEER, ROUTER, b"old")).unwrap();
+        let results = build(vec![old.clone()]);
+        assert_eq!(iv(&results[0][0]), 1u64.to_be_bytes());
+        assert_eq!(results[1].len(), 1);
+
+        let restarted = Sequences::default();
+        restarted.load(&path).unwrap();
+        assert_eq!(
+            restarted.get((SaDirection::Outbound, 0x3003)),
+            Some(SEQUENCE_BLOCK)
+        );
+        assert_eq!(
+            restarted.get((SaDirection::Inbound, 0x3003)),
+            Some(SEQUENCE_BLOCK + 1)
+        );
+
+        // Built again, the composite sends after the block it took, and refuses the old packet.
+        let results = build(vec![old]);
+        assert_eq!(iv(&results[0][0]), (SEQUENCE_BLOCK + 1).to_be_bytes());
+        assert!(results[1].is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_window_slides() {
+        let mut window = ReplayWindow::new(4);
+        assert!(!window.check(0));
+        for sequence in [1, 3, 2] {
+            assert!(window.check(sequence));
+            window.update(sequence);
+        }
+        assert!(!window.check(2));
+        window.update(10);
+        assert!(window.check(7));
+        assert!(!window.check(6));
+        assert!(!window.check(10));
+
+        let resumed = ReplayWindow::resume(4, 10);
+        assert!(!resumed.check(9));
+        assert!(!resumed.check(10));
+        assert!(resumed.check(11));
+    }
+
+    #[test]
+    fn transport_mode_composite() {
+        let sa = |local, remote| {
+            SecurityAssociation::new(0x2002, SaMode::Transport, local, remote, &key(20)).unwrap()
+        };
+        let mut peer = EspEncapsulator::new(vec![sa(PEER, ROUTER)], Arc::default());
+        let from_peer = peer.process(packet(PEER, ROUTER, b"ping")).unwrap();
+
+        let mut runtime = initialize_runtime();
+        let mut results = runtime.block_on(async {
+            let link = IpsecComposite::new()
+                .outbound(sa(ROUTER, PEER))
+                .inbound(sa(ROUTER, PEER).replay_window(32))
+                .ingressor(immediate_stream(vec![packet(ROUTER, PEER, b"pong")]))
+                .inbound_ingressor(immediate_stream(vec![from_peer]))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 1);
+        assert_eq!(results[0][0].protocol(), IpProtocol::ESP);
+        assert_eq!(results[1].len(), 1);
+        assert_eq!(results[1][0].protocol(), IpProtocol::UDP);
+        assert_eq!(&results[1][0].payload()[..], b"ping");
+        assert!(results[1][0].validate_checksum());
+    }
+}
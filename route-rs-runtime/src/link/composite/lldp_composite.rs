@@ -0,0 +1,616 @@
+use crate::link::primitive::{ForkLink, ProcessLink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+#[cfg(feature = "mgmt")]
+use crate::mgmt::ManagementApi;
+use crate::processor::Processor;
+use crate::state::StateStore;
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::*;
+#[cfg(feature = "mgmt")]
+use serde_json::{json, Value};
+use std::convert::{TryFrom, TryInto};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::time::{interval_at, Interval};
+
+/// The name the neighbor table is shared under in the `StateStore`, unless the composite is given
+/// its own `LldpNeighbors`.
+pub const LLDP_NEIGHBORS_STATE: &str = "lldp-neighbors";
+
+/// The name the neighbor table is registered under in the management API.
+pub const LLDP_NEIGHBORS_TABLE: &str = "lldp-neighbors";
+
+/// The capabilities the router advertises, and has enabled: a router, IEEE 802.1AB-2016 section
+/// 8.5.8.1.
+const CAPABILITY_ROUTER: u16 = 0x0010;
+
+/// A chassis or port ID as text: a MAC address if that is its subtype, the ID itself if it is
+/// text, and its bytes in hex otherwise.
+fn id_string(subtype: u8, id: &[u8], mac_subtype: u8) -> String {
+    if subtype == mac_subtype {
+        if let Ok(bytes) = id.try_into() {
+            return MacAddr::new(bytes).to_string();
+        }
+    }
+    match std::str::from_utf8(id) {
+        Ok(text) if text.chars().all(|c| !c.is_control()) => String::from(text),
+        _ => id.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    }
+}
+
+fn tlv_string(lldp: &LldpFrame, tlv_type: u8) -> Option<String> {
+    lldp.tlv(tlv_type)
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+}
+
+/// A system heard from on one of the router's ports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LldpNeighbor {
+    /// The port of the router it was heard on.
+    pub local_port: String,
+    pub chassis_id: String,
+    pub port_id: String,
+    pub port_description: Option<String>,
+    pub system_name: Option<String>,
+    pub system_description: Option<String>,
+    /// When the neighbor is forgotten, unless it is heard from again.
+    pub expires: Instant,
+}
+
+/// The neighbors the `LldpComposite`s of a router have heard from, on all its ports, each kept for
+/// the time to live its last frame gave.
+#[derive(Default)]
+pub struct LldpNeighbors {
+    neighbors: RwLock<Vec<LldpNeighbor>>,
+}
+
+impl LldpNeighbors {
+    pub fn new() -> Self {
+        LldpNeighbors {
+            neighbors: RwLock::new(vec![]),
+        }
+    }
+
+    /// The neighbors that haven't expired, by local port, in the order they were first heard.
+    pub fn neighbors(&self) -> Vec<LldpNeighbor> {
+        let now = clock::now();
+        let mut neighbors: Vec<LldpNeighbor> = self
+            .neighbors
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|neighbor| neighbor.expires > now)
+            .cloned()
+            .collect();
+        neighbors.sort_by(|a, b| a.local_port.cmp(&b.local_port));
+        neighbors
+    }
+
+    /// Records a neighbor heard from, replacing what was heard from the same chassis and port
+    /// before, or forgets it if its time to live is 0. Expired neighbors are swept out too.
+    fn update(&self, neighbor: LldpNeighbor, ttl: Duration, now: Instant) {
+        let mut neighbors = self.neighbors.write().unwrap();
+        neighbors.retain(|known| known.expires > now);
+        let position = neighbors.iter().position(|known| {
+            known.local_port == neighbor.local_port
+                && known.chassis_id == neighbor.chassis_id
+                && known.port_id == neighbor.port_id
+        });
+        match (position, ttl > Duration::from_secs(0)) {
+            (Some(position), true) => neighbors[position] = neighbor,
+            (Some(position), false) => {
+                neighbors.remove(position);
+            }
+            (None, true) => neighbors.push(neighbor),
+            (None, false) => {}
+        }
+    }
+}
+
+#[cfg(feature = "mgmt")]
+impl LldpNeighbors {
+    /// Registers the neighbors as a table of the management API, under `LLDP_NEIGHBORS_TABLE`.
+    /// Each row has the seconds left before the neighbor expires, rather than when it does.
+    pub fn register(neighbors: &Arc<LldpNeighbors>, api: &ManagementApi) {
+        let neighbors = Arc::clone(neighbors);
+        api.register_table(LLDP_NEIGHBORS_TABLE, move || {
+            let now = clock::now();
+            Value::Array(
+                neighbors
+                    .neighbors()
+                    .into_iter()
+                    .map(|neighbor| {
+                        json!({
+                            "local_port": neighbor.local_port,
+                            "chassis_id": neighbor.chassis_id,
+                            "port_id": neighbor.port_id,
+                            "port_description": neighbor.port_description,
+                            "system_name": neighbor.system_name,
+                            "system_description": neighbor.system_description,
+                            "ttl": neighbor.expires.saturating_duration_since(now).as_secs(),
+                        })
+                    })
+                    .collect(),
+            )
+        });
+    }
+}
+
+/// What the router says of itself, and of the port, in its LLDP frames.
+struct Advertisement {
+    mac_addr: MacAddr,
+    port_id: String,
+    port_description: Option<String>,
+    system_name: Option<String>,
+    system_description: Option<String>,
+}
+
+impl Advertisement {
+    fn frame(&self, ttl: u16) -> EthernetFrame {
+        let mut chassis_id = vec![LLDP_CHASSIS_ID_MAC_ADDRESS];
+        chassis_id.extend_from_slice(&self.mac_addr.bytes);
+        let mut port_id = vec![LLDP_PORT_ID_INTERFACE_NAME];
+        port_id.extend_from_slice(self.port_id.as_bytes());
+        let mut capabilities = CAPABILITY_ROUTER.to_be_bytes().to_vec();
+        capabilities.extend_from_slice(&CAPABILITY_ROUTER.to_be_bytes());
+
+        let mut lldp = LldpFrame::new();
+        lldp.add_tlv(LLDP_TLV_CHASSIS_ID, &chassis_id)
+            .add_tlv(LLDP_TLV_PORT_ID, &port_id)
+            .add_tlv(LLDP_TLV_TTL, &ttl.to_be_bytes());
+        // A frame going away carries nothing but the mandatory TLVs.
+        if ttl > 0 {
+            if let Some(port_description) = &self.port_description {
+                lldp.add_tlv(LLDP_TLV_PORT_DESCRIPTION, port_description.as_bytes());
+            }
+            if let Some(system_name) = &self.system_name {
+                lldp.add_tlv(LLDP_TLV_SYSTEM_NAME, system_name.as_bytes());
+            }
+            if let Some(system_description) = &self.system_description {
+                lldp.add_tlv(LLDP_TLV_SYSTEM_DESCRIPTION, system_description.as_bytes());
+            }
+            lldp.add_tlv(LLDP_TLV_SYSTEM_CAPABILITIES, &capabilities);
+        }
+        let mut frame = lldp.frame();
+        frame.set_src_mac(self.mac_addr);
+        frame
+    }
+}
+
+/// Records the neighbor an LLDP frame is from in the table.
+fn receive(neighbors: &LldpNeighbors, local_port: &str, frame: EthernetFrame, now: Instant) {
+    let lldp = match LldpFrame::try_from(frame) {
+        Ok(lldp) => lldp,
+        Err(_) => return,
+    };
+    let (chassis_subtype, chassis_id) = lldp.chassis_id().unwrap();
+    let (port_subtype, port_id) = lldp.port_id().unwrap();
+    let ttl = Duration::from_secs(u64::from(lldp.ttl().unwrap()));
+    let neighbor = LldpNeighbor {
+        local_port: String::from(local_port),
+        chassis_id: id_string(chassis_subtype, chassis_id, LLDP_CHASSIS_ID_MAC_ADDRESS),
+        port_id: id_string(port_subtype, port_id, LLDP_PORT_ID_MAC_ADDRESS),
+        port_description: tlv_string(&lldp, LLDP_TLV_PORT_DESCRIPTION),
+        system_name: tlv_string(&lldp, LLDP_TLV_SYSTEM_NAME),
+        system_description: tlv_string(&lldp, LLDP_TLV_SYSTEM_DESCRIPTION),
+        expires: now + ttl,
+    };
+    neighbors.update(neighbor, ttl, now);
+}
+
+struct LldpAgent {
+    in_stream: Option<PacketStream<EthernetFrame>>,
+    advertisement: Advertisement,
+    ttl: u16,
+    neighbors: Arc<LldpNeighbors>,
+    ticks: Interval,
+    started: bool,
+}
+
+impl Unpin for LldpAgent {}
+
+impl Stream for LldpAgent {
+    type Item = EthernetFrame;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let agent = &mut *self;
+        let in_stream = match agent.in_stream.as_mut() {
+            Some(in_stream) => in_stream,
+            None => return Poll::Ready(None),
+        };
+        if !agent.started {
+            agent.started = true;
+            return Poll::Ready(Some(agent.advertisement.frame(agent.ttl)));
+        }
+        if Pin::new(&mut agent.ticks).poll_next(cx).is_ready() {
+            return Poll::Ready(Some(agent.advertisement.frame(agent.ttl)));
+        }
+        loop {
+            match Pin::new(&mut *in_stream).poll_next(cx) {
+                Poll::Ready(Some(frame)) => receive(
+                    &agent.neighbors,
+                    &agent.advertisement.port_id,
+                    frame,
+                    clock::now(),
+                ),
+                Poll::Ready(None) => {
+                    agent.in_stream = None;
+                    return Poll::Ready(Some(agent.advertisement.frame(0)));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Passes the frames received on the port that aren't LLDP.
+struct LldpFilter {}
+
+impl Processor for LldpFilter {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        if frame.ether_type() == LLDP_ETHER_TYPE {
+            None
+        } else {
+            Some(frame)
+        }
+    }
+}
+
+/// Tells the systems on the other end of one of the router's ports what it is, and hears what
+/// they are, with LLDP, IEEE 802.1AB.
+///
+/// The composite takes the frames received on the port, named `port_id`, and keeps each neighbor
+/// its LLDP frames tell of in an `LldpNeighbors` table, shared in the `StateStore` under
+/// `LLDP_NEIGHBORS_STATE` unless one is given, so the composites of all the ports fill the same
+/// table. With the `mgmt` feature, the table can be registered with the management API. Egressor
+/// 0 carries the frames that aren't LLDP, and egressor 1 the router's LLDP frames to send on the
+/// port: one as the composite starts, and one every `tx_interval`, from the port's MAC address
+/// `mac_addr`, to be kept by neighbors for `tx_hold` intervals. When the ingressor ends, a last
+/// frame with a time to live of 0 tells neighbors to forget the router.
+pub struct LldpComposite {
+    in_stream: Option<PacketStream<EthernetFrame>>,
+    mac_addr: Option<MacAddr>,
+    port_id: Option<String>,
+    port_description: Option<String>,
+    system_name: Option<String>,
+    system_description: Option<String>,
+    tx_interval: Duration,
+    tx_hold: u32,
+    neighbors: Option<Arc<LldpNeighbors>>,
+}
+
+impl Default for LldpComposite {
+    fn default() -> Self {
+        LldpComposite::new()
+    }
+}
+
+impl LldpComposite {
+    pub fn new() -> Self {
+        LldpComposite {
+            in_stream: None,
+            mac_addr: None,
+            port_id: None,
+            port_description: None,
+            system_name: None,
+            system_description: None,
+            tx_interval: Duration::from_secs(30),
+            tx_hold: 4,
+            neighbors: None,
+        }
+    }
+
+    /// The MAC address of the port, which identifies the router's chassis too.
+    pub fn mac_addr(self, mac_addr: MacAddr) -> Self {
+        LldpComposite {
+            in_stream: self.in_stream,
+            mac_addr: Some(mac_addr),
+            port_id: self.port_id,
+            port_description: self.port_description,
+            system_name: self.system_name,
+            system_description: self.system_description,
+            tx_interval: self.tx_interval,
+            tx_hold: self.tx_hold,
+            neighbors: self.neighbors,
+        }
+    }
+
+    /// The name of the port, such as its interface name, which neighbors are listed under.
+    pub fn port_id(self, port_id: &str) -> Self {
+        LldpComposite {
+            in_stream: self.in_stream,
+            mac_addr: self.mac_addr,
+            port_id: Some(String::from(port_id)),
+            port_description: self.port_description,
+            system_name: self.system_name,
+            system_description: self.system_description,
+            tx_interval: self.tx_interval,
+            tx_hold: self.tx_hold,
+            neighbors: self.neighbors,
+        }
+    }
+
+    pub fn port_description(self, port_description: &str) -> Self {
+        LldpComposite {
+            in_stream: self.in_stream,
+            mac_addr: self.mac_addr,
+            port_id: self.port_id,
+            port_description: Some(String::from(port_description)),
+            system_name: self.system_name,
+            system_description: self.system_description,
+            tx_interval: self.tx_interval,
+            tx_hold: self.tx_hold,
+            neighbors: self.neighbors,
+        }
+    }
+
+    pub fn system_name(self, system_name: &str) -> Self {
+        LldpComposite {
+            in_stream: self.in_stream,
+            mac_addr: self.mac_addr,
+            port_id: self.port_id,
+            port_description: self.port_description,
+            system_name: Some(String::from(system_name)),
+            system_description: self.system_description,
+            tx_interval: self.tx_interval,
+            tx_hold: self.tx_hold,
+            neighbors: self.neighbors,
+        }
+    }
+
+    pub fn system_description(self, system_description: &str) -> Self {
+        LldpComposite {
+            in_stream: self.in_stream,
+            mac_addr: self.mac_addr,
+            port_id: self.port_id,
+            port_description: self.port_description,
+            system_name: self.system_name,
+            system_description: Some(String::from(system_description)),
+            tx_interval: self.tx_interval,
+            tx_hold: self.tx_hold,
+            neighbors: self.neighbors,
+        }
+    }
+
+    /// Changes tx_interval, the time between LLDP frames, default value is 30 seconds.
+    pub fn tx_interval(self, tx_interval: Duration) -> Self {
+        assert!(
+            tx_interval >= Duration::from_secs(1),
+            "tx_interval must be >= 1 second"
+        );
+        LldpComposite {
+            in_stream: self.in_stream,
+            mac_addr: self.mac_addr,
+            port_id: self.port_id,
+            port_description: self.port_description,
+            system_name: self.system_name,
+            system_description: self.system_description,
+            tx_interval,
+            tx_hold: self.tx_hold,
+            neighbors: self.neighbors,
+        }
+    }
+
+    /// Changes tx_hold, the number of intervals neighbors keep what a frame says, default value
+    /// is 4.
+    pub fn tx_hold(self, tx_hold: u32) -> Self {
+        assert!(tx_hold > 0, "tx_hold must be > 0");
+        LldpComposite {
+            in_stream: self.in_stream,
+            mac_addr: self.mac_addr,
+            port_id: self.port_id,
+            port_description: self.port_description,
+            system_name: self.system_name,
+            system_description: self.system_description,
+            tx_interval: self.tx_interval,
+            tx_hold,
+            neighbors: self.neighbors,
+        }
+    }
+
+    /// Keeps the neighbors heard from in this table rather than in the `StateStore`.
+    pub fn neighbors(self, neighbors: Arc<LldpNeighbors>) -> Self {
+        LldpComposite {
+            in_stream: self.in_stream,
+            mac_addr: self.mac_addr,
+            port_id: self.port_id,
+            port_description: self.port_description,
+            system_name: self.system_name,
+            system_description: self.system_description,
+            tx_interval: self.tx_interval,
+            tx_hold: self.tx_hold,
+            neighbors: Some(neighbors),
+        }
+    }
+}
+
+impl LinkBuilder<EthernetFrame, EthernetFrame> for LldpComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<EthernetFrame>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "LldpComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<EthernetFrame>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "LldpComposite may only take 1 input stream",
+            ));
+        }
+        Ok(LldpComposite {
+            in_stream: Some(in_stream),
+            mac_addr: self.mac_addr,
+            port_id: self.port_id,
+            port_description: self.port_description,
+            system_name: self.system_name,
+            system_description: self.system_description,
+            tx_interval: self.tx_interval,
+            tx_hold: self.tx_hold,
+            neighbors: self.neighbors,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<EthernetFrame>, LinkBuildError> {
+        let (in_stream, mac_addr, port_id) = match (self.in_stream, self.mac_addr, self.port_id) {
+            (None, _, _) => return Err(LinkBuildError::Missing("input stream")),
+            (_, None, _) => return Err(LinkBuildError::Missing("mac_addr")),
+            (_, _, None) => return Err(LinkBuildError::Missing("port_id")),
+            (Some(in_stream), Some(mac_addr), Some(port_id)) => (in_stream, mac_addr, port_id),
+        };
+        let neighbors = self.neighbors.unwrap_or_else(|| {
+            StateStore::global().get_or_insert_with(LLDP_NEIGHBORS_STATE, LldpNeighbors::new)
+        });
+        let ttl = (self.tx_interval.as_secs() * u64::from(self.tx_hold)).min(0xffff) as u16;
+
+        let (mut runnables, mut fork_egressors) = ForkLink::new()
+            .try_ingressor(in_stream)?
+            .num_egressors(2)
+            .try_build_link()?;
+        let agent = LldpAgent {
+            in_stream: fork_egressors.pop(),
+            advertisement: Advertisement {
+                mac_addr,
+                port_id,
+                port_description: self.port_description,
+                system_name: self.system_name,
+                system_description: self.system_description,
+            },
+            ttl,
+            neighbors,
+            ticks: interval_at(
+                tokio::time::Instant::now() + self.tx_interval,
+                self.tx_interval,
+            ),
+            started: false,
+        };
+
+        let (mut filter_runnables, mut egressors) = ProcessLink::new()
+            .try_ingressor(fork_egressors.pop().unwrap())?
+            .processor(LldpFilter {})
+            .try_build_link()?;
+        runnables.append(&mut filter_runnables);
+        egressors.push(Box::new(agent));
+
+        Ok((runnables, egressors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    const ROUTER_MAC: MacAddr = MacAddr {
+        bytes: [0x02, 0, 0, 0, 0, 0x01],
+    };
+
+    fn switch_frame(ttl: u16) -> EthernetFrame {
+        let mut lldp = LldpFrame::new();
+        lldp.add_tlv(LLDP_TLV_CHASSIS_ID, b"\x07core-switch")
+            .add_tlv(LLDP_TLV_PORT_ID, b"\x05Gi1/0/24")
+            .add_tlv(LLDP_TLV_TTL, &ttl.to_be_bytes())
+            .add_tlv(LLDP_TLV_SYSTEM_NAME, b"core-1");
+        lldp.frame()
+    }
+
+    #[test]
+    fn keeps_neighbors_until_they_expire_or_leave() {
+        let neighbors = LldpNeighbors::new();
+        let now = clock::now();
+        receive(&neighbors, "eth0", switch_frame(120), now);
+        receive(&neighbors, "eth0", switch_frame(120), now);
+        receive(
+            &neighbors,
+            "eth0",
+            EthernetFrame::encap_ipv4(Ipv4Packet::empty()),
+            now,
+        );
+
+        let heard = neighbors.neighbors();
+        assert_eq!(heard.len(), 1);
+        assert_eq!(heard[0].local_port, "eth0");
+        assert_eq!(heard[0].chassis_id, "core-switch");
+        assert_eq!(heard[0].port_id, "Gi1/0/24");
+        assert_eq!(heard[0].system_name.as_deref(), Some("core-1"));
+        assert_eq!(heard[0].expires, now + Duration::from_secs(120));
+
+        receive(&neighbors, "eth0", switch_frame(0), now);
+        assert!(neighbors.neighbors().is_empty());
+
+        receive(
+            &neighbors,
+            "eth1",
+            switch_frame(120),
+            now - Duration::from_secs(121),
+        );
+        assert!(neighbors.neighbors().is_empty());
+    }
+
+    #[test]
+    fn advertises_and_passes_other_frames() {
+        let neighbors = Arc::new(LldpNeighbors::new());
+        let frames = vec![
+            switch_frame(120),
+            EthernetFrame::encap_ipv4(Ipv4Packet::empty()),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = LldpComposite::new()
+                .mac_addr(ROUTER_MAC)
+                .port_id("eth0")
+                .system_name("edge-router")
+                .neighbors(Arc::clone(&neighbors))
+                .ingressor(immediate_stream(frames))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 1);
+        assert_eq!(results[0][0].ether_type(), IPV4_ETHER_TYPE);
+
+        assert_eq!(results[1].len(), 2);
+        let lldp = LldpFrame::try_from(results[1][0].clone()).unwrap();
+        assert_eq!(
+            lldp.chassis_id(),
+            Some((LLDP_CHASSIS_ID_MAC_ADDRESS, &ROUTER_MAC.bytes[..]))
+        );
+        assert_eq!(lldp.ttl(), Some(120));
+        assert_eq!(lldp.tlv(LLDP_TLV_SYSTEM_NAME), Some(&b"edge-router"[..]));
+        let goodbye = LldpFrame::try_from(results[1][1].clone()).unwrap();
+        assert_eq!(goodbye.ttl(), Some(0));
+        assert_eq!(goodbye.tlvs().count(), 3);
+
+        assert_eq!(
+            neighbors.neighbors()[0].system_name.as_deref(),
+            Some("core-1")
+        );
+    }
+
+    #[cfg(feature = "mgmt")]
+    #[test]
+    fn registers_neighbor_table() {
+        let neighbors = Arc::new(LldpNeighbors::new());
+        receive(&neighbors, "eth0", switch_frame(120), clock::now());
+        let api = ManagementApi::new();
+        LldpNeighbors::register(&neighbors, &api);
+
+        let table = api.table(LLDP_NEIGHBORS_TABLE).unwrap();
+        assert_eq!(table[0]["chassis_id"], json!("core-switch"));
+        assert_eq!(table[0]["system_name"], json!("core-1"));
+        assert!(table[0]["ttl"].as_u64().unwrap() <= 120);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::link::{
     primitive::{ForkLink, JoinLink, ProcessLink},
-    Link, LinkBuilder, PacketStream, ProcessLinkBuilder,
+    Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder,
 };
 use crate::processor::Processor;
 
@@ -70,73 +70,67 @@ impl<P: Processor + Send> MtransformNLink<P> {
 }
 
 impl<P: Processor + Send + 'static> LinkBuilder<P::Input, P::Output> for MtransformNLink<P> {
-    fn ingressors(self, in_streams: Vec<PacketStream<P::Input>>) -> Self {
-        assert!(
-            !in_streams.is_empty(),
-            format!("Input streams: {} should be > 0", in_streams.len())
-        );
-
+    fn try_ingressors(
+        self,
+        in_streams: Vec<PacketStream<P::Input>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.is_empty() {
+            return Err(LinkBuildError::Ingressors(
+                "M transform N link must take at least 1 input stream",
+            ));
+        }
         if self.in_streams.is_some() {
-            panic!("M transform N link already has input streams")
+            return Err(LinkBuildError::Ingressors(
+                "M transform N link already has input streams",
+            ));
         }
 
-        MtransformNLink {
+        Ok(MtransformNLink {
             in_streams: Some(in_streams),
             processor: self.processor,
             join_queue_capacity: self.join_queue_capacity,
             fork_queue_capacity: self.fork_queue_capacity,
             num_egressors: self.num_egressors,
-        }
+        })
     }
 
-    fn ingressor(self, in_stream: PacketStream<P::Input>) -> Self {
-        match self.in_streams {
-            None => MtransformNLink {
-                in_streams: Some(vec![in_stream]),
-                processor: self.processor,
-                join_queue_capacity: self.join_queue_capacity,
-                fork_queue_capacity: self.fork_queue_capacity,
-                num_egressors: self.num_egressors,
-            },
-            Some(mut existing_streams) => {
-                existing_streams.push(in_stream);
-                MtransformNLink {
-                    in_streams: Some(existing_streams),
-                    processor: self.processor,
-                    join_queue_capacity: self.join_queue_capacity,
-                    fork_queue_capacity: self.fork_queue_capacity,
-                    num_egressors: self.num_egressors,
-                }
-            }
-        }
+    fn try_ingressor(self, in_stream: PacketStream<P::Input>) -> Result<Self, LinkBuildError> {
+        let mut in_streams = self.in_streams.unwrap_or_default();
+        in_streams.push(in_stream);
+        Ok(MtransformNLink {
+            in_streams: Some(in_streams),
+            processor: self.processor,
+            join_queue_capacity: self.join_queue_capacity,
+            fork_queue_capacity: self.fork_queue_capacity,
+            num_egressors: self.num_egressors,
+        })
     }
 
-    fn build_link(self) -> Link<P::Output> {
-        if self.in_streams.is_none() {
-            panic!("Cannot build link! Missing input stream");
-        } else if self.num_egressors.is_none() {
-            panic!("Cannot build link! Missing number of num_egressors");
-        } else if self.processor.is_none() {
-            panic!("Cannot build link! Missing processor");
-        } else {
-            let (mut join_runnables, join_egressors) = JoinLink::new()
-                .ingressors(self.in_streams.unwrap())
-                .queue_capacity(self.join_queue_capacity)
-                .build_link();
-
-            let (_, process_egressors) = ProcessLink::new()
-                .ingressors(join_egressors)
-                .processor(self.processor.unwrap())
-                .build_link();
-
-            let (mut fork_link_runnables, fork_link_egressors) = ForkLink::new()
-                .ingressors(process_egressors)
-                .queue_capacity(self.fork_queue_capacity)
-                .num_egressors(self.num_egressors.unwrap())
-                .build_link();
-            fork_link_runnables.append(&mut join_runnables);
-
-            (fork_link_runnables, fork_link_egressors)
+    fn try_build_link(self) -> Result<Link<P::Output>, LinkBuildError> {
+        match (self.in_streams, self.num_egressors, self.processor) {
+            (None, _, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None, _) => Err(LinkBuildError::Missing("number of num_egressors")),
+            (_, _, None) => Err(LinkBuildError::Missing("processor")),
+            (Some(in_streams), Some(num_egressors), Some(processor)) => {
+                let (mut join_runnables, join_egressors) = JoinLink::new()
+                    .try_ingressors(in_streams)?
+                    .queue_capacity(self.join_queue_capacity)
+                    .try_build_link()?;
+
+                let (_, process_egressors) = ProcessLink::new()
+                    .try_ingressors(join_egressors)?
+                    .processor(processor)
+                    .try_build_link()?;
+
+                let (mut fork_link_runnables, fork_link_egressors) = ForkLink::new()
+                    .try_ingressors(process_egressors)?
+                    .queue_capacity(self.fork_queue_capacity)
+                    .num_egressors(num_egressors)
+                    .try_build_link()?;
+                fork_link_runnables.append(&mut join_runnables);
+
+                Ok((fork_link_runnables, fork_link_egressors))
+            }
         }
     }
 }
@@ -0,0 +1,912 @@
+use crate::link::event::{DropAccounting, DropCounters, DropReason};
+use crate::link::primitive::QueueLink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::{Processor, ProcessorContext};
+use crate::state::StateStore;
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{Aes128Gcm, Aes256Gcm, KeyInit, Nonce, Tag};
+use route_rs_packets::{
+    EthernetFrame, MacAddr, MacsecFrame, EAPOL_ETHER_TYPE, MACSEC_ETHER_TYPE, MACSEC_TCI_C,
+    MACSEC_TCI_E,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The name the packet numbers of MACsec secure associations are kept under in the
+/// `StateStore`, so that a composite built again carries on from those used before.
+pub const MACSEC_PACKET_NUMBERS_STATE: &str = "macsec-packet-numbers";
+
+/// The ICV of the GCM-AES cipher suites, IEEE 802.1AE-2018 section 14.5.
+const ICV_LEN: usize = 16;
+
+/// Secure data shorter than this is given as the short length of the SecTAG, so that padding
+/// added to short frames on the wire can be told apart from it.
+const SHORT_LENGTH_LIMIT: usize = 48;
+
+/// The Secure Channel Identifier of the port `port` of the system of `mac`, IEEE 802.1AE-2018
+/// section 7.1.2.
+pub fn secure_channel_id(mac: MacAddr, port: u16) -> u64 {
+    let mut sci = [0; 8];
+    sci[..6].copy_from_slice(&mac.bytes);
+    sci[6..].copy_from_slice(&port.to_be_bytes());
+    u64::from_be_bytes(sci)
+}
+
+/// How many packet numbers a transmit secure association takes at once, recording the end of them
+/// before it sends the first, and how far a receive secure association moves on between
+/// recording where it is.
+const PN_BLOCK: u32 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Direction {
+    Transmit,
+    Receive,
+}
+
+/// A secure association, by its direction, SCI and association number.
+type AssociationKey = (Direction, u64, u8);
+
+#[derive(Serialize, Deserialize)]
+struct SavedAssociation {
+    direction: Direction,
+    /// The SCI in hex, as TOML integers are signed.
+    sci: String,
+    association_number: u8,
+    next_pn: u32,
+}
+
+/// `PacketNumbers` as they are saved to a file.
+#[derive(Serialize, Deserialize)]
+struct SavedPacketNumbers {
+    #[serde(default)]
+    association: Vec<SavedAssociation>,
+}
+
+/// The next packet number each transmit secure association may send, and the lowest each receive
+/// secure association accepts. The packet numbers of a SAK must never repeat, nor be accepted
+/// twice, and static SAKs outlive the composite, so these do too.
+#[derive(Default)]
+struct PacketNumbers {
+    next_pns: Mutex<HashMap<AssociationKey, u32>>,
+}
+
+impl PacketNumbers {
+    fn get(&self, key: AssociationKey) -> Option<u32> {
+        self.next_pns.lock().unwrap().get(&key).copied()
+    }
+
+    /// Moves the next packet number of an association on to `next_pn`, never back, and saves
+    /// them all to `persist`, if it is given, replacing it whole.
+    fn record(&self, key: AssociationKey, next_pn: u32, persist: Option<&Path>) -> io::Result<()> {
+        let mut next_pns = self.next_pns.lock().unwrap();
+        let entry = next_pns.entry(key).or_insert(next_pn);
+        *entry = (*entry).max(next_pn);
+        let path = match persist {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let saved = SavedPacketNumbers {
+            association: next_pns
+                .iter()
+                .map(
+                    |(&(direction, sci, association_number), &next_pn)| SavedAssociation {
+                        direction,
+                        sci: format!("{:016x}", sci),
+                        association_number,
+                        next_pn,
+                    },
+                )
+                .collect(),
+        };
+        let contents = toml::to_string(&saved)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let partial = path.with_extension("partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, path)
+    }
+
+    /// Moves the packet numbers on to those saved to a file, if they are further on.
+    fn load(&self, path: &Path) -> io::Result<()> {
+        let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+        let saved: SavedPacketNumbers =
+            toml::from_str(&fs::read_to_string(path)?).map_err(|err| invalid(err.to_string()))?;
+        let mut next_pns = self.next_pns.lock().unwrap();
+        for association in saved.association {
+            let sci = u64::from_str_radix(&association.sci, 16)
+                .map_err(|_| invalid(format!("bad SCI {}", association.sci)))?;
+            let key = (association.direction, sci, association.association_number);
+            let entry = next_pns.entry(key).or_insert(association.next_pn);
+            *entry = (*entry).max(association.next_pn);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+enum Cipher {
+    Aes128(Box<Aes128Gcm>),
+    Aes256(Box<Aes256Gcm>),
+}
+
+impl Cipher {
+    fn new(sak: &[u8]) -> Result<Self, String> {
+        match sak.len() {
+            16 => Ok(Cipher::Aes128(Box::new(
+                Aes128Gcm::new_from_slice(sak).unwrap(),
+            ))),
+            32 => Ok(Cipher::Aes256(Box::new(
+                Aes256Gcm::new_from_slice(sak).unwrap(),
+            ))),
+            len => Err(format!("SAK of {} bytes, must be 16 or 32", len)),
+        }
+    }
+
+    /// The nonce of a frame, its SCI followed by its packet number, IEEE 802.1AE-2018 section
+    /// 14.5.
+    fn nonce(sci: u64, packet_number: u32) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[..8].copy_from_slice(&sci.to_be_bytes());
+        nonce[8..].copy_from_slice(&packet_number.to_be_bytes());
+        nonce
+    }
+
+    fn seal(&self, sci: u64, packet_number: u32, aad: &[u8], data: &mut [u8]) -> [u8; ICV_LEN] {
+        let nonce = Cipher::nonce(sci, packet_number);
+        let nonce = Nonce::from_slice(&nonce);
+        let tag = match self {
+            Cipher::Aes128(cipher) => cipher.encrypt_in_place_detached(nonce, aad, data),
+            Cipher::Aes256(cipher) => cipher.encrypt_in_place_detached(nonce, aad, data),
+        };
+        tag.expect("Ethernet frames are never too long for AES-GCM")
+            .into()
+    }
+
+    fn open(&self, sci: u64, packet_number: u32, aad: &[u8], data: &mut [u8], icv: &[u8]) -> bool {
+        let nonce = Cipher::nonce(sci, packet_number);
+        let nonce = Nonce::from_slice(&nonce);
+        let tag = Tag::from_slice(icv);
+        match self {
+            Cipher::Aes128(cipher) => cipher.decrypt_in_place_detached(nonce, aad, data, tag),
+            Cipher::Aes256(cipher) => cipher.decrypt_in_place_detached(nonce, aad, data, tag),
+        }
+        .is_ok()
+    }
+}
+
+fn count(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Encrypts every frame with the transmit secure association, other than EAPOL, which carries
+/// the key agreement between peers, and so must pass in the clear.
+struct MacsecEncryptor {
+    sci: u64,
+    association_number: u8,
+    cipher: Cipher,
+    next_pn: u32,
+    /// The packet numbers below this are recorded as taken.
+    reserved: u32,
+    numbers: Arc<PacketNumbers>,
+    persist: Option<PathBuf>,
+    protected: Arc<AtomicU64>,
+    exhausted: Arc<AtomicU64>,
+    unsaved: Arc<AtomicU64>,
+    drops: DropAccounting,
+}
+
+impl MacsecEncryptor {
+    fn new(sci: u64, association_number: u8, cipher: Cipher) -> Self {
+        MacsecEncryptor {
+            sci,
+            association_number,
+            cipher,
+            next_pn: 1,
+            reserved: 1,
+            numbers: Arc::new(PacketNumbers::default()),
+            persist: None,
+            protected: Arc::new(AtomicU64::new(0)),
+            exhausted: Arc::new(AtomicU64::new(0)),
+            unsaved: Arc::new(AtomicU64::new(0)),
+            drops: DropAccounting::default(),
+        }
+    }
+}
+
+impl Processor for MacsecEncryptor {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        if frame.ether_type() == EAPOL_ETHER_TYPE {
+            return Some(frame);
+        }
+        // Packet numbers must never repeat under a SAK, and without a key agreement to install
+        // another, an association that has used them all up sends nothing more.
+        if self.next_pn == 0 {
+            count(&self.exhausted, 1);
+            self.drops.dropped(DropReason::Discarded);
+            return None;
+        }
+        // The frames sent after a restart must not reuse the packet numbers of those sent before
+        // it, so none is sent before it is recorded as taken.
+        if self.next_pn >= self.reserved {
+            let reserved = self.next_pn.saturating_add(PN_BLOCK);
+            let key = (Direction::Transmit, self.sci, self.association_number);
+            if self
+                .numbers
+                .record(key, reserved, self.persist.as_deref())
+                .is_err()
+            {
+                count(&self.unsaved, 1);
+                self.drops.dropped(DropReason::Discarded);
+                return None;
+            }
+            self.reserved = reserved;
+        }
+        let packet_number = self.next_pn;
+        self.next_pn = self.next_pn.wrapping_add(1);
+
+        let l2 = frame.layer2_offset;
+        let mut secure_data = frame.data[l2 + 12..].to_vec();
+        let short_length = if secure_data.len() < SHORT_LENGTH_LIMIT {
+            secure_data.len() as u8
+        } else {
+            0
+        };
+
+        let mut macsec = MacsecFrame::new();
+        macsec.ethernet_mut().data[..12].copy_from_slice(&frame.data[l2..l2 + 12]);
+        macsec
+            .set_tci(MACSEC_TCI_E | MACSEC_TCI_C | self.association_number)
+            .set_short_length(short_length)
+            .set_packet_number(packet_number)
+            .set_sci(Some(self.sci));
+        let icv = self
+            .cipher
+            .seal(self.sci, packet_number, macsec.header(), &mut secure_data);
+        secure_data.extend_from_slice(&icv);
+        macsec.set_secure_data(&secure_data);
+
+        count(&self.protected, 1);
+        Some(macsec.frame())
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.protected = context.counter("protected");
+        self.exhausted = context.counter("exhausted");
+        self.unsaved = context.counter("unsaved");
+    }
+}
+
+/// A receive secure association, the lowest packet number it accepts next, and the last of those
+/// recorded.
+struct ReceiveSa {
+    cipher: Cipher,
+    next_pn: u32,
+    recorded: u32,
+}
+
+impl ReceiveSa {
+    fn new(cipher: Cipher, next_pn: u32) -> Self {
+        ReceiveSa {
+            cipher,
+            next_pn,
+            recorded: next_pn,
+        }
+    }
+}
+
+/// Decrypts the frames of the receive secure channels, dropping those that fail validation or
+/// arrive too late, and EAPOL frames in the clear. Anything else unprotected is dropped.
+struct MacsecDecryptor {
+    channels: HashMap<u64, [Option<ReceiveSa>; 4]>,
+    replay_window: u32,
+    numbers: Arc<PacketNumbers>,
+    persist: Option<PathBuf>,
+    validated: Arc<AtomicU64>,
+    late: Arc<AtomicU64>,
+    not_valid: Arc<AtomicU64>,
+    no_sa: Arc<AtomicU64>,
+    untagged: Arc<AtomicU64>,
+    unsaved: Arc<AtomicU64>,
+    drops: DropAccounting,
+}
+
+impl MacsecDecryptor {
+    fn new(channels: HashMap<u64, [Option<ReceiveSa>; 4]>, replay_window: u32) -> Self {
+        MacsecDecryptor {
+            channels,
+            replay_window,
+            numbers: Arc::new(PacketNumbers::default()),
+            persist: None,
+            validated: Arc::new(AtomicU64::new(0)),
+            late: Arc::new(AtomicU64::new(0)),
+            not_valid: Arc::new(AtomicU64::new(0)),
+            no_sa: Arc::new(AtomicU64::new(0)),
+            untagged: Arc::new(AtomicU64::new(0)),
+            unsaved: Arc::new(AtomicU64::new(0)),
+            drops: DropAccounting::default(),
+        }
+    }
+}
+
+impl Processor for MacsecDecryptor {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        match frame.ether_type() {
+            EAPOL_ETHER_TYPE => return Some(frame),
+            MACSEC_ETHER_TYPE => {}
+            _ => {
+                count(&self.untagged, 1);
+                self.drops.dropped(DropReason::Unclassified);
+                return None;
+            }
+        }
+        let macsec = match MacsecFrame::try_from(frame) {
+            Ok(macsec) => macsec,
+            Err(err) => {
+                count(&self.not_valid, 1);
+                self.drops.parse_error(err);
+                return None;
+            }
+        };
+        // A frame may leave out its SCI on a point to point link, where there is only the one
+        // channel it could be of.
+        let sci = match macsec.sci() {
+            Some(sci) => sci,
+            None if self.channels.len() == 1 => *self.channels.keys().next().unwrap(),
+            None => {
+                count(&self.no_sa, 1);
+                self.drops.dropped(DropReason::Unclassified);
+                return None;
+            }
+        };
+        let sa = match self.channels.get_mut(&sci) {
+            Some(channel) => match &mut channel[usize::from(macsec.association_number())] {
+                Some(sa) => sa,
+                None => {
+                    count(&self.no_sa, 1);
+                    self.drops.dropped(DropReason::Unclassified);
+                    return None;
+                }
+            },
+            None => {
+                count(&self.no_sa, 1);
+                self.drops.dropped(DropReason::Unclassified);
+                return None;
+            }
+        };
+
+        let packet_number = macsec.packet_number();
+        if packet_number == 0 || packet_number < sa.next_pn.saturating_sub(self.replay_window) {
+            count(&self.late, 1);
+            self.drops.dropped(DropReason::Unauthenticated);
+            return None;
+        }
+        let secure_data = match usize::from(macsec.short_length()) {
+            0 => macsec.secure_data(),
+            short_length if short_length + ICV_LEN <= macsec.secure_data().len() => {
+                &macsec.secure_data()[..short_length + ICV_LEN]
+            }
+            _ => {
+                count(&self.not_valid, 1);
+                self.drops.dropped(DropReason::Malformed);
+                return None;
+            }
+        };
+        if secure_data.len() < 2 + ICV_LEN {
+            count(&self.not_valid, 1);
+            self.drops.dropped(DropReason::Malformed);
+            return None;
+        }
+        let (ciphertext, icv) = secure_data.split_at(secure_data.len() - ICV_LEN);
+        let mut plaintext = ciphertext.to_vec();
+        if !sa
+            .cipher
+            .open(sci, packet_number, macsec.header(), &mut plaintext, icv)
+        {
+            count(&self.not_valid, 1);
+            self.drops.dropped(DropReason::Unauthenticated);
+            return None;
+        }
+        sa.next_pn = sa.next_pn.max(packet_number.saturating_add(1));
+        // Where the association is is recorded every block of packet numbers, so that frames
+        // captured before a restart are refused after it, bar those of the last block.
+        if sa.next_pn >= sa.recorded.saturating_add(PN_BLOCK) {
+            let key = (Direction::Receive, sci, macsec.association_number());
+            if self
+                .numbers
+                .record(key, sa.next_pn, self.persist.as_deref())
+                .is_err()
+            {
+                count(&self.unsaved, 1);
+            }
+            sa.recorded = sa.next_pn;
+        }
+        count(&self.validated, 1);
+
+        let mut out = EthernetFrame::empty();
+        out.data[..12].copy_from_slice(&macsec.header()[..12]);
+        out.set_ether_type(u16::from_be_bytes([plaintext[0], plaintext[1]]));
+        out.set_payload(&plaintext[2..]);
+        Some(out)
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.validated = context.counter("validated");
+        self.late = context.counter("late");
+        self.not_valid = context.counter("not_valid");
+        self.no_sa = context.counter("no_sa");
+        self.untagged = context.counter("untagged");
+        self.unsaved = context.counter("unsaved");
+    }
+}
+
+/// Protects an Ethernet link with MACsec, IEEE 802.1AE, using GCM-AES-128 or GCM-AES-256 with
+/// static SAKs. There is no MKA yet, so keys are configured by hand, with `transmit` for the
+/// router's own secure channel, and `receive` for each of its peers'.
+///
+/// The composite's ingressor takes the frames the router sends onto the link, and its
+/// `secured_ingressor` those it receives from it, so it can wrap any branch of Ethernet frames
+/// between a link's interface and the rest of the router. Its first egressor sends on the
+/// outbound frames, encrypted, and its second the inbound frames, decrypted. EAPOL frames pass
+/// both ways in the clear. Inbound frames that are not MACsec, fail to validate, are of an SCI or
+/// association number the router doesn't know, or whose packet number is further behind those
+/// received than the replay window, are dropped.
+///
+/// Encryption and decryption each run in a queue of their own, and count the frames they
+/// protect, validate and drop under `macsec-tx` and `macsec-rx` in the metrics. The frames they
+/// drop are counted in the composite's `drop_counters` too, if it has them: those failing
+/// validation or replay protection as `DropReason::Unauthenticated`, those of unknown channels, and
+/// untagged, as `DropReason::Unclassified`.
+///
+/// The packet numbers of each SAK must never repeat, as they make up its nonces, and those of its
+/// peers must never be accepted twice, so the composite carries on from the packet numbers of the
+/// secure associations of the same SCI and association number used before, kept in the
+/// `StateStore` under `MACSEC_PACKET_NUMBERS_STATE`, and saved to `persist` if it is given, to be
+/// loaded when the link is built. The transmit association takes its packet numbers a block at a
+/// time, recording the block before sending any of it, and drops frames, counted as `unsaved`, if
+/// the block can't be saved. Receive associations record where they are after each block, so the
+/// frames of the last block received before a restart may be accepted again after it. Without
+/// `persist`, packet numbers start over when the router restarts, so the SAKs must be replaced
+/// each time it does.
+pub struct MacsecComposite {
+    in_stream: Option<PacketStream<EthernetFrame>>,
+    secured_stream: Option<PacketStream<EthernetFrame>>,
+    transmit: Option<(u64, u8, Vec<u8>)>,
+    receive: Vec<(u64, u8, Vec<u8>)>,
+    replay_window: u32,
+    queue_capacity: usize,
+    drop_counters: Option<Arc<DropCounters>>,
+    persist: Option<PathBuf>,
+}
+
+impl Default for MacsecComposite {
+    fn default() -> Self {
+        MacsecComposite::new()
+    }
+}
+
+impl MacsecComposite {
+    pub fn new() -> Self {
+        MacsecComposite {
+            in_stream: None,
+            secured_stream: None,
+            transmit: None,
+            receive: vec![],
+            replay_window: 0,
+            queue_capacity: 256,
+            drop_counters: None,
+            persist: None,
+        }
+    }
+
+    /// The frames received from the link.
+    pub fn secured_ingressor(self, secured_stream: PacketStream<EthernetFrame>) -> Self {
+        MacsecComposite {
+            in_stream: self.in_stream,
+            secured_stream: Some(secured_stream),
+            transmit: self.transmit,
+            receive: self.receive,
+            replay_window: self.replay_window,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        }
+    }
+
+    /// The router's secure channel, `sci`, and the association number and SAK it sends with.
+    pub fn transmit(self, sci: u64, association_number: u8, sak: &[u8]) -> Self {
+        MacsecComposite {
+            in_stream: self.in_stream,
+            secured_stream: self.secured_stream,
+            transmit: Some((sci, association_number, sak.to_vec())),
+            receive: self.receive,
+            replay_window: self.replay_window,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        }
+    }
+
+    /// Adds a receive secure association, of a peer's channel `sci`.
+    pub fn receive(self, sci: u64, association_number: u8, sak: &[u8]) -> Self {
+        let mut receive = self.receive;
+        receive.push((sci, association_number, sak.to_vec()));
+        MacsecComposite {
+            in_stream: self.in_stream,
+            secured_stream: self.secured_stream,
+            transmit: self.transmit,
+            receive,
+            replay_window: self.replay_window,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        }
+    }
+
+    /// Changes replay_window, how far behind the highest packet number received a frame may be,
+    /// default value is 0, for strict ordering.
+    pub fn replay_window(self, replay_window: u32) -> Self {
+        MacsecComposite {
+            in_stream: self.in_stream,
+            secured_stream: self.secured_stream,
+            transmit: self.transmit,
+            receive: self.receive,
+            replay_window,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        }
+    }
+
+    /// Changes queue_capacity, of each of the queues the ciphers run in, default value is 256.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        MacsecComposite {
+            in_stream: self.in_stream,
+            secured_stream: self.secured_stream,
+            transmit: self.transmit,
+            receive: self.receive,
+            replay_window: self.replay_window,
+            queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        }
+    }
+
+    /// The file the packet numbers are loaded from, and saved to.
+    pub fn persist<P: AsRef<Path>>(self, path: P) -> Self {
+        MacsecComposite {
+            in_stream: self.in_stream,
+            secured_stream: self.secured_stream,
+            transmit: self.transmit,
+            receive: self.receive,
+            replay_window: self.replay_window,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: Some(path.as_ref().to_path_buf()),
+        }
+    }
+
+    /// Counts the frames the composite drops in `drop_counters`, by reason.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        MacsecComposite {
+            in_stream: self.in_stream,
+            secured_stream: self.secured_stream,
+            transmit: self.transmit,
+            receive: self.receive,
+            replay_window: self.replay_window,
+            queue_capacity: self.queue_capacity,
+            drop_counters: Some(drop_counters),
+            persist: self.persist,
+        }
+    }
+}
+
+impl LinkBuilder<EthernetFrame, EthernetFrame> for MacsecComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<EthernetFrame>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "MacsecComposite may only take 1 input stream, and 1 secured input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<EthernetFrame>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "MacsecComposite may only take 1 input stream, and 1 secured input stream",
+            ));
+        }
+        Ok(MacsecComposite {
+            in_stream: Some(in_stream),
+            secured_stream: self.secured_stream,
+            transmit: self.transmit,
+            receive: self.receive,
+            replay_window: self.replay_window,
+            queue_capacity: self.queue_capacity,
+            drop_counters: self.drop_counters,
+            persist: self.persist,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<EthernetFrame>, LinkBuildError> {
+        let (in_stream, secured_stream) = match (self.in_stream, self.secured_stream) {
+            (None, _) => return Err(LinkBuildError::Missing("input stream")),
+            (_, None) => return Err(LinkBuildError::Missing("secured input stream")),
+            (Some(in_stream), Some(secured_stream)) => (in_stream, secured_stream),
+        };
+        let (sci, association_number, sak) = self
+            .transmit
+            .ok_or(LinkBuildError::Missing("transmit secure association"))?;
+        if association_number > 3 {
+            return Err(LinkBuildError::Invalid(format!(
+                "transmit association number {} is over 3",
+                association_number
+            )));
+        }
+        let cipher = Cipher::new(&sak)
+            .map_err(|err| LinkBuildError::Invalid(format!("transmit {}", err)))?;
+        let numbers = StateStore::global()
+            .get_or_insert_with(MACSEC_PACKET_NUMBERS_STATE, PacketNumbers::default);
+        if let Some(path) = self.persist.as_ref().filter(|path| path.exists()) {
+            numbers.load(path).map_err(|err| {
+                LinkBuildError::Invalid(format!("could not load {}, {}", path.display(), err))
+            })?;
+        }
+        let next_pn = numbers
+            .get((Direction::Transmit, sci, association_number))
+            .unwrap_or(1);
+        let encryptor = MacsecEncryptor {
+            next_pn,
+            reserved: next_pn,
+            numbers: Arc::clone(&numbers),
+            persist: self.persist.clone(),
+            drops: DropAccounting::new(self.drop_counters.clone(), None),
+            ..MacsecEncryptor::new(sci, association_number, cipher)
+        };
+
+        let mut channels: HashMap<u64, [Option<ReceiveSa>; 4]> = HashMap::new();
+        for (sci, association_number, sak) in self.receive {
+            let invalid = |err| LinkBuildError::Invalid(format!("SCI {:016x} {}", sci, err));
+            if association_number > 3 {
+                return Err(invalid(format!(
+                    "association number {} is over 3",
+                    association_number
+                )));
+            }
+            let cipher = Cipher::new(&sak).map_err(invalid)?;
+            let sa = &mut channels.entry(sci).or_default()[usize::from(association_number)];
+            if sa.is_some() {
+                return Err(invalid(format!(
+                    "association number {} is configured more than once",
+                    association_number
+                )));
+            }
+            let next_pn = numbers
+                .get((Direction::Receive, sci, association_number))
+                .unwrap_or(1);
+            *sa = Some(ReceiveSa::new(cipher, next_pn));
+        }
+        let decryptor = MacsecDecryptor {
+            numbers,
+            persist: self.persist,
+            drops: DropAccounting::new(self.drop_counters, None),
+            ..MacsecDecryptor::new(channels, self.replay_window)
+        };
+
+        let (mut runnables, mut egressors) = QueueLink::new()
+            .try_ingressor(in_stream)?
+            .processor(encryptor)
+            .queue_capacity(self.queue_capacity)
+            .context(ProcessorContext::new("macsec-tx"))
+            .try_build_link()?;
+        let (mut rx_runnables, mut rx_egressors) = QueueLink::new()
+            .try_ingressor(secured_stream)?
+            .processor(decryptor)
+            .queue_capacity(self.queue_capacity)
+            .context(ProcessorContext::new("macsec-rx"))
+            .try_build_link()?;
+        runnables.append(&mut rx_runnables);
+        egressors.append(&mut rx_egressors);
+        Ok((runnables, egressors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::IPV4_ETHER_TYPE;
+    use uuid::Uuid;
+
+    const ROUTER_SCI: u64 = 0x0200_0000_0001_0001;
+    const PEER_SCI: u64 = 0x0200_0000_0002_0001;
+
+    fn frame(ether_type: u16, payload: &[u8]) -> EthernetFrame {
+        let mut frame = EthernetFrame::empty();
+        frame.data[..12].copy_from_slice(&[2, 0, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1]);
+        frame.set_ether_type(ether_type);
+        frame.set_payload(payload);
+        frame
+    }
+
+    fn receive_channels(sak: &[u8]) -> HashMap<u64, [Option<ReceiveSa>; 4]> {
+        let mut channel: [Option<ReceiveSa>; 4] = Default::default();
+        channel[1] = Some(ReceiveSa::new(Cipher::new(sak).unwrap(), 1));
+        vec![(ROUTER_SCI, channel)].into_iter().collect()
+    }
+
+    #[test]
+    fn round_trips_frames() {
+        let sak = [7; 16];
+        let mut encryptor = MacsecEncryptor::new(ROUTER_SCI, 1, Cipher::new(&sak).unwrap());
+        let mut decryptor = MacsecDecryptor::new(receive_channels(&sak), 0);
+
+        let short = frame(IPV4_ETHER_TYPE, b"hello");
+        let secured = encryptor.process(short.clone()).unwrap();
+        assert_eq!(secured.ether_type(), MACSEC_ETHER_TYPE);
+        let macsec = MacsecFrame::try_from(secured.clone()).unwrap();
+        assert_eq!(macsec.sci(), Some(ROUTER_SCI));
+        assert_eq!(macsec.association_number(), 1);
+        assert_eq!(macsec.packet_number(), 1);
+        assert_eq!(macsec.short_length(), 7);
+        assert!(!macsec.secure_data().windows(5).any(|w| w == b"hello"));
+
+        // Padding added on the wire is left out by the short length.
+        let mut padded = secured.clone();
+        padded.data.extend_from_slice(&[0; 10]);
+        assert_eq!(decryptor.process(padded), Some(short));
+
+        let long = frame(IPV4_ETHER_TYPE, &[3; 100]);
+        let secured_long = encryptor.process(long.clone()).unwrap();
+        assert_eq!(decryptor.process(secured_long), Some(long));
+
+        // EAPOL passes in the clear both ways.
+        let eapol = frame(EAPOL_ETHER_TYPE, &[1, 0, 0, 0]);
+        assert_eq!(encryptor.process(eapol.clone()), Some(eapol.clone()));
+        assert_eq!(decryptor.process(eapol.clone()), Some(eapol));
+        assert_eq!(encryptor.protected.load(Ordering::Relaxed), 2);
+        assert_eq!(decryptor.validated.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn drops_late_tampered_and_unknown_frames() {
+        let sak = [9; 32];
+        let mut encryptor = MacsecEncryptor::new(ROUTER_SCI, 1, Cipher::new(&sak).unwrap());
+        let drop_counters = Arc::new(DropCounters::new());
+        let mut decryptor = MacsecDecryptor {
+            drops: DropAccounting::new(Some(Arc::clone(&drop_counters)), None),
+            ..MacsecDecryptor::new(receive_channels(&sak), 0)
+        };
+
+        let first = encryptor.process(frame(IPV4_ETHER_TYPE, b"one")).unwrap();
+        let second = encryptor.process(frame(IPV4_ETHER_TYPE, b"two")).unwrap();
+        assert!(decryptor.process(second).is_some());
+        assert!(decryptor.process(first).is_none());
+        assert_eq!(decryptor.late.load(Ordering::Relaxed), 1);
+
+        let mut tampered = encryptor.process(frame(IPV4_ETHER_TYPE, b"three")).unwrap();
+        let last = tampered.data.len() - 1;
+        tampered.data[last] ^= 1;
+        assert!(decryptor.process(tampered).is_none());
+        assert_eq!(decryptor.not_valid.load(Ordering::Relaxed), 1);
+
+        let mut other = MacsecEncryptor::new(PEER_SCI, 1, Cipher::new(&sak).unwrap());
+        assert!(decryptor
+            .process(other.process(frame(IPV4_ETHER_TYPE, b"four")).unwrap())
+            .is_none());
+        assert_eq!(decryptor.no_sa.load(Ordering::Relaxed), 1);
+
+        assert!(decryptor
+            .process(frame(IPV4_ETHER_TYPE, b"plain"))
+            .is_none());
+        assert_eq!(decryptor.untagged.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            drop_counters.snapshot(),
+            vec![
+                (DropReason::Unclassified, 2),
+                (DropReason::Unauthenticated, 2)
+            ]
+        );
+
+        encryptor.next_pn = u32::MAX;
+        assert!(encryptor.process(frame(IPV4_ETHER_TYPE, b"five")).is_some());
+        assert!(encryptor.process(frame(IPV4_ETHER_TYPE, b"six")).is_none());
+        assert_eq!(encryptor.exhausted.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn packet_numbers_outlive_the_composite() {
+        let path = std::env::temp_dir().join(format!("macsec-pns-{}.toml", Uuid::new_v4()));
+        let (router_sci, peer_sci) = (0x0200_0000_0003_0001, 0x0200_0000_0004_0001);
+        let sak = [6; 16];
+        let build = |frames| {
+            let mut runtime = initialize_runtime();
+            runtime.block_on(async {
+                let link = MacsecComposite::new()
+                    .transmit(router_sci, 2, &sak)
+                    .receive(peer_sci, 2, &sak)
+                    .persist(&path)
+                    .ingressor(immediate_stream(vec![frame(IPV4_ETHER_TYPE, b"out")]))
+                    .secured_ingressor(immediate_stream(frames))
+                    .build_link();
+                run_link(link).await
+            })
+        };
+        let packet_number = |frame: &EthernetFrame| {
+            MacsecFrame::try_from(frame.clone())
+                .unwrap()
+                .packet_number()
+        };
+
+        // The peer has sent a block of frames, so where it is gets recorded.
+        let mut peer = MacsecEncryptor::new(peer_sci, 2, Cipher::new(&sak).unwrap());
+        peer.next_pn = PN_BLOCK + 1;
+        let old = peer.process(frame(IPV4_ETHER_TYPE, b"old")).unwrap();
+        let results = build(vec![old.clone()]);
+        assert_eq!(packet_number(&results[0][0]), 1);
+        assert_eq!(results[1].len(), 1);
+
+        let restarted = PacketNumbers::default();
+        restarted.load(&path).unwrap();
+        assert_eq!(
+            restarted.get((Direction::Transmit, router_sci, 2)),
+            Some(PN_BLOCK + 1)
+        );
+        assert_eq!(
+            restarted.get((Direction::Receive, peer_sci, 2)),
+            Some(PN_BLOCK + 2)
+        );
+
+        // Built again, the composite sends after the block it took, and refuses the old frame.
+        let results = build(vec![old]);
+        assert_eq!(packet_number(&results[0][0]), PN_BLOCK + 1);
+        assert!(results[1].is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn composite_between_peers() {
+        let sak = [5; 16];
+        let mut peer = MacsecEncryptor::new(PEER_SCI, 0, Cipher::new(&sak).unwrap());
+        let from_peer = peer.process(frame(IPV4_ETHER_TYPE, b"ping")).unwrap();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = MacsecComposite::new()
+                .transmit(ROUTER_SCI, 0, &sak)
+                .receive(PEER_SCI, 0, &sak)
+                .ingressor(immediate_stream(vec![frame(IPV4_ETHER_TYPE, b"pong")]))
+                .secured_ingressor(immediate_stream(vec![from_peer]))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 1);
+        assert_eq!(results[0][0].ether_type(), MACSEC_ETHER_TYPE);
+        assert_eq!(results[1], vec![frame(IPV4_ETHER_TYPE, b"ping")]);
+
+        assert!(MacsecComposite::new()
+            .transmit(ROUTER_SCI, 0, &sak)
+            .receive(PEER_SCI, 4, &sak)
+            .ingressor(immediate_stream(vec![]))
+            .secured_ingressor(immediate_stream(vec![]))
+            .try_build_link()
+            .is_err());
+    }
+}
@@ -11,3 +11,96 @@ pub use self::m_transform_n_link::*;
 /// Drops packets with weighted randomness.
 mod drop_link;
 pub use self::drop_link::*;
+
+/// Fragments, or answers with ICMP errors, packets too big for an interface's MTU.
+mod mtu_enforce_link;
+pub use self::mtu_enforce_link::*;
+
+/// Meters IPv4 traffic into flows and exports them to an IPFIX collector.
+mod flow_exporter_composite;
+pub use self::flow_exporter_composite::*;
+
+/// Sends sampled frames and interface counters to an sFlow collector.
+mod sflow_exporter_link;
+pub use self::sflow_exporter_link::*;
+
+/// Snoops IGMP and MLD reports to replicate multicast frames only to ports with listeners.
+mod multicast_composite;
+pub use self::multicast_composite::*;
+
+/// Proxies IGMP membership of downstream ports upstream, and forwards the groups they join.
+mod igmp_proxy_composite;
+pub use self::igmp_proxy_composite::*;
+
+/// Brings up a PPPoE session on the WAN and carries IPv4 traffic over it.
+mod pppoe_client_composite;
+pub use self::pppoe_client_composite::*;
+
+/// Answers NAT-PMP and PCP requests from the LAN for port mappings.
+mod port_mapping_composite;
+pub use self::port_mapping_composite::*;
+
+/// Sends IPv6 Router Advertisements on the LAN, and answers Router Solicitations.
+mod ra_daemon_composite;
+pub use self::ra_daemon_composite::*;
+
+/// Acquires an IPv6 prefix for the LANs with DHCPv6 prefix delegation on the WAN.
+mod dhcpv6_pd_client_composite;
+pub use self::dhcpv6_pd_client_composite::*;
+
+/// Reads and writes the UDP datagrams of the composites that talk to servers themselves.
+mod datagram;
+
+/// Forwards the DNS queries of the LAN upstream, and caches their answers.
+mod dns_forwarder_composite;
+pub use self::dns_forwarder_composite::*;
+
+/// Advertises the router to its neighbors with LLDP, and keeps a table of theirs.
+mod lldp_composite;
+pub use self::lldp_composite::*;
+
+/// Shares a gateway address between routers with VRRP, one of them acting for it at a time.
+mod vrrp_composite;
+pub use self::vrrp_composite::*;
+
+/// Runs BFD sessions with next hops and tunnel endpoints, to learn quickly when they go down.
+mod bfd_composite;
+pub use self::bfd_composite::*;
+
+/// Sends traffic out of the first of several WANs that answers its health probes.
+mod wan_failover_composite;
+pub use self::wan_failover_composite::*;
+
+/// Routes packets by their ingress interface, source and DSCP, ahead of the routing table.
+mod policy_router_composite;
+pub use self::policy_router_composite::*;
+
+/// Counts half-open TCP connections per destination, and answers SYN floods with SYN cookies.
+mod syn_protect_composite;
+pub use self::syn_protect_composite::*;
+
+/// Reports, and optionally blocks, sources scanning many ports or hosts.
+mod scan_detector_link;
+pub use self::scan_detector_link::*;
+
+/// Counts what each subscriber sends, and holds them to monthly quotas.
+mod accounting_composite;
+pub use self::accounting_composite::*;
+
+/// Authenticates users, and reports what they use, to a RADIUS server.
+#[cfg(feature = "radius")]
+mod radius_client_composite;
+#[cfg(feature = "radius")]
+pub use self::radius_client_composite::*;
+
+/// Protects traffic with IPsec ESP, in transport or tunnel mode, with static keys.
+#[cfg(feature = "ipsec")]
+mod ipsec_composite;
+#[cfg(feature = "ipsec")]
+pub use self::ipsec_composite::*;
+
+/// Protects an Ethernet link with MACsec, with static SAKs.
+#[cfg(feature = "macsec")]
+mod macsec_composite;
+#[cfg(feature = "macsec")]
+pub use self::macsec_composite::*;
@@ -1,6 +1,6 @@
 use crate::link::{
     primitive::{ForkLink, JoinLink},
-    Link, LinkBuilder, PacketStream,
+    Link, LinkBuildError, LinkBuilder, PacketStream,
 };
 
 #[derive(Default)]
@@ -67,61 +67,54 @@ impl<Packet: Sized + Send + Clone> MtoNLink<Packet> {
 }
 
 impl<Packet: Sized + Send + Clone + 'static> LinkBuilder<Packet, Packet> for MtoNLink<Packet> {
-    fn ingressors(self, in_streams: Vec<PacketStream<Packet>>) -> Self {
-        assert!(
-            !in_streams.is_empty(),
-            format!("Input streams: {} must be > 0", in_streams.len())
-        );
-
+    fn try_ingressors(self, in_streams: Vec<PacketStream<Packet>>) -> Result<Self, LinkBuildError> {
+        if in_streams.is_empty() {
+            return Err(LinkBuildError::Ingressors(
+                "MtoNLink must take at least 1 input stream",
+            ));
+        }
         if self.in_streams.is_some() {
-            panic!("MtoNLink already has input streams")
+            return Err(LinkBuildError::Ingressors(
+                "MtoNLink already has input streams",
+            ));
         }
 
-        MtoNLink {
+        Ok(MtoNLink {
             in_streams: Some(in_streams),
             join_queue_capacity: self.join_queue_capacity,
             fork_queue_capacity: self.fork_queue_capacity,
             num_egressors: self.num_egressors,
-        }
+        })
     }
 
-    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
-        match self.in_streams {
-            None => MtoNLink {
-                in_streams: Some(vec![in_stream]),
-                join_queue_capacity: self.join_queue_capacity,
-                fork_queue_capacity: self.fork_queue_capacity,
-                num_egressors: self.num_egressors,
-            },
-            Some(mut existing_streams) => {
-                existing_streams.push(in_stream);
-                MtoNLink {
-                    in_streams: Some(existing_streams),
-                    join_queue_capacity: self.join_queue_capacity,
-                    fork_queue_capacity: self.fork_queue_capacity,
-                    num_egressors: self.num_egressors,
-                }
-            }
-        }
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        let mut in_streams = self.in_streams.unwrap_or_default();
+        in_streams.push(in_stream);
+        Ok(MtoNLink {
+            in_streams: Some(in_streams),
+            join_queue_capacity: self.join_queue_capacity,
+            fork_queue_capacity: self.fork_queue_capacity,
+            num_egressors: self.num_egressors,
+        })
     }
 
-    fn build_link(self) -> Link<Packet> {
-        if self.in_streams.is_none() {
-            panic!("Cannot build link! Missing input stream");
-        } else if self.num_egressors.is_none() {
-            panic!("Cannot build link! Missing number of num_egressors");
-        } else {
-            let (mut join_runnables, join_egressors) = JoinLink::new()
-                .ingressors(self.in_streams.unwrap())
-                .queue_capacity(self.join_queue_capacity)
-                .build_link();
-            let (mut fork_link_runnables, fork_link_egressors) = ForkLink::new()
-                .ingressors(join_egressors)
-                .queue_capacity(self.fork_queue_capacity)
-                .num_egressors(self.num_egressors.unwrap())
-                .build_link();
-            fork_link_runnables.append(&mut join_runnables);
-            (fork_link_runnables, fork_link_egressors)
+    fn try_build_link(self) -> Result<Link<Packet>, LinkBuildError> {
+        match (self.in_streams, self.num_egressors) {
+            (None, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None) => Err(LinkBuildError::Missing("number of num_egressors")),
+            (Some(in_streams), Some(num_egressors)) => {
+                let (mut join_runnables, join_egressors) = JoinLink::new()
+                    .try_ingressors(in_streams)?
+                    .queue_capacity(self.join_queue_capacity)
+                    .try_build_link()?;
+                let (mut fork_link_runnables, fork_link_egressors) = ForkLink::new()
+                    .try_ingressors(join_egressors)?
+                    .queue_capacity(self.fork_queue_capacity)
+                    .num_egressors(num_egressors)
+                    .try_build_link()?;
+                fork_link_runnables.append(&mut join_runnables);
+                Ok((fork_link_runnables, fork_link_egressors))
+            }
         }
     }
 }
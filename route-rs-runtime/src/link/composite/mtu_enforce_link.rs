@@ -0,0 +1,459 @@
+use crate::classifier::Classifier;
+use crate::link::event::{DropAccounting, DropCounters, DropReason, EventSink};
+use crate::link::primitive::{ClassifyLink, ProcessLink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::{PmtuCache, Processor};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::*;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// What becomes of a frame: sent on the interface, or answered with an ICMP error.
+#[derive(Clone)]
+enum Enforced {
+    Sent(EthernetFrame),
+    TooBig(EthernetFrame),
+}
+
+struct ByOutcome {}
+
+impl Classifier for ByOutcome {
+    type Packet = Enforced;
+    type Class = usize;
+
+    fn classify(&self, enforced: &Self::Packet) -> Self::Class {
+        match enforced {
+            Enforced::Sent(_) => 0,
+            Enforced::TooBig(_) => 1,
+        }
+    }
+}
+
+struct IntoFrame {}
+
+impl Processor for IntoFrame {
+    type Input = Enforced;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, enforced: Self::Input) -> Option<Self::Output> {
+        match enforced {
+            Enforced::Sent(frame) | Enforced::TooBig(frame) => Some(frame),
+        }
+    }
+}
+
+struct MtuEnforcer {
+    in_stream: PacketStream<EthernetFrame>,
+    mtu: usize,
+    fragment: bool,
+    icmp_source: Option<Ipv4Addr>,
+    icmpv6_source: Option<Ipv6Addr>,
+    drops: DropAccounting,
+    pmtu_cache: Option<Arc<PmtuCache>>,
+    ready: VecDeque<Enforced>,
+}
+
+impl Unpin for MtuEnforcer {}
+
+impl MtuEnforcer {
+    /// The MTU of the interface, or of the path to `dest` if the cache knows it to be smaller.
+    fn path_mtu(&self, dest: IpAddr) -> usize {
+        let path_mtu = self.pmtu_cache.as_ref().and_then(|cache| cache.mtu(dest));
+        path_mtu.map_or(self.mtu, |path_mtu| path_mtu.min(self.mtu))
+    }
+
+    fn enforce(&mut self, frame: EthernetFrame) {
+        let len = frame.data.len() - frame.payload_offset;
+        if len <= self.mtu && self.pmtu_cache.is_none() {
+            self.ready.push_back(Enforced::Sent(frame));
+            return;
+        }
+        match frame.ether_type() {
+            IPV4_ETHER_TYPE => {
+                let packet = match Ipv4Packet::try_from(frame) {
+                    Ok(packet) => packet,
+                    Err(_) => return self.drops.dropped(DropReason::Malformed),
+                };
+                let mtu = self.path_mtu(IpAddr::V4(packet.dest_addr()));
+                if len <= mtu {
+                    let frame = EthernetFrame::try_from(packet).unwrap();
+                    return self.ready.push_back(Enforced::Sent(frame));
+                }
+                if self.fragment && !packet.flags().0 {
+                    let fragments = packet.fragment(mtu);
+                    if !fragments.is_empty() {
+                        let frames = fragments
+                            .into_iter()
+                            .map(|fragment| EthernetFrame::try_from(fragment).unwrap());
+                        self.ready.extend(frames.map(Enforced::Sent));
+                        return;
+                    }
+                }
+                match self.icmp_source {
+                    Some(src_addr) => {
+                        let error = icmp_fragmentation_needed(&packet, mtu as u16, src_addr);
+                        self.ready
+                            .push_back(Enforced::TooBig(EthernetFrame::encap_ipv4(error)));
+                    }
+                    None => self.drops.dropped(DropReason::TooBig),
+                }
+            }
+            IPV6_ETHER_TYPE => {
+                let packet = match Ipv6Packet::try_from(frame) {
+                    Ok(packet) => packet,
+                    Err(_) => return self.drops.dropped(DropReason::Malformed),
+                };
+                let mtu = self.path_mtu(IpAddr::V6(packet.dest_addr()));
+                if len <= mtu {
+                    let frame = EthernetFrame::try_from(packet).unwrap();
+                    return self.ready.push_back(Enforced::Sent(frame));
+                }
+                match self.icmpv6_source {
+                    Some(src_addr) => {
+                        let error = icmpv6_packet_too_big(&packet, mtu as u32, src_addr);
+                        self.ready
+                            .push_back(Enforced::TooBig(EthernetFrame::encap_ipv6(error)));
+                    }
+                    None => self.drops.dropped(DropReason::TooBig),
+                }
+            }
+            _ if len <= self.mtu => self.ready.push_back(Enforced::Sent(frame)),
+            _ => self.drops.dropped(DropReason::TooBig),
+        }
+    }
+}
+
+impl Stream for MtuEnforcer {
+    type Item = Enforced;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(enforced) = self.ready.pop_front() {
+                return Poll::Ready(Some(enforced));
+            }
+            match futures::ready!(Pin::new(&mut self.in_stream).poll_next(cx)) {
+                Some(frame) => self.enforce(frame),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// Keeps the frames sent out of an interface within its `mtu`, the most bytes of payload after
+/// the Ethernet header and any VLAN tags, 1500 by default. One goes in front of each interface,
+/// after any encapsulation, which is what most often takes packets over the MTU.
+///
+/// Frames that fit pass on egressor 0 untouched. An IPv4 packet that doesn't is fragmented, if it
+/// has Don't Fragment clear and `fragment` is left on, and its fragments pass on egressor 0.
+/// Otherwise, the source of the packet is told its path's MTU, with an ICMP Fragmentation Needed
+/// error from `icmp_source`, or for IPv6, which routers never fragment, an ICMPv6 Packet Too Big
+/// error from `icmpv6_source`. The errors leave on egressor 1, to be routed back to the source,
+/// and should go through an `IcmpRateLimiter`, which suppresses the errors that mustn't be sent.
+/// Without a source address for the family, and for anything other than IP, frames that don't
+/// fit are dropped, counted in the `drop_counters` and reported to the `event_sink` if there are
+/// any.
+pub struct MtuEnforceLink {
+    in_stream: Option<PacketStream<EthernetFrame>>,
+    mtu: usize,
+    fragment: bool,
+    icmp_source: Option<Ipv4Addr>,
+    icmpv6_source: Option<Ipv6Addr>,
+    event_sink: Option<EventSink>,
+    drop_counters: Option<Arc<DropCounters>>,
+    pmtu_cache: Option<Arc<PmtuCache>>,
+}
+
+impl Default for MtuEnforceLink {
+    fn default() -> Self {
+        MtuEnforceLink::new()
+    }
+}
+
+impl MtuEnforceLink {
+    pub fn new() -> Self {
+        MtuEnforceLink {
+            in_stream: None,
+            mtu: 1500,
+            fragment: true,
+            icmp_source: None,
+            icmpv6_source: None,
+            event_sink: None,
+            drop_counters: None,
+            pmtu_cache: None,
+        }
+    }
+
+    /// Changes mtu, default value is 1500. Jumbo frames are up to 9000, and IPv6 links can't be
+    /// less than 1280.
+    pub fn mtu(self, mtu: usize) -> Self {
+        assert!((68..=65535).contains(&mtu), "mtu must be within 68..=65535");
+        MtuEnforceLink {
+            in_stream: self.in_stream,
+            mtu,
+            fragment: self.fragment,
+            icmp_source: self.icmp_source,
+            icmpv6_source: self.icmpv6_source,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+            pmtu_cache: self.pmtu_cache,
+        }
+    }
+
+    /// Changes fragment, whether IPv4 packets without Don't Fragment are fragmented, default value
+    /// is true. Otherwise, they are treated as if they had it set.
+    pub fn fragment(self, fragment: bool) -> Self {
+        MtuEnforceLink {
+            in_stream: self.in_stream,
+            mtu: self.mtu,
+            fragment,
+            icmp_source: self.icmp_source,
+            icmpv6_source: self.icmpv6_source,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+            pmtu_cache: self.pmtu_cache,
+        }
+    }
+
+    /// Sends Fragmentation Needed errors from `icmp_source`, an address of the router.
+    pub fn icmp_source(self, icmp_source: Ipv4Addr) -> Self {
+        MtuEnforceLink {
+            in_stream: self.in_stream,
+            mtu: self.mtu,
+            fragment: self.fragment,
+            icmp_source: Some(icmp_source),
+            icmpv6_source: self.icmpv6_source,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+            pmtu_cache: self.pmtu_cache,
+        }
+    }
+
+    /// Sends Packet Too Big errors from `icmpv6_source`, an address of the router.
+    pub fn icmpv6_source(self, icmpv6_source: Ipv6Addr) -> Self {
+        MtuEnforceLink {
+            in_stream: self.in_stream,
+            mtu: self.mtu,
+            fragment: self.fragment,
+            icmp_source: self.icmp_source,
+            icmpv6_source: Some(icmpv6_source),
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+            pmtu_cache: self.pmtu_cache,
+        }
+    }
+
+    /// Holds IP packets to the MTUs `pmtu_cache` knows of the paths to their destinations too,
+    /// where smaller than the interface's, as learned by a `PmtuLearn`.
+    pub fn pmtu_cache(self, pmtu_cache: Arc<PmtuCache>) -> Self {
+        MtuEnforceLink {
+            in_stream: self.in_stream,
+            mtu: self.mtu,
+            fragment: self.fragment,
+            icmp_source: self.icmp_source,
+            icmpv6_source: self.icmpv6_source,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+            pmtu_cache: Some(pmtu_cache),
+        }
+    }
+
+    /// Reports the frames the link drops to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        MtuEnforceLink {
+            in_stream: self.in_stream,
+            mtu: self.mtu,
+            fragment: self.fragment,
+            icmp_source: self.icmp_source,
+            icmpv6_source: self.icmpv6_source,
+            event_sink: Some(event_sink),
+            drop_counters: self.drop_counters,
+            pmtu_cache: self.pmtu_cache,
+        }
+    }
+
+    /// Counts the frames the link drops in `drop_counters`, by reason.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        MtuEnforceLink {
+            in_stream: self.in_stream,
+            mtu: self.mtu,
+            fragment: self.fragment,
+            icmp_source: self.icmp_source,
+            icmpv6_source: self.icmpv6_source,
+            event_sink: self.event_sink,
+            drop_counters: Some(drop_counters),
+            pmtu_cache: self.pmtu_cache,
+        }
+    }
+}
+
+impl LinkBuilder<EthernetFrame, EthernetFrame> for MtuEnforceLink {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<EthernetFrame>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "MtuEnforceLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<EthernetFrame>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "MtuEnforceLink may only take 1 input stream",
+            ));
+        }
+        Ok(MtuEnforceLink {
+            in_stream: Some(in_stream),
+            mtu: self.mtu,
+            fragment: self.fragment,
+            icmp_source: self.icmp_source,
+            icmpv6_source: self.icmpv6_source,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+            pmtu_cache: self.pmtu_cache,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<EthernetFrame>, LinkBuildError> {
+        let in_stream = self
+            .in_stream
+            .ok_or(LinkBuildError::Missing("input stream"))?;
+        let enforcer = MtuEnforcer {
+            in_stream,
+            mtu: self.mtu,
+            fragment: self.fragment,
+            icmp_source: self.icmp_source,
+            icmpv6_source: self.icmpv6_source,
+            drops: DropAccounting::new(self.drop_counters, self.event_sink),
+            pmtu_cache: self.pmtu_cache,
+            ready: VecDeque::new(),
+        };
+
+        let (mut runnables, classify_egressors) = ClassifyLink::new()
+            .try_ingressor(Box::new(enforcer))?
+            .num_egressors(2)
+            .classifier(ByOutcome {})
+            .dispatcher(Box::new(|class| class))
+            .try_build_link()?;
+        let mut egressors = vec![];
+        for egressor in classify_egressors {
+            let (mut process_runnables, mut process_egressors) = ProcessLink::new()
+                .try_ingressor(egressor)?
+                .processor(IntoFrame {})
+                .try_build_link()?;
+            runnables.append(&mut process_runnables);
+            egressors.append(&mut process_egressors);
+        }
+        Ok((runnables, egressors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+    fn udp(len: usize, dont_fragment: bool) -> EthernetFrame {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(17);
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 2));
+        packet.set_payload(&vec![0; len - 20]);
+        packet.set_flags(dont_fragment, false);
+        packet.set_checksum();
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    fn run(link: MtuEnforceLink, frames: Vec<EthernetFrame>) -> Vec<Vec<EthernetFrame>> {
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let link = link.ingressor(immediate_stream(frames)).build_link();
+            run_link(link).await
+        })
+    }
+
+    #[test]
+    fn fragments_or_reports_ipv4() {
+        let frames = vec![udp(1500, true), udp(3000, false), udp(3000, true)];
+        let results = run(MtuEnforceLink::new().icmp_source(ROUTER), frames.clone());
+
+        assert_eq!(results[0].len(), 4);
+        assert_eq!(results[0][0], frames[0]);
+        assert!(results[0][1..]
+            .iter()
+            .all(|frame| frame.payload().len() <= 1500));
+
+        assert_eq!(results[1].len(), 1);
+        let error = Ipv4Packet::try_from(results[1][0].clone()).unwrap();
+        assert_eq!(error.src_addr(), ROUTER);
+        assert_eq!(error.dest_addr(), Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(error.payload()[6..8], 1500u16.to_be_bytes());
+    }
+
+    #[test]
+    fn holds_packets_to_path_mtu() {
+        let cache = Arc::new(PmtuCache::new());
+        cache.update(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1400);
+        let frames = vec![udp(1400, true), udp(1450, true)];
+        let results = run(
+            MtuEnforceLink::new().icmp_source(ROUTER).pmtu_cache(cache),
+            frames.clone(),
+        );
+
+        assert_eq!(results[0], vec![frames[0].clone()]);
+        let error = Ipv4Packet::try_from(results[1][0].clone()).unwrap();
+        assert_eq!(error.payload()[6..8], 1400u16.to_be_bytes());
+    }
+
+    #[test]
+    fn keeps_vlan_tags_on_fragments() {
+        let mut frame = udp(2000, false);
+        frame.push_vlan_tag(VlanTag::new(VLAN_CTAG_TPID, 10));
+        let results = run(MtuEnforceLink::new().mtu(1000), vec![frame]);
+
+        assert_eq!(results[0].len(), 3);
+        assert!(results[0]
+            .iter()
+            .all(|frame| frame.vlan_tags()[0].vid == 10 && frame.payload().len() <= 1000));
+    }
+
+    #[test]
+    fn drops_without_icmp_source() {
+        let mut ipv6 = Ipv6Packet::empty();
+        ipv6.set_payload(&[0; 9000]);
+        let frames = vec![
+            udp(9000, true),
+            udp(9000, false),
+            EthernetFrame::encap_ipv6(ipv6.clone()),
+        ];
+        let drop_counters = Arc::new(DropCounters::new());
+        let results = run(
+            MtuEnforceLink::new()
+                .mtu(9000)
+                .fragment(false)
+                .drop_counters(Arc::clone(&drop_counters)),
+            frames,
+        );
+        assert_eq!(results[0].len(), 2);
+        assert!(results[1].is_empty());
+        assert_eq!(drop_counters.snapshot(), vec![(DropReason::TooBig, 1)]);
+
+        let router = "2001:db8::1".parse().unwrap();
+        let results = run(
+            MtuEnforceLink::new().icmpv6_source(router),
+            vec![EthernetFrame::encap_ipv6(ipv6)],
+        );
+        assert!(results[0].is_empty());
+        assert_eq!(results[1].len(), 1);
+        assert_eq!(results[1][0].payload().len(), IPV6_MIN_MTU);
+    }
+}
@@ -0,0 +1,400 @@
+use crate::link::primitive::{ForkLink, ProcessLink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::Processor;
+use route_rs_packets::{
+    EthernetFrame, IgmpPacket, Ipv4Packet, Ipv6Packet, MldPacket, IPV4_ETHER_TYPE, IPV6_ETHER_TYPE,
+};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
+
+/// Which ports have listeners for each multicast group, as learned from IGMP and MLD reports.
+#[derive(Default, Debug)]
+pub struct MulticastMembership {
+    groups: RwLock<HashMap<IpAddr, HashSet<usize>>>,
+}
+
+impl MulticastMembership {
+    pub fn new() -> Self {
+        MulticastMembership::default()
+    }
+
+    pub fn join(&self, group: IpAddr, port: usize) {
+        self.groups
+            .write()
+            .unwrap()
+            .entry(group)
+            .or_default()
+            .insert(port);
+    }
+
+    pub fn leave(&self, group: IpAddr, port: usize) {
+        let mut groups = self.groups.write().unwrap();
+        if let Some(ports) = groups.get_mut(&group) {
+            ports.remove(&port);
+            if ports.is_empty() {
+                groups.remove(&group);
+            }
+        }
+    }
+
+    /// Ports with listeners for `group`, in ascending order.
+    pub fn members(&self, group: IpAddr) -> Vec<usize> {
+        let mut ports: Vec<usize> = self
+            .groups
+            .read()
+            .unwrap()
+            .get(&group)
+            .map(|ports| ports.iter().cloned().collect())
+            .unwrap_or_default();
+        ports.sort();
+        ports
+    }
+
+    /// Groups some port has joined.
+    pub fn groups(&self) -> Vec<IpAddr> {
+        self.groups.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Whether a frame for `group` should be sent out of `port`. Groups nobody has joined are
+    /// flooded, as are link local groups, which hosts do not report.
+    fn forwards_to(&self, group: IpAddr, port: usize) -> bool {
+        if is_link_local_group(group) {
+            return true;
+        }
+        match self.groups.read().unwrap().get(&group) {
+            Some(ports) => ports.contains(&port),
+            None => true,
+        }
+    }
+}
+
+pub(crate) fn is_link_local_group(group: IpAddr) -> bool {
+    match group {
+        IpAddr::V4(addr) => addr.octets()[..3] == [224, 0, 0],
+        IpAddr::V6(addr) => addr.segments()[0] & 0xff0f == 0xff02,
+    }
+}
+
+/// Destination group of a multicast IP frame, or `None` for anything else.
+pub(crate) fn multicast_group(frame: &EthernetFrame) -> Option<IpAddr> {
+    let payload = &frame.data[frame.payload_offset..];
+    match frame.ether_type() {
+        IPV4_ETHER_TYPE if payload.len() >= 20 => {
+            let dest = Ipv4Addr::from(<[u8; 4]>::try_from(&payload[16..20]).unwrap());
+            if dest.is_multicast() {
+                Some(IpAddr::V4(dest))
+            } else {
+                None
+            }
+        }
+        IPV6_ETHER_TYPE if payload.len() >= 40 => {
+            let dest = Ipv6Addr::from(<[u8; 16]>::try_from(&payload[24..40]).unwrap());
+            if dest.is_multicast() {
+                Some(IpAddr::V6(dest))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Watches for IGMP and MLD reports and updates the membership of the port they arrived on.
+struct MembershipSnooper {
+    membership: Arc<MulticastMembership>,
+}
+
+impl MembershipSnooper {
+    fn snoop(&self, port: usize, frame: &EthernetFrame) {
+        let records = match frame.ether_type() {
+            IPV4_ETHER_TYPE => Ipv4Packet::try_from(frame.clone())
+                .and_then(IgmpPacket::try_from)
+                .map(|igmp| igmp.group_records()),
+            IPV6_ETHER_TYPE => Ipv6Packet::try_from(frame.clone())
+                .and_then(MldPacket::try_from)
+                .map(|mld| mld.group_records()),
+            _ => return,
+        };
+
+        for record in records.unwrap_or_default() {
+            if record.is_join() {
+                self.membership.join(record.group, port);
+            } else if record.is_leave() {
+                self.membership.leave(record.group, port);
+            }
+        }
+    }
+}
+
+impl Processor for MembershipSnooper {
+    type Input = (usize, EthernetFrame);
+    type Output = (usize, EthernetFrame);
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let (port, frame) = &packet;
+        let payload = &frame.data[frame.payload_offset..];
+        let may_be_report = match frame.ether_type() {
+            IPV4_ETHER_TYPE => payload.len() > 9 && payload[9] == 2,
+            IPV6_ETHER_TYPE => payload.len() > 6 && payload[6] == 58,
+            _ => false,
+        };
+        if may_be_report {
+            self.snoop(*port, frame);
+        }
+        Some(packet)
+    }
+}
+
+/// Passes the copy of a frame meant for one egress port, if that port should receive it.
+struct PortFilter {
+    port: usize,
+    membership: Arc<MulticastMembership>,
+}
+
+impl Processor for PortFilter {
+    type Input = (usize, EthernetFrame);
+    type Output = EthernetFrame;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let (ingress_port, frame) = packet;
+        if ingress_port == self.port {
+            return None;
+        }
+        match multicast_group(&frame) {
+            Some(group) if !self.membership.forwards_to(group, self.port) => None,
+            _ => Some(frame),
+        }
+    }
+}
+
+/// Forwards frames between ports, snooping IGMP and MLD reports so multicast traffic is only
+/// replicated to the ports that have listeners for its group.
+///
+/// Frames come in tagged with the port they arrived on, and egressor `n` carries the frames to send
+/// out of port `n`. A frame is never sent back out of the port it arrived on. Frames for groups that
+/// no port has joined, link local groups, and anything that is not multicast IP are flooded to every
+/// other port.
+#[derive(Default)]
+pub struct MulticastComposite {
+    in_stream: Option<PacketStream<(usize, EthernetFrame)>>,
+    num_ports: Option<usize>,
+    queue_capacity: usize,
+    membership: Option<Arc<MulticastMembership>>,
+}
+
+impl MulticastComposite {
+    pub fn new() -> Self {
+        MulticastComposite {
+            in_stream: None,
+            num_ports: None,
+            queue_capacity: 10,
+            membership: None,
+        }
+    }
+
+    pub fn num_ports(self, num_ports: usize) -> Self {
+        assert!(num_ports > 0, "num_ports: {}, must be > 0", num_ports);
+
+        MulticastComposite {
+            in_stream: self.in_stream,
+            num_ports: Some(num_ports),
+            queue_capacity: self.queue_capacity,
+            membership: self.membership,
+        }
+    }
+
+    /// Changes queue_capacity of the fork to each port, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        MulticastComposite {
+            in_stream: self.in_stream,
+            num_ports: self.num_ports,
+            queue_capacity,
+            membership: self.membership,
+        }
+    }
+
+    /// Shares the membership table, for instance to inspect it or to add static members.
+    pub fn membership(self, membership: Arc<MulticastMembership>) -> Self {
+        MulticastComposite {
+            in_stream: self.in_stream,
+            num_ports: self.num_ports,
+            queue_capacity: self.queue_capacity,
+            membership: Some(membership),
+        }
+    }
+}
+
+impl LinkBuilder<(usize, EthernetFrame), EthernetFrame> for MulticastComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<(usize, EthernetFrame)>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "MulticastComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(
+        self,
+        in_stream: PacketStream<(usize, EthernetFrame)>,
+    ) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "MulticastComposite may only take 1 input stream",
+            ));
+        }
+
+        Ok(MulticastComposite {
+            in_stream: Some(in_stream),
+            num_ports: self.num_ports,
+            queue_capacity: self.queue_capacity,
+            membership: self.membership,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<EthernetFrame>, LinkBuildError> {
+        match (self.in_stream, self.num_ports) {
+            (None, _) => Err(LinkBuildError::Missing("input streams")),
+            (_, None) => Err(LinkBuildError::Missing("num_ports")),
+            (Some(in_stream), Some(num_ports)) => {
+                let membership = self.membership.unwrap_or_default();
+
+                let (mut runnables, snooper_egressors) = ProcessLink::new()
+                    .try_ingressor(in_stream)?
+                    .processor(MembershipSnooper {
+                        membership: Arc::clone(&membership),
+                    })
+                    .try_build_link()?;
+
+                let (mut fork_runnables, fork_egressors) = ForkLink::new()
+                    .try_ingressors(snooper_egressors)?
+                    .queue_capacity(self.queue_capacity)
+                    .num_egressors(num_ports)
+                    .try_build_link()?;
+                runnables.append(&mut fork_runnables);
+
+                let mut egressors = vec![];
+                for (port, fork_egressor) in fork_egressors.into_iter().enumerate() {
+                    let (mut filter_runnables, mut filter_egressors) = ProcessLink::new()
+                        .try_ingressor(fork_egressor)?
+                        .processor(PortFilter {
+                            port,
+                            membership: Arc::clone(&membership),
+                        })
+                        .try_build_link()?;
+                    runnables.append(&mut filter_runnables);
+                    egressors.append(&mut filter_egressors);
+                }
+
+                Ok((runnables, egressors))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::{
+        UdpSegment, IGMP_LEAVE_GROUP, IGMP_V2_MEMBERSHIP_REPORT, MLD_V1_LISTENER_REPORT,
+    };
+
+    fn igmp_frame(message_type: u8, group: Ipv4Addr) -> EthernetFrame {
+        let mut igmp = IgmpPacket::new();
+        igmp.set_message_type(message_type)
+            .set_group_addr(group)
+            .set_checksum();
+        let mut packet = igmp.packet();
+        packet.set_dest_addr(group);
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    fn udp_frame(dest: Ipv4Addr) -> EthernetFrame {
+        let mut packet = Ipv4Packet::encap_udp(UdpSegment::empty());
+        packet.set_dest_addr(dest);
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_num_ports() {
+        MulticastComposite::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_ingressor() {
+        MulticastComposite::new().num_ports(2).build_link();
+    }
+
+    #[test]
+    fn learns_membership() {
+        let group = Ipv4Addr::new(239, 1, 1, 1);
+        let mld_group = Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 1);
+        let mut mld = MldPacket::new();
+        mld.set_message_type(MLD_V1_LISTENER_REPORT)
+            .set_multicast_addr(mld_group);
+
+        let membership = Arc::new(MulticastMembership::new());
+        let mut snooper = MembershipSnooper {
+            membership: Arc::clone(&membership),
+        };
+        snooper.process((1, igmp_frame(IGMP_V2_MEMBERSHIP_REPORT, group)));
+        snooper.process((2, igmp_frame(IGMP_V2_MEMBERSHIP_REPORT, group)));
+        snooper.process((3, EthernetFrame::encap_ipv6(mld.packet())));
+        assert_eq!(membership.members(IpAddr::V4(group)), vec![1, 2]);
+        assert_eq!(membership.members(IpAddr::V6(mld_group)), vec![3]);
+
+        snooper.process((1, igmp_frame(IGMP_LEAVE_GROUP, group)));
+        assert_eq!(membership.members(IpAddr::V4(group)), vec![2]);
+    }
+
+    #[test]
+    fn replicates_to_members_only() {
+        let joined = Ipv4Addr::new(239, 1, 1, 1);
+        let unjoined = Ipv4Addr::new(239, 2, 2, 2);
+
+        let packets = vec![
+            (1, igmp_frame(IGMP_V2_MEMBERSHIP_REPORT, joined)),
+            (0, udp_frame(joined)),
+            (0, udp_frame(unjoined)),
+            (2, udp_frame(Ipv4Addr::new(224, 0, 0, 251))),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = MulticastComposite::new()
+                .ingressor(immediate_stream(packets))
+                .num_ports(3)
+                .build_link();
+
+            run_link(link).await
+        });
+        // The report went to the joined group, so it only reaches the other members of it.
+        assert_eq!(results[0], vec![udp_frame(Ipv4Addr::new(224, 0, 0, 251))]);
+        assert_eq!(
+            results[1],
+            vec![
+                udp_frame(joined),
+                udp_frame(unjoined),
+                udp_frame(Ipv4Addr::new(224, 0, 0, 251))
+            ]
+        );
+        assert_eq!(results[2], vec![udp_frame(unjoined)]);
+    }
+}
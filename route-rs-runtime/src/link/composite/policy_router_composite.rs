@@ -0,0 +1,182 @@
+use crate::classifier::{PolicyRouter, PolicyRule};
+use crate::link::primitive::{ClassifyLink, JoinLink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use route_rs_packets::Ipv4Packet;
+
+/// Routes IPv4 packets by policy, ahead of the routing table, with a `PolicyRouter` for each of
+/// its ingressors, one per ingress interface, rules matching an interface by the index of its
+/// ingressor.
+///
+/// The composite has an egressor for each of `num_branches` branches, which packets leave by the
+/// first rule to match them, and one more, last, for the packets no rule matches, to be looked up
+/// in the routing table as usual.
+#[derive(Default)]
+pub struct PolicyRouterComposite {
+    in_streams: Option<Vec<PacketStream<Ipv4Packet>>>,
+    rules: Vec<PolicyRule>,
+    num_branches: Option<usize>,
+}
+
+impl PolicyRouterComposite {
+    pub fn new() -> Self {
+        PolicyRouterComposite {
+            in_streams: None,
+            rules: vec![],
+            num_branches: None,
+        }
+    }
+
+    /// Adds a rule, tried after those already added.
+    pub fn rule(self, rule: PolicyRule) -> Self {
+        let mut rules = self.rules;
+        rules.push(rule);
+        PolicyRouterComposite {
+            in_streams: self.in_streams,
+            rules,
+            num_branches: self.num_branches,
+        }
+    }
+
+    pub fn num_branches(self, num_branches: usize) -> Self {
+        assert!(num_branches > 0, "num_branches must be > 0");
+        PolicyRouterComposite {
+            in_streams: self.in_streams,
+            rules: self.rules,
+            num_branches: Some(num_branches),
+        }
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for PolicyRouterComposite {
+    fn try_ingressors(
+        self,
+        in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.is_empty() {
+            return Err(LinkBuildError::Ingressors(
+                "PolicyRouterComposite must take at least 1 input stream",
+            ));
+        }
+        if self.in_streams.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "PolicyRouterComposite already has input streams",
+            ));
+        }
+        Ok(PolicyRouterComposite {
+            in_streams: Some(in_streams),
+            rules: self.rules,
+            num_branches: self.num_branches,
+        })
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        let mut in_streams = self.in_streams.unwrap_or_default();
+        in_streams.push(in_stream);
+        Ok(PolicyRouterComposite {
+            in_streams: Some(in_streams),
+            rules: self.rules,
+            num_branches: self.num_branches,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        match (self.in_streams, self.num_branches) {
+            (None, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None) => Err(LinkBuildError::Missing("num_branches")),
+            (Some(in_streams), Some(num_branches)) => {
+                if let Some(rule) = self.rules.iter().find(|rule| rule.branch >= num_branches) {
+                    return Err(LinkBuildError::Invalid(format!(
+                        "rule to branch {} of {} branches",
+                        rule.branch, num_branches
+                    )));
+                }
+
+                // Each ingressor is classified on its own, so its rules know where its packets
+                // came from, and each branch joins what every classifier sends it.
+                let mut runnables = vec![];
+                let mut branches: Vec<Vec<PacketStream<Ipv4Packet>>> =
+                    (0..=num_branches).map(|_| vec![]).collect();
+                for (ingress, in_stream) in in_streams.into_iter().enumerate() {
+                    let (mut classify_runnables, classify_egressors) = ClassifyLink::new()
+                        .try_ingressor(in_stream)?
+                        .num_egressors(num_branches + 1)
+                        .classifier(PolicyRouter::new(ingress, self.rules.clone()))
+                        .dispatcher(Box::new(move |branch| branch.unwrap_or(num_branches)))
+                        .try_build_link()?;
+                    runnables.append(&mut classify_runnables);
+                    for (branch, egressor) in classify_egressors.into_iter().enumerate() {
+                        branches[branch].push(egressor);
+                    }
+                }
+                if branches[0].len() == 1 {
+                    let egressors = branches.into_iter().flatten().collect();
+                    return Ok((runnables, egressors));
+                }
+
+                let mut egressors = vec![];
+                for branch in branches {
+                    let (mut join_runnables, mut join_egressors) =
+                        JoinLink::new().try_ingressors(branch)?.try_build_link()?;
+                    runnables.append(&mut join_runnables);
+                    egressors.append(&mut join_egressors);
+                }
+                Ok((runnables, egressors))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::net::Ipv4Addr;
+
+    fn packet(src_addr: Ipv4Addr) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(src_addr);
+        packet.set_dest_addr(Ipv4Addr::new(10, 0, 0, 1));
+        packet
+    }
+
+    #[test]
+    fn isolates_guests_and_steers_hosts() {
+        let lan = vec![
+            packet(Ipv4Addr::new(10, 0, 0, 10)),
+            packet(Ipv4Addr::new(10, 0, 0, 200)),
+        ];
+        let guest = vec![
+            packet(Ipv4Addr::new(10, 0, 0, 10)),
+            packet(Ipv4Addr::new(10, 9, 0, 10)),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = PolicyRouterComposite::new()
+                .num_branches(2)
+                .rule(PolicyRule::to(0).ingress(1))
+                .rule(PolicyRule::to(1).source("10.0.0.128/25".parse().unwrap()))
+                .ingressors(vec![immediate_stream(lan), immediate_stream(guest)])
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].len(), 2);
+        assert_eq!(results[1].len(), 1);
+        assert_eq!(results[1][0].src_addr(), Ipv4Addr::new(10, 0, 0, 200));
+        assert_eq!(results[2].len(), 1);
+        assert_eq!(results[2][0].src_addr(), Ipv4Addr::new(10, 0, 0, 10));
+    }
+
+    #[test]
+    fn rejects_rules_to_missing_branches() {
+        let result = PolicyRouterComposite::new()
+            .num_branches(2)
+            .rule(PolicyRule::to(2))
+            .ingressor(immediate_stream(vec![]))
+            .try_build_link();
+        assert!(matches!(result, Err(LinkBuildError::Invalid(_))));
+    }
+}
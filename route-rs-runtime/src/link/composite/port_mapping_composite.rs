@@ -0,0 +1,928 @@
+use crate::link::event::{DropAccounting, DropCounters, DropReason, EventSink};
+use crate::link::primitive::ProcessLink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::Processor;
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::{IpProtocol, Ipv4Packet, UdpSegment};
+use serde::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::{delay_until, Delay};
+
+/// The port NAT-PMP and PCP servers listen on, RFC 6887 section 19.1.
+pub const PCP_SERVER_PORT: u16 = 5351;
+
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+/// RFC 6886 section 3.5.
+const NAT_PMP_VERSION: u8 = 0;
+const NAT_PMP_OP_EXTERNAL_ADDRESS: u8 = 0;
+const NAT_PMP_OP_MAP_UDP: u8 = 1;
+const NAT_PMP_OP_MAP_TCP: u8 = 2;
+const NAT_PMP_UNSUPPORTED_OPCODE: u16 = 5;
+const NAT_PMP_OUT_OF_RESOURCES: u16 = 4;
+
+/// RFC 6887 section 7.4.
+const PCP_VERSION: u8 = 2;
+const PCP_OP_ANNOUNCE: u8 = 0;
+const PCP_OP_MAP: u8 = 1;
+const PCP_UNSUPP_VERSION: u8 = 1;
+const PCP_MALFORMED_REQUEST: u8 = 3;
+const PCP_UNSUPP_OPCODE: u8 = 4;
+const PCP_NO_RESOURCES: u8 = 8;
+const PCP_UNSUPP_PROTOCOL: u8 = 9;
+const PCP_ADDRESS_MISMATCH: u8 = 12;
+/// How long clients should take an error to hold, in seconds.
+const PCP_ERROR_LIFETIME: u32 = 30;
+
+/// A port on the router's external address forwarded to a host on the LAN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortMapping {
+    /// The IP protocol number of the mapping, 6 for TCP or 17 for UDP.
+    pub protocol: u8,
+    pub internal: SocketAddrV4,
+    pub external_port: u16,
+    pub expires: Instant,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedMapping {
+    protocol: u8,
+    internal: SocketAddrV4,
+    external_port: u16,
+    /// When the mapping expires, in seconds since the epoch, as an `Instant` means nothing once
+    /// the router has restarted.
+    expires: u64,
+}
+
+/// `PortMappings` as they are saved to a file.
+#[derive(Serialize, Deserialize)]
+struct SavedMappings {
+    #[serde(default)]
+    mapping: Vec<SavedMapping>,
+}
+
+/// The port mappings hosts have asked for, shared so the NAT can forward traffic arriving on a
+/// mapped port to its host, and source traffic from the host from the same port. Mappings are
+/// forgotten once they expire.
+#[derive(Debug, Default)]
+pub struct PortMappings {
+    mappings: Mutex<Vec<PortMapping>>,
+}
+
+impl PortMappings {
+    pub fn new() -> Self {
+        PortMappings {
+            mappings: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Where traffic arriving on an external port goes, if it is mapped.
+    pub fn inbound(&self, protocol: u8, external_port: u16) -> Option<SocketAddrV4> {
+        let now = clock::now();
+        let mappings = self.mappings.lock().unwrap();
+        mappings
+            .iter()
+            .find(|m| m.protocol == protocol && m.external_port == external_port && m.expires > now)
+            .map(|m| m.internal)
+    }
+
+    /// The external port traffic from a host's port leaves from, if it is mapped.
+    pub fn outbound(&self, protocol: u8, internal: SocketAddrV4) -> Option<u16> {
+        let now = clock::now();
+        let mappings = self.mappings.lock().unwrap();
+        mappings
+            .iter()
+            .find(|m| m.protocol == protocol && m.internal == internal && m.expires > now)
+            .map(|m| m.external_port)
+    }
+
+    /// Every mapping that hasn't expired.
+    pub fn mappings(&self) -> Vec<PortMapping> {
+        let now = clock::now();
+        let mappings = self.mappings.lock().unwrap();
+        mappings
+            .iter()
+            .filter(|m| m.expires > now)
+            .cloned()
+            .collect()
+    }
+
+    /// Maps a host's port for `lifetime`, to the suggested external port if it is in `ports` and
+    /// free, or else the first that is. A port already mapped keeps its external port, and has its
+    /// lifetime renewed. None if every port is taken.
+    fn map(
+        &self,
+        protocol: u8,
+        internal: SocketAddrV4,
+        suggested: u16,
+        lifetime: Duration,
+        ports: &RangeInclusive<u16>,
+    ) -> Option<PortMapping> {
+        let now = clock::now();
+        let mut mappings = self.mappings.lock().unwrap();
+        mappings.retain(|m| m.expires > now);
+        if let Some(mapping) = mappings
+            .iter_mut()
+            .find(|m| m.protocol == protocol && m.internal == internal)
+        {
+            mapping.expires = now + lifetime;
+            return Some(*mapping);
+        }
+
+        let taken = |port: u16| {
+            mappings
+                .iter()
+                .any(|m| m.protocol == protocol && m.external_port == port)
+        };
+        let external_port = if ports.contains(&suggested) && !taken(suggested) {
+            suggested
+        } else {
+            ports.clone().find(|port| !taken(*port))?
+        };
+        let mapping = PortMapping {
+            protocol,
+            internal,
+            external_port,
+            expires: now + lifetime,
+        };
+        mappings.push(mapping);
+        Some(mapping)
+    }
+
+    /// Saves the mappings that haven't expired to a TOML file, replacing it whole, so a reader
+    /// never sees it half written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (now, wall_now) = (clock::now(), SystemTime::now());
+        let saved = SavedMappings {
+            mapping: self
+                .mappings()
+                .into_iter()
+                .map(|m| {
+                    let expires = wall_now + m.expires.saturating_duration_since(now);
+                    SavedMapping {
+                        protocol: m.protocol,
+                        internal: m.internal,
+                        external_port: m.external_port,
+                        expires: expires
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs(),
+                    }
+                })
+                .collect(),
+        };
+        let contents = toml::to_string(&saved)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let path = path.as_ref();
+        let partial = path.with_extension("partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, path)
+    }
+
+    /// Replaces the mappings with those saved to a file, leaving out any that expired while the
+    /// router was down, and returns how many were restored. Each keeps what was left of its
+    /// lifetime, by the wall clock.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        let saved: SavedMappings = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let (now, wall_now) = (clock::now(), SystemTime::now());
+        let restored: Vec<PortMapping> = saved
+            .mapping
+            .into_iter()
+            .filter_map(|m| {
+                let expires = UNIX_EPOCH + Duration::from_secs(m.expires);
+                let left = expires.duration_since(wall_now).ok()?;
+                Some(PortMapping {
+                    protocol: m.protocol,
+                    internal: m.internal,
+                    external_port: m.external_port,
+                    expires: now + left,
+                })
+            })
+            .collect();
+        let count = restored.len();
+        *self.mappings.lock().unwrap() = restored;
+        Ok(count)
+    }
+
+    /// Removes the mapping of a host's port, or, if the port is 0, every mapping of the host.
+    fn unmap(&self, protocol: u8, internal: SocketAddrV4) {
+        let mut mappings = self.mappings.lock().unwrap();
+        mappings.retain(|m| {
+            m.protocol != protocol
+                || m.internal.ip() != internal.ip()
+                || (internal.port() != 0 && m.internal.port() != internal.port())
+        });
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Answers NAT-PMP and PCP requests, keeping the mappings they ask for in `PortMappings`.
+struct PortMappingServer {
+    external_addr: Ipv4Addr,
+    mappings: Arc<PortMappings>,
+    ports: RangeInclusive<u16>,
+    max_lifetime: Duration,
+    /// When the server started, which both protocols report as the epoch, so clients can tell a
+    /// server that has restarted, and lost their mappings, from one that hasn't.
+    started: Instant,
+    drops: DropAccounting,
+}
+
+impl PortMappingServer {
+    fn epoch(&self) -> u32 {
+        clock::now()
+            .saturating_duration_since(self.started)
+            .as_secs() as u32
+    }
+
+    fn lifetime(&self, requested: u32) -> Duration {
+        Duration::from_secs(u64::from(requested)).min(self.max_lifetime)
+    }
+
+    /// RFC 6886 sections 3.2 and 3.3.
+    fn nat_pmp(&self, client: Ipv4Addr, request: &[u8]) -> Option<Vec<u8>> {
+        let opcode = request[1];
+        if opcode >= 128 {
+            return None;
+        }
+        let mut reply = vec![NAT_PMP_VERSION, 128 + opcode, 0, 0];
+        reply.extend_from_slice(&self.epoch().to_be_bytes());
+        let protocol = match opcode {
+            NAT_PMP_OP_EXTERNAL_ADDRESS => {
+                reply.extend_from_slice(&self.external_addr.octets());
+                return Some(reply);
+            }
+            NAT_PMP_OP_MAP_UDP => PROTOCOL_UDP,
+            NAT_PMP_OP_MAP_TCP => PROTOCOL_TCP,
+            _ => {
+                reply[2..4].copy_from_slice(&NAT_PMP_UNSUPPORTED_OPCODE.to_be_bytes());
+                return Some(reply);
+            }
+        };
+        if request.len() < 12 {
+            return None;
+        }
+        let internal = SocketAddrV4::new(client, read_u16(request, 4));
+        let suggested = read_u16(request, 6);
+        let lifetime = read_u32(request, 8);
+
+        let (external_port, lifetime) = if lifetime == 0 {
+            self.mappings.unmap(protocol, internal);
+            (0, 0)
+        } else {
+            let lifetime = self.lifetime(lifetime);
+            match self
+                .mappings
+                .map(protocol, internal, suggested, lifetime, &self.ports)
+            {
+                Some(mapping) => (mapping.external_port, lifetime.as_secs() as u32),
+                None => {
+                    reply[2..4].copy_from_slice(&NAT_PMP_OUT_OF_RESOURCES.to_be_bytes());
+                    (0, 0)
+                }
+            }
+        };
+        reply.extend_from_slice(&internal.port().to_be_bytes());
+        reply.extend_from_slice(&external_port.to_be_bytes());
+        reply.extend_from_slice(&lifetime.to_be_bytes());
+        Some(reply)
+    }
+
+    /// RFC 6887 sections 7 and 11, for the MAP and ANNOUNCE opcodes.
+    fn pcp(&self, client: Ipv4Addr, request: &[u8]) -> Option<Vec<u8>> {
+        if request.len() < 24 || request[1] & 0x80 != 0 {
+            return None;
+        }
+        let opcode = request[1] & 0x7f;
+        let reply = |result: u8, lifetime: u32, body: &[u8]| {
+            let mut reply = vec![PCP_VERSION, 0x80 | opcode, 0, result];
+            reply.extend_from_slice(&lifetime.to_be_bytes());
+            reply.extend_from_slice(&self.epoch().to_be_bytes());
+            reply.extend_from_slice(&[0; 12]);
+            reply.extend_from_slice(body);
+            Some(reply)
+        };
+        if request[0] != PCP_VERSION {
+            return reply(PCP_UNSUPP_VERSION, PCP_ERROR_LIFETIME, &[]);
+        }
+        if request.len() > 1100 || !request.len().is_multiple_of(4) {
+            return reply(PCP_MALFORMED_REQUEST, PCP_ERROR_LIFETIME, &[]);
+        }
+        let client_field: [u8; 16] = request[8..24].try_into().unwrap();
+        if Ipv6Addr::from(client_field) != client.to_ipv6_mapped() {
+            return reply(PCP_ADDRESS_MISMATCH, PCP_ERROR_LIFETIME, &[]);
+        }
+        match opcode {
+            PCP_OP_ANNOUNCE => return reply(0, 0, &[]),
+            PCP_OP_MAP if request.len() >= 60 => {}
+            PCP_OP_MAP => return reply(PCP_MALFORMED_REQUEST, PCP_ERROR_LIFETIME, &[]),
+            _ => return reply(PCP_UNSUPP_OPCODE, PCP_ERROR_LIFETIME, &[]),
+        }
+
+        let mut body = request[24..60].to_vec();
+        let protocol = body[12];
+        let internal = SocketAddrV4::new(client, read_u16(&body, 16));
+        let suggested = read_u16(&body, 18);
+        let lifetime = read_u32(request, 4);
+        if protocol != PROTOCOL_TCP && protocol != PROTOCOL_UDP {
+            return reply(PCP_UNSUPP_PROTOCOL, PCP_ERROR_LIFETIME, &body);
+        }
+
+        if lifetime == 0 {
+            self.mappings.unmap(protocol, internal);
+            return reply(0, 0, &body);
+        }
+        let lifetime = self.lifetime(lifetime);
+        match self
+            .mappings
+            .map(protocol, internal, suggested, lifetime, &self.ports)
+        {
+            Some(mapping) => {
+                body[18..20].copy_from_slice(&mapping.external_port.to_be_bytes());
+                body[20..36].copy_from_slice(&self.external_addr.to_ipv6_mapped().octets());
+                reply(0, lifetime.as_secs() as u32, &body)
+            }
+            None => reply(PCP_NO_RESOURCES, PCP_ERROR_LIFETIME, &body),
+        }
+    }
+}
+
+impl Processor for PortMappingServer {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        if packet.protocol() != IpProtocol::UDP {
+            self.drops.dropped(DropReason::Unclassified);
+            return None;
+        }
+        let (client, server) = (packet.src_addr(), packet.dest_addr());
+        let segment = match UdpSegment::try_from(packet) {
+            Ok(segment) => segment,
+            Err(err) => {
+                self.drops.parse_error(err);
+                return None;
+            }
+        };
+        let request = segment.payload();
+        if request.len() < 2 {
+            self.drops.dropped(DropReason::Malformed);
+            return None;
+        }
+        let reply = match request[0] {
+            NAT_PMP_VERSION => self.nat_pmp(client, &request),
+            _ => self.pcp(client, &request),
+        };
+        // Replies, and requests too short to answer, go unanswered.
+        let reply = match reply {
+            Some(reply) => reply,
+            None => {
+                self.drops.dropped(DropReason::Malformed);
+                return None;
+            }
+        };
+
+        let mut datagram = Vec::with_capacity(8 + reply.len());
+        datagram.extend_from_slice(&PCP_SERVER_PORT.to_be_bytes());
+        datagram.extend_from_slice(&segment.src_port().to_be_bytes());
+        datagram.extend_from_slice(&(8 + reply.len() as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(&reply);
+        let mut packet =
+            Ipv4Packet::encap_udp(UdpSegment::from_buffer(datagram, None, None, 0).ok()?);
+        packet.set_src_addr(server);
+        packet.set_dest_addr(client);
+        packet.set_ttl(64);
+        packet.set_checksum();
+        Some(packet)
+    }
+}
+
+/// Passes requests through, saving the mappings every interval, and once more as its ingressor
+/// ends.
+struct Checkpointer {
+    in_stream: PacketStream<Ipv4Packet>,
+    mappings: Arc<PortMappings>,
+    path: PathBuf,
+    interval: Duration,
+    next_checkpoint: Instant,
+    timer: Delay,
+    event_sink: Option<EventSink>,
+}
+
+impl Checkpointer {
+    fn checkpoint(&self) {
+        if let Err(err) = self.mappings.save(&self.path) {
+            if let Some(event_sink) = &self.event_sink {
+                event_sink.alert(format!("could not save {}, {}", self.path.display(), err));
+            }
+        }
+    }
+}
+
+impl Unpin for Checkpointer {}
+
+impl Stream for Checkpointer {
+    type Item = Ipv4Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let checkpointer = &mut *self;
+        loop {
+            if clock::now() >= checkpointer.next_checkpoint {
+                checkpointer.checkpoint();
+                checkpointer.next_checkpoint += checkpointer.interval;
+            }
+            match Pin::new(&mut checkpointer.in_stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => return Poll::Ready(Some(packet)),
+                Poll::Ready(None) => {
+                    checkpointer.checkpoint();
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => {}
+            }
+
+            checkpointer
+                .timer
+                .reset(tokio::time::Instant::from_std(checkpointer.next_checkpoint));
+            if Pin::new(&mut checkpointer.timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Lets hosts on the LAN ask for ports on the router's external address to be forwarded to them,
+/// as games consoles and VoIP phones behind a NAT need, speaking both NAT-PMP (RFC 6886) and the
+/// MAP opcode of PCP (RFC 6887). UPnP IGD isn't spoken.
+///
+/// The composite takes the requests the router receives on `PCP_SERVER_PORT`, and sends out its
+/// replies, addressed back to each client. The mappings it grants are kept in `PortMappings`, which
+/// the NAT shares to forward traffic arriving on a mapped port to its host. Mappings are granted
+/// from `ports`, for up to `max_lifetime`, after which a host must renew them.
+///
+/// Every `checkpoint_interval` the mappings are saved to `persist` if it is given, so forwarded
+/// ports survive a restart: mappings saved there that haven't expired are loaded when the link is
+/// built. Failures to save are reported as alerts on the `event_sink`.
+///
+/// Requests the composite can't answer are dropped, and counted in its `drop_counters` if it has
+/// them, and reported as drops on the `event_sink`.
+pub struct PortMappingComposite {
+    in_stream: Option<PacketStream<Ipv4Packet>>,
+    external_addr: Option<Ipv4Addr>,
+    mappings: Option<Arc<PortMappings>>,
+    ports: RangeInclusive<u16>,
+    max_lifetime: Duration,
+    persist: Option<PathBuf>,
+    checkpoint_interval: Duration,
+    event_sink: Option<EventSink>,
+    drop_counters: Option<Arc<DropCounters>>,
+}
+
+impl Default for PortMappingComposite {
+    fn default() -> Self {
+        PortMappingComposite::new()
+    }
+}
+
+impl PortMappingComposite {
+    pub fn new() -> Self {
+        PortMappingComposite {
+            in_stream: None,
+            external_addr: None,
+            mappings: None,
+            ports: 1024..=65535,
+            max_lifetime: Duration::from_secs(2 * 60 * 60),
+            persist: None,
+            checkpoint_interval: Duration::from_secs(60),
+            event_sink: None,
+            drop_counters: None,
+        }
+    }
+
+    /// The router's address on the WAN, that mapped ports are on.
+    pub fn external_addr(self, external_addr: Ipv4Addr) -> Self {
+        PortMappingComposite {
+            in_stream: self.in_stream,
+            external_addr: Some(external_addr),
+            mappings: self.mappings,
+            ports: self.ports,
+            max_lifetime: self.max_lifetime,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// The table to keep the mappings in, shared with the NAT. By default the composite keeps its
+    /// own, which nothing else reads.
+    pub fn mappings(self, mappings: Arc<PortMappings>) -> Self {
+        PortMappingComposite {
+            in_stream: self.in_stream,
+            external_addr: self.external_addr,
+            mappings: Some(mappings),
+            ports: self.ports,
+            max_lifetime: self.max_lifetime,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes ports, the external ports that may be mapped, default value is 1024 to 65535.
+    pub fn ports(self, ports: RangeInclusive<u16>) -> Self {
+        assert!(!ports.is_empty(), "ports must not be empty");
+        PortMappingComposite {
+            in_stream: self.in_stream,
+            external_addr: self.external_addr,
+            mappings: self.mappings,
+            ports,
+            max_lifetime: self.max_lifetime,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes max_lifetime, the longest a mapping is granted for, default value is 2 hours.
+    pub fn max_lifetime(self, max_lifetime: Duration) -> Self {
+        PortMappingComposite {
+            in_stream: self.in_stream,
+            external_addr: self.external_addr,
+            mappings: self.mappings,
+            ports: self.ports,
+            max_lifetime,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// The file the mappings are loaded from, and saved to.
+    pub fn persist<P: AsRef<Path>>(self, path: P) -> Self {
+        PortMappingComposite {
+            in_stream: self.in_stream,
+            external_addr: self.external_addr,
+            mappings: self.mappings,
+            ports: self.ports,
+            max_lifetime: self.max_lifetime,
+            persist: Some(path.as_ref().to_path_buf()),
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes checkpoint_interval, default value is 1 minute.
+    pub fn checkpoint_interval(self, checkpoint_interval: Duration) -> Self {
+        assert!(
+            checkpoint_interval > Duration::from_secs(0),
+            "checkpoint_interval must be > 0"
+        );
+        PortMappingComposite {
+            in_stream: self.in_stream,
+            external_addr: self.external_addr,
+            mappings: self.mappings,
+            ports: self.ports,
+            max_lifetime: self.max_lifetime,
+            persist: self.persist,
+            checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Counts the requests the composite drops unanswered in `drop_counters`, by reason.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        PortMappingComposite {
+            in_stream: self.in_stream,
+            external_addr: self.external_addr,
+            mappings: self.mappings,
+            ports: self.ports,
+            max_lifetime: self.max_lifetime,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: Some(drop_counters),
+        }
+    }
+
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        PortMappingComposite {
+            in_stream: self.in_stream,
+            external_addr: self.external_addr,
+            mappings: self.mappings,
+            ports: self.ports,
+            max_lifetime: self.max_lifetime,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: Some(event_sink),
+            drop_counters: self.drop_counters,
+        }
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for PortMappingComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "PortMappingComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "PortMappingComposite may only take 1 input stream",
+            ));
+        }
+        Ok(PortMappingComposite {
+            in_stream: Some(in_stream),
+            external_addr: self.external_addr,
+            mappings: self.mappings,
+            ports: self.ports,
+            max_lifetime: self.max_lifetime,
+            persist: self.persist,
+            checkpoint_interval: self.checkpoint_interval,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        let (in_stream, external_addr) = match (self.in_stream, self.external_addr) {
+            (None, _) => return Err(LinkBuildError::Missing("input stream")),
+            (_, None) => return Err(LinkBuildError::Missing("external_addr")),
+            (Some(in_stream), Some(external_addr)) => (in_stream, external_addr),
+        };
+        let mappings = self.mappings.unwrap_or_default();
+        let in_stream: PacketStream<Ipv4Packet> = match self.persist {
+            Some(path) => {
+                if path.exists() {
+                    mappings.load(&path).map_err(|err| {
+                        LinkBuildError::Invalid(format!(
+                            "could not load {}, {}",
+                            path.display(),
+                            err
+                        ))
+                    })?;
+                }
+                let next_checkpoint = clock::now() + self.checkpoint_interval;
+                Box::new(Checkpointer {
+                    in_stream,
+                    mappings: Arc::clone(&mappings),
+                    path,
+                    interval: self.checkpoint_interval,
+                    next_checkpoint,
+                    timer: delay_until(tokio::time::Instant::from_std(next_checkpoint)),
+                    event_sink: self.event_sink.clone(),
+                })
+            }
+            None => in_stream,
+        };
+        ProcessLink::new()
+            .try_ingressor(in_stream)?
+            .processor(PortMappingServer {
+                external_addr,
+                mappings,
+                ports: self.ports,
+                max_lifetime: self.max_lifetime,
+                started: clock::now(),
+                drops: DropAccounting::new(self.drop_counters, self.event_sink),
+            })
+            .try_build_link()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use uuid::Uuid;
+
+    const EXTERNAL_ADDR: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 7);
+    const ROUTER_ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+
+    fn request(client: [u8; 4], payload: &[u8]) -> Ipv4Packet {
+        let mut datagram = vec![0x30, 0x39, 0x14, 0xe7, 0, 8 + payload.len() as u8, 0, 0];
+        datagram.extend_from_slice(payload);
+        let mut packet =
+            Ipv4Packet::encap_udp(UdpSegment::from_buffer(datagram, None, None, 0).unwrap());
+        packet.set_src_addr(Ipv4Addr::from(client));
+        packet.set_dest_addr(ROUTER_ADDR);
+        packet
+    }
+
+    fn reply_payload(packet: Ipv4Packet) -> Vec<u8> {
+        assert_eq!(packet.src_addr(), ROUTER_ADDR);
+        let segment = UdpSegment::try_from(packet).unwrap();
+        assert_eq!(segment.src_port(), PCP_SERVER_PORT);
+        assert_eq!(segment.dest_port(), 12345);
+        segment.payload().to_vec()
+    }
+
+    fn server(mappings: Arc<PortMappings>) -> PortMappingServer {
+        PortMappingServer {
+            external_addr: EXTERNAL_ADDR,
+            mappings,
+            ports: 1024..=65535,
+            max_lifetime: Duration::from_secs(3600),
+            started: clock::now(),
+            drops: DropAccounting::default(),
+        }
+    }
+
+    fn nat_pmp_map(op: u8, internal: u16, suggested: u16, lifetime: u32) -> Vec<u8> {
+        let mut payload = vec![0, op, 0, 0];
+        payload.extend_from_slice(&internal.to_be_bytes());
+        payload.extend_from_slice(&suggested.to_be_bytes());
+        payload.extend_from_slice(&lifetime.to_be_bytes());
+        payload
+    }
+
+    fn pcp_map(client: [u8; 4], protocol: u8, internal: u16, suggested: u16) -> Vec<u8> {
+        let mut payload = vec![2, 1, 0, 0];
+        payload.extend_from_slice(&600u32.to_be_bytes());
+        payload.extend_from_slice(&Ipv4Addr::from(client).to_ipv6_mapped().octets());
+        payload.extend_from_slice(&[7; 12]);
+        payload.extend_from_slice(&[protocol, 0, 0, 0]);
+        payload.extend_from_slice(&internal.to_be_bytes());
+        payload.extend_from_slice(&suggested.to_be_bytes());
+        payload.extend_from_slice(&[0; 16]);
+        payload
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_external_addr() {
+        PortMappingComposite::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn nat_pmp_external_address() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = PortMappingComposite::new()
+                .ingressor(immediate_stream(vec![request([192, 168, 1, 20], &[0, 0])]))
+                .external_addr(EXTERNAL_ADDR)
+                .build_link();
+
+            run_link(link).await
+        });
+        let reply = reply_payload(results[0][0].clone());
+        assert_eq!(&reply[..4], &[0, 128, 0, 0]);
+        assert_eq!(&reply[8..], &EXTERNAL_ADDR.octets());
+    }
+
+    #[test]
+    fn nat_pmp_maps_ports() {
+        let mappings = Arc::new(PortMappings::new());
+        let mut server = server(Arc::clone(&mappings));
+
+        let map = |server: &mut PortMappingServer, client, lifetime| {
+            let payload = nat_pmp_map(NAT_PMP_OP_MAP_TCP, 3074, 3074, lifetime);
+            reply_payload(server.process(request(client, &payload)).unwrap())
+        };
+        let reply = map(&mut server, [192, 168, 1, 20], 7200);
+        assert_eq!(&reply[..4], &[0, 130, 0, 0]);
+        assert_eq!(read_u16(&reply, 8), 3074);
+        assert_eq!(read_u16(&reply, 10), 3074);
+        assert_eq!(read_u32(&reply, 12), 3600);
+
+        // The suggested port is taken, so the second console is given another.
+        let reply = map(&mut server, [192, 168, 1, 21], 7200);
+        assert_eq!(read_u16(&reply, 10), 1024);
+
+        let console = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), 3074);
+        assert_eq!(mappings.inbound(PROTOCOL_TCP, 3074), Some(console));
+        assert_eq!(mappings.outbound(PROTOCOL_TCP, console), Some(3074));
+        assert_eq!(mappings.inbound(PROTOCOL_UDP, 3074), None);
+
+        let reply = map(&mut server, [192, 168, 1, 20], 0);
+        assert_eq!(read_u16(&reply, 10), 0);
+        assert_eq!(mappings.inbound(PROTOCOL_TCP, 3074), None);
+        assert_eq!(mappings.mappings().len(), 1);
+    }
+
+    #[test]
+    fn mappings_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("port-mappings-{}.toml", Uuid::new_v4()));
+        let mappings = Arc::new(PortMappings::new());
+        let mut server = server(Arc::clone(&mappings));
+        let payload = nat_pmp_map(NAT_PMP_OP_MAP_UDP, 5060, 5060, 600);
+        server.process(request([192, 168, 1, 30], &payload));
+
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let link = PortMappingComposite::new()
+                .ingressor(immediate_stream(vec![]))
+                .external_addr(EXTERNAL_ADDR)
+                .mappings(mappings)
+                .persist(&path)
+                .build_link();
+            run_link(link).await
+        });
+
+        let restored = Arc::new(PortMappings::new());
+        assert_eq!(restored.load(&path).unwrap(), 1);
+        let phone = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 30), 5060);
+        assert_eq!(restored.inbound(PROTOCOL_UDP, 5060), Some(phone));
+        let left = restored.mappings()[0].expires - clock::now();
+        assert!(left > Duration::from_secs(590) && left <= Duration::from_secs(600));
+
+        // A mapping that expired while the router was down isn't restored.
+        let stale = "[[mapping]]\nprotocol = 17\ninternal = \"192.168.1.30:5060\"\n\
+                     external_port = 5060\nexpires = 1\n";
+        fs::write(&path, stale).unwrap();
+        assert_eq!(restored.load(&path).unwrap(), 0);
+        assert_eq!(restored.inbound(PROTOCOL_UDP, 5060), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pcp_maps_ports() {
+        let mappings = Arc::new(PortMappings::new());
+        let mut server = server(Arc::clone(&mappings));
+
+        let payload = pcp_map([192, 168, 1, 20], PROTOCOL_UDP, 5060, 5060);
+        let reply = reply_payload(
+            server
+                .process(request([192, 168, 1, 20], &payload))
+                .unwrap(),
+        );
+        assert_eq!(&reply[..4], &[2, 0x81, 0, 0]);
+        assert_eq!(read_u32(&reply, 4), 600);
+        // The nonce is sent back, with the port and address mapped.
+        assert_eq!(&reply[24..36], &[7; 12]);
+        assert_eq!(read_u16(&reply, 42), 5060);
+        assert_eq!(&reply[44..60], &EXTERNAL_ADDR.to_ipv6_mapped().octets());
+        assert_eq!(
+            mappings.inbound(PROTOCOL_UDP, 5060),
+            Some(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), 5060))
+        );
+    }
+
+    #[test]
+    fn pcp_rejects_bad_requests() {
+        let mut server = server(Arc::new(PortMappings::new()));
+        let mut result = |client, payload: &[u8]| {
+            reply_payload(server.process(request(client, payload)).unwrap())[3]
+        };
+
+        let spoofed = pcp_map([192, 168, 1, 99], PROTOCOL_UDP, 5060, 5060);
+        assert_eq!(result([192, 168, 1, 20], &spoofed), PCP_ADDRESS_MISMATCH);
+        let sctp = pcp_map([192, 168, 1, 20], 132, 5060, 5060);
+        assert_eq!(result([192, 168, 1, 20], &sctp), PCP_UNSUPP_PROTOCOL);
+        let mut peer = pcp_map([192, 168, 1, 20], PROTOCOL_UDP, 5060, 5060);
+        peer[1] = 2;
+        assert_eq!(result([192, 168, 1, 20], &peer), PCP_UNSUPP_OPCODE);
+        let mut future = pcp_map([192, 168, 1, 20], PROTOCOL_UDP, 5060, 5060);
+        future[0] = 3;
+        assert_eq!(result([192, 168, 1, 20], &future), PCP_UNSUPP_VERSION);
+    }
+
+    #[test]
+    fn drops_requests_it_cannot_answer() {
+        let drop_counters = Arc::new(DropCounters::new());
+        let mut server = PortMappingServer {
+            drops: DropAccounting::new(Some(Arc::clone(&drop_counters)), None),
+            ..server(Arc::new(PortMappings::new()))
+        };
+        // Another server's reply, and a request too short to have an opcode.
+        assert!(server
+            .process(request([192, 168, 1, 20], &[0, 128, 0, 0]))
+            .is_none());
+        assert!(server.process(request([192, 168, 1, 20], &[0])).is_none());
+        assert_eq!(drop_counters.snapshot(), vec![(DropReason::Malformed, 2)]);
+    }
+}
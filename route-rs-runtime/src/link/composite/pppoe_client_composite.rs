@@ -0,0 +1,1114 @@
+use crate::link::event::{DropAccounting, DropCounters, DropReason, EventSink};
+use crate::link::primitive::QueueEgressor;
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::*;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::net::Ipv4Addr;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::{interval_at, Instant, Interval};
+
+const BROADCAST_MAC: MacAddr = MacAddr {
+    bytes: [0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+};
+
+/// RFC 2516 section 7: the MRU must not be negotiated above 1492, to leave room for the PPPoE and
+/// PPP headers within a 1500 byte Ethernet payload.
+const PPPOE_MRU: u16 = 1492;
+
+const LCP_OPTION_MRU: u16 = 1;
+const LCP_OPTION_MAGIC_NUMBER: u16 = 5;
+const IPCP_OPTION_IP_ADDRESS: u16 = 3;
+
+/// Progress of a PPPoE session, from discovery through to carrying IP traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PppoeState {
+    /// Waiting for a PADO in reply to our PADI.
+    Discovering,
+    /// Waiting for a PADS in reply to our PADR.
+    Requesting,
+    /// Negotiating the link with LCP.
+    LcpNegotiation,
+    /// Negotiating our address with IPCP.
+    IpcpNegotiation,
+    /// IPv4 traffic is flowing.
+    Established,
+    /// The access concentrator sent a PADT or an LCP Terminate-Request.
+    Terminated,
+}
+
+#[derive(Debug, Clone)]
+struct SessionInfo {
+    state: PppoeState,
+    session_id: u16,
+    access_concentrator: Option<MacAddr>,
+    local_addr: Option<Ipv4Addr>,
+    peer_addr: Option<Ipv4Addr>,
+}
+
+/// What the PPPoE client has learned about its session, shared so the rest of the router can read
+/// it, for instance to source NAT traffic to the address assigned by IPCP.
+#[derive(Debug)]
+pub struct PppoeSession {
+    info: RwLock<SessionInfo>,
+}
+
+impl PppoeSession {
+    pub fn new() -> Self {
+        PppoeSession {
+            info: RwLock::new(SessionInfo {
+                state: PppoeState::Discovering,
+                session_id: 0,
+                access_concentrator: None,
+                local_addr: None,
+                peer_addr: None,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> PppoeState {
+        self.info.read().unwrap().state
+    }
+
+    /// Session ID assigned by the access concentrator's PADS, or 0 before that.
+    pub fn session_id(&self) -> u16 {
+        self.info.read().unwrap().session_id
+    }
+
+    pub fn access_concentrator(&self) -> Option<MacAddr> {
+        self.info.read().unwrap().access_concentrator
+    }
+
+    /// Our address, once IPCP has acknowledged it.
+    pub fn local_addr(&self) -> Option<Ipv4Addr> {
+        self.info.read().unwrap().local_addr
+    }
+
+    /// The access concentrator's address, if it sent one in its IPCP Configure-Request.
+    pub fn peer_addr(&self) -> Option<Ipv4Addr> {
+        self.info.read().unwrap().peer_addr
+    }
+}
+
+impl Default for PppoeSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The client side of PPPoE discovery, LCP and IPCP. It consumes frames from the WAN and returns the
+/// frames to send in reply, leaving all I/O to `PppoeClientRunner`.
+struct PppoeClient {
+    mac_addr: MacAddr,
+    service_name: Vec<u8>,
+    session: Arc<PppoeSession>,
+    magic_number: u32,
+    identifier: u8,
+    requested_addr: Ipv4Addr,
+    lcp_acked_by_peer: bool,
+    lcp_acked_by_us: bool,
+    ipcp_acked_by_peer: bool,
+    ipcp_acked_by_us: bool,
+    /// Our outstanding request, sent again if the retransmit timer fires before a reply arrives.
+    last_request: Option<EthernetFrame>,
+    drops: DropAccounting,
+}
+
+impl PppoeClient {
+    fn new(mac_addr: MacAddr, service_name: Vec<u8>, session: Arc<PppoeSession>) -> Self {
+        PppoeClient {
+            mac_addr,
+            service_name,
+            session,
+            magic_number: rand::random(),
+            identifier: 0,
+            requested_addr: Ipv4Addr::UNSPECIFIED,
+            lcp_acked_by_peer: false,
+            lcp_acked_by_us: false,
+            ipcp_acked_by_peer: false,
+            ipcp_acked_by_us: false,
+            last_request: None,
+            drops: DropAccounting::default(),
+        }
+    }
+
+    fn state(&self) -> PppoeState {
+        self.session.state()
+    }
+
+    fn set_state(&self, state: PppoeState) {
+        self.session.info.write().unwrap().state = state;
+    }
+
+    fn session_id(&self) -> u16 {
+        self.session.session_id()
+    }
+
+    fn access_concentrator(&self) -> MacAddr {
+        self.session.access_concentrator().unwrap_or(BROADCAST_MAC)
+    }
+
+    /// Begins discovery by broadcasting a PADI.
+    fn start(&mut self) -> Vec<EthernetFrame> {
+        let mut padi = PppoeFrame::discovery(PADI);
+        padi.add_tag(TAG_SERVICE_NAME, &self.service_name);
+        let mut frame = padi.frame();
+        frame.set_dest_mac(BROADCAST_MAC);
+        frame.set_src_mac(self.mac_addr);
+
+        self.set_state(PppoeState::Discovering);
+        self.last_request = Some(frame.clone());
+        vec![frame]
+    }
+
+    /// Resends our outstanding request, if we are still waiting on a reply.
+    fn retransmit(&self) -> Option<EthernetFrame> {
+        match self.state() {
+            PppoeState::Established | PppoeState::Terminated => None,
+            _ => self.last_request.clone(),
+        }
+    }
+
+    /// Handles a frame from the WAN. Returns the frames to send back to the WAN, and the IPv4 frame
+    /// to forward to the LAN if the frame carried one.
+    fn handle_wan(&mut self, frame: EthernetFrame) -> (Vec<EthernetFrame>, Option<EthernetFrame>) {
+        let src_mac = frame.src_mac();
+        let dest_mac = frame.dest_mac();
+        if dest_mac != self.mac_addr && dest_mac != BROADCAST_MAC {
+            self.drops.dropped(DropReason::Discarded);
+            return (vec![], None);
+        }
+        let pppoe = match PppoeFrame::try_from(frame) {
+            Ok(pppoe) => pppoe,
+            Err(err) => {
+                self.drops.parse_error(err);
+                return (vec![], None);
+            }
+        };
+
+        if pppoe.is_discovery() {
+            return (self.handle_discovery(src_mac, &pppoe), None);
+        }
+
+        if self.session.access_concentrator() != Some(src_mac)
+            || pppoe.session_id() != self.session_id()
+            || pppoe.code() != PPPOE_SESSION_DATA
+        {
+            self.drops.dropped(DropReason::Discarded);
+            return (vec![], None);
+        }
+        match pppoe.ppp_protocol() {
+            Some(PPP_IPV4) if self.state() == PppoeState::Established => {
+                let mut frame = EthernetFrame::empty();
+                frame.set_ether_type(IPV4_ETHER_TYPE);
+                frame.set_payload(pppoe.ppp_payload());
+                (vec![], Some(frame))
+            }
+            Some(PPP_LCP) => (self.handle_lcp(pppoe.ppp_payload()), None),
+            Some(PPP_IPCP) => (self.handle_ipcp(pppoe.ppp_payload()), None),
+            Some(PPP_IPV4) => {
+                self.drops.dropped(DropReason::Discarded);
+                (vec![], None)
+            }
+            _ => {
+                self.drops.dropped(DropReason::Unclassified);
+                (vec![], None)
+            }
+        }
+    }
+
+    /// Encapsulates an IPv4 frame from the LAN into the session, or drops it if the session is not up.
+    fn handle_lan(&self, frame: EthernetFrame) -> Option<EthernetFrame> {
+        if frame.ether_type() != IPV4_ETHER_TYPE {
+            self.drops.dropped(DropReason::Unclassified);
+            return None;
+        }
+        if self.state() != PppoeState::Established {
+            self.drops.dropped(DropReason::NoRoute);
+            return None;
+        }
+        let payload = &frame.data[frame.payload_offset..];
+        Some(self.session_frame(PPP_IPV4, payload))
+    }
+
+    fn handle_discovery(&mut self, src_mac: MacAddr, pppoe: &PppoeFrame) -> Vec<EthernetFrame> {
+        match (pppoe.code(), self.state()) {
+            (PADO, PppoeState::Discovering) => {
+                let mut padr = PppoeFrame::discovery(PADR);
+                padr.add_tag(TAG_SERVICE_NAME, &self.service_name);
+                // The cookie must be echoed back for the access concentrator to accept the PADR.
+                if let Some(cookie) = pppoe.tag(TAG_AC_COOKIE) {
+                    padr.add_tag(TAG_AC_COOKIE, &cookie);
+                }
+                self.session.info.write().unwrap().access_concentrator = Some(src_mac);
+                self.set_state(PppoeState::Requesting);
+
+                let mut frame = padr.frame();
+                frame.set_dest_mac(src_mac);
+                frame.set_src_mac(self.mac_addr);
+                self.last_request = Some(frame.clone());
+                vec![frame]
+            }
+            (PADS, PppoeState::Requesting)
+                if Some(src_mac) == self.session.access_concentrator() =>
+            {
+                if pppoe.session_id() == 0 {
+                    // A PADS without a session ID is an error, so go back to looking for another.
+                    return self.start();
+                }
+                self.session.info.write().unwrap().session_id = pppoe.session_id();
+                self.set_state(PppoeState::LcpNegotiation);
+                vec![self.lcp_configure_request()]
+            }
+            (PADT, _)
+                if Some(src_mac) == self.session.access_concentrator()
+                    && pppoe.session_id() == self.session_id() =>
+            {
+                self.set_state(PppoeState::Terminated);
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn handle_lcp(&mut self, payload: &[u8]) -> Vec<EthernetFrame> {
+        let packet = match PppControlPacket::try_from(payload) {
+            Ok(packet) => packet,
+            Err(err) => {
+                self.drops.parse_error(err);
+                return vec![];
+            }
+        };
+
+        match packet.code {
+            PPP_CONFIGURE_REQUEST => {
+                let (reply, acked) =
+                    configure_reply(&packet, &[LCP_OPTION_MRU, LCP_OPTION_MAGIC_NUMBER]);
+                let mut replies = vec![self.session_frame(PPP_LCP, &reply.to_bytes())];
+                if acked {
+                    self.lcp_acked_by_us = true;
+                    replies.extend(self.lcp_opened());
+                }
+                replies
+            }
+            PPP_CONFIGURE_ACK if packet.identifier == self.identifier => {
+                self.lcp_acked_by_peer = true;
+                self.lcp_opened()
+            }
+            PPP_CONFIGURE_NAK | PPP_CONFIGURE_REJECT if packet.identifier == self.identifier => {
+                // Our options are the bare minimum, so try again without any.
+                self.identifier = self.identifier.wrapping_add(1);
+                let request = PppControlPacket::new(PPP_CONFIGURE_REQUEST, self.identifier, &[]);
+                let frame = self.session_frame(PPP_LCP, &request.to_bytes());
+                self.last_request = Some(frame.clone());
+                vec![frame]
+            }
+            PPP_TERMINATE_REQUEST => {
+                self.set_state(PppoeState::Terminated);
+                let ack = PppControlPacket::new(PPP_TERMINATE_ACK, packet.identifier, &[]);
+                vec![self.session_frame(PPP_LCP, &ack.to_bytes())]
+            }
+            PPP_ECHO_REQUEST => {
+                let mut data = self.magic_number.to_be_bytes().to_vec();
+                data.extend_from_slice(packet.data.get(4..).unwrap_or(&[]));
+                let reply = PppControlPacket::new(PPP_ECHO_REPLY, packet.identifier, &data);
+                vec![self.session_frame(PPP_LCP, &reply.to_bytes())]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn handle_ipcp(&mut self, payload: &[u8]) -> Vec<EthernetFrame> {
+        if self.state() != PppoeState::IpcpNegotiation && self.state() != PppoeState::Established {
+            return vec![];
+        }
+        let packet = match PppControlPacket::try_from(payload) {
+            Ok(packet) => packet,
+            Err(err) => {
+                self.drops.parse_error(err);
+                return vec![];
+            }
+        };
+
+        match packet.code {
+            PPP_CONFIGURE_REQUEST => {
+                let (reply, acked) = configure_reply(&packet, &[IPCP_OPTION_IP_ADDRESS]);
+                if acked {
+                    self.session.info.write().unwrap().peer_addr = ip_address_option(&packet);
+                    self.ipcp_acked_by_us = true;
+                }
+                let reply = self.session_frame(PPP_IPCP, &reply.to_bytes());
+                if acked {
+                    self.ipcp_opened();
+                }
+                vec![reply]
+            }
+            PPP_CONFIGURE_ACK if packet.identifier == self.identifier => {
+                self.ipcp_acked_by_peer = true;
+                self.session.info.write().unwrap().local_addr = Some(self.requested_addr);
+                self.ipcp_opened();
+                vec![]
+            }
+            PPP_CONFIGURE_NAK if packet.identifier == self.identifier => {
+                // The access concentrator suggests the address we should use.
+                if let Some(addr) = ip_address_option(&packet) {
+                    self.requested_addr = addr;
+                }
+                vec![self.ipcp_configure_request()]
+            }
+            PPP_TERMINATE_REQUEST => {
+                self.set_state(PppoeState::Terminated);
+                let ack = PppControlPacket::new(PPP_TERMINATE_ACK, packet.identifier, &[]);
+                vec![self.session_frame(PPP_IPCP, &ack.to_bytes())]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Moves on to IPCP once both sides have acknowledged each other's LCP options.
+    fn lcp_opened(&mut self) -> Vec<EthernetFrame> {
+        if self.state() != PppoeState::LcpNegotiation
+            || !self.lcp_acked_by_peer
+            || !self.lcp_acked_by_us
+        {
+            return vec![];
+        }
+        self.set_state(PppoeState::IpcpNegotiation);
+        vec![self.ipcp_configure_request()]
+    }
+
+    fn ipcp_opened(&mut self) {
+        if self.state() == PppoeState::IpcpNegotiation
+            && self.ipcp_acked_by_peer
+            && self.ipcp_acked_by_us
+        {
+            self.set_state(PppoeState::Established);
+            self.last_request = None;
+        }
+    }
+
+    fn lcp_configure_request(&mut self) -> EthernetFrame {
+        self.identifier = self.identifier.wrapping_add(1);
+        let request = PppControlPacket::with_options(
+            PPP_CONFIGURE_REQUEST,
+            self.identifier,
+            &[
+                PppoeTag {
+                    tag_type: LCP_OPTION_MRU,
+                    value: PPPOE_MRU.to_be_bytes().to_vec(),
+                },
+                PppoeTag {
+                    tag_type: LCP_OPTION_MAGIC_NUMBER,
+                    value: self.magic_number.to_be_bytes().to_vec(),
+                },
+            ],
+        );
+        let frame = self.session_frame(PPP_LCP, &request.to_bytes());
+        self.last_request = Some(frame.clone());
+        frame
+    }
+
+    /// Asks for `requested_addr`, which starts out as 0.0.0.0 to have the access concentrator pick one.
+    fn ipcp_configure_request(&mut self) -> EthernetFrame {
+        self.identifier = self.identifier.wrapping_add(1);
+        let request = PppControlPacket::with_options(
+            PPP_CONFIGURE_REQUEST,
+            self.identifier,
+            &[PppoeTag {
+                tag_type: IPCP_OPTION_IP_ADDRESS,
+                value: self.requested_addr.octets().to_vec(),
+            }],
+        );
+        let frame = self.session_frame(PPP_IPCP, &request.to_bytes());
+        self.last_request = Some(frame.clone());
+        frame
+    }
+
+    fn session_frame(&self, protocol: u16, payload: &[u8]) -> EthernetFrame {
+        let mut frame = PppoeFrame::session(self.session_id(), protocol, payload).frame();
+        frame.set_dest_mac(self.access_concentrator());
+        frame.set_src_mac(self.mac_addr);
+        frame
+    }
+}
+
+/// Acks a Configure-Request if we understand all of its options, or rejects the ones we don't.
+/// Returns the reply, and whether it is an ack.
+fn configure_reply(request: &PppControlPacket, supported: &[u16]) -> (PppControlPacket, bool) {
+    let unsupported: Vec<PppoeTag> = request
+        .options()
+        .into_iter()
+        .filter(|option| !supported.contains(&option.tag_type))
+        .collect();
+
+    if unsupported.is_empty() {
+        let ack = PppControlPacket::new(PPP_CONFIGURE_ACK, request.identifier, &request.data);
+        (ack, true)
+    } else {
+        let reject =
+            PppControlPacket::with_options(PPP_CONFIGURE_REJECT, request.identifier, &unsupported);
+        (reject, false)
+    }
+}
+
+fn ip_address_option(packet: &PppControlPacket) -> Option<Ipv4Addr> {
+    packet
+        .options()
+        .into_iter()
+        .find(|option| option.tag_type == IPCP_OPTION_IP_ADDRESS && option.value.len() == 4)
+        .map(|option| {
+            Ipv4Addr::new(
+                option.value[0],
+                option.value[1],
+                option.value[2],
+                option.value[3],
+            )
+        })
+}
+
+/// Drives a `PppoeClient`, feeding it frames from both sides and queueing what it produces for the
+/// two egressors. Control frames are queued here rather than in the channel, since a single WAN frame
+/// may need several replies.
+struct PppoeClientRunner {
+    client: PppoeClient,
+    wan_stream: Option<PacketStream<EthernetFrame>>,
+    lan_stream: Option<PacketStream<EthernetFrame>>,
+    started: bool,
+    retransmit_interval: Duration,
+    retransmit: Option<Interval>,
+    to_wan: Sender<Option<EthernetFrame>>,
+    to_lan: Sender<Option<EthernetFrame>>,
+    wan_park: Arc<AtomicCell<TaskParkState>>,
+    lan_park: Arc<AtomicCell<TaskParkState>>,
+    wan_queue: VecDeque<EthernetFrame>,
+    lan_queue: VecDeque<EthernetFrame>,
+}
+
+/// Moves queued frames into an egressor's channel. Returns false, having parked the task, if the
+/// channel fills up first.
+fn flush(
+    queue: &mut VecDeque<EthernetFrame>,
+    sender: &Sender<Option<EthernetFrame>>,
+    task_park: &Arc<AtomicCell<TaskParkState>>,
+    cx: &mut Context,
+) -> bool {
+    while !queue.is_empty() {
+        if sender.is_full() {
+            park_and_wake(task_park, cx.waker().clone());
+            return false;
+        }
+        if let Err(err) = sender.try_send(queue.pop_front()) {
+            panic!("PppoeClientRunner: failed to send to egressor: {:?}", err);
+        }
+        unpark_and_wake(task_park);
+    }
+    true
+}
+
+/// Polls a stream if it has not finished yet. Returns whether the poll made progress.
+fn poll_input(
+    stream: &mut Option<PacketStream<EthernetFrame>>,
+    cx: &mut Context,
+) -> Option<Option<EthernetFrame>> {
+    let input = stream.as_mut()?;
+    match Pin::new(input).poll_next(cx) {
+        Poll::Ready(Some(frame)) => Some(Some(frame)),
+        Poll::Ready(None) => {
+            *stream = None;
+            Some(None)
+        }
+        Poll::Pending => None,
+    }
+}
+
+impl Future for PppoeClientRunner {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let runner = Pin::into_inner(self.as_mut());
+        loop {
+            if !flush(&mut runner.wan_queue, &runner.to_wan, &runner.wan_park, cx)
+                || !flush(&mut runner.lan_queue, &runner.to_lan, &runner.lan_park, cx)
+            {
+                return Poll::Pending;
+            }
+
+            if !runner.started {
+                runner.started = true;
+                runner.wan_queue.extend(runner.client.start());
+                continue;
+            }
+
+            if runner.wan_stream.is_none() && runner.lan_stream.is_none() {
+                for (sender, task_park) in [
+                    (&runner.to_wan, &runner.wan_park),
+                    (&runner.to_lan, &runner.lan_park),
+                ]
+                .iter()
+                {
+                    if let Err(err) = sender.try_send(None) {
+                        panic!(
+                            "PppoeClientRunner: Drop: try_send to egressor, fail?: {:?}",
+                            err
+                        );
+                    }
+                    die_and_wake(task_park);
+                }
+                return Poll::Ready(());
+            }
+
+            let mut progressed = false;
+
+            if let Some(frame) = poll_input(&mut runner.wan_stream, cx) {
+                progressed = true;
+                if let Some(frame) = frame {
+                    let (replies, to_lan) = runner.client.handle_wan(frame);
+                    runner.wan_queue.extend(replies);
+                    runner.lan_queue.extend(to_lan);
+                }
+            }
+
+            if let Some(frame) = poll_input(&mut runner.lan_stream, cx) {
+                progressed = true;
+                if let Some(frame) = frame {
+                    runner.wan_queue.extend(runner.client.handle_lan(frame));
+                }
+            }
+
+            if let Some(request) = runner.client.retransmit() {
+                let period = runner.retransmit_interval;
+                let retransmit = runner
+                    .retransmit
+                    .get_or_insert_with(|| interval_at(Instant::now() + period, period));
+                if Pin::new(retransmit).poll_next(cx).is_ready() {
+                    progressed = true;
+                    runner.wan_queue.push_back(request);
+                }
+            }
+
+            if !progressed {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// A PPPoE client for the WAN side of a router. It discovers an access concentrator, negotiates LCP
+/// and IPCP, and then carries IPv4 traffic over the session.
+///
+/// Takes exactly two ingressors: the first carries frames received from the WAN, and the second IPv4
+/// frames from the LAN to send out over the session. Egressor 0 carries frames to transmit on the WAN,
+/// which includes the discovery and control traffic the client generates itself, and egressor 1 the
+/// decapsulated IPv4 frames received over the session. Decapsulated frames have zeroed MAC addresses
+/// for later links to fill in.
+///
+/// LAN traffic is dropped until the session is established. Requests are retransmitted every second
+/// until they are answered. Authentication with PAP or CHAP is not supported, so the Auth-Protocol LCP
+/// option is rejected; access concentrators that insist on it will not bring the session up.
+///
+/// The frames the composite drops, LAN traffic before the session is up, and WAN frames that
+/// aren't for it or its session, are counted by reason in its `drop_counters`, if it has them.
+#[derive(Default)]
+pub struct PppoeClientComposite {
+    in_streams: Vec<PacketStream<EthernetFrame>>,
+    mac_addr: Option<MacAddr>,
+    service_name: Option<String>,
+    retransmit_interval: Option<Duration>,
+    queue_capacity: usize,
+    session: Option<Arc<PppoeSession>>,
+    event_sink: Option<EventSink>,
+    drop_counters: Option<Arc<DropCounters>>,
+}
+
+impl PppoeClientComposite {
+    pub fn new() -> Self {
+        PppoeClientComposite {
+            in_streams: vec![],
+            mac_addr: None,
+            service_name: None,
+            retransmit_interval: None,
+            queue_capacity: 10,
+            session: None,
+            event_sink: None,
+            drop_counters: None,
+        }
+    }
+
+    /// MAC address of the WAN interface, used as the source of every frame sent to the WAN.
+    pub fn mac_addr(self, mac_addr: MacAddr) -> Self {
+        PppoeClientComposite {
+            in_streams: self.in_streams,
+            mac_addr: Some(mac_addr),
+            service_name: self.service_name,
+            retransmit_interval: self.retransmit_interval,
+            queue_capacity: self.queue_capacity,
+            session: self.session,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Service name to request during discovery. By default any service is accepted.
+    pub fn service_name(self, service_name: &str) -> Self {
+        PppoeClientComposite {
+            in_streams: self.in_streams,
+            mac_addr: self.mac_addr,
+            service_name: Some(service_name.to_string()),
+            retransmit_interval: self.retransmit_interval,
+            queue_capacity: self.queue_capacity,
+            session: self.session,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// How long to wait for a reply before resending a request, default value is 1 second.
+    pub fn retransmit_interval(self, retransmit_interval: Duration) -> Self {
+        assert!(
+            retransmit_interval > Duration::from_secs(0),
+            "retransmit_interval must be > 0"
+        );
+
+        PppoeClientComposite {
+            in_streams: self.in_streams,
+            mac_addr: self.mac_addr,
+            service_name: self.service_name,
+            retransmit_interval: Some(retransmit_interval),
+            queue_capacity: self.queue_capacity,
+            session: self.session,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes queue_capacity of each egressor, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        PppoeClientComposite {
+            in_streams: self.in_streams,
+            mac_addr: self.mac_addr,
+            service_name: self.service_name,
+            retransmit_interval: self.retransmit_interval,
+            queue_capacity,
+            session: self.session,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Shares the session state, for instance to find out which address IPCP assigned.
+    pub fn session(self, session: Arc<PppoeSession>) -> Self {
+        PppoeClientComposite {
+            in_streams: self.in_streams,
+            mac_addr: self.mac_addr,
+            service_name: self.service_name,
+            retransmit_interval: self.retransmit_interval,
+            queue_capacity: self.queue_capacity,
+            session: Some(session),
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Reports frames from the WAN that can't be parsed as PPPoE, or whose LCP or IPCP packets
+    /// can't be parsed, and the frames the composite drops, to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        PppoeClientComposite {
+            in_streams: self.in_streams,
+            mac_addr: self.mac_addr,
+            service_name: self.service_name,
+            retransmit_interval: self.retransmit_interval,
+            queue_capacity: self.queue_capacity,
+            session: self.session,
+            event_sink: Some(event_sink),
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Counts the frames the composite drops in `drop_counters`, by reason.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        PppoeClientComposite {
+            in_streams: self.in_streams,
+            mac_addr: self.mac_addr,
+            service_name: self.service_name,
+            retransmit_interval: self.retransmit_interval,
+            queue_capacity: self.queue_capacity,
+            session: self.session,
+            event_sink: self.event_sink,
+            drop_counters: Some(drop_counters),
+        }
+    }
+}
+
+impl LinkBuilder<EthernetFrame, EthernetFrame> for PppoeClientComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<EthernetFrame>>,
+    ) -> Result<Self, LinkBuildError> {
+        if self.in_streams.len() + in_streams.len() != 2 {
+            return Err(LinkBuildError::Ingressors(
+                "PppoeClientComposite takes exactly 2 input streams, WAN then LAN",
+            ));
+        }
+
+        let mut streams = self.in_streams;
+        streams.append(&mut in_streams);
+        Ok(PppoeClientComposite {
+            in_streams: streams,
+            mac_addr: self.mac_addr,
+            service_name: self.service_name,
+            retransmit_interval: self.retransmit_interval,
+            queue_capacity: self.queue_capacity,
+            session: self.session,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        })
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<EthernetFrame>) -> Result<Self, LinkBuildError> {
+        if self.in_streams.len() == 2 {
+            return Err(LinkBuildError::Ingressors(
+                "PppoeClientComposite takes exactly 2 input streams, WAN then LAN",
+            ));
+        }
+
+        let mut streams = self.in_streams;
+        streams.push(in_stream);
+        Ok(PppoeClientComposite {
+            in_streams: streams,
+            mac_addr: self.mac_addr,
+            service_name: self.service_name,
+            retransmit_interval: self.retransmit_interval,
+            queue_capacity: self.queue_capacity,
+            session: self.session,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        })
+    }
+
+    fn try_build_link(mut self) -> Result<Link<EthernetFrame>, LinkBuildError> {
+        if self.in_streams.len() != 2 {
+            return Err(LinkBuildError::Invalid(String::from(
+                "Cannot build link! Needs a WAN and a LAN input stream",
+            )));
+        }
+        if self.mac_addr.is_none() {
+            return Err(LinkBuildError::Missing("mac_addr"));
+        }
+
+        let lan_stream = self.in_streams.pop();
+        let wan_stream = self.in_streams.pop();
+        let (to_wan, from_runner_wan) =
+            crossbeam_channel::bounded::<Option<EthernetFrame>>(self.queue_capacity);
+        let (to_lan, from_runner_lan) =
+            crossbeam_channel::bounded::<Option<EthernetFrame>>(self.queue_capacity);
+        let wan_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+        let lan_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+        let mut client = PppoeClient::new(
+            self.mac_addr.unwrap(),
+            self.service_name.unwrap_or_default().into_bytes(),
+            self.session.unwrap_or_default(),
+        );
+        client.drops = DropAccounting::new(self.drop_counters, self.event_sink);
+        let runner = PppoeClientRunner {
+            client,
+            wan_stream,
+            lan_stream,
+            started: false,
+            retransmit_interval: self.retransmit_interval.unwrap_or(Duration::from_secs(1)),
+            retransmit: None,
+            to_wan,
+            to_lan,
+            wan_park: Arc::clone(&wan_park),
+            lan_park: Arc::clone(&lan_park),
+            wan_queue: VecDeque::new(),
+            lan_queue: VecDeque::new(),
+        };
+
+        Ok((
+            vec![Box::new(runner)],
+            vec![
+                Box::new(QueueEgressor::new(from_runner_wan, wan_park)),
+                Box::new(QueueEgressor::new(from_runner_lan, lan_park)),
+            ],
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+
+    const CLIENT_MAC: MacAddr = MacAddr {
+        bytes: [0x02, 0, 0, 0, 0, 1],
+    };
+    const AC_MAC: MacAddr = MacAddr {
+        bytes: [0x02, 0, 0, 0, 0, 2],
+    };
+
+    fn from_ac(pppoe: PppoeFrame) -> EthernetFrame {
+        let mut frame = pppoe.frame();
+        frame.set_dest_mac(CLIENT_MAC);
+        frame.set_src_mac(AC_MAC);
+        frame
+    }
+
+    fn pado() -> EthernetFrame {
+        let mut pado = PppoeFrame::discovery(PADO);
+        pado.add_tag(TAG_AC_NAME, b"ac")
+            .add_tag(TAG_AC_COOKIE, &[9, 9]);
+        from_ac(pado)
+    }
+
+    fn pads(session_id: u16) -> EthernetFrame {
+        let mut pads = PppoeFrame::discovery(PADS);
+        pads.set_session_id(session_id);
+        from_ac(pads)
+    }
+
+    fn control(protocol: u16, code: u8, identifier: u8, options: &[PppoeTag]) -> EthernetFrame {
+        let packet = PppControlPacket::with_options(code, identifier, options);
+        from_ac(PppoeFrame::session(7, protocol, &packet.to_bytes()))
+    }
+
+    fn ip_option(addr: [u8; 4]) -> PppoeTag {
+        PppoeTag {
+            tag_type: IPCP_OPTION_IP_ADDRESS,
+            value: addr.to_vec(),
+        }
+    }
+
+    fn parse_control(frame: &EthernetFrame) -> (u16, PppControlPacket) {
+        let pppoe = PppoeFrame::try_from(frame.clone()).unwrap();
+        let packet = PppControlPacket::try_from(pppoe.ppp_payload()).unwrap();
+        (pppoe.ppp_protocol().unwrap(), packet)
+    }
+
+    /// The access concentrator's half of a session with the identifiers the client will use.
+    fn negotiation() -> Vec<EthernetFrame> {
+        vec![
+            pado(),
+            pads(7),
+            control(PPP_LCP, PPP_CONFIGURE_REQUEST, 1, &[]),
+            control(PPP_LCP, PPP_CONFIGURE_ACK, 1, &[]),
+            control(
+                PPP_IPCP,
+                PPP_CONFIGURE_REQUEST,
+                1,
+                &[ip_option([10, 0, 0, 1])],
+            ),
+            control(PPP_IPCP, PPP_CONFIGURE_NAK, 2, &[ip_option([10, 0, 0, 2])]),
+            control(PPP_IPCP, PPP_CONFIGURE_ACK, 3, &[ip_option([10, 0, 0, 2])]),
+        ]
+    }
+
+    fn established_client() -> PppoeClient {
+        let mut client = PppoeClient::new(CLIENT_MAC, vec![], Arc::new(PppoeSession::new()));
+        client.start();
+        for frame in negotiation() {
+            client.handle_wan(frame);
+        }
+        client
+    }
+
+    fn ipv4_frame() -> EthernetFrame {
+        EthernetFrame::encap_ipv4(Ipv4Packet::encap_udp(UdpSegment::empty()))
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_mac_addr() {
+        PppoeClientComposite::new()
+            .ingressors(vec![immediate_stream(vec![]), immediate_stream(vec![])])
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_with_one_input_stream() {
+        PppoeClientComposite::new()
+            .mac_addr(CLIENT_MAC)
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn discovery() {
+        let session = Arc::new(PppoeSession::new());
+        let mut client = PppoeClient::new(CLIENT_MAC, b"isp".to_vec(), Arc::clone(&session));
+
+        let padi = PppoeFrame::try_from(client.start().remove(0)).unwrap();
+        assert_eq!(padi.code(), PADI);
+        assert_eq!(padi.tag(TAG_SERVICE_NAME), Some(b"isp".to_vec()));
+        assert_eq!(padi.clone().frame().dest_mac(), BROADCAST_MAC);
+
+        let (mut replies, _) = client.handle_wan(pado());
+        let padr = PppoeFrame::try_from(replies.remove(0)).unwrap();
+        assert_eq!(padr.code(), PADR);
+        assert_eq!(padr.tag(TAG_AC_COOKIE), Some(vec![9, 9]));
+        assert_eq!(padr.frame().dest_mac(), AC_MAC);
+        assert_eq!(session.state(), PppoeState::Requesting);
+
+        let (mut replies, _) = client.handle_wan(pads(7));
+        assert_eq!(session.state(), PppoeState::LcpNegotiation);
+        assert_eq!(session.session_id(), 7);
+        let (protocol, request) = parse_control(&replies.remove(0));
+        assert_eq!(protocol, PPP_LCP);
+        assert_eq!(request.code, PPP_CONFIGURE_REQUEST);
+    }
+
+    #[test]
+    fn negotiates_address() {
+        let client = established_client();
+        let session = &client.session;
+        assert_eq!(session.state(), PppoeState::Established);
+        assert_eq!(session.local_addr(), Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(session.peer_addr(), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(client.retransmit(), None);
+    }
+
+    #[test]
+    fn rejects_authentication() {
+        let mut client = PppoeClient::new(CLIENT_MAC, vec![], Arc::new(PppoeSession::new()));
+        client.start();
+        client.handle_wan(pado());
+        client.handle_wan(pads(7));
+
+        let auth_pap = PppoeTag {
+            tag_type: 3,
+            value: vec![0xc0, 0x23],
+        };
+        let (replies, _) = client.handle_wan(control(
+            PPP_LCP,
+            PPP_CONFIGURE_REQUEST,
+            4,
+            std::slice::from_ref(&auth_pap),
+        ));
+        let (_, reply) = parse_control(&replies[0]);
+        assert_eq!(reply.code, PPP_CONFIGURE_REJECT);
+        assert_eq!(reply.options(), vec![auth_pap]);
+    }
+
+    #[test]
+    fn echo_and_terminate() {
+        let mut client = established_client();
+
+        let echo = PppControlPacket::new(PPP_ECHO_REQUEST, 5, &[0, 0, 0, 1, 0xaa]);
+        let (replies, _) =
+            client.handle_wan(from_ac(PppoeFrame::session(7, PPP_LCP, &echo.to_bytes())));
+        let (_, reply) = parse_control(&replies[0]);
+        assert_eq!(reply.code, PPP_ECHO_REPLY);
+        assert_eq!(reply.identifier, 5);
+        assert_eq!(reply.data[4..], [0xaa]);
+
+        let mut padt = PppoeFrame::discovery(PADT);
+        padt.set_session_id(7);
+        client.handle_wan(from_ac(padt));
+        assert_eq!(client.state(), PppoeState::Terminated);
+        assert_eq!(client.handle_lan(ipv4_frame()), None);
+    }
+
+    #[test]
+    fn encapsulates_and_decapsulates() {
+        let client = established_client();
+
+        let pppoe = PppoeFrame::try_from(client.handle_lan(ipv4_frame()).unwrap()).unwrap();
+        assert_eq!(pppoe.session_id(), 7);
+        assert_eq!(pppoe.ppp_protocol(), Some(PPP_IPV4));
+        let ip_bytes = pppoe.ppp_payload().to_vec();
+
+        let mut client = client;
+        let (_, to_lan) = client.handle_wan(from_ac(PppoeFrame::session(7, PPP_IPV4, &ip_bytes)));
+        assert_eq!(to_lan, Some(ipv4_frame()));
+
+        let (_, to_lan) = client.handle_wan(from_ac(PppoeFrame::session(8, PPP_IPV4, &ip_bytes)));
+        assert_eq!(to_lan, None);
+    }
+
+    #[test]
+    fn reports_unparseable_frames() {
+        let (sender, events) = crossbeam_channel::unbounded();
+        let mut client = PppoeClient::new(CLIENT_MAC, vec![], Arc::new(PppoeSession::new()));
+        client.drops = DropAccounting::new(None, Some(EventSink::new("pppoe", sender)));
+
+        let mut frame = ipv4_frame();
+        frame.set_dest_mac(CLIENT_MAC);
+        assert_eq!(client.handle_wan(frame), (vec![], None));
+
+        let events: Vec<String> = events.try_iter().map(|event| event.to_string()).collect();
+        assert_eq!(
+            events,
+            vec!["pppoe: could not parse packet, Frame does not have a PPPoE ether type"]
+        );
+    }
+
+    #[test]
+    fn drops_lan_traffic_before_session() {
+        let drop_counters = Arc::new(DropCounters::new());
+        let mut client = PppoeClient::new(CLIENT_MAC, vec![], Arc::new(PppoeSession::new()));
+        client.drops = DropAccounting::new(Some(Arc::clone(&drop_counters)), None);
+        assert_eq!(client.handle_lan(ipv4_frame()), None);
+        assert_eq!(drop_counters.snapshot(), vec![(DropReason::NoRoute, 1)]);
+    }
+
+    #[test]
+    fn full_session() {
+        let session = Arc::new(PppoeSession::new());
+        let mut from_wan = negotiation();
+        from_wan.push(from_ac(PppoeFrame::session(
+            7,
+            PPP_IPV4,
+            &ipv4_frame().data[14..],
+        )));
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let mut arp = EthernetFrame::empty();
+            arp.set_ether_type(ARP_ETHER_TYPE);
+            let from_lan = PacketIntervalGenerator::new(
+                Duration::from_millis(100),
+                vec![arp, ipv4_frame()].into_iter(),
+            );
+
+            let link = PppoeClientComposite::new()
+                .mac_addr(CLIENT_MAC)
+                .session(Arc::clone(&session))
+                .ingressors(vec![immediate_stream(from_wan), Box::new(from_lan)])
+                .build_link();
+
+            run_link(link).await
+        });
+
+        assert_eq!(session.state(), PppoeState::Established);
+        assert_eq!(session.local_addr(), Some(Ipv4Addr::new(10, 0, 0, 2)));
+
+        let codes: Vec<u8> = results[0]
+            .iter()
+            .take(2)
+            .map(|frame| PppoeFrame::try_from(frame.clone()).unwrap().code())
+            .collect();
+        assert_eq!(codes, vec![PADI, PADR]);
+
+        let sent_ip = results[0].last().unwrap();
+        let sent_ip = PppoeFrame::try_from(sent_ip.clone()).unwrap();
+        assert_eq!(sent_ip.ppp_protocol(), Some(PPP_IPV4));
+
+        assert_eq!(results[1], vec![ipv4_frame()]);
+    }
+}
@@ -0,0 +1,461 @@
+use crate::config::{InterfaceConfig, Subnet};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::{ipv6_pseudo_header_checksum, IpProtocol, Ipv6Packet, MacAddr};
+use std::net::{IpAddr, Ipv6Addr};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tokio::time::{interval_at, Interval};
+
+/// RFC 4861 section 4.
+const ICMPV6_ROUTER_SOLICITATION: u8 = 133;
+const ICMPV6_ROUTER_ADVERTISEMENT: u8 = 134;
+
+const OPTION_SOURCE_LINK_LAYER_ADDR: u8 = 1;
+const OPTION_PREFIX_INFORMATION: u8 = 3;
+const OPTION_MTU: u8 = 5;
+/// RFC 8106 section 5.1.
+const OPTION_RDNSS: u8 = 25;
+
+/// The on-link and autonomous address configuration flags of a prefix, RFC 4861 section 4.6.2.
+const PREFIX_FLAGS_ON_LINK_AUTONOMOUS: u8 = 0xc0;
+const PREFIX_VALID_LIFETIME: u32 = 30 * 24 * 60 * 60;
+const PREFIX_PREFERRED_LIFETIME: u32 = 7 * 24 * 60 * 60;
+
+/// Solicited advertisements are sent at most this often, RFC 4861 section 10.
+const MIN_DELAY_BETWEEN_RAS: Duration = Duration::from_secs(3);
+
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+/// What the router advertises about itself and the LAN.
+#[derive(Clone)]
+struct Advertisement {
+    router_addr: Ipv6Addr,
+    mac_addr: Option<MacAddr>,
+    prefixes: Vec<Subnet>,
+    mtu: Option<u16>,
+    rdnss: Vec<Ipv6Addr>,
+    router_lifetime: Duration,
+    rdnss_lifetime: Duration,
+}
+
+impl Advertisement {
+    fn packet(&self) -> Ipv6Packet {
+        let mut message = vec![ICMPV6_ROUTER_ADVERTISEMENT, 0, 0, 0, 64, 0];
+        let router_lifetime = self.router_lifetime.as_secs().min(9000) as u16;
+        message.extend_from_slice(&router_lifetime.to_be_bytes());
+        // Reachable time and retransmission timer, left to the hosts.
+        message.extend_from_slice(&[0; 8]);
+
+        if let Some(mac_addr) = self.mac_addr {
+            message.extend_from_slice(&[OPTION_SOURCE_LINK_LAYER_ADDR, 1]);
+            message.extend_from_slice(&mac_addr.bytes);
+        }
+        if let Some(mtu) = self.mtu {
+            message.extend_from_slice(&[OPTION_MTU, 1, 0, 0]);
+            message.extend_from_slice(&u32::from(mtu).to_be_bytes());
+        }
+        for prefix in &self.prefixes {
+            let network = match prefix.address {
+                IpAddr::V6(address) => {
+                    let mask = u128::MAX
+                        .checked_shl(128 - u32::from(prefix.prefix_len))
+                        .unwrap_or(0);
+                    Ipv6Addr::from(u128::from(address) & mask)
+                }
+                IpAddr::V4(_) => continue,
+            };
+            message.extend_from_slice(&[OPTION_PREFIX_INFORMATION, 4, prefix.prefix_len]);
+            message.push(PREFIX_FLAGS_ON_LINK_AUTONOMOUS);
+            message.extend_from_slice(&PREFIX_VALID_LIFETIME.to_be_bytes());
+            message.extend_from_slice(&PREFIX_PREFERRED_LIFETIME.to_be_bytes());
+            message.extend_from_slice(&[0; 4]);
+            message.extend_from_slice(&network.octets());
+        }
+        if !self.rdnss.is_empty() {
+            let len = 1 + 2 * self.rdnss.len() as u8;
+            message.extend_from_slice(&[OPTION_RDNSS, len, 0, 0]);
+            let lifetime = self.rdnss_lifetime.as_secs() as u32;
+            message.extend_from_slice(&lifetime.to_be_bytes());
+            for server in &self.rdnss {
+                message.extend_from_slice(&server.octets());
+            }
+        }
+
+        let checksum = ipv6_pseudo_header_checksum(self.router_addr, ALL_NODES, 58, &message);
+        message[2..4].copy_from_slice(&checksum.to_be_bytes());
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(58);
+        packet.set_hop_limit(255);
+        packet.set_src_addr(self.router_addr);
+        packet.set_dest_addr(ALL_NODES);
+        packet.set_payload(&message);
+        packet
+    }
+}
+
+/// Whether a packet is a valid Router Solicitation, RFC 4861 section 6.1.1.
+fn is_router_solicitation(packet: &Ipv6Packet) -> bool {
+    let message = packet.payload();
+    packet.next_header() == IpProtocol::IPv6_ICMP
+        && packet.hop_limit() == 255
+        && message.len() >= 8
+        && message[0] == ICMPV6_ROUTER_SOLICITATION
+        && message[1] == 0
+}
+
+struct RaDaemon {
+    in_stream: PacketStream<Ipv6Packet>,
+    advertisement: Advertisement,
+    ticks: Interval,
+    started: bool,
+    last_solicited: Option<Instant>,
+}
+
+impl Unpin for RaDaemon {}
+
+impl Stream for RaDaemon {
+    type Item = Ipv6Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let daemon = &mut *self;
+        if !daemon.started {
+            daemon.started = true;
+            return Poll::Ready(Some(daemon.advertisement.packet()));
+        }
+        if Pin::new(&mut daemon.ticks).poll_next(cx).is_ready() {
+            return Poll::Ready(Some(daemon.advertisement.packet()));
+        }
+        loop {
+            match ready!(Pin::new(&mut daemon.in_stream).poll_next(cx)) {
+                None => return Poll::Ready(None),
+                Some(packet) if is_router_solicitation(&packet) => {
+                    let now = clock::now();
+                    let recently = daemon.last_solicited.is_some_and(|last| {
+                        now.saturating_duration_since(last) < MIN_DELAY_BETWEEN_RAS
+                    });
+                    if !recently {
+                        daemon.last_solicited = Some(now);
+                        return Poll::Ready(Some(daemon.advertisement.packet()));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+/// Advertises the router to the hosts of a LAN, with IPv6 Router Advertisements, so they can
+/// configure their addresses from its prefixes, and route through it, RFC 4861.
+///
+/// The composite takes the ICMPv6 messages the router receives on the LAN, and answers each Router
+/// Solicitation among them, at most one every 3 seconds, with an advertisement to all nodes.
+/// Other messages are dropped. An unsolicited advertisement is sent as soon as the composite
+/// starts, and every `interval` after, until its ingressor ends.
+///
+/// Advertisements carry each IPv6 prefix given, for hosts to configure addresses from with SLAAC,
+/// along with the MTU and DNS servers, RFC 8106, if any are given, and the router's MAC address if
+/// it is. Prefixes and MTU can be taken from the interface's section of the `RouterConfig`.
+pub struct RaDaemonComposite {
+    in_stream: Option<PacketStream<Ipv6Packet>>,
+    router_addr: Option<Ipv6Addr>,
+    mac_addr: Option<MacAddr>,
+    prefixes: Vec<Subnet>,
+    mtu: Option<u16>,
+    rdnss: Vec<Ipv6Addr>,
+    interval: Duration,
+    router_lifetime: Duration,
+}
+
+impl Default for RaDaemonComposite {
+    fn default() -> Self {
+        RaDaemonComposite::new()
+    }
+}
+
+impl RaDaemonComposite {
+    pub fn new() -> Self {
+        RaDaemonComposite {
+            in_stream: None,
+            router_addr: None,
+            mac_addr: None,
+            prefixes: vec![],
+            mtu: None,
+            rdnss: vec![],
+            interval: Duration::from_secs(200),
+            router_lifetime: Duration::from_secs(1800),
+        }
+    }
+
+    /// The router's link-local address on the LAN, which advertisements are sent from.
+    pub fn router_addr(self, router_addr: Ipv6Addr) -> Self {
+        RaDaemonComposite {
+            in_stream: self.in_stream,
+            router_addr: Some(router_addr),
+            mac_addr: self.mac_addr,
+            prefixes: self.prefixes,
+            mtu: self.mtu,
+            rdnss: self.rdnss,
+            interval: self.interval,
+            router_lifetime: self.router_lifetime,
+        }
+    }
+
+    /// The router's MAC address on the LAN, advertised so hosts needn't solicit it.
+    pub fn mac_addr(self, mac_addr: MacAddr) -> Self {
+        RaDaemonComposite {
+            in_stream: self.in_stream,
+            router_addr: self.router_addr,
+            mac_addr: Some(mac_addr),
+            prefixes: self.prefixes,
+            mtu: self.mtu,
+            rdnss: self.rdnss,
+            interval: self.interval,
+            router_lifetime: self.router_lifetime,
+        }
+    }
+
+    /// The prefixes to advertise. IPv4 subnets are left out.
+    pub fn prefixes(self, prefixes: Vec<Subnet>) -> Self {
+        RaDaemonComposite {
+            in_stream: self.in_stream,
+            router_addr: self.router_addr,
+            mac_addr: self.mac_addr,
+            prefixes,
+            mtu: self.mtu,
+            rdnss: self.rdnss,
+            interval: self.interval,
+            router_lifetime: self.router_lifetime,
+        }
+    }
+
+    pub fn mtu(self, mtu: u16) -> Self {
+        RaDaemonComposite {
+            in_stream: self.in_stream,
+            router_addr: self.router_addr,
+            mac_addr: self.mac_addr,
+            prefixes: self.prefixes,
+            mtu: Some(mtu),
+            rdnss: self.rdnss,
+            interval: self.interval,
+            router_lifetime: self.router_lifetime,
+        }
+    }
+
+    /// The DNS servers to advertise.
+    pub fn rdnss(self, rdnss: Vec<Ipv6Addr>) -> Self {
+        RaDaemonComposite {
+            in_stream: self.in_stream,
+            router_addr: self.router_addr,
+            mac_addr: self.mac_addr,
+            prefixes: self.prefixes,
+            mtu: self.mtu,
+            rdnss,
+            interval: self.interval,
+            router_lifetime: self.router_lifetime,
+        }
+    }
+
+    /// Changes interval, the time between unsolicited advertisements, default value is 200
+    /// seconds.
+    pub fn interval(self, interval: Duration) -> Self {
+        assert!(interval > Duration::from_secs(0), "interval must be > 0");
+        RaDaemonComposite {
+            in_stream: self.in_stream,
+            router_addr: self.router_addr,
+            mac_addr: self.mac_addr,
+            prefixes: self.prefixes,
+            mtu: self.mtu,
+            rdnss: self.rdnss,
+            interval,
+            router_lifetime: self.router_lifetime,
+        }
+    }
+
+    /// Changes router_lifetime, how long hosts may use the router as their default router
+    /// without hearing from it again, default value is 30 minutes. Zero advertises the prefixes
+    /// without offering the router as a default router.
+    pub fn router_lifetime(self, router_lifetime: Duration) -> Self {
+        RaDaemonComposite {
+            in_stream: self.in_stream,
+            router_addr: self.router_addr,
+            mac_addr: self.mac_addr,
+            prefixes: self.prefixes,
+            mtu: self.mtu,
+            rdnss: self.rdnss,
+            interval: self.interval,
+            router_lifetime,
+        }
+    }
+
+    /// Applies the subnets and MTU of an interface's section of a `RouterConfig`.
+    pub fn config(self, config: &InterfaceConfig) -> Self {
+        let mut composite = self.prefixes(config.subnets.clone());
+        if let Some(mtu) = config.mtu {
+            composite = composite.mtu(mtu);
+        }
+        composite
+    }
+}
+
+impl LinkBuilder<Ipv6Packet, Ipv6Packet> for RaDaemonComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv6Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "RaDaemonComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv6Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "RaDaemonComposite may only take 1 input stream",
+            ));
+        }
+        Ok(RaDaemonComposite {
+            in_stream: Some(in_stream),
+            router_addr: self.router_addr,
+            mac_addr: self.mac_addr,
+            prefixes: self.prefixes,
+            mtu: self.mtu,
+            rdnss: self.rdnss,
+            interval: self.interval,
+            router_lifetime: self.router_lifetime,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv6Packet>, LinkBuildError> {
+        match (self.in_stream, self.router_addr) {
+            (None, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None) => Err(LinkBuildError::Missing("router_addr")),
+            (Some(in_stream), Some(router_addr)) => Ok((
+                vec![],
+                vec![Box::new(RaDaemon {
+                    in_stream,
+                    advertisement: Advertisement {
+                        router_addr,
+                        mac_addr: self.mac_addr,
+                        prefixes: self.prefixes,
+                        mtu: self.mtu,
+                        rdnss: self.rdnss,
+                        router_lifetime: self.router_lifetime,
+                        // RFC 8106 section 5.1 recommends at least three times the interval.
+                        rdnss_lifetime: self.interval * 3,
+                    },
+                    ticks: interval_at(tokio::time::Instant::now() + self.interval, self.interval),
+                    started: false,
+                    last_solicited: None,
+                })],
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    const ROUTER_ADDR: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+
+    fn solicitation() -> Ipv6Packet {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(58);
+        packet.set_hop_limit(255);
+        packet.set_src_addr(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0x20));
+        packet.set_dest_addr(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2));
+        packet.set_payload(&[ICMPV6_ROUTER_SOLICITATION, 0, 0, 0, 0, 0, 0, 0]);
+        packet
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_router_addr() {
+        RaDaemonComposite::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn advertises_config() {
+        let config = InterfaceConfig {
+            name: String::from("eth0"),
+            subnets: vec![
+                "192.168.1.1/24".parse().unwrap(),
+                "fd00::1/64".parse().unwrap(),
+            ],
+            mtu: Some(1492),
+        };
+        let advertisement = Advertisement {
+            router_addr: ROUTER_ADDR,
+            mac_addr: Some(MacAddr::new([2, 0, 0, 0, 0, 1])),
+            prefixes: config.subnets,
+            mtu: config.mtu,
+            rdnss: vec![Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)],
+            router_lifetime: Duration::from_secs(1800),
+            rdnss_lifetime: Duration::from_secs(600),
+        };
+
+        let packet = advertisement.packet();
+        assert_eq!(packet.hop_limit(), 255);
+        assert_eq!(packet.src_addr(), ROUTER_ADDR);
+        assert_eq!(packet.dest_addr(), ALL_NODES);
+        let mut message = packet.payload().to_vec();
+        assert_eq!(&message[..2], &[ICMPV6_ROUTER_ADVERTISEMENT, 0]);
+        assert_eq!(&message[6..8], &1800u16.to_be_bytes());
+
+        let options = &message[16..];
+        assert_eq!(&options[..8], &[1, 1, 2, 0, 0, 0, 0, 1]);
+        assert_eq!(&options[8..16], &[5, 1, 0, 0, 0, 0, 0x05, 0xd4]);
+        assert_eq!(&options[16..20], &[3, 4, 64, 0xc0]);
+        assert_eq!(
+            &options[32..48],
+            &Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0).octets()
+        );
+        assert_eq!(&options[48..56], &[25, 3, 0, 0, 0, 0, 0x02, 0x58]);
+        assert_eq!(options.len(), 72);
+
+        let checksum = u16::from_be_bytes([message[2], message[3]]);
+        message[2..4].copy_from_slice(&[0, 0]);
+        assert_eq!(
+            ipv6_pseudo_header_checksum(ROUTER_ADDR, ALL_NODES, 58, &message),
+            checksum
+        );
+    }
+
+    #[test]
+    fn answers_solicitations() {
+        let mut not_a_solicitation = solicitation();
+        not_a_solicitation.set_hop_limit(64);
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = RaDaemonComposite::new()
+                .ingressor(immediate_stream(vec![
+                    not_a_solicitation,
+                    solicitation(),
+                    solicitation(),
+                ]))
+                .router_addr(ROUTER_ADDR)
+                .prefixes(vec!["2001:db8:1::/64".parse().unwrap()])
+                .interval(Duration::from_secs(60))
+                .build_link();
+
+            run_link(link).await
+        });
+        // One advertisement on starting, and one for the first solicitation, the second coming
+        // too soon after it.
+        assert_eq!(results[0].len(), 2);
+        assert!(results[0]
+            .iter()
+            .all(|packet| packet.payload()[0] == ICMPV6_ROUTER_ADVERTISEMENT));
+    }
+}
@@ -0,0 +1,862 @@
+use crate::link::composite::datagram::{datagram, udp};
+use crate::link::composite::Usage;
+use crate::link::event::{DropAccounting, DropCounters, DropReason};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::state::StateStore;
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{AtomicWaker, Context, Poll};
+use route_rs_packets::{Ipv4Packet, MacAddr};
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::time::{delay_until, Delay};
+
+pub const RADIUS_AUTH_PORT: u16 = 1812;
+pub const RADIUS_ACCT_PORT: u16 = 1813;
+
+/// The name the `RadiusClient` of a `RadiusClientComposite` is kept under in the `StateStore`,
+/// unless it is given one of its own.
+pub const RADIUS_CLIENT_STATE: &str = "radius-client";
+
+/// RFC 2865 section 3, RFC 2866 section 3.
+const ACCESS_REQUEST: u8 = 1;
+const ACCESS_ACCEPT: u8 = 2;
+const ACCESS_REJECT: u8 = 3;
+const ACCOUNTING_REQUEST: u8 = 4;
+const ACCOUNTING_RESPONSE: u8 = 5;
+const ACCESS_CHALLENGE: u8 = 11;
+
+/// RFC 2865 section 5, RFC 2866 section 5, RFC 2869 section 5.
+const USER_NAME: u8 = 1;
+const USER_PASSWORD: u8 = 2;
+const NAS_IP_ADDRESS: u8 = 4;
+const CALLING_STATION_ID: u8 = 31;
+const NAS_IDENTIFIER: u8 = 32;
+const ACCT_STATUS_TYPE: u8 = 40;
+const ACCT_INPUT_OCTETS: u8 = 42;
+const ACCT_SESSION_ID: u8 = 44;
+const ACCT_INPUT_PACKETS: u8 = 47;
+const ACCT_INPUT_GIGAWORDS: u8 = 52;
+
+const ACCT_STATUS_START: u32 = 1;
+const ACCT_STATUS_STOP: u32 = 2;
+
+/// The header of a RADIUS packet, code, identifier, length and authenticator.
+const HEADER_LEN: usize = 20;
+
+/// What a `RadiusClient` asks of the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RadiusRequest {
+    /// Authenticates a user by password, PAP, from the station with MAC address
+    /// `calling_station`, if known.
+    Access {
+        user: String,
+        password: Vec<u8>,
+        calling_station: Option<MacAddr>,
+    },
+    AccountingStart {
+        session_id: String,
+        user: String,
+    },
+    /// Ends a session, reporting what the user sent during it.
+    AccountingStop {
+        session_id: String,
+        user: String,
+        usage: Usage,
+    },
+}
+
+/// What came of a `RadiusRequest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RadiusReply {
+    /// Access-Accept, with its attributes, as type and value, such as Session-Timeout.
+    Accept(Vec<(u8, Vec<u8>)>),
+    /// Access-Reject, or an Access-Challenge, which a password can't answer.
+    Reject,
+    /// Accounting-Response.
+    Acknowledged,
+    /// No valid reply came, after every retransmission.
+    Timeout,
+}
+
+/// Where composites, such as those authenticating 802.1X supplicants or accounting for
+/// subscribers, send their requests to a RADIUS server, by way of the `RadiusClientComposite`
+/// talking to it.
+#[derive(Default)]
+pub struct RadiusClient {
+    queue: Mutex<VecDeque<(RadiusRequest, oneshot::Sender<RadiusReply>)>>,
+    task: AtomicWaker,
+}
+
+impl RadiusClient {
+    pub fn new() -> Self {
+        RadiusClient {
+            queue: Mutex::new(VecDeque::new()),
+            task: AtomicWaker::new(),
+        }
+    }
+
+    /// Sends a request, returning where its reply will arrive.
+    pub fn send(&self, request: RadiusRequest) -> oneshot::Receiver<RadiusReply> {
+        let (reply, received) = oneshot::channel();
+        self.queue.lock().unwrap().push_back((request, reply));
+        self.task.wake();
+        received
+    }
+
+    pub fn authenticate(&self, user: &str, password: &[u8]) -> oneshot::Receiver<RadiusReply> {
+        self.send(RadiusRequest::Access {
+            user: String::from(user),
+            password: password.to_vec(),
+            calling_station: None,
+        })
+    }
+
+    pub fn accounting_start(&self, session_id: &str, user: &str) -> oneshot::Receiver<RadiusReply> {
+        self.send(RadiusRequest::AccountingStart {
+            session_id: String::from(session_id),
+            user: String::from(user),
+        })
+    }
+
+    pub fn accounting_stop(
+        &self,
+        session_id: &str,
+        user: &str,
+        usage: Usage,
+    ) -> oneshot::Receiver<RadiusReply> {
+        self.send(RadiusRequest::AccountingStop {
+            session_id: String::from(session_id),
+            user: String::from(user),
+            usage,
+        })
+    }
+
+    /// Takes a request sent, or registers to be woken when one is.
+    fn poll_request(
+        &self,
+        cx: &mut Context,
+    ) -> Option<(RadiusRequest, oneshot::Sender<RadiusReply>)> {
+        self.task.register(cx.waker());
+        self.queue.lock().unwrap().pop_front()
+    }
+}
+
+fn push_attribute(message: &mut Vec<u8>, attribute: u8, value: &[u8]) {
+    message.push(attribute);
+    message.push((2 + value.len()) as u8);
+    message.extend_from_slice(value);
+}
+
+/// The attributes of a RADIUS packet, as type and value. A truncated attribute ends them.
+fn attributes(mut data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut attributes = vec![];
+    while data.len() >= 2 {
+        let len = usize::from(data[1]);
+        if len < 2 || data.len() < len {
+            break;
+        }
+        attributes.push((data[0], data[2..len].to_vec()));
+        data = &data[len..];
+    }
+    attributes
+}
+
+/// A User-Password hidden with the shared secret, RFC 2865 section 5.2.
+fn hide_password(password: &[u8], secret: &[u8], authenticator: &[u8; 16]) -> Vec<u8> {
+    let mut padded = password[..password.len().min(128)].to_vec();
+    let padded_len = padded.len().div_ceil(16).max(1) * 16;
+    padded.resize(padded_len, 0);
+
+    let mut hidden: Vec<u8> = Vec::with_capacity(padded_len);
+    for chunk in padded.chunks(16) {
+        let mut context = md5::Context::new();
+        context.consume(secret);
+        match hidden.len() {
+            0 => context.consume(authenticator),
+            len => context.consume(&hidden[len - 16..]),
+        }
+        let b = context.compute();
+        hidden.extend(chunk.iter().zip(b.0.iter()).map(|(p, b)| p ^ b));
+    }
+    hidden
+}
+
+/// The MD5 of a packet with `authenticator` in place of its own, followed by the secret, which is
+/// the Response Authenticator of a reply, RFC 2865 section 3, and the Request Authenticator of an
+/// Accounting-Request, with a zero one, RFC 2866 section 3.
+fn authenticator(packet: &[u8], authenticator: &[u8; 16], secret: &[u8]) -> [u8; 16] {
+    let mut context = md5::Context::new();
+    context.consume(&packet[..4]);
+    context.consume(authenticator);
+    context.consume(&packet[HEADER_LEN..]);
+    context.consume(secret);
+    context.compute().0
+}
+
+/// A request sent to the server, and not yet answered.
+struct Outstanding {
+    packet: Ipv4Packet,
+    authenticator: [u8; 16],
+    reply: oneshot::Sender<RadiusReply>,
+    next_send: Instant,
+    attempts: u32,
+}
+
+/// Sends the requests of a `RadiusClient` to the server, retransmitting each every `timeout` up to
+/// `max_retries` times, and matches the replies that come back to them by identifier and
+/// authenticator.
+struct RadiusTransport {
+    reply_stream: PacketStream<Ipv4Packet>,
+    client: Arc<RadiusClient>,
+    nas: SocketAddrV4,
+    server: Ipv4Addr,
+    auth_port: u16,
+    acct_port: u16,
+    secret: Vec<u8>,
+    nas_identifier: Option<String>,
+    timeout: Duration,
+    max_retries: u32,
+    next_identifier: u8,
+    outstanding: HashMap<u8, Outstanding>,
+    backlog: VecDeque<(RadiusRequest, oneshot::Sender<RadiusReply>)>,
+    outbox: VecDeque<Ipv4Packet>,
+    timer: Delay,
+    drops: DropAccounting,
+}
+
+impl RadiusTransport {
+    /// An identifier no outstanding request has, if there is one.
+    fn identifier(&mut self) -> Option<u8> {
+        if self.outstanding.len() > usize::from(u8::MAX) {
+            return None;
+        }
+        while self.outstanding.contains_key(&self.next_identifier) {
+            self.next_identifier = self.next_identifier.wrapping_add(1);
+        }
+        let identifier = self.next_identifier;
+        self.next_identifier = self.next_identifier.wrapping_add(1);
+        Some(identifier)
+    }
+
+    /// The datagram of a request, and its Request Authenticator.
+    fn encode(&self, identifier: u8, request: &RadiusRequest) -> (Ipv4Packet, [u8; 16]) {
+        let mut message = vec![0; HEADER_LEN];
+        message[1] = identifier;
+        let (code, port, request_authenticator) = match request {
+            RadiusRequest::Access {
+                user,
+                password,
+                calling_station,
+            } => {
+                let request_authenticator: [u8; 16] = rand::random();
+                push_attribute(&mut message, USER_NAME, user.as_bytes());
+                let hidden = hide_password(password, &self.secret, &request_authenticator);
+                push_attribute(&mut message, USER_PASSWORD, &hidden);
+                if let Some(mac) = calling_station {
+                    // RFC 3580 section 3.21.
+                    let station = mac
+                        .bytes
+                        .iter()
+                        .map(|b| format!("{:02X}", b))
+                        .collect::<Vec<_>>()
+                        .join("-");
+                    push_attribute(&mut message, CALLING_STATION_ID, station.as_bytes());
+                }
+                (ACCESS_REQUEST, self.auth_port, Some(request_authenticator))
+            }
+            RadiusRequest::AccountingStart { session_id, user } => {
+                push_attribute(&mut message, USER_NAME, user.as_bytes());
+                push_attribute(&mut message, ACCT_SESSION_ID, session_id.as_bytes());
+                let status = ACCT_STATUS_START.to_be_bytes();
+                push_attribute(&mut message, ACCT_STATUS_TYPE, &status);
+                (ACCOUNTING_REQUEST, self.acct_port, None)
+            }
+            RadiusRequest::AccountingStop {
+                session_id,
+                user,
+                usage,
+            } => {
+                push_attribute(&mut message, USER_NAME, user.as_bytes());
+                push_attribute(&mut message, ACCT_SESSION_ID, session_id.as_bytes());
+                let status = ACCT_STATUS_STOP.to_be_bytes();
+                push_attribute(&mut message, ACCT_STATUS_TYPE, &status);
+                let octets = (usage.bytes as u32).to_be_bytes();
+                push_attribute(&mut message, ACCT_INPUT_OCTETS, &octets);
+                let gigawords = ((usage.bytes >> 32) as u32).to_be_bytes();
+                push_attribute(&mut message, ACCT_INPUT_GIGAWORDS, &gigawords);
+                let packets = (usage.packets.min(u64::from(u32::MAX)) as u32).to_be_bytes();
+                push_attribute(&mut message, ACCT_INPUT_PACKETS, &packets);
+                (ACCOUNTING_REQUEST, self.acct_port, None)
+            }
+        };
+        push_attribute(&mut message, NAS_IP_ADDRESS, &self.nas.ip().octets());
+        if let Some(nas_identifier) = &self.nas_identifier {
+            push_attribute(&mut message, NAS_IDENTIFIER, nas_identifier.as_bytes());
+        }
+        message[0] = code;
+        let len = message.len() as u16;
+        message[2..4].copy_from_slice(&len.to_be_bytes());
+        let request_authenticator = request_authenticator
+            .unwrap_or_else(|| authenticator(&message, &[0; 16], &self.secret));
+        message[4..HEADER_LEN].copy_from_slice(&request_authenticator);
+
+        let packet = datagram(self.nas, SocketAddrV4::new(self.server, port), &message);
+        (packet, request_authenticator)
+    }
+
+    /// Sends a request, unless every identifier is taken, when it waits for one to be free.
+    fn send(&mut self, request: RadiusRequest, reply: oneshot::Sender<RadiusReply>, now: Instant) {
+        let identifier = match self.identifier() {
+            Some(identifier) => identifier,
+            None => {
+                self.backlog.push_back((request, reply));
+                return;
+            }
+        };
+        let (packet, authenticator) = self.encode(identifier, &request);
+        self.outbox.push_back(packet.clone());
+        self.outstanding.insert(
+            identifier,
+            Outstanding {
+                packet,
+                authenticator,
+                reply,
+                next_send: now + self.timeout,
+                attempts: 1,
+            },
+        );
+    }
+
+    /// Handles a packet from the server. Anything but a valid reply to an outstanding request is
+    /// dropped, RFC 2865 section 3.
+    fn receive(&mut self, packet: &Ipv4Packet) {
+        if packet.src_addr() != self.server || packet.dest_addr() != *self.nas.ip() {
+            return self.drops.dropped(DropReason::Discarded);
+        }
+        let (src_port, dest_port, message) = match udp(packet) {
+            Some(datagram) => datagram,
+            None => return self.drops.dropped(DropReason::Unclassified),
+        };
+        if (src_port != self.auth_port && src_port != self.acct_port)
+            || dest_port != self.nas.port()
+        {
+            return self.drops.dropped(DropReason::Unclassified);
+        }
+        if message.len() < HEADER_LEN {
+            return self.drops.dropped(DropReason::Malformed);
+        }
+        let len = usize::from(u16::from_be_bytes([message[2], message[3]]));
+        if len < HEADER_LEN || message.len() < len {
+            return self.drops.dropped(DropReason::Malformed);
+        }
+        let message = &message[..len];
+        let outstanding = match self.outstanding.get(&message[1]) {
+            Some(outstanding) => outstanding,
+            // A reply to a request that has timed out, or was never sent.
+            None => return self.drops.dropped(DropReason::Discarded),
+        };
+        if authenticator(message, &outstanding.authenticator, &self.secret)[..]
+            != message[4..HEADER_LEN]
+        {
+            return self.drops.dropped(DropReason::Unauthenticated);
+        }
+        let reply = match message[0] {
+            ACCESS_ACCEPT => RadiusReply::Accept(attributes(&message[HEADER_LEN..])),
+            ACCESS_REJECT | ACCESS_CHALLENGE => RadiusReply::Reject,
+            ACCOUNTING_RESPONSE => RadiusReply::Acknowledged,
+            _ => return self.drops.dropped(DropReason::Unclassified),
+        };
+        if let Some(outstanding) = self.outstanding.remove(&message[1]) {
+            outstanding.reply.send(reply).ok();
+        }
+    }
+
+    /// Retransmits the requests due, and gives up on those retransmitted `max_retries` times.
+    fn poll_timers(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let max_retries = self.max_retries;
+        let mut expired = vec![];
+        for (identifier, outstanding) in self.outstanding.iter_mut() {
+            if now < outstanding.next_send {
+                continue;
+            }
+            if outstanding.attempts > max_retries {
+                expired.push(*identifier);
+                continue;
+            }
+            outstanding.attempts += 1;
+            outstanding.next_send = now + timeout;
+            self.outbox.push_back(outstanding.packet.clone());
+        }
+        for identifier in expired {
+            if let Some(outstanding) = self.outstanding.remove(&identifier) {
+                outstanding.reply.send(RadiusReply::Timeout).ok();
+            }
+        }
+    }
+}
+
+impl Unpin for RadiusTransport {}
+
+impl Stream for RadiusTransport {
+    type Item = Ipv4Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let transport = &mut *self;
+        loop {
+            if let Some(packet) = transport.outbox.pop_front() {
+                return Poll::Ready(Some(packet));
+            }
+            loop {
+                match Pin::new(&mut transport.reply_stream).poll_next(cx) {
+                    Poll::Ready(Some(reply)) => transport.receive(&reply),
+                    Poll::Ready(None) => return Poll::Ready(None),
+                    Poll::Pending => break,
+                }
+            }
+            let now = clock::now();
+            transport.poll_timers(now);
+            while transport.outstanding.len() <= usize::from(u8::MAX) {
+                let request = match transport.backlog.pop_front() {
+                    Some(request) => request,
+                    None => match transport.client.poll_request(cx) {
+                        Some(request) => request,
+                        None => break,
+                    },
+                };
+                transport.send(request.0, request.1, now);
+            }
+            if !transport.outbox.is_empty() {
+                continue;
+            }
+
+            let deadline = match transport.outstanding.values().map(|o| o.next_send).min() {
+                Some(deadline) => deadline,
+                None => return Poll::Pending,
+            };
+            transport
+                .timer
+                .reset(tokio::time::Instant::from_std(deadline));
+            if Pin::new(&mut transport.timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// The client side of RADIUS, RFC 2865 and RFC 2866, through which other composites authenticate
+/// users and report what they use, by sending `RadiusRequest`s to a `RadiusClient`, and awaiting
+/// the replies.
+///
+/// The composite's ingressor takes the packets the server sends the router, and its egressor the
+/// requests the router sends the server, from the `nas` address, to the `server`'s
+/// authentication and accounting ports. Requests are retransmitted every `timeout` until a reply
+/// with the right authenticator comes, up to `max_retries` times, after which they time out. Up
+/// to 256 requests are outstanding at once, one for each identifier, and any more wait. Packets
+/// that aren't a valid reply to an outstanding request are dropped, and counted by reason in the
+/// composite's `drop_counters`, if it has them.
+///
+/// Passwords are sent as PAP, hidden with the shared `secret`; EAP, which needs the
+/// Message-Authenticator of RFC 3579, is not supported yet.
+pub struct RadiusClientComposite {
+    reply_stream: Option<PacketStream<Ipv4Packet>>,
+    client: Option<Arc<RadiusClient>>,
+    nas: Option<SocketAddrV4>,
+    server: Option<Ipv4Addr>,
+    auth_port: u16,
+    acct_port: u16,
+    secret: Option<Vec<u8>>,
+    nas_identifier: Option<String>,
+    timeout: Duration,
+    max_retries: u32,
+    drop_counters: Option<Arc<DropCounters>>,
+}
+
+impl Default for RadiusClientComposite {
+    fn default() -> Self {
+        RadiusClientComposite::new()
+    }
+}
+
+impl RadiusClientComposite {
+    pub fn new() -> Self {
+        RadiusClientComposite {
+            reply_stream: None,
+            client: None,
+            nas: None,
+            server: None,
+            auth_port: RADIUS_AUTH_PORT,
+            acct_port: RADIUS_ACCT_PORT,
+            secret: None,
+            nas_identifier: None,
+            timeout: Duration::from_secs(3),
+            max_retries: 3,
+            drop_counters: None,
+        }
+    }
+
+    /// Takes the requests of `client`, rather than the one under `RADIUS_CLIENT_STATE` in the
+    /// global `StateStore`.
+    pub fn client(self, client: Arc<RadiusClient>) -> Self {
+        RadiusClientComposite {
+            reply_stream: self.reply_stream,
+            client: Some(client),
+            nas: self.nas,
+            server: self.server,
+            auth_port: self.auth_port,
+            acct_port: self.acct_port,
+            secret: self.secret,
+            nas_identifier: self.nas_identifier,
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// The address and port the router sends requests from, which the server knows it by.
+    pub fn nas(self, nas: SocketAddrV4) -> Self {
+        RadiusClientComposite {
+            reply_stream: self.reply_stream,
+            client: self.client,
+            nas: Some(nas),
+            server: self.server,
+            auth_port: self.auth_port,
+            acct_port: self.acct_port,
+            secret: self.secret,
+            nas_identifier: self.nas_identifier,
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    pub fn server(self, server: Ipv4Addr) -> Self {
+        RadiusClientComposite {
+            reply_stream: self.reply_stream,
+            client: self.client,
+            nas: self.nas,
+            server: Some(server),
+            auth_port: self.auth_port,
+            acct_port: self.acct_port,
+            secret: self.secret,
+            nas_identifier: self.nas_identifier,
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes the server's ports, default values are 1812 for authentication, and 1813 for
+    /// accounting.
+    pub fn ports(self, auth_port: u16, acct_port: u16) -> Self {
+        RadiusClientComposite {
+            reply_stream: self.reply_stream,
+            client: self.client,
+            nas: self.nas,
+            server: self.server,
+            auth_port,
+            acct_port,
+            secret: self.secret,
+            nas_identifier: self.nas_identifier,
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// The secret the router shares with the server.
+    pub fn secret(self, secret: &[u8]) -> Self {
+        assert!(!secret.is_empty(), "secret must not be empty");
+        RadiusClientComposite {
+            reply_stream: self.reply_stream,
+            client: self.client,
+            nas: self.nas,
+            server: self.server,
+            auth_port: self.auth_port,
+            acct_port: self.acct_port,
+            secret: Some(secret.to_vec()),
+            nas_identifier: self.nas_identifier,
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// The name the router gives the server in each request, as well as its address.
+    pub fn nas_identifier(self, nas_identifier: &str) -> Self {
+        RadiusClientComposite {
+            reply_stream: self.reply_stream,
+            client: self.client,
+            nas: self.nas,
+            server: self.server,
+            auth_port: self.auth_port,
+            acct_port: self.acct_port,
+            secret: self.secret,
+            nas_identifier: Some(String::from(nas_identifier)),
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes timeout, default value is 3 seconds.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        assert!(timeout > Duration::from_secs(0), "timeout must be > 0");
+        RadiusClientComposite {
+            reply_stream: self.reply_stream,
+            client: self.client,
+            nas: self.nas,
+            server: self.server,
+            auth_port: self.auth_port,
+            acct_port: self.acct_port,
+            secret: self.secret,
+            nas_identifier: self.nas_identifier,
+            timeout,
+            max_retries: self.max_retries,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes max_retries, default value is 3.
+    pub fn max_retries(self, max_retries: u32) -> Self {
+        RadiusClientComposite {
+            reply_stream: self.reply_stream,
+            client: self.client,
+            nas: self.nas,
+            server: self.server,
+            auth_port: self.auth_port,
+            acct_port: self.acct_port,
+            secret: self.secret,
+            nas_identifier: self.nas_identifier,
+            timeout: self.timeout,
+            max_retries,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Counts the packets from the server the composite drops in `drop_counters`, by reason.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        RadiusClientComposite {
+            reply_stream: self.reply_stream,
+            client: self.client,
+            nas: self.nas,
+            server: self.server,
+            auth_port: self.auth_port,
+            acct_port: self.acct_port,
+            secret: self.secret,
+            nas_identifier: self.nas_identifier,
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            drop_counters: Some(drop_counters),
+        }
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for RadiusClientComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "RadiusClientComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        if self.reply_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "RadiusClientComposite may only take 1 input stream",
+            ));
+        }
+        Ok(RadiusClientComposite {
+            reply_stream: Some(in_stream),
+            client: self.client,
+            nas: self.nas,
+            server: self.server,
+            auth_port: self.auth_port,
+            acct_port: self.acct_port,
+            secret: self.secret,
+            nas_identifier: self.nas_identifier,
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            drop_counters: self.drop_counters,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        match (self.reply_stream, self.nas, self.server, self.secret) {
+            (None, _, _, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None, _, _) => Err(LinkBuildError::Missing("nas")),
+            (_, _, None, _) => Err(LinkBuildError::Missing("server")),
+            (_, _, _, None) => Err(LinkBuildError::Missing("secret")),
+            (Some(reply_stream), Some(nas), Some(server), Some(secret)) => {
+                let client = self.client.unwrap_or_else(|| {
+                    StateStore::global().get_or_insert_with(RADIUS_CLIENT_STATE, RadiusClient::new)
+                });
+                let transport = RadiusTransport {
+                    reply_stream,
+                    client,
+                    nas,
+                    server,
+                    auth_port: self.auth_port,
+                    acct_port: self.acct_port,
+                    secret,
+                    nas_identifier: self.nas_identifier,
+                    timeout: self.timeout,
+                    max_retries: self.max_retries,
+                    next_identifier: rand::random(),
+                    outstanding: HashMap::new(),
+                    backlog: VecDeque::new(),
+                    outbox: VecDeque::new(),
+                    timer: delay_until(tokio::time::Instant::from_std(clock::now())),
+                    drops: DropAccounting::new(self.drop_counters, None),
+                };
+                Ok((vec![], vec![Box::new(transport)]))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::initialize_runtime;
+
+    const NAS: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 50000);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 10);
+    const SECRET: &[u8] = b"xyzzy5461";
+
+    fn transport(client: Arc<RadiusClient>) -> RadiusTransport {
+        RadiusTransport {
+            reply_stream: Box::new(stream::pending()),
+            client,
+            nas: NAS,
+            server: SERVER,
+            auth_port: RADIUS_AUTH_PORT,
+            acct_port: RADIUS_ACCT_PORT,
+            secret: SECRET.to_vec(),
+            nas_identifier: None,
+            timeout: Duration::from_secs(3),
+            max_retries: 1,
+            next_identifier: 0,
+            outstanding: HashMap::new(),
+            backlog: VecDeque::new(),
+            outbox: VecDeque::new(),
+            timer: delay_until(tokio::time::Instant::now()),
+            drops: DropAccounting::default(),
+        }
+    }
+
+    /// The server's reply, of `code`, to the request the router sent in `request`.
+    fn reply(request: &Ipv4Packet, code: u8, attrs: &[u8]) -> Ipv4Packet {
+        let (_, port, message) = udp(request).unwrap();
+        let mut reply = vec![code, message[1], 0, 0];
+        reply.extend_from_slice(&message[4..HEADER_LEN]);
+        reply.extend_from_slice(attrs);
+        let len = reply.len() as u16;
+        reply[2..4].copy_from_slice(&len.to_be_bytes());
+        let mut request_authenticator = [0; 16];
+        request_authenticator.copy_from_slice(&message[4..HEADER_LEN]);
+        let response_authenticator = authenticator(&reply, &request_authenticator, SECRET);
+        reply[4..HEADER_LEN].copy_from_slice(&response_authenticator);
+        datagram(SocketAddrV4::new(SERVER, port), NAS, &reply)
+    }
+
+    #[test]
+    fn hides_passwords() {
+        // RFC 2865 section 7.1.
+        let authenticator = [
+            0x0f, 0x40, 0x3f, 0x94, 0x73, 0x97, 0x80, 0x57, 0xbd, 0x83, 0xd5, 0xcb, 0x98, 0xf4,
+            0x22, 0x7a,
+        ];
+        let hidden = hide_password(b"arctangent", SECRET, &authenticator);
+        assert_eq!(
+            hidden,
+            vec![
+                0x0d, 0xbe, 0x70, 0x8d, 0x93, 0xd4, 0x13, 0xce, 0x31, 0x96, 0xe4, 0x3f, 0x78, 0x2a,
+                0x0a, 0xee
+            ]
+        );
+    }
+
+    #[test]
+    fn authenticates_and_accounts() {
+        let client = Arc::new(RadiusClient::new());
+        let runtime = initialize_runtime();
+        let mut transport = runtime.enter(|| transport(Arc::clone(&client)));
+        let drop_counters = Arc::new(DropCounters::new());
+        transport.drops = DropAccounting::new(Some(Arc::clone(&drop_counters)), None);
+        let now = clock::now();
+        let mut accepted = client.authenticate("nemo", b"arctangent");
+        let mut acknowledged = client.accounting_stop(
+            "session-1",
+            "nemo",
+            Usage {
+                packets: 3,
+                bytes: (1 << 32) + 5,
+            },
+        );
+        while let Some((request, reply)) = client.queue.lock().unwrap().pop_front() {
+            transport.send(request, reply, now);
+        }
+        let access = transport.outbox.pop_front().unwrap();
+        let accounting = transport.outbox.pop_front().unwrap();
+
+        let (_, port, message) = udp(&accounting).unwrap();
+        assert_eq!(port, RADIUS_ACCT_PORT);
+        assert_eq!(
+            authenticator(&message, &[0; 16], SECRET)[..],
+            message[4..HEADER_LEN]
+        );
+        let attrs = attributes(&message[HEADER_LEN..]);
+        assert!(attrs.contains(&(ACCT_INPUT_OCTETS, vec![0, 0, 0, 5])));
+        assert!(attrs.contains(&(ACCT_INPUT_GIGAWORDS, vec![0, 0, 0, 1])));
+
+        // A reply signed with the wrong secret is dropped.
+        let mut forged = reply(&access, ACCESS_ACCEPT, &[]);
+        let mut data = forged.payload().to_vec();
+        data[12] ^= 0xff;
+        forged.set_payload(&data);
+        transport.receive(&forged);
+        assert_eq!(transport.outstanding.len(), 2);
+        assert_eq!(
+            drop_counters.snapshot(),
+            vec![(DropReason::Unauthenticated, 1)]
+        );
+
+        let session_timeout = [27, 6, 0, 0, 0x0e, 0x10];
+        transport.receive(&reply(&access, ACCESS_ACCEPT, &session_timeout));
+        transport.receive(&reply(&accounting, ACCOUNTING_RESPONSE, &[]));
+        assert!(transport.outstanding.is_empty());
+        assert_eq!(
+            accepted.try_recv().unwrap(),
+            RadiusReply::Accept(vec![(27, vec![0, 0, 0x0e, 0x10])])
+        );
+        assert_eq!(acknowledged.try_recv().unwrap(), RadiusReply::Acknowledged);
+    }
+
+    #[test]
+    fn retransmits_then_times_out() {
+        let client = Arc::new(RadiusClient::new());
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let mut transport = transport(Arc::clone(&client));
+            transport.timeout = Duration::from_millis(10);
+            let rejected = client.authenticate("nemo", b"wrong");
+
+            let first = transport.next().await.unwrap();
+            let retransmitted = transport.next().await.unwrap();
+            assert_eq!(first.payload(), retransmitted.payload());
+            tokio::spawn(async move { while transport.next().await.is_some() {} });
+            assert_eq!(rejected.await.unwrap(), RadiusReply::Timeout);
+        });
+    }
+}
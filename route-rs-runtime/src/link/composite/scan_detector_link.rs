@@ -0,0 +1,517 @@
+use crate::link::event::{DropAccounting, DropCounters, DropReason, EventSink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::state::StateStore;
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::ready;
+use futures::task::{Context, Poll};
+use route_rs_packets::{IpProtocol, Ipv4Packet};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::net::Ipv4Addr;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// The name the block list is shared under in the `StateStore`, unless the link is given its own
+/// `BlockList`.
+pub const BLOCK_LIST_STATE: &str = "block-list";
+
+/// Sources whose traffic is dropped until a time, shared by whatever decides to block them and
+/// whatever drops their packets.
+#[derive(Default)]
+pub struct BlockList {
+    blocked: RwLock<HashMap<Ipv4Addr, Instant>>,
+}
+
+impl BlockList {
+    pub fn new() -> Self {
+        BlockList {
+            blocked: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Blocks `addr` until `until`, or later if it is already blocked until later.
+    pub fn block(&self, addr: Ipv4Addr, until: Instant) {
+        let mut blocked = self.blocked.write().unwrap();
+        let entry = blocked.entry(addr).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+
+    pub fn unblock(&self, addr: Ipv4Addr) {
+        self.blocked.write().unwrap().remove(&addr);
+    }
+
+    pub fn is_blocked(&self, addr: Ipv4Addr, now: Instant) -> bool {
+        self.blocked
+            .read()
+            .unwrap()
+            .get(&addr)
+            .is_some_and(|until| now < *until)
+    }
+
+    /// The sources blocked at `now`, with when each is unblocked, forgetting those no longer
+    /// blocked.
+    pub fn blocked(&self, now: Instant) -> Vec<(Ipv4Addr, Instant)> {
+        let mut blocked = self.blocked.write().unwrap();
+        blocked.retain(|_, until| now < *until);
+        blocked
+            .iter()
+            .map(|(addr, until)| (*addr, *until))
+            .collect()
+    }
+}
+
+/// The distinct keys seen within a sliding window, with when each was last seen.
+struct DistinctWindow<K> {
+    last_seen: HashMap<K, Instant>,
+    /// Each sighting, in order, to expire keys not seen since.
+    seen: VecDeque<(Instant, K)>,
+}
+
+impl<K: Copy + Eq + Hash> DistinctWindow<K> {
+    fn new() -> Self {
+        DistinctWindow {
+            last_seen: HashMap::new(),
+            seen: VecDeque::new(),
+        }
+    }
+
+    /// Counts `key` as seen at `now`, returning how many distinct keys have been.
+    fn insert(&mut self, key: K, now: Instant) -> usize {
+        if self.last_seen.insert(key, now) != Some(now) {
+            self.seen.push_back((now, key));
+        }
+        self.last_seen.len()
+    }
+
+    /// Forgets the keys not seen since `since`.
+    fn expire(&mut self, since: Instant) {
+        while let Some((seen, key)) = self.seen.front().copied() {
+            if seen >= since {
+                break;
+            }
+            self.seen.pop_front();
+            if self.last_seen.get(&key) == Some(&seen) {
+                self.last_seen.remove(&key);
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.last_seen.is_empty()
+    }
+}
+
+/// What one source has sent to lately.
+struct Activity {
+    ports: DistinctWindow<(Ipv4Addr, u16)>,
+    hosts: DistinctWindow<Ipv4Addr>,
+    /// The end of the window the source was last reported in, before which it isn't again.
+    alerted_until: Option<Instant>,
+}
+
+struct ScanDetector {
+    in_stream: PacketStream<Ipv4Packet>,
+    window: Duration,
+    max_ports: usize,
+    max_hosts: usize,
+    block_for: Option<Duration>,
+    block_list: Arc<BlockList>,
+    sources: HashMap<Ipv4Addr, Activity>,
+    drops: DropAccounting,
+    event_sink: Option<EventSink>,
+}
+
+impl ScanDetector {
+    /// Counts the destination of `packet` against its source, returning what the source has been
+    /// caught at, if it has just crossed a threshold.
+    fn observe(&mut self, packet: &Ipv4Packet, now: Instant) -> Option<String> {
+        let since = now.checked_sub(self.window).unwrap_or(now);
+        // Forgets quiet sources once there are enough to be worth sweeping.
+        if self.sources.len() >= 4096 {
+            self.sources.retain(|_, activity| {
+                activity.ports.expire(since);
+                activity.hosts.expire(since);
+                !(activity.ports.is_empty() && activity.hosts.is_empty())
+            });
+        }
+
+        let activity = self
+            .sources
+            .entry(packet.src_addr())
+            .or_insert_with(|| Activity {
+                ports: DistinctWindow::new(),
+                hosts: DistinctWindow::new(),
+                alerted_until: None,
+            });
+        activity.ports.expire(since);
+        activity.hosts.expire(since);
+        let dest_addr = packet.dest_addr();
+        let hosts = activity.hosts.insert(dest_addr, now);
+        let payload = packet.payload();
+        let ports = match packet.protocol() {
+            IpProtocol::TCP | IpProtocol::UDP if payload.len() >= 4 => {
+                let port = u16::from_be_bytes([payload[2], payload[3]]);
+                activity.ports.insert((dest_addr, port), now)
+            }
+            _ => activity.ports.last_seen.len(),
+        };
+
+        if activity.alerted_until.is_some_and(|until| now < until) {
+            return None;
+        }
+        let scan = if ports > self.max_ports {
+            format!("{} ports", ports)
+        } else if hosts > self.max_hosts {
+            format!("{} hosts", hosts)
+        } else {
+            return None;
+        };
+        activity.alerted_until = Some(now + self.window);
+        Some(scan)
+    }
+}
+
+impl Unpin for ScanDetector {}
+
+impl Stream for ScanDetector {
+    type Item = Ipv4Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let detector = &mut *self;
+        loop {
+            let packet = match ready!(Pin::new(&mut detector.in_stream).poll_next(cx)) {
+                Some(packet) => packet,
+                None => return Poll::Ready(None),
+            };
+            let now = clock::now();
+            let src_addr = packet.src_addr();
+            if detector.block_list.is_blocked(src_addr, now) {
+                detector.drops.dropped(DropReason::Blocked);
+                continue;
+            }
+
+            if let Some(scan) = detector.observe(&packet, now) {
+                let window = detector.window.as_secs();
+                let alert = match detector.block_for {
+                    Some(block_for) => {
+                        detector.block_list.block(src_addr, now + block_for);
+                        format!(
+                            "{} scanned {} in {}s, blocked for {}s",
+                            src_addr,
+                            scan,
+                            window,
+                            block_for.as_secs()
+                        )
+                    }
+                    None => format!("{} scanned {} in {}s", src_addr, scan, window),
+                };
+                if let Some(event_sink) = &detector.event_sink {
+                    event_sink.alert(alert);
+                }
+            }
+            return Poll::Ready(Some(packet));
+        }
+    }
+}
+
+/// Watches for hosts scanning the network: sources that send to more than `max_ports` distinct
+/// ports, counting each port of each host, or more than `max_hosts` distinct hosts within a
+/// sliding `window`. Packets pass through the link unchanged.
+///
+/// A source caught scanning is reported as a `LinkEvent::Alert` on the link's `event_sink`, once
+/// a window. With `block_for`, it is also blocked in a `BlockList` for that long, shared in the
+/// `StateStore` under `BLOCK_LIST_STATE` unless one is given, and the link drops its packets until
+/// then, as it does those of any other source in the list, counting them in its `drop_counters`,
+/// if it has them.
+pub struct ScanDetectorLink {
+    in_stream: Option<PacketStream<Ipv4Packet>>,
+    window: Duration,
+    max_ports: usize,
+    max_hosts: usize,
+    block_for: Option<Duration>,
+    block_list: Option<Arc<BlockList>>,
+    event_sink: Option<EventSink>,
+    drop_counters: Option<Arc<DropCounters>>,
+}
+
+impl Default for ScanDetectorLink {
+    fn default() -> Self {
+        ScanDetectorLink::new()
+    }
+}
+
+impl ScanDetectorLink {
+    pub fn new() -> Self {
+        ScanDetectorLink {
+            in_stream: None,
+            window: Duration::from_secs(60),
+            max_ports: 100,
+            max_hosts: 50,
+            block_for: None,
+            block_list: None,
+            event_sink: None,
+            drop_counters: None,
+        }
+    }
+
+    /// Changes window, how far back destinations are counted, default value is a minute.
+    pub fn window(self, window: Duration) -> Self {
+        assert!(window > Duration::from_secs(0), "window must be > 0");
+        ScanDetectorLink {
+            in_stream: self.in_stream,
+            window,
+            max_ports: self.max_ports,
+            max_hosts: self.max_hosts,
+            block_for: self.block_for,
+            block_list: self.block_list,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes max_ports, the distinct ports a source may send to within the window, default
+    /// value is 100.
+    pub fn max_ports(self, max_ports: usize) -> Self {
+        ScanDetectorLink {
+            in_stream: self.in_stream,
+            window: self.window,
+            max_ports,
+            max_hosts: self.max_hosts,
+            block_for: self.block_for,
+            block_list: self.block_list,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes max_hosts, the distinct hosts a source may send to within the window, default
+    /// value is 50.
+    pub fn max_hosts(self, max_hosts: usize) -> Self {
+        ScanDetectorLink {
+            in_stream: self.in_stream,
+            window: self.window,
+            max_ports: self.max_ports,
+            max_hosts,
+            block_for: self.block_for,
+            block_list: self.block_list,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Blocks sources caught scanning for `block_for`. By default, they are only reported.
+    pub fn block_for(self, block_for: Duration) -> Self {
+        ScanDetectorLink {
+            in_stream: self.in_stream,
+            window: self.window,
+            max_ports: self.max_ports,
+            max_hosts: self.max_hosts,
+            block_for: Some(block_for),
+            block_list: self.block_list,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Blocks sources here rather than in the `StateStore`.
+    pub fn block_list(self, block_list: Arc<BlockList>) -> Self {
+        ScanDetectorLink {
+            in_stream: self.in_stream,
+            window: self.window,
+            max_ports: self.max_ports,
+            max_hosts: self.max_hosts,
+            block_for: self.block_for,
+            block_list: Some(block_list),
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Reports sources caught scanning, and the packets the link drops, to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        ScanDetectorLink {
+            in_stream: self.in_stream,
+            window: self.window,
+            max_ports: self.max_ports,
+            max_hosts: self.max_hosts,
+            block_for: self.block_for,
+            block_list: self.block_list,
+            event_sink: Some(event_sink),
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Counts the packets the link drops in `drop_counters`, by reason.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        ScanDetectorLink {
+            in_stream: self.in_stream,
+            window: self.window,
+            max_ports: self.max_ports,
+            max_hosts: self.max_hosts,
+            block_for: self.block_for,
+            block_list: self.block_list,
+            event_sink: self.event_sink,
+            drop_counters: Some(drop_counters),
+        }
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for ScanDetectorLink {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "ScanDetectorLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "ScanDetectorLink may only take 1 input stream",
+            ));
+        }
+        Ok(ScanDetectorLink {
+            in_stream: Some(in_stream),
+            window: self.window,
+            max_ports: self.max_ports,
+            max_hosts: self.max_hosts,
+            block_for: self.block_for,
+            block_list: self.block_list,
+            event_sink: self.event_sink,
+            drop_counters: self.drop_counters,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        let in_stream = self
+            .in_stream
+            .ok_or(LinkBuildError::Missing("input stream"))?;
+        let block_list = self.block_list.unwrap_or_else(|| {
+            StateStore::global().get_or_insert_with(BLOCK_LIST_STATE, BlockList::new)
+        });
+        Ok((
+            vec![],
+            vec![Box::new(ScanDetector {
+                in_stream,
+                window: self.window,
+                max_ports: self.max_ports,
+                max_hosts: self.max_hosts,
+                block_for: self.block_for,
+                block_list,
+                sources: HashMap::new(),
+                drops: DropAccounting::new(self.drop_counters, self.event_sink.clone()),
+                event_sink: self.event_sink,
+            })],
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::event::LinkEvent;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use crossbeam::crossbeam_channel;
+
+    const SCANNER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 66);
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 10);
+
+    fn tcp(src_addr: Ipv4Addr, dest_addr: Ipv4Addr, dest_port: u16) -> Ipv4Packet {
+        let mut segment = vec![0; 20];
+        segment[0..2].copy_from_slice(&40000u16.to_be_bytes());
+        segment[2..4].copy_from_slice(&dest_port.to_be_bytes());
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(6);
+        packet.set_src_addr(src_addr);
+        packet.set_dest_addr(dest_addr);
+        packet.set_payload(&segment);
+        packet
+    }
+
+    #[test]
+    fn distinct_window_expires_old_keys() {
+        let start = Instant::now();
+        let mut window = DistinctWindow::new();
+        assert_eq!(window.insert(1, start), 1);
+        assert_eq!(window.insert(2, start + Duration::from_secs(1)), 2);
+        assert_eq!(window.insert(1, start + Duration::from_secs(2)), 2);
+
+        // 1 was seen again since, so only 2 is forgotten.
+        window.expire(start + Duration::from_millis(1500));
+        assert_eq!(window.last_seen.len(), 1);
+        window.expire(start + Duration::from_secs(3));
+        assert!(window.is_empty());
+    }
+
+    #[test]
+    fn reports_and_blocks_port_scans() {
+        let mut packets: Vec<Ipv4Packet> = (1..=5).map(|port| tcp(SCANNER, SERVER, port)).collect();
+        packets.push(tcp(CLIENT, SERVER, 443));
+        packets.push(tcp(SCANNER, SERVER, 443));
+        let block_list = Arc::new(BlockList::new());
+        let drop_counters = Arc::new(DropCounters::new());
+        let (sender, events) = crossbeam_channel::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ScanDetectorLink::new()
+                .max_ports(3)
+                .block_for(Duration::from_secs(600))
+                .block_list(Arc::clone(&block_list))
+                .event_sink(EventSink::new("scan-detector", sender))
+                .drop_counters(Arc::clone(&drop_counters))
+                .ingressor(immediate_stream(packets))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 5);
+        assert_eq!(results[0][4].src_addr(), CLIENT);
+        assert!(block_list.is_blocked(SCANNER, clock::now()));
+        assert!(!block_list.is_blocked(CLIENT, clock::now()));
+
+        let events: Vec<LinkEvent> = events.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0].to_string(),
+            "scan-detector: 198.51.100.66 scanned 4 ports in 60s, blocked for 600s"
+        );
+        assert!(matches!(events[1], LinkEvent::Dropped { .. }));
+        assert_eq!(drop_counters.snapshot(), vec![(DropReason::Blocked, 2)]);
+    }
+
+    #[test]
+    fn reports_host_sweeps_once_a_window() {
+        let packets: Vec<Ipv4Packet> = (1..=6)
+            .map(|host| tcp(SCANNER, Ipv4Addr::new(192, 0, 2, host), 22))
+            .collect();
+        let (sender, events) = crossbeam_channel::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ScanDetectorLink::new()
+                .max_hosts(3)
+                .block_list(Arc::new(BlockList::new()))
+                .event_sink(EventSink::new("scan-detector", sender))
+                .ingressor(immediate_stream(packets))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 6);
+        let alerts: Vec<String> = events.try_iter().map(|event| event.to_string()).collect();
+        assert_eq!(
+            alerts,
+            vec!["scan-detector: 198.51.100.66 scanned 4 hosts in 60s"]
+        );
+    }
+}
@@ -0,0 +1,577 @@
+use crate::config::SFlowConfig;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::EthernetFrame;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+const SFLOW_VERSION: u32 = 5;
+const SFLOW_FLOW_SAMPLE: u32 = 1;
+const SFLOW_COUNTER_SAMPLE: u32 = 2;
+const SFLOW_RAW_PACKET_HEADER: u32 = 1;
+const SFLOW_GENERIC_INTERFACE_COUNTERS: u32 = 1;
+const SFLOW_HEADER_PROTOCOL_ETHERNET: u32 = 1;
+/// ifType of an ethernetCsmacd interface, RFC 2863.
+const IF_TYPE_ETHERNET: u32 = 6;
+/// Bytes of each sampled frame copied into its flow sample.
+const SFLOW_MAX_HEADER_LEN: usize = 128;
+/// Flow samples batched into one datagram before it is sent.
+const SFLOW_MAX_SAMPLES_PER_DATAGRAM: usize = 8;
+
+/// Packet and octet counters for one interface, reported in the counter sample attached to every
+/// datagram. Share the handle with whatever sees the interface's traffic and call `count_input` and
+/// `count_output` as frames pass.
+#[derive(Default, Debug)]
+pub struct InterfaceCounters {
+    in_octets: AtomicU64,
+    in_packets: AtomicU64,
+    out_octets: AtomicU64,
+    out_packets: AtomicU64,
+}
+
+impl InterfaceCounters {
+    pub fn new() -> Self {
+        InterfaceCounters::default()
+    }
+
+    pub fn count_input(&self, octets: usize) {
+        self.in_octets.fetch_add(octets as u64, Ordering::Relaxed);
+        self.in_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_output(&self, octets: usize) {
+        self.out_octets.fetch_add(octets as u64, Ordering::Relaxed);
+        self.out_packets.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Builds sFlow version 5 datagrams, as described at https://sflow.org/sflow_version_5.txt.
+/// All fields are XDR encoded, ie. big endian and padded to 4 bytes.
+pub struct SFlowDatagram {
+    agent_address: IpAddr,
+    sub_agent_id: u32,
+    sequence_number: u32,
+    uptime_ms: u32,
+    samples: Vec<Vec<u8>>,
+}
+
+impl SFlowDatagram {
+    pub fn new(
+        agent_address: IpAddr,
+        sub_agent_id: u32,
+        sequence_number: u32,
+        uptime_ms: u32,
+    ) -> Self {
+        SFlowDatagram {
+            agent_address,
+            sub_agent_id,
+            sequence_number,
+            uptime_ms,
+            samples: vec![],
+        }
+    }
+
+    /// Adds a flow sample carrying the start of `frame` as a raw packet header record.
+    pub fn add_flow_sample(
+        &mut self,
+        sequence_number: u32,
+        if_index: u32,
+        sampling_rate: u32,
+        sample_pool: u32,
+        drops: u32,
+        frame: &[u8],
+    ) -> &mut Self {
+        let header_len = frame.len().min(SFLOW_MAX_HEADER_LEN);
+        let mut record = vec![];
+        put_u32(&mut record, SFLOW_HEADER_PROTOCOL_ETHERNET);
+        put_u32(&mut record, frame.len() as u32);
+        put_u32(&mut record, (frame.len() - header_len) as u32);
+        put_opaque(&mut record, &frame[..header_len]);
+
+        let mut sample = vec![];
+        put_u32(&mut sample, sequence_number);
+        put_u32(&mut sample, if_index);
+        put_u32(&mut sample, sampling_rate);
+        put_u32(&mut sample, sample_pool);
+        put_u32(&mut sample, drops);
+        put_u32(&mut sample, if_index);
+        put_u32(&mut sample, 0); // output interface unknown
+        put_u32(&mut sample, 1);
+        put_u32(&mut sample, SFLOW_RAW_PACKET_HEADER);
+        put_u32(&mut sample, record.len() as u32);
+        sample.extend_from_slice(&record);
+
+        self.push_sample(SFLOW_FLOW_SAMPLE, sample)
+    }
+
+    /// Adds a counter sample with a generic interface counters record.
+    pub fn add_counter_sample(
+        &mut self,
+        sequence_number: u32,
+        if_index: u32,
+        counters: &InterfaceCounters,
+    ) -> &mut Self {
+        let mut record = vec![];
+        put_u32(&mut record, if_index);
+        put_u32(&mut record, IF_TYPE_ETHERNET);
+        put_u64(&mut record, 0); // ifSpeed unknown
+        put_u32(&mut record, 0); // ifDirection unknown
+        put_u32(&mut record, 3); // ifStatus, admin and oper up
+        put_u64(&mut record, counters.in_octets.load(Ordering::Relaxed));
+        put_u32(
+            &mut record,
+            counters.in_packets.load(Ordering::Relaxed) as u32,
+        );
+        for _ in 0..5 {
+            // multicast, broadcast, discards, errors, unknown protocols
+            put_u32(&mut record, 0);
+        }
+        put_u64(&mut record, counters.out_octets.load(Ordering::Relaxed));
+        put_u32(
+            &mut record,
+            counters.out_packets.load(Ordering::Relaxed) as u32,
+        );
+        for _ in 0..4 {
+            // multicast, broadcast, discards, errors
+            put_u32(&mut record, 0);
+        }
+        put_u32(&mut record, 0); // ifPromiscuousMode
+
+        let mut sample = vec![];
+        put_u32(&mut sample, sequence_number);
+        put_u32(&mut sample, if_index);
+        put_u32(&mut sample, 1);
+        put_u32(&mut sample, SFLOW_GENERIC_INTERFACE_COUNTERS);
+        put_u32(&mut sample, record.len() as u32);
+        sample.extend_from_slice(&record);
+
+        self.push_sample(SFLOW_COUNTER_SAMPLE, sample)
+    }
+
+    pub fn num_samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut datagram = vec![];
+        put_u32(&mut datagram, SFLOW_VERSION);
+        match self.agent_address {
+            IpAddr::V4(addr) => {
+                put_u32(&mut datagram, 1);
+                datagram.extend_from_slice(&addr.octets());
+            }
+            IpAddr::V6(addr) => {
+                put_u32(&mut datagram, 2);
+                datagram.extend_from_slice(&addr.octets());
+            }
+        }
+        put_u32(&mut datagram, self.sub_agent_id);
+        put_u32(&mut datagram, self.sequence_number);
+        put_u32(&mut datagram, self.uptime_ms);
+        put_u32(&mut datagram, self.samples.len() as u32);
+        for sample in self.samples.iter() {
+            datagram.extend_from_slice(sample);
+        }
+        datagram
+    }
+
+    fn push_sample(&mut self, format: u32, body: Vec<u8>) -> &mut Self {
+        let mut sample = vec![];
+        put_u32(&mut sample, format);
+        put_u32(&mut sample, body.len() as u32);
+        sample.extend_from_slice(&body);
+        self.samples.push(sample);
+        self
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Variable length opaque data is prefixed with its length and padded to a multiple of 4.
+fn put_opaque(buf: &mut Vec<u8>, data: &[u8]) {
+    put_u32(buf, data.len() as u32);
+    buf.extend_from_slice(data);
+    buf.resize(buf.len() + (4 - data.len() % 4) % 4, 0);
+}
+
+/// Sends sampled frames to an sFlow collector.
+///
+/// The input stream should only carry the frames that were picked for sampling, for instance by
+/// forking traffic into a `DropLink` with a `drop_chance` of `1 - 1 / sampling_rate`. Samples are
+/// batched into datagrams, which are sent once they are full or the input has no more frames ready.
+/// Every datagram also carries a counter sample built from the configured `InterfaceCounters`.
+#[derive(Default)]
+pub struct SFlowExporterLink {
+    in_stream: Option<PacketStream<EthernetFrame>>,
+    collector: Option<SocketAddr>,
+    agent_address: Option<IpAddr>,
+    if_index: Option<u32>,
+    sampling_rate: Option<u32>,
+    counters: Option<Arc<InterfaceCounters>>,
+}
+
+impl SFlowExporterLink {
+    pub fn new() -> Self {
+        SFlowExporterLink {
+            in_stream: None,
+            collector: None,
+            agent_address: None,
+            if_index: None,
+            sampling_rate: None,
+            counters: None,
+        }
+    }
+
+    /// Address of the sFlow collector, required.
+    pub fn collector(self, collector: SocketAddr) -> Self {
+        SFlowExporterLink {
+            in_stream: self.in_stream,
+            collector: Some(collector),
+            agent_address: self.agent_address,
+            if_index: self.if_index,
+            sampling_rate: self.sampling_rate,
+            counters: self.counters,
+        }
+    }
+
+    /// Address the collector identifies this router by, defaults to 0.0.0.0.
+    pub fn agent_address(self, agent_address: IpAddr) -> Self {
+        SFlowExporterLink {
+            in_stream: self.in_stream,
+            collector: self.collector,
+            agent_address: Some(agent_address),
+            if_index: self.if_index,
+            sampling_rate: self.sampling_rate,
+            counters: self.counters,
+        }
+    }
+
+    /// Index of the interface the samples were taken on, defaults to 1.
+    pub fn if_index(self, if_index: u32) -> Self {
+        SFlowExporterLink {
+            in_stream: self.in_stream,
+            collector: self.collector,
+            agent_address: self.agent_address,
+            if_index: Some(if_index),
+            sampling_rate: self.sampling_rate,
+            counters: self.counters,
+        }
+    }
+
+    /// One in how many frames was sampled, defaults to 1.
+    pub fn sampling_rate(self, sampling_rate: u32) -> Self {
+        assert!(sampling_rate > 0, "sampling_rate must be > 0");
+        SFlowExporterLink {
+            in_stream: self.in_stream,
+            collector: self.collector,
+            agent_address: self.agent_address,
+            if_index: self.if_index,
+            sampling_rate: Some(sampling_rate),
+            counters: self.counters,
+        }
+    }
+
+    /// Counters of the sampled interface, all zero if not provided.
+    pub fn counters(self, counters: Arc<InterfaceCounters>) -> Self {
+        SFlowExporterLink {
+            in_stream: self.in_stream,
+            collector: self.collector,
+            agent_address: self.agent_address,
+            if_index: self.if_index,
+            sampling_rate: self.sampling_rate,
+            counters: Some(counters),
+        }
+    }
+
+    /// Applies every setting given in the `sflow` section of a `RouterConfig`.
+    pub fn config(self, config: &SFlowConfig) -> Self {
+        let mut link = self.collector(config.collector);
+        if let Some(agent_address) = config.agent_address {
+            link = link.agent_address(agent_address);
+        }
+        if let Some(if_index) = config.if_index {
+            link = link.if_index(if_index);
+        }
+        if let Some(sampling_rate) = config.sampling_rate {
+            link = link.sampling_rate(sampling_rate);
+        }
+        link
+    }
+}
+
+impl LinkBuilder<EthernetFrame, ()> for SFlowExporterLink {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<EthernetFrame>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "SFlowExporterLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<EthernetFrame>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "SFlowExporterLink may only take 1 input stream",
+            ));
+        }
+
+        Ok(SFlowExporterLink {
+            in_stream: Some(in_stream),
+            collector: self.collector,
+            agent_address: self.agent_address,
+            if_index: self.if_index,
+            sampling_rate: self.sampling_rate,
+            counters: self.counters,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<()>, LinkBuildError> {
+        match (self.in_stream, self.collector) {
+            (None, _) => Err(LinkBuildError::Missing("input streams")),
+            (_, None) => Err(LinkBuildError::Missing("collector")),
+            (Some(in_stream), Some(collector)) => {
+                let bind_addr: SocketAddr = if collector.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let socket = UdpSocket::bind(bind_addr).map_err(|err| {
+                    LinkBuildError::Invalid(format!(
+                        "SFlowExporterLink could not bind socket, {}",
+                        err
+                    ))
+                })?;
+                socket.set_nonblocking(true).map_err(|err| {
+                    LinkBuildError::Invalid(format!(
+                        "SFlowExporterLink could not configure socket, {}",
+                        err
+                    ))
+                })?;
+
+                Ok((
+                    vec![Box::new(SFlowExporter {
+                        stream: in_stream,
+                        socket,
+                        collector,
+                        agent_address: self
+                            .agent_address
+                            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                        if_index: self.if_index.unwrap_or(1),
+                        sampling_rate: self.sampling_rate.unwrap_or(1),
+                        counters: self.counters.unwrap_or_default(),
+                        started: clock::now(),
+                        pending_frames: vec![],
+                        datagram_sequence: 0,
+                        flow_sample_sequence: 0,
+                        counter_sample_sequence: 0,
+                    })],
+                    vec![],
+                ))
+            }
+        }
+    }
+}
+
+struct SFlowExporter {
+    stream: PacketStream<EthernetFrame>,
+    socket: UdpSocket,
+    collector: SocketAddr,
+    agent_address: IpAddr,
+    if_index: u32,
+    sampling_rate: u32,
+    counters: Arc<InterfaceCounters>,
+    started: Instant,
+    pending_frames: Vec<EthernetFrame>,
+    datagram_sequence: u32,
+    flow_sample_sequence: u32,
+    counter_sample_sequence: u32,
+}
+
+impl SFlowExporter {
+    fn send_pending(&mut self) {
+        self.datagram_sequence = self.datagram_sequence.wrapping_add(1);
+        let mut datagram = SFlowDatagram::new(
+            self.agent_address,
+            0,
+            self.datagram_sequence,
+            clock::now().duration_since(self.started).as_millis() as u32,
+        );
+
+        for frame in self.pending_frames.drain(..) {
+            self.flow_sample_sequence = self.flow_sample_sequence.wrapping_add(1);
+            datagram.add_flow_sample(
+                self.flow_sample_sequence,
+                self.if_index,
+                self.sampling_rate,
+                self.flow_sample_sequence.wrapping_mul(self.sampling_rate),
+                0,
+                &frame.data[frame.layer2_offset..],
+            );
+        }
+        self.counter_sample_sequence = self.counter_sample_sequence.wrapping_add(1);
+        datagram.add_counter_sample(self.counter_sample_sequence, self.if_index, &self.counters);
+
+        // Export is best effort, the same as the UDP transport it rides on.
+        let _ = self.socket.send_to(&datagram.to_bytes(), self.collector);
+    }
+}
+
+impl Future for SFlowExporter {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let exporter = Pin::into_inner(self.as_mut());
+        loop {
+            match Pin::new(&mut exporter.stream).poll_next(cx) {
+                Poll::Ready(Some(frame)) => {
+                    exporter.pending_frames.push(frame);
+                    if exporter.pending_frames.len() >= SFLOW_MAX_SAMPLES_PER_DATAGRAM {
+                        exporter.send_pending();
+                    }
+                }
+                Poll::Ready(None) => {
+                    if !exporter.pending_frames.is_empty() {
+                        exporter.send_pending();
+                    }
+                    return Poll::Ready(());
+                }
+                Poll::Pending => {
+                    if !exporter.pending_frames.is_empty() {
+                        exporter.send_pending();
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::convert::TryInto;
+    use std::time::Duration;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn frame(len: usize) -> EthernetFrame {
+        let mut data = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0xff, 0xff,
+        ];
+        data.resize(len, 0xab);
+        EthernetFrame::from_buffer(data, 0).unwrap()
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_collector() {
+        SFlowExporterLink::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_ingressor() {
+        SFlowExporterLink::new()
+            .collector(([127, 0, 0, 1], 6343).into())
+            .build_link();
+    }
+
+    #[test]
+    fn datagram_layout() {
+        let counters = InterfaceCounters::new();
+        counters.count_input(100);
+        counters.count_output(60);
+
+        let mut datagram = SFlowDatagram::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 0, 7, 1000);
+        datagram
+            .add_flow_sample(1, 2, 64, 64, 0, &[1, 2, 3, 4, 5])
+            .add_counter_sample(1, 2, &counters);
+        let bytes = datagram.to_bytes();
+
+        assert_eq!(read_u32(&bytes, 0), SFLOW_VERSION);
+        assert_eq!(read_u32(&bytes, 4), 1);
+        assert_eq!(&bytes[8..12], &[10, 0, 0, 1]);
+        assert_eq!(read_u32(&bytes, 16), 7);
+        assert_eq!(read_u32(&bytes, 24), 2);
+
+        let flow_sample = 28;
+        assert_eq!(read_u32(&bytes, flow_sample), SFLOW_FLOW_SAMPLE);
+        let flow_sample_len = read_u32(&bytes, flow_sample + 4) as usize;
+        // 8 words of sample header, record format and length, 4 words of raw header record
+        // with the 5 header bytes padded to 8.
+        assert_eq!(flow_sample_len, 4 * 8 + 8 + 16 + 8);
+        assert_eq!(read_u32(&bytes, flow_sample + 8 + 8), 64);
+
+        let counter_sample = flow_sample + 8 + flow_sample_len;
+        assert_eq!(read_u32(&bytes, counter_sample), SFLOW_COUNTER_SAMPLE);
+        assert_eq!(read_u32(&bytes, counter_sample + 4), 12 + 8 + 88);
+        let record = counter_sample + 8 + 12 + 8;
+        assert_eq!(read_u32(&bytes, record + 28), 100);
+        assert_eq!(read_u32(&bytes, record + 32), 1);
+        assert_eq!(read_u32(&bytes, record + 60), 60);
+        assert_eq!(bytes.len(), counter_sample + 8 + 12 + 8 + 88);
+    }
+
+    #[test]
+    fn truncates_long_frames() {
+        let mut datagram = SFlowDatagram::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0, 1, 0);
+        datagram.add_flow_sample(1, 1, 1, 1, 0, &[0; 1500]);
+        let bytes = datagram.to_bytes();
+        let record = 28 + 8 + 4 * 8 + 8;
+        assert_eq!(read_u32(&bytes, record + 4), 1500);
+        assert_eq!(
+            read_u32(&bytes, record + 8),
+            (1500 - SFLOW_MAX_HEADER_LEN) as u32
+        );
+        assert_eq!(read_u32(&bytes, record + 12), SFLOW_MAX_HEADER_LEN as u32);
+    }
+
+    #[test]
+    fn sends_samples_to_collector() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let collector_addr = collector.local_addr().unwrap();
+        let frames = vec![frame(60), frame(1500), frame(64)];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = SFlowExporterLink::new()
+                .ingressor(immediate_stream(frames))
+                .collector(collector_addr)
+                .sampling_rate(100)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert!(results.is_empty());
+
+        let mut buf = [0; 2048];
+        let len = collector.recv(&mut buf).unwrap();
+        assert_eq!(read_u32(&buf[..len], 0), SFLOW_VERSION);
+        // Three flow samples and one counter sample
+        assert_eq!(read_u32(&buf[..len], 24), 4);
+    }
+}
@@ -0,0 +1,625 @@
+use crate::classifier::Classifier;
+use crate::link::event::{DropAccounting, DropCounters, DropReason};
+use crate::link::primitive::{ClassifyLink, ProcessLink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::{transport_checksum, Processor, ProcessorContext};
+use crate::utils::clock;
+use route_rs_packets::{IpProtocol, Ipv4Packet};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// RFC 793 section 3.1.
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+/// The maximum segment sizes a cookie can encode, in 3 bits, the largest not above the one a
+/// client offers being chosen.
+const COOKIE_MSS: [u16; 8] = [536, 1024, 1220, 1300, 1360, 1400, 1440, 1460];
+
+/// How long each value of the 5 bit counter in a cookie lasts. A cookie is accepted back while
+/// the counter is still its own, or the next.
+const COOKIE_PERIOD: Duration = Duration::from_secs(64);
+
+/// The addresses and ports of a TCP connection, as the client sends them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Connection {
+    src_addr: Ipv4Addr,
+    src_port: u16,
+    dest_addr: Ipv4Addr,
+    dest_port: u16,
+}
+
+impl Connection {
+    fn of(packet: &Ipv4Packet, segment: &[u8]) -> Self {
+        Connection {
+            src_addr: packet.src_addr(),
+            src_port: u16::from_be_bytes([segment[0], segment[1]]),
+            dest_addr: packet.dest_addr(),
+            dest_port: u16::from_be_bytes([segment[2], segment[3]]),
+        }
+    }
+}
+
+/// Makes and checks SYN cookies: initial sequence numbers that encode what the router would have
+/// kept about a half-open connection, so it needn't keep anything until the client's ACK returns
+/// the cookie, RFC 4987 section 3.6.
+///
+/// A cookie is a 5 bit counter of 64 second periods, 3 bits choosing the maximum segment size,
+/// and a 24 bit keyed hash of the connection and both. Share the cookies with the service that
+/// terminates connections completed with them, which recovers the maximum segment size the client
+/// offered from its ACK with `check`.
+pub struct SynCookies {
+    key: RandomState,
+    started: Instant,
+}
+
+impl SynCookies {
+    pub fn new() -> Self {
+        SynCookies {
+            key: RandomState::new(),
+            started: clock::now(),
+        }
+    }
+
+    fn counter(&self, now: Instant) -> u32 {
+        (now.saturating_duration_since(self.started).as_secs() / COOKIE_PERIOD.as_secs()) as u32
+    }
+
+    fn hash(&self, connection: &Connection, client_isn: u32, counter: u32, mss: u32) -> u32 {
+        let mut hasher = self.key.build_hasher();
+        connection.hash(&mut hasher);
+        (client_isn, counter & 0x1f, mss).hash(&mut hasher);
+        hasher.finish() as u32 & 0x00ff_ffff
+    }
+
+    /// The cookie for a SYN on `connection` with sequence number `client_isn`, offering a
+    /// maximum segment size of `mss`.
+    fn cookie(&self, connection: &Connection, client_isn: u32, mss: u16, now: Instant) -> u32 {
+        let counter = self.counter(now);
+        let mss = COOKIE_MSS.iter().rposition(|&m| m <= mss).unwrap_or(0) as u32;
+        ((counter & 0x1f) << 27) | (mss << 24) | self.hash(connection, client_isn, counter, mss)
+    }
+
+    /// The maximum segment size a cookie was made with, if the ACK `segment`, of an IPv4
+    /// `packet`, returns a cookie made within the last two periods.
+    pub fn check(&self, packet: &Ipv4Packet, segment: &[u8]) -> Option<u16> {
+        if segment.len() < 20 {
+            return None;
+        }
+        let connection = Connection::of(packet, segment);
+        let client_isn =
+            u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]).wrapping_sub(1);
+        let cookie =
+            u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]).wrapping_sub(1);
+        let counter = self.counter(clock::now());
+        let cookie_counter = cookie >> 27;
+        let mss = (cookie >> 24) & 0x7;
+        [counter, counter.wrapping_sub(1)]
+            .iter()
+            .find(|&&c| c & 0x1f == cookie_counter)
+            .filter(|&&c| self.hash(&connection, client_isn, c, mss) == cookie & 0x00ff_ffff)
+            .map(|_| COOKIE_MSS[mss as usize])
+    }
+}
+
+impl Default for SynCookies {
+    fn default() -> Self {
+        SynCookies::new()
+    }
+}
+
+/// What a `SynProtectComposite` does with the SYNs to a destination with too many half-open
+/// connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SynMitigation {
+    /// Answers SYNs to the router's own addresses with SYN cookies, and drops the rest.
+    Cookies,
+    /// Drops every SYN over the limit.
+    Drop,
+}
+
+/// The maximum segment size a SYN offers, or the 536 assumed if it offers none, RFC 1122 section
+/// 4.2.2.6.
+fn offered_mss(segment: &[u8]) -> u16 {
+    let header_len = usize::from(segment[12] >> 4) * 4;
+    let mut options = segment.get(20..header_len).unwrap_or(&[]);
+    while let Some(&kind) = options.first() {
+        match kind {
+            0 => break,
+            1 => options = &options[1..],
+            _ => {
+                let len = usize::from(*options.get(1).unwrap_or(&0));
+                if len < 2 || len > options.len() {
+                    break;
+                }
+                if kind == 2 && len == 4 {
+                    return u16::from_be_bytes([options[2], options[3]]);
+                }
+                options = &options[len..];
+            }
+        }
+    }
+    536
+}
+
+/// A SYN-ACK answering `syn`, whose segment is `segment`, with `cookie` as its sequence number.
+fn syn_ack(syn: &Ipv4Packet, segment: &[u8], cookie: u32, mss: u16) -> Ipv4Packet {
+    let client_isn = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+    let mut reply = vec![0; 24];
+    reply[0..2].copy_from_slice(&segment[2..4]);
+    reply[2..4].copy_from_slice(&segment[0..2]);
+    reply[4..8].copy_from_slice(&cookie.to_be_bytes());
+    reply[8..12].copy_from_slice(&client_isn.wrapping_add(1).to_be_bytes());
+    reply[12] = 0x60;
+    reply[13] = TCP_SYN | TCP_ACK;
+    reply[14..16].copy_from_slice(&u16::MAX.to_be_bytes());
+    reply[20..24].copy_from_slice(&[2, 4, (mss >> 8) as u8, mss as u8]);
+    let checksum = transport_checksum(syn.dest_addr(), syn.src_addr(), 6, &reply);
+    reply[16..18].copy_from_slice(&checksum.to_be_bytes());
+
+    let mut packet = Ipv4Packet::empty();
+    packet.set_protocol(6);
+    packet.set_ttl(64);
+    packet.set_src_addr(syn.dest_addr());
+    packet.set_dest_addr(syn.src_addr());
+    packet.set_payload(&reply);
+    packet.set_checksum();
+    packet
+}
+
+struct SynProtect {
+    local_addrs: Vec<Ipv4Addr>,
+    max_half_open: usize,
+    half_open_timeout: Duration,
+    mitigation: SynMitigation,
+    cookies: Arc<SynCookies>,
+    /// Connections a SYN has been let through for, but not yet an ACK, by when the SYN was.
+    half_open: HashMap<Connection, Instant>,
+    /// The half-open connections to each destination.
+    per_destination: HashMap<Ipv4Addr, usize>,
+    /// Half-open connections in the order their SYNs came, to expire them.
+    opened: VecDeque<(Instant, Connection)>,
+    syns: Arc<AtomicU64>,
+    syns_dropped: Arc<AtomicU64>,
+    cookies_sent: Arc<AtomicU64>,
+    cookies_validated: Arc<AtomicU64>,
+    drops: DropAccounting,
+}
+
+impl SynProtect {
+    fn expire(&mut self, now: Instant) {
+        while let Some((opened, connection)) = self.opened.front().copied() {
+            if now.saturating_duration_since(opened) < self.half_open_timeout {
+                break;
+            }
+            self.opened.pop_front();
+            if self.half_open.get(&connection) == Some(&opened) {
+                self.close(&connection);
+            }
+        }
+    }
+
+    fn close(&mut self, connection: &Connection) {
+        if self.half_open.remove(connection).is_some() {
+            if let Some(count) = self.per_destination.get_mut(&connection.dest_addr) {
+                *count -= 1;
+                if *count == 0 {
+                    self.per_destination.remove(&connection.dest_addr);
+                }
+            }
+        }
+    }
+
+    fn syn(&mut self, packet: Ipv4Packet, segment: &[u8], now: Instant) -> Option<Ipv4Packet> {
+        self.syns.fetch_add(1, Ordering::Relaxed);
+        let connection = Connection::of(&packet, segment);
+        let half_open = self
+            .per_destination
+            .get(&connection.dest_addr)
+            .copied()
+            .unwrap_or(0);
+        if half_open < self.max_half_open || self.half_open.contains_key(&connection) {
+            if self.half_open.insert(connection, now).is_none() {
+                *self
+                    .per_destination
+                    .entry(connection.dest_addr)
+                    .or_insert(0) += 1;
+            }
+            self.opened.push_back((now, connection));
+            return Some(packet);
+        }
+
+        if self.mitigation == SynMitigation::Cookies
+            && self.local_addrs.contains(&connection.dest_addr)
+        {
+            let client_isn = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+            let offered = offered_mss(segment);
+            let cookie = self.cookies.cookie(&connection, client_isn, offered, now);
+            let mss = COOKIE_MSS[((cookie >> 24) & 0x7) as usize];
+            self.cookies_sent.fetch_add(1, Ordering::Relaxed);
+            return Some(syn_ack(&packet, segment, cookie, mss));
+        }
+        self.syns_dropped.fetch_add(1, Ordering::Relaxed);
+        self.drops.dropped(DropReason::RateLimited);
+        None
+    }
+}
+
+impl Processor for SynProtect {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        if packet.protocol() != IpProtocol::TCP || packet.payload().len() < 20 {
+            return Some(packet);
+        }
+        let segment = packet.payload().into_owned();
+        let flags = segment[13];
+        let now = clock::now();
+        self.expire(now);
+
+        if flags & (TCP_SYN | TCP_ACK) == TCP_SYN {
+            return self.syn(packet, &segment, now);
+        }
+        let connection = Connection::of(&packet, &segment);
+        if self.half_open.contains_key(&connection) {
+            if flags & (TCP_ACK | TCP_RST | TCP_FIN) != 0 {
+                self.close(&connection);
+            }
+        } else if flags & TCP_SYN == 0
+            && flags & TCP_ACK != 0
+            && self.local_addrs.contains(&connection.dest_addr)
+            && self.cookies.check(&packet, &segment).is_some()
+        {
+            self.cookies_validated.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(packet)
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.syns = context.counter("syns");
+        self.syns_dropped = context.counter("syns_dropped");
+        self.cookies_sent = context.counter("cookies_sent");
+        self.cookies_validated = context.counter("cookies_validated");
+    }
+}
+
+/// Sends the SYN-ACKs the router answers with itself back where their SYNs came from.
+struct ByReply {
+    local_addrs: Vec<Ipv4Addr>,
+}
+
+impl Classifier for ByReply {
+    type Packet = Ipv4Packet;
+    type Class = usize;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        if self.local_addrs.contains(&packet.src_addr()) {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+/// Protects hosts behind the router, and the router itself, from SYN floods, by keeping count of
+/// the half-open TCP connections to each destination: those a SYN has been let through for, but
+/// not yet an ACK or RST, in the last `half_open_timeout`.
+///
+/// The composite takes the IPv4 traffic from outside, and passes it on out of its first egressor.
+/// Once a destination has `max_half_open` connections half-open, further SYNs to it are mitigated:
+/// with `SynMitigation::Cookies`, the default, those to the router's own `local_addrs` are answered
+/// with SYN cookies, out of the second egressor, back to the clients, and the rest dropped. The
+/// ACKs that return a valid cookie are counted, and passed on like any other packet, to the
+/// service terminating the connection, which can check them with the composite's `SynCookies`.
+///
+/// The composite counts the SYNs it sees, drops, and answers with cookies, and the cookies that
+/// return, as `syns`, `syns_dropped`, `cookies_sent` and `cookies_validated` under `syn-protect`
+/// in the metrics. The SYNs it drops are counted in its `drop_counters` too, if it has them, as
+/// `DropReason::RateLimited`.
+pub struct SynProtectComposite {
+    in_stream: Option<PacketStream<Ipv4Packet>>,
+    local_addrs: Vec<Ipv4Addr>,
+    max_half_open: usize,
+    half_open_timeout: Duration,
+    mitigation: SynMitigation,
+    cookies: Option<Arc<SynCookies>>,
+    drop_counters: Option<Arc<DropCounters>>,
+}
+
+impl Default for SynProtectComposite {
+    fn default() -> Self {
+        SynProtectComposite::new()
+    }
+}
+
+impl SynProtectComposite {
+    pub fn new() -> Self {
+        SynProtectComposite {
+            in_stream: None,
+            local_addrs: vec![],
+            max_half_open: 256,
+            half_open_timeout: Duration::from_secs(10),
+            mitigation: SynMitigation::Cookies,
+            cookies: None,
+            drop_counters: None,
+        }
+    }
+
+    /// The router's own addresses, whose services may be answered for with SYN cookies.
+    pub fn local_addrs(self, local_addrs: Vec<Ipv4Addr>) -> Self {
+        SynProtectComposite {
+            in_stream: self.in_stream,
+            local_addrs,
+            max_half_open: self.max_half_open,
+            half_open_timeout: self.half_open_timeout,
+            mitigation: self.mitigation,
+            cookies: self.cookies,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes max_half_open, the half-open connections to a destination beyond which its SYNs
+    /// are mitigated, default value is 256.
+    pub fn max_half_open(self, max_half_open: usize) -> Self {
+        SynProtectComposite {
+            in_stream: self.in_stream,
+            local_addrs: self.local_addrs,
+            max_half_open,
+            half_open_timeout: self.half_open_timeout,
+            mitigation: self.mitigation,
+            cookies: self.cookies,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes half_open_timeout, how long a connection is counted as half-open after its SYN
+    /// without an ACK, default value is 10 seconds.
+    pub fn half_open_timeout(self, half_open_timeout: Duration) -> Self {
+        SynProtectComposite {
+            in_stream: self.in_stream,
+            local_addrs: self.local_addrs,
+            max_half_open: self.max_half_open,
+            half_open_timeout,
+            mitigation: self.mitigation,
+            cookies: self.cookies,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes mitigation, default value is `SynMitigation::Cookies`.
+    pub fn mitigation(self, mitigation: SynMitigation) -> Self {
+        SynProtectComposite {
+            in_stream: self.in_stream,
+            local_addrs: self.local_addrs,
+            max_half_open: self.max_half_open,
+            half_open_timeout: self.half_open_timeout,
+            mitigation,
+            cookies: self.cookies,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Makes cookies with `cookies`, shared with the services they are sent for, rather than
+    /// cookies of the composite's own.
+    pub fn cookies(self, cookies: Arc<SynCookies>) -> Self {
+        SynProtectComposite {
+            in_stream: self.in_stream,
+            local_addrs: self.local_addrs,
+            max_half_open: self.max_half_open,
+            half_open_timeout: self.half_open_timeout,
+            mitigation: self.mitigation,
+            cookies: Some(cookies),
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Counts the SYNs the composite drops in `drop_counters`.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        SynProtectComposite {
+            in_stream: self.in_stream,
+            local_addrs: self.local_addrs,
+            max_half_open: self.max_half_open,
+            half_open_timeout: self.half_open_timeout,
+            mitigation: self.mitigation,
+            cookies: self.cookies,
+            drop_counters: Some(drop_counters),
+        }
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for SynProtectComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "SynProtectComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "SynProtectComposite may only take 1 input stream",
+            ));
+        }
+        Ok(SynProtectComposite {
+            in_stream: Some(in_stream),
+            local_addrs: self.local_addrs,
+            max_half_open: self.max_half_open,
+            half_open_timeout: self.half_open_timeout,
+            mitigation: self.mitigation,
+            cookies: self.cookies,
+            drop_counters: self.drop_counters,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        let in_stream = self
+            .in_stream
+            .ok_or(LinkBuildError::Missing("input stream"))?;
+        let protect = SynProtect {
+            local_addrs: self.local_addrs.clone(),
+            max_half_open: self.max_half_open,
+            half_open_timeout: self.half_open_timeout,
+            mitigation: self.mitigation,
+            cookies: self.cookies.unwrap_or_default(),
+            half_open: HashMap::new(),
+            per_destination: HashMap::new(),
+            opened: VecDeque::new(),
+            syns: Arc::new(AtomicU64::new(0)),
+            syns_dropped: Arc::new(AtomicU64::new(0)),
+            cookies_sent: Arc::new(AtomicU64::new(0)),
+            cookies_validated: Arc::new(AtomicU64::new(0)),
+            drops: DropAccounting::new(self.drop_counters, None),
+        };
+
+        let (mut runnables, mut egressors) = ProcessLink::new()
+            .try_ingressor(in_stream)?
+            .processor(protect)
+            .context(ProcessorContext::new("syn-protect"))
+            .try_build_link()?;
+        let (mut classify_runnables, classify_egressors) = ClassifyLink::new()
+            .try_ingressor(egressors.remove(0))?
+            .num_egressors(2)
+            .classifier(ByReply {
+                local_addrs: self.local_addrs,
+            })
+            .dispatcher(Box::new(|class| class))
+            .try_build_link()?;
+        runnables.append(&mut classify_runnables);
+        Ok((runnables, classify_egressors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const HOST: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 80);
+
+    fn segment(src_addr: Ipv4Addr, src_port: u16, dest_addr: Ipv4Addr, flags: u8) -> Ipv4Packet {
+        let mut segment = vec![0; 24];
+        segment[0..2].copy_from_slice(&src_port.to_be_bytes());
+        segment[2..4].copy_from_slice(&443u16.to_be_bytes());
+        segment[4..8].copy_from_slice(&1000u32.to_be_bytes());
+        segment[12] = 0x60;
+        segment[13] = flags;
+        segment[20..24].copy_from_slice(&[2, 4, 0x05, 0xb4]);
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(6);
+        packet.set_src_addr(src_addr);
+        packet.set_dest_addr(dest_addr);
+        packet.set_payload(&segment);
+        packet
+    }
+
+    fn protect(mitigation: SynMitigation) -> SynProtect {
+        SynProtect {
+            local_addrs: vec![SERVER],
+            max_half_open: 2,
+            half_open_timeout: Duration::from_secs(10),
+            mitigation,
+            cookies: Arc::new(SynCookies::new()),
+            half_open: HashMap::new(),
+            per_destination: HashMap::new(),
+            opened: VecDeque::new(),
+            syns: Arc::new(AtomicU64::new(0)),
+            syns_dropped: Arc::new(AtomicU64::new(0)),
+            cookies_sent: Arc::new(AtomicU64::new(0)),
+            cookies_validated: Arc::new(AtomicU64::new(0)),
+            drops: DropAccounting::default(),
+        }
+    }
+
+    #[test]
+    fn drops_syns_over_the_limit() {
+        let drop_counters = Arc::new(DropCounters::new());
+        let mut protect = SynProtect {
+            drops: DropAccounting::new(Some(Arc::clone(&drop_counters)), None),
+            ..protect(SynMitigation::Drop)
+        };
+        let client = Ipv4Addr::new(198, 51, 100, 1);
+        assert!(protect.process(segment(client, 1, HOST, TCP_SYN)).is_some());
+        assert!(protect.process(segment(client, 2, HOST, TCP_SYN)).is_some());
+        assert!(protect.process(segment(client, 3, HOST, TCP_SYN)).is_none());
+
+        // Completing a handshake makes room for another.
+        assert!(protect.process(segment(client, 1, HOST, TCP_ACK)).is_some());
+        assert!(protect.process(segment(client, 3, HOST, TCP_SYN)).is_some());
+        assert_eq!(protect.syns.load(Ordering::Relaxed), 4);
+        assert_eq!(protect.syns_dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(drop_counters.get(DropReason::RateLimited), 1);
+    }
+
+    #[test]
+    fn answers_with_cookies_that_check() {
+        let mut protect = protect(SynMitigation::Cookies);
+        let client = Ipv4Addr::new(198, 51, 100, 1);
+        protect.process(segment(client, 1, SERVER, TCP_SYN));
+        protect.process(segment(client, 2, SERVER, TCP_SYN));
+
+        let reply = protect
+            .process(segment(client, 3, SERVER, TCP_SYN))
+            .unwrap();
+        assert_eq!(reply.src_addr(), SERVER);
+        assert_eq!(reply.dest_addr(), client);
+        let syn_ack = reply.payload().into_owned();
+        assert_eq!(syn_ack[13], TCP_SYN | TCP_ACK);
+        assert_eq!(&syn_ack[0..4], &[1, 187, 0, 3]);
+        assert_eq!(&syn_ack[8..12], &1001u32.to_be_bytes());
+        assert_eq!(&syn_ack[20..24], &[2, 4, 0x05, 0xb4]);
+        assert_eq!(transport_checksum(SERVER, client, 6, &syn_ack), 0);
+
+        let mut ack = segment(client, 3, SERVER, TCP_ACK);
+        let mut returned = ack.payload().into_owned();
+        returned[4..8].copy_from_slice(&1001u32.to_be_bytes());
+        let cookie = u32::from_be_bytes([syn_ack[4], syn_ack[5], syn_ack[6], syn_ack[7]]);
+        returned[8..12].copy_from_slice(&cookie.wrapping_add(1).to_be_bytes());
+        ack.set_payload(&returned);
+        assert_eq!(protect.cookies.check(&ack, &returned), Some(1460));
+        assert!(protect.process(ack.clone()).is_some());
+        assert_eq!(protect.cookies_validated.load(Ordering::Relaxed), 1);
+
+        // A cookie for another connection doesn't check.
+        let mut forged = returned;
+        forged[0..2].copy_from_slice(&4u16.to_be_bytes());
+        assert_eq!(protect.cookies.check(&ack, &forged), None);
+    }
+
+    #[test]
+    fn sends_cookies_back_to_clients() {
+        let client = Ipv4Addr::new(198, 51, 100, 1);
+        let packets: Vec<Ipv4Packet> = (1..=3)
+            .map(|port| segment(client, port, SERVER, TCP_SYN))
+            .collect();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = SynProtectComposite::new()
+                .local_addrs(vec![SERVER])
+                .max_half_open(2)
+                .ingressor(immediate_stream(packets))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 2);
+        assert_eq!(results[1].len(), 1);
+        assert_eq!(results[1][0].dest_addr(), client);
+    }
+}
@@ -0,0 +1,805 @@
+use crate::link::event::{DropAccounting, DropCounters, DropReason};
+use crate::link::primitive::{ForkLink, ProcessLink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::Processor;
+use crate::state::StateStore;
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::*;
+use std::collections::VecDeque;
+use std::convert::{TryFrom, TryInto};
+use std::net::{IpAddr, Ipv4Addr};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::time::{delay_until, Delay};
+
+/// The name the VRRP status is shared under in the `StateStore`, unless the composite is given its
+/// own `VrrpStatus`.
+pub const VRRP_STATUS_STATE: &str = "vrrp-status";
+
+const BROADCAST_MAC: MacAddr = MacAddr {
+    bytes: [0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+};
+
+/// The MAC address of the VRRP group, 224.0.0.18.
+const VRRP_GROUP_MAC: MacAddr = MacAddr {
+    bytes: [0x01, 0x00, 0x5e, 0x00, 0x00, 0x12],
+};
+
+/// The state of a virtual router, RFC 5798 section 6.4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VrrpState {
+    Initialize,
+    Backup,
+    Master,
+}
+
+/// Whether the router is master of a `VrrpComposite`'s virtual router, which decides whether it
+/// takes traffic for the virtual addresses, and answers for them.
+pub struct VrrpStatus {
+    state: RwLock<VrrpState>,
+}
+
+impl VrrpStatus {
+    pub fn new() -> Self {
+        VrrpStatus {
+            state: RwLock::new(VrrpState::Initialize),
+        }
+    }
+
+    pub fn state(&self) -> VrrpState {
+        *self.state.read().unwrap()
+    }
+
+    pub fn is_master(&self) -> bool {
+        self.state() == VrrpState::Master
+    }
+
+    fn set(&self, state: VrrpState) {
+        *self.state.write().unwrap() = state;
+    }
+}
+
+impl Default for VrrpStatus {
+    fn default() -> Self {
+        VrrpStatus::new()
+    }
+}
+
+/// The VRRP advertisement in a frame, if it is one.
+fn advertisement(frame: &EthernetFrame) -> Option<VrrpPacket> {
+    if frame.ether_type() != IPV4_ETHER_TYPE {
+        return None;
+    }
+    let packet = Ipv4Packet::try_from(frame.clone()).ok()?;
+    VrrpPacket::try_from(packet).ok()
+}
+
+/// The address an ARP request asks for, if the frame is one, and not gratuitous.
+fn arp_request(frame: &EthernetFrame) -> Option<(Ipv4Addr, ArpFrame)> {
+    let arp = ArpFrame::try_from(frame.clone()).ok()?;
+    if arp.opcode() != ArpOp::Request as u16 || arp.protocol_addr_len() != 4 {
+        return None;
+    }
+    let target = Ipv4Addr::from(<[u8; 4]>::try_from(arp.target_protocol_addr()).ok()?);
+    if arp.sender_protocol_addr() == target.octets() {
+        return None;
+    }
+    Some((target, arp))
+}
+
+/// One virtual router, with the state variables of RFC 5798 section 6.1.
+struct VirtualRouter {
+    vrid: u8,
+    priority: u8,
+    addresses: Vec<Ipv4Addr>,
+    primary_addr: Ipv4Addr,
+    advert_interval: Duration,
+    preempt: bool,
+    state: VrrpState,
+    master_advert_interval: Duration,
+    master_down_at: Option<Instant>,
+    next_advert: Option<Instant>,
+    status: Arc<VrrpStatus>,
+}
+
+impl VirtualRouter {
+    fn mac(&self) -> MacAddr {
+        vrrp_virtual_mac(self.vrid)
+    }
+
+    fn skew_time(&self) -> Duration {
+        self.master_advert_interval * (256 - u32::from(self.priority)) / 256
+    }
+
+    fn master_down_interval(&self) -> Duration {
+        self.master_advert_interval * 3 + self.skew_time()
+    }
+
+    fn advertisement(&self, priority: u8) -> EthernetFrame {
+        let centiseconds = (self.advert_interval.as_millis() / 10).min(0x0fff) as u16;
+        let mut vrrp = VrrpPacket::new();
+        vrrp.ipv4_mut().set_src_addr(self.primary_addr);
+        vrrp.set_vrid(self.vrid)
+            .set_priority(priority)
+            .set_max_advert_interval(centiseconds)
+            .set_addresses(&self.addresses)
+            .set_checksum();
+        let mut packet = vrrp.packet();
+        packet.set_checksum();
+        let mut frame = EthernetFrame::encap_ipv4(packet);
+        frame.set_src_mac(self.mac());
+        frame.set_dest_mac(VRRP_GROUP_MAC);
+        frame
+    }
+
+    /// An ARP frame from the virtual MAC address for `addr`, a gratuitous request if `to` is none.
+    fn arp(&self, addr: Ipv4Addr, to: Option<(MacAddr, Ipv4Addr)>) -> EthernetFrame {
+        let (op, dest_mac, target_mac, target_addr) = match to {
+            Some((mac, target_addr)) => (ArpOp::Reply, mac, mac, target_addr),
+            None => (ArpOp::Request, BROADCAST_MAC, MacAddr::new([0; 6]), addr),
+        };
+        let mut arp = ArpFrame::new(6, 4);
+        arp.set_hardware_type(ArpHardwareType::Ethernet as u16)
+            .set_protocol_type(IPV4_ETHER_TYPE)
+            .set_opcode(op as u16)
+            .set_sender_hardware_addr(self.mac())
+            .set_sender_protocol_addr(IpAddr::V4(addr))
+            .set_target_hardware_addr(target_mac)
+            .set_target_protocol_addr(IpAddr::V4(target_addr));
+        let mut frame = arp.frame();
+        frame.set_ether_type(ARP_ETHER_TYPE);
+        frame.set_src_mac(self.mac());
+        frame.set_dest_mac(dest_mac);
+        frame
+    }
+
+    fn set_state(&mut self, state: VrrpState) {
+        self.state = state;
+        self.status.set(state);
+    }
+
+    /// Takes the virtual router up, as master if the router owns its addresses.
+    fn start(&mut self, now: Instant) -> Vec<EthernetFrame> {
+        if self.priority == VRRP_OWNER_PRIORITY {
+            return self.become_master(now);
+        }
+        self.master_advert_interval = self.advert_interval;
+        self.master_down_at = Some(now + self.master_down_interval());
+        self.set_state(VrrpState::Backup);
+        vec![]
+    }
+
+    /// Takes over the virtual addresses, announcing them with gratuitous ARP so the hosts of the
+    /// LAN and its switches send their traffic to the new master at once.
+    fn become_master(&mut self, now: Instant) -> Vec<EthernetFrame> {
+        self.set_state(VrrpState::Master);
+        self.master_down_at = None;
+        self.next_advert = Some(now + self.advert_interval);
+        let mut frames = vec![self.advertisement(self.priority)];
+        frames.extend(self.addresses.iter().map(|addr| self.arp(*addr, None)));
+        frames
+    }
+
+    /// Runs a frame received on the LAN, returning what to send on it in answer.
+    fn receive(&mut self, frame: &EthernetFrame, now: Instant) -> Vec<EthernetFrame> {
+        if let Some((target, request)) = arp_request(frame) {
+            if self.state != VrrpState::Master || !self.addresses.contains(&target) {
+                return vec![];
+            }
+            let sender = match request.sender_hardware_addr().try_into() {
+                Ok(mac) => MacAddr::new(mac),
+                Err(_) => return vec![],
+            };
+            let sender_addr = request.sender_protocol_addr().try_into().unwrap_or([0; 4]);
+            return vec![self.arp(target, Some((sender, Ipv4Addr::from(sender_addr))))];
+        }
+
+        let vrrp = match advertisement(frame) {
+            Some(vrrp) => vrrp,
+            None => return vec![],
+        };
+        // Advertisements no router has forwarded, for this virtual router, RFC 5798 section 7.1.
+        if vrrp.ipv4().ttl() != 255 || vrrp.vrid() != self.vrid || !vrrp.validate_checksum() {
+            return vec![];
+        }
+        let interval = Duration::from_millis(u64::from(vrrp.max_advert_interval()) * 10);
+        match self.state {
+            VrrpState::Backup => {
+                if vrrp.priority() == VRRP_RESIGN_PRIORITY {
+                    self.master_down_at = Some(now + self.skew_time());
+                } else if !self.preempt || vrrp.priority() >= self.priority {
+                    self.master_advert_interval = interval;
+                    self.master_down_at = Some(now + self.master_down_interval());
+                }
+                vec![]
+            }
+            VrrpState::Master => {
+                let sender = vrrp.ipv4().src_addr();
+                if vrrp.priority() == VRRP_RESIGN_PRIORITY {
+                    self.next_advert = Some(now + self.advert_interval);
+                    vec![self.advertisement(self.priority)]
+                } else if vrrp.priority() > self.priority
+                    || (vrrp.priority() == self.priority && sender > self.primary_addr)
+                {
+                    self.next_advert = None;
+                    self.master_advert_interval = interval;
+                    self.master_down_at = Some(now + self.master_down_interval());
+                    self.set_state(VrrpState::Backup);
+                    vec![]
+                } else {
+                    vec![]
+                }
+            }
+            VrrpState::Initialize => vec![],
+        }
+    }
+
+    /// Takes over from a master gone quiet, and sends the advertisements that are due.
+    fn expire(&mut self, now: Instant) -> Vec<EthernetFrame> {
+        if self.master_down_at.is_some_and(|at| now >= at) {
+            return self.become_master(now);
+        }
+        if self.next_advert.is_some_and(|at| now >= at) {
+            self.next_advert = Some(now + self.advert_interval);
+            return vec![self.advertisement(self.priority)];
+        }
+        vec![]
+    }
+
+    /// Gives up the virtual router, so a backup takes over after its skew time rather than a
+    /// whole master down interval.
+    fn shutdown(&mut self) -> Vec<EthernetFrame> {
+        let was_master = self.state == VrrpState::Master;
+        self.master_down_at = None;
+        self.next_advert = None;
+        self.set_state(VrrpState::Initialize);
+        if was_master {
+            vec![self.advertisement(VRRP_RESIGN_PRIORITY)]
+        } else {
+            vec![]
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.master_down_at
+            .into_iter()
+            .chain(self.next_advert)
+            .min()
+    }
+}
+
+struct VrrpRunner {
+    in_stream: Option<PacketStream<EthernetFrame>>,
+    router: VirtualRouter,
+    frames: VecDeque<EthernetFrame>,
+    timer: Delay,
+}
+
+impl Unpin for VrrpRunner {}
+
+impl Stream for VrrpRunner {
+    type Item = EthernetFrame;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let runner = &mut *self;
+        loop {
+            if let Some(frame) = runner.frames.pop_front() {
+                return Poll::Ready(Some(frame));
+            }
+            let in_stream = match runner.in_stream.as_mut() {
+                Some(in_stream) => in_stream,
+                None => return Poll::Ready(None),
+            };
+            let now = clock::now();
+            let deadline = runner.router.next_deadline();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                runner.frames.extend(runner.router.expire(now));
+                continue;
+            }
+            match Pin::new(in_stream).poll_next(cx) {
+                Poll::Ready(Some(frame)) => {
+                    runner.frames.extend(runner.router.receive(&frame, now));
+                    continue;
+                }
+                Poll::Ready(None) => {
+                    runner.in_stream = None;
+                    runner.frames.extend(runner.router.shutdown());
+                    continue;
+                }
+                Poll::Pending => {}
+            }
+
+            match deadline {
+                Some(deadline) => runner.timer.reset(tokio::time::Instant::from_std(deadline)),
+                None => return Poll::Pending,
+            }
+            if Pin::new(&mut runner.timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Passes the frames received on the LAN that the router should handle. VRRP advertisements, and
+/// ARP requests for the virtual addresses, are left to the virtual router. While it isn't master,
+/// frames to the virtual MAC address or the virtual addresses are dropped too, since they are the
+/// master's to take.
+struct VrrpFilter {
+    vrid: u8,
+    addresses: Vec<Ipv4Addr>,
+    status: Arc<VrrpStatus>,
+    drops: DropAccounting,
+}
+
+impl Processor for VrrpFilter {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        if let Some((target, _)) = arp_request(&frame) {
+            if self.addresses.contains(&target) {
+                return None;
+            }
+            return Some(frame);
+        }
+        if frame.ether_type() == IPV4_ETHER_TYPE {
+            let packet = match Ipv4Packet::try_from(frame.clone()) {
+                Ok(packet) => packet,
+                Err(err) => {
+                    self.drops.parse_error(err);
+                    return None;
+                }
+            };
+            if packet.protocol() == IpProtocol::VRRP {
+                return None;
+            }
+            if self.addresses.contains(&packet.dest_addr()) && !self.status.is_master() {
+                self.drops.dropped(DropReason::Discarded);
+                return None;
+            }
+        }
+        if frame.dest_mac() == vrrp_virtual_mac(self.vrid) && !self.status.is_master() {
+            self.drops.dropped(DropReason::Discarded);
+            return None;
+        }
+        Some(frame)
+    }
+}
+
+/// Shares a gateway address between routers on a LAN with VRRP version 3, RFC 5798, so that
+/// when the one acting for it fails, another takes over within a few advertisement intervals.
+///
+/// Each router runs a `VrrpComposite` with the same `vrid` and `addresses`, and its own
+/// `priority`, from 1 to 254, or 255 on the router that owns the addresses. The router of the
+/// highest priority is master: it sends an advertisement every `advert_interval`, answers ARP for
+/// the addresses with the virtual MAC address of the VRID, and takes the traffic sent to them. A
+/// backup that hears no advertisement for three intervals, and a skew that is shorter the higher
+/// its priority, takes over, announcing the addresses with gratuitous ARP. A backup of higher
+/// priority than the master takes over from it if `preempt` is set, as it is by default.
+///
+/// The composite takes the frames received on the LAN. Egressor 0 carries those the router should
+/// handle, without the advertisements and ARP requests for the addresses, and without the traffic
+/// for the addresses unless the router is master. Egressor 1 carries the frames to send on the LAN,
+/// advertisements from `primary_addr`, the router's own address there, and ARP. Whether the router
+/// is master is published in a `VrrpStatus`, shared in the `StateStore` under `VRRP_STATUS_STATE`
+/// unless one is given, for what routes onto the LAN to follow. When the ingressor ends, a master
+/// sends an advertisement of priority 0, so a backup takes over at once.
+///
+/// The frames egressor 0 goes without as a backup are counted in the composite's `drop_counters`,
+/// if it has them, as `DropReason::Discarded`.
+pub struct VrrpComposite {
+    in_stream: Option<PacketStream<EthernetFrame>>,
+    vrid: Option<u8>,
+    priority: u8,
+    addresses: Vec<Ipv4Addr>,
+    primary_addr: Option<Ipv4Addr>,
+    advert_interval: Duration,
+    preempt: bool,
+    status: Option<Arc<VrrpStatus>>,
+    drop_counters: Option<Arc<DropCounters>>,
+}
+
+impl Default for VrrpComposite {
+    fn default() -> Self {
+        VrrpComposite::new()
+    }
+}
+
+impl VrrpComposite {
+    pub fn new() -> Self {
+        VrrpComposite {
+            in_stream: None,
+            vrid: None,
+            priority: 100,
+            addresses: vec![],
+            primary_addr: None,
+            advert_interval: Duration::from_secs(1),
+            preempt: true,
+            status: None,
+            drop_counters: None,
+        }
+    }
+
+    /// The Virtual Router Identifier, from 1 to 255, which all the routers sharing the addresses
+    /// are given.
+    pub fn vrid(self, vrid: u8) -> Self {
+        assert!(vrid > 0, "vrid must be > 0");
+        VrrpComposite {
+            in_stream: self.in_stream,
+            vrid: Some(vrid),
+            priority: self.priority,
+            addresses: self.addresses,
+            primary_addr: self.primary_addr,
+            advert_interval: self.advert_interval,
+            preempt: self.preempt,
+            status: self.status,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes priority, default value is 100. 255 is for the router that owns the addresses.
+    pub fn priority(self, priority: u8) -> Self {
+        assert!(priority > 0, "priority must be > 0");
+        VrrpComposite {
+            in_stream: self.in_stream,
+            vrid: self.vrid,
+            priority,
+            addresses: self.addresses,
+            primary_addr: self.primary_addr,
+            advert_interval: self.advert_interval,
+            preempt: self.preempt,
+            status: self.status,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// The virtual addresses the master answers for.
+    pub fn addresses(self, addresses: Vec<Ipv4Addr>) -> Self {
+        VrrpComposite {
+            in_stream: self.in_stream,
+            vrid: self.vrid,
+            priority: self.priority,
+            addresses,
+            primary_addr: self.primary_addr,
+            advert_interval: self.advert_interval,
+            preempt: self.preempt,
+            status: self.status,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// The router's own address on the LAN, which advertisements are sent from.
+    pub fn primary_addr(self, primary_addr: Ipv4Addr) -> Self {
+        VrrpComposite {
+            in_stream: self.in_stream,
+            vrid: self.vrid,
+            priority: self.priority,
+            addresses: self.addresses,
+            primary_addr: Some(primary_addr),
+            advert_interval: self.advert_interval,
+            preempt: self.preempt,
+            status: self.status,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes advert_interval, the time between the master's advertisements, in steps of 10
+    /// milliseconds up to 40.95 seconds, default value is 1 second.
+    pub fn advert_interval(self, advert_interval: Duration) -> Self {
+        assert!(
+            advert_interval >= Duration::from_millis(10)
+                && advert_interval <= Duration::from_millis(40950),
+            "advert_interval must be from 10ms to 40.95s"
+        );
+        VrrpComposite {
+            in_stream: self.in_stream,
+            vrid: self.vrid,
+            priority: self.priority,
+            addresses: self.addresses,
+            primary_addr: self.primary_addr,
+            advert_interval,
+            preempt: self.preempt,
+            status: self.status,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Changes preempt, whether a backup of higher priority than the master takes over from it,
+    /// default value is true.
+    pub fn preempt(self, preempt: bool) -> Self {
+        VrrpComposite {
+            in_stream: self.in_stream,
+            vrid: self.vrid,
+            priority: self.priority,
+            addresses: self.addresses,
+            primary_addr: self.primary_addr,
+            advert_interval: self.advert_interval,
+            preempt,
+            status: self.status,
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Publishes whether the router is master here rather than in the `StateStore`.
+    pub fn vrrp_status(self, status: Arc<VrrpStatus>) -> Self {
+        VrrpComposite {
+            in_stream: self.in_stream,
+            vrid: self.vrid,
+            priority: self.priority,
+            addresses: self.addresses,
+            primary_addr: self.primary_addr,
+            advert_interval: self.advert_interval,
+            preempt: self.preempt,
+            status: Some(status),
+            drop_counters: self.drop_counters,
+        }
+    }
+
+    /// Counts the frames the composite drops as a backup, the master's to take, in
+    /// `drop_counters`.
+    pub fn drop_counters(self, drop_counters: Arc<DropCounters>) -> Self {
+        VrrpComposite {
+            in_stream: self.in_stream,
+            vrid: self.vrid,
+            priority: self.priority,
+            addresses: self.addresses,
+            primary_addr: self.primary_addr,
+            advert_interval: self.advert_interval,
+            preempt: self.preempt,
+            status: self.status,
+            drop_counters: Some(drop_counters),
+        }
+    }
+}
+
+impl LinkBuilder<EthernetFrame, EthernetFrame> for VrrpComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<EthernetFrame>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "VrrpComposite may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<EthernetFrame>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "VrrpComposite may only take 1 input stream",
+            ));
+        }
+        Ok(VrrpComposite {
+            in_stream: Some(in_stream),
+            vrid: self.vrid,
+            priority: self.priority,
+            addresses: self.addresses,
+            primary_addr: self.primary_addr,
+            advert_interval: self.advert_interval,
+            preempt: self.preempt,
+            status: self.status,
+            drop_counters: self.drop_counters,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<EthernetFrame>, LinkBuildError> {
+        let (in_stream, vrid, primary_addr) = match (self.in_stream, self.vrid, self.primary_addr) {
+            (None, _, _) => return Err(LinkBuildError::Missing("input stream")),
+            (_, None, _) => return Err(LinkBuildError::Missing("vrid")),
+            (_, _, None) => return Err(LinkBuildError::Missing("primary_addr")),
+            (Some(in_stream), Some(vrid), Some(primary_addr)) => (in_stream, vrid, primary_addr),
+        };
+        if self.addresses.is_empty() {
+            return Err(LinkBuildError::Missing("addresses"));
+        }
+        let status = self.status.unwrap_or_else(|| {
+            StateStore::global().get_or_insert_with(VRRP_STATUS_STATE, VrrpStatus::new)
+        });
+
+        let (mut runnables, mut fork_egressors) = ForkLink::new()
+            .try_ingressor(in_stream)?
+            .num_egressors(2)
+            .try_build_link()?;
+
+        // The virtual router starts as the link is built, so an owner is master before the first
+        // frame reaches the filter.
+        let now = clock::now();
+        let mut router = VirtualRouter {
+            vrid,
+            priority: self.priority,
+            addresses: self.addresses.clone(),
+            primary_addr,
+            advert_interval: self.advert_interval,
+            preempt: self.preempt,
+            state: VrrpState::Initialize,
+            master_advert_interval: self.advert_interval,
+            master_down_at: None,
+            next_advert: None,
+            status: Arc::clone(&status),
+        };
+        let frames = router.start(now).into_iter().collect();
+        let runner = VrrpRunner {
+            in_stream: fork_egressors.pop(),
+            router,
+            frames,
+            timer: delay_until(tokio::time::Instant::from_std(now)),
+        };
+
+        let (mut filter_runnables, mut egressors) = ProcessLink::new()
+            .try_ingressor(fork_egressors.pop().unwrap())?
+            .processor(VrrpFilter {
+                vrid,
+                addresses: self.addresses,
+                status,
+                drops: DropAccounting::new(self.drop_counters, None),
+            })
+            .try_build_link()?;
+        runnables.append(&mut filter_runnables);
+        egressors.push(Box::new(runner));
+
+        Ok((runnables, egressors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const ROUTER_A: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 2);
+    const ROUTER_B: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 3);
+    const HOST: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 100);
+
+    fn router(priority: u8, primary_addr: Ipv4Addr) -> VirtualRouter {
+        VirtualRouter {
+            vrid: 9,
+            priority,
+            addresses: vec![GATEWAY],
+            primary_addr,
+            advert_interval: Duration::from_secs(1),
+            preempt: true,
+            state: VrrpState::Initialize,
+            master_advert_interval: Duration::from_secs(1),
+            master_down_at: None,
+            next_advert: None,
+            status: Arc::new(VrrpStatus::new()),
+        }
+    }
+
+    fn arp_request_for(target: Ipv4Addr) -> EthernetFrame {
+        let mut arp = ArpFrame::new(6, 4);
+        arp.set_hardware_type(1)
+            .set_protocol_type(IPV4_ETHER_TYPE)
+            .set_opcode(ArpOp::Request as u16)
+            .set_sender_hardware_addr(MacAddr::new([2, 0, 0, 0, 0, 100]))
+            .set_sender_protocol_addr(IpAddr::V4(HOST))
+            .set_target_protocol_addr(IpAddr::V4(target));
+        let mut frame = arp.frame();
+        frame.set_ether_type(ARP_ETHER_TYPE);
+        frame
+    }
+
+    #[test]
+    fn elects_the_higher_priority() {
+        let start = Instant::now();
+        let mut a = router(200, ROUTER_A);
+        let mut b = router(100, ROUTER_B);
+        assert!(a.start(start).is_empty());
+        assert!(b.start(start).is_empty());
+        assert_eq!(b.status.state(), VrrpState::Backup);
+
+        // A's master down interval, 3 seconds and a skew of 56/256ths of one, ends first.
+        assert!(a.expire(start + Duration::from_millis(3218)).is_empty());
+        let sent = a.expire(start + Duration::from_millis(3219));
+        assert!(a.status.is_master());
+        assert_eq!(sent.len(), 2);
+        let vrrp = advertisement(&sent[0]).unwrap();
+        assert_eq!(vrrp.priority(), 200);
+        assert_eq!(vrrp.addresses(), vec![GATEWAY]);
+        assert_eq!(sent[0].src_mac(), vrrp_virtual_mac(9));
+        let arp = ArpFrame::try_from(sent[1].clone()).unwrap();
+        assert_eq!(arp.sender_protocol_addr(), GATEWAY.octets());
+        assert_eq!(arp.target_protocol_addr(), GATEWAY.octets());
+
+        // B hears A, and stays backup past its own master down interval.
+        let heard = start + Duration::from_millis(3219);
+        b.receive(&sent[0], heard);
+        assert!(b.expire(heard + Duration::from_millis(3000)).is_empty());
+        assert_eq!(b.status.state(), VrrpState::Backup);
+
+        // A resigns, and B takes over after its skew.
+        let resigned = a.shutdown();
+        b.receive(&resigned[0], heard + Duration::from_secs(1));
+        b.expire(heard + Duration::from_millis(1610));
+        assert!(b.status.is_master());
+    }
+
+    #[test]
+    fn master_answers_arp_and_yields_to_preemption() {
+        let now = Instant::now();
+        let mut b = router(100, ROUTER_B);
+        b.start(now);
+        b.expire(now + Duration::from_secs(4));
+        assert!(b.status.is_master());
+
+        let reply = b.receive(&arp_request_for(GATEWAY), now);
+        let arp = ArpFrame::try_from(reply[0].clone()).unwrap();
+        assert_eq!(arp.opcode(), ArpOp::Reply as u16);
+        assert_eq!(arp.sender_hardware_addr(), vrrp_virtual_mac(9).bytes);
+        assert_eq!(arp.target_protocol_addr(), HOST.octets());
+        assert!(b.receive(&arp_request_for(ROUTER_A), now).is_empty());
+
+        // The owner of the address comes up, and takes it back.
+        let mut owner = router(VRRP_OWNER_PRIORITY, ROUTER_A);
+        let sent = owner.start(now);
+        assert!(owner.status.is_master());
+        b.receive(&sent[0], now);
+        assert_eq!(b.status.state(), VrrpState::Backup);
+        assert!(b.receive(&arp_request_for(GATEWAY), now).is_empty());
+    }
+
+    #[test]
+    fn owner_filters_and_advertises() {
+        let status = Arc::new(VrrpStatus::new());
+        let mut to_gateway = Ipv4Packet::empty();
+        to_gateway.set_dest_addr(GATEWAY);
+        let frames = vec![
+            arp_request_for(GATEWAY),
+            arp_request_for(ROUTER_A),
+            EthernetFrame::encap_ipv4(to_gateway),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = VrrpComposite::new()
+                .vrid(9)
+                .priority(VRRP_OWNER_PRIORITY)
+                .addresses(vec![GATEWAY])
+                .primary_addr(ROUTER_A)
+                .vrrp_status(Arc::clone(&status))
+                .ingressor(immediate_stream(frames))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].len(), 2);
+        assert_eq!(results[0][1].ether_type(), IPV4_ETHER_TYPE);
+
+        // An advertisement and gratuitous ARP on taking over, the ARP reply, and the
+        // advertisement of priority 0 on shutting down.
+        assert_eq!(results[1].len(), 4);
+        assert_eq!(advertisement(&results[1][0]).unwrap().priority(), 255);
+        assert_eq!(results[1][2].dest_mac(), MacAddr::new([2, 0, 0, 0, 0, 100]));
+        assert_eq!(advertisement(&results[1][3]).unwrap().priority(), 0);
+        assert_eq!(status.state(), VrrpState::Initialize);
+    }
+
+    #[test]
+    fn backup_drops_the_masters_traffic() {
+        let drop_counters = Arc::new(DropCounters::new());
+        let mut filter = VrrpFilter {
+            vrid: 9,
+            addresses: vec![GATEWAY],
+            status: Arc::new(VrrpStatus::new()),
+            drops: DropAccounting::new(Some(Arc::clone(&drop_counters)), None),
+        };
+        let mut to_gateway = Ipv4Packet::empty();
+        to_gateway.set_dest_addr(GATEWAY);
+        let mut to_host = Ipv4Packet::empty();
+        to_host.set_dest_addr(HOST);
+
+        assert!(filter
+            .process(EthernetFrame::encap_ipv4(to_gateway))
+            .is_none());
+        assert!(filter.process(EthernetFrame::encap_ipv4(to_host)).is_some());
+        assert_eq!(drop_counters.snapshot(), vec![(DropReason::Discarded, 1)]);
+    }
+}
@@ -0,0 +1,796 @@
+use crate::classifier::Classifier;
+use crate::link::composite::{BfdStatus, BFD_STATUS_STATE};
+use crate::link::primitive::ClassifyLink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::processor::transport_checksum;
+use crate::state::StateStore;
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::{internet_checksum, IpProtocol, Ipv4Packet};
+use std::collections::VecDeque;
+use std::net::Ipv4Addr;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::time::{delay_until, Delay};
+
+/// The name the WAN status is shared under in the `StateStore`, unless the composite is given its
+/// own `WanStatus`.
+pub const WAN_STATUS_STATE: &str = "wan-status";
+
+/// The port TCP probes are sent from, so their answers can be told from other traffic to the
+/// router.
+pub const TCP_PROBE_PORT: u16 = 61000;
+
+/// The identifier of the ICMP echo requests sent as probes.
+const ECHO_IDENTIFIER: u16 = 0x7766;
+
+/// RFC 792.
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+/// RFC 793 section 3.1.
+const TCP_SYN: u16 = 0x02;
+const TCP_RST: u16 = 0x04;
+const TCP_ACK: u16 = 0x10;
+
+/// How the reachability of the internet through a WAN is checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// ICMP echo requests, answered by echo replies.
+    Icmp,
+    /// TCP SYNs to a port, answered by a SYN-ACK if it is open, or a RST if it is closed.
+    Tcp(u16),
+    /// The session of a `BfdComposite` at an index, the WAN being up while it is. No probes are
+    /// sent, and the WAN fails over as soon as the session goes down.
+    Bfd(usize),
+}
+
+/// One WAN a `WanFailoverComposite` sends traffic out of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WanBranch {
+    /// The router's address on the WAN, which probes are sent from.
+    pub addr: Ipv4Addr,
+    /// The host probed through the WAN, such as a public DNS server.
+    pub target: Ipv4Addr,
+    pub probe: Probe,
+}
+
+impl WanBranch {
+    fn probe(&self, sequence: u16) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_src_addr(self.addr);
+        packet.set_dest_addr(self.target);
+        match self.probe {
+            Probe::Icmp => {
+                let mut message = vec![ICMP_ECHO_REQUEST, 0, 0, 0];
+                message.extend_from_slice(&ECHO_IDENTIFIER.to_be_bytes());
+                message.extend_from_slice(&sequence.to_be_bytes());
+                let checksum = internet_checksum(&message);
+                message[2..4].copy_from_slice(&checksum.to_be_bytes());
+                packet.set_protocol(1);
+                packet.set_payload(&message);
+            }
+            Probe::Tcp(port) => {
+                let mut segment = vec![0; 20];
+                segment[0..2].copy_from_slice(&TCP_PROBE_PORT.to_be_bytes());
+                segment[2..4].copy_from_slice(&port.to_be_bytes());
+                segment[4..8].copy_from_slice(&u32::from(sequence).to_be_bytes());
+                segment[12] = 0x50;
+                segment[13] = TCP_SYN as u8;
+                segment[14..16].copy_from_slice(&1024u16.to_be_bytes());
+                let checksum = transport_checksum(self.addr, self.target, 6, &segment);
+                segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+                packet.set_protocol(6);
+                packet.set_payload(&segment);
+            }
+            Probe::Bfd(_) => unreachable!("WANs followed by BFD are not probed"),
+        }
+        packet.set_checksum();
+        packet
+    }
+
+    /// Whether `packet` answers the probe with `sequence`.
+    fn answers(&self, packet: &Ipv4Packet, sequence: u16) -> bool {
+        if packet.src_addr() != self.target || packet.dest_addr() != self.addr {
+            return false;
+        }
+        let payload = packet.payload();
+        match self.probe {
+            Probe::Icmp => {
+                packet.protocol() == IpProtocol::ICMP
+                    && payload.len() >= 8
+                    && payload[0] == ICMP_ECHO_REPLY
+                    && payload[4..6] == ECHO_IDENTIFIER.to_be_bytes()
+                    && payload[6..8] == sequence.to_be_bytes()
+            }
+            Probe::Tcp(port) => {
+                let flags = u16::from(payload.get(13).copied().unwrap_or(0));
+                packet.protocol() == IpProtocol::TCP
+                    && payload.len() >= 20
+                    && payload[0..2] == port.to_be_bytes()
+                    && payload[2..4] == TCP_PROBE_PORT.to_be_bytes()
+                    && payload[8..12] == (u32::from(sequence) + 1).to_be_bytes()
+                    && flags & TCP_ACK != 0
+                    && flags & (TCP_SYN | TCP_RST) != 0
+            }
+            Probe::Bfd(_) => false,
+        }
+    }
+}
+
+struct WanState {
+    up: Vec<bool>,
+    active: usize,
+    failovers: u64,
+}
+
+/// Which of the WANs of a `WanFailoverComposite` are up, and which traffic is sent out of.
+pub struct WanStatus {
+    state: RwLock<WanState>,
+}
+
+impl WanStatus {
+    pub fn new() -> Self {
+        WanStatus {
+            state: RwLock::new(WanState {
+                up: vec![],
+                active: 0,
+                failovers: 0,
+            }),
+        }
+    }
+
+    /// The index of the WAN traffic is sent out of.
+    pub fn active(&self) -> usize {
+        self.state.read().unwrap().active
+    }
+
+    /// Whether the WAN at `wan` answers its probes.
+    pub fn is_up(&self, wan: usize) -> bool {
+        self.state
+            .read()
+            .unwrap()
+            .up
+            .get(wan)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// How many times traffic has moved from one WAN to another.
+    pub fn failovers(&self) -> u64 {
+        self.state.read().unwrap().failovers
+    }
+
+    fn set(&self, up: Vec<bool>, active: usize) {
+        let mut state = self.state.write().unwrap();
+        if active != state.active && !state.up.is_empty() {
+            state.failovers += 1;
+        }
+        state.up = up;
+        state.active = active;
+    }
+}
+
+impl Default for WanStatus {
+    fn default() -> Self {
+        WanStatus::new()
+    }
+}
+
+/// How the probes of one WAN have been answered lately.
+#[derive(Default)]
+struct Health {
+    down: bool,
+    /// Probes answered, or missed, in a row.
+    answered: u32,
+    missed: u32,
+    /// The sequence number of the probe waiting for an answer.
+    outstanding: Option<u16>,
+}
+
+struct WanMonitor {
+    wans: Vec<WanBranch>,
+    health: Vec<Health>,
+    down_after: u32,
+    up_after: u32,
+    sequence: u16,
+    active: usize,
+    status: Arc<WanStatus>,
+    bfd: Option<Arc<BfdStatus>>,
+    /// The number of BFD state changes the health of the WANs was last taken from.
+    bfd_changes: Option<u64>,
+}
+
+impl WanMonitor {
+    fn new(wans: Vec<WanBranch>, down_after: u32, up_after: u32, status: Arc<WanStatus>) -> Self {
+        let health = wans.iter().map(|_| Health::default()).collect();
+        let monitor = WanMonitor {
+            wans,
+            health,
+            down_after,
+            up_after,
+            sequence: 0,
+            active: 0,
+            status,
+            bfd: None,
+            bfd_changes: None,
+        };
+        monitor.publish();
+        monitor
+    }
+
+    /// Counts the probes still unanswered as missed, and sends the next round.
+    fn probe(&mut self) -> Vec<Ipv4Packet> {
+        self.sequence = self.sequence.wrapping_add(1);
+        for wan in 0..self.wans.len() {
+            if let Probe::Bfd(_) = self.wans[wan].probe {
+                continue;
+            }
+            if self.health[wan].outstanding.is_some() {
+                self.record(wan, false);
+            }
+            self.health[wan].outstanding = Some(self.sequence);
+        }
+        self.follow_bfd();
+        self.select();
+        self.wans
+            .iter()
+            .filter(|wan| !matches!(wan.probe, Probe::Bfd(_)))
+            .map(|wan| wan.probe(self.sequence))
+            .collect()
+    }
+
+    fn receive(&mut self, packet: &Ipv4Packet) {
+        for wan in 0..self.wans.len() {
+            if let Some(sequence) = self.health[wan].outstanding {
+                if self.wans[wan].answers(packet, sequence) {
+                    self.health[wan].outstanding = None;
+                    self.record(wan, true);
+                    self.select();
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, wan: usize, answered: bool) {
+        let health = &mut self.health[wan];
+        if answered {
+            health.answered += 1;
+            health.missed = 0;
+            if health.down && health.answered >= self.up_after {
+                health.down = false;
+            }
+        } else {
+            health.missed += 1;
+            health.answered = 0;
+            if !health.down && health.missed >= self.down_after {
+                health.down = true;
+            }
+        }
+    }
+
+    /// Takes the health of the WANs followed by BFD from their sessions, if any has changed state
+    /// since it last was.
+    fn follow_bfd(&mut self) {
+        let bfd = match &self.bfd {
+            Some(bfd) => Arc::clone(bfd),
+            None => return,
+        };
+        let changes = bfd.changes();
+        if self.bfd_changes == Some(changes) {
+            return;
+        }
+        self.bfd_changes = Some(changes);
+        for (wan, branch) in self.wans.iter().enumerate() {
+            if let Probe::Bfd(session) = branch.probe {
+                self.health[wan].down = !bfd.is_up(session);
+            }
+        }
+        self.select();
+    }
+
+    /// Sends traffic out of the first WAN that is up. While none are, it stays where it was.
+    fn select(&mut self) {
+        if let Some(wan) = self.health.iter().position(|health| !health.down) {
+            self.active = wan;
+        }
+        self.publish();
+    }
+
+    fn publish(&self) {
+        let up = self.health.iter().map(|health| !health.down).collect();
+        self.status.set(up, self.active);
+    }
+}
+
+struct WanFailoverRunner {
+    in_stream: PacketStream<Ipv4Packet>,
+    reply_stream: Option<PacketStream<Ipv4Packet>>,
+    monitor: WanMonitor,
+    probes: VecDeque<Ipv4Packet>,
+    interval: Duration,
+    next_probe: Instant,
+    timer: Delay,
+}
+
+impl Unpin for WanFailoverRunner {}
+
+impl Stream for WanFailoverRunner {
+    type Item = Ipv4Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let runner = &mut *self;
+        loop {
+            if let Some(probe) = runner.probes.pop_front() {
+                return Poll::Ready(Some(probe));
+            }
+            if clock::now() >= runner.next_probe {
+                runner.probes.extend(runner.monitor.probe());
+                runner.next_probe += runner.interval;
+                continue;
+            }
+            while let Some(reply_stream) = runner.reply_stream.as_mut() {
+                match Pin::new(reply_stream).poll_next(cx) {
+                    Poll::Ready(Some(reply)) => runner.monitor.receive(&reply),
+                    Poll::Ready(None) => runner.reply_stream = None,
+                    Poll::Pending => break,
+                }
+            }
+            match Pin::new(&mut runner.in_stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    runner.monitor.follow_bfd();
+                    return Poll::Ready(Some(packet));
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => {}
+            }
+
+            runner
+                .timer
+                .reset(tokio::time::Instant::from_std(runner.next_probe));
+            if Pin::new(&mut runner.timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Sends packets from a WAN's own address out of that WAN, and the rest out of the active one.
+struct ByWan {
+    addrs: Vec<Ipv4Addr>,
+    status: Arc<WanStatus>,
+}
+
+impl Classifier for ByWan {
+    type Packet = Ipv4Packet;
+    type Class = usize;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        let src_addr = packet.src_addr();
+        self.addrs
+            .iter()
+            .position(|addr| *addr == src_addr)
+            .unwrap_or_else(|| self.status.active())
+    }
+}
+
+/// Sends IPv4 traffic out of the first of several WANs that can reach the internet, failing over
+/// to the next when it can't, and back once it can again.
+///
+/// The composite has an egressor for each WAN given to `wan`, in the order given, which is their
+/// order of preference. Every `interval`, it sends each WAN a probe, from the router's address on
+/// it to its target. A WAN that misses `down_after` probes in a row is down, and one that is down
+/// is back up only once it has answered `up_after` in a row, so a WAN that drops the odd probe
+/// doesn't have traffic flapping in and out of it.
+///
+/// A WAN checked with `Probe::Bfd` is instead up while its BFD session is, as published in the
+/// `BfdStatus` given to `bfd_status`, or shared in the `StateStore` under `BFD_STATUS_STATE`, and
+/// fails over as soon as the session goes down, without waiting for the next interval.
+///
+/// The composite takes the traffic to route, and the packets received on the WANs for the
+/// router's addresses there, to the `reply_ingressor`: ICMP echo replies, and TCP segments to port
+/// `TCP_PROBE_PORT`. Those answering probes are counted, and all of them dropped. Traffic from the
+/// router's address on a WAN leaves that WAN, whether it is up or not, and any other the active
+/// WAN. The WANs that are up, and the active one, are published in a `WanStatus`, shared in the
+/// `StateStore` under `WAN_STATUS_STATE` unless one is given. The composite ends when its
+/// ingressor does.
+pub struct WanFailoverComposite {
+    in_stream: Option<PacketStream<Ipv4Packet>>,
+    reply_stream: Option<PacketStream<Ipv4Packet>>,
+    wans: Vec<WanBranch>,
+    interval: Duration,
+    down_after: u32,
+    up_after: u32,
+    status: Option<Arc<WanStatus>>,
+    bfd_status: Option<Arc<BfdStatus>>,
+}
+
+impl Default for WanFailoverComposite {
+    fn default() -> Self {
+        WanFailoverComposite::new()
+    }
+}
+
+impl WanFailoverComposite {
+    pub fn new() -> Self {
+        WanFailoverComposite {
+            in_stream: None,
+            reply_stream: None,
+            wans: vec![],
+            interval: Duration::from_secs(1),
+            down_after: 3,
+            up_after: 5,
+            status: None,
+            bfd_status: None,
+        }
+    }
+
+    /// The ingressor of the packets received on the WANs for the router's addresses there.
+    pub fn reply_ingressor(self, reply_stream: PacketStream<Ipv4Packet>) -> Self {
+        WanFailoverComposite {
+            in_stream: self.in_stream,
+            reply_stream: Some(reply_stream),
+            wans: self.wans,
+            interval: self.interval,
+            down_after: self.down_after,
+            up_after: self.up_after,
+            status: self.status,
+            bfd_status: self.bfd_status,
+        }
+    }
+
+    /// Adds a WAN, after those already added, where the router's address is `addr`, checked by
+    /// sending `probe`s to `target`.
+    pub fn wan(self, addr: Ipv4Addr, target: Ipv4Addr, probe: Probe) -> Self {
+        let mut wans = self.wans;
+        wans.push(WanBranch {
+            addr,
+            target,
+            probe,
+        });
+        WanFailoverComposite {
+            in_stream: self.in_stream,
+            reply_stream: self.reply_stream,
+            wans,
+            interval: self.interval,
+            down_after: self.down_after,
+            up_after: self.up_after,
+            status: self.status,
+            bfd_status: self.bfd_status,
+        }
+    }
+
+    /// Changes interval, the time between probes of each WAN, and the time each has to be
+    /// answered, default value is 1 second.
+    pub fn interval(self, interval: Duration) -> Self {
+        assert!(interval > Duration::from_secs(0), "interval must be > 0");
+        WanFailoverComposite {
+            in_stream: self.in_stream,
+            reply_stream: self.reply_stream,
+            wans: self.wans,
+            interval,
+            down_after: self.down_after,
+            up_after: self.up_after,
+            status: self.status,
+            bfd_status: self.bfd_status,
+        }
+    }
+
+    /// Changes down_after, the number of probes a WAN must miss in a row to be down, default
+    /// value is 3.
+    pub fn down_after(self, down_after: u32) -> Self {
+        assert!(down_after > 0, "down_after must be > 0");
+        WanFailoverComposite {
+            in_stream: self.in_stream,
+            reply_stream: self.reply_stream,
+            wans: self.wans,
+            interval: self.interval,
+            down_after,
+            up_after: self.up_after,
+            status: self.status,
+            bfd_status: self.bfd_status,
+        }
+    }
+
+    /// Changes up_after, the number of probes a WAN that is down must answer in a row to be up
+    /// again, default value is 5.
+    pub fn up_after(self, up_after: u32) -> Self {
+        assert!(up_after > 0, "up_after must be > 0");
+        WanFailoverComposite {
+            in_stream: self.in_stream,
+            reply_stream: self.reply_stream,
+            wans: self.wans,
+            interval: self.interval,
+            down_after: self.down_after,
+            up_after,
+            status: self.status,
+            bfd_status: self.bfd_status,
+        }
+    }
+
+    /// Follows the BFD sessions of `Probe::Bfd` WANs here rather than in the `StateStore`.
+    pub fn bfd_status(self, bfd_status: Arc<BfdStatus>) -> Self {
+        WanFailoverComposite {
+            in_stream: self.in_stream,
+            reply_stream: self.reply_stream,
+            wans: self.wans,
+            interval: self.interval,
+            down_after: self.down_after,
+            up_after: self.up_after,
+            status: self.status,
+            bfd_status: Some(bfd_status),
+        }
+    }
+
+    /// Publishes the status of the WANs here rather than in the `StateStore`.
+    pub fn wan_status(self, status: Arc<WanStatus>) -> Self {
+        WanFailoverComposite {
+            in_stream: self.in_stream,
+            reply_stream: self.reply_stream,
+            wans: self.wans,
+            interval: self.interval,
+            down_after: self.down_after,
+            up_after: self.up_after,
+            status: Some(status),
+            bfd_status: self.bfd_status,
+        }
+    }
+}
+
+impl LinkBuilder<Ipv4Packet, Ipv4Packet> for WanFailoverComposite {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Ipv4Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "WanFailoverComposite may only take 1 input stream, and 1 reply input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Ipv4Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "WanFailoverComposite may only take 1 input stream, and 1 reply input stream",
+            ));
+        }
+        Ok(WanFailoverComposite {
+            in_stream: Some(in_stream),
+            reply_stream: self.reply_stream,
+            wans: self.wans,
+            interval: self.interval,
+            down_after: self.down_after,
+            up_after: self.up_after,
+            status: self.status,
+            bfd_status: self.bfd_status,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ipv4Packet>, LinkBuildError> {
+        match (self.in_stream, self.reply_stream) {
+            (None, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None) => Err(LinkBuildError::Missing("reply input stream")),
+            (_, _) if self.wans.is_empty() => Err(LinkBuildError::Missing("wan")),
+            (Some(in_stream), Some(reply_stream)) => {
+                let status = self.status.unwrap_or_else(|| {
+                    StateStore::global().get_or_insert_with(WAN_STATUS_STATE, WanStatus::new)
+                });
+                let addrs = self.wans.iter().map(|wan| wan.addr).collect();
+                let num_egressors = self.wans.len();
+                let follows_bfd = self
+                    .wans
+                    .iter()
+                    .any(|wan| matches!(wan.probe, Probe::Bfd(_)));
+                let bfd_status = self.bfd_status;
+                let mut monitor = WanMonitor::new(
+                    self.wans,
+                    self.down_after,
+                    self.up_after,
+                    Arc::clone(&status),
+                );
+                if follows_bfd {
+                    monitor.bfd = Some(bfd_status.unwrap_or_else(|| {
+                        StateStore::global().get_or_insert_with(BFD_STATUS_STATE, BfdStatus::new)
+                    }));
+                    monitor.follow_bfd();
+                }
+                let now = clock::now();
+                let runner = WanFailoverRunner {
+                    in_stream,
+                    reply_stream: Some(reply_stream),
+                    monitor,
+                    probes: VecDeque::new(),
+                    interval: self.interval,
+                    next_probe: now,
+                    timer: delay_until(tokio::time::Instant::from_std(now)),
+                };
+                ClassifyLink::new()
+                    .try_ingressor(Box::new(runner))?
+                    .num_egressors(num_egressors)
+                    .classifier(ByWan { addrs, status })
+                    .dispatcher(Box::new(|wan| wan))
+                    .try_build_link()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::BfdState;
+
+    const PRIMARY: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 2);
+    const BACKUP: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 2);
+    const TARGET: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 53);
+    const HOST: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 10);
+
+    fn monitor(probe: Probe) -> WanMonitor {
+        WanMonitor::new(
+            vec![
+                WanBranch {
+                    addr: PRIMARY,
+                    target: TARGET,
+                    probe,
+                },
+                WanBranch {
+                    addr: BACKUP,
+                    target: TARGET,
+                    probe,
+                },
+            ],
+            3,
+            2,
+            Arc::new(WanStatus::new()),
+        )
+    }
+
+    /// The answer the target gives to a probe.
+    fn answer(probe: &Ipv4Packet) -> Ipv4Packet {
+        let mut reply = probe.clone();
+        reply.set_src_addr(probe.dest_addr());
+        reply.set_dest_addr(probe.src_addr());
+        let mut payload = probe.payload().to_vec();
+        if probe.protocol() == IpProtocol::ICMP {
+            payload[0] = ICMP_ECHO_REPLY;
+        } else {
+            payload.swap(0, 2);
+            payload.swap(1, 3);
+            let sequence = u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]);
+            payload[8..12].copy_from_slice(&(sequence + 1).to_be_bytes());
+            payload[13] = (TCP_RST | TCP_ACK) as u8;
+        }
+        reply.set_payload(&payload);
+        reply
+    }
+
+    #[test]
+    fn fails_over_and_back_with_hysteresis() {
+        let mut monitor = monitor(Probe::Icmp);
+        let status = Arc::clone(&monitor.status);
+        assert_eq!(status.active(), 0);
+
+        // The primary misses three probes in a row, while the backup answers them.
+        let mut probes = monitor.probe();
+        for _ in 0..3 {
+            monitor.receive(&answer(&probes[1]));
+            probes = monitor.probe();
+        }
+        assert!(!status.is_up(0));
+        assert!(status.is_up(1));
+        assert_eq!(status.active(), 1);
+        assert_eq!(status.failovers(), 1);
+
+        // It answers one, which is not yet enough to fail back.
+        monitor.receive(&answer(&probes[0]));
+        monitor.receive(&answer(&probes[1]));
+        assert_eq!(status.active(), 1);
+
+        // A stale answer counts for nothing.
+        probes = monitor.probe();
+        monitor.receive(&answer(&monitor.wans[0].probe(1)));
+        assert_eq!(status.active(), 1);
+
+        monitor.receive(&answer(&probes[0]));
+        assert!(status.is_up(0));
+        assert_eq!(status.active(), 0);
+        assert_eq!(status.failovers(), 2);
+    }
+
+    #[test]
+    fn probes_over_tcp() {
+        let mut monitor = monitor(Probe::Tcp(53));
+        let probes = monitor.probe();
+        let segment = probes[0].payload();
+        assert_eq!(probes[0].protocol(), IpProtocol::TCP);
+        assert_eq!(&segment[0..4], &[0xee, 0x48, 0, 53]);
+        assert_eq!(u16::from(segment[13]), TCP_SYN);
+        assert_eq!(transport_checksum(PRIMARY, TARGET, 6, &segment), 0);
+
+        // A closed port still shows the target is reachable.
+        for _ in 0..3 {
+            monitor.probe();
+        }
+        assert_eq!(monitor.status.active(), 0);
+        assert!(!monitor.status.is_up(0));
+        for _ in 0..2 {
+            let probes = monitor.probe();
+            monitor.receive(&answer(&probes[0]));
+        }
+        assert!(monitor.status.is_up(0));
+    }
+
+    #[test]
+    fn routes_by_source_and_active_wan() {
+        let packets: Vec<Ipv4Packet> = [HOST, BACKUP, HOST]
+            .iter()
+            .map(|src_addr| {
+                let mut packet = Ipv4Packet::empty();
+                packet.set_src_addr(*src_addr);
+                packet.set_dest_addr(TARGET);
+                packet
+            })
+            .collect();
+        let status = Arc::new(WanStatus::new());
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = WanFailoverComposite::new()
+                .wan(PRIMARY, TARGET, Probe::Icmp)
+                .wan(BACKUP, TARGET, Probe::Tcp(443))
+                .wan_status(Arc::clone(&status))
+                .reply_ingressor(immediate_stream(vec![]))
+                .ingressor(immediate_stream(packets))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].len(), 3);
+        assert_eq!(results[0][0].protocol(), IpProtocol::ICMP);
+        assert!(results[0][1..].iter().all(|p| p.src_addr() == HOST));
+        assert_eq!(results[1].len(), 2);
+        assert_eq!(results[1][0].protocol(), IpProtocol::TCP);
+        assert_eq!(results[1][1].src_addr(), BACKUP);
+        assert!(status.is_up(0));
+    }
+
+    #[test]
+    fn follows_bfd_sessions() {
+        let mut monitor = monitor(Probe::Icmp);
+        monitor.wans[0].probe = Probe::Bfd(0);
+        let bfd = Arc::new(BfdStatus::new());
+        monitor.bfd = Some(Arc::clone(&bfd));
+
+        // Only the WAN not followed by BFD is probed, and the other is down until its session is up.
+        let probes = monitor.probe();
+        assert_eq!(probes.len(), 1);
+        assert_eq!(probes[0].src_addr(), BACKUP);
+        assert_eq!(monitor.status.active(), 1);
+
+        bfd.set(0, BfdState::Up);
+        monitor.follow_bfd();
+        assert_eq!(monitor.status.active(), 0);
+
+        bfd.set(0, BfdState::Down);
+        monitor.follow_bfd();
+        assert!(!monitor.status.is_up(0));
+        assert_eq!(monitor.status.active(), 1);
+    }
+
+    #[test]
+    fn requires_a_wan() {
+        let result = WanFailoverComposite::new()
+            .reply_ingressor(immediate_stream(vec![]))
+            .ingressor(immediate_stream(vec![]))
+            .try_build_link();
+        assert!(matches!(result, Err(LinkBuildError::Missing("wan"))));
+    }
+}
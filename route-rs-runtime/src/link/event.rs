@@ -0,0 +1,401 @@
+use crate::link::message::ControlMsg;
+use crate::metrics::Registry;
+use crossbeam::crossbeam_channel::Sender;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Something that happened to a packet in a link that the router may want to know about, reported
+/// on the `EventSink` the link was built with. Each event carries the name the sink was given, so
+/// one channel can collect the events of every link in a pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The link dropped a packet, such as one its processor filtered out, or one with nowhere to
+    /// go.
+    Dropped {
+        link: Arc<str>,
+        reason: &'static str,
+    },
+    /// The link could not parse a packet, and dropped it.
+    ParseError { link: Arc<str>, error: String },
+    /// A queue of the link became full, so it stopped taking packets until the other side drains
+    /// it. Reported once each time the queue becomes full, not again until a packet has been
+    /// pushed onto it. Queues are numbered by the egressor they feed, or by the ingressor for a
+    /// `JoinLink`.
+    QueueOverflow { link: Arc<str>, queue: usize },
+    /// The link saw traffic it watches for, such as a host scanning the network.
+    Alert { link: Arc<str>, alert: String },
+    /// The link did something an operator may want in the router's logs, such as being delegated
+    /// a prefix or blocking a DNS query.
+    Activity { link: Arc<str>, activity: String },
+}
+
+impl LinkEvent {
+    /// Name of the link the event happened in.
+    pub fn link(&self) -> &str {
+        match self {
+            LinkEvent::Dropped { link, .. }
+            | LinkEvent::ParseError { link, .. }
+            | LinkEvent::QueueOverflow { link, .. }
+            | LinkEvent::Alert { link, .. }
+            | LinkEvent::Activity { link, .. } => link,
+        }
+    }
+}
+
+impl fmt::Display for LinkEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkEvent::Dropped { link, reason } => {
+                write!(f, "{}: dropped packet, {}", link, reason)
+            }
+            LinkEvent::ParseError { link, error } => {
+                write!(f, "{}: could not parse packet, {}", link, error)
+            }
+            LinkEvent::QueueOverflow { link, queue } => {
+                write!(f, "{}: queue {} became full", link, queue)
+            }
+            LinkEvent::Alert { link, alert } => write!(f, "{}: {}", link, alert),
+            LinkEvent::Activity { link, activity } => write!(f, "{}: {}", link, activity),
+        }
+    }
+}
+
+/// Where a link reports its `LinkEvent`s. Links only report events if they are built with a sink,
+/// and otherwise keep to what they have always done, dropping silently or panicking.
+///
+/// Events are sent without blocking, so a link never waits on whoever reads them. If the channel
+/// is full, the event is lost, which a bounded channel can use to cap the cost of a flood of drops.
+/// Drops are also counted in the global metrics `Registry`, as `<link>.dropped`, whether or not
+/// their events are lost.
+///
+/// With `grant_credits`, the sink also grants a `ControlMsg::Credit` for each packet dropped or
+/// unparsable, so the `InputChannelLink` reading for a pipeline gets back the credits of packets
+/// that never reach the `OutputChannelLink` at its end, which would otherwise be lost for good.
+#[derive(Clone)]
+pub struct EventSink {
+    link: Arc<str>,
+    sender: Sender<LinkEvent>,
+    dropped: Arc<AtomicU64>,
+    control: Option<Sender<ControlMsg>>,
+}
+
+impl EventSink {
+    /// Reports the events of the link named `link` on `sender`. Sinks of several links may share
+    /// a channel.
+    pub fn new(link: &str, sender: Sender<LinkEvent>) -> Self {
+        EventSink {
+            link: Arc::from(link),
+            sender,
+            dropped: Registry::global().counter(&format!("{}.dropped", link)),
+            control: None,
+        }
+    }
+
+    /// Grants a credit on `control` for each packet dropped.
+    pub fn grant_credits(self, control: Sender<ControlMsg>) -> Self {
+        EventSink {
+            link: self.link,
+            sender: self.sender,
+            dropped: self.dropped,
+            control: Some(control),
+        }
+    }
+
+    pub fn link(&self) -> &str {
+        &self.link
+    }
+
+    pub fn dropped(&self, reason: &'static str) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        self.report(LinkEvent::Dropped {
+            link: Arc::clone(&self.link),
+            reason,
+        });
+        self.grant();
+    }
+
+    pub fn parse_error(&self, error: &str) {
+        self.report(LinkEvent::ParseError {
+            link: Arc::clone(&self.link),
+            error: String::from(error),
+        });
+        self.grant();
+    }
+
+    pub fn queue_overflow(&self, queue: usize) {
+        self.report(LinkEvent::QueueOverflow {
+            link: Arc::clone(&self.link),
+            queue,
+        });
+    }
+
+    pub fn alert(&self, alert: String) {
+        self.report(LinkEvent::Alert {
+            link: Arc::clone(&self.link),
+            alert,
+        });
+    }
+
+    pub fn activity(&self, activity: String) {
+        self.report(LinkEvent::Activity {
+            link: Arc::clone(&self.link),
+            activity,
+        });
+    }
+
+    fn report(&self, event: LinkEvent) {
+        let _ = self.sender.try_send(event);
+    }
+
+    /// Unlike events, credits are never lost, or the ingress link would read less and less.
+    fn grant(&self) {
+        if let Some(control) = &self.control {
+            let _ = control.send(ControlMsg::Credit(1));
+        }
+    }
+}
+
+/// Why a packet was dropped, as a `DropLink` counts it, so the drops of every branch of a router
+/// are accounted for under the same few reasons rather than strings that differ by link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    /// Dropped by choice, such as by a `DropLink` at the end of a branch nothing else wants.
+    Discarded,
+    /// Filtered out, by a processor or firewall rule.
+    Filtered,
+    /// Classified as nothing the router handles.
+    Unclassified,
+    /// Addressed somewhere the router has no route to.
+    NoRoute,
+    /// Its time to live ran out.
+    TtlExpired,
+    /// Too short, or otherwise not what its headers claim.
+    Malformed,
+    /// Too big for the MTU of the interface it was bound for, and could not be fragmented.
+    TooBig,
+    /// Failed an integrity check, such as a message authenticator, or was replayed.
+    Unauthenticated,
+    /// A queue it was bound for was full.
+    QueueFull,
+    /// Over a rate limit.
+    RateLimited,
+    /// From a source that is blocked.
+    Blocked,
+}
+
+impl DropReason {
+    /// Every reason, in the order `DropCounters` keeps them.
+    pub const ALL: [DropReason; 11] = [
+        DropReason::Discarded,
+        DropReason::Filtered,
+        DropReason::Unclassified,
+        DropReason::NoRoute,
+        DropReason::TtlExpired,
+        DropReason::Malformed,
+        DropReason::TooBig,
+        DropReason::Unauthenticated,
+        DropReason::QueueFull,
+        DropReason::RateLimited,
+        DropReason::Blocked,
+    ];
+
+    /// The reason as `LinkEvent::Dropped` reports it.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DropReason::Discarded => "discarded",
+            DropReason::Filtered => "filtered by processor",
+            DropReason::Unclassified => "unclassified",
+            DropReason::NoRoute => "no route",
+            DropReason::TtlExpired => "time to live expired",
+            DropReason::Malformed => "malformed packet",
+            DropReason::TooBig => "too big for MTU",
+            DropReason::Unauthenticated => "failed authentication",
+            DropReason::QueueFull => "queue full",
+            DropReason::RateLimited => "rate limited",
+            DropReason::Blocked => "source blocked",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Counts of dropped packets by `DropReason`, shared by the `DropLink`s of a router, and whatever
+/// reports its metrics, which can read them while it runs.
+#[derive(Debug, Default)]
+pub struct DropCounters {
+    counts: [AtomicU64; DropReason::ALL.len()],
+}
+
+impl DropCounters {
+    pub fn new() -> Self {
+        DropCounters::default()
+    }
+
+    /// Counts a packet dropped for `reason`.
+    pub fn count(&self, reason: DropReason) {
+        self.counts[reason.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The packets dropped for `reason` so far.
+    pub fn get(&self, reason: DropReason) -> u64 {
+        self.counts[reason.index()].load(Ordering::Relaxed)
+    }
+
+    /// The packets dropped for any reason so far.
+    pub fn total(&self) -> u64 {
+        DropReason::ALL.iter().map(|reason| self.get(*reason)).sum()
+    }
+
+    /// The reasons packets have been dropped for so far, with how many each.
+    pub fn snapshot(&self) -> Vec<(DropReason, u64)> {
+        DropReason::ALL
+            .iter()
+            .map(|reason| (*reason, self.get(*reason)))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+}
+
+/// The accounting of the packets a composite drops inside its own streams, as a `DropLink` does
+/// it: each is counted in the router's `DropCounters` under its `DropReason`, if the composite
+/// was given them, and reported to its event sink, if it has one.
+#[derive(Clone, Default)]
+pub(crate) struct DropAccounting {
+    counters: Option<Arc<DropCounters>>,
+    event_sink: Option<EventSink>,
+}
+
+impl DropAccounting {
+    pub(crate) fn new(counters: Option<Arc<DropCounters>>, event_sink: Option<EventSink>) -> Self {
+        DropAccounting {
+            counters,
+            event_sink,
+        }
+    }
+
+    /// Accounts for a packet dropped for `reason`.
+    pub(crate) fn dropped(&self, reason: DropReason) {
+        if let Some(counters) = &self.counters {
+            counters.count(reason);
+        }
+        if let Some(event_sink) = &self.event_sink {
+            event_sink.dropped(reason.as_str());
+        }
+    }
+
+    /// Accounts for a packet dropped as it could not be parsed, which is counted as
+    /// `DropReason::Malformed`, and reported as a `LinkEvent::ParseError` with what was wrong.
+    pub(crate) fn parse_error(&self, error: &str) {
+        if let Some(counters) = &self.counters {
+            counters.count(DropReason::Malformed);
+        }
+        if let Some(event_sink) = &self.event_sink {
+            event_sink.parse_error(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam::crossbeam_channel;
+
+    #[test]
+    fn names_events_after_the_sink() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let sink = EventSink::new("filter", sender);
+
+        sink.dropped("filtered by processor");
+        sink.queue_overflow(2);
+
+        let events: Vec<LinkEvent> = receiver.try_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                LinkEvent::Dropped {
+                    link: Arc::from("filter"),
+                    reason: "filtered by processor"
+                },
+                LinkEvent::QueueOverflow {
+                    link: Arc::from("filter"),
+                    queue: 2
+                },
+            ]
+        );
+        assert_eq!(events[1].link(), "filter");
+        assert_eq!(events[1].to_string(), "filter: queue 2 became full");
+    }
+
+    #[test]
+    fn never_blocks_on_a_full_channel() {
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        let sink = EventSink::new("parser", sender);
+
+        sink.parse_error("too short");
+        sink.parse_error("bad checksum");
+
+        let events: Vec<LinkEvent> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].to_string(),
+            "parser: could not parse packet, too short"
+        );
+    }
+
+    #[test]
+    fn counts_drops_lost_to_a_full_channel() {
+        let (sender, _receiver) = crossbeam_channel::bounded(1);
+        let sink = EventSink::new("event-test-policer", sender);
+
+        sink.dropped("rate limited");
+        sink.dropped("rate limited");
+        sink.clone().dropped("rate limited");
+
+        assert!(Registry::global()
+            .counters()
+            .contains(&(String::from("event-test-policer.dropped"), 3)));
+    }
+
+    #[test]
+    fn grants_credits_even_for_lost_events() {
+        let (sender, _receiver) = crossbeam_channel::bounded(1);
+        let (grant, control) = crossbeam_channel::unbounded();
+        let sink = EventSink::new("parser", sender).grant_credits(grant);
+
+        sink.dropped("malformed packet");
+        sink.parse_error("too short");
+        sink.queue_overflow(0);
+
+        let granted: Vec<ControlMsg> = control.try_iter().collect();
+        assert_eq!(granted, vec![ControlMsg::Credit(1), ControlMsg::Credit(1)]);
+    }
+
+    #[test]
+    fn counts_drops_by_reason() {
+        let counters = DropCounters::new();
+        counters.count(DropReason::NoRoute);
+        counters.count(DropReason::NoRoute);
+        counters.count(DropReason::Blocked);
+
+        assert_eq!(counters.get(DropReason::NoRoute), 2);
+        assert_eq!(counters.get(DropReason::Filtered), 0);
+        assert_eq!(counters.total(), 3);
+        assert_eq!(
+            counters.snapshot(),
+            vec![(DropReason::NoRoute, 2), (DropReason::Blocked, 1)]
+        );
+        assert!(DropReason::ALL
+            .iter()
+            .enumerate()
+            .all(|(index, reason)| reason.index() == index));
+    }
+}
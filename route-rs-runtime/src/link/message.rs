@@ -0,0 +1,61 @@
+/// A message for the processors and links of a pipeline, rather than a packet to route. Control
+/// messages travel the same queues as the packets around them, so everything sent before one has
+/// been seen by the time it arrives, and everything sent after it hasn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMsg {
+    /// Processors that keep tables or caches should empty them.
+    Flush,
+    /// A marker, with an id to tell markers apart. Whoever injected it can tell when it comes out
+    /// of the pipeline that every packet sent ahead of it has been handled.
+    Barrier(u64),
+    /// Credits for as many more packets, granted by the egress link of a pipeline to the ingress
+    /// link feeding it. Unlike the others, credits travel against the flow of packets, on a
+    /// channel of their own from the `OutputChannelLink` granting them to the `InputChannelLink`
+    /// spending them.
+    Credit(usize),
+}
+
+/// What the links of a pipeline that carries control messages pass along: either a packet, or a
+/// control message. This is the same idea as the `None` a link's ingressor sends its egressors to
+/// tear them down, open to any message the router's own processors need to see in order.
+///
+/// Links that don't look inside packets, such as `QueueLink`, `JoinLink`, `ForkLink` and the
+/// channel links, carry control messages as they would anything else. Processors and classifiers
+/// are made to forward them by wrapping them in `processor::ControlAware` and
+/// `classifier::ControlAwareClassifier`, which pass packets to the wrapped processor or classifier.
+/// `ClassifyLink` sends the control messages it is given to every egressor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouterMessage<P> {
+    Data(P),
+    Control(ControlMsg),
+}
+
+impl<P> RouterMessage<P> {
+    /// The packet of the message, or none if it is a control message.
+    pub fn data(self) -> Option<P> {
+        match self {
+            RouterMessage::Data(packet) => Some(packet),
+            RouterMessage::Control(_) => None,
+        }
+    }
+
+    /// The control message of the message, or none if it is a packet.
+    pub fn control(&self) -> Option<ControlMsg> {
+        match self {
+            RouterMessage::Data(_) => None,
+            RouterMessage::Control(msg) => Some(*msg),
+        }
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.control().is_some()
+    }
+
+    /// Changes the packet of the message with `f`, leaving control messages as they are.
+    pub fn map<Q, F: FnOnce(P) -> Q>(self, f: F) -> RouterMessage<Q> {
+        match self {
+            RouterMessage::Data(packet) => RouterMessage::Data(f(packet)),
+            RouterMessage::Control(msg) => RouterMessage::Control(msg),
+        }
+    }
+}
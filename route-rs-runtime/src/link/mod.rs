@@ -13,6 +13,7 @@
 //! chaining asynchronous computation together around Channels; freeing you to focus on the business logic you would like your router to implement.
 
 use crate::processor::Processor;
+use std::fmt;
 
 /// Composites are groups of links pre-assmebled to provide higher level functionality. They are highly customizable and users of the
 /// library are encourged to make their own to encourage code reuse.
@@ -26,6 +27,19 @@ pub mod primitive;
 /// Commmon utilities used by links, for instance the `task_park` utility used in primitive links to facilite sleeping and waking.
 pub mod utils;
 
+/// Events links report as packets are dropped or queues fill up, for the management and metrics
+/// layers of a router to subscribe to.
+pub mod event;
+
+/// Sends the events of a router to a syslog server, alongside the logs of the rest of the network.
+pub mod syslog;
+
+/// Control messages, such as flushes and barriers, that travel a pipeline in order with its packets.
+pub mod message;
+
+/// Composes links one after the other with `Chain`, which keeps track of their runnables.
+pub mod chain;
+
 /// All Links communicate through streams of packets. This allows them to be composable.
 pub type PacketStream<Input> = Box<dyn futures::Stream<Item = Input> + Send + Unpin>;
 /// Some Links may need to be driven by Tokio. This represents a handle to something Tokio can run.
@@ -33,26 +47,72 @@ pub type TokioRunnable = Box<dyn futures::Future<Output = ()> + Send + Unpin>;
 /// LinkBuilders build this.
 pub type Link<Output> = (Vec<TokioRunnable>, Vec<PacketStream<Output>>);
 
+/// Why a link could not be built, as returned by the `try_` methods of `LinkBuilder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkBuildError {
+    /// The link can't take the ingressors it was given, saying how many it takes.
+    Ingressors(&'static str),
+    /// The link was built without something it needs, such as its input streams or processor.
+    Missing(&'static str),
+    /// The link was given a setting it can't be built with.
+    Invalid(String),
+}
+
+impl fmt::Display for LinkBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkBuildError::Ingressors(message) => write!(f, "{}", message),
+            LinkBuildError::Missing(setting) => write!(f, "Cannot build link! Missing {}", setting),
+            LinkBuildError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for LinkBuildError {}
+
 /// `LinkBuilder` applies a builder pattern to create `Links`! `Links` should be created this way
 /// so they can be composed together
 ///
 /// The two type parameters, Input and Output, refer to the input and output types of the Link.
 /// You can tell because the ingress/egress streams are of type `PacketStream<Input>`/`PacketStream<Output>` respectively.
-pub trait LinkBuilder<Input, Output> {
+///
+/// Links implement the `try_` methods, which return an error where the link can't be built as
+/// asked, so that a pipeline built from configuration at runtime can report it. Generated
+/// pipelines are wired correctly by construction, and use the methods without the prefix, which
+/// panic with the error instead.
+pub trait LinkBuilder<Input, Output>: Sized {
     /// Links need a way to receive input from upstream.
     /// Some Links such as `ProcessLink` will only need at most 1, but others can accept many.
-    /// Links that can not support the number of ingressors provided will panic. Links that already have
-    /// ingressors will panic on calling this function, since we expect this is a user configuration error.
-    fn ingressors(self, in_streams: Vec<PacketStream<Input>>) -> Self;
+    /// Links that can not support the number of ingressors provided will fail. Links that already have
+    /// ingressors will fail on calling this function, since we expect this is a user configuration error.
+    fn try_ingressors(self, in_streams: Vec<PacketStream<Input>>) -> Result<Self, LinkBuildError>;
 
     /// Append ingressor to list of ingressors, works like push() for a Vector
-    /// If the link can not support the addition of another ingressor, it will panic.
-    fn ingressor(self, in_stream: PacketStream<Input>) -> Self;
+    /// If the link can not support the addition of another ingressor, it will fail.
+    fn try_ingressor(self, in_stream: PacketStream<Input>) -> Result<Self, LinkBuildError>;
 
     /// Provides any tokio-driven Futures needed to drive the Link, as well as handles for downstream
     /// `Link`s to use. This method consumes the `Link` since we want to move ownership of a `Link`'s
     /// runnables and egressors to the caller.
-    fn build_link(self) -> Link<Output>;
+    fn try_build_link(self) -> Result<Link<Output>, LinkBuildError>;
+
+    /// `try_ingressors`, panicking on error.
+    fn ingressors(self, in_streams: Vec<PacketStream<Input>>) -> Self {
+        self.try_ingressors(in_streams)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// `try_ingressor`, panicking on error.
+    fn ingressor(self, in_stream: PacketStream<Input>) -> Self {
+        self.try_ingressor(in_stream)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// `try_build_link`, panicking on error.
+    fn build_link(self) -> Link<Output> {
+        self.try_build_link()
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
 }
 
 /// `ProcessLink` and `QueueLink` impl `ProcessLinkBuilder`, since they are required to have their
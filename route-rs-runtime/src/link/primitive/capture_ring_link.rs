@@ -0,0 +1,602 @@
+use crate::link::event::{EventSink, LinkEvent};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, TokioRunnable};
+use crate::utils::clock;
+use crate::utils::pcap::{write_pcap, LINKTYPE_ETHERNET, LINKTYPE_IPV4, LINKTYPE_IPV6};
+use crossbeam::crossbeam_channel::{Receiver, TryRecvError};
+use futures::prelude::*;
+use futures::ready;
+use futures::task::{Context, Poll};
+use route_rs_packets::{EthernetFrame, Ipv4Packet, Ipv6Packet};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A packet a `CaptureRingLink` can write to a pcap file, and a pcap file can be replayed as.
+pub trait Capture {
+    /// The pcap link-layer header type of the packets.
+    const LINK_TYPE: u32;
+
+    /// The bytes of the packet, from the header of its link type on.
+    fn capture(&self) -> &[u8];
+
+    /// The packet captured as `data`, if it parses as one.
+    fn from_capture(data: Vec<u8>) -> Option<Self>
+    where
+        Self: Sized;
+}
+
+impl Capture for EthernetFrame {
+    const LINK_TYPE: u32 = LINKTYPE_ETHERNET;
+
+    fn capture(&self) -> &[u8] {
+        &self.data[self.layer2_offset..]
+    }
+
+    fn from_capture(data: Vec<u8>) -> Option<Self> {
+        EthernetFrame::from_buffer(data, 0).ok()
+    }
+}
+
+impl Capture for Ipv4Packet {
+    const LINK_TYPE: u32 = LINKTYPE_IPV4;
+
+    fn capture(&self) -> &[u8] {
+        &self.data[self.layer3_offset..]
+    }
+
+    fn from_capture(data: Vec<u8>) -> Option<Self> {
+        Ipv4Packet::from_buffer(data, None, 0).ok()
+    }
+}
+
+impl Capture for Ipv6Packet {
+    const LINK_TYPE: u32 = LINKTYPE_IPV6;
+
+    fn capture(&self) -> &[u8] {
+        &self.data[self.layer3_offset..]
+    }
+
+    fn from_capture(data: Vec<u8>) -> Option<Self> {
+        Ipv6Packet::from_buffer(data, None, 0).ok()
+    }
+}
+
+/// Arms `CaptureRingLink`s to write what they hold to a pcap file, shared by whatever decides a
+/// capture is worth keeping and the links capturing.
+#[derive(Default)]
+pub struct CaptureTrigger {
+    armed: AtomicBool,
+    reason: Mutex<Option<String>>,
+    captures: Mutex<Vec<PathBuf>>,
+}
+
+impl CaptureTrigger {
+    pub fn new() -> Self {
+        CaptureTrigger {
+            armed: AtomicBool::new(false),
+            reason: Mutex::new(None),
+            captures: Mutex::new(vec![]),
+        }
+    }
+
+    /// Arms the trigger for `reason`, unless it is armed already, returning whether it was armed.
+    pub fn arm(&self, reason: &str) -> bool {
+        let mut armed = self.reason.lock().unwrap();
+        if armed.is_some() {
+            return false;
+        }
+        *armed = Some(String::from(reason));
+        self.armed.store(true, Ordering::Release);
+        true
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed.load(Ordering::Acquire)
+    }
+
+    /// The pcap files written so far, oldest first.
+    pub fn captures(&self) -> Vec<PathBuf> {
+        self.captures.lock().unwrap().clone()
+    }
+
+    /// Disarms the trigger, returning what it was armed for.
+    fn take(&self) -> Option<String> {
+        if !self.is_armed() {
+            return None;
+        }
+        self.armed.store(false, Ordering::Release);
+        self.reason.lock().unwrap().take()
+    }
+}
+
+/// A capture in progress: the packets before the trigger, and those after it so far.
+struct Pending {
+    reason: String,
+    records: Vec<(SystemTime, Vec<u8>)>,
+    remaining: usize,
+}
+
+struct CaptureRing<Packet> {
+    in_stream: PacketStream<Packet>,
+    capacity: usize,
+    post_trigger: usize,
+    directory: PathBuf,
+    name: String,
+    trigger: Arc<CaptureTrigger>,
+    ring: VecDeque<(SystemTime, Vec<u8>)>,
+    pending: Option<Pending>,
+    written: usize,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet: Capture> CaptureRing<Packet> {
+    fn record(&mut self, packet: &Packet) {
+        let record = (SystemTime::now(), packet.capture().to_vec());
+        if let Some(reason) = self.trigger.take() {
+            self.pending = Some(Pending {
+                reason,
+                records: self.ring.drain(..).collect(),
+                remaining: self.post_trigger,
+            });
+        }
+        match &mut self.pending {
+            Some(pending) => {
+                pending.records.push(record);
+                pending.remaining = pending.remaining.saturating_sub(1);
+                if pending.remaining == 0 {
+                    self.write();
+                }
+            }
+            None => {
+                if self.ring.len() == self.capacity {
+                    self.ring.pop_front();
+                }
+                self.ring.push_back(record);
+            }
+        }
+    }
+
+    fn write(&mut self) {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.written += 1;
+        let path = self.directory.join(format!(
+            "{}-{}-{}.pcap",
+            self.name,
+            since_epoch.as_secs(),
+            self.written
+        ));
+        match write_pcap(&path, Packet::LINK_TYPE, &pending.records) {
+            Ok(()) => {
+                if let Some(event_sink) = &self.event_sink {
+                    event_sink.alert(format!(
+                        "captured {} packets to {}, {}",
+                        pending.records.len(),
+                        path.display(),
+                        pending.reason
+                    ));
+                }
+                self.trigger.captures.lock().unwrap().push(path);
+            }
+            Err(err) => {
+                if let Some(event_sink) = &self.event_sink {
+                    event_sink.alert(format!("could not write {}, {}", path.display(), err));
+                }
+            }
+        }
+    }
+}
+
+impl<Packet> Unpin for CaptureRing<Packet> {}
+
+impl<Packet: Capture> Stream for CaptureRing<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let ring = &mut *self;
+        match ready!(Pin::new(&mut ring.in_stream).poll_next(cx)) {
+            Some(packet) => {
+                ring.record(&packet);
+                Poll::Ready(Some(packet))
+            }
+            None => {
+                ring.write();
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+/// Keeps the last `capacity` packets to pass through it in a ring, and writes them to a pcap file
+/// in `directory` when its `CaptureTrigger` is armed, along with the `post_trigger` packets that
+/// follow, so what led up to an event, and what came of it, can be looked at after the fact.
+/// Packets pass through unchanged.
+///
+/// Each capture is written as `<name>-<unix time>-<n>.pcap`, once its last packet has passed, or
+/// the link's ingressor ends. The file is reported as a `LinkEvent::Alert` on the link's
+/// `event_sink`, with the reason the trigger was armed, and listed by the trigger's `captures`.
+/// The trigger is disarmed as the capture starts, so it can be armed again for the next.
+pub struct CaptureRingLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    capacity: usize,
+    post_trigger: usize,
+    directory: Option<PathBuf>,
+    name: String,
+    trigger: Option<Arc<CaptureTrigger>>,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet> Default for CaptureRingLink<Packet> {
+    fn default() -> Self {
+        CaptureRingLink::new()
+    }
+}
+
+impl<Packet> CaptureRingLink<Packet> {
+    pub fn new() -> Self {
+        CaptureRingLink {
+            in_stream: None,
+            capacity: 1000,
+            post_trigger: 100,
+            directory: None,
+            name: String::from("capture"),
+            trigger: None,
+            event_sink: None,
+        }
+    }
+
+    /// Changes capacity, the packets kept from before the trigger, default value is 1000.
+    pub fn capacity(self, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be > 0");
+        CaptureRingLink {
+            in_stream: self.in_stream,
+            capacity,
+            post_trigger: self.post_trigger,
+            directory: self.directory,
+            name: self.name,
+            trigger: self.trigger,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes post_trigger, the packets captured after the trigger, default value is 100.
+    pub fn post_trigger(self, post_trigger: usize) -> Self {
+        CaptureRingLink {
+            in_stream: self.in_stream,
+            capacity: self.capacity,
+            post_trigger,
+            directory: self.directory,
+            name: self.name,
+            trigger: self.trigger,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// The directory pcap files are written to.
+    pub fn directory<P: AsRef<Path>>(self, directory: P) -> Self {
+        CaptureRingLink {
+            in_stream: self.in_stream,
+            capacity: self.capacity,
+            post_trigger: self.post_trigger,
+            directory: Some(directory.as_ref().to_path_buf()),
+            name: self.name,
+            trigger: self.trigger,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes name, which pcap file names start with, default value is `capture`.
+    pub fn name(self, name: &str) -> Self {
+        CaptureRingLink {
+            in_stream: self.in_stream,
+            capacity: self.capacity,
+            post_trigger: self.post_trigger,
+            directory: self.directory,
+            name: String::from(name),
+            trigger: self.trigger,
+            event_sink: self.event_sink,
+        }
+    }
+
+    pub fn trigger(self, trigger: Arc<CaptureTrigger>) -> Self {
+        CaptureRingLink {
+            in_stream: self.in_stream,
+            capacity: self.capacity,
+            post_trigger: self.post_trigger,
+            directory: self.directory,
+            name: self.name,
+            trigger: Some(trigger),
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Reports the pcap files the link writes, or fails to, to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        CaptureRingLink {
+            in_stream: self.in_stream,
+            capacity: self.capacity,
+            post_trigger: self.post_trigger,
+            directory: self.directory,
+            name: self.name,
+            trigger: self.trigger,
+            event_sink: Some(event_sink),
+        }
+    }
+}
+
+impl<Packet: Capture + Send + 'static> LinkBuilder<Packet, Packet> for CaptureRingLink<Packet> {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "CaptureRingLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "CaptureRingLink may only take 1 input stream",
+            ));
+        }
+        Ok(CaptureRingLink {
+            in_stream: Some(in_stream),
+            capacity: self.capacity,
+            post_trigger: self.post_trigger,
+            directory: self.directory,
+            name: self.name,
+            trigger: self.trigger,
+            event_sink: self.event_sink,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Packet>, LinkBuildError> {
+        match (self.in_stream, self.directory, self.trigger) {
+            (None, _, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None, _) => Err(LinkBuildError::Missing("directory")),
+            (_, _, None) => Err(LinkBuildError::Missing("trigger")),
+            (Some(in_stream), Some(directory), Some(trigger)) => Ok((
+                vec![],
+                vec![Box::new(CaptureRing {
+                    in_stream,
+                    capacity: self.capacity,
+                    post_trigger: self.post_trigger,
+                    directory,
+                    name: self.name,
+                    trigger,
+                    ring: VecDeque::with_capacity(self.capacity),
+                    pending: None,
+                    written: 0,
+                    event_sink: self.event_sink,
+                })],
+            )),
+        }
+    }
+}
+
+/// An event that arms a `CaptureOnEvent`'s trigger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureOn {
+    /// Any `LinkEvent::Alert`, such as a host caught scanning.
+    Alert,
+    /// Any `LinkEvent::ParseError`.
+    ParseError,
+    /// `drops` or more `LinkEvent::Dropped`, from any link, within `within`.
+    DropSpike { drops: usize, within: Duration },
+}
+
+/// Watches the `LinkEvent`s of a pipeline, and arms a `CaptureTrigger` when one of its
+/// conditions is met, so the `CaptureRingLink`s it arms write out what they hold. Alerts of the
+/// capture links themselves, about the files they write, never arm the trigger.
+pub struct CaptureOnEvent {
+    trigger: Arc<CaptureTrigger>,
+    conditions: Vec<CaptureOn>,
+    /// When the drops within the longest `DropSpike` window were reported.
+    drops: VecDeque<Instant>,
+}
+
+impl CaptureOnEvent {
+    pub fn new(trigger: Arc<CaptureTrigger>) -> Self {
+        CaptureOnEvent {
+            trigger,
+            conditions: vec![],
+            drops: VecDeque::new(),
+        }
+    }
+
+    /// Adds a condition that arms the trigger.
+    pub fn on(self, condition: CaptureOn) -> Self {
+        let mut conditions = self.conditions;
+        conditions.push(condition);
+        CaptureOnEvent {
+            trigger: self.trigger,
+            conditions,
+            drops: self.drops,
+        }
+    }
+
+    /// Counts `event`, arming the trigger if it meets a condition, and returning whether it did.
+    pub fn observe(&mut self, event: &LinkEvent, now: Instant) -> bool {
+        let reason = match event {
+            LinkEvent::Alert { alert, .. } if alert.starts_with("captured ") => None,
+            LinkEvent::Alert { .. } if self.conditions.contains(&CaptureOn::Alert) => {
+                Some(event.to_string())
+            }
+            LinkEvent::ParseError { .. } if self.conditions.contains(&CaptureOn::ParseError) => {
+                Some(event.to_string())
+            }
+            LinkEvent::Dropped { .. } => {
+                self.drops.push_back(now);
+                self.drop_spike(now)
+            }
+            _ => None,
+        };
+        reason.is_some_and(|reason| self.trigger.arm(&reason))
+    }
+
+    fn drop_spike(&mut self, now: Instant) -> Option<String> {
+        let longest = self
+            .conditions
+            .iter()
+            .filter_map(|condition| match condition {
+                CaptureOn::DropSpike { within, .. } => Some(*within),
+                _ => None,
+            })
+            .max()?;
+        while self
+            .drops
+            .front()
+            .is_some_and(|dropped| now.saturating_duration_since(*dropped) > longest)
+        {
+            self.drops.pop_front();
+        }
+        self.conditions
+            .iter()
+            .find_map(|condition| match condition {
+                CaptureOn::DropSpike { drops, within } => {
+                    let recent = self
+                        .drops
+                        .iter()
+                        .filter(|dropped| now.saturating_duration_since(**dropped) <= *within)
+                        .count();
+                    if recent >= *drops {
+                        Some(format!("{} drops within {:?}", recent, within))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+    }
+
+    /// A runnable that observes the events received on `events`, checking for more every
+    /// `interval`, until every sender of the channel is gone.
+    pub fn watch(mut self, events: Receiver<LinkEvent>, interval: Duration) -> TokioRunnable {
+        Box::new(Box::pin(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                loop {
+                    match events.try_recv() {
+                        Ok(event) => {
+                            self.observe(&event, clock::now());
+                        }
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => return,
+                    }
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::pcap;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use crossbeam::crossbeam_channel;
+    use std::fs;
+    use std::net::Ipv4Addr;
+    use uuid::Uuid;
+
+    fn packet(n: u8) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, n));
+        packet
+    }
+
+    /// The source addresses of the packets in a pcap file of IPv4 packets.
+    fn read_sources(path: &Path) -> Vec<Ipv4Addr> {
+        let (link_type, records) = pcap::read_pcap(path).unwrap();
+        assert_eq!(link_type, LINKTYPE_IPV4);
+        records
+            .into_iter()
+            .map(|(_, packet)| Ipv4Packet::from_capture(packet).unwrap().src_addr())
+            .collect()
+    }
+
+    #[test]
+    fn writes_ring_and_following_packets() {
+        let directory = std::env::temp_dir().join(format!("capture-{}", Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        let trigger = Arc::new(CaptureTrigger::new());
+        let (sender, events) = crossbeam_channel::unbounded();
+
+        // Armed after the fourth packet, by a link upstream.
+        let armer = Arc::clone(&trigger);
+        let packets = immediate_stream((1..=8).map(packet).collect::<Vec<_>>()).map(move |p| {
+            if p.src_addr() == Ipv4Addr::new(10, 0, 0, 5) {
+                armer.arm("scan detected");
+            }
+            p
+        });
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = CaptureRingLink::new()
+                .capacity(3)
+                .post_trigger(2)
+                .directory(&directory)
+                .trigger(Arc::clone(&trigger))
+                .event_sink(EventSink::new("capture", sender))
+                .ingressor(Box::new(packets))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 8);
+        assert!(!trigger.is_armed());
+
+        let captures = trigger.captures();
+        assert_eq!(captures.len(), 1);
+        let sources = read_sources(&captures[0]);
+        let expected: Vec<Ipv4Addr> = (2..=6).map(|n| Ipv4Addr::new(10, 0, 0, n)).collect();
+        assert_eq!(sources, expected);
+        let alert = events.try_recv().unwrap().to_string();
+        assert!(alert.ends_with(", scan detected"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn arms_on_alerts_and_drop_spikes() {
+        let trigger = Arc::new(CaptureTrigger::new());
+        let mut watcher = CaptureOnEvent::new(Arc::clone(&trigger)).on(CaptureOn::DropSpike {
+            drops: 3,
+            within: Duration::from_secs(1),
+        });
+        let dropped = LinkEvent::Dropped {
+            link: Arc::from("queue"),
+            reason: "queue full",
+        };
+        let alert = LinkEvent::Alert {
+            link: Arc::from("scan-detector"),
+            alert: String::from("198.51.100.66 scanned 101 ports in 60s"),
+        };
+        let start = Instant::now();
+
+        assert!(!watcher.observe(&alert, start));
+        assert!(!watcher.observe(&dropped, start));
+        assert!(!watcher.observe(&dropped, start + Duration::from_millis(1500)));
+        assert!(!watcher.observe(&dropped, start + Duration::from_secs(2)));
+        assert!(watcher.observe(&dropped, start + Duration::from_millis(2500)));
+        assert_eq!(trigger.take(), Some(String::from("3 drops within 1s")));
+
+        let mut watcher = watcher.on(CaptureOn::Alert);
+        assert!(watcher.observe(&alert, start));
+        assert!(!watcher.observe(&alert, start));
+    }
+}
@@ -1,6 +1,9 @@
-use crate::classifier::Classifier;
+use crate::classifier::{Classifier, Dispatch};
+use crate::link::event::EventSink;
 use crate::link::utils::task_park::*;
-use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crate::link::utils::watchdog::Watchdog;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::metrics;
 use crossbeam::atomic::AtomicCell;
 use crossbeam::crossbeam_channel;
 use crossbeam::crossbeam_channel::{Receiver, Sender};
@@ -16,7 +19,10 @@ pub struct ClassifyLink<C: Classifier> {
     classifier: Option<C>,
     dispatcher: Option<Box<dyn Fn(C::Class) -> usize + Send + Sync + 'static>>,
     queue_capacity: usize,
+    batch_size: usize,
+    work_budget: usize,
     num_egressors: Option<usize>,
+    event_sink: Option<EventSink>,
 }
 
 impl<C: Classifier> ClassifyLink<C> {
@@ -26,7 +32,10 @@ impl<C: Classifier> ClassifyLink<C> {
             classifier: None,
             dispatcher: None,
             queue_capacity: 10,
+            batch_size: 8,
+            work_budget: 128,
             num_egressors: None,
+            event_sink: None,
         }
     }
 
@@ -36,7 +45,10 @@ impl<C: Classifier> ClassifyLink<C> {
             classifier: Some(classifier),
             dispatcher: self.dispatcher,
             queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
             num_egressors: self.num_egressors,
+            event_sink: self.event_sink,
         }
     }
 
@@ -49,7 +61,28 @@ impl<C: Classifier> ClassifyLink<C> {
             classifier: self.classifier,
             dispatcher: Some(dispatcher),
             queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
             num_egressors: self.num_egressors,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Sends each class to the egressor its `Dispatch` impl gives, with as many egressors as it
+    /// has ports, in place of `dispatcher` and `num_egressors`.
+    pub fn dispatch(self) -> Self
+    where
+        C::Class: Dispatch,
+    {
+        ClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: Some(Box::new(|class: C::Class| class.port())),
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            num_egressors: Some(C::Class::PORTS),
+            event_sink: self.event_sink,
         }
     }
 
@@ -63,7 +96,44 @@ impl<C: Classifier> ClassifyLink<C> {
             classifier: self.classifier,
             dispatcher: self.dispatcher,
             queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            num_egressors: self.num_egressors,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes batch_size, the number of packets passed to an egressor, or taken
+    /// off its queue by it, before waking the other side, default value is 8.
+    /// Either side also wakes the other before it goes to sleep.
+    pub fn batch_size(self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch_size must be > 0");
+        ClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            batch_size,
+            work_budget: self.work_budget,
+            num_egressors: self.num_egressors,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes work_budget, the number of packets the ingressor takes from its
+    /// input in one poll before yielding to other tasks, default value is 128.
+    pub fn work_budget(self, work_budget: usize) -> Self {
+        assert!(work_budget > 0, "work_budget must be > 0");
+
+        ClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget,
             num_egressors: self.num_egressors,
+            event_sink: self.event_sink,
         }
     }
 
@@ -77,84 +147,121 @@ impl<C: Classifier> ClassifyLink<C> {
             classifier: self.classifier,
             dispatcher: self.dispatcher,
             queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
             num_egressors: Some(num_egressors),
+            event_sink: self.event_sink,
         }
     }
-}
-
-impl<C: Classifier + Send + 'static> LinkBuilder<C::Packet, C::Packet> for ClassifyLink<C> {
-    fn ingressors(self, mut in_streams: Vec<PacketStream<C::Packet>>) -> Self {
-        assert_eq!(
-            in_streams.len(),
-            1,
-            "ClassifyLink may only take 1 input stream"
-        );
-
-        if self.in_stream.is_some() {
-            panic!("ClassifyLink may only take 1 input stream")
-        }
 
+    /// Reports queues filling up to `event_sink`. With a sink, packets the dispatcher sends to an
+    /// egressor that doesn't exist, or that has been dropped, are dropped and reported rather than
+    /// panicking.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
         ClassifyLink {
-            in_stream: Some(in_streams.remove(0)),
+            in_stream: self.in_stream,
             classifier: self.classifier,
             dispatcher: self.dispatcher,
             queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
             num_egressors: self.num_egressors,
+            event_sink: Some(event_sink),
         }
     }
+}
 
-    fn ingressor(self, in_stream: PacketStream<C::Packet>) -> Self {
+impl<C: Classifier + Send + 'static> LinkBuilder<C::Packet, C::Packet> for ClassifyLink<C> {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<C::Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "ClassifyLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<C::Packet>) -> Result<Self, LinkBuildError> {
         if self.in_stream.is_some() {
-            panic!("ClassifyLink may only take 1 input stream")
+            return Err(LinkBuildError::Ingressors(
+                "ClassifyLink may only take 1 input stream",
+            ));
         }
 
-        ClassifyLink {
+        Ok(ClassifyLink {
             in_stream: Some(in_stream),
             classifier: self.classifier,
             dispatcher: self.dispatcher,
             queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
             num_egressors: self.num_egressors,
-        }
+            event_sink: self.event_sink,
+        })
     }
 
-    fn build_link(self) -> Link<C::Packet> {
-        if self.in_stream.is_none() {
-            panic!("Cannot build link! Missing input streams");
-        } else if self.classifier.is_none() {
-            panic!("Cannot build link! Missing classifier");
-        } else if self.dispatcher.is_none() {
-            panic!("Cannot build link! Missing dispatcher");
-        } else if self.num_egressors.is_none() {
-            panic!("Cannot build link! Missing num_egressors");
-        } else {
-            let mut to_egressors: Vec<Sender<Option<C::Packet>>> = Vec::new();
-            let mut egressors: Vec<PacketStream<C::Packet>> = Vec::new();
-
-            let mut from_ingressors: Vec<Receiver<Option<C::Packet>>> = Vec::new();
-
-            let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
-
-            for _ in 0..self.num_egressors.unwrap() {
-                let (to_egressor, from_ingressor) =
-                    crossbeam_channel::bounded::<Option<C::Packet>>(self.queue_capacity);
-                let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
-
-                let provider = QueueEgressor::new(from_ingressor.clone(), Arc::clone(&task_park));
-
-                to_egressors.push(to_egressor);
-                egressors.push(Box::new(provider));
-                from_ingressors.push(from_ingressor);
-                task_parks.push(task_park);
+    fn try_build_link(self) -> Result<Link<C::Packet>, LinkBuildError> {
+        let (in_stream, classifier, dispatcher, num_egressors) = match (
+            self.in_stream,
+            self.classifier,
+            self.dispatcher,
+            self.num_egressors,
+        ) {
+            (None, _, _, _) => return Err(LinkBuildError::Missing("input streams")),
+            (_, None, _, _) => return Err(LinkBuildError::Missing("classifier")),
+            (_, _, None, _) => return Err(LinkBuildError::Missing("dispatcher")),
+            (_, _, _, None) => return Err(LinkBuildError::Missing("num_egressors")),
+            (Some(in_stream), Some(classifier), Some(dispatcher), Some(num_egressors)) => {
+                (in_stream, classifier, dispatcher, num_egressors)
             }
-            let ingressor = ClassifyIngressor::new(
-                self.in_stream.unwrap(),
-                self.dispatcher.unwrap(),
-                to_egressors,
-                self.classifier.unwrap(),
-                task_parks,
-            );
-            (vec![Box::new(ingressor)], egressors)
+        };
+        let mut to_egressors: Vec<Sender<Option<C::Packet>>> = Vec::new();
+        let mut egressors: Vec<PacketStream<C::Packet>> = Vec::new();
+
+        let mut from_ingressors: Vec<Receiver<Option<C::Packet>>> = Vec::new();
+
+        let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+        let watchdog = Watchdog::global();
+        let link_id = watchdog.next_id();
+
+        for port in 0..num_egressors {
+            let (to_egressor, from_ingressor) =
+                crossbeam_channel::bounded::<Option<C::Packet>>(self.queue_capacity);
+            let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+            let queue = to_egressor.clone();
+            metrics::register_queue(self.queue_capacity, move || queue.len());
+            if cfg!(debug_assertions) {
+                let queue = to_egressor.clone();
+                watchdog.register(
+                    format!("ClassifyLink#{} egressor {}", link_id, port),
+                    &task_park,
+                    self.queue_capacity,
+                    move || queue.len(),
+                );
+            }
+
+            let provider = QueueEgressor::new(from_ingressor.clone(), Arc::clone(&task_park))
+                .batch_size(self.batch_size);
+
+            to_egressors.push(to_egressor);
+            egressors.push(Box::new(provider));
+            from_ingressors.push(from_ingressor);
+            task_parks.push(task_park);
         }
+        let ingressor = ClassifyIngressor::new(
+            in_stream,
+            dispatcher,
+            to_egressors,
+            classifier,
+            task_parks,
+            self.batch_size,
+            self.work_budget,
+        )
+        .event_sink(self.event_sink);
+        Ok((vec![Box::new(ingressor)], egressors))
     }
 }
 
@@ -164,6 +271,12 @@ pub struct ClassifyIngressor<'a, C: Classifier> {
     to_egressors: Vec<Sender<Option<C::Packet>>>,
     classifier: C,
     task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    batch_size: usize,
+    unwoken: Vec<usize>,
+    work_budget: usize,
+    event_sink: Option<EventSink>,
+    /// Whether each to_egressor queue has been full since we last pushed onto it.
+    full: Vec<bool>,
 }
 
 impl<'a, C: Classifier> Unpin for ClassifyIngressor<'a, C> {}
@@ -175,13 +288,33 @@ impl<'a, C: Classifier> ClassifyIngressor<'a, C> {
         to_egressors: Vec<Sender<Option<C::Packet>>>,
         classifier: C,
         task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+        batch_size: usize,
+        work_budget: usize,
     ) -> Self {
+        let unwoken = vec![0; task_parks.len()];
+        let full = vec![false; to_egressors.len()];
         ClassifyIngressor {
             input_stream,
             dispatcher,
             to_egressors,
             classifier,
             task_parks,
+            batch_size,
+            unwoken,
+            work_budget,
+            event_sink: None,
+            full,
+        }
+    }
+
+    fn event_sink(self, event_sink: Option<EventSink>) -> Self {
+        ClassifyIngressor { event_sink, ..self }
+    }
+
+    /// Wakes every egressor that has been passed packets since it was last woken.
+    fn flush(&mut self) {
+        for (task_park, unwoken) in self.task_parks.iter().zip(self.unwoken.iter_mut()) {
+            flush_and_wake(task_park, unwoken);
         }
     }
 }
@@ -192,49 +325,112 @@ impl<'a, C: Classifier> Future for ClassifyIngressor<'a, C> {
     /// Same logic as QueueEgressor, except if any of the channels are full we
     /// await that channel to clear before processing a new packet. This is somewhat
     /// inefficient, but seems acceptable for now since we want to yield compute to
-    /// that egressor, as there is a backup in its queue.
+    /// that egressor, as there is a backup in its queue. A queue that has just become
+    /// full is reported to our event sink, if we have one. Before sleeping, we wake
+    /// every egressor we have passed packets since we last woke it. Once we have
+    /// taken `work_budget` packets in one poll, we wake ourselves and sleep, so that
+    /// other tasks on this thread get a turn.
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         let ingressor = Pin::into_inner(self);
-        loop {
-            for (port, to_egressor) in ingressor.to_egressors.iter().enumerate() {
-                if to_egressor.is_full() {
-                    park_and_wake(&ingressor.task_parks[port], cx.waker().clone());
-                    return Poll::Pending;
+        for _ in 0..ingressor.work_budget {
+            if let Some(port) = ingressor
+                .to_egressors
+                .iter()
+                .position(|to_egressor| to_egressor.is_full())
+            {
+                if let (false, Some(event_sink)) = (ingressor.full[port], &ingressor.event_sink) {
+                    event_sink.queue_overflow(port);
                 }
+                ingressor.full[port] = true;
+                ingressor.unwoken[port] = 0;
+                ingressor.flush();
+                park_and_wake(&ingressor.task_parks[port], cx.waker().clone());
+                return Poll::Pending;
             }
 
             //TODO: Standardize in_stream, input_stream, and stream to one name
             let packet_option: Option<C::Packet> =
-                ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx));
+                match Pin::new(&mut ingressor.input_stream).poll_next(cx) {
+                    Poll::Ready(packet_option) => packet_option,
+                    Poll::Pending => {
+                        ingressor.flush();
+                        return Poll::Pending;
+                    }
+                };
 
             match packet_option {
                 None => {
                     for to_egressor in ingressor.to_egressors.iter() {
-                        to_egressor
-                            .try_send(None)
-                            .expect("ClassifyIngressor::Drop: try_send to_egressor shouldn't fail");
+                        if let Err(err) = to_egressor.try_send(None) {
+                            if ingressor.event_sink.is_none() {
+                                panic!(
+                                    "ClassifyIngressor::Drop: try_send to_egressor shouldn't fail: {:?}",
+                                    err
+                                );
+                            }
+                        }
                     }
                     for task_park in ingressor.task_parks.iter() {
                         die_and_wake(&task_park);
                     }
                     return Poll::Ready(());
                 }
+                Some(packet) if ingressor.classifier.broadcast(&packet) => {
+                    // No queue is full, so each has room for a copy.
+                    for port in 0..ingressor.to_egressors.len() {
+                        if ingressor.to_egressors[port]
+                            .try_send(Some(packet.clone()))
+                            .is_err()
+                        {
+                            if let Some(event_sink) = &ingressor.event_sink {
+                                event_sink.dropped("egressor has been dropped");
+                            }
+                            continue;
+                        }
+                        ingressor.full[port] = false;
+                        batch_and_wake(
+                            &ingressor.task_parks[port],
+                            &mut ingressor.unwoken[port],
+                            ingressor.batch_size,
+                        );
+                    }
+                }
                 Some(packet) => {
                     let class = ingressor.classifier.classify(&packet);
                     let port = (ingressor.dispatcher)(class);
                     if port >= ingressor.to_egressors.len() {
-                        panic!("Tried to access invalid port: {}", port);
+                        match &ingressor.event_sink {
+                            Some(event_sink) => {
+                                event_sink.dropped("dispatched to an invalid port");
+                                continue;
+                            }
+                            None => panic!("Tried to access invalid port: {}", port),
+                        }
                     }
                     if let Err(err) = ingressor.to_egressors[port].try_send(Some(packet)) {
-                        panic!(
-                            "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
-                            port, err
-                        );
+                        match &ingressor.event_sink {
+                            Some(event_sink) => {
+                                event_sink.dropped("egressor has been dropped");
+                                continue;
+                            }
+                            None => panic!(
+                                "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
+                                port, err
+                            ),
+                        }
                     }
-                    unpark_and_wake(&ingressor.task_parks[port]);
+                    ingressor.full[port] = false;
+                    batch_and_wake(
+                        &ingressor.task_parks[port],
+                        &mut ingressor.unwoken[port],
+                        ingressor.batch_size,
+                    );
                 }
             }
         }
+        ingressor.flush();
+        cx.waker().wake_by_ref();
+        Poll::Pending
     }
 }
 
@@ -329,7 +525,7 @@ mod tests {
 
             run_link(even_link(packet_generator)).await
         });
-        assert_eq!(results[0], []);
+        assert_eq!(results[0], Vec::<i32>::new());
         assert_eq!(results[1], vec![1, 1337, 3, 5, 7, 9]);
     }
 
@@ -385,4 +581,28 @@ mod tests {
         assert_eq!(results[0], vec![2, 4, 8, 14, 16, 22, 26, 28]);
         assert_eq!(results[1], vec![1, 7, 11, 13, 17, 19, 23, 29]);
     }
+
+    #[test]
+    fn reports_packets_dispatched_to_invalid_ports() {
+        let (sender, events) = crossbeam_channel::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ClassifyLink::new()
+                .ingressor(immediate_stream(0..10))
+                .num_egressors(1)
+                .classifier(Even::new())
+                .dispatcher(Box::new(|evenness| if evenness { 0 } else { 1 }))
+                .event_sink(EventSink::new("evens", sender))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 2, 4, 6, 8]);
+        let events: Vec<String> = events.try_iter().map(|event| event.to_string()).collect();
+        assert_eq!(
+            events,
+            vec!["evens: dropped packet, dispatched to an invalid port"; 5]
+        );
+    }
 }
@@ -0,0 +1,305 @@
+use crate::link::event::EventSink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+type DedupKeyFn<Packet, K> = Box<dyn Fn(&Packet) -> K + Send>;
+
+/// Drops packets with the same key, as `key` gives it, as a packet passed within the last `window`,
+/// such as the second copy of a packet sent down redundant paths from a `ForkLink`, or the repeats
+/// of a noisy source.
+///
+/// The keys passed are kept on a timing wheel of `slots` slots, each `window / slots` long, and
+/// each slot's keys are forgotten together when the wheel comes back around to it, so the keys
+/// kept are only those of the last `window`, and forgetting them costs nothing per packet. A key is
+/// forgotten between `window` and `window` plus one slot after its packet passed. Copies within the
+/// window don't lengthen it, so a packet repeated forever still passes once a window.
+pub struct DedupLink<Packet, K> {
+    in_stream: Option<PacketStream<Packet>>,
+    key: Option<DedupKeyFn<Packet, K>>,
+    window: Option<Duration>,
+    slots: usize,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet, K> Default for DedupLink<Packet, K> {
+    fn default() -> Self {
+        DedupLink::new()
+    }
+}
+
+impl<Packet, K> DedupLink<Packet, K> {
+    pub fn new() -> Self {
+        DedupLink {
+            in_stream: None,
+            key: None,
+            window: None,
+            slots: 16,
+            event_sink: None,
+        }
+    }
+
+    /// The key packets are compared by, such as a hash of their contents.
+    pub fn key<F: Fn(&Packet) -> K + Send + 'static>(self, key: F) -> Self {
+        DedupLink {
+            in_stream: self.in_stream,
+            key: Some(Box::new(key)),
+            window: self.window,
+            slots: self.slots,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// How long after a packet passes its copies are dropped.
+    pub fn window(self, window: Duration) -> Self {
+        assert!(window > Duration::from_secs(0), "window must be > 0");
+        DedupLink {
+            in_stream: self.in_stream,
+            key: self.key,
+            window: Some(window),
+            slots: self.slots,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes slots, the number of slots of the timing wheel, default value is 16. More slots
+    /// forget keys closer to the end of the window.
+    pub fn slots(self, slots: usize) -> Self {
+        assert!(slots > 0, "slots must be > 0");
+        DedupLink {
+            in_stream: self.in_stream,
+            key: self.key,
+            window: self.window,
+            slots,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Reports the copies dropped to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        DedupLink {
+            in_stream: self.in_stream,
+            key: self.key,
+            window: self.window,
+            slots: self.slots,
+            event_sink: Some(event_sink),
+        }
+    }
+}
+
+impl<Packet, K> LinkBuilder<Packet, Packet> for DedupLink<Packet, K>
+where
+    Packet: Send + 'static,
+    K: Hash + Eq + Clone + Send + 'static,
+{
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "DedupLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "DedupLink may only take 1 input stream",
+            ));
+        }
+        Ok(DedupLink {
+            in_stream: Some(in_stream),
+            key: self.key,
+            window: self.window,
+            slots: self.slots,
+            event_sink: self.event_sink,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Packet>, LinkBuildError> {
+        match (self.in_stream, self.key, self.window) {
+            (None, _, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None, _) => Err(LinkBuildError::Missing("key")),
+            (_, _, None) => Err(LinkBuildError::Missing("window")),
+            (Some(in_stream), Some(key), Some(window)) => Ok((
+                vec![],
+                vec![Box::new(Dedup {
+                    in_stream,
+                    key,
+                    wheel: TimingWheel::new(window, self.slots),
+                    event_sink: self.event_sink,
+                })],
+            )),
+        }
+    }
+}
+
+/// The keys seen in the last window, in the slots of the times they were seen.
+struct TimingWheel<K> {
+    seen: HashSet<K>,
+    slots: Vec<Vec<K>>,
+    slot_length: Duration,
+    start: Instant,
+    /// The number of slot lengths from `start` to the slot keys are added to.
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone> TimingWheel<K> {
+    fn new(window: Duration, slots: usize) -> Self {
+        TimingWheel {
+            seen: HashSet::new(),
+            slots: (0..slots).map(|_| vec![]).collect(),
+            slot_length: window / slots as u32,
+            start: clock::now(),
+            tick: 0,
+        }
+    }
+
+    /// Turns the wheel to `now`, forgetting the keys of each slot it comes back around to.
+    fn advance(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.start);
+        let tick = (elapsed.as_nanos() / self.slot_length.as_nanos().max(1)) as u64;
+        // Past a whole turn every slot is forgotten, however many turns it has been.
+        let turns = (tick - self.tick).min(self.slots.len() as u64);
+        for t in self.tick + 1..=self.tick + turns {
+            let slot = (t % self.slots.len() as u64) as usize;
+            for key in self.slots[slot].drain(..) {
+                self.seen.remove(&key);
+            }
+        }
+        self.tick = tick;
+    }
+
+    /// Whether the key has been seen in the last window, adding it to the current slot if not.
+    fn check(&mut self, key: K) -> bool {
+        if self.seen.contains(&key) {
+            return true;
+        }
+        let slot = (self.tick % self.slots.len() as u64) as usize;
+        self.slots[slot].push(key.clone());
+        self.seen.insert(key);
+        false
+    }
+}
+
+struct Dedup<Packet, K> {
+    in_stream: PacketStream<Packet>,
+    key: DedupKeyFn<Packet, K>,
+    wheel: TimingWheel<K>,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet, K> Unpin for Dedup<Packet, K> {}
+
+impl<Packet, K: Hash + Eq + Clone> Stream for Dedup<Packet, K> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let dedup = &mut *self;
+        loop {
+            match ready!(Pin::new(&mut dedup.in_stream).poll_next(cx)) {
+                None => return Poll::Ready(None),
+                Some(packet) => {
+                    dedup.wheel.advance(clock::now());
+                    if !dedup.wheel.check((dedup.key)(&packet)) {
+                        return Poll::Ready(Some(packet));
+                    }
+                    if let Some(event_sink) = &dedup.event_sink {
+                        event_sink.dropped("packet is a duplicate");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::event::LinkEvent;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use crossbeam::crossbeam_channel;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_window() {
+        DedupLink::<i32, i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .key(|packet| *packet)
+            .build_link();
+    }
+
+    #[test]
+    fn drops_duplicates() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DedupLink::new()
+                .ingressor(immediate_stream(vec![1, 2, 1, 3, 2, 2, 4]))
+                .key(|packet: &i32| *packet)
+                .window(Duration::from_secs(60))
+                .event_sink(EventSink::new("dedup", sender))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![1, 2, 3, 4]);
+        let events: Vec<LinkEvent> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn forgets_keys_after_window() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            // A copy 20ms later is dropped, one 200ms later passes.
+            let link = DedupLink::new()
+                .ingressor(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(20),
+                    vec![1, 1, 2].into_iter().chain(10..20).chain(vec![1, 2]),
+                )))
+                .key(|packet: &i32| *packet)
+                .window(Duration::from_millis(100))
+                .slots(4)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(
+            results[0],
+            vec![1, 2]
+                .into_iter()
+                .chain(10..20)
+                .chain(vec![1, 2])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn timing_wheel_forgets_whole_slots() {
+        let mut wheel = TimingWheel::new(Duration::from_secs(4), 4);
+        let start = wheel.start;
+        assert!(!wheel.check(1));
+        wheel.advance(start + Duration::from_millis(1500));
+        assert!(!wheel.check(2));
+        assert!(wheel.check(1));
+
+        // The slot of 1 comes back around after 4 seconds, that of 2 after 5.
+        wheel.advance(start + Duration::from_secs(4));
+        assert!(!wheel.check(1));
+        assert!(wheel.check(2));
+        wheel.advance(start + Duration::from_secs(60));
+        assert!(wheel.seen.is_empty());
+        assert!(wheel.slots.iter().all(|slot| slot.is_empty()));
+    }
+}
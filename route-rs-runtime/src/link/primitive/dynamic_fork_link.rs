@@ -0,0 +1,451 @@
+use crate::link::event::EventSink;
+use crate::link::utils::task_park::*;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuildError, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::{Receiver, Sender, TrySendError};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A `ForkLink` whose egressors can be subscribed and unsubscribed while it runs, through the
+/// `DynamicForkHandle`s it hands out, as for a mirror or capture session started on demand.
+///
+/// Each packet is put in an `Arc` once, and every egressor is given a reference to it, so packets
+/// are never copied however many egressors there are. A new egressor is given the packets the link
+/// takes after it subscribes, and an egressor unsubscribes by being dropped. With no egressors,
+/// the link drops the packets it takes.
+pub struct DynamicForkLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    work_budget: usize,
+    num_egressors: usize,
+    lossy: bool,
+    event_sink: Option<EventSink>,
+    shared: Arc<Shared<Packet>>,
+    from_handles: Receiver<Subscriber<Packet>>,
+}
+
+/// What the link shares with its handles.
+struct Shared<Packet> {
+    to_ingressor: Sender<Subscriber<Packet>>,
+    queue_capacity: AtomicUsize,
+    /// Set once the ingressor has ended, after which egressors subscribe to nothing.
+    closed: Mutex<bool>,
+}
+
+impl<Packet> Default for DynamicForkLink<Packet> {
+    fn default() -> Self {
+        DynamicForkLink::new()
+    }
+}
+
+impl<Packet> DynamicForkLink<Packet> {
+    pub fn new() -> Self {
+        let (to_ingressor, from_handles) = crossbeam_channel::unbounded();
+        DynamicForkLink {
+            in_stream: None,
+            work_budget: 128,
+            num_egressors: 0,
+            lossy: false,
+            event_sink: None,
+            shared: Arc::new(Shared {
+                to_ingressor,
+                queue_capacity: AtomicUsize::new(10),
+                closed: Mutex::new(false),
+            }),
+            from_handles,
+        }
+    }
+
+    /// Changes queue_capacity, the capacity of the queue of each egressor, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(queue_capacity > 0, "queue_capacity must be > 0");
+        self.shared
+            .queue_capacity
+            .store(queue_capacity, Ordering::Relaxed);
+        self
+    }
+
+    /// Changes work_budget, the number of packets the ingressor takes from its
+    /// input in one poll before yielding to other tasks, default value is 128.
+    pub fn work_budget(self, work_budget: usize) -> Self {
+        assert!(work_budget > 0, "work_budget must be > 0");
+        DynamicForkLink {
+            in_stream: self.in_stream,
+            work_budget,
+            num_egressors: self.num_egressors,
+            lossy: self.lossy,
+            event_sink: self.event_sink,
+            shared: self.shared,
+            from_handles: self.from_handles,
+        }
+    }
+
+    /// The number of egressors the link is built with, default value is 0.
+    pub fn num_egressors(self, num_egressors: usize) -> Self {
+        DynamicForkLink {
+            in_stream: self.in_stream,
+            work_budget: self.work_budget,
+            num_egressors,
+            lossy: self.lossy,
+            event_sink: self.event_sink,
+            shared: self.shared,
+            from_handles: self.from_handles,
+        }
+    }
+
+    /// When lossy, an egressor whose queue is full misses the packets that come while it is,
+    /// rather than holding up every other egressor until it has room, as a capture session
+    /// shouldn't slow down the traffic it watches. Default value is false.
+    pub fn lossy(self, lossy: bool) -> Self {
+        DynamicForkLink {
+            in_stream: self.in_stream,
+            work_budget: self.work_budget,
+            num_egressors: self.num_egressors,
+            lossy,
+            event_sink: self.event_sink,
+            shared: self.shared,
+            from_handles: self.from_handles,
+        }
+    }
+
+    /// Reports queues filling up, and packets lossy egressors miss, to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        DynamicForkLink {
+            in_stream: self.in_stream,
+            work_budget: self.work_budget,
+            num_egressors: self.num_egressors,
+            lossy: self.lossy,
+            event_sink: Some(event_sink),
+            shared: self.shared,
+            from_handles: self.from_handles,
+        }
+    }
+
+    /// A handle to subscribe egressors to the link with, before or after it is built.
+    pub fn handle(&self) -> DynamicForkHandle<Packet> {
+        DynamicForkHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<Packet: Send + Sync + 'static> LinkBuilder<Packet, Arc<Packet>> for DynamicForkLink<Packet> {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "DynamicForkLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "DynamicForkLink may only take 1 input stream",
+            ));
+        }
+        Ok(DynamicForkLink {
+            in_stream: Some(in_stream),
+            work_budget: self.work_budget,
+            num_egressors: self.num_egressors,
+            lossy: self.lossy,
+            event_sink: self.event_sink,
+            shared: self.shared,
+            from_handles: self.from_handles,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Arc<Packet>>, LinkBuildError> {
+        let handle = self.handle();
+        let in_stream = match self.in_stream {
+            Some(in_stream) => in_stream,
+            None => return Err(LinkBuildError::Missing("input stream")),
+        };
+        let egressors = (0..self.num_egressors)
+            .map(|_| handle.subscribe())
+            .collect();
+        let ingressor = DynamicForkIngressor {
+            input_stream: in_stream,
+            subscribers: vec![],
+            from_handles: self.from_handles,
+            shared: self.shared,
+            work_budget: self.work_budget,
+            lossy: self.lossy,
+            event_sink: self.event_sink,
+        };
+        Ok((vec![Box::new(ingressor)], egressors))
+    }
+}
+
+/// Subscribes egressors to a `DynamicForkLink`.
+pub struct DynamicForkHandle<Packet> {
+    shared: Arc<Shared<Packet>>,
+}
+
+impl<Packet> Clone for DynamicForkHandle<Packet> {
+    fn clone(&self) -> Self {
+        DynamicForkHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<Packet: Send + Sync + 'static> DynamicForkHandle<Packet> {
+    /// A new egressor, given every packet the link takes from now on, and ending when the link's
+    /// input does. Egressors subscribed after that end straight away.
+    pub fn subscribe(&self) -> PacketStream<Arc<Packet>> {
+        let queue_capacity = self.shared.queue_capacity.load(Ordering::Relaxed);
+        let (to_egressor, from_ingressor) = crossbeam_channel::bounded(queue_capacity);
+        let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+        let unsubscribed = Arc::new(AtomicBool::new(false));
+
+        let closed = self.shared.closed.lock().unwrap();
+        if !*closed {
+            // Only fails once the link has been dropped, taking its ingressor with it, which drops
+            // the sending end, so the egressor ends.
+            let _ = self.shared.to_ingressor.send(Subscriber {
+                to_egressor,
+                task_park: Arc::clone(&task_park),
+                unsubscribed: Arc::clone(&unsubscribed),
+                full: false,
+            });
+        }
+        Box::new(Subscription {
+            egressor: QueueEgressor::new(from_ingressor, Arc::clone(&task_park)),
+            task_park,
+            unsubscribed,
+        })
+    }
+}
+
+struct Subscriber<Packet> {
+    to_egressor: Sender<Option<Arc<Packet>>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    unsubscribed: Arc<AtomicBool>,
+    /// Whether the queue has been full since the ingressor last pushed onto it.
+    full: bool,
+}
+
+/// An egressor of a `DynamicForkLink`, which unsubscribes when dropped.
+struct Subscription<Packet> {
+    egressor: QueueEgressor<Arc<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    unsubscribed: Arc<AtomicBool>,
+}
+
+impl<Packet> Unpin for Subscription<Packet> {}
+
+impl<Packet> Stream for Subscription<Packet> {
+    type Item = Arc<Packet>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.egressor).poll_next(cx)
+    }
+}
+
+impl<Packet> Drop for Subscription<Packet> {
+    fn drop(&mut self) {
+        // Wakes the ingressor if it is waiting for room in our queue, which it won't get now.
+        self.unsubscribed.store(true, Ordering::Release);
+        die_and_wake(&self.task_park);
+    }
+}
+
+struct DynamicForkIngressor<Packet> {
+    input_stream: PacketStream<Packet>,
+    subscribers: Vec<Subscriber<Packet>>,
+    from_handles: Receiver<Subscriber<Packet>>,
+    shared: Arc<Shared<Packet>>,
+    work_budget: usize,
+    lossy: bool,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet> Unpin for DynamicForkIngressor<Packet> {}
+
+impl<Packet> DynamicForkIngressor<Packet> {
+    /// Tells every egressor, including any subscribed but not yet taken on, that the link's input
+    /// has ended.
+    fn close(&mut self) {
+        let mut closed = self.shared.closed.lock().unwrap();
+        *closed = true;
+        self.subscribers.extend(self.from_handles.try_iter());
+        for subscriber in self.subscribers.drain(..) {
+            // Only fails if the egressor has unsubscribed, or its queue is full, in which case it
+            // ends once it has drained its queue.
+            let _ = subscriber.to_egressor.try_send(None);
+            die_and_wake(&subscriber.task_park);
+        }
+    }
+}
+
+impl<Packet> Future for DynamicForkIngressor<Packet> {
+    type Output = ();
+
+    /// As with `ForkIngressor`, but taking on new egressors before each packet, and dropping
+    /// those that have unsubscribed. A queue is numbered by its place among the egressors
+    /// subscribed when it becomes full.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        for _ in 0..ingressor.work_budget {
+            ingressor
+                .subscribers
+                .extend(ingressor.from_handles.try_iter());
+            ingressor
+                .subscribers
+                .retain(|subscriber| !subscriber.unsubscribed.load(Ordering::Acquire));
+            if !ingressor.lossy {
+                let event_sink = &ingressor.event_sink;
+                for (port, subscriber) in ingressor.subscribers.iter_mut().enumerate() {
+                    if subscriber.to_egressor.is_full() {
+                        if let (false, Some(event_sink)) = (subscriber.full, event_sink) {
+                            event_sink.queue_overflow(port);
+                        }
+                        subscriber.full = true;
+                        park_and_wake(&subscriber.task_park, cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                }
+            }
+
+            let packet = match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                Some(packet) => Arc::new(packet),
+                None => {
+                    ingressor.close();
+                    return Poll::Ready(());
+                }
+            };
+            let event_sink = &ingressor.event_sink;
+            ingressor.subscribers.retain_mut(|subscriber| {
+                match subscriber.to_egressor.try_send(Some(Arc::clone(&packet))) {
+                    Ok(()) => {
+                        subscriber.full = false;
+                        unpark_and_wake(&subscriber.task_park);
+                    }
+                    Err(TrySendError::Full(_)) => {
+                        if let Some(event_sink) = event_sink {
+                            event_sink.dropped("egressor queue is full");
+                        }
+                    }
+                    Err(TrySendError::Disconnected(_)) => return false,
+                }
+                true
+            });
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use std::time::Duration;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_stream() {
+        DynamicForkLink::<i32>::new().num_egressors(2).build_link();
+    }
+
+    #[test]
+    fn shares_packets_between_egressors() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DynamicForkLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .num_egressors(2)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), packets.len());
+        for (first, second) in results[0].iter().zip(&results[1]) {
+            assert!(Arc::ptr_eq(first, second));
+        }
+        let results: Vec<i32> = results[1].iter().map(|p| **p).collect();
+        assert_eq!(results, packets);
+    }
+
+    #[test]
+    fn subscribers_get_later_packets() {
+        let mut runtime = initialize_runtime();
+        let (all, mirrored) = runtime.block_on(async {
+            let builder = DynamicForkLink::new()
+                .ingressor(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(1),
+                    0..100,
+                )))
+                .num_egressors(1);
+            let handle = builder.handle();
+            let link = builder.build_link();
+
+            let mirror = tokio::spawn(async move {
+                tokio::time::delay_for(Duration::from_millis(20)).await;
+                let mirrored: Vec<i32> = handle.subscribe().map(|p| *p).collect().await;
+                mirrored
+            });
+            let all = run_link(link).await.remove(0);
+            (all, mirror.await.unwrap())
+        });
+        assert_eq!(all.len(), 100);
+        assert!(!mirrored.is_empty());
+        assert!(mirrored.len() < 100);
+        assert_eq!(*mirrored.last().unwrap(), 99);
+        assert_eq!(
+            mirrored,
+            (100 - mirrored.len() as i32..100).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn unsubscribed_egressors_hold_up_nothing() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let builder = DynamicForkLink::new()
+                .ingressor(immediate_stream(0..1000))
+                .queue_capacity(2)
+                .num_egressors(1);
+            let unread = builder.handle().subscribe();
+            let link = builder.build_link();
+
+            tokio::spawn(async move {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+                drop(unread);
+            });
+            run_link(link).await.remove(0)
+        });
+        assert_eq!(results.len(), 1000);
+    }
+
+    #[test]
+    fn lossy_egressors_miss_packets() {
+        let mut runtime = initialize_runtime();
+        let (results, unread) = runtime.block_on(async {
+            let builder = DynamicForkLink::new()
+                .ingressor(immediate_stream(0..1000))
+                .queue_capacity(2)
+                .lossy(true)
+                .num_egressors(1);
+            let unread = builder.handle().subscribe();
+            let link = builder.build_link();
+
+            let results = run_link(link).await.remove(0);
+            let unread: Vec<i32> = unread.map(|p| *p).collect().await;
+            (results, unread)
+        });
+        assert!(!results.is_empty());
+        assert_eq!(unread, vec![0, 1]);
+    }
+}
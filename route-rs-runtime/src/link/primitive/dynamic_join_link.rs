@@ -0,0 +1,419 @@
+use crate::link::primitive::JoinIngressor;
+use crate::link::utils::task_park::*;
+use crate::link::utils::watchdog::Watchdog;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, TokioRunnable};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::{Receiver, Sender, TryRecvError};
+use futures::prelude::*;
+use futures::task::{AtomicWaker, Context, Poll};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A `JoinLink` whose ingressors can be attached and detached while it runs, through the
+/// `DynamicJoinHandle`s it hands out, as when an interface is plugged in, or a tunnel comes up
+/// and needs a branch of its own.
+///
+/// Ingressors attached after the link is built are spawned onto the runtime that polls its
+/// egressor, and the egressor takes from them in turn with the rest. Ingressors leave the link
+/// once their input ends, or they are detached. The egressor ends once it has no ingressors left
+/// and every handle has been dropped, so no more can come.
+pub struct DynamicJoinLink<Packet> {
+    in_streams: Vec<PacketStream<Packet>>,
+    queue_capacity: usize,
+    batch_size: usize,
+    work_budget: usize,
+    to_egressor: Sender<PacketStream<Packet>>,
+    from_handles: Receiver<PacketStream<Packet>>,
+    egressor_task: Arc<AtomicWaker>,
+}
+
+impl<Packet> Default for DynamicJoinLink<Packet> {
+    fn default() -> Self {
+        DynamicJoinLink::new()
+    }
+}
+
+impl<Packet> DynamicJoinLink<Packet> {
+    pub fn new() -> Self {
+        let (to_egressor, from_handles) = crossbeam_channel::unbounded();
+        DynamicJoinLink {
+            in_streams: vec![],
+            queue_capacity: 10,
+            batch_size: 8,
+            work_budget: 128,
+            to_egressor,
+            from_handles,
+            egressor_task: Arc::new(AtomicWaker::new()),
+        }
+    }
+
+    /// Changes queue_capacity, the capacity of the queue of each ingressor, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(queue_capacity > 0, "queue_capacity must be > 0");
+        DynamicJoinLink {
+            in_streams: self.in_streams,
+            queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            to_egressor: self.to_egressor,
+            from_handles: self.from_handles,
+            egressor_task: self.egressor_task,
+        }
+    }
+
+    /// Changes batch_size, as for `JoinLink`, default value is 8.
+    pub fn batch_size(self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch_size must be > 0");
+        DynamicJoinLink {
+            in_streams: self.in_streams,
+            queue_capacity: self.queue_capacity,
+            batch_size,
+            work_budget: self.work_budget,
+            to_egressor: self.to_egressor,
+            from_handles: self.from_handles,
+            egressor_task: self.egressor_task,
+        }
+    }
+
+    /// Changes work_budget, as for `JoinLink`, default value is 128.
+    pub fn work_budget(self, work_budget: usize) -> Self {
+        assert!(work_budget > 0, "work_budget must be > 0");
+        DynamicJoinLink {
+            in_streams: self.in_streams,
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget,
+            to_egressor: self.to_egressor,
+            from_handles: self.from_handles,
+            egressor_task: self.egressor_task,
+        }
+    }
+
+    /// A handle to attach ingressors to the link with, before or after it is built.
+    pub fn handle(&self) -> DynamicJoinHandle<Packet> {
+        DynamicJoinHandle {
+            to_egressor: self.to_egressor.clone(),
+            egressor_task: Arc::clone(&self.egressor_task),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for DynamicJoinLink<Packet> {
+    fn try_ingressors(self, in_streams: Vec<PacketStream<Packet>>) -> Result<Self, LinkBuildError> {
+        if !self.in_streams.is_empty() {
+            return Err(LinkBuildError::Ingressors(
+                "DynamicJoinLink already has input streams",
+            ));
+        }
+        Ok(DynamicJoinLink {
+            in_streams,
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            to_egressor: self.to_egressor,
+            from_handles: self.from_handles,
+            egressor_task: self.egressor_task,
+        })
+    }
+
+    /// Appends the ingressor to the ingressors of the link.
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        let mut in_streams = self.in_streams;
+        in_streams.push(in_stream);
+        Ok(DynamicJoinLink {
+            in_streams,
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            to_egressor: self.to_egressor,
+            from_handles: self.from_handles,
+            egressor_task: self.egressor_task,
+        })
+    }
+
+    /// Builds the link, which may have no ingressors until some are attached.
+    fn try_build_link(self) -> Result<Link<Packet>, LinkBuildError> {
+        let watchdog = Watchdog::global();
+        let mut egressor = DynamicJoinEgressor {
+            inputs: vec![],
+            from_handles: self.from_handles,
+            handles_dropped: false,
+            egressor_task: self.egressor_task,
+            next_pull_ingressor: 0,
+            next_port: 0,
+            link_id: watchdog.next_id(),
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+        };
+        let ingressors = self
+            .in_streams
+            .into_iter()
+            .map(|in_stream| Box::new(egressor.attach(in_stream)) as TokioRunnable)
+            .collect();
+        Ok((ingressors, vec![Box::new(egressor)]))
+    }
+}
+
+/// Attaches ingressors to a `DynamicJoinLink`, and keeps it running while any is held.
+pub struct DynamicJoinHandle<Packet> {
+    to_egressor: Sender<PacketStream<Packet>>,
+    egressor_task: Arc<AtomicWaker>,
+}
+
+impl<Packet> Clone for DynamicJoinHandle<Packet> {
+    fn clone(&self) -> Self {
+        DynamicJoinHandle {
+            to_egressor: self.to_egressor.clone(),
+            egressor_task: Arc::clone(&self.egressor_task),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> DynamicJoinHandle<Packet> {
+    /// Attaches an ingressor, returning what detaches it. Packets the link takes from it are
+    /// joined with the rest from then on.
+    pub fn attach(&self, in_stream: PacketStream<Packet>) -> Attachment {
+        let detached = Arc::new(Detached {
+            detached: AtomicBool::new(false),
+            ingressor_task: AtomicWaker::new(),
+        });
+        let stream = Detachable {
+            in_stream,
+            detached: Arc::clone(&detached),
+        };
+        // Only fails once the link has been dropped, taking its ingressors with it.
+        let _ = self.to_egressor.send(Box::new(stream));
+        self.egressor_task.wake();
+        Attachment { detached }
+    }
+}
+
+impl<Packet> Drop for DynamicJoinHandle<Packet> {
+    fn drop(&mut self) {
+        // So the egressor checks whether this was the last handle.
+        self.egressor_task.wake();
+    }
+}
+
+/// An ingressor attached to a `DynamicJoinLink`.
+pub struct Attachment {
+    detached: Arc<Detached>,
+}
+
+impl Attachment {
+    /// Detaches the ingressor, which ends as if its input had, once it has room in its queue to say
+    /// so. Packets it has already passed on still leave the link.
+    pub fn detach(&self) {
+        self.detached.detached.store(true, Ordering::Release);
+        self.detached.ingressor_task.wake();
+    }
+}
+
+struct Detached {
+    detached: AtomicBool,
+    ingressor_task: AtomicWaker,
+}
+
+/// The input of an attached ingressor, which ends once detached.
+struct Detachable<Packet> {
+    in_stream: PacketStream<Packet>,
+    detached: Arc<Detached>,
+}
+
+impl<Packet> Unpin for Detachable<Packet> {}
+
+impl<Packet> Stream for Detachable<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.detached.ingressor_task.register(cx.waker());
+        if self.detached.detached.load(Ordering::Acquire) {
+            return Poll::Ready(None);
+        }
+        Pin::new(&mut self.in_stream).poll_next(cx)
+    }
+}
+
+struct Input<Packet> {
+    from_ingressor: Receiver<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    unwoken: usize,
+}
+
+struct DynamicJoinEgressor<Packet> {
+    inputs: Vec<Input<Packet>>,
+    from_handles: Receiver<PacketStream<Packet>>,
+    handles_dropped: bool,
+    egressor_task: Arc<AtomicWaker>,
+    next_pull_ingressor: usize,
+    next_port: usize,
+    link_id: usize,
+    queue_capacity: usize,
+    batch_size: usize,
+    work_budget: usize,
+}
+
+impl<Packet> Unpin for DynamicJoinEgressor<Packet> {}
+
+impl<Packet: Send + 'static> DynamicJoinEgressor<Packet> {
+    /// Adds an input for an ingressor of `in_stream`, returning the ingressor.
+    fn attach(&mut self, in_stream: PacketStream<Packet>) -> JoinIngressor<Packet> {
+        let port = self.next_port;
+        self.next_port += 1;
+        let (to_egressor, from_ingressor) =
+            crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+        let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+        if cfg!(debug_assertions) {
+            let queue = to_egressor.clone();
+            Watchdog::global().register(
+                format!("DynamicJoinLink#{} ingressor {}", self.link_id, port),
+                &task_park,
+                self.queue_capacity,
+                move || queue.len(),
+            );
+        }
+
+        self.inputs.push(Input {
+            from_ingressor,
+            task_park: Arc::clone(&task_park),
+            unwoken: 0,
+        });
+        JoinIngressor::new(
+            in_stream,
+            to_egressor,
+            task_park,
+            self.batch_size,
+            self.work_budget,
+            port,
+            None,
+        )
+    }
+}
+
+impl<Packet: Send + 'static> Stream for DynamicJoinEgressor<Packet> {
+    type Item = Packet;
+
+    /// As with `JoinEgressor`, takes the first packet available, starting from the ingressor after
+    /// the one last taken from, having first attached any ingressors handed over by the handles.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let egressor = Pin::into_inner(self);
+        egressor.egressor_task.register(cx.waker());
+        loop {
+            match egressor.from_handles.try_recv() {
+                Ok(in_stream) => {
+                    tokio::spawn(egressor.attach(in_stream));
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    egressor.handles_dropped = true;
+                    break;
+                }
+            }
+        }
+
+        let mut port = 0;
+        let mut tried = 0;
+        while tried < egressor.inputs.len() {
+            port = (egressor.next_pull_ingressor + tried) % egressor.inputs.len();
+            let input = &mut egressor.inputs[port];
+            match input.from_ingressor.try_recv() {
+                Ok(Some(packet)) => {
+                    batch_and_wake(&input.task_park, &mut input.unwoken, egressor.batch_size);
+                    egressor.next_pull_ingressor = port + 1;
+                    return Poll::Ready(Some(packet));
+                }
+                Ok(None) => {
+                    // The ingressor has ended, and sends nothing after.
+                    egressor.inputs.remove(port);
+                    continue;
+                }
+                Err(_) => tried += 1,
+            }
+        }
+        egressor.next_pull_ingressor = port;
+        if egressor.inputs.is_empty() && egressor.handles_dropped {
+            return Poll::Ready(None);
+        }
+
+        // Parks as `JoinEgressor` does. With no ingressors, it is left to the handles to wake us.
+        let egressor_task = Arc::new(AtomicCell::new(Some(cx.waker().clone())));
+        let mut parked_egressor_task = egressor.inputs.is_empty();
+        for input in egressor.inputs.iter_mut() {
+            input.unwoken = 0;
+            if indirect_park_and_wake(&input.task_park, Arc::clone(&egressor_task)) {
+                parked_egressor_task = true;
+            }
+        }
+        if !parked_egressor_task {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use std::time::Duration;
+
+    #[test]
+    fn ends_without_ingressors_or_handles() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DynamicJoinLink::<i32>::new().build_link();
+            run_link(link).await
+        });
+        assert_eq!(results[0], Vec::<i32>::new());
+    }
+
+    #[test]
+    fn attaches_ingressors_after_build() {
+        let mut runtime = initialize_runtime();
+        let mut results = runtime.block_on(async {
+            let builder = DynamicJoinLink::new().ingressor(immediate_stream(0..5));
+            let handle = builder.handle();
+            let link = builder.build_link();
+
+            tokio::spawn(async move {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+                handle.attach(immediate_stream(100..105));
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+                handle.attach(immediate_stream(200..205));
+            });
+            run_link(link).await.remove(0)
+        });
+        results.sort();
+        assert_eq!(
+            results,
+            (0..5).chain(100..105).chain(200..205).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn detaches_ingressors() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let builder = DynamicJoinLink::new();
+            let handle = builder.handle();
+            let link = builder.build_link();
+
+            tokio::spawn(async move {
+                let attachment = handle.attach(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(1),
+                    0..10_000,
+                )));
+                tokio::time::delay_for(Duration::from_millis(20)).await;
+                attachment.detach();
+            });
+            run_link(link).await.remove(0)
+        });
+        assert!(!results.is_empty());
+        assert!(results.len() < 10_000);
+        assert_eq!(results, (0..results.len() as i32).collect::<Vec<_>>());
+    }
+}
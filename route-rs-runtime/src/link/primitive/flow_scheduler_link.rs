@@ -0,0 +1,348 @@
+use crate::link::event::EventSink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::pin::Pin;
+
+type FlowKeyFn<Packet, K> = Box<dyn Fn(&Packet) -> K + Send>;
+type MarkFn<Packet> = Box<dyn Fn(&mut Packet) -> bool + Send>;
+
+/// Queues packets by flow, and sends them on taking one from each flow with packets waiting in
+/// turn, as Stochastic Fairness Queueing does, so a light interactive flow isn't stuck behind a
+/// bulk one sharing its port. Flows are told apart by the key `flow_key` gives each packet, such as
+/// its `FlowKey`.
+///
+/// The link takes packets from its ingressor for as long as it has them, up to `capacity` packets
+/// across every flow, and sends on the next in turn whenever asked, so it never holds back a packet
+/// while another waits. A flow with `flow_capacity` packets waiting has any more of its packets
+/// dropped, so no one flow fills the link. A flow is forgotten once it has no packets waiting.
+///
+/// With `ecn`, a flow with a threshold of packets waiting is taken to be congested before it is
+/// full, and its packets are marked Congestion Experienced rather than queued as they were, so
+/// the senders of ECN capable flows slow down without losing packets, RFC 3168. Packets that
+/// can't be marked, not being ECN capable, are dropped instead, as a full flow's are.
+pub struct FlowSchedulerLink<Packet, K> {
+    in_stream: Option<PacketStream<Packet>>,
+    flow_key: Option<FlowKeyFn<Packet, K>>,
+    capacity: usize,
+    flow_capacity: usize,
+    ecn: Option<(usize, MarkFn<Packet>)>,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet, K> Default for FlowSchedulerLink<Packet, K> {
+    fn default() -> Self {
+        FlowSchedulerLink::new()
+    }
+}
+
+impl<Packet, K> FlowSchedulerLink<Packet, K> {
+    pub fn new() -> Self {
+        FlowSchedulerLink {
+            in_stream: None,
+            flow_key: None,
+            capacity: 1024,
+            flow_capacity: 64,
+            ecn: None,
+            event_sink: None,
+        }
+    }
+
+    /// The key of the flow a packet belongs to.
+    pub fn flow_key<F: Fn(&Packet) -> K + Send + 'static>(self, flow_key: F) -> Self {
+        FlowSchedulerLink {
+            in_stream: self.in_stream,
+            flow_key: Some(Box::new(flow_key)),
+            capacity: self.capacity,
+            flow_capacity: self.flow_capacity,
+            ecn: self.ecn,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes capacity, the number of packets of every flow the link holds before it stops taking
+    /// more, default value is 1024.
+    pub fn capacity(self, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be > 0");
+        FlowSchedulerLink {
+            in_stream: self.in_stream,
+            flow_key: self.flow_key,
+            capacity,
+            flow_capacity: self.flow_capacity,
+            ecn: self.ecn,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes flow_capacity, the number of packets of one flow the link holds before it drops the
+    /// flow's packets, default value is 64.
+    pub fn flow_capacity(self, flow_capacity: usize) -> Self {
+        assert!(flow_capacity > 0, "flow_capacity must be > 0");
+        FlowSchedulerLink {
+            in_stream: self.in_stream,
+            flow_key: self.flow_key,
+            capacity: self.capacity,
+            flow_capacity,
+            ecn: self.ecn,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Marks the packets of flows with `threshold` packets waiting with `mark`, which sets
+    /// Congestion Experienced on a packet and returns whether it could, as
+    /// `EthernetFrame::mark_ce` does. The threshold must be less than flow_capacity to matter.
+    pub fn ecn<F: Fn(&mut Packet) -> bool + Send + 'static>(
+        self,
+        threshold: usize,
+        mark: F,
+    ) -> Self {
+        assert!(threshold > 0, "threshold must be > 0");
+        FlowSchedulerLink {
+            in_stream: self.in_stream,
+            flow_key: self.flow_key,
+            capacity: self.capacity,
+            flow_capacity: self.flow_capacity,
+            ecn: Some((threshold, Box::new(mark))),
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Reports packets dropped for a full or congested flow queue to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        FlowSchedulerLink {
+            in_stream: self.in_stream,
+            flow_key: self.flow_key,
+            capacity: self.capacity,
+            flow_capacity: self.flow_capacity,
+            ecn: self.ecn,
+            event_sink: Some(event_sink),
+        }
+    }
+}
+
+impl<Packet, K> LinkBuilder<Packet, Packet> for FlowSchedulerLink<Packet, K>
+where
+    Packet: Send + 'static,
+    K: Hash + Eq + Clone + Send + 'static,
+{
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "FlowSchedulerLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "FlowSchedulerLink may only take 1 input stream",
+            ));
+        }
+        Ok(FlowSchedulerLink {
+            in_stream: Some(in_stream),
+            flow_key: self.flow_key,
+            capacity: self.capacity,
+            flow_capacity: self.flow_capacity,
+            ecn: self.ecn,
+            event_sink: self.event_sink,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Packet>, LinkBuildError> {
+        match (self.in_stream, self.flow_key) {
+            (None, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None) => Err(LinkBuildError::Missing("flow_key")),
+            (Some(in_stream), Some(flow_key)) => Ok((
+                vec![],
+                vec![Box::new(FlowScheduler {
+                    in_stream,
+                    flow_key,
+                    flows: HashMap::new(),
+                    turns: VecDeque::new(),
+                    queued: 0,
+                    ended: false,
+                    capacity: self.capacity,
+                    flow_capacity: self.flow_capacity,
+                    ecn: self.ecn,
+                    event_sink: self.event_sink,
+                })],
+            )),
+        }
+    }
+}
+
+struct FlowScheduler<Packet, K> {
+    in_stream: PacketStream<Packet>,
+    flow_key: FlowKeyFn<Packet, K>,
+    flows: HashMap<K, VecDeque<Packet>>,
+    /// Keys of the flows with packets waiting, the next to send from first.
+    turns: VecDeque<K>,
+    queued: usize,
+    ended: bool,
+    capacity: usize,
+    flow_capacity: usize,
+    ecn: Option<(usize, MarkFn<Packet>)>,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet, K> Unpin for FlowScheduler<Packet, K> {}
+
+impl<Packet, K: Hash + Eq + Clone> FlowScheduler<Packet, K> {
+    fn enqueue(&mut self, mut packet: Packet) {
+        let key = (self.flow_key)(&packet);
+        let flow = match self.flows.get_mut(&key) {
+            Some(flow) => flow,
+            None => {
+                self.turns.push_back(key.clone());
+                self.flows.entry(key).or_default()
+            }
+        };
+        let dropped = if flow.len() >= self.flow_capacity {
+            Some("flow queue is full")
+        } else {
+            match &self.ecn {
+                Some((threshold, mark)) if flow.len() >= *threshold && !mark(&mut packet) => {
+                    Some("flow queue is congested")
+                }
+                _ => None,
+            }
+        };
+        if let Some(reason) = dropped {
+            if let Some(event_sink) = &self.event_sink {
+                event_sink.dropped(reason);
+            }
+            return;
+        }
+        flow.push_back(packet);
+        self.queued += 1;
+    }
+
+    fn dequeue(&mut self) -> Option<Packet> {
+        let key = self.turns.pop_front()?;
+        let flow = self.flows.get_mut(&key).unwrap();
+        let packet = flow.pop_front().unwrap();
+        self.queued -= 1;
+        if flow.is_empty() {
+            self.flows.remove(&key);
+        } else {
+            self.turns.push_back(key);
+        }
+        Some(packet)
+    }
+}
+
+impl<Packet, K: Hash + Eq + Clone> Stream for FlowScheduler<Packet, K> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let scheduler = &mut *self;
+        while !scheduler.ended && scheduler.queued < scheduler.capacity {
+            match Pin::new(&mut scheduler.in_stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => scheduler.enqueue(packet),
+                Poll::Ready(None) => scheduler.ended = true,
+                Poll::Pending => break,
+            }
+        }
+        match scheduler.dequeue() {
+            Some(packet) => Poll::Ready(Some(packet)),
+            None if scheduler.ended => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::event::LinkEvent;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use crossbeam::crossbeam_channel;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_flow_key() {
+        FlowSchedulerLink::<i32, i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn light_flows_pass_bulk_flows() {
+        // A bulk flow of 0..50, then an interactive flow of 1000..1003 behind it.
+        let packets: Vec<i32> = (0..50).chain(1000..1003).collect();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = FlowSchedulerLink::new()
+                .ingressor(immediate_stream(packets))
+                .flow_key(|packet: &i32| *packet / 1000)
+                .build_link();
+
+            run_link(link).await.remove(0)
+        });
+        assert_eq!(results.len(), 53);
+        assert_eq!(&results[..6], &[0, 1000, 1, 1001, 2, 1002]);
+        assert_eq!(
+            results
+                .iter()
+                .filter(|p| **p < 1000)
+                .cloned()
+                .collect::<Vec<_>>(),
+            (0..50).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn bounds_flow_queues() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = FlowSchedulerLink::new()
+                .ingressor(immediate_stream(0..50))
+                .flow_key(|_: &i32| ())
+                .flow_capacity(10)
+                .event_sink(EventSink::new("sfq", sender))
+                .build_link();
+
+            run_link(link).await.remove(0)
+        });
+        assert_eq!(results, (0..10).collect::<Vec<_>>());
+        let events: Vec<LinkEvent> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 40);
+    }
+
+    #[test]
+    fn marks_congested_flows() {
+        // Even packets are ECN capable, and marked by negating them.
+        let mark = |packet: &mut i32| {
+            if *packet % 2 == 0 {
+                *packet = -*packet;
+                true
+            } else {
+                false
+            }
+        };
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = FlowSchedulerLink::new()
+                .ingressor(immediate_stream(0..20))
+                .flow_key(|_: &i32| ())
+                .flow_capacity(10)
+                .ecn(4, mark)
+                .build_link();
+
+            run_link(link).await.remove(0)
+        });
+        assert_eq!(results, vec![0, 1, 2, 3, -4, -6, -8, -10, -12, -14]);
+    }
+}
@@ -1,5 +1,8 @@
+use crate::link::event::EventSink;
 use crate::link::utils::task_park::*;
-use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crate::link::utils::watchdog::Watchdog;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::metrics;
 use crossbeam::atomic::AtomicCell;
 use crossbeam::crossbeam_channel;
 use crossbeam::crossbeam_channel::{Receiver, Sender};
@@ -12,7 +15,9 @@ use std::sync::Arc;
 pub struct ForkLink<Packet: Clone + Send> {
     in_stream: Option<PacketStream<Packet>>,
     queue_capacity: usize,
+    work_budget: usize,
     num_egressors: Option<usize>,
+    event_sink: Option<EventSink>,
 }
 
 impl<Packet: Clone + Send> ForkLink<Packet> {
@@ -20,7 +25,9 @@ impl<Packet: Clone + Send> ForkLink<Packet> {
         ForkLink {
             in_stream: None,
             queue_capacity: 10,
+            work_budget: 128,
             num_egressors: None,
+            event_sink: None,
         }
     }
 
@@ -34,7 +41,23 @@ impl<Packet: Clone + Send> ForkLink<Packet> {
         ForkLink {
             in_stream: self.in_stream,
             queue_capacity,
+            work_budget: self.work_budget,
             num_egressors: self.num_egressors,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes work_budget, the number of packets the ingressor takes from its
+    /// input in one poll before yielding to other tasks, default value is 128.
+    pub fn work_budget(self, work_budget: usize) -> Self {
+        assert!(work_budget > 0, "work_budget must be > 0");
+
+        ForkLink {
+            in_stream: self.in_stream,
+            queue_capacity: self.queue_capacity,
+            work_budget,
+            num_egressors: self.num_egressors,
+            event_sink: self.event_sink,
         }
     }
 
@@ -47,71 +70,103 @@ impl<Packet: Clone + Send> ForkLink<Packet> {
         ForkLink {
             in_stream: self.in_stream,
             queue_capacity: self.queue_capacity,
+            work_budget: self.work_budget,
             num_egressors: Some(num_egressors),
+            event_sink: self.event_sink,
         }
     }
-}
-
-impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for ForkLink<Packet> {
-    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
-        assert_eq!(
-            in_streams.len(),
-            1,
-            "ForkLinks may only take one input stream!"
-        );
-
-        if self.in_stream.is_some() {
-            panic!("ForkLink may only take 1 input stream")
-        }
 
+    /// Reports queues filling up to `event_sink`. With a sink, copies of packets for an egressor
+    /// that has been dropped are dropped and reported rather than panicking.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
         ForkLink {
-            in_stream: Some(in_streams.remove(0)),
+            in_stream: self.in_stream,
             queue_capacity: self.queue_capacity,
+            work_budget: self.work_budget,
             num_egressors: self.num_egressors,
+            event_sink: Some(event_sink),
+        }
+    }
+}
+
+impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for ForkLink<Packet> {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "ForkLink may only take 1 input stream",
+            ));
         }
+        self.try_ingressor(in_streams.remove(0))
     }
 
-    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
         if self.in_stream.is_some() {
-            panic!("ForkLink may only take 1 input stream")
+            return Err(LinkBuildError::Ingressors(
+                "ForkLink may only take 1 input stream",
+            ));
         }
 
-        ForkLink {
+        Ok(ForkLink {
             in_stream: Some(in_stream),
             queue_capacity: self.queue_capacity,
+            work_budget: self.work_budget,
             num_egressors: self.num_egressors,
-        }
+            event_sink: self.event_sink,
+        })
     }
 
-    fn build_link(self) -> Link<Packet> {
-        if self.in_stream.is_none() {
-            panic!("Cannot build link! Missing input stream");
-        } else if self.num_egressors.is_none() {
-            panic!("Cannot build link! Missing number of num_egressors");
-        } else {
-            let mut to_egressors: Vec<Sender<Option<Packet>>> = Vec::new();
-            let mut egressors: Vec<PacketStream<Packet>> = Vec::new();
-
-            let mut from_ingressors: Vec<Receiver<Option<Packet>>> = Vec::new();
+    fn try_build_link(self) -> Result<Link<Packet>, LinkBuildError> {
+        match (self.in_stream, self.num_egressors) {
+            (None, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None) => Err(LinkBuildError::Missing("number of num_egressors")),
+            (Some(in_stream), Some(num_egressors)) => {
+                let mut to_egressors: Vec<Sender<Option<Packet>>> = Vec::new();
+                let mut egressors: Vec<PacketStream<Packet>> = Vec::new();
+
+                let mut from_ingressors: Vec<Receiver<Option<Packet>>> = Vec::new();
+
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+                let watchdog = Watchdog::global();
+                let link_id = watchdog.next_id();
+
+                for port in 0..num_egressors {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+                    let queue = to_egressor.clone();
+                    metrics::register_queue(self.queue_capacity, move || queue.len());
+                    if cfg!(debug_assertions) {
+                        let queue = to_egressor.clone();
+                        watchdog.register(
+                            format!("ForkLink#{} egressor {}", link_id, port),
+                            &task_park,
+                            self.queue_capacity,
+                            move || queue.len(),
+                        );
+                    }
 
-            let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+                    let egressor =
+                        QueueEgressor::new(from_ingressor.clone(), Arc::clone(&task_park));
 
-            for _ in 0..self.num_egressors.unwrap() {
-                let (to_egressor, from_ingressor) =
-                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
-                let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+                    to_egressors.push(to_egressor);
+                    egressors.push(Box::new(egressor));
+                    from_ingressors.push(from_ingressor);
+                    task_parks.push(task_park);
+                }
 
-                let egressor = QueueEgressor::new(from_ingressor.clone(), Arc::clone(&task_park));
+                let ingressor = ForkIngressor::new(
+                    in_stream,
+                    to_egressors,
+                    task_parks,
+                    self.work_budget,
+                    self.event_sink,
+                );
 
-                to_egressors.push(to_egressor);
-                egressors.push(Box::new(egressor));
-                from_ingressors.push(from_ingressor);
-                task_parks.push(task_park);
+                Ok((vec![Box::new(ingressor)], egressors))
             }
-
-            let ingressor = ForkIngressor::new(self.in_stream.unwrap(), to_egressors, task_parks);
-
-            (vec![Box::new(ingressor)], egressors)
         }
     }
 }
@@ -120,6 +175,10 @@ pub struct ForkIngressor<P> {
     input_stream: PacketStream<P>,
     to_egressors: Vec<Sender<Option<P>>>,
     task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    work_budget: usize,
+    event_sink: Option<EventSink>,
+    /// Whether each to_egressor queue has been full since we last pushed onto it.
+    full: Vec<bool>,
 }
 
 impl<P> ForkIngressor<P> {
@@ -127,11 +186,17 @@ impl<P> ForkIngressor<P> {
         input_stream: PacketStream<P>,
         to_egressors: Vec<Sender<Option<P>>>,
         task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+        work_budget: usize,
+        event_sink: Option<EventSink>,
     ) -> Self {
+        let full = vec![false; to_egressors.len()];
         ForkIngressor {
             input_stream,
             to_egressors,
             task_parks,
+            work_budget,
+            event_sink,
+            full,
         }
     }
 }
@@ -139,11 +204,18 @@ impl<P> ForkIngressor<P> {
 impl<P: Send + Clone> Future for ForkIngressor<P> {
     type Output = ();
 
-    /// If any of the channels are full, we await that channel to clear before processing a new packet.
+    /// If any of the channels are full, we await that channel to clear before processing a new packet,
+    /// reporting to our event sink, if we have one, that it has become full.
+    /// Once we have taken `work_budget` packets in one poll, we wake ourselves and sleep, so that
+    /// other tasks on this thread get a turn.
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        loop {
-            for (port, to_egressor) in self.to_egressors.iter().enumerate() {
-                if to_egressor.is_full() {
+        for _ in 0..self.work_budget {
+            for port in 0..self.to_egressors.len() {
+                if self.to_egressors[port].is_full() {
+                    if let (false, Some(event_sink)) = (self.full[port], &self.event_sink) {
+                        event_sink.queue_overflow(port);
+                    }
+                    self.full[port] = true;
                     park_and_wake(&self.task_parks[port], cx.waker().clone());
                     return Poll::Pending;
                 }
@@ -154,7 +226,9 @@ impl<P: Send + Clone> Future for ForkIngressor<P> {
                 None => {
                     for to_egressor in self.to_egressors.iter() {
                         if let Err(err) = to_egressor.try_send(None) {
-                            panic!("Ingressor: Drop: try_send to egressor, fail?: {:?}", err);
+                            if self.event_sink.is_none() {
+                                panic!("Ingressor: Drop: try_send to egressor, fail?: {:?}", err);
+                            }
                         }
                     }
                     for task_park in self.task_parks.iter() {
@@ -167,16 +241,23 @@ impl<P: Send + Clone> Future for ForkIngressor<P> {
                     assert!(self.to_egressors.len() == self.task_parks.len());
                     for port in 0..self.to_egressors.len() {
                         if let Err(err) = self.to_egressors[port].try_send(Some(packet.clone())) {
-                            panic!(
-                                "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
-                                port, err
-                            );
+                            match &self.event_sink {
+                                Some(event_sink) => event_sink.dropped("egressor has been dropped"),
+                                None => panic!(
+                                    "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
+                                    port, err
+                                ),
+                            }
+                        } else {
+                            self.full[port] = false;
                         }
                         unpark_and_wake(&self.task_parks[port]);
                     }
                 }
             }
         }
+        cx.waker().wake_by_ref();
+        Poll::Pending
     }
 }
 
@@ -277,4 +358,27 @@ mod tests {
         assert_eq!(results[1], packets.clone());
         assert_eq!(results[2], packets);
     }
+
+    #[test]
+    fn reports_packets_for_dropped_egressors() {
+        let (sender, events) = crossbeam_channel::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (runnables, mut egressors) = ForkLink::new()
+                .ingressor(immediate_stream(vec![1, 2, 3]))
+                .num_egressors(2)
+                .event_sink(EventSink::new("fork", sender))
+                .build_link();
+            egressors.truncate(1);
+
+            run_link((runnables, egressors)).await
+        });
+        assert_eq!(results[0], vec![1, 2, 3]);
+        let events: Vec<String> = events.try_iter().map(|event| event.to_string()).collect();
+        assert_eq!(
+            events,
+            vec!["fork: dropped packet, egressor has been dropped"; 3]
+        );
+    }
 }
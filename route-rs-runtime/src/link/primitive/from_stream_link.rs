@@ -0,0 +1,86 @@
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use futures::prelude::*;
+
+/// Takes any stream as the input of a pipeline, such as the messages of a QUIC connection or
+/// WebSocket, or the lines of a file, without a bespoke link. The pipeline ends once the stream
+/// does.
+pub struct FromStreamLink<S> {
+    stream: Option<S>,
+}
+
+impl<S> Default for FromStreamLink<S> {
+    fn default() -> Self {
+        FromStreamLink::new()
+    }
+}
+
+impl<S> FromStreamLink<S> {
+    pub fn new() -> Self {
+        FromStreamLink { stream: None }
+    }
+
+    pub fn stream(self, stream: S) -> Self {
+        FromStreamLink {
+            stream: Some(stream),
+        }
+    }
+}
+
+impl<S> LinkBuilder<(), S::Item> for FromStreamLink<S>
+where
+    S: Stream + Send + 'static,
+    S::Item: Send + 'static,
+{
+    fn try_ingressors(self, _in_streams: Vec<PacketStream<()>>) -> Result<Self, LinkBuildError> {
+        Err(LinkBuildError::Ingressors(
+            "FromStreamLink does not take stream ingressors",
+        ))
+    }
+
+    fn try_ingressor(self, _in_stream: PacketStream<()>) -> Result<Self, LinkBuildError> {
+        Err(LinkBuildError::Ingressors(
+            "FromStreamLink does not take any stream ingressors",
+        ))
+    }
+
+    fn try_build_link(self) -> Result<Link<S::Item>, LinkBuildError> {
+        match self.stream {
+            None => Err(LinkBuildError::Missing("stream")),
+            // Pinned on the heap, so the stream need not be Unpin.
+            Some(stream) => Ok((vec![], vec![Box::new(Box::pin(stream))])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_with_ingressors() {
+        FromStreamLink::<stream::Empty<i32>>::new()
+            .ingressors(vec![immediate_stream(vec![])])
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_stream() {
+        FromStreamLink::<stream::Empty<i32>>::new().build_link();
+    }
+
+    #[test]
+    fn takes_packets_from_any_stream() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let lines = stream::iter(vec!["a", "bb", "ccc"]).then(|line| async move { line.len() });
+            let link = FromStreamLink::new().stream(lines).build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![1, 2, 3]);
+    }
+}
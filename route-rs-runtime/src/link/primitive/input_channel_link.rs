@@ -1,53 +1,104 @@
-use crate::link::{Link, LinkBuilder, PacketStream};
+use crate::link::message::ControlMsg;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
 use crossbeam::crossbeam_channel;
 use futures::prelude::*;
 use futures::task::{Context, Poll};
 use std::pin::Pin;
 
+/// Reads packets from a channel, such as the receive queue of a NIC, as fast as the pipeline takes
+/// them.
+///
+/// Several threads of an application can inject packets into one pipeline, each with a clone of
+/// the same `crossbeam::Sender`, or each with a channel of its own, given to the link by calling
+/// `add_channel` for each. Packets sent into one channel leave the link in the order they were sent,
+/// whichever clone of the sender sent them. The link takes from several channels in turn, one
+/// packet from each that has one waiting, so packets of different channels are interleaved, and
+/// one channel can't starve the others. The stream ends once every channel has disconnected.
+///
+/// With `credits`, the link reads only as many packets as it has credits for, one each, starting
+/// with `initial` and adding those granted on the control channel as `ControlMsg::Credit`s,
+/// usually by the `OutputChannelLink` at the end of the pipeline as it sends packets on. Once out
+/// of credits, it leaves packets in the channel, so a pipeline that can't send as fast as it
+/// receives pushes back on the NIC rather than dropping packets silently at whichever of its
+/// queues is deepest. Packets dropped on the way get their credits back from the `EventSink`s
+/// of the links that drop them, built with `grant_credits` on the same control channel; a link
+/// dropping packets without such a sink leaks a credit for each, until the link reads nothing
+/// more. The stream ends once it is out of credits and nothing is left to grant more.
 #[derive(Default)]
 pub struct InputChannelLink<Packet> {
-    channel_receiver: Option<crossbeam::Receiver<Packet>>,
+    channel_receivers: Vec<crossbeam::Receiver<Packet>>,
+    credits: Option<(crossbeam::Receiver<ControlMsg>, usize)>,
 }
 
 impl<Packet> InputChannelLink<Packet> {
     pub fn new() -> Self {
         InputChannelLink {
-            channel_receiver: None,
+            channel_receivers: vec![],
+            credits: None,
         }
     }
 
+    /// The channel to read packets from, replacing any given before.
     pub fn channel(self, channel_receiver: crossbeam::Receiver<Packet>) -> Self {
         InputChannelLink {
-            channel_receiver: Some(channel_receiver),
+            channel_receivers: vec![channel_receiver],
+            credits: self.credits,
+        }
+    }
+
+    /// Adds a channel to read packets from, alongside any already given.
+    pub fn add_channel(self, channel_receiver: crossbeam::Receiver<Packet>) -> Self {
+        let mut channel_receivers = self.channel_receivers;
+        channel_receivers.push(channel_receiver);
+        InputChannelLink {
+            channel_receivers,
+            credits: self.credits,
+        }
+    }
+
+    /// Reads only the packets granted credits on `control`, after the first `initial`.
+    pub fn credits(self, control: crossbeam::Receiver<ControlMsg>, initial: usize) -> Self {
+        InputChannelLink {
+            channel_receivers: self.channel_receivers,
+            credits: Some((control, initial)),
         }
     }
 }
 
 impl<Packet: Send + 'static> LinkBuilder<(), Packet> for InputChannelLink<Packet> {
-    fn ingressors(self, mut _in_streams: Vec<PacketStream<()>>) -> Self {
-        panic!("InputChannelLink does not take stream ingressors")
+    fn try_ingressors(self, _in_streams: Vec<PacketStream<()>>) -> Result<Self, LinkBuildError> {
+        Err(LinkBuildError::Ingressors(
+            "InputChannelLink does not take stream ingressors",
+        ))
     }
 
-    fn ingressor(self, _in_stream: PacketStream<()>) -> Self {
-        panic!("InputChannelLink does not take any stream ingressors")
+    fn try_ingressor(self, _in_stream: PacketStream<()>) -> Result<Self, LinkBuildError> {
+        Err(LinkBuildError::Ingressors(
+            "InputChannelLink does not take any stream ingressors",
+        ))
     }
 
-    fn build_link(self) -> Link<Packet> {
-        if self.channel_receiver.is_none() {
-            panic!("Cannot build link! Missing channel");
-        } else {
-            (
-                vec![],
-                vec![Box::new(StreamFromChannel {
-                    channel_receiver: self.channel_receiver.unwrap(),
-                })],
-            )
+    fn try_build_link(self) -> Result<Link<Packet>, LinkBuildError> {
+        if self.channel_receivers.is_empty() {
+            return Err(LinkBuildError::Missing("channel"));
         }
+        Ok((
+            vec![],
+            vec![Box::new(StreamFromChannel {
+                channel_receivers: self.channel_receivers,
+                next: 0,
+                credits: self.credits,
+            })],
+        ))
     }
 }
 
 struct StreamFromChannel<Packet> {
-    channel_receiver: crossbeam::Receiver<Packet>,
+    /// The channels not yet disconnected.
+    channel_receivers: Vec<crossbeam::Receiver<Packet>>,
+    /// The channel to try first on the next poll, the one after the last a packet came from.
+    next: usize,
+    credits: Option<(crossbeam::Receiver<ControlMsg>, usize)>,
 }
 
 impl<Packet> Unpin for StreamFromChannel<Packet> {}
@@ -55,18 +106,68 @@ impl<Packet> Unpin for StreamFromChannel<Packet> {}
 impl<Packet> Stream for StreamFromChannel<Packet> {
     type Item = Packet;
 
-    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
-        match self.channel_receiver.try_recv() {
-            Ok(packet) => Poll::Ready(Some(packet)),
-            Err(crossbeam_channel::TryRecvError::Empty) => Poll::Pending,
-            Err(crossbeam_channel::TryRecvError::Disconnected) => Poll::Ready(None),
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if let Some((control, credits)) = &mut self.credits {
+            let mut granting = true;
+            loop {
+                match control.try_recv() {
+                    Ok(ControlMsg::Credit(granted)) => *credits += granted,
+                    Ok(_) => {}
+                    Err(crossbeam_channel::TryRecvError::Empty) => break,
+                    Err(crossbeam_channel::TryRecvError::Disconnected) => {
+                        granting = false;
+                        break;
+                    }
+                }
+            }
+            if *credits == 0 {
+                if !granting {
+                    return Poll::Ready(None);
+                }
+                // Credits are granted on a crossbeam channel too, so self-wake to check for more.
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+        }
+        let mut tried = 0;
+        while tried < self.channel_receivers.len() {
+            let index = self.next % self.channel_receivers.len();
+            match self.channel_receivers[index].try_recv() {
+                Ok(packet) => {
+                    self.next = index + 1;
+                    if let Some((_, credits)) = &mut self.credits {
+                        *credits -= 1;
+                    }
+                    return Poll::Ready(Some(packet));
+                }
+                Err(crossbeam_channel::TryRecvError::Empty) => {
+                    self.next = index + 1;
+                    tried += 1;
+                }
+                Err(crossbeam_channel::TryRecvError::Disconnected) => {
+                    // The channel after it moves into its place, and is tried next.
+                    self.channel_receivers.remove(index);
+                    self.next = index;
+                }
+            }
+        }
+        if self.channel_receivers.is_empty() {
+            return Poll::Ready(None);
         }
+        // As in OutputChannelLink, nothing on the other side of the channels can wake us, so
+        // self-wake to check again once the other tasks have had their turn.
+        cx.waker().wake_by_ref();
+        Poll::Pending
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::link::event::EventSink;
+    use crate::link::primitive::{OutputChannelLink, ProcessLink};
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::FnProcessor;
     use crate::utils::test::harness::{initialize_runtime, run_link};
     use crate::utils::test::packet_generators::immediate_stream;
 
@@ -106,4 +207,153 @@ mod tests {
         });
         assert_eq!(results[0], packets);
     }
+
+    #[test]
+    fn packets_sent_while_running() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, recv) = crossbeam_channel::unbounded();
+
+            let link = InputChannelLink::new().channel(recv).build_link();
+
+            let sent = packets.clone();
+            std::thread::spawn(move || {
+                for p in sent {
+                    std::thread::sleep(std::time::Duration::from_millis(2));
+                    send.send(p).unwrap();
+                }
+            });
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn takes_from_each_channel_in_turn() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (first, first_receiver) = crossbeam_channel::unbounded();
+            let (second, second_receiver) = crossbeam_channel::unbounded();
+            let (third, third_receiver) = crossbeam_channel::unbounded();
+            let link = InputChannelLink::new()
+                .channel(first_receiver)
+                .add_channel(second_receiver)
+                .add_channel(third_receiver)
+                .build_link();
+
+            // Two clones of a sender share their channel, and its order.
+            let first_clone = first.clone();
+            for p in 0..3 {
+                first.send(p).unwrap();
+                first_clone.send(p + 10).unwrap();
+            }
+            second.send(100).unwrap();
+            drop((first, first_clone, second, third));
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 100, 10, 1, 11, 2, 12]);
+    }
+
+    #[test]
+    fn channel_replaces_those_given_before() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (replaced, replaced_receiver) = crossbeam_channel::unbounded();
+            let (send, recv) = crossbeam_channel::unbounded();
+            let link = InputChannelLink::new()
+                .channel(replaced_receiver)
+                .channel(recv)
+                .build_link();
+
+            // The link let go of the channel it no longer reads.
+            assert!(replaced.send(0).is_err());
+            send.send(1).unwrap();
+            drop(send);
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![1]);
+    }
+
+    #[test]
+    fn reads_only_what_it_has_credits_for() {
+        let packets: Vec<i32> = (0..10).collect();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, recv) = crossbeam_channel::unbounded();
+            let (grant, control) = crossbeam_channel::unbounded();
+
+            let link = InputChannelLink::new()
+                .channel(recv.clone())
+                .credits(control, 3)
+                .build_link();
+
+            for p in packets.clone() {
+                send.send(p).unwrap();
+            }
+            drop(send);
+            let granter = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                let left = recv.len();
+                grant.send(ControlMsg::Credit(7)).unwrap();
+                left
+            });
+
+            let results = run_link(link).await;
+            (results, granter.join().unwrap())
+        });
+        assert_eq!(results.1, 7);
+        assert_eq!(results.0[0], packets);
+    }
+
+    #[test]
+    fn gets_back_credits_for_packets_dropped_on_the_way() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, recv) = crossbeam_channel::unbounded();
+            let (output, sent) = crossbeam_channel::unbounded();
+            let (grant, control) = crossbeam_channel::unbounded();
+            let (events, _) = crossbeam_channel::unbounded();
+
+            let (mut runnables, mut egressors) = InputChannelLink::new()
+                .channel(recv)
+                .credits(control, 2)
+                .build_link();
+            let (_, mut odd) = ProcessLink::new()
+                .ingressor(egressors.remove(0))
+                .processor(FnProcessor::new(|packet: i32| {
+                    if packet % 2 == 1 {
+                        Some(packet)
+                    } else {
+                        None
+                    }
+                }))
+                .event_sink(EventSink::new("odd", events).grant_credits(grant.clone()))
+                .build_link();
+            let (output_runnables, _) = OutputChannelLink::new()
+                .ingressor(odd.remove(0))
+                .channel(output)
+                .grant_credits(grant)
+                .build_link();
+            runnables.extend(output_runnables);
+
+            // Far more even packets are filtered out than the link started with credits for.
+            for p in 0..20 {
+                send.send(p).unwrap();
+            }
+            drop(send);
+
+            future::join_all(runnables).await;
+            sent
+        });
+        assert_eq!(
+            results.try_iter().collect::<Vec<i32>>(),
+            vec![1, 3, 5, 7, 9, 11, 13, 15, 17, 19]
+        );
+    }
 }
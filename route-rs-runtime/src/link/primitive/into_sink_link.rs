@@ -0,0 +1,220 @@
+use crate::link::event::EventSink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+
+/// Sends the packets of a pipeline into any sink, such as the sending half of a QUIC connection or
+/// WebSocket, or a file, without a bespoke link. The link waits on the sink when it isn't ready
+/// for more, flushes it whenever its input has nothing for it, and closes it once its input
+/// finishes.
+///
+/// Once the sink fails, the link drops the packets it is given, reporting them to its
+/// `EventSink`, if it has one.
+pub struct IntoSinkLink<Packet, K> {
+    in_stream: Option<PacketStream<Packet>>,
+    sink: Option<K>,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet, K> Default for IntoSinkLink<Packet, K> {
+    fn default() -> Self {
+        IntoSinkLink::new()
+    }
+}
+
+impl<Packet, K> IntoSinkLink<Packet, K> {
+    pub fn new() -> Self {
+        IntoSinkLink {
+            in_stream: None,
+            sink: None,
+            event_sink: None,
+        }
+    }
+
+    pub fn sink(self, sink: K) -> Self {
+        IntoSinkLink {
+            in_stream: self.in_stream,
+            sink: Some(sink),
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Reports packets dropped after the sink fails to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        IntoSinkLink {
+            in_stream: self.in_stream,
+            sink: self.sink,
+            event_sink: Some(event_sink),
+        }
+    }
+}
+
+impl<Packet, K> LinkBuilder<Packet, ()> for IntoSinkLink<Packet, K>
+where
+    Packet: Send + 'static,
+    K: Sink<Packet> + Send + 'static,
+{
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "IntoSinkLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "IntoSinkLink may only take 1 input stream",
+            ));
+        }
+        Ok(IntoSinkLink {
+            in_stream: Some(in_stream),
+            sink: self.sink,
+            event_sink: self.event_sink,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<()>, LinkBuildError> {
+        match (self.in_stream, self.sink) {
+            (None, _) => Err(LinkBuildError::Missing("input streams")),
+            (_, None) => Err(LinkBuildError::Missing("sink")),
+            (Some(in_stream), Some(sink)) => Ok((
+                vec![Box::new(StreamToSink {
+                    stream: in_stream,
+                    sink: Box::pin(sink),
+                    failed: false,
+                    finished: false,
+                    event_sink: self.event_sink,
+                })],
+                vec![],
+            )),
+        }
+    }
+}
+
+struct StreamToSink<Packet, K> {
+    stream: PacketStream<Packet>,
+    sink: Pin<Box<K>>,
+    failed: bool,
+    finished: bool,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet, K> Unpin for StreamToSink<Packet, K> {}
+
+impl<Packet, K: Sink<Packet>> StreamToSink<Packet, K> {
+    fn fail(&mut self) {
+        self.failed = true;
+        if let Some(event_sink) = &self.event_sink {
+            event_sink.dropped("sink has failed");
+        }
+    }
+}
+
+impl<Packet, K: Sink<Packet>> Future for StreamToSink<Packet, K> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        while !self.finished {
+            if !self.failed && ready!(self.sink.as_mut().poll_ready(cx)).is_err() {
+                self.failed = true;
+            }
+
+            match Pin::new(&mut self.stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    if self.failed || self.sink.as_mut().start_send(packet).is_err() {
+                        self.fail();
+                    }
+                }
+                Poll::Ready(None) => self.finished = true,
+                Poll::Pending => {
+                    if !self.failed {
+                        if let Poll::Ready(Err(_)) = self.sink.as_mut().poll_flush(cx) {
+                            self.failed = true;
+                        }
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+
+        if !self.failed {
+            // Closing flushes whatever the sink still holds, failing only if the sink has.
+            let _ = ready!(self.sink.as_mut().poll_close(cx));
+        }
+        Poll::Ready(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::event::LinkEvent;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use crossbeam::crossbeam_channel;
+    use futures::channel::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_sink() {
+        IntoSinkLink::<i32, mpsc::Sender<i32>>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn sends_packets_into_any_sink() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, recv) = mpsc::channel(1);
+            let receiver = tokio::spawn(recv.collect::<Vec<i32>>());
+
+            let link = IntoSinkLink::new()
+                .ingressor(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(1),
+                    packets.clone().into_iter(),
+                )))
+                .sink(send)
+                .build_link();
+
+            run_link(link).await;
+            receiver.await.unwrap()
+        });
+        assert_eq!(results, packets);
+    }
+
+    #[test]
+    fn drops_packets_once_sink_fails() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let (send, recv) = mpsc::channel::<i32>(1);
+            drop(recv);
+
+            let link = IntoSinkLink::new()
+                .ingressor(immediate_stream(0..3))
+                .sink(send)
+                .event_sink(EventSink::new("sink", sender))
+                .build_link();
+
+            run_link(link).await;
+        });
+        let events: Vec<LinkEvent> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0].to_string(),
+            "sink: dropped packet, sink has failed"
+        );
+    }
+}
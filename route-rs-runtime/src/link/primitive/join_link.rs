@@ -1,5 +1,8 @@
+use crate::link::event::EventSink;
 use crate::link::utils::task_park::*;
-use crate::link::{Link, LinkBuilder, PacketStream, TokioRunnable};
+use crate::link::utils::watchdog::Watchdog;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, TokioRunnable};
+use crate::metrics;
 use crossbeam::atomic::AtomicCell;
 use crossbeam::crossbeam_channel;
 use crossbeam::crossbeam_channel::{Receiver, Sender};
@@ -12,6 +15,9 @@ use std::sync::Arc;
 pub struct JoinLink<Packet: Send + Clone> {
     in_streams: Option<Vec<PacketStream<Packet>>>,
     queue_capacity: usize,
+    batch_size: usize,
+    work_budget: usize,
+    event_sink: Option<EventSink>,
 }
 
 impl<Packet: Send + Clone> JoinLink<Packet> {
@@ -19,6 +25,9 @@ impl<Packet: Send + Clone> JoinLink<Packet> {
         JoinLink {
             in_streams: None,
             queue_capacity: 10,
+            batch_size: 8,
+            work_budget: 128,
+            event_sink: None,
         }
     }
 
@@ -32,76 +41,139 @@ impl<Packet: Send + Clone> JoinLink<Packet> {
         JoinLink {
             in_streams: self.in_streams,
             queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes batch_size, the number of packets an ingressor passes, or the
+    /// egressor takes off the queue of an ingressor, before waking the other side,
+    /// default value is 8. Either side also wakes the other before it goes to sleep.
+    pub fn batch_size(self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch_size must be > 0");
+
+        JoinLink {
+            in_streams: self.in_streams,
+            queue_capacity: self.queue_capacity,
+            batch_size,
+            work_budget: self.work_budget,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes work_budget, the number of packets the ingressor takes from its
+    /// input in one poll before yielding to other tasks, default value is 128.
+    pub fn work_budget(self, work_budget: usize) -> Self {
+        assert!(work_budget > 0, "work_budget must be > 0");
+
+        JoinLink {
+            in_streams: self.in_streams,
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Reports queues filling up to `event_sink`. With a sink, packets taken after the egressor
+    /// has been dropped are dropped and reported rather than panicking.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        JoinLink {
+            in_streams: self.in_streams,
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            event_sink: Some(event_sink),
         }
     }
 }
 
 impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for JoinLink<Packet> {
-    fn ingressors(self, in_streams: Vec<PacketStream<Packet>>) -> Self {
-        assert!(
-            !in_streams.is_empty(),
-            format!(
-                "number of in_streams: {}, must be greater than 0",
-                in_streams.len()
-            )
-        );
-
+    fn try_ingressors(self, in_streams: Vec<PacketStream<Packet>>) -> Result<Self, LinkBuildError> {
+        if in_streams.is_empty() {
+            return Err(LinkBuildError::Ingressors(
+                "JoinLink must take at least 1 input stream",
+            ));
+        }
         if self.in_streams.is_some() {
-            panic!("Join link already has input streams")
+            return Err(LinkBuildError::Ingressors(
+                "JoinLink already has input streams",
+            ));
         }
 
-        JoinLink {
+        Ok(JoinLink {
             in_streams: Some(in_streams),
             queue_capacity: self.queue_capacity,
-        }
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            event_sink: self.event_sink,
+        })
     }
 
     /// Appends the ingressor to the ingressors of the link.
-    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
-        match self.in_streams {
-            None => {
-                let in_streams = Some(vec![in_stream]);
-                JoinLink {
-                    in_streams,
-                    queue_capacity: self.queue_capacity,
-                }
-            }
-            Some(mut in_streams) => {
-                in_streams.push(in_stream);
-                JoinLink {
-                    in_streams: Some(in_streams),
-                    queue_capacity: self.queue_capacity,
-                }
-            }
-        }
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        let mut in_streams = self.in_streams.unwrap_or_default();
+        in_streams.push(in_stream);
+        Ok(JoinLink {
+            in_streams: Some(in_streams),
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            event_sink: self.event_sink,
+        })
     }
 
-    fn build_link(self) -> Link<Packet> {
-        if self.in_streams.is_none() {
-            panic!("Cannot build link! Missing input streams");
-        } else {
-            let input_streams = self.in_streams.unwrap();
-            let number_ingressors = input_streams.len();
-            let mut ingressors: Vec<TokioRunnable> = Vec::new();
-            let mut from_ingressors: Vec<Receiver<Option<Packet>>> = Vec::new();
-            let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
-
-            for input_stream in input_streams {
-                let (to_egressor, from_ingressor) =
-                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
-                let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
-
-                let ingressor =
-                    JoinIngressor::new(input_stream, to_egressor, Arc::clone(&task_park));
-                ingressors.push(Box::new(ingressor));
-                from_ingressors.push(from_ingressor);
-                task_parks.push(task_park);
+    fn try_build_link(self) -> Result<Link<Packet>, LinkBuildError> {
+        let input_streams = match self.in_streams {
+            Some(in_streams) => in_streams,
+            None => return Err(LinkBuildError::Missing("input streams")),
+        };
+        let number_ingressors = input_streams.len();
+        let mut ingressors: Vec<TokioRunnable> = Vec::new();
+        let mut from_ingressors: Vec<Receiver<Option<Packet>>> = Vec::new();
+        let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+        let watchdog = Watchdog::global();
+        let link_id = watchdog.next_id();
+
+        for (port, input_stream) in input_streams.into_iter().enumerate() {
+            let (to_egressor, from_ingressor) =
+                crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+            let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+            let queue = to_egressor.clone();
+            metrics::register_queue(self.queue_capacity, move || queue.len());
+            if cfg!(debug_assertions) {
+                let queue = to_egressor.clone();
+                watchdog.register(
+                    format!("JoinLink#{} ingressor {}", link_id, port),
+                    &task_park,
+                    self.queue_capacity,
+                    move || queue.len(),
+                );
             }
 
-            let egressor = JoinEgressor::new(from_ingressors, task_parks, number_ingressors);
-
-            (ingressors, vec![Box::new(egressor)])
+            let ingressor = JoinIngressor::new(
+                input_stream,
+                to_egressor,
+                Arc::clone(&task_park),
+                self.batch_size,
+                self.work_budget,
+                port,
+                self.event_sink.clone(),
+            );
+            ingressors.push(Box::new(ingressor));
+            from_ingressors.push(from_ingressor);
+            task_parks.push(task_park);
         }
+
+        let egressor = JoinEgressor::new(
+            from_ingressors,
+            task_parks,
+            number_ingressors,
+            self.batch_size,
+        );
+
+        Ok((ingressors, vec![Box::new(egressor)]))
     }
 }
 
@@ -109,20 +181,37 @@ pub struct JoinIngressor<Packet: Sized> {
     input_stream: PacketStream<Packet>,
     to_egressor: Sender<Option<Packet>>,
     task_park: Arc<AtomicCell<TaskParkState>>,
+    batch_size: usize,
+    unwoken: usize,
+    work_budget: usize,
+    port: usize,
+    event_sink: Option<EventSink>,
+    /// Whether the to_egressor queue has been full since we last pushed onto it.
+    full: bool,
 }
 
 impl<Packet: Sized> Unpin for JoinIngressor<Packet> {}
 
 impl<Packet: Sized> JoinIngressor<Packet> {
-    fn new(
+    pub(crate) fn new(
         input_stream: PacketStream<Packet>,
         to_egressor: Sender<Option<Packet>>,
         task_park: Arc<AtomicCell<TaskParkState>>,
+        batch_size: usize,
+        work_budget: usize,
+        port: usize,
+        event_sink: Option<EventSink>,
     ) -> Self {
         JoinIngressor {
             input_stream,
             to_egressor,
             task_park,
+            batch_size,
+            unwoken: 0,
+            work_budget,
+            port,
+            event_sink,
+            full: false,
         }
     }
 }
@@ -134,45 +223,81 @@ impl<Packet: Sized> Future for JoinIngressor<Packet> {
     ///
     /// Note that this function works a bit different, it continues to process
     /// packets off it's input queue until it reaches a point where it can not
-    /// make forward progress. There are three cases:
+    /// make forward progress. There are four cases:
     /// ###
     /// #1 The to_egressor queue is full, we wake the egressor that we need
-    /// awaking when there is work to do, and go to sleep.
+    /// awaking when there is work to do, and go to sleep. If the queue has just become full,
+    /// that is reported to our event sink, if we have one.
     ///
-    /// #2 The input_stream returns a NotReady, we sleep, with the assumption
+    /// #2 The input_stream returns a NotReady, we wake the egressor if we have
+    /// passed it packets since we last did, and sleep, with the assumption
     /// that whomever produced the NotReady will awaken the task in the Future.
     ///
     /// #3 We get a Ready(None), in which case we push a None onto the to_egressor
     /// queue and then return Ready(()), which means we enter tear-down, since there
     /// is no futher work to complete.
+    ///
+    /// #4 We have taken `work_budget` packets from our input in this poll, so we
+    /// wake ourselves and sleep, so that other tasks on this thread get a turn.
     /// ###
     /// By Sleep, we mean we return a NotReady to the runtime which will sleep the task.
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         let ingressor = Pin::into_inner(self);
-        loop {
+        for _ in 0..ingressor.work_budget {
             if ingressor.to_egressor.is_full() {
+                if let (false, Some(event_sink)) = (ingressor.full, &ingressor.event_sink) {
+                    event_sink.queue_overflow(ingressor.port);
+                }
+                ingressor.full = true;
+                ingressor.unwoken = 0;
                 park_and_wake(&ingressor.task_park, cx.waker().clone()); //TODO: Change task park to cx based
                 return Poll::Pending;
             }
             let input_packet_option: Option<Packet> =
-                ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx));
+                match Pin::new(&mut ingressor.input_stream).poll_next(cx) {
+                    Poll::Ready(input_packet_option) => input_packet_option,
+                    Poll::Pending => {
+                        flush_and_wake(&ingressor.task_park, &mut ingressor.unwoken);
+                        return Poll::Pending;
+                    }
+                };
 
             match input_packet_option {
                 None => {
-                    ingressor.to_egressor.try_send(None).expect(
-                        "JoinIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
-                    );
+                    if ingressor.to_egressor.try_send(None).is_err()
+                        && ingressor.event_sink.is_none()
+                    {
+                        panic!(
+                            "JoinIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail"
+                        );
+                    }
                     die_and_wake(&ingressor.task_park);
                     return Poll::Ready(());
                 }
                 Some(packet) => {
-                    ingressor.to_egressor.try_send(Some(packet)).expect(
-                        "JoinIngressor::Poll:Ready(Some(Val)) try_send to_egressor shouldn't fail",
+                    if ingressor.to_egressor.try_send(Some(packet)).is_err() {
+                        match &ingressor.event_sink {
+                            Some(event_sink) => {
+                                event_sink.dropped("egressor has been dropped");
+                                continue;
+                            }
+                            None => panic!(
+                                "JoinIngressor::Poll:Ready(Some(Val)) try_send to_egressor shouldn't fail"
+                            ),
+                        }
+                    }
+                    ingressor.full = false;
+                    batch_and_wake(
+                        &ingressor.task_park,
+                        &mut ingressor.unwoken,
+                        ingressor.batch_size,
                     );
-                    unpark_and_wake(&ingressor.task_park);
                 }
             }
         }
+        flush_and_wake(&ingressor.task_park, &mut ingressor.unwoken);
+        cx.waker().wake_by_ref();
+        Poll::Pending
     }
 }
 
@@ -182,6 +307,8 @@ pub struct JoinEgressor<Packet: Sized> {
     task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
     ingressors_alive: usize,
     next_pull_ingressor: usize,
+    batch_size: usize,
+    unwoken: Vec<usize>,
 }
 
 impl<Packet: Sized> JoinEgressor<Packet> {
@@ -189,13 +316,17 @@ impl<Packet: Sized> JoinEgressor<Packet> {
         from_ingressors: Vec<Receiver<Option<Packet>>>,
         task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
         ingressors_alive: usize,
+        batch_size: usize,
     ) -> Self {
         let next_pull_ingressor = 0;
+        let unwoken = vec![0; task_parks.len()];
         JoinEgressor {
             from_ingressors,
             task_parks,
             ingressors_alive,
             next_pull_ingressor,
+            batch_size,
+            unwoken,
         }
     }
 }
@@ -224,7 +355,11 @@ impl<Packet: Sized> Stream for JoinEgressor<Packet> {
         for (port, from_ingressor) in rotated_iter {
             match from_ingressor.try_recv() {
                 Ok(Some(packet)) => {
-                    unpark_and_wake(&egressor.task_parks[port]);
+                    batch_and_wake(
+                        &egressor.task_parks[port],
+                        &mut egressor.unwoken[port],
+                        egressor.batch_size,
+                    );
                     egressor.next_pull_ingressor = port + 1;
                     return Poll::Ready(Some(packet));
                 }
@@ -247,6 +382,8 @@ impl<Packet: Sized> Stream for JoinEgressor<Packet> {
         // We could not get a packet from any of our ingressors, this means we will park our task in a
         // common location, and then hand out Arcs to all the ingressors to the common location. The first
         // one to access the egressor task will awaken us, so we can continue providing packets.
+        // Parking also wakes every ingressor, so none is left waiting on the rest of a batch.
+        egressor.unwoken.iter_mut().for_each(|unwoken| *unwoken = 0);
         let mut parked_egressor_task = false;
         let egressor_task = Arc::new(AtomicCell::new(Some(cx.waker().clone())));
         for task_park in egressor.task_parks.iter() {
@@ -266,6 +403,7 @@ impl<Packet: Sized> Stream for JoinEgressor<Packet> {
 #[allow(dead_code)]
 mod tests {
     use super::*;
+    use crate::link::event::LinkEvent;
     use crate::link::LinkBuilder;
     use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
     use core::time;
@@ -288,6 +426,12 @@ mod tests {
             .build_link();
     }
 
+    #[test]
+    fn try_ingressors_rejects_empty_input_streams() {
+        let link = JoinLink::<i32>::new().try_ingressors(vec![]);
+        assert!(matches!(link, Err(LinkBuildError::Ingressors(_))));
+    }
+
     #[test]
     fn builder_methods_work_in_any_order() {
         let packets: Vec<i32> = vec![];
@@ -413,6 +557,60 @@ mod tests {
         assert_eq!(results[0].len(), packets.len() * 2);
     }
 
+    #[test]
+    fn batch_larger_than_queue() {
+        let mut runtime = initialize_runtime();
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9, 11];
+        let results = runtime.block_on(async {
+            let input_streams: Vec<PacketStream<usize>> = vec![
+                immediate_stream(packets.clone()),
+                Box::new(PacketIntervalGenerator::new(
+                    time::Duration::from_millis(1),
+                    packets.clone().into_iter(),
+                )),
+            ];
+
+            let link = JoinLink::new()
+                .ingressors(input_streams)
+                .queue_capacity(2)
+                .batch_size(64)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), packets.len() * 2);
+    }
+
+    #[test]
+    fn reports_overflowing_ingressor_queues() {
+        let (sender, events) = crossbeam_channel::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let input_streams: Vec<PacketStream<usize>> =
+                vec![immediate_stream(0..20), immediate_stream(100..120)];
+
+            let link = JoinLink::new()
+                .ingressors(input_streams)
+                .queue_capacity(1)
+                .event_sink(EventSink::new("join", sender))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 40);
+
+        let queues: Vec<usize> = events
+            .try_iter()
+            .map(|event| match event {
+                LinkEvent::QueueOverflow { queue, .. } => queue,
+                event => panic!("unexpected event {}", event),
+            })
+            .collect();
+        assert!(!queues.is_empty());
+        assert!(queues.iter().all(|queue| *queue < 2));
+    }
+
     #[test]
     fn empty_stream() {
         let mut runtime = initialize_runtime();
@@ -425,7 +623,7 @@ mod tests {
 
             run_link(link).await
         });
-        assert_eq!(results[0], []);
+        assert_eq!(results[0], Vec::<usize>::new());
     }
 
     #[test]
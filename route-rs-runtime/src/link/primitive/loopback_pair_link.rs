@@ -0,0 +1,368 @@
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crossbeam::crossbeam_channel::{self, Receiver, Sender, TryRecvError};
+use futures::prelude::*;
+use futures::task::{AtomicWaker, Context, Poll};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::time::{delay_until, Delay, Duration, Instant};
+
+/// Packets a wire takes from its transmitters in one poll before letting other tasks run.
+const WORK_BUDGET: usize = 128;
+
+/// Two interface-like ends joined by a wire: what one end transmits, the other receives, after
+/// `delay` and with a chance of `loss`, so routers can be joined into a topology and tested
+/// together in one process, without TUN devices, as VRRP peers or the two ends of a tunnel are.
+///
+/// Egressor `0` is what end `0` receives, which end `1` transmitted, and egressor `1` the other
+/// way. What each end transmits can be given as the ingressors of the link, one for each end, or
+/// attached later through the `LoopbackEndpoint` of the end. The latter joins routers whose output
+/// depends on their input: build the pair first, build each router on the egressor of its end, and
+/// attach each router's output to its endpoint. Streams attached to one end are interleaved as
+/// their packets arrive, as on a shared segment.
+///
+/// Packets are delayed alike, so a wire never reorders them. Losses are drawn from `seed`, so a
+/// test loses the same packets each run. An egressor ends once everything transmitted towards it
+/// has arrived, and no endpoint of the other end is held to transmit more.
+pub struct LoopbackPairLink<Packet> {
+    in_streams: Vec<PacketStream<Packet>>,
+    delay: Duration,
+    loss: f64,
+    seed: u64,
+    /// What each end transmits on, and the task of the egressor at the other end.
+    to_wires: Vec<(Sender<PacketStream<Packet>>, Arc<AtomicWaker>)>,
+    /// What each egressor takes transmitters from.
+    from_endpoints: Vec<Receiver<PacketStream<Packet>>>,
+}
+
+impl<Packet> Default for LoopbackPairLink<Packet> {
+    fn default() -> Self {
+        LoopbackPairLink::new()
+    }
+}
+
+impl<Packet> LoopbackPairLink<Packet> {
+    pub fn new() -> Self {
+        let (zero_to_one, one_from_zero) = crossbeam_channel::unbounded();
+        let (one_to_zero, zero_from_one) = crossbeam_channel::unbounded();
+        LoopbackPairLink {
+            in_streams: vec![],
+            delay: Duration::from_secs(0),
+            loss: 0.0,
+            seed: 0,
+            to_wires: vec![
+                (zero_to_one, Arc::new(AtomicWaker::new())),
+                (one_to_zero, Arc::new(AtomicWaker::new())),
+            ],
+            from_endpoints: vec![zero_from_one, one_from_zero],
+        }
+    }
+
+    /// Changes delay, the time each packet takes to cross, default value is none.
+    pub fn delay(self, delay: Duration) -> Self {
+        LoopbackPairLink {
+            in_streams: self.in_streams,
+            delay,
+            loss: self.loss,
+            seed: self.seed,
+            to_wires: self.to_wires,
+            from_endpoints: self.from_endpoints,
+        }
+    }
+
+    /// Changes loss, the chance each packet is lost crossing, default value is 0.
+    pub fn loss(self, loss: f64) -> Self {
+        assert!((0.0..=1.0).contains(&loss), "loss must be from 0 to 1");
+        LoopbackPairLink {
+            in_streams: self.in_streams,
+            delay: self.delay,
+            loss,
+            seed: self.seed,
+            to_wires: self.to_wires,
+            from_endpoints: self.from_endpoints,
+        }
+    }
+
+    /// Changes seed, which losses are drawn from, default value is 0.
+    pub fn seed(self, seed: u64) -> Self {
+        LoopbackPairLink {
+            in_streams: self.in_streams,
+            delay: self.delay,
+            loss: self.loss,
+            seed,
+            to_wires: self.to_wires,
+            from_endpoints: self.from_endpoints,
+        }
+    }
+
+    /// The endpoint of end `0` or `1`, to attach what the end transmits to, before or after the
+    /// link is built.
+    pub fn endpoint(&self, end: usize) -> LoopbackEndpoint<Packet> {
+        assert!(end < 2, "end must be 0 or 1");
+        let (to_wire, wire_task) = &self.to_wires[end];
+        LoopbackEndpoint {
+            to_wire: to_wire.clone(),
+            wire_task: Arc::clone(wire_task),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for LoopbackPairLink<Packet> {
+    fn try_ingressors(self, in_streams: Vec<PacketStream<Packet>>) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 2 {
+            return Err(LinkBuildError::Ingressors(
+                "LoopbackPairLink takes two ingressors, one for each end",
+            ));
+        }
+        Ok(LoopbackPairLink {
+            in_streams,
+            delay: self.delay,
+            loss: self.loss,
+            seed: self.seed,
+            to_wires: self.to_wires,
+            from_endpoints: self.from_endpoints,
+        })
+    }
+
+    /// Gives the ingressor to end `0`, or to end `1` if end `0` has one.
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_streams.len() == 2 {
+            return Err(LinkBuildError::Ingressors(
+                "LoopbackPairLink takes at most two ingressors, one for each end",
+            ));
+        }
+        let mut in_streams = self.in_streams;
+        in_streams.push(in_stream);
+        Ok(LoopbackPairLink {
+            in_streams,
+            delay: self.delay,
+            loss: self.loss,
+            seed: self.seed,
+            to_wires: self.to_wires,
+            from_endpoints: self.from_endpoints,
+        })
+    }
+
+    /// Builds the link, whose ends may have nothing to transmit until it is attached.
+    fn try_build_link(self) -> Result<Link<Packet>, LinkBuildError> {
+        let mut transmitters: Vec<Vec<PacketStream<Packet>>> = vec![vec![], vec![]];
+        for (end, in_stream) in self.in_streams.into_iter().enumerate() {
+            transmitters[1 - end].push(in_stream);
+        }
+        let wire_tasks: Vec<Arc<AtomicWaker>> = self
+            .to_wires
+            .iter()
+            .rev()
+            .map(|(_, wire_task)| Arc::clone(wire_task))
+            .collect();
+        let (delay, loss, seed) = (self.delay, self.loss, self.seed);
+        let egressors = transmitters
+            .into_iter()
+            .zip(self.from_endpoints)
+            .zip(wire_tasks)
+            .enumerate()
+            .map(|(end, ((transmitters, from_endpoints), wire_task))| {
+                Box::new(WireEgressor {
+                    transmitters,
+                    from_endpoints,
+                    endpoints_dropped: false,
+                    wire_task,
+                    in_flight: VecDeque::new(),
+                    delay,
+                    loss,
+                    // Each direction loses packets of its own.
+                    rng: StdRng::seed_from_u64(seed.wrapping_add(end as u64)),
+                    timer: None,
+                }) as PacketStream<Packet>
+            })
+            .collect();
+        Ok((vec![], egressors))
+    }
+}
+
+/// One end of a `LoopbackPairLink`, for attaching what the end transmits, and keeping the egressor
+/// of the other end open for more while it is held.
+pub struct LoopbackEndpoint<Packet> {
+    to_wire: Sender<PacketStream<Packet>>,
+    wire_task: Arc<AtomicWaker>,
+}
+
+impl<Packet> Clone for LoopbackEndpoint<Packet> {
+    fn clone(&self) -> Self {
+        LoopbackEndpoint {
+            to_wire: self.to_wire.clone(),
+            wire_task: Arc::clone(&self.wire_task),
+        }
+    }
+}
+
+impl<Packet> LoopbackEndpoint<Packet> {
+    /// Transmits the packets of `out_stream` to the other end, alongside any already attached.
+    pub fn transmit(&self, out_stream: PacketStream<Packet>) {
+        // Only fails once the link has been dropped, with nothing left to receive.
+        let _ = self.to_wire.send(out_stream);
+        self.wire_task.wake();
+    }
+}
+
+impl<Packet> Drop for LoopbackEndpoint<Packet> {
+    fn drop(&mut self) {
+        // So the egressor checks whether this was the last endpoint.
+        self.wire_task.wake();
+    }
+}
+
+/// What one end of the pair receives: the packets the other end transmitted, once they have
+/// crossed.
+struct WireEgressor<Packet> {
+    transmitters: Vec<PacketStream<Packet>>,
+    from_endpoints: Receiver<PacketStream<Packet>>,
+    endpoints_dropped: bool,
+    wire_task: Arc<AtomicWaker>,
+    /// Packets crossing, with when each arrives.
+    in_flight: VecDeque<(Instant, Packet)>,
+    delay: Duration,
+    loss: f64,
+    rng: StdRng,
+    timer: Option<Delay>,
+}
+
+impl<Packet> Unpin for WireEgressor<Packet> {}
+
+impl<Packet> Stream for WireEgressor<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let wire = &mut *self;
+        wire.wire_task.register(cx.waker());
+        loop {
+            match wire.from_endpoints.try_recv() {
+                Ok(transmitter) => wire.transmitters.push(transmitter),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    wire.endpoints_dropped = true;
+                    break;
+                }
+            }
+        }
+
+        // Everything transmitted by now is on the wire, whether or not this end is ready for it.
+        let mut budget = WORK_BUDGET;
+        let mut index = 0;
+        while index < wire.transmitters.len() {
+            if budget == 0 {
+                cx.waker().wake_by_ref();
+                break;
+            }
+            match Pin::new(&mut wire.transmitters[index]).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    budget -= 1;
+                    if wire.loss > 0.0 && wire.rng.gen::<f64>() < wire.loss {
+                        continue;
+                    }
+                    wire.in_flight
+                        .push_back((Instant::now() + wire.delay, packet));
+                }
+                Poll::Ready(None) => drop(wire.transmitters.remove(index)),
+                Poll::Pending => index += 1,
+            }
+        }
+
+        while let Some((arrives, _)) = wire.in_flight.front() {
+            let arrives = *arrives;
+            if arrives <= Instant::now() {
+                wire.timer = None;
+                return Poll::Ready(wire.in_flight.pop_front().map(|(_, packet)| packet));
+            }
+            let timer = wire.timer.get_or_insert_with(|| delay_until(arrives));
+            if timer.deadline() != arrives {
+                timer.reset(arrives);
+            }
+            ready!(Pin::new(timer).poll(cx));
+        }
+        if wire.transmitters.is_empty() && wire.endpoints_dropped {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::FnProcessor;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_with_one_of_two_ingressors() {
+        LoopbackPairLink::new()
+            .ingressors(vec![immediate_stream(vec![1])])
+            .build_link();
+    }
+
+    #[test]
+    fn each_end_receives_what_the_other_transmits() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = LoopbackPairLink::new()
+                .ingressors(vec![immediate_stream(0..3), immediate_stream(10..13)])
+                .build_link();
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![10, 11, 12]);
+        assert_eq!(results[1], vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn joins_routers_into_a_topology() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let wire = LoopbackPairLink::new().delay(Duration::from_millis(5));
+            let (near, far) = (wire.endpoint(0), wire.endpoint(1));
+            let (_, mut receive) = wire.build_link();
+
+            // The far router answers each packet it receives with one of its own.
+            let (runnables, mut answers) = ProcessLink::new()
+                .ingressor(receive.remove(1))
+                .processor(FnProcessor::new(|packet: i32| Some(packet + 100)))
+                .build_link();
+            assert!(runnables.is_empty());
+            far.transmit(answers.remove(0));
+            drop(far);
+
+            near.transmit(immediate_stream(vec![1, 2, 3]));
+            drop(near);
+            let started = Instant::now();
+            let received: Vec<i32> = receive.remove(0).collect().await;
+            (received, started.elapsed())
+        });
+        assert_eq!(results.0, vec![101, 102, 103]);
+        // Once there, and once back.
+        assert!(results.1 >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn loses_the_same_packets_each_run() {
+        let run = || {
+            let mut runtime = initialize_runtime();
+            runtime.block_on(async {
+                let link = LoopbackPairLink::new()
+                    .loss(0.5)
+                    .seed(7)
+                    .ingressors(vec![immediate_stream(0..100), immediate_stream(0..100)])
+                    .build_link();
+                run_link(link).await
+            })
+        };
+        let first = run();
+        assert!(first[0].len() > 20 && first[0].len() < 80);
+        assert_ne!(first[0], first[1]);
+        assert_eq!(run(), first);
+    }
+}
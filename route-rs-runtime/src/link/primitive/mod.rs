@@ -23,14 +23,76 @@ pub use self::classify_link::*;
 mod join_link;
 pub use self::join_link::*;
 
+/// Fairly combines all inputs into a single output, with inputs attached and detached as it runs,
+/// asynchronous.
+mod dynamic_join_link;
+pub use self::dynamic_join_link::*;
+
 /// Copies all input to each of its outputs, asynchronous.
 mod fork_link;
 pub use self::fork_link::*;
 
-/// Takes a channel for input and converts it to a stream.
+/// Shares all input with each of its outputs, with outputs subscribed and unsubscribed as it runs,
+/// asynchronous.
+mod dynamic_fork_link;
+pub use self::dynamic_fork_link::*;
+
+/// Pairs the packets of two inputs into tuples, in the order they arrive, synchronous.
+mod zip_link;
+pub use self::zip_link::*;
+
+/// Queues packets by flow, and takes from each flow in turn, so no flow starves another,
+/// synchronous.
+mod flow_scheduler_link;
+pub use self::flow_scheduler_link::*;
+
+/// Drops copies of packets passed within a window of time, synchronous.
+mod dedup_link;
+pub use self::dedup_link::*;
+
+/// Holds packets until their next hop is resolved, synchronous.
+mod pending_resolution_link;
+pub use self::pending_resolution_link::*;
+
+/// Takes a channel for input and converts it to a stream, optionally reading only the packets it
+/// is granted credits for.
 mod input_channel_link;
 pub use self::input_channel_link::*;
 
-/// Takes a stream and converts it to a channel for output.
+/// Takes a stream and converts it to a channel for output, optionally granting credits upstream
+/// for the packets it sends.
 mod output_channel_link;
 pub use self::output_channel_link::*;
+
+/// Takes a `tokio::sync::mpsc` channel for input and converts it to a stream, for async code
+/// feeding the router.
+mod tokio_input_channel_link;
+pub use self::tokio_input_channel_link::*;
+
+/// Takes a stream and converts it to a `tokio::sync::mpsc` channel for output, waiting for room in
+/// the channel as async code drains it.
+mod tokio_output_channel_link;
+pub use self::tokio_output_channel_link::*;
+
+/// Takes any stream as input, for sources with no link of their own.
+mod from_stream_link;
+pub use self::from_stream_link::*;
+
+/// Joins two ends with a wire, what one transmits the other receiving, for testing routers
+/// together in one process, asynchronous.
+mod loopback_pair_link;
+pub use self::loopback_pair_link::*;
+
+/// Sends its input into any sink, for destinations with no link of their own.
+mod into_sink_link;
+pub use self::into_sink_link::*;
+
+/// Passes packets through with a `Tick` injected every period, for processors that keep tables
+/// to age, synchronous.
+mod tick_source_link;
+pub use self::tick_source_link::*;
+
+/// Keeps the last packets to pass through it in a ring, and writes them to a pcap file when
+/// triggered, synchronous.
+mod capture_ring_link;
+pub use self::capture_ring_link::*;
@@ -1,12 +1,19 @@
-use crate::link::{Link, LinkBuilder, PacketStream};
+use crate::link::message::ControlMsg;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
 use futures::prelude::*;
 use futures::task::{Context, Poll};
 use std::pin::Pin;
 
+/// Sends packets into a channel, such as the transmit queue of a NIC, waiting while it is full.
+///
+/// With `grant_credits`, the link grants a `ControlMsg::Credit` on the control channel for every
+/// packet it sends into its channel, to the `InputChannelLink` reading the packets it is sent, so
+/// that link reads no faster than this one can send.
 #[derive(Default)]
 pub struct OutputChannelLink<Packet> {
     in_stream: Option<PacketStream<Packet>>,
     channel_sender: Option<crossbeam::Sender<Packet>>,
+    control: Option<crossbeam::Sender<ControlMsg>>,
 }
 
 impl<Packet> OutputChannelLink<Packet> {
@@ -14,6 +21,7 @@ impl<Packet> OutputChannelLink<Packet> {
         OutputChannelLink {
             in_stream: None,
             channel_sender: None,
+            control: None,
         }
     }
 
@@ -21,49 +29,58 @@ impl<Packet> OutputChannelLink<Packet> {
         OutputChannelLink {
             in_stream: self.in_stream,
             channel_sender: Some(channel_sender),
+            control: self.control,
         }
     }
-}
-
-impl<Packet: Send + 'static> LinkBuilder<Packet, ()> for OutputChannelLink<Packet> {
-    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
-        assert_eq!(
-            in_streams.len(),
-            1,
-            "OutputChannelLink may only take 1 input stream"
-        );
-
-        if self.in_stream.is_some() {
-            panic!("OutputChannelLink may only take 1 input stream");
-        }
 
+    /// Grants credits on `control` for the packets sent.
+    pub fn grant_credits(self, control: crossbeam::Sender<ControlMsg>) -> Self {
         OutputChannelLink {
-            in_stream: Some(in_streams.remove(0)),
+            in_stream: self.in_stream,
             channel_sender: self.channel_sender,
+            control: Some(control),
         }
     }
+}
 
-    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+impl<Packet: Send + 'static> LinkBuilder<Packet, ()> for OutputChannelLink<Packet> {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "OutputChannelLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
         if self.in_stream.is_some() {
-            panic!("OutputChannelLink may only take 1 input stream");
+            return Err(LinkBuildError::Ingressors(
+                "OutputChannelLink may only take 1 input stream",
+            ));
         }
-        OutputChannelLink {
+        Ok(OutputChannelLink {
             in_stream: Some(in_stream),
             channel_sender: self.channel_sender,
-        }
+            control: self.control,
+        })
     }
 
-    fn build_link(self) -> Link<()> {
+    fn try_build_link(self) -> Result<Link<()>, LinkBuildError> {
         match (self.in_stream, self.channel_sender) {
-            (None, _) => panic!("Cannot build link! Missing input streams"),
-            (_, None) => panic!("Cannot build link! Missing channel"),
-            (Some(in_stream), Some(sender)) => (
+            (None, _) => Err(LinkBuildError::Missing("input streams")),
+            (_, None) => Err(LinkBuildError::Missing("channel")),
+            (Some(in_stream), Some(sender)) => Ok((
                 vec![Box::new(StreamToChannel {
                     stream: in_stream,
                     channel_sender: sender,
+                    control: self.control,
                 })],
                 vec![],
-            ),
+            )),
         }
     }
 }
@@ -71,26 +88,50 @@ impl<Packet: Send + 'static> LinkBuilder<Packet, ()> for OutputChannelLink<Packe
 struct StreamToChannel<Packet> {
     stream: PacketStream<Packet>,
     channel_sender: crossbeam::Sender<Packet>,
+    control: Option<crossbeam::Sender<ControlMsg>>,
+}
+
+impl<Packet> StreamToChannel<Packet> {
+    /// Grants the credits for `sent` packets at once, rather than one message a packet. The link
+    /// granted to may be gone, in which case there is no one left to care.
+    fn grant(&self, sent: usize) {
+        if let Some(control) = &self.control {
+            if sent > 0 {
+                let _ = control.send(ControlMsg::Credit(sent));
+            }
+        }
+    }
 }
 
 impl<Packet> Future for StreamToChannel<Packet> {
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut sent = 0;
         loop {
             if self.channel_sender.is_full() {
+                self.grant(sent);
                 // Since we don't know anything about the other side of our channel, we have to
                 // self-wake and just hope that the other side empties it eventually.
                 cx.waker().clone().wake();
                 return Poll::Pending;
             }
 
-            match ready!(Pin::new(&mut self.stream).poll_next(cx)) {
-                Some(packet) => self
-                    .channel_sender
-                    .try_send(packet)
-                    .expect("OutputChannelLink::poll: try_send shouldn't fail"),
-                None => return Poll::Ready(()),
+            match Pin::new(&mut self.stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    self.channel_sender
+                        .try_send(packet)
+                        .expect("OutputChannelLink::poll: try_send shouldn't fail");
+                    sent += 1;
+                }
+                Poll::Ready(None) => {
+                    self.grant(sent);
+                    return Poll::Ready(());
+                }
+                Poll::Pending => {
+                    self.grant(sent);
+                    return Poll::Pending;
+                }
             }
         }
     }
@@ -182,4 +223,31 @@ mod tests {
         assert!(results.0.is_empty());
         assert_eq!(results.1, packets);
     }
+
+    #[test]
+    fn grants_credits_for_packets_sent() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, recv) = crossbeam_channel::unbounded::<i32>();
+            let (grant, control) = crossbeam_channel::unbounded();
+            let link = OutputChannelLink::new()
+                .ingressor(immediate_stream(0..20))
+                .channel(send)
+                .grant_credits(grant)
+                .build_link();
+
+            run_link(link).await;
+            (recv, control)
+        });
+        assert_eq!(results.0.len(), 20);
+        let granted: usize = results
+            .1
+            .try_iter()
+            .map(|msg| match msg {
+                ControlMsg::Credit(granted) => granted,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(granted, 20);
+    }
 }
@@ -0,0 +1,412 @@
+use crate::link::event::EventSink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tokio::time::{delay_until, Delay};
+
+type NextHopFn<Packet, K> = Box<dyn Fn(&Packet) -> K + Send>;
+
+/// Holds packets whose next hop hasn't been resolved yet, rather than dropping them, and sends them
+/// on once it is. The next hop of each packet is given by `next_hop`, such as the IP address of
+/// the gateway it is routed to, and resolutions, each a next hop and what it resolved to, such as
+/// a MAC address, arrive on the ingressor given to `resolutions`, as from an ARP or NDP handler.
+/// Each packet leaves paired with the resolution of its next hop, synchronous.
+///
+/// Up to `queue_capacity` packets wait for each next hop, past which the oldest is dropped to make
+/// room, and a packet still waiting `timeout` after it arrived is dropped, as its next hop is
+/// likely down. Resolutions are remembered for `timeout` too, so packets for a next hop that arrive
+/// just after it has been resolved, sent here before the router's tables were updated, go straight
+/// through. The link ends once its ingressor has, and every packet waiting has been sent or
+/// dropped.
+pub struct PendingResolutionLink<Packet, K, V> {
+    in_stream: Option<PacketStream<Packet>>,
+    resolutions: Option<PacketStream<(K, V)>>,
+    next_hop: Option<NextHopFn<Packet, K>>,
+    queue_capacity: usize,
+    timeout: Duration,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet, K, V> Default for PendingResolutionLink<Packet, K, V> {
+    fn default() -> Self {
+        PendingResolutionLink::new()
+    }
+}
+
+impl<Packet, K, V> PendingResolutionLink<Packet, K, V> {
+    pub fn new() -> Self {
+        PendingResolutionLink {
+            in_stream: None,
+            resolutions: None,
+            next_hop: None,
+            queue_capacity: 3,
+            timeout: Duration::from_secs(3),
+            event_sink: None,
+        }
+    }
+
+    /// The ingressor of resolutions, each a next hop and what it resolved to.
+    pub fn resolutions(self, resolutions: PacketStream<(K, V)>) -> Self {
+        PendingResolutionLink {
+            in_stream: self.in_stream,
+            resolutions: Some(resolutions),
+            next_hop: self.next_hop,
+            queue_capacity: self.queue_capacity,
+            timeout: self.timeout,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// The next hop of a packet.
+    pub fn next_hop<F: Fn(&Packet) -> K + Send + 'static>(self, next_hop: F) -> Self {
+        PendingResolutionLink {
+            in_stream: self.in_stream,
+            resolutions: self.resolutions,
+            next_hop: Some(Box::new(next_hop)),
+            queue_capacity: self.queue_capacity,
+            timeout: self.timeout,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes queue_capacity, the number of packets that may wait for each next hop, default
+    /// value is 3.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(queue_capacity > 0, "queue_capacity must be > 0");
+        PendingResolutionLink {
+            in_stream: self.in_stream,
+            resolutions: self.resolutions,
+            next_hop: self.next_hop,
+            queue_capacity,
+            timeout: self.timeout,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes timeout, how long packets wait for their next hop to be resolved, and resolutions
+    /// are remembered, default value is 3 seconds.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        PendingResolutionLink {
+            in_stream: self.in_stream,
+            resolutions: self.resolutions,
+            next_hop: self.next_hop,
+            queue_capacity: self.queue_capacity,
+            timeout,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Reports the packets the link drops, unresolved or over capacity, to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        PendingResolutionLink {
+            in_stream: self.in_stream,
+            resolutions: self.resolutions,
+            next_hop: self.next_hop,
+            queue_capacity: self.queue_capacity,
+            timeout: self.timeout,
+            event_sink: Some(event_sink),
+        }
+    }
+}
+
+impl<Packet, K, V> LinkBuilder<Packet, (Packet, V)> for PendingResolutionLink<Packet, K, V>
+where
+    Packet: Send + 'static,
+    K: Hash + Eq + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "PendingResolutionLink may only take 1 input stream, and 1 resolution stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "PendingResolutionLink may only take 1 input stream, and 1 resolution stream",
+            ));
+        }
+        Ok(PendingResolutionLink {
+            in_stream: Some(in_stream),
+            resolutions: self.resolutions,
+            next_hop: self.next_hop,
+            queue_capacity: self.queue_capacity,
+            timeout: self.timeout,
+            event_sink: self.event_sink,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<(Packet, V)>, LinkBuildError> {
+        match (self.in_stream, self.resolutions, self.next_hop) {
+            (None, _, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None, _) => Err(LinkBuildError::Missing("resolution stream")),
+            (_, _, None) => Err(LinkBuildError::Missing("next_hop")),
+            (Some(in_stream), Some(resolutions), Some(next_hop)) => Ok((
+                vec![],
+                vec![Box::new(PendingResolution {
+                    in_stream,
+                    resolutions,
+                    next_hop,
+                    pending: HashMap::new(),
+                    arrivals: VecDeque::new(),
+                    resolved: HashMap::new(),
+                    resolved_order: VecDeque::new(),
+                    ready: VecDeque::new(),
+                    in_ended: false,
+                    resolutions_ended: false,
+                    queue_capacity: self.queue_capacity,
+                    timeout: self.timeout,
+                    timer: None,
+                    event_sink: self.event_sink,
+                })],
+            )),
+        }
+    }
+}
+
+struct PendingResolution<Packet, K, V> {
+    in_stream: PacketStream<Packet>,
+    resolutions: PacketStream<(K, V)>,
+    next_hop: NextHopFn<Packet, K>,
+    /// The packets waiting for each next hop, oldest first, with when each arrived.
+    pending: HashMap<K, VecDeque<(Instant, Packet)>>,
+    /// When each packet waiting arrived, and its next hop, oldest first. Entries of packets that
+    /// have since been sent or dropped are skipped when they come up.
+    arrivals: VecDeque<(Instant, K)>,
+    resolved: HashMap<K, (Instant, V)>,
+    resolved_order: VecDeque<(Instant, K)>,
+    ready: VecDeque<(Packet, V)>,
+    in_ended: bool,
+    resolutions_ended: bool,
+    queue_capacity: usize,
+    timeout: Duration,
+    timer: Option<Delay>,
+    event_sink: Option<EventSink>,
+}
+
+impl<Packet, K, V> Unpin for PendingResolution<Packet, K, V> {}
+
+impl<Packet, K: Hash + Eq + Clone, V: Clone> PendingResolution<Packet, K, V> {
+    fn report(&self, reason: &'static str) {
+        if let Some(event_sink) = &self.event_sink {
+            event_sink.dropped(reason);
+        }
+    }
+
+    fn resolve(&mut self, next_hop: K, resolution: V, now: Instant) {
+        if let Some(waiting) = self.pending.remove(&next_hop) {
+            let released = waiting.into_iter().map(|(_, p)| (p, resolution.clone()));
+            self.ready.extend(released);
+        }
+        self.resolved_order.push_back((now, next_hop.clone()));
+        self.resolved.insert(next_hop, (now, resolution));
+    }
+
+    fn arrive(&mut self, packet: Packet, now: Instant) {
+        let next_hop = (self.next_hop)(&packet);
+        if let Some((_, resolution)) = self.resolved.get(&next_hop) {
+            self.ready.push_back((packet, resolution.clone()));
+            return;
+        }
+        let waiting = self.pending.entry(next_hop.clone()).or_default();
+        let full = waiting.len() >= self.queue_capacity;
+        if full {
+            waiting.pop_front();
+        }
+        waiting.push_back((now, packet));
+        if full {
+            self.report("too many packets waiting for next hop");
+        }
+        self.arrivals.push_back((now, next_hop));
+    }
+
+    /// Drops the packets, and forgets the resolutions, older than the timeout.
+    fn expire(&mut self, now: Instant) {
+        let expired_before = now.checked_sub(self.timeout).unwrap_or(now);
+        while let Some((arrived, next_hop)) = self.arrivals.front().cloned() {
+            if arrived > expired_before {
+                break;
+            }
+            self.arrivals.pop_front();
+            let waiting = match self.pending.get_mut(&next_hop) {
+                Some(waiting) => waiting,
+                None => continue,
+            };
+            if waiting.front().is_some_and(|(at, _)| *at <= expired_before) {
+                waiting.pop_front();
+                if waiting.is_empty() {
+                    self.pending.remove(&next_hop);
+                }
+                self.report("next hop was not resolved in time");
+            }
+        }
+        while let Some((resolved, next_hop)) = self.resolved_order.front().cloned() {
+            if resolved > expired_before {
+                break;
+            }
+            self.resolved_order.pop_front();
+            // Unless it has been resolved again since.
+            if self
+                .resolved
+                .get(&next_hop)
+                .is_some_and(|(at, _)| *at == resolved)
+            {
+                self.resolved.remove(&next_hop);
+            }
+        }
+    }
+}
+
+impl<Packet, K: Hash + Eq + Clone, V: Clone> Stream for PendingResolution<Packet, K, V> {
+    type Item = (Packet, V);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let link = &mut *self;
+        loop {
+            let now = clock::now();
+            while !link.resolutions_ended {
+                match Pin::new(&mut link.resolutions).poll_next(cx) {
+                    Poll::Ready(Some((next_hop, resolution))) => {
+                        link.resolve(next_hop, resolution, now)
+                    }
+                    Poll::Ready(None) => link.resolutions_ended = true,
+                    Poll::Pending => break,
+                }
+            }
+            link.expire(now);
+
+            if let Some(released) = link.ready.pop_front() {
+                return Poll::Ready(Some(released));
+            }
+
+            if !link.in_ended {
+                match Pin::new(&mut link.in_stream).poll_next(cx) {
+                    Poll::Ready(Some(packet)) => {
+                        link.arrive(packet, now);
+                        continue;
+                    }
+                    Poll::Ready(None) => link.in_ended = true,
+                    Poll::Pending => {}
+                }
+            }
+            if link.in_ended && link.pending.is_empty() {
+                return Poll::Ready(None);
+            }
+
+            // Wakes when the oldest packet waiting times out, should its next hop not be resolved
+            // before then.
+            if let Some((arrived, _)) = link.arrivals.front() {
+                let deadline = tokio::time::Instant::from_std(*arrived + link.timeout);
+                let timer = link.timer.get_or_insert_with(|| delay_until(deadline));
+                timer.reset(deadline);
+                if Pin::new(timer).poll(cx).is_ready() {
+                    continue;
+                }
+            }
+            return Poll::Pending;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::event::LinkEvent;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use crossbeam::crossbeam_channel;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_resolutions() {
+        PendingResolutionLink::<i32, i32, i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .next_hop(|packet| *packet)
+            .build_link();
+    }
+
+    #[test]
+    fn releases_packets_once_resolved() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = PendingResolutionLink::new()
+                .ingressor(immediate_stream(vec![(1, "a"), (2, "b"), (1, "c")]))
+                .resolutions(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(20),
+                    vec![(1, "mac1"), (2, "mac2")].into_iter(),
+                )))
+                .next_hop(|packet: &(i32, &str)| packet.0)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(
+            results[0],
+            vec![((1, "a"), "mac1"), ((1, "c"), "mac1"), ((2, "b"), "mac2")]
+        );
+    }
+
+    #[test]
+    fn passes_packets_for_resolved_next_hops() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = PendingResolutionLink::new()
+                .ingressor(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(20),
+                    vec![1, 1].into_iter(),
+                )))
+                .resolutions(immediate_stream(vec![(1, "mac1")]))
+                .next_hop(|packet: &i32| *packet)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![(1, "mac1"), (1, "mac1")]);
+    }
+
+    #[test]
+    fn drops_packets_past_capacity_or_timeout() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = PendingResolutionLink::new()
+                .ingressor(immediate_stream(vec![1, 2, 1, 1]))
+                .resolutions(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(10),
+                    vec![(1, "mac1")].into_iter(),
+                )))
+                .next_hop(|packet: &i32| *packet % 2)
+                .queue_capacity(2)
+                .timeout(Duration::from_millis(50))
+                .event_sink(EventSink::new("pending", sender))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![(1, "mac1"), (1, "mac1")]);
+        let events: Vec<String> = receiver
+            .try_iter()
+            .map(|event: LinkEvent| event.to_string())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                "pending: dropped packet, too many packets waiting for next hop",
+                "pending: dropped packet, next hop was not resolved in time",
+            ]
+        );
+    }
+}
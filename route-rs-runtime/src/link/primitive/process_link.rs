@@ -1,5 +1,6 @@
-use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
-use crate::processor::Processor;
+use crate::link::event::{DropReason, EventSink};
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::{Processor, ProcessorContext};
 use futures::prelude::*;
 use futures::task::{Context, Poll};
 use std::pin::Pin;
@@ -11,6 +12,8 @@ use std::pin::Pin;
 pub struct ProcessLink<P: Processor> {
     in_stream: Option<PacketStream<P::Input>>,
     processor: Option<P>,
+    event_sink: Option<EventSink>,
+    context: Option<ProcessorContext>,
 }
 
 impl<P: Processor> ProcessLink<P> {
@@ -18,6 +21,29 @@ impl<P: Processor> ProcessLink<P> {
         ProcessLink {
             in_stream: None,
             processor: None,
+            event_sink: None,
+            context: None,
+        }
+    }
+
+    /// Reports the packets the processor drops to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        ProcessLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            event_sink: Some(event_sink),
+            context: self.context,
+        }
+    }
+
+    /// Sets up the processor with `context` when the link is built. By default the processor is
+    /// given a context named after its type.
+    pub fn context(self, context: ProcessorContext) -> Self {
+        ProcessLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            event_sink: self.event_sink,
+            context: Some(context),
         }
     }
 }
@@ -26,42 +52,45 @@ impl<P: Processor> ProcessLink<P> {
 /// may only have one ingress and egress stream since it lacks some kind of queue
 /// storage.
 impl<P: Processor + Send + 'static> LinkBuilder<P::Input, P::Output> for ProcessLink<P> {
-    fn ingressors(self, mut in_streams: Vec<PacketStream<P::Input>>) -> Self {
-        assert_eq!(
-            in_streams.len(),
-            1,
-            "ProcessLink may only take 1 input stream"
-        );
-
-        if self.in_stream.is_some() {
-            panic!("ProcessLink may only take 1 input stream")
-        }
-
-        ProcessLink {
-            in_stream: Some(in_streams.remove(0)),
-            processor: self.processor,
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<P::Input>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "ProcessLink may only take 1 input stream",
+            ));
         }
+        self.try_ingressor(in_streams.remove(0))
     }
 
-    fn ingressor(self, in_stream: PacketStream<P::Input>) -> Self {
+    fn try_ingressor(self, in_stream: PacketStream<P::Input>) -> Result<Self, LinkBuildError> {
         if self.in_stream.is_some() {
-            panic!("ProcessLink may only take 1 input stream")
+            return Err(LinkBuildError::Ingressors(
+                "ProcessLink may only take 1 input stream",
+            ));
         }
 
-        ProcessLink {
+        Ok(ProcessLink {
             in_stream: Some(in_stream),
             processor: self.processor,
-        }
+            event_sink: self.event_sink,
+            context: self.context,
+        })
     }
 
-    fn build_link(self) -> Link<P::Output> {
-        if self.in_stream.is_none() {
-            panic!("Cannot build link! Missing input streams");
-        } else if self.processor.is_none() {
-            panic!("Cannot build link! Missing processor");
-        } else {
-            let processor = ProcessRunner::new(self.in_stream.unwrap(), self.processor.unwrap());
-            (vec![], vec![Box::new(processor)])
+    fn try_build_link(self) -> Result<Link<P::Output>, LinkBuildError> {
+        match (self.in_stream, self.processor) {
+            (None, _) => Err(LinkBuildError::Missing("input streams")),
+            (_, None) => Err(LinkBuildError::Missing("processor")),
+            (Some(in_stream), Some(mut processor)) => {
+                let context = self
+                    .context
+                    .unwrap_or_else(|| ProcessorContext::new(std::any::type_name::<P>()));
+                processor.setup(&context);
+                let processor = ProcessRunner::new(in_stream, processor, self.event_sink);
+                Ok((vec![], vec![Box::new(processor)]))
+            }
         }
     }
 }
@@ -71,6 +100,8 @@ impl<P: Processor + Send + 'static> ProcessLinkBuilder<P> for ProcessLink<P> {
         ProcessLink {
             in_stream: self.in_stream,
             processor: Some(processor),
+            event_sink: self.event_sink,
+            context: self.context,
         }
     }
 }
@@ -79,13 +110,15 @@ impl<P: Processor + Send + 'static> ProcessLinkBuilder<P> for ProcessLink<P> {
 struct ProcessRunner<P: Processor> {
     in_stream: PacketStream<P::Input>,
     processor: P,
+    event_sink: Option<EventSink>,
 }
 
 impl<P: Processor> ProcessRunner<P> {
-    fn new(in_stream: PacketStream<P::Input>, processor: P) -> Self {
+    fn new(in_stream: PacketStream<P::Input>, processor: P, event_sink: Option<EventSink>) -> Self {
         ProcessRunner {
             in_stream,
             processor,
+            event_sink,
         }
     }
 }
@@ -118,9 +151,14 @@ impl<P: Processor> Stream for ProcessRunner<P> {
             match ready!(Pin::new(&mut self.in_stream).poll_next(cx)) {
                 None => return Poll::Ready(None),
                 Some(input_packet) => {
-                    // if `processor.process` returns None, do nothing, loop around and try polling again.
-                    if let Some(output_packet) = self.processor.process(input_packet) {
-                        return Poll::Ready(Some(output_packet));
+                    // if `processor.process` returns None, report the drop, loop around and try polling again.
+                    match self.processor.process(input_packet) {
+                        Some(output_packet) => return Poll::Ready(Some(output_packet)),
+                        None => {
+                            if let Some(event_sink) = &self.event_sink {
+                                event_sink.dropped(DropReason::Filtered.as_str());
+                            }
+                        }
                     }
                 }
             }
@@ -131,10 +169,14 @@ impl<P: Processor> Stream for ProcessRunner<P> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::metrics::Registry;
     use crate::processor::{Drop, Identity, TransformFrom};
+    use crate::state::StateStore;
     use crate::utils::test::harness::{initialize_runtime, run_link};
     use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
     use core::time;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
 
     #[test]
     #[should_panic]
@@ -154,6 +196,33 @@ mod tests {
             .build_link();
     }
 
+    #[test]
+    fn try_build_link_reports_missing_settings() {
+        let missing_input = ProcessLink::new()
+            .processor(Identity::<i32>::new())
+            .try_build_link();
+        assert_eq!(
+            missing_input.err(),
+            Some(LinkBuildError::Missing("input streams"))
+        );
+
+        let missing_processor = ProcessLink::<Identity<i32>>::new()
+            .ingressor(immediate_stream(vec![]))
+            .try_build_link();
+        assert_eq!(
+            missing_processor.err().map(|err| err.to_string()),
+            Some(String::from("Cannot build link! Missing processor"))
+        );
+    }
+
+    #[test]
+    fn try_ingressor_rejects_second_input_stream() {
+        let link = ProcessLink::<Identity<i32>>::new()
+            .ingressor(immediate_stream(vec![]))
+            .try_ingressor(immediate_stream(vec![]));
+        assert!(matches!(link, Err(LinkBuildError::Ingressors(_))));
+    }
+
     #[test]
     fn builder_methods_work_in_any_order() {
         let packets: Vec<i32> = vec![];
@@ -238,6 +307,87 @@ mod tests {
 
             run_link(link).await
         });
-        assert_eq!(results[0], []);
+        assert_eq!(results[0], Vec::<i32>::new());
+    }
+
+    #[test]
+    fn reports_dropped_packets() {
+        let (sender, events) = crossbeam::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ProcessLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2]))
+                .processor(Drop::new())
+                .event_sink(EventSink::new("drop", sender))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], Vec::<i32>::new());
+        let reasons: Vec<String> = events.try_iter().map(|event| event.to_string()).collect();
+        assert_eq!(
+            reasons,
+            vec!["drop: dropped packet, filtered by processor"; 3]
+        );
+    }
+
+    /// Counts the packets it sees in a counter and a total shared through the state store.
+    struct Tally {
+        seen: Option<Arc<AtomicU64>>,
+        total: Option<Arc<AtomicU64>>,
+    }
+
+    impl Processor for Tally {
+        type Input = i32;
+        type Output = i32;
+
+        fn process(&mut self, packet: i32) -> Option<i32> {
+            self.seen.as_ref().unwrap().fetch_add(1, Ordering::Relaxed);
+            self.total.as_ref().unwrap().fetch_add(1, Ordering::Relaxed);
+            Some(packet)
+        }
+
+        fn setup(&mut self, context: &ProcessorContext) {
+            self.seen = Some(context.counter("seen"));
+            self.total = Some(
+                context
+                    .state()
+                    .get_or_insert_with("process-link-tally", || AtomicU64::new(0)),
+            );
+        }
+    }
+
+    #[test]
+    fn sets_up_processor_with_context() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let tallies: Vec<_> = vec!["tally-a", "tally-b"]
+                .into_iter()
+                .map(|name| {
+                    ProcessLink::new()
+                        .ingressor(immediate_stream(vec![1, 2, 3]))
+                        .processor(Tally {
+                            seen: None,
+                            total: None,
+                        })
+                        .context(ProcessorContext::new(name))
+                        .build_link()
+                })
+                .collect();
+
+            let mut results = vec![];
+            for link in tallies {
+                results.append(&mut run_link(link).await);
+            }
+            results
+        });
+        assert_eq!(results, vec![vec![1, 2, 3], vec![1, 2, 3]]);
+
+        let counters = Registry::global().counters();
+        assert!(counters.contains(&(String::from("tally-a.seen"), 3)));
+        assert!(counters.contains(&(String::from("tally-b.seen"), 3)));
+        let total: Arc<AtomicU64> = StateStore::global().get("process-link-tally").unwrap();
+        assert_eq!(total.load(Ordering::Relaxed), 6);
     }
 }
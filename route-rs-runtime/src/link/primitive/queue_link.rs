@@ -1,11 +1,15 @@
+use crate::link::event::{DropReason, EventSink};
+use crate::link::utils::spsc::{self, Producer, QueueReceiver};
 use crate::link::utils::task_park::*;
-use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
-use crate::processor::Processor;
+use crate::link::utils::watchdog::Watchdog;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::metrics;
+use crate::processor::{Processor, ProcessorContext};
 use crossbeam::atomic::AtomicCell;
-use crossbeam::crossbeam_channel;
-use crossbeam::crossbeam_channel::{Receiver, Sender, TryRecvError};
+use crossbeam::crossbeam_channel::{Receiver, TryRecvError};
 use futures::prelude::*;
 use futures::task::{Context, Poll};
+use std::marker::PhantomData;
 use std::pin::Pin;
 use std::sync::Arc;
 
@@ -16,6 +20,10 @@ pub struct QueueLink<P: Processor> {
     in_stream: Option<PacketStream<P::Input>>,
     processor: Option<P>,
     queue_capacity: usize,
+    batch_size: usize,
+    work_budget: usize,
+    event_sink: Option<EventSink>,
+    context: Option<ProcessorContext>,
 }
 
 impl<P: Processor> QueueLink<P> {
@@ -24,6 +32,10 @@ impl<P: Processor> QueueLink<P> {
             in_stream: None,
             processor: None,
             queue_capacity: 10,
+            batch_size: 8,
+            work_budget: 128,
+            event_sink: None,
+            context: None,
         }
     }
 
@@ -39,61 +51,145 @@ impl<P: Processor> QueueLink<P> {
             in_stream: self.in_stream,
             processor: self.processor,
             queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            event_sink: self.event_sink,
+            context: self.context,
         }
     }
-}
 
-impl<P: Processor + Send + 'static> LinkBuilder<P::Input, P::Output> for QueueLink<P> {
-    fn ingressors(self, mut in_streams: Vec<PacketStream<P::Input>>) -> Self {
-        assert_eq!(
-            in_streams.len(),
-            1,
-            "QueueLink may only take 1 input stream"
-        );
+    /// Changes batch_size, the number of packets either side of the queue passes
+    /// before waking the other, default value is 8. Each side also wakes the
+    /// other before it goes to sleep.
+    pub fn batch_size(self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch_size must be > 0");
 
-        if self.in_stream.is_some() {
-            panic!("QueueLink may only take 1 input stream")
+        QueueLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            queue_capacity: self.queue_capacity,
+            batch_size,
+            work_budget: self.work_budget,
+            event_sink: self.event_sink,
+            context: self.context,
         }
+    }
+
+    /// Changes work_budget, the number of packets the ingressor takes from its
+    /// input in one poll before yielding to other tasks, default value is 128.
+    pub fn work_budget(self, work_budget: usize) -> Self {
+        assert!(work_budget > 0, "work_budget must be > 0");
 
         QueueLink {
-            in_stream: Some(in_streams.remove(0)),
+            in_stream: self.in_stream,
             processor: self.processor,
             queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget,
+            event_sink: self.event_sink,
+            context: self.context,
         }
     }
 
-    fn ingressor(self, in_stream: PacketStream<P::Input>) -> Self {
-        if self.in_stream.is_some() {
-            panic!("QueueLink may only take 1 input stream")
+    /// Reports the packets the processor drops, and the queue filling up, to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        QueueLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            event_sink: Some(event_sink),
+            context: self.context,
         }
+    }
 
+    /// Sets up the processor with `context` when the link is built. By default the processor is
+    /// given a context named after its type.
+    pub fn context(self, context: ProcessorContext) -> Self {
         QueueLink {
-            in_stream: Some(in_stream),
+            in_stream: self.in_stream,
             processor: self.processor,
             queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            event_sink: self.event_sink,
+            context: Some(context),
         }
     }
+}
 
-    fn build_link(self) -> Link<P::Output> {
-        if self.in_stream.is_none() {
-            panic!("Cannot build link! Missing input stream");
-        } else if self.processor.is_none() {
-            panic!("Cannot build link! Missing processor");
-        } else {
-            let (to_egressor, from_ingressor) =
-                crossbeam_channel::bounded::<Option<P::Output>>(self.queue_capacity);
-            let task_park: Arc<AtomicCell<TaskParkState>> =
-                Arc::new(AtomicCell::new(TaskParkState::Empty));
-
-            let ingresssor = QueueIngressor::new(
-                self.in_stream.unwrap(),
-                to_egressor,
-                self.processor.unwrap(),
-                Arc::clone(&task_park),
-            );
-            let egressor = QueueEgressor::new(from_ingressor, task_park);
+impl<P: Processor + Send + 'static> LinkBuilder<P::Input, P::Output> for QueueLink<P> {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<P::Input>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "QueueLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<P::Input>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "QueueLink may only take 1 input stream",
+            ));
+        }
+
+        Ok(QueueLink {
+            in_stream: Some(in_stream),
+            processor: self.processor,
+            queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            event_sink: self.event_sink,
+            context: self.context,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<P::Output>, LinkBuildError> {
+        match (self.in_stream, self.processor) {
+            (None, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None) => Err(LinkBuildError::Missing("processor")),
+            (Some(in_stream), Some(mut processor)) => {
+                let context = self
+                    .context
+                    .unwrap_or_else(|| ProcessorContext::new(std::any::type_name::<P>()));
+                processor.setup(&context);
+                let (to_egressor, from_ingressor) =
+                    spsc::channel::<Option<P::Output>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+                let queue = from_ingressor.gauge();
+                metrics::register_queue(self.queue_capacity, move || queue.len());
+                if cfg!(debug_assertions) {
+                    let watchdog = Watchdog::global();
+                    let queue = from_ingressor.gauge();
+                    watchdog.register(
+                        format!("QueueLink#{}", watchdog.next_id()),
+                        &task_park,
+                        self.queue_capacity,
+                        move || queue.len(),
+                    );
+                }
 
-            (vec![Box::new(ingresssor)], vec![Box::new(egressor)])
+                let ingresssor = QueueIngressor::new(
+                    in_stream,
+                    to_egressor,
+                    processor,
+                    Arc::clone(&task_park),
+                    self.batch_size,
+                    self.work_budget,
+                    self.event_sink,
+                );
+                let egressor =
+                    QueueEgressor::new(from_ingressor, task_park).batch_size(self.batch_size);
+
+                Ok((vec![Box::new(ingresssor)], vec![Box::new(egressor)]))
+            }
         }
     }
 }
@@ -104,6 +200,10 @@ impl<P: Processor + Send + 'static> ProcessLinkBuilder<P> for QueueLink<P> {
             in_stream: self.in_stream,
             processor: Some(processor),
             queue_capacity: self.queue_capacity,
+            batch_size: self.batch_size,
+            work_budget: self.work_budget,
+            event_sink: self.event_sink,
+            context: self.context,
         }
     }
 }
@@ -116,23 +216,38 @@ impl<P: Processor + Send + 'static> ProcessLinkBuilder<P> for QueueLink<P> {
 /// polled by the runtime.
 pub struct QueueIngressor<P: Processor> {
     input_stream: PacketStream<P::Input>,
-    to_egressor: Sender<Option<P::Output>>,
+    to_egressor: Producer<Option<P::Output>>,
     processor: P,
     task_park: Arc<AtomicCell<TaskParkState>>,
+    batch_size: usize,
+    unwoken: usize,
+    work_budget: usize,
+    event_sink: Option<EventSink>,
+    /// Whether the to_egressor queue has been full since we last pushed onto it, so an overflow
+    /// is reported once, rather than on every poll until it drains.
+    full: bool,
 }
 
 impl<P: Processor> QueueIngressor<P> {
     fn new(
         input_stream: PacketStream<P::Input>,
-        to_egressor: Sender<Option<P::Output>>,
+        to_egressor: Producer<Option<P::Output>>,
         processor: P,
         task_park: Arc<AtomicCell<TaskParkState>>,
+        batch_size: usize,
+        work_budget: usize,
+        event_sink: Option<EventSink>,
     ) -> Self {
         QueueIngressor {
             input_stream,
             to_egressor,
             processor,
             task_park,
+            batch_size,
+            unwoken: 0,
+            work_budget,
+            event_sink,
+            full: false,
         }
     }
 }
@@ -150,8 +265,10 @@ impl<P: Processor> Future for QueueIngressor<P> {
     /// ###
     /// #1 The to_egressor queue is full, we wake the Egressor that we need
     /// awaking when there is work to do, and go to sleep by returning `Async::NotReady`.
+    /// If the queue has just become full, that is reported to our event sink, if we have one.
     ///
-    /// #2 The input_stream returns a NotReady, we sleep, with the assumption
+    /// #2 The input_stream returns a NotReady, we wake the Egressor if we have
+    /// passed it packets since we last did, and sleep, with the assumption
     /// that whomever produced the NotReady will awaken the task in the Future.
     ///
     /// #3 We get a Ready(None), in which case we push a None onto the to_Egressor
@@ -160,66 +277,116 @@ impl<P: Processor> Future for QueueIngressor<P> {
     ///
     /// #4 If our upstream `PacketStream` has a packet for us, we pass it to our `processor`
     /// for `process`ing. Most of the time, it will yield a `Some(output_packet)` that has
-    /// been transformed in some way. We pass that on to our egress channel and wake
-    /// our `Egressor` that it has work to do, and continue polling our upstream `PacketStream`.
+    /// been transformed in some way. We pass that on to our egress channel, wake
+    /// our `Egressor` that it has work to do if that completes a batch, and continue
+    /// polling our upstream `PacketStream`.
+    ///
+    /// #5 `processor`s may also choose to "drop" packets by returning `None`, so we report
+    /// the drop to our event sink, if we have one, and poll our upstream `PacketStream` again.
     ///
-    /// #5 `processor`s may also choose to "drop" packets by returning `None`, so we do nothing
-    /// and poll our upstream `PacketStream` again.
+    /// #6 We have taken `work_budget` packets from our input in this poll, so we wake
+    /// the Egressor if we have passed it packets since we last did, wake ourselves, and
+    /// return NotReady, so that other tasks on this thread get a turn.
     ///
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        loop {
-            if self.to_egressor.is_full() {
-                park_and_wake(&self.task_park, cx.waker().clone());
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        for _ in 0..ingressor.work_budget {
+            if ingressor.to_egressor.is_full() {
+                if let (false, Some(event_sink)) = (ingressor.full, &ingressor.event_sink) {
+                    event_sink.queue_overflow(0);
+                }
+                ingressor.full = true;
+                ingressor.unwoken = 0;
+                park_and_wake(&ingressor.task_park, cx.waker().clone());
                 return Poll::Pending;
             }
             let input_packet_option: Option<P::Input> =
-                ready!(Pin::new(&mut self.input_stream).poll_next(cx));
+                match Pin::new(&mut ingressor.input_stream).poll_next(cx) {
+                    Poll::Ready(input_packet_option) => input_packet_option,
+                    Poll::Pending => {
+                        flush_and_wake(&ingressor.task_park, &mut ingressor.unwoken);
+                        return Poll::Pending;
+                    }
+                };
 
             match input_packet_option {
                 None => {
-                    self.to_egressor.try_send(None).expect(
-                        "QueueIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
-                    );
-                    die_and_wake(&self.task_park);
+                    if ingressor.to_egressor.try_send(None).is_err() {
+                        panic!(
+                            "QueueIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail"
+                        );
+                    }
+                    die_and_wake(&ingressor.task_park);
                     return Poll::Ready(());
                 }
-                Some(input_packet) => {
-                    if let Some(output_packet) = self.processor.process(input_packet) {
-                        self.to_egressor
-                            .try_send(Some(output_packet))
-                            .expect("QueueIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail");
-                        unpark_and_wake(&self.task_park);
+                Some(input_packet) => match ingressor.processor.process(input_packet) {
+                    Some(output_packet) => {
+                        if ingressor.to_egressor.try_send(Some(output_packet)).is_err() {
+                            panic!("QueueIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail");
+                        }
+                        ingressor.full = false;
+                        batch_and_wake(
+                            &ingressor.task_park,
+                            &mut ingressor.unwoken,
+                            ingressor.batch_size,
+                        );
                     }
-                }
+                    None => {
+                        if let Some(event_sink) = &ingressor.event_sink {
+                            event_sink.dropped(DropReason::Filtered.as_str());
+                        }
+                    }
+                },
             }
         }
+        flush_and_wake(&ingressor.task_park, &mut ingressor.unwoken);
+        cx.waker().wake_by_ref();
+        Poll::Pending
     }
 }
 
 /// The Egressor side of the QueueLink is responsible to converting the
-/// output queue of processed packets, which is a crossbeam channel, to a
+/// output queue of processed packets, which is an SPSC ring, to a
 /// Stream that can be polled for packets. It ends up being owned by the
-/// processor which is polling for packets.
-pub struct QueueEgressor<Packet: Sized> {
-    from_ingressor: Receiver<Option<Packet>>,
+/// processor which is polling for packets. Links that feed several egressors
+/// from one channel use it with a crossbeam channel instead.
+pub struct QueueEgressor<Packet: Sized, Q = Receiver<Option<Packet>>> {
+    from_ingressor: Q,
     task_park: Arc<AtomicCell<TaskParkState>>,
+    batch_size: usize,
+    unwoken: usize,
+    packet: PhantomData<fn() -> Packet>,
 }
 
-impl<Packet: Sized> QueueEgressor<Packet> {
-    pub fn new(
-        from_ingressor: Receiver<Option<Packet>>,
-        task_park: Arc<AtomicCell<TaskParkState>>,
-    ) -> Self {
+impl<Packet: Sized, Q: QueueReceiver<Option<Packet>>> QueueEgressor<Packet, Q> {
+    pub fn new(from_ingressor: Q, task_park: Arc<AtomicCell<TaskParkState>>) -> Self {
         QueueEgressor {
             from_ingressor,
             task_park,
+            batch_size: 1,
+            unwoken: 0,
+            packet: PhantomData,
+        }
+    }
+
+    /// Changes batch_size, the number of packets taken off the queue before waking
+    /// the ingressor, default value is 1.
+    pub fn batch_size(self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch_size must be > 0");
+
+        QueueEgressor {
+            from_ingressor: self.from_ingressor,
+            task_park: self.task_park,
+            batch_size,
+            unwoken: self.unwoken,
+            packet: PhantomData,
         }
     }
 }
 
-impl<Packet: Sized> Unpin for QueueEgressor<Packet> {}
+impl<Packet: Sized, Q> Unpin for QueueEgressor<Packet, Q> {}
 
-impl<Packet: Sized> Stream for QueueEgressor<Packet> {
+impl<Packet: Sized, Q: QueueReceiver<Option<Packet>>> Stream for QueueEgressor<Packet, Q> {
     type Item = Packet;
 
     /// Implement Poll for Stream for QueueEgressor
@@ -227,31 +394,38 @@ impl<Packet: Sized> Stream for QueueEgressor<Packet> {
     /// This function, tries to retrieve a packet off the `from_ingressor`
     /// channel, there are four cases:
     /// ###
-    /// #1 Ok(Some(Packet)): Got a packet. If the Ingressor needs (likely due to
-    /// an until now full channel) to be awoken, wake them. Return the Async::Ready(Option(Packet))
+    /// #1 Ok(Some(Packet)): Got a packet. If that completes a batch, and the Ingressor needs
+    /// (likely due to an until now full channel) to be awoken, wake them. Return the
+    /// Async::Ready(Option(Packet))
     ///
     /// #2 Ok(None): this means that the Ingressor is in tear-down, and we
     /// will no longer be receivig packets. Return Async::Ready(None) to forward propagate teardown
     ///
-    /// #3 Err(TryRecvError::Empty): Packet queue is empty, await the Ingressor to awaken us with more
-    /// work, by returning Async::NotReady to signal to runtime to sleep this task.
+    /// #3 Err(TryRecvError::Empty): Packet queue is empty, wake the Ingressor and await it to awaken
+    /// us with more work, by returning Async::NotReady to signal to runtime to sleep this task.
     ///
     /// #4 Err(TryRecvError::Disconnected): Ingressor is in teardown and has dropped its side of the
     /// from_ingressor channel; we will no longer receive packets. Return Async::Ready(None) to forward
     /// propagate teardown.
     /// ###
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        match self.from_ingressor.try_recv() {
+        let egressor = Pin::into_inner(self);
+        match egressor.from_ingressor.try_recv() {
             Ok(Some(packet)) => {
-                unpark_and_wake(&self.task_park);
+                batch_and_wake(
+                    &egressor.task_park,
+                    &mut egressor.unwoken,
+                    egressor.batch_size,
+                );
                 Poll::Ready(Some(packet))
             }
             Ok(None) => {
-                die_and_wake(&self.task_park);
+                die_and_wake(&egressor.task_park);
                 Poll::Ready(None)
             }
             Err(TryRecvError::Empty) => {
-                park_and_wake(&self.task_park, cx.waker().clone());
+                egressor.unwoken = 0;
+                park_and_wake(&egressor.task_park, cx.waker().clone());
                 Poll::Pending
             }
             Err(TryRecvError::Disconnected) => Poll::Ready(None),
@@ -262,14 +436,32 @@ impl<Packet: Sized> Stream for QueueEgressor<Packet> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::link::event::LinkEvent;
     use crate::link::primitive::ProcessLink;
     use crate::link::{LinkBuilder, ProcessLinkBuilder};
     use crate::processor::{Drop, Identity, TransformFrom};
     use crate::utils::test::harness::{initialize_runtime, run_link};
     use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
     use core::time;
+    use futures::task::noop_waker;
     use rand::{thread_rng, Rng};
 
+    /// Passes odd numbers, dropping even ones.
+    struct Odd;
+
+    impl Processor for Odd {
+        type Input = i32;
+        type Output = i32;
+
+        fn process(&mut self, packet: i32) -> Option<i32> {
+            if packet % 2 == 1 {
+                Some(packet)
+            } else {
+                None
+            }
+        }
+    }
+
     #[test]
     #[should_panic]
     fn panics_when_built_without_input_streams() {
@@ -362,6 +554,112 @@ mod tests {
         assert_eq!(results[0], packets);
     }
 
+    #[test]
+    fn yields_to_other_tasks() {
+        // An always ready input the processor drops entirely never fills the queue, so the
+        // ingressor must yield by itself for anything else on the thread to run.
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (mut ingressors, _egressors) = QueueLink::new()
+                .ingressor(Box::new(stream::iter(0..)))
+                .processor(Drop::new())
+                .work_budget(16)
+                .build_link();
+            tokio::spawn(ingressors.remove(0));
+
+            tokio::spawn(async { 42 }).await.unwrap()
+        });
+    }
+
+    #[test]
+    fn batch_larger_than_queue() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(
+                time::Duration::from_millis(1),
+                packets.clone().into_iter(),
+            );
+
+            let link = QueueLink::new()
+                .ingressor(Box::new(packet_generator))
+                .processor(Identity::new())
+                .queue_capacity(2)
+                .batch_size(64)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn reports_overflow_and_drops() {
+        let (sender, events) = crossbeam::unbounded();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = QueueLink::new()
+                .ingressor(immediate_stream(0..20))
+                .processor(Odd)
+                .queue_capacity(1)
+                .event_sink(EventSink::new("odd", sender))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(
+            results[0],
+            (0..20).filter(|n| n % 2 == 1).collect::<Vec<_>>()
+        );
+
+        let events: Vec<LinkEvent> = events.try_iter().collect();
+        let drops = events
+            .iter()
+            .filter(|event| matches!(event, LinkEvent::Dropped { .. }))
+            .count();
+        assert_eq!(drops, 10);
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, LinkEvent::QueueOverflow { queue: 0, .. })));
+    }
+
+    #[test]
+    fn reports_overflow_once_until_queue_drains() {
+        let (sender, events) = crossbeam::unbounded();
+        let (mut ingressors, mut egressors) = QueueLink::new()
+            .ingressor(immediate_stream(0..10))
+            .processor(Identity::new())
+            .queue_capacity(1)
+            .event_sink(EventSink::new("identity", sender))
+            .build_link();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let overflows = || {
+            events
+                .try_iter()
+                .filter(|event| matches!(event, LinkEvent::QueueOverflow { .. }))
+                .count()
+        };
+
+        // Polled again and again while the queue stays full.
+        for _ in 0..3 {
+            assert!(Pin::new(&mut ingressors[0]).poll(&mut cx).is_pending());
+        }
+        assert_eq!(overflows(), 1);
+
+        assert_eq!(
+            Pin::new(&mut egressors[0]).poll_next(&mut cx),
+            Poll::Ready(Some(0))
+        );
+        assert!(Pin::new(&mut ingressors[0]).poll(&mut cx).is_pending());
+        assert_eq!(overflows(), 1);
+    }
+
     #[test]
     fn empty_stream() {
         let mut runtime = initialize_runtime();
@@ -375,7 +673,7 @@ mod tests {
 
             run_link(link).await
         });
-        assert_eq!(results[0], []);
+        assert_eq!(results[0], Vec::<i32>::new());
     }
 
     #[test]
@@ -492,6 +790,6 @@ mod tests {
 
             run_link(link).await
         });
-        assert_eq!(results[0], [])
+        assert_eq!(results[0], Vec::<i32>::new())
     }
 }
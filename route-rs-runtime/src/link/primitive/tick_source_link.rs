@@ -0,0 +1,201 @@
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tokio::time::{interval_at, Interval};
+
+/// A control item a `TickSourceLink` sends down its egressor every period, for processors that
+/// need to do time-driven maintenance, such as aging entries out of a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tick {
+    /// The time of the tick, by the runtime clock, as `utils::clock::now` would give it.
+    pub now: Instant,
+}
+
+/// What leaves a `TickSourceLink`, either a packet from its ingressor or a tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ticked<Packet> {
+    Packet(Packet),
+    Tick(Tick),
+}
+
+/// Passes packets through unchanged, with a `Tick` injected every period. A processor downstream
+/// takes `Ticked<Packet>`, and can age its tables or flush its caches on each tick without spawning
+/// a task of its own, as NAT, ARP, connection tracking and reassembly all need to.
+///
+/// The first tick comes one period after the link is built. A tick that is due is sent ahead of
+/// the next packet, and if the link isn't polled for several periods, the missed ticks are sent
+/// one after another as soon as it is. Ticks stop once the ingressor finishes.
+#[derive(Default)]
+pub struct TickSourceLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    period: Option<Duration>,
+}
+
+impl<Packet> TickSourceLink<Packet> {
+    pub fn new() -> Self {
+        TickSourceLink {
+            in_stream: None,
+            period: None,
+        }
+    }
+
+    /// Time between ticks.
+    pub fn period(self, period: Duration) -> Self {
+        assert!(period > Duration::from_secs(0), "period must be > 0");
+        TickSourceLink {
+            in_stream: self.in_stream,
+            period: Some(period),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Ticked<Packet>> for TickSourceLink<Packet> {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "TickSourceLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "TickSourceLink may only take 1 input stream",
+            ));
+        }
+
+        Ok(TickSourceLink {
+            in_stream: Some(in_stream),
+            period: self.period,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Ticked<Packet>>, LinkBuildError> {
+        match (self.in_stream, self.period) {
+            (None, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None) => Err(LinkBuildError::Missing("period")),
+            (Some(in_stream), Some(period)) => {
+                let ticks = interval_at(tokio::time::Instant::now() + period, period);
+                Ok((vec![], vec![Box::new(TickSource { in_stream, ticks })]))
+            }
+        }
+    }
+}
+
+struct TickSource<Packet> {
+    in_stream: PacketStream<Packet>,
+    ticks: Interval,
+}
+
+impl<Packet> Unpin for TickSource<Packet> {}
+
+impl<Packet> Stream for TickSource<Packet> {
+    type Item = Ticked<Packet>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if Pin::new(&mut self.ticks).poll_next(cx).is_ready() {
+            return Poll::Ready(Some(Ticked::Tick(Tick { now: clock::now() })));
+        }
+        Pin::new(&mut self.in_stream)
+            .poll_next(cx)
+            .map(|packet| packet.map(Ticked::Packet))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::Processor;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use std::collections::HashMap;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_period() {
+        TickSourceLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn passes_packets_through() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = TickSourceLink::new()
+                .ingressor(immediate_stream(vec![1, 2, 3]))
+                .period(Duration::from_secs(60))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(
+            results[0],
+            vec![Ticked::Packet(1), Ticked::Packet(2), Ticked::Packet(3)]
+        );
+    }
+
+    /// Remembers the packets it has seen, forgetting those not seen again within two ticks, and
+    /// passes on the number it remembers at each tick.
+    struct AgingTable {
+        last_seen: HashMap<i32, usize>,
+        ticks: usize,
+    }
+
+    impl Processor for AgingTable {
+        type Input = Ticked<i32>;
+        type Output = usize;
+
+        fn process(&mut self, item: Self::Input) -> Option<Self::Output> {
+            match item {
+                Ticked::Packet(packet) => {
+                    self.last_seen.insert(packet, self.ticks);
+                    None
+                }
+                Ticked::Tick(_) => {
+                    self.ticks += 1;
+                    let ticks = self.ticks;
+                    self.last_seen.retain(|_, seen| ticks - *seen <= 2);
+                    Some(self.last_seen.len())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn drives_time_based_maintenance() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (_, mut ticked) = TickSourceLink::new()
+                .ingressor(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(100),
+                    vec![1, 2, 3, 4].into_iter(),
+                )))
+                .period(Duration::from_millis(30))
+                .build_link();
+            let link = ProcessLink::new()
+                .ingressor(ticked.remove(0))
+                .processor(AgingTable {
+                    last_seen: HashMap::new(),
+                    ticks: 0,
+                })
+                .build_link();
+
+            run_link(link).await
+        });
+        // Each packet is remembered for two or three ticks, so at most one at a time.
+        assert!(results[0].len() >= 6);
+        assert!(results[0].iter().all(|remembered| *remembered <= 1));
+        assert!(results[0].contains(&0));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use tokio::sync::mpsc;
+
+/// `InputChannelLink` over a `tokio::sync::mpsc` channel, for feeding a pipeline from async code.
+/// The channel wakes the link as packets are sent, where a crossbeam channel has the link poll it
+/// again and again while it is empty.
+///
+/// As with `InputChannelLink`, several tasks can send into one channel with clones of its sender,
+/// and their packets leave in the order the channel took them, or into channels of their own,
+/// given by calling `add_channel` for each, whose packets are interleaved as they arrive.
+#[derive(Default)]
+pub struct TokioInputChannelLink<Packet> {
+    channel_receivers: Vec<mpsc::Receiver<Packet>>,
+}
+
+impl<Packet> TokioInputChannelLink<Packet> {
+    pub fn new() -> Self {
+        TokioInputChannelLink {
+            channel_receivers: vec![],
+        }
+    }
+
+    /// The channel to read packets from, replacing any given before.
+    pub fn channel(self, channel_receiver: mpsc::Receiver<Packet>) -> Self {
+        TokioInputChannelLink {
+            channel_receivers: vec![channel_receiver],
+        }
+    }
+
+    /// Adds a channel to read packets from, alongside any already given.
+    pub fn add_channel(self, channel_receiver: mpsc::Receiver<Packet>) -> Self {
+        let mut channel_receivers = self.channel_receivers;
+        channel_receivers.push(channel_receiver);
+        TokioInputChannelLink { channel_receivers }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<(), Packet> for TokioInputChannelLink<Packet> {
+    fn try_ingressors(self, _in_streams: Vec<PacketStream<()>>) -> Result<Self, LinkBuildError> {
+        Err(LinkBuildError::Ingressors(
+            "TokioInputChannelLink does not take stream ingressors",
+        ))
+    }
+
+    fn try_ingressor(self, _in_stream: PacketStream<()>) -> Result<Self, LinkBuildError> {
+        Err(LinkBuildError::Ingressors(
+            "TokioInputChannelLink does not take any stream ingressors",
+        ))
+    }
+
+    fn try_build_link(self) -> Result<Link<Packet>, LinkBuildError> {
+        let mut channel_receivers = self.channel_receivers;
+        match channel_receivers.len() {
+            0 => Err(LinkBuildError::Missing("channel")),
+            // The receiver is a stream of its own, ending once every sender has been dropped.
+            1 => Ok((vec![], vec![Box::new(channel_receivers.remove(0))])),
+            _ => Ok((
+                vec![],
+                vec![Box::new(futures::stream::select_all(channel_receivers))],
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::time::Duration;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_with_ingressors() {
+        TokioInputChannelLink::<()>::new()
+            .ingressors(vec![immediate_stream(vec![])])
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_channel() {
+        TokioInputChannelLink::<()>::new().build_link();
+    }
+
+    #[test]
+    fn packets_sent_from_a_task() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut send, recv) = mpsc::channel(2);
+            let link = TokioInputChannelLink::new().channel(recv).build_link();
+
+            let sent = packets.clone();
+            tokio::spawn(async move {
+                for p in sent {
+                    tokio::time::delay_for(Duration::from_millis(1)).await;
+                    send.send(p).await.unwrap();
+                }
+            });
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn merges_channels() {
+        let mut runtime = initialize_runtime();
+        let mut results = runtime.block_on(async {
+            let (mut first, first_receiver) = mpsc::channel(4);
+            let (mut second, second_receiver) = mpsc::channel(4);
+            let link = TokioInputChannelLink::new()
+                .channel(first_receiver)
+                .add_channel(second_receiver)
+                .build_link();
+
+            tokio::spawn(async move {
+                for p in 0..10 {
+                    first.send(p).await.unwrap();
+                }
+            });
+            tokio::spawn(async move {
+                for p in 100..110 {
+                    second.send(p).await.unwrap();
+                }
+            });
+
+            run_link(link).await.remove(0)
+        });
+        let second: Vec<i32> = results.iter().cloned().filter(|p| *p >= 100).collect();
+        assert_eq!(second, (100..110).collect::<Vec<_>>());
+        results.retain(|p| *p < 100);
+        assert_eq!(results, (0..10).collect::<Vec<_>>());
+    }
+}
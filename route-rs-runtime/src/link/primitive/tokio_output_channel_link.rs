@@ -0,0 +1,161 @@
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use tokio::sync::mpsc;
+
+/// `OutputChannelLink` over a `tokio::sync::mpsc` channel, for handing packets to async code. When
+/// the channel is full, the link sleeps until the receiver makes room, where with a crossbeam
+/// channel it has to keep waking itself to check. Once the receiver has been dropped, the link
+/// drops the packets it is given.
+#[derive(Default)]
+pub struct TokioOutputChannelLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    channel_sender: Option<mpsc::Sender<Packet>>,
+}
+
+impl<Packet> TokioOutputChannelLink<Packet> {
+    pub fn new() -> Self {
+        TokioOutputChannelLink {
+            in_stream: None,
+            channel_sender: None,
+        }
+    }
+
+    pub fn channel(self, channel_sender: mpsc::Sender<Packet>) -> Self {
+        TokioOutputChannelLink {
+            in_stream: self.in_stream,
+            channel_sender: Some(channel_sender),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, ()> for TokioOutputChannelLink<Packet> {
+    fn try_ingressors(
+        self,
+        mut in_streams: Vec<PacketStream<Packet>>,
+    ) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "TokioOutputChannelLink may only take 1 input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Packet>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "TokioOutputChannelLink may only take 1 input stream",
+            ));
+        }
+        Ok(TokioOutputChannelLink {
+            in_stream: Some(in_stream),
+            channel_sender: self.channel_sender,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<()>, LinkBuildError> {
+        match (self.in_stream, self.channel_sender) {
+            (None, _) => Err(LinkBuildError::Missing("input streams")),
+            (_, None) => Err(LinkBuildError::Missing("channel")),
+            (Some(in_stream), Some(sender)) => Ok((
+                vec![Box::new(StreamToTokioChannel {
+                    stream: in_stream,
+                    channel_sender: sender,
+                    closed: false,
+                })],
+                vec![],
+            )),
+        }
+    }
+}
+
+struct StreamToTokioChannel<Packet> {
+    stream: PacketStream<Packet>,
+    channel_sender: mpsc::Sender<Packet>,
+    closed: bool,
+}
+
+impl<Packet> Unpin for StreamToTokioChannel<Packet> {}
+
+impl<Packet> Future for StreamToTokioChannel<Packet> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        loop {
+            // Reserves a slot before taking a packet, so none is held while the channel is full.
+            if !self.closed && ready!(self.channel_sender.poll_ready(cx)).is_err() {
+                self.closed = true;
+            }
+
+            match ready!(Pin::new(&mut self.stream).poll_next(cx)) {
+                Some(packet) if !self.closed => {
+                    if self.channel_sender.try_send(packet).is_err() {
+                        self.closed = true;
+                    }
+                }
+                Some(_) => {}
+                None => return Poll::Ready(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_channel() {
+        TokioOutputChannelLink::<()>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn waits_for_room_in_small_queue() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, mut recv) = mpsc::channel(2);
+            let receiver = tokio::spawn(async move {
+                let mut outputs = vec![];
+                while let Some(n) = recv.recv().await {
+                    outputs.push(n);
+                }
+                outputs
+            });
+
+            let link = TokioOutputChannelLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .channel(send)
+                .build_link();
+
+            let link_results = run_link(link).await;
+            (link_results, receiver.await.unwrap())
+        });
+        assert!(results.0.is_empty());
+        assert_eq!(results.1, packets);
+    }
+
+    #[test]
+    fn drops_packets_once_receiver_is_dropped() {
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let (send, recv) = mpsc::channel(2);
+            drop(recv);
+
+            let link = TokioOutputChannelLink::new()
+                .ingressor(immediate_stream(0..100))
+                .channel(send)
+                .build_link();
+
+            run_link(link).await;
+        });
+    }
+}
@@ -0,0 +1,369 @@
+use crate::link::event::EventSink;
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use crate::utils::clock;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tokio::time::{delay_until, Delay};
+
+/// Pairs the packets of two ingressors in the order they arrive, the first of one with the first of
+/// the other, and so on, as when joining packets with the verdicts an off-path branch computes for
+/// them. The ingressor given to `ingressor` is on the left of each pair, and the one given to
+/// `right_ingressor` on the right, synchronous.
+///
+/// Up to `queue_capacity` packets of each side wait for a partner, after which the link takes no
+/// more from that side until the other catches up. With a `max_wait`, a packet whose partner
+/// arrives more than `max_wait` after it is dropped as stale, and the other is paired with the next
+/// in line, so one side losing a packet doesn't leave every later pair mismatched.
+///
+/// Once one side has ended, and every packet it sent has been paired, no more pairs can be made.
+/// The link then drops whatever the other side has waiting, and goes on taking from it and
+/// dropping what it takes until it ends too, so nothing upstream of the link finds its egressor
+/// gone. The link ends once both sides have.
+pub struct ZipLink<A, B> {
+    in_stream: Option<PacketStream<A>>,
+    right_stream: Option<PacketStream<B>>,
+    queue_capacity: usize,
+    max_wait: Option<Duration>,
+    event_sink: Option<EventSink>,
+}
+
+impl<A, B> Default for ZipLink<A, B> {
+    fn default() -> Self {
+        ZipLink::new()
+    }
+}
+
+impl<A, B> ZipLink<A, B> {
+    pub fn new() -> Self {
+        ZipLink {
+            in_stream: None,
+            right_stream: None,
+            queue_capacity: 10,
+            max_wait: None,
+            event_sink: None,
+        }
+    }
+
+    /// The ingressor whose packets are on the right of each pair.
+    pub fn right_ingressor(self, right_stream: PacketStream<B>) -> Self {
+        ZipLink {
+            in_stream: self.in_stream,
+            right_stream: Some(right_stream),
+            queue_capacity: self.queue_capacity,
+            max_wait: self.max_wait,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Changes queue_capacity, the number of packets of each side that may wait for a partner,
+    /// default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(queue_capacity > 0, "queue_capacity must be > 0");
+        ZipLink {
+            in_stream: self.in_stream,
+            right_stream: self.right_stream,
+            queue_capacity,
+            max_wait: self.max_wait,
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// The longest a packet waits for its partner before it is dropped as stale. By default,
+    /// packets wait for as long as it takes.
+    pub fn max_wait(self, max_wait: Duration) -> Self {
+        ZipLink {
+            in_stream: self.in_stream,
+            right_stream: self.right_stream,
+            queue_capacity: self.queue_capacity,
+            max_wait: Some(max_wait),
+            event_sink: self.event_sink,
+        }
+    }
+
+    /// Reports the packets the link drops, stale or without a partner, to `event_sink`.
+    pub fn event_sink(self, event_sink: EventSink) -> Self {
+        ZipLink {
+            in_stream: self.in_stream,
+            right_stream: self.right_stream,
+            queue_capacity: self.queue_capacity,
+            max_wait: self.max_wait,
+            event_sink: Some(event_sink),
+        }
+    }
+}
+
+impl<A: Send + 'static, B: Send + 'static> LinkBuilder<A, (A, B)> for ZipLink<A, B> {
+    fn try_ingressors(self, mut in_streams: Vec<PacketStream<A>>) -> Result<Self, LinkBuildError> {
+        if in_streams.len() != 1 {
+            return Err(LinkBuildError::Ingressors(
+                "ZipLink may only take 1 input stream, and 1 right input stream",
+            ));
+        }
+        self.try_ingressor(in_streams.remove(0))
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<A>) -> Result<Self, LinkBuildError> {
+        if self.in_stream.is_some() {
+            return Err(LinkBuildError::Ingressors(
+                "ZipLink may only take 1 input stream, and 1 right input stream",
+            ));
+        }
+        Ok(ZipLink {
+            in_stream: Some(in_stream),
+            right_stream: self.right_stream,
+            queue_capacity: self.queue_capacity,
+            max_wait: self.max_wait,
+            event_sink: self.event_sink,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<(A, B)>, LinkBuildError> {
+        match (self.in_stream, self.right_stream) {
+            (None, _) => Err(LinkBuildError::Missing("input stream")),
+            (_, None) => Err(LinkBuildError::Missing("right input stream")),
+            (Some(in_stream), Some(right_stream)) => Ok((
+                vec![],
+                vec![Box::new(Zip {
+                    left: Side::new(in_stream),
+                    right: Side::new(right_stream),
+                    queue_capacity: self.queue_capacity,
+                    max_wait: self.max_wait,
+                    timer: None,
+                    event_sink: self.event_sink,
+                })],
+            )),
+        }
+    }
+}
+
+/// The packets of one side of a `Zip` waiting for partners, with when each arrived.
+struct Side<T> {
+    stream: PacketStream<T>,
+    waiting: VecDeque<(Instant, T)>,
+    ended: bool,
+}
+
+impl<T> Side<T> {
+    fn new(stream: PacketStream<T>) -> Self {
+        Side {
+            stream,
+            waiting: VecDeque::new(),
+            ended: false,
+        }
+    }
+
+    /// Takes packets from the stream until `capacity` are waiting, or it has none to give.
+    fn fill(&mut self, cx: &mut Context, capacity: usize) {
+        while !self.ended && self.waiting.len() < capacity {
+            match Pin::new(&mut self.stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => self.waiting.push_back((clock::now(), packet)),
+                Poll::Ready(None) => self.ended = true,
+                Poll::Pending => return,
+            }
+        }
+    }
+
+    /// Drops packets that arrived before `arrived`, returning how many.
+    fn drop_before(&mut self, arrived: Instant) -> usize {
+        let mut dropped = 0;
+        while self.waiting.front().is_some_and(|(at, _)| *at < arrived) {
+            self.waiting.pop_front();
+            dropped += 1;
+        }
+        dropped
+    }
+
+    /// Drops every packet waiting, and every packet the stream has to give, returning how many.
+    fn drain(&mut self, cx: &mut Context) -> usize {
+        let mut dropped = self.waiting.len();
+        self.waiting.clear();
+        while !self.ended {
+            match Pin::new(&mut self.stream).poll_next(cx) {
+                Poll::Ready(Some(_)) => dropped += 1,
+                Poll::Ready(None) => self.ended = true,
+                Poll::Pending => break,
+            }
+        }
+        dropped
+    }
+
+    fn finished(&self) -> bool {
+        self.ended && self.waiting.is_empty()
+    }
+}
+
+struct Zip<A, B> {
+    left: Side<A>,
+    right: Side<B>,
+    queue_capacity: usize,
+    max_wait: Option<Duration>,
+    timer: Option<Delay>,
+    event_sink: Option<EventSink>,
+}
+
+impl<A, B> Unpin for Zip<A, B> {}
+
+impl<A, B> Zip<A, B> {
+    fn report(&self, dropped: usize, reason: &'static str) {
+        if let Some(event_sink) = &self.event_sink {
+            for _ in 0..dropped {
+                event_sink.dropped(reason);
+            }
+        }
+    }
+
+    /// Drops the packets at the front of either side that waited too long for the other's.
+    fn drop_stale(&mut self, max_wait: Duration) -> usize {
+        let now = clock::now();
+        let (left, right) = (self.left.waiting.front(), self.right.waiting.front());
+        let latest = match (left, right) {
+            // Pairs the fronts, unless one arrived too long after the other.
+            (Some((left, _)), Some((right, _))) => (*left).max(*right),
+            // No partner has come, and none that does now is in time.
+            _ => now,
+        };
+        let stale_before = latest.checked_sub(max_wait).unwrap_or(latest);
+        self.left.drop_before(stale_before) + self.right.drop_before(stale_before)
+    }
+}
+
+impl<A, B> Stream for Zip<A, B> {
+    type Item = (A, B);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let zip = &mut *self;
+        loop {
+            zip.left.fill(cx, zip.queue_capacity);
+            zip.right.fill(cx, zip.queue_capacity);
+            if let Some(max_wait) = zip.max_wait {
+                let dropped = zip.drop_stale(max_wait);
+                zip.report(dropped, "no partner arrived in time");
+                // Fronts dropped make room, so fill again before pairing.
+                if dropped > 0 {
+                    continue;
+                }
+            }
+
+            if !zip.left.waiting.is_empty() && !zip.right.waiting.is_empty() {
+                let (_, left) = zip.left.waiting.pop_front().unwrap();
+                let (_, right) = zip.right.waiting.pop_front().unwrap();
+                return Poll::Ready(Some((left, right)));
+            }
+
+            if zip.left.finished() || zip.right.finished() {
+                let dropped = zip.left.drain(cx) + zip.right.drain(cx);
+                zip.report(dropped, "partner input has ended");
+                if zip.left.ended && zip.right.ended {
+                    return Poll::Ready(None);
+                }
+                return Poll::Pending;
+            }
+
+            // Wakes when the oldest packet waiting has waited too long, should no partner or more
+            // packets come before then.
+            if let Some(max_wait) = zip.max_wait {
+                let oldest = zip.left.waiting.front().map(|(at, _)| *at);
+                let oldest = oldest.or_else(|| zip.right.waiting.front().map(|(at, _)| *at));
+                if let Some(oldest) = oldest {
+                    let deadline = tokio::time::Instant::from_std(oldest + max_wait);
+                    let timer = zip.timer.get_or_insert_with(|| delay_until(deadline));
+                    timer.reset(deadline);
+                    if Pin::new(timer).poll(cx).is_ready() {
+                        continue;
+                    }
+                }
+            }
+            return Poll::Pending;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link, LinkHarness};
+    use crate::utils::test::packet_generators::{
+        immediate_stream, spaced_stream, PacketIntervalGenerator,
+    };
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_right_ingressor() {
+        ZipLink::<i32, i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn pairs_packets_in_order() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ZipLink::new()
+                .ingressor(immediate_stream(vec![1, 2, 3]))
+                .right_ingressor(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(5),
+                    vec!["one", "two", "three"].into_iter(),
+                )))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![(1, "one"), (2, "two"), (3, "three")]);
+    }
+
+    #[test]
+    fn drains_the_longer_side() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let harness = LinkHarness::new().egressor("pairs");
+            let link = ZipLink::new()
+                .ingressor(immediate_stream(0..2))
+                .right_ingressor(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_millis(1),
+                    0..10,
+                )))
+                .queue_capacity(2)
+                .event_sink(harness.event_sink("zip"))
+                .build_link();
+
+            harness.run(link).await
+        });
+        assert_eq!(results.packets("pairs"), &[(0, 0), (1, 1)]);
+        assert_eq!(results.events.len(), 8);
+        assert_eq!(
+            results.events[0].to_string(),
+            "zip: dropped packet, partner input has ended"
+        );
+    }
+
+    /// Packets each sent the given number of milliseconds after the one before.
+    fn spaced<T: Send + 'static>(packets: Vec<(u64, T)>) -> PacketStream<T> {
+        spaced_stream(
+            packets
+                .into_iter()
+                .map(|(gap, packet)| (Duration::from_millis(gap), packet))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn drops_stale_packets() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            // The verdict for packet 1 never comes, so packet 1 goes stale before packet 2's comes.
+            let packets = spaced(vec![(0, 0), (50, 1), (50, 2), (50, 3)]);
+            let verdicts = spaced(vec![(5, 0), (100, 2), (50, 3)]);
+            let link = ZipLink::new()
+                .ingressor(packets)
+                .right_ingressor(verdicts)
+                .max_wait(Duration::from_millis(30))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![(0, 0), (2, 2), (3, 3)]);
+    }
+}
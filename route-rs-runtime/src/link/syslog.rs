@@ -0,0 +1,468 @@
+use crate::config::{SyslogConfig, SyslogFacility, SyslogTransport};
+use crate::link::event::LinkEvent;
+use crate::link::TokioRunnable;
+use crate::utils::clock;
+use crossbeam::crossbeam_channel::{Receiver, TryRecvError};
+use std::net::{SocketAddr, UdpSocket};
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+/// Messages sent per second at most, unless `max_rate` says otherwise.
+const DEFAULT_MAX_RATE: u64 = 100;
+
+/// Severity of a message, RFC 5424 section 6.2.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Warning = 4,
+    Notice = 5,
+    Informational = 6,
+}
+
+/// Sends the `LinkEvent`s of a router to a syslog server as RFC 5424 messages, over UDP, or over
+/// TCP framed by octet counting as in RFC 6587. Each message has the MSGID of its kind of event,
+/// `drop`, `parse-error`, `queue-overflow`, `alert` or `activity`, and the event as its text,
+/// starting with the link it happened in. Firewall verdicts come from a `LogProcessor` logging to
+/// `LogTarget::Events`, and DHCP and DNS activity from the `event_sink` of their composites.
+///
+/// Drops are left out unless `drops` asks for them, as a busy router drops many. Past `max_rate`
+/// messages in a second, the rest are left out, and how many is sent once the next second starts,
+/// as a `rate-limit` message.
+/// Messages that can't be sent, as when the TCP connection is down, are lost, and the connection
+/// is tried again with the next.
+pub struct SyslogExporter {
+    server: SocketAddr,
+    transport: SyslogTransport,
+    facility: SyslogFacility,
+    hostname: String,
+    app_name: String,
+    drops: bool,
+    max_rate: u64,
+}
+
+impl SyslogExporter {
+    pub fn new(server: SocketAddr) -> Self {
+        SyslogExporter {
+            server,
+            transport: SyslogTransport::Udp,
+            facility: SyslogFacility::Daemon,
+            hostname: String::from("-"),
+            app_name: String::from("route-rs"),
+            drops: false,
+            max_rate: DEFAULT_MAX_RATE,
+        }
+    }
+
+    /// Changes transport, default value is UDP.
+    pub fn transport(self, transport: SyslogTransport) -> Self {
+        SyslogExporter {
+            server: self.server,
+            transport,
+            facility: self.facility,
+            hostname: self.hostname,
+            app_name: self.app_name,
+            drops: self.drops,
+            max_rate: self.max_rate,
+        }
+    }
+
+    /// Changes facility, default value is daemon.
+    pub fn facility(self, facility: SyslogFacility) -> Self {
+        SyslogExporter {
+            server: self.server,
+            transport: self.transport,
+            facility,
+            hostname: self.hostname,
+            app_name: self.app_name,
+            drops: self.drops,
+            max_rate: self.max_rate,
+        }
+    }
+
+    /// Changes hostname, the router as the server knows it, default value is `-`, which leaves
+    /// the server to tell by the address messages come from.
+    pub fn hostname(self, hostname: &str) -> Self {
+        SyslogExporter {
+            server: self.server,
+            transport: self.transport,
+            facility: self.facility,
+            hostname: String::from(hostname),
+            app_name: self.app_name,
+            drops: self.drops,
+            max_rate: self.max_rate,
+        }
+    }
+
+    /// Changes app_name, default value is `route-rs`.
+    pub fn app_name(self, app_name: &str) -> Self {
+        SyslogExporter {
+            server: self.server,
+            transport: self.transport,
+            facility: self.facility,
+            hostname: self.hostname,
+            app_name: String::from(app_name),
+            drops: self.drops,
+            max_rate: self.max_rate,
+        }
+    }
+
+    /// Changes drops, whether each packet dropped is sent, default value is false.
+    pub fn drops(self, drops: bool) -> Self {
+        SyslogExporter {
+            server: self.server,
+            transport: self.transport,
+            facility: self.facility,
+            hostname: self.hostname,
+            app_name: self.app_name,
+            drops,
+            max_rate: self.max_rate,
+        }
+    }
+
+    /// Changes max_rate, the messages sent per second at most, default value is 100.
+    pub fn max_rate(self, messages_per_second: u64) -> Self {
+        assert!(messages_per_second > 0, "max_rate must be > 0");
+        SyslogExporter {
+            server: self.server,
+            transport: self.transport,
+            facility: self.facility,
+            hostname: self.hostname,
+            app_name: self.app_name,
+            drops: self.drops,
+            max_rate: messages_per_second,
+        }
+    }
+
+    /// Applies every setting given in the `syslog` section of a `RouterConfig`.
+    pub fn config(self, config: &SyslogConfig) -> Self {
+        let mut exporter = SyslogExporter {
+            server: config.server,
+            ..self
+        };
+        if let Some(transport) = config.transport {
+            exporter = exporter.transport(transport);
+        }
+        if let Some(facility) = config.facility {
+            exporter = exporter.facility(facility);
+        }
+        if let Some(hostname) = &config.hostname {
+            exporter = exporter.hostname(hostname);
+        }
+        if let Some(drops) = config.drops {
+            exporter = exporter.drops(drops);
+        }
+        if let Some(max_rate) = config.max_rate {
+            exporter = exporter.max_rate(max_rate);
+        }
+        exporter
+    }
+
+    /// A runnable that sends the events received on `events`, checking for more every
+    /// `interval`, until every sender of the channel is gone.
+    pub fn watch(self, events: Receiver<LinkEvent>, interval: Duration) -> TokioRunnable {
+        Box::new(Box::pin(async move {
+            let mut connection = Connection::new(self.server, self.transport);
+            let mut window = RateWindow::new(self.max_rate, clock::now());
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                let mut messages = vec![];
+                let mut disconnected = false;
+                if let Some(left_out) = window.roll(clock::now()) {
+                    messages.push(self.format(
+                        Severity::Notice,
+                        "rate-limit",
+                        &format!("left out {} messages over max_rate", left_out),
+                        SystemTime::now(),
+                    ));
+                }
+                loop {
+                    match events.try_recv() {
+                        Ok(event) => {
+                            if !self.drops && matches!(event, LinkEvent::Dropped { .. }) {
+                                continue;
+                            }
+                            if window.admit() {
+                                let (severity, msg_id) = classify(&event);
+                                messages.push(self.format(
+                                    severity,
+                                    msg_id,
+                                    &event.to_string(),
+                                    SystemTime::now(),
+                                ));
+                            }
+                        }
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => {
+                            disconnected = true;
+                            break;
+                        }
+                    }
+                }
+                connection.send(&messages).await;
+                if disconnected {
+                    return;
+                }
+            }
+        }))
+    }
+
+    /// An RFC 5424 message, section 6, with no structured data.
+    fn format(&self, severity: Severity, msg_id: &str, text: &str, at: SystemTime) -> String {
+        format!(
+            "<{}>1 {} {} {} {} {} - {}",
+            self.facility as u8 * 8 + severity as u8,
+            timestamp(at),
+            self.hostname,
+            self.app_name,
+            process::id(),
+            msg_id,
+            text
+        )
+    }
+}
+
+/// The severity and MSGID `event` is sent with.
+fn classify(event: &LinkEvent) -> (Severity, &'static str) {
+    match event {
+        LinkEvent::Dropped { .. } => (Severity::Informational, "drop"),
+        LinkEvent::ParseError { .. } => (Severity::Notice, "parse-error"),
+        LinkEvent::QueueOverflow { .. } => (Severity::Warning, "queue-overflow"),
+        LinkEvent::Alert { .. } => (Severity::Warning, "alert"),
+        LinkEvent::Activity { .. } => (Severity::Informational, "activity"),
+    }
+}
+
+/// `at` in UTC as RFC 3339 has it, to the millisecond, RFC 5424 section 6.2.3.
+fn timestamp(at: SystemTime) -> String {
+    let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+    // The civil date of a day since the epoch, by Howard Hinnant's `civil_from_days`, in eras of
+    // 400 years starting on March 1st.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Counts the messages sent in each second, to keep to `max_rate`.
+struct RateWindow {
+    max_rate: u64,
+    started: Instant,
+    sent: u64,
+    left_out: u64,
+}
+
+impl RateWindow {
+    fn new(max_rate: u64, now: Instant) -> Self {
+        RateWindow {
+            max_rate,
+            started: now,
+            sent: 0,
+            left_out: 0,
+        }
+    }
+
+    /// Starts the next second if this one is over, returning how many messages this one left
+    /// out, if any.
+    fn roll(&mut self, now: Instant) -> Option<u64> {
+        if now < self.started + Duration::from_secs(1) {
+            return None;
+        }
+        let left_out = self.left_out;
+        self.started = now;
+        self.sent = 0;
+        self.left_out = 0;
+        Some(left_out).filter(|left_out| *left_out > 0)
+    }
+
+    /// Whether another message may be sent this second.
+    fn admit(&mut self) -> bool {
+        if self.sent >= self.max_rate {
+            self.left_out += 1;
+            return false;
+        }
+        self.sent += 1;
+        true
+    }
+}
+
+/// How messages reach the server.
+enum Connection {
+    Udp(SocketAddr, Option<UdpSocket>),
+    /// Connected on the first message, and again after the connection fails.
+    Tcp(SocketAddr, Option<TcpStream>),
+}
+
+impl Connection {
+    fn new(server: SocketAddr, transport: SyslogTransport) -> Self {
+        match transport {
+            SyslogTransport::Udp => {
+                let bind_addr = if server.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                Connection::Udp(server, UdpSocket::bind(bind_addr).ok())
+            }
+            SyslogTransport::Tcp => Connection::Tcp(server, None),
+        }
+    }
+
+    async fn send(&mut self, messages: &[String]) {
+        if messages.is_empty() {
+            return;
+        }
+        match self {
+            Connection::Udp(server, socket) => {
+                if let Some(socket) = socket {
+                    for message in messages {
+                        let _ = socket.send_to(message.as_bytes(), *server);
+                    }
+                }
+            }
+            Connection::Tcp(server, stream) => {
+                if stream.is_none() {
+                    *stream = TcpStream::connect(*server).await.ok();
+                }
+                if let Some(connected) = stream {
+                    let mut frames = Vec::new();
+                    for message in messages {
+                        frames.extend_from_slice(format!("{} ", message.len()).as_bytes());
+                        frames.extend_from_slice(message.as_bytes());
+                    }
+                    if connected.write_all(&frames).await.is_err() {
+                        *stream = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::event::EventSink;
+    use crate::utils::test::harness::initialize_runtime;
+    use crossbeam::crossbeam_channel;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn formats_rfc_5424_messages() {
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_millis(1_700_000_000_250)),
+            "2023-11-14T22:13:20.250Z"
+        );
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000Z"
+        );
+
+        let exporter = SyslogExporter::new("127.0.0.1:514".parse().unwrap())
+            .facility(SyslogFacility::Local3)
+            .hostname("edge1");
+        let (severity, msg_id) = classify(&LinkEvent::QueueOverflow {
+            link: "uplink".into(),
+            queue: 0,
+        });
+        assert_eq!(
+            exporter.format(severity, msg_id, "uplink: queue 0 full", UNIX_EPOCH),
+            format!(
+                "<156>1 1970-01-01T00:00:00.000Z edge1 route-rs {} queue-overflow - uplink: \
+                 queue 0 full",
+                process::id()
+            )
+        );
+    }
+
+    #[test]
+    fn sends_events_over_udp_up_to_max_rate() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let sink = EventSink::new("dhcp", sender);
+        sink.dropped("no route");
+        sink.activity(String::from("delegated prefix 2001:db8::/56 for 3600s"));
+        sink.alert(String::from("port scan from 10.0.0.9"));
+        sink.activity(String::from("over the limit"));
+        drop(sink);
+
+        let exporter = SyslogExporter::new(server.local_addr().unwrap()).max_rate(2);
+        let mut runtime = initialize_runtime();
+        runtime.block_on(exporter.watch(receiver, Duration::from_millis(10)));
+
+        let mut received = vec![];
+        let mut buf = [0; 1024];
+        while let Ok(len) = server.recv(&mut buf) {
+            received.push(String::from_utf8(buf[..len].to_vec()).unwrap());
+        }
+        assert_eq!(received.len(), 2);
+        assert!(received[0].starts_with("<30>1 "));
+        assert!(received[0].ends_with(" activity - dhcp: delegated prefix 2001:db8::/56 for 3600s"));
+        assert!(received[1].starts_with("<28>1 "));
+        assert!(received[1].ends_with(" alert - dhcp: port scan from 10.0.0.9"));
+    }
+
+    #[test]
+    fn frames_messages_by_length_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap();
+        let reader = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let sink = EventSink::new("firewall", sender);
+        sink.activity(String::from("verdict=deny src=10.0.0.9"));
+        sink.parse_error("too short");
+        drop(sink);
+        let exporter = SyslogExporter::new(server)
+            .transport(SyslogTransport::Tcp)
+            .drops(true);
+        let mut runtime = initialize_runtime();
+        runtime.block_on(exporter.watch(receiver, Duration::from_millis(10)));
+        drop(runtime);
+
+        let mut received = reader.join().unwrap();
+        let mut messages = vec![];
+        while let Some(space) = received.find(' ') {
+            let len: usize = received[..space].parse().unwrap();
+            messages.push(received[space + 1..space + 1 + len].to_string());
+            received = received[space + 1 + len..].to_string();
+        }
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].ends_with(" activity - firewall: verdict=deny src=10.0.0.9"));
+        assert!(messages[1].starts_with("<29>1 "));
+        assert!(messages[1].ends_with(" parse-error - firewall: could not parse packet, too short"));
+    }
+}
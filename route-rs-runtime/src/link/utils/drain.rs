@@ -0,0 +1,62 @@
+use crate::link::PacketStream;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+
+/// Pulls every packet out of a stream nothing else reads, and discards it. Pull based links only
+/// make progress when their egressors are polled, so an egressor left unread, such as that of a
+/// `DropLink` at the end of a pipeline, would stop its upstream once its queues fill. Driving it
+/// with `Drain` keeps the packets flowing.
+pub struct Drain<Packet> {
+    stream: PacketStream<Packet>,
+}
+
+impl<Packet> Drain<Packet> {
+    pub fn new(stream: PacketStream<Packet>) -> Self {
+        Drain { stream }
+    }
+}
+
+impl<Packet> Unpin for Drain<Packet> {}
+
+impl<Packet> Future for Drain<Packet> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        while ready!(Pin::new(&mut self.stream).poll_next(cx)).is_some() {}
+        Poll::Ready(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::even_link;
+    use crate::link::composite::DropLink;
+    use crate::link::{LinkBuilder, TokioRunnable};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    fn drains_dropped_packets() {
+        let packets: Vec<i32> = (0..100).collect();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, mut egressors) = even_link(immediate_stream(packets));
+
+            let (mut drop_runnables, mut drop_egressors) = DropLink::new()
+                .ingressor(egressors.pop().unwrap())
+                .build_link();
+            let drain: TokioRunnable = Box::new(Drain::new(drop_egressors.remove(0)));
+            runnables.append(&mut drop_runnables);
+            runnables.push(drain);
+
+            run_link((runnables, egressors)).await
+        });
+        assert_eq!(
+            results[0],
+            (0..100).filter(|p| p % 2 == 0).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,2 +1,15 @@
 /// A cache for storing task handles.
 pub mod task_park;
+
+/// Drives an egressor nothing reads, so its packets are dropped rather than stalling the link.
+pub mod drain;
+
+/// A bounded queue between exactly one producer and one consumer.
+pub mod spsc;
+
+/// Reports task parks that look to have missed a wakeup.
+pub mod watchdog;
+
+/// The `unpack_link!` macro, binding the egressors of a built link to names.
+#[macro_use]
+pub mod unpack;
@@ -0,0 +1,263 @@
+//! # What is it for?
+//!
+//! A bounded queue with exactly one producer and one consumer, as between the ingressor and
+//! egressor of a `QueueLink`. With a single thread at each end, sending and receiving are each a
+//! load and a store of an atomic index, where an MPMC channel has to coordinate any number of
+//! senders and receivers. Each end keeps its own copy of the other's index, and only reads the
+//! shared one when its copy says the queue is full or empty, so the two ends rarely touch the same
+//! cache line.
+
+use crossbeam::crossbeam_channel::{Receiver, TryRecvError};
+use crossbeam::utils::CachePadded;
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Count of values ever received, only written by the consumer.
+    head: CachePadded<AtomicUsize>,
+    /// Count of values ever sent, only written by the producer.
+    tail: CachePadded<AtomicUsize>,
+    producer_dropped: AtomicBool,
+}
+
+// Each slot is only accessed by one end at a time, as handed over by head and tail.
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.slots[index % self.slots.len()].get()
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            unsafe { std::ptr::drop_in_place((*self.slot(head)).as_mut_ptr()) };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// Creates a queue that holds up to `capacity` values.
+pub fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "capacity must be > 0");
+    let ring = Arc::new(Ring {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        head: CachePadded::new(AtomicUsize::new(0)),
+        tail: CachePadded::new(AtomicUsize::new(0)),
+        producer_dropped: AtomicBool::new(false),
+    });
+    (
+        Producer {
+            ring: Arc::clone(&ring),
+            tail: 0,
+            cached_head: 0,
+        },
+        Consumer {
+            ring,
+            head: 0,
+            cached_tail: 0,
+        },
+    )
+}
+
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+    tail: usize,
+    cached_head: usize,
+}
+
+impl<T> Producer<T> {
+    pub fn is_full(&mut self) -> bool {
+        if self.tail.wrapping_sub(self.cached_head) < self.ring.slots.len() {
+            return false;
+        }
+        self.cached_head = self.ring.head.load(Ordering::Acquire);
+        self.tail.wrapping_sub(self.cached_head) == self.ring.slots.len()
+    }
+
+    /// Sends a value, or gives it back if the queue is full. Values sent after the consumer has
+    /// gone are dropped along with the queue.
+    pub fn try_send(&mut self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+        unsafe { (*self.ring.slot(self.tail)).as_mut_ptr().write(value) };
+        self.tail = self.tail.wrapping_add(1);
+        self.ring.tail.store(self.tail, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.ring.producer_dropped.store(true, Ordering::Release);
+    }
+}
+
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+    head: usize,
+    cached_tail: usize,
+}
+
+impl<T> Consumer<T> {
+    /// Receives the oldest value, failing with `Disconnected` once the queue is empty and the
+    /// producer has gone.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if self.head == self.cached_tail {
+            // Checked before tail, so a value sent right before the producer dropped is still seen.
+            let producer_dropped = self.ring.producer_dropped.load(Ordering::Acquire);
+            self.cached_tail = self.ring.tail.load(Ordering::Acquire);
+            if self.head == self.cached_tail {
+                return Err(if producer_dropped {
+                    TryRecvError::Disconnected
+                } else {
+                    TryRecvError::Empty
+                });
+            }
+        }
+        let value = unsafe { (*self.ring.slot(self.head)).as_ptr().read() };
+        self.head = self.head.wrapping_add(1);
+        self.ring.head.store(self.head, Ordering::Release);
+        Ok(value)
+    }
+}
+
+impl<T> Consumer<T> {
+    /// A handle that tells how many values are in the queue, from any thread.
+    pub fn gauge(&self) -> Gauge<T> {
+        Gauge {
+            ring: Arc::clone(&self.ring),
+        }
+    }
+}
+
+pub struct Gauge<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T> Gauge<T> {
+    pub fn len(&self) -> usize {
+        // Read in this order, tail is never behind head, though both may move in between.
+        let head = self.ring.head.load(Ordering::Acquire);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head).min(self.ring.slots.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The receiving end of the queue a `QueueEgressor` reads its packets from.
+pub trait QueueReceiver<T> {
+    fn try_recv(&mut self) -> Result<T, TryRecvError>;
+}
+
+impl<T> QueueReceiver<T> for Receiver<T> {
+    fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        Receiver::try_recv(self)
+    }
+}
+
+impl<T> QueueReceiver<T> for Consumer<T> {
+    fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        Consumer::try_recv(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn fills_and_drains_in_order() {
+        let (mut producer, mut consumer) = channel(3);
+
+        assert_eq!(consumer.try_recv(), Err(TryRecvError::Empty));
+        for n in 0..3 {
+            assert_eq!(producer.try_send(n), Ok(()));
+        }
+        assert!(producer.is_full());
+        assert_eq!(producer.try_send(3), Err(3));
+
+        assert_eq!(consumer.try_recv(), Ok(0));
+        assert_eq!(producer.try_send(3), Ok(()));
+        for n in 1..4 {
+            assert_eq!(consumer.try_recv(), Ok(n));
+        }
+        assert_eq!(consumer.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn gauge_counts_queued_values() {
+        let (mut producer, mut consumer) = channel(3);
+        let gauge = consumer.gauge();
+
+        assert!(gauge.is_empty());
+        producer.try_send(1).unwrap();
+        producer.try_send(2).unwrap();
+        assert_eq!(gauge.len(), 2);
+        consumer.try_recv().unwrap();
+        assert_eq!(gauge.len(), 1);
+    }
+
+    #[test]
+    fn disconnects_once_drained() {
+        let (mut producer, mut consumer) = channel(2);
+        producer.try_send(1).unwrap();
+        drop(producer);
+
+        assert_eq!(consumer.try_recv(), Ok(1));
+        assert_eq!(consumer.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn drops_values_left_in_queue() {
+        let value = Arc::new(());
+        let (mut producer, consumer) = channel(4);
+        producer.try_send(Arc::clone(&value)).unwrap();
+        producer.try_send(Arc::clone(&value)).unwrap();
+        assert_eq!(Arc::strong_count(&value), 3);
+
+        drop(consumer);
+        drop(producer);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn carries_values_across_threads() {
+        let (mut producer, mut consumer) = channel(8);
+        let sender = thread::spawn(move || {
+            for mut n in 0..100_000 {
+                while let Err(value) = producer.try_send(n) {
+                    n = value;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+        loop {
+            match consumer.try_recv() {
+                Ok(n) => {
+                    assert_eq!(n, expected);
+                    expected += 1;
+                }
+                Err(TryRecvError::Empty) => thread::yield_now(),
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        sender.join().unwrap();
+        assert_eq!(expected, 100_000);
+    }
+}
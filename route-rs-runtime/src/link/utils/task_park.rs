@@ -9,6 +9,7 @@
 //! `task_park` also contains logic to prevent one side from sleeping when the other side will be unable to awaken them,
 //! in order to prevent deadlocks.
 
+use crate::link::utils::watchdog;
 use crossbeam::atomic::AtomicCell;
 use futures::task;
 use std::sync::Arc;
@@ -54,7 +55,16 @@ pub enum TaskParkState {
 /// Swaps in the provided TaskParkState. wakes any task that it finds currently in the `task_park`
 /// Returns `true` if it was able to successfully park the provided task, ie the `task_park` is not dead.
 fn swap_and_wake(task_park: &Arc<AtomicCell<TaskParkState>>, swap: TaskParkState) -> bool {
-    match task_park.swap(swap) {
+    // Parks are noted before they happen and wakes after, so a race can only hide a stuck task
+    // from the watchdog, never report one that isn't.
+    let parking = match swap {
+        TaskParkState::Parked(_) | TaskParkState::IndirectParked(_) => true,
+        TaskParkState::Dead | TaskParkState::Empty => false,
+    };
+    if parking {
+        watchdog::observe(task_park, true);
+    }
+    let parked = match task_park.swap(swap) {
         TaskParkState::Dead => {
             task_park.store(TaskParkState::Dead);
             false
@@ -70,7 +80,11 @@ fn swap_and_wake(task_park: &Arc<AtomicCell<TaskParkState>>, swap: TaskParkState
             }
             true
         }
+    };
+    if !parking || !parked {
+        watchdog::observe(task_park, false);
     }
+    parked
 }
 
 /// Notifies a task if it resides in the `task_park`
@@ -106,3 +120,27 @@ pub fn indirect_park_and_wake(
 pub fn die_and_wake(task_park: &Arc<AtomicCell<TaskParkState>>) {
     swap_and_wake(task_park, TaskParkState::Dead);
 }
+
+/// Counts a packet handed to the task in the `task_park`, and notifies it once `batch_size`
+/// packets have been handed over since it was last notified, rather than for every packet.
+/// Use `flush_and_wake` before sleeping, so the last packets of a batch are not left unannounced.
+pub fn batch_and_wake(
+    task_park: &Arc<AtomicCell<TaskParkState>>,
+    unwoken: &mut usize,
+    batch_size: usize,
+) {
+    *unwoken += 1;
+    if *unwoken >= batch_size {
+        *unwoken = 0;
+        unpark_and_wake(task_park);
+    }
+}
+
+/// Notifies a task if it resides in the `task_park` and packets have been handed to it by
+/// `batch_and_wake` since it was last notified.
+pub fn flush_and_wake(task_park: &Arc<AtomicCell<TaskParkState>>, unwoken: &mut usize) {
+    if *unwoken > 0 {
+        *unwoken = 0;
+        unpark_and_wake(task_park);
+    }
+}
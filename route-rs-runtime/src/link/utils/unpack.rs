@@ -0,0 +1,129 @@
+/// Binds the runnables and egressors of a built `Link` to names, in place of the `remove(0)`s of
+/// hand-wired routers, checking that the link has the egressors the names expect:
+///
+/// ```
+/// use route_rs_runtime::classifier::even_link;
+/// use route_rs_runtime::unpack_link;
+/// use route_rs_runtime::utils::test::packet_generators::immediate_stream;
+///
+/// let even = even_link(immediate_stream(vec![0, 1, 2]));
+/// unpack_link!(even => runnables, [evens, odds]);
+/// ```
+///
+/// An egressor bound to `_` is skipped, its packets discarded by a `Drain` added to the
+/// runnables, so the link doesn't stall on it. A last `rest @ ..` binds the egressors after those
+/// named as a `Vec`. `mut` may come before any name. The macro panics, naming the link, if the
+/// link has fewer egressors than are named, or more, without a `rest @ ..` to take them.
+#[macro_export]
+macro_rules! unpack_link {
+    ($link:ident => $runnables:pat, [$($egressors:tt)*]) => {
+        // Runnables are only added to for skipped egressors.
+        #[allow(unused_mut)]
+        let (mut runnables, egressors) = $link;
+        let (named, rest) = $crate::unpack_link!(@count $($egressors)*);
+        if egressors.len() < named || (!rest && egressors.len() > named) {
+            panic!(
+                "link {} has {} egressors, but {}{} were unpacked",
+                stringify!($link),
+                egressors.len(),
+                named,
+                if rest { " or more" } else { "" }
+            );
+        }
+        #[allow(unused_mut)]
+        let mut egressors = egressors.into_iter();
+        $crate::unpack_link!(@bind runnables, egressors, $($egressors)*);
+        let $runnables = runnables;
+    };
+
+    (@count) => {
+        (0usize, false)
+    };
+    (@count $(mut)? $rest:ident @ .. $(,)?) => {
+        (0usize, true)
+    };
+    (@count _ $(, $($tail:tt)*)?) => {{
+        let (named, rest) = $crate::unpack_link!(@count $($($tail)*)?);
+        (named + 1, rest)
+    }};
+    (@count $(mut)? $name:ident $(, $($tail:tt)*)?) => {{
+        let (named, rest) = $crate::unpack_link!(@count $($($tail)*)?);
+        (named + 1, rest)
+    }};
+
+    (@bind $runnables:ident, $egressors:ident,) => {};
+    (@bind $runnables:ident, $egressors:ident, $rest:ident @ .. $(,)?) => {
+        let $rest: Vec<_> = $egressors.collect();
+    };
+    (@bind $runnables:ident, $egressors:ident, mut $rest:ident @ .. $(,)?) => {
+        let mut $rest: Vec<_> = $egressors.collect();
+    };
+    (@bind $runnables:ident, $egressors:ident, _ $(, $($tail:tt)*)?) => {
+        $runnables.push(Box::new($crate::link::utils::drain::Drain::new(
+            $egressors.next().unwrap(),
+        )));
+        $crate::unpack_link!(@bind $runnables, $egressors, $($($tail)*)?);
+    };
+    (@bind $runnables:ident, $egressors:ident, mut $name:ident $(, $($tail:tt)*)?) => {
+        let mut $name = $egressors.next().unwrap();
+        $crate::unpack_link!(@bind $runnables, $egressors, $($($tail)*)?);
+    };
+    (@bind $runnables:ident, $egressors:ident, $name:ident $(, $($tail:tt)*)?) => {
+        let $name = $egressors.next().unwrap();
+        $crate::unpack_link!(@bind $runnables, $egressors, $($($tail)*)?);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::classifier::{even_link, fizz_buzz_link};
+    use crate::link::primitive::JoinLink;
+    use crate::link::{Link, LinkBuilder};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    fn binds_named_egressors() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let even = even_link(immediate_stream(0..6));
+            unpack_link!(even => runnables, [evens, odds]);
+            let link: Link<i32> = (runnables, vec![odds, evens]);
+
+            run_link(link).await
+        });
+        assert_eq!(results, vec![vec![1, 3, 5], vec![0, 2, 4]]);
+    }
+
+    #[test]
+    fn skips_and_collects_the_rest() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let fizz_buzz = fizz_buzz_link(immediate_stream(1..16));
+            unpack_link!(fizz_buzz => mut runnables, [_, fizz, rest @ ..]);
+            assert_eq!(rest.len(), 2);
+            let (mut join_runnables, joined) = JoinLink::new().ingressors(rest).build_link();
+            runnables.append(&mut join_runnables);
+
+            run_link((runnables, vec![fizz, joined.into_iter().next().unwrap()])).await
+        });
+        assert_eq!(results[0], vec![3, 6, 9, 12]);
+        let mut rest = results[1].clone();
+        rest.sort_unstable();
+        assert_eq!(rest, vec![1, 2, 4, 5, 7, 8, 10, 11, 13, 14]);
+    }
+
+    #[test]
+    #[should_panic(expected = "link even has 2 egressors, but 3 were unpacked")]
+    fn panics_on_too_few_egressors() {
+        let even = even_link(immediate_stream(0..6));
+        unpack_link!(even => _runnables, [_evens, _odds, _more]);
+    }
+
+    #[test]
+    #[should_panic(expected = "link fizz_buzz has 4 egressors, but 2 were unpacked")]
+    fn panics_on_too_many_egressors() {
+        let fizz_buzz = fizz_buzz_link(immediate_stream(0..6));
+        unpack_link!(fizz_buzz => _runnables, [_fizz_buzz, _fizz]);
+    }
+}
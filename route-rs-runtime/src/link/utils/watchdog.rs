@@ -0,0 +1,213 @@
+//! # What is it for?
+//!
+//! Finding lost wakeups. A task that parks in a `task_park` relies on the task at the other end of
+//! its queue to wake it, and if that wake is ever missed the link stalls without a trace: nothing
+//! panics, both tasks are simply asleep. In debug builds the primitive links register each of their
+//! task parks here, along with the queue it guards. Once the watchdog is running, it notes when
+//! each task park is parked in and woken from, and logs those that have stayed parked for too long
+//! while their queue holds packets but isn't full, as neither side should be asleep then.
+//!
+//! An ingressor still parked on a queue its egressor has only begun to drain, because whatever
+//! reads the egressor has stopped, looks the same, so a report is a lead rather than a verdict.
+
+use crate::link::utils::task_park::TaskParkState;
+use crossbeam::atomic::AtomicCell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+struct WatchedPark {
+    link: String,
+    task_park: Weak<AtomicCell<TaskParkState>>,
+    capacity: usize,
+    queue_len: Box<dyn Fn() -> usize + Send>,
+    parked_since: Option<Instant>,
+}
+
+/// A task park that has been parked in for longer than the watchdog allows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StuckPark {
+    pub link: String,
+    pub parked_for: Duration,
+    pub queue_len: usize,
+    pub capacity: usize,
+}
+
+impl fmt::Display for StuckPark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} has been parked for {:?} with {} of {} packets queued, likely a lost wakeup",
+            self.link, self.parked_for, self.queue_len, self.capacity
+        )
+    }
+}
+
+/// The task parks of every link in the process, with the queues they guard.
+pub struct Watchdog {
+    parks: Mutex<Vec<WatchedPark>>,
+    enabled: AtomicBool,
+    next_id: AtomicUsize,
+}
+
+static GLOBAL_WATCHDOG: Watchdog = Watchdog::new();
+
+impl Watchdog {
+    const fn new() -> Self {
+        Watchdog {
+            parks: Mutex::new(Vec::new()),
+            enabled: AtomicBool::new(false),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    /// The watchdog links register with.
+    pub fn global() -> &'static Watchdog {
+        &GLOBAL_WATCHDOG
+    }
+
+    /// A number to tell apart links of the same kind in reports.
+    pub fn next_id(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Watches a task park, guarding a queue of `capacity` packets that currently holds
+    /// `queue_len()`. It is forgotten once both sides of the queue have dropped the task park.
+    pub fn register<F>(
+        &self,
+        link: String,
+        task_park: &Arc<AtomicCell<TaskParkState>>,
+        capacity: usize,
+        queue_len: F,
+    ) where
+        F: Fn() -> usize + Send + 'static,
+    {
+        let mut parks = self.parks.lock().unwrap();
+        parks.retain(|park| park.task_park.strong_count() > 0);
+        parks.push(WatchedPark {
+            link,
+            task_park: Arc::downgrade(task_park),
+            capacity,
+            queue_len: Box::new(queue_len),
+            parked_since: None,
+        });
+    }
+
+    /// Starts noting when task parks are parked in and woken from. Until then the task parks skip
+    /// the bookkeeping, and no task park is reported as stuck.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Task parks that have been parked in for at least `threshold` while their queue holds
+    /// packets but isn't full.
+    pub fn stuck(&self, threshold: Duration) -> Vec<StuckPark> {
+        let mut parks = self.parks.lock().unwrap();
+        parks.retain(|park| park.task_park.strong_count() > 0);
+        parks
+            .iter()
+            .filter_map(|park| {
+                let parked_for = park.parked_since?.elapsed();
+                let queue_len = (park.queue_len)();
+                if parked_for >= threshold && queue_len > 0 && queue_len < park.capacity {
+                    Some(StuckPark {
+                        link: park.link.clone(),
+                        parked_for,
+                        queue_len,
+                        capacity: park.capacity,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Enables the watchdog, then checks every `period` for task parks stuck for at least
+    /// `threshold`, logging each as a `tracing` warning with the target `route_rs::watchdog`, for
+    /// the embedder's subscriber to route or silence. Runs for as long as it is polled, so spawn
+    /// it alongside the links of a debug build.
+    pub async fn run(&'static self, period: Duration, threshold: Duration) {
+        self.enable();
+        loop {
+            tokio::time::delay_for(period).await;
+            for stuck in self.stuck(threshold) {
+                tracing::warn!(target: "route_rs::watchdog", link = %stuck.link, "{}", stuck);
+            }
+        }
+    }
+}
+
+/// Called by `task_park` as a task parks in, or is woken from, a task park.
+pub(crate) fn observe(task_park: &Arc<AtomicCell<TaskParkState>>, parked: bool) {
+    let watchdog = Watchdog::global();
+    if !watchdog.enabled.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut parks = watchdog.parks.lock().unwrap();
+    if let Some(park) = parks
+        .iter_mut()
+        .find(|park| park.task_park.as_ptr() == Arc::as_ptr(task_park))
+    {
+        park.parked_since = if parked { Some(Instant::now()) } else { None };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::utils::task_park::{park_and_wake, unpark_and_wake};
+    use futures::task::noop_waker;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn reports_parks_left_with_queued_packets() {
+        let watchdog = Watchdog::global();
+        watchdog.enable();
+        let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+        let queue_len = Arc::new(AtomicUsize::new(3));
+        let link = format!("TestLink#{}", watchdog.next_id());
+        let len = Arc::clone(&queue_len);
+        watchdog.register(link.clone(), &task_park, 10, move || {
+            len.load(Ordering::Relaxed)
+        });
+        let stuck = || -> Vec<StuckPark> {
+            watchdog
+                .stuck(Duration::from_secs(0))
+                .into_iter()
+                .filter(|stuck| stuck.link == link)
+                .collect()
+        };
+
+        assert!(stuck().is_empty());
+
+        park_and_wake(&task_park, noop_waker());
+        let reports = stuck();
+        assert_eq!(reports.len(), 1);
+        assert_eq!((reports[0].queue_len, reports[0].capacity), (3, 10));
+
+        // Parked on a full or empty queue is how the links are meant to wait.
+        queue_len.store(10, Ordering::Relaxed);
+        assert!(stuck().is_empty());
+        queue_len.store(0, Ordering::Relaxed);
+        assert!(stuck().is_empty());
+
+        queue_len.store(3, Ordering::Relaxed);
+        unpark_and_wake(&task_park);
+        assert!(stuck().is_empty());
+    }
+
+    #[test]
+    fn forgets_dropped_parks() {
+        let watchdog = Watchdog::global();
+        let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+        let link = format!("TestLink#{}", watchdog.next_id());
+        watchdog.register(link.clone(), &task_park, 10, || 1);
+        drop(task_park);
+
+        watchdog.stuck(Duration::from_secs(0));
+        let parks = watchdog.parks.lock().unwrap();
+        assert!(parks.iter().all(|park| park.link != link));
+    }
+}
@@ -0,0 +1,366 @@
+use crate::link::{Link, LinkBuildError, LinkBuilder, PacketStream};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::cell::RefCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A queue of a link, with a way to tell how many packets it holds from any thread.
+struct QueueGauge {
+    capacity: usize,
+    len: Box<dyn Fn() -> usize + Send + Sync>,
+}
+
+thread_local! {
+    /// The queues registered by the links being built inside each `NamedLink` on this thread, the
+    /// innermost last, so a named composite's queues are its own and not those of the link around
+    /// it.
+    static BUILDING: RefCell<Vec<Vec<QueueGauge>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Lets the `NamedLink` being built report the depth of a queue built inside it, of `capacity`
+/// packets and currently holding `len()`. Links call this as they build their queues, and queues
+/// of links built outside any `NamedLink` are not tracked.
+pub fn register_queue<F>(capacity: usize, len: F)
+where
+    F: Fn() -> usize + Send + Sync + 'static,
+{
+    BUILDING.with(|building| {
+        if let Some(queues) = building.borrow_mut().last_mut() {
+            queues.push(QueueGauge {
+                capacity,
+                len: Box::new(len),
+            });
+        }
+    });
+}
+
+/// Counters of a link named with `NameLink::name`, and the depths of its queues.
+pub struct LinkMetrics {
+    name: String,
+    sent: Vec<AtomicU64>,
+    queues: Vec<QueueGauge>,
+}
+
+impl fmt::Debug for LinkMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LinkMetrics")
+            .field("name", &self.name)
+            .field("sent", &self.sent)
+            .field("queue_depths", &self.queue_depths())
+            .finish()
+    }
+}
+
+impl LinkMetrics {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn num_egressors(&self) -> usize {
+        self.sent.len()
+    }
+
+    /// Packets that have left through one egressor of the link.
+    pub fn egress_packets(&self, egressor: usize) -> u64 {
+        self.sent[egressor].load(Ordering::Relaxed)
+    }
+
+    /// Packets that have left through every egressor of the link. Links without egressors, such as
+    /// an `OutputChannelLink`, never count any.
+    pub fn packets(&self) -> u64 {
+        self.sent.iter().map(|s| s.load(Ordering::Relaxed)).sum()
+    }
+
+    /// The packets held in each queue of the link, and how many it can hold, in the order they
+    /// were built.
+    pub fn queue_depths(&self) -> Vec<(usize, usize)> {
+        self.queues
+            .iter()
+            .map(|queue| ((queue.len)(), queue.capacity))
+            .collect()
+    }
+}
+
+/// Metrics of every named link in the process, for a router to export however it likes.
+#[derive(Default)]
+pub struct Registry {
+    links: Mutex<Vec<Arc<LinkMetrics>>>,
+    counters: Mutex<Vec<(String, Arc<AtomicU64>)>>,
+}
+
+static GLOBAL_REGISTRY: Registry = Registry::new();
+
+impl Registry {
+    pub const fn new() -> Self {
+        Registry {
+            links: Mutex::new(Vec::new()),
+            counters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The registry named links register with.
+    pub fn global() -> &'static Registry {
+        &GLOBAL_REGISTRY
+    }
+
+    /// Registers fresh counters for a link, replacing those of an earlier link of the same name,
+    /// as when a pipeline is run again.
+    pub fn register(&self, name: &str, num_egressors: usize) -> Arc<LinkMetrics> {
+        self.register_with_queues(name, num_egressors, vec![])
+    }
+
+    fn register_with_queues(
+        &self,
+        name: &str,
+        num_egressors: usize,
+        queues: Vec<QueueGauge>,
+    ) -> Arc<LinkMetrics> {
+        let metrics = Arc::new(LinkMetrics {
+            name: String::from(name),
+            sent: (0..num_egressors).map(|_| AtomicU64::new(0)).collect(),
+            queues,
+        });
+        let mut links = self.links.lock().unwrap();
+        links.retain(|l| l.name != name);
+        links.push(Arc::clone(&metrics));
+        metrics
+    }
+
+    /// Metrics of the registered links, in the order they were registered.
+    pub fn links(&self) -> Vec<Arc<LinkMetrics>> {
+        self.links.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<LinkMetrics>> {
+        self.links
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|l| l.name == name)
+            .cloned()
+    }
+
+    /// A counter of anything a processor cares to count, such as packets it rejected. Every caller
+    /// asking for the same name shares the counter, which is created at zero by the first.
+    pub fn counter(&self, name: &str) -> Arc<AtomicU64> {
+        let mut counters = self.counters.lock().unwrap();
+        if let Some((_, counter)) = counters.iter().find(|(n, _)| n == name) {
+            return Arc::clone(counter);
+        }
+        let counter = Arc::new(AtomicU64::new(0));
+        counters.push((String::from(name), Arc::clone(&counter)));
+        counter
+    }
+
+    /// Names and values of the counters, in the order they were created.
+    pub fn counters(&self) -> Vec<(String, u64)> {
+        self.counters
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, counter)| (name.clone(), counter.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+/// Gives every `LinkBuilder` a `name`, which registers the link with the global `Registry` when
+/// it is built, counts the packets leaving each of its egressors, and tracks the depths of the
+/// queues built with it. Name a link after its other
+/// setters, right before `build_link`:
+///
+/// ```ignore
+/// ProcessLink::new()
+///     .ingressor(stream)
+///     .processor(Identity::new())
+///     .name("identity")
+///     .build_link()
+/// ```
+pub trait NameLink<Input, Output>: LinkBuilder<Input, Output> + Sized {
+    fn name(self, name: &str) -> NamedLink<Self, Input, Output> {
+        NamedLink {
+            builder: self,
+            name: String::from(name),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Input, Output, B: LinkBuilder<Input, Output>> NameLink<Input, Output> for B {}
+
+pub struct NamedLink<B, Input, Output> {
+    builder: B,
+    name: String,
+    phantom: PhantomData<(Input, Output)>,
+}
+
+impl<B, Input, Output> LinkBuilder<Input, Output> for NamedLink<B, Input, Output>
+where
+    B: LinkBuilder<Input, Output>,
+    Output: Send + 'static,
+{
+    fn try_ingressors(self, in_streams: Vec<PacketStream<Input>>) -> Result<Self, LinkBuildError> {
+        Ok(NamedLink {
+            builder: self.builder.try_ingressors(in_streams)?,
+            name: self.name,
+            phantom: PhantomData,
+        })
+    }
+
+    fn try_ingressor(self, in_stream: PacketStream<Input>) -> Result<Self, LinkBuildError> {
+        Ok(NamedLink {
+            builder: self.builder.try_ingressor(in_stream)?,
+            name: self.name,
+            phantom: PhantomData,
+        })
+    }
+
+    fn try_build_link(self) -> Result<Link<Output>, LinkBuildError> {
+        BUILDING.with(|building| building.borrow_mut().push(vec![]));
+        let link = self.builder.try_build_link();
+        let queues = BUILDING.with(|building| building.borrow_mut().pop().unwrap());
+        let (runnables, egressors) = link?;
+        let metrics = Registry::global().register_with_queues(&self.name, egressors.len(), queues);
+        let egressors = egressors
+            .into_iter()
+            .enumerate()
+            .map(|(egressor, stream)| {
+                Box::new(CountingStream {
+                    stream,
+                    metrics: Arc::clone(&metrics),
+                    egressor,
+                }) as PacketStream<Output>
+            })
+            .collect();
+        Ok((runnables, egressors))
+    }
+}
+
+/// Passes packets through from an egressor, counting each in the `LinkMetrics` of its link.
+struct CountingStream<Packet> {
+    stream: PacketStream<Packet>,
+    metrics: Arc<LinkMetrics>,
+    egressor: usize,
+}
+
+impl<Packet> Stream for CountingStream<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let packet = ready!(Pin::new(&mut self.stream).poll_next(cx));
+        if packet.is_some() {
+            self.metrics.sent[self.egressor].fetch_add(1, Ordering::Relaxed);
+        }
+        Poll::Ready(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::Even;
+    use crate::link::primitive::{ClassifyLink, ProcessLink, QueueLink};
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::Identity;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    fn counts_egress_packets() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ProcessLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2, 3]))
+                .processor(Identity::new())
+                .name("metrics-identity")
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 1, 2, 3]);
+
+        let metrics = Registry::global().get("metrics-identity").unwrap();
+        assert_eq!(metrics.num_egressors(), 1);
+        assert_eq!(metrics.packets(), 4);
+    }
+
+    #[test]
+    fn counts_each_egressor() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ClassifyLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2, 3, 4]))
+                .classifier(Even::new())
+                .dispatcher(Box::new(|even| if even { 0 } else { 1 }))
+                .num_egressors(2)
+                .name("metrics-even")
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 2, 4]);
+
+        let metrics = Registry::global().get("metrics-even").unwrap();
+        assert_eq!(metrics.egress_packets(0), 3);
+        assert_eq!(metrics.egress_packets(1), 2);
+    }
+
+    #[test]
+    fn tracks_queues_built_with_a_named_link() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let inner = QueueLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2, 3, 4]))
+                .processor(Identity::new())
+                .queue_capacity(4)
+                .name("metrics-inner-queue")
+                .build_link();
+            let link = ClassifyLink::new()
+                .ingressor(inner.1.into_iter().next().unwrap())
+                .classifier(Even::new())
+                .dispatcher(Box::new(|even| if even { 0 } else { 1 }))
+                .num_egressors(2)
+                .name("metrics-classifier-queues")
+                .build_link();
+            let link = (inner.0.into_iter().chain(link.0).collect(), link.1);
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 2, 4]);
+
+        let inner = Registry::global().get("metrics-inner-queue").unwrap();
+        assert_eq!(inner.queue_depths(), vec![(0, 4)]);
+        let outer = Registry::global().get("metrics-classifier-queues").unwrap();
+        assert_eq!(outer.queue_depths(), vec![(0, 10), (0, 10)]);
+
+        register_queue(1, || 1);
+        assert_eq!(outer.queue_depths().len(), 2);
+    }
+
+    #[test]
+    fn reregistering_replaces_metrics() {
+        let registry = Registry::new();
+        registry.register("link", 1).sent[0].fetch_add(1, Ordering::Relaxed);
+        registry.register("link", 2);
+
+        assert_eq!(registry.links().len(), 1);
+        assert_eq!(registry.get("link").unwrap().num_egressors(), 2);
+        assert_eq!(registry.get("link").unwrap().packets(), 0);
+    }
+
+    #[test]
+    fn counters_are_shared_by_name() {
+        let registry = Registry::new();
+        registry.counter("rejected").fetch_add(2, Ordering::Relaxed);
+        registry.counter("accepted");
+        registry.counter("rejected").fetch_add(1, Ordering::Relaxed);
+
+        assert_eq!(
+            registry.counters(),
+            vec![(String::from("rejected"), 3), (String::from("accepted"), 0)]
+        );
+    }
+}
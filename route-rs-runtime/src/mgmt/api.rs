@@ -0,0 +1,182 @@
+use crate::metrics::Registry;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+type TableReader = Box<dyn Fn() -> Value + Send + Sync>;
+type ControlHandler = Box<dyn Fn(Value) -> Result<Value, String> + Send + Sync>;
+
+/// The state of a running router that the management API exposes. Tables are read-only views of
+/// state the links share, and controls are handlers that change it, such as adding a firewall rule
+/// or a static route. Both are registered by name, and read or called as requests arrive, so they
+/// should take their locks briefly.
+///
+/// Clones share their tables and controls, so the API can be handed to the server while the
+/// router keeps registering.
+#[derive(Clone)]
+pub struct ManagementApi {
+    registry: &'static Registry,
+    tables: Arc<RwLock<BTreeMap<String, TableReader>>>,
+    controls: Arc<RwLock<BTreeMap<String, ControlHandler>>>,
+}
+
+impl Default for ManagementApi {
+    fn default() -> Self {
+        ManagementApi::new()
+    }
+}
+
+impl ManagementApi {
+    pub fn new() -> Self {
+        ManagementApi {
+            registry: Registry::global(),
+            tables: Arc::new(RwLock::new(BTreeMap::new())),
+            controls: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+
+    /// Lists the links of another metrics registry rather than the global one.
+    pub fn registry(self, registry: &'static Registry) -> Self {
+        ManagementApi {
+            registry,
+            tables: self.tables,
+            controls: self.controls,
+        }
+    }
+
+    /// Registers a table under a name, replacing any registered before under the same. The reader
+    /// is called for every request of the table.
+    pub fn register_table<F>(&self, name: &str, reader: F)
+    where
+        F: Fn() -> Value + Send + Sync + 'static,
+    {
+        self.tables
+            .write()
+            .unwrap()
+            .insert(String::from(name), Box::new(reader));
+    }
+
+    /// Registers a control under a name, replacing any registered before under the same. The
+    /// handler is given the body of the request, and its error is returned to the client.
+    pub fn register_control<F>(&self, name: &str, handler: F)
+    where
+        F: Fn(Value) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.controls
+            .write()
+            .unwrap()
+            .insert(String::from(name), Box::new(handler));
+    }
+
+    /// Counters of every named link, in the order they were registered, with the packets its
+    /// event sink has dropped, if it has one of the same name, and the depths of its queues.
+    pub fn links(&self) -> Value {
+        let counters = self.registry.counters();
+        Value::Array(
+            self.registry
+                .links()
+                .iter()
+                .map(|link| {
+                    let dropped = format!("{}.dropped", link.name());
+                    let (depths, capacities): (Vec<usize>, Vec<usize>) =
+                        link.queue_depths().into_iter().unzip();
+                    json!({
+                        "name": link.name(),
+                        "packets": link.packets(),
+                        "egress_packets": (0..link.num_egressors())
+                            .map(|egressor| link.egress_packets(egressor))
+                            .collect::<Vec<_>>(),
+                        "dropped": counters
+                            .iter()
+                            .find(|(name, _)| *name == dropped)
+                            .map_or(0, |(_, count)| *count),
+                        "queue_depths": depths,
+                        "queue_capacities": capacities,
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    pub fn table_names(&self) -> Vec<String> {
+        self.tables.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn table(&self, name: &str) -> Option<Value> {
+        self.tables.read().unwrap().get(name).map(|reader| reader())
+    }
+
+    /// Calls a control, or returns `None` if nothing is registered under its name.
+    pub fn control(&self, name: &str, request: Value) -> Option<Result<Value, String>> {
+        self.controls
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|handler| handler(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    static REGISTRY: Registry = Registry::new();
+
+    #[test]
+    fn lists_named_links() {
+        let api = ManagementApi::new().registry(&REGISTRY);
+        assert_eq!(api.links(), json!([]));
+
+        REGISTRY.register("classifier", 2);
+        REGISTRY
+            .counter("classifier.dropped")
+            .fetch_add(3, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(
+            api.links(),
+            json!([{
+                "name": "classifier",
+                "packets": 0,
+                "egress_packets": [0, 0],
+                "dropped": 3,
+                "queue_depths": [],
+                "queue_capacities": [],
+            }])
+        );
+    }
+
+    #[test]
+    fn reads_tables_and_calls_controls() {
+        let routes = Arc::new(Mutex::new(vec![String::from("10.0.0.0/8")]));
+        let api = ManagementApi::new();
+
+        let table_routes = Arc::clone(&routes);
+        api.register_table("routes", move || json!(*table_routes.lock().unwrap()));
+        let control_routes = Arc::clone(&routes);
+        api.register_control("add_route", move |request| match request.as_str() {
+            Some(route) => {
+                control_routes.lock().unwrap().push(String::from(route));
+                Ok(json!(control_routes.lock().unwrap().len()))
+            }
+            None => Err(String::from("Expected a route")),
+        });
+
+        assert_eq!(api.table_names(), vec!["routes"]);
+        assert_eq!(api.table("routes"), Some(json!(["10.0.0.0/8"])));
+        assert_eq!(api.table("arp"), None);
+
+        assert_eq!(
+            api.control("add_route", json!("192.168.0.0/16")),
+            Some(Ok(json!(2)))
+        );
+        assert_eq!(
+            api.control("add_route", json!(1)),
+            Some(Err(String::from("Expected a route")))
+        );
+        assert_eq!(api.control("del_route", json!(null)), None);
+        assert_eq!(
+            api.table("routes"),
+            Some(json!(["10.0.0.0/8", "192.168.0.0/16"]))
+        );
+    }
+}
@@ -0,0 +1,23 @@
+//! # What is it for?
+//!
+//! The management API lets an operator look at and change a router while it runs. A router opts
+//! in by building a `ManagementApi`, registering the state its links share, such as address or
+//! connection tables, and serving it on a local address. Named links are listed from the metrics
+//! `Registry` without any registration.
+//!
+//! The API is JSON over HTTP:
+//!
+//! - `GET /links` lists the counters of every named link, its drops and the depths of its queues.
+//! - `GET /tables` lists the names of the registered tables, and `GET /tables/<name>` reads one.
+//! - `POST /controls/<name>` calls a registered control with the JSON request body, and returns
+//!   its result.
+//!
+//! Requests must name the server by `localhost`, a loopback address or the address they are sent
+//! to in their `Host`, and `POST`s must be sent as `application/json`, so that web pages open in a
+//! browser on the router's host can't use the API, see `ManagementServer`.
+
+mod api;
+pub use self::api::*;
+
+mod server;
+pub use self::server::*;
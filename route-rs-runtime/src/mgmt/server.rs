@@ -0,0 +1,422 @@
+use crate::mgmt::ManagementApi;
+use serde_json::{json, Value};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+/// Requests larger than this are refused, the API has no use for them.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+/// A client has this long to send its whole request before the connection is answered with a 408.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves a `ManagementApi` over HTTP, one request per connection.
+///
+/// The API is meant for the host the router runs on or a management network, and has no
+/// authentication of its own, so it should be bound to a loopback or otherwise private address.
+/// Nor should a web page open in a browser there be able to use it: requests are refused with a
+/// 403 unless their `Host` is `localhost`, a loopback address, or the address they were sent to,
+/// so a name rebound to the router's address gets nowhere, and `POST`s with a 415 unless their
+/// `Content-Type` is `application/json`, which a form can't send.
+pub struct ManagementServer {
+    api: ManagementApi,
+    listener: TcpListener,
+}
+
+impl ManagementServer {
+    pub async fn bind(api: ManagementApi, addr: SocketAddr) -> io::Result<Self> {
+        Ok(ManagementServer {
+            api,
+            listener: TcpListener::bind(addr).await?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Answers requests until the runtime shuts down. Each connection is handled on a task of its
+    /// own, so a slow client does not hold up the rest.
+    pub async fn serve(mut self) {
+        loop {
+            let (stream, _) = match self.listener.accept().await {
+                Ok(connection) => connection,
+                Err(_) => continue,
+            };
+            let api = self.api.clone();
+            tokio::spawn(async move {
+                // The client may have gone away, there is no one left to tell.
+                let _ = handle_connection(api, stream).await;
+            });
+        }
+    }
+}
+
+/// A request, with the headers the server looks at.
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+async fn handle_connection(api: ManagementApi, mut stream: TcpStream) -> io::Result<()> {
+    let local = stream.local_addr()?.ip();
+    let (status, body) = match timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(request) => match request? {
+            Some(request) => match refusal(&request, local) {
+                Some(refused) => refused,
+                None => respond(&api, &request.method, &request.path, &request.body),
+            },
+            None => (400, json!({"error": "Malformed request"})),
+        },
+        Err(_) => (408, json!({"error": "Request timed out"})),
+    };
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown(std::net::Shutdown::Write)
+}
+
+/// Reads a request, or `None` if it isn't one.
+async fn read_request(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+    let mut request = vec![];
+    let mut buf = [0; 4096];
+    let header_len = loop {
+        if let Some(end) = find_header_end(&request) {
+            break end;
+        }
+        let len = stream.read(&mut buf).await?;
+        if len == 0 || request.len() + len > MAX_REQUEST_LEN {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buf[..len]);
+    };
+
+    let header = String::from_utf8_lossy(&request[..header_len]).into_owned();
+    let mut lines = header.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let (method, path) = match (request_line.next(), request_line.next()) {
+        (Some(method), Some(path)) => (String::from(method), String::from(path)),
+        _ => return Ok(None),
+    };
+    let headers: Vec<(&str, &str)> = lines
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => Some((name.trim(), value.trim())),
+                _ => None,
+            }
+        })
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| String::from(*value))
+    };
+    let content_len = header("content-length")
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    match (header_len + 4).checked_add(content_len) {
+        Some(request_len) if request_len <= MAX_REQUEST_LEN => {}
+        _ => return Ok(None),
+    }
+
+    let mut body = request.split_off(header_len + 4);
+    while body.len() < content_len {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            return Ok(None);
+        }
+        body.extend_from_slice(&buf[..len]);
+    }
+    body.truncate(content_len);
+    Ok(Some(Request {
+        method,
+        path,
+        host: header("host"),
+        content_type: header("content-type"),
+        body,
+    }))
+}
+
+/// The response refusing a request that may come from a web page rather than an operator, or
+/// none if it may be answered. `local` is the address the request was sent to.
+fn refusal(request: &Request, local: IpAddr) -> Option<(u16, Value)> {
+    let host = request.host.as_deref().unwrap_or("");
+    if !host_allowed(host, local) {
+        return Some((
+            403,
+            json!({ "error": format!("Host {:?} is not allowed", host) }),
+        ));
+    }
+    let media_type = request
+        .content_type
+        .as_deref()
+        .and_then(|content_type| content_type.split(';').next())
+        .map(str::trim);
+    match media_type {
+        _ if request.method != "POST" => None,
+        Some(media_type) if media_type.eq_ignore_ascii_case("application/json") => None,
+        _ => Some((
+            415,
+            json!({"error": "POST requests must be sent as application/json"}),
+        )),
+    }
+}
+
+/// Whether a `Host` header, with or without a port, names the server by an address no one else
+/// can put a name to.
+fn host_allowed(host: &str, local: IpAddr) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or(""),
+        None if host.matches(':').count() == 1 => host.split(':').next().unwrap_or(""),
+        None => host,
+    };
+    if name.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    match name.parse::<IpAddr>() {
+        Ok(addr) => addr.is_loopback() || addr == local,
+        Err(_) => false,
+    }
+}
+
+fn find_header_end(request: &[u8]) -> Option<usize> {
+    request.windows(4).position(|window| window == b"\r\n\r\n")
+}
+
+fn respond(api: &ManagementApi, method: &str, path: &str, body: &[u8]) -> (u16, Value) {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["links"]) => (200, api.links()),
+        ("GET", ["tables"]) => (200, json!(api.table_names())),
+        ("GET", ["tables", name]) => match api.table(name) {
+            Some(table) => (200, table),
+            None => not_found(path),
+        },
+        ("POST", ["controls", name]) => {
+            let request = if body.is_empty() {
+                Value::Null
+            } else {
+                match serde_json::from_slice(body) {
+                    Ok(request) => request,
+                    Err(err) => return (400, json!({ "error": err.to_string() })),
+                }
+            };
+            match api.control(name, request) {
+                Some(Ok(response)) => (200, response),
+                Some(Err(err)) => (400, json!({ "error": err })),
+                None => not_found(path),
+            }
+        }
+        (_, ["links"]) | (_, ["tables"]) | (_, ["tables", _]) | (_, ["controls", _]) => (
+            405,
+            json!({ "error": format!("{} is not allowed on {}", method, path) }),
+        ),
+        _ => not_found(path),
+    }
+}
+
+fn not_found(path: &str) -> (u16, Value) {
+    (404, json!({ "error": format!("Nothing is at {}", path) }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        415 => "Unsupported Media Type",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::initialize_runtime;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    async fn request(addr: SocketAddr, request: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn serves_tables_and_controls() {
+        let api = ManagementApi::new();
+        api.register_table(
+            "arp",
+            || json!([{"ip": "10.0.0.2", "mac": "de:ad:be:ef:00:01"}]),
+        );
+        api.register_control("echo", Ok);
+
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let server = ManagementServer::bind(api, "127.0.0.1:0".parse().unwrap())
+                .await
+                .unwrap();
+            let addr = server.local_addr().unwrap();
+            tokio::spawn(server.serve());
+
+            assert_eq!(
+                request(addr, "GET /tables HTTP/1.1\r\nHost: localhost\r\n\r\n").await,
+                (200, json!(["arp"]))
+            );
+            assert_eq!(
+                request(addr, "GET /tables/arp HTTP/1.1\r\nHost: localhost\r\n\r\n").await,
+                (200, json!([{"ip": "10.0.0.2", "mac": "de:ad:be:ef:00:01"}]))
+            );
+            assert_eq!(
+                request(
+                    addr,
+                    "POST /controls/echo HTTP/1.1\r\nHost: 127.0.0.1\r\n\
+                     Content-Type: application/json; charset=utf-8\r\n\
+                     Content-Length: 11\r\n\r\n{\"a\": [1]}\n"
+                )
+                .await,
+                (200, json!({"a": [1]}))
+            );
+            assert_eq!(
+                request(addr, "GET /links HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                    .await
+                    .0,
+                200
+            );
+        });
+    }
+
+    #[test]
+    fn reports_errors() {
+        let api = ManagementApi::new();
+        api.register_control("fail", |_| Err(String::from("No such rule")));
+
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let server = ManagementServer::bind(api, "127.0.0.1:0".parse().unwrap())
+                .await
+                .unwrap();
+            let addr = server.local_addr().unwrap();
+            tokio::spawn(server.serve());
+
+            assert_eq!(
+                request(addr, "GET /tables/nat HTTP/1.1\r\nHost: localhost\r\n\r\n").await,
+                (404, json!({"error": "Nothing is at /tables/nat"}))
+            );
+            assert_eq!(
+                request(
+                    addr,
+                    "POST /controls/fail HTTP/1.1\r\nHost: localhost\r\n\
+                     Content-Type: application/json\r\n\r\n"
+                )
+                .await,
+                (400, json!({"error": "No such rule"}))
+            );
+            assert_eq!(
+                request(addr, "DELETE /links HTTP/1.1\r\nHost: localhost\r\n\r\n").await,
+                (405, json!({"error": "DELETE is not allowed on /links"}))
+            );
+            assert_eq!(
+                request(addr, "nonsense\r\n\r\n").await,
+                (400, json!({"error": "Malformed request"}))
+            );
+            assert_eq!(
+                request(
+                    addr,
+                    "POST /controls/fail HTTP/1.1\r\nHost: localhost\r\n\
+                     Content-Length: 18446744073709551615\r\n\r\n"
+                )
+                .await,
+                (400, json!({"error": "Malformed request"}))
+            );
+        });
+    }
+    #[test]
+    fn refuses_requests_a_web_page_could_send() {
+        let api = ManagementApi::new();
+        let called = Arc::new(AtomicUsize::new(0));
+        let calls = Arc::clone(&called);
+        api.register_control("flush", move |_| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            Ok(Value::Null)
+        });
+
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let server = ManagementServer::bind(api, "127.0.0.1:0".parse().unwrap())
+                .await
+                .unwrap();
+            let addr = server.local_addr().unwrap();
+            tokio::spawn(server.serve());
+
+            // A name rebound to the router's address, and no name at all.
+            assert_eq!(
+                request(
+                    addr,
+                    "GET /tables HTTP/1.1\r\nHost: rebind.example:8080\r\n\r\n"
+                )
+                .await,
+                (
+                    403,
+                    json!({"error": "Host \"rebind.example:8080\" is not allowed"})
+                )
+            );
+            assert_eq!(request(addr, "GET /tables HTTP/1.1\r\n\r\n").await.0, 403);
+
+            // What a form can send.
+            for content_type in &[
+                "Content-Type: application/x-www-form-urlencoded\r\n",
+                "Content-Type: text/plain\r\n",
+                "",
+            ] {
+                let post = format!(
+                    "POST /controls/flush HTTP/1.1\r\nHost: localhost\r\n{}\
+                     Content-Length: 2\r\n\r\n{{}}",
+                    content_type
+                );
+                assert_eq!(
+                    request(addr, &post).await,
+                    (
+                        415,
+                        json!({"error": "POST requests must be sent as application/json"})
+                    )
+                );
+            }
+            assert_eq!(called.load(Ordering::Relaxed), 0);
+        });
+    }
+
+    #[test]
+    fn allows_loopback_and_bound_hosts() {
+        let bound: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(host_allowed("localhost:8080", bound));
+        assert!(host_allowed("127.0.0.1", bound));
+        assert!(host_allowed("[::1]:8080", bound));
+        assert!(host_allowed("192.0.2.1:8080", bound));
+        assert!(!host_allowed("192.0.2.2", bound));
+        assert!(!host_allowed("router.example", bound));
+        assert!(!host_allowed("", bound));
+    }
+}
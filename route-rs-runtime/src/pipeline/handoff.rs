@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::ptr;
+
+/// The version of the handoff protocol, the first byte an old process sends, so a new process
+/// built from an incompatible release refuses the sockets rather than misreading them.
+pub const HANDOFF_VERSION: u8 = 1;
+
+/// The most descriptors Linux passes in one message, `SCM_MAX_FD`.
+const MAX_HANDOFF_SOCKETS: usize = 253;
+
+/// The byte a new process answers with once it has taken the sockets.
+const HANDOFF_TAKEN: u8 = b'T';
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Sends `data`, with `fds` attached to its first byte, man 7 unix.
+fn send_fds(stream: &UnixStream, data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let fds_len = mem::size_of_val(fds) as libc::c_uint;
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    // This block is unsafe because it uses FFI, and builds the control message by hand. The
+    // control buffer is sized by CMSG_SPACE for exactly the descriptors copied into it.
+    let sent = unsafe {
+        let mut control = vec![0u8; libc::CMSG_SPACE(fds_len) as usize];
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+        ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        libc::sendmsg(stream.as_raw_fd(), &msg, 0)
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The descriptors went with the first byte, and the rest is ordinary data.
+    (&*stream).write_all(&data[sent as usize..])
+}
+
+/// Receives into `data` the descriptors attached to its first byte, and as much of it as has
+/// arrived, returning the descriptors and the bytes read.
+fn recv_fds(stream: &UnixStream, data: &mut [u8]) -> io::Result<(Vec<RawFd>, usize)> {
+    let fds_len = (MAX_HANDOFF_SOCKETS * mem::size_of::<RawFd>()) as libc::c_uint;
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    // This block is unsafe because it uses FFI, and walks the control messages the kernel wrote,
+    // which it bounds by msg_controllen as the CMSG macros do.
+    unsafe {
+        let mut control = vec![0u8; libc::CMSG_SPACE(fds_len) as usize];
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        let received = libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC);
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut fds = vec![];
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                for index in 0..len / mem::size_of::<RawFd>() {
+                    fds.push(ptr::read_unaligned(data.add(index)));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            for fd in fds {
+                libc::close(fd);
+            }
+            return Err(invalid("too many sockets handed over"));
+        }
+        Ok((fds, received as usize))
+    }
+}
+
+/// Hands the sockets of a running router over to the process replacing it, such as a newer build
+/// of the router, so it can take over without ever unbinding them, and without losing the
+/// packets queued on them meanwhile.
+///
+/// The old process listens on a Unix socket with `HandoffListener`, and the new one connects with
+/// `take_sockets`. The old sends the protocol version and the names of its sockets, with their
+/// descriptors attached over `SCM_RIGHTS`, and the new answers once it has taken them, whereupon
+/// `hand_off` returns. The old process should then close its input channels, so its pipeline
+/// drains, and any composite saving state to a `persist` file saves it one last time, for the new
+/// process to load as it builds its own. Both processes hold the sockets until the old exits, so
+/// either may read a packet in the meantime, but none is lost.
+pub struct HandoffListener {
+    listener: UnixListener,
+}
+
+impl HandoffListener {
+    /// Listens at `path`, replacing anything left there by a process that didn't clean up.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(HandoffListener {
+            listener: UnixListener::bind(path)?,
+        })
+    }
+
+    /// Waits for the next process to connect, and hands it `sockets`, by name, returning once it
+    /// has taken them. The sockets are given by their descriptors, from `AsRawFd`, so sockets of
+    /// any kind, `AF_PACKET` and UDP alike, are handed off together.
+    pub fn hand_off(&self, sockets: &[(&str, RawFd)]) -> io::Result<()> {
+        assert!(
+            sockets.len() <= MAX_HANDOFF_SOCKETS,
+            "at most 253 sockets can be handed off"
+        );
+        assert!(
+            sockets.iter().all(|(name, _)| !name.contains('\n')),
+            "socket names must not contain newlines"
+        );
+        let (stream, _) = self.listener.accept()?;
+        let names: Vec<&str> = sockets.iter().map(|(name, _)| *name).collect();
+        let names = names.join("\n");
+        let mut message = vec![HANDOFF_VERSION];
+        message.extend_from_slice(&(names.len() as u32).to_be_bytes());
+        message.extend_from_slice(names.as_bytes());
+        let fds: Vec<RawFd> = sockets.iter().map(|(_, fd)| *fd).collect();
+        send_fds(&stream, &message, &fds)?;
+
+        let mut taken = [0];
+        (&stream).read_exact(&mut taken)?;
+        if taken[0] != HANDOFF_TAKEN {
+            return Err(invalid("the new process didn't take the sockets"));
+        }
+        Ok(())
+    }
+}
+
+/// The sockets handed over by the process this one replaces, by name. Those not taken are closed
+/// when it is dropped.
+pub struct HandedOff {
+    sockets: HashMap<String, RawFd>,
+}
+
+impl HandedOff {
+    /// The descriptor of the socket named `name`, which the caller then owns, to wrap with
+    /// `FromRawFd`, or `afpacket::BoundSocket::from_raw_fd` for an `AF_PACKET` socket.
+    pub fn take(&mut self, name: &str) -> Option<RawFd> {
+        self.sockets.remove(name)
+    }
+
+    /// The names of the sockets not yet taken.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.sockets.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+impl Drop for HandedOff {
+    fn drop(&mut self) {
+        for fd in self.sockets.values() {
+            // The descriptors were received by this process, and are closed once, here.
+            unsafe {
+                libc::close(*fd);
+            }
+        }
+    }
+}
+
+/// Connects to the process listening at `path` with a `HandoffListener`, and takes the sockets it
+/// hands over.
+pub fn take_sockets<P: AsRef<Path>>(path: P) -> io::Result<HandedOff> {
+    let stream = UnixStream::connect(path)?;
+    let mut header = [0; 5];
+    let (fds, mut read) = recv_fds(&stream, &mut header)?;
+    // The descriptors are ours from here, and closed with `handed_off` if anything goes wrong.
+    let mut handed_off = HandedOff {
+        sockets: HashMap::new(),
+    };
+    let mut fds = fds.into_iter();
+    let mut take_fds = |handed_off: &mut HandedOff, names: &[&str]| {
+        for name in names {
+            if let Some(fd) = fds.next() {
+                handed_off.sockets.insert(String::from(*name), fd);
+            }
+        }
+        for fd in &mut fds {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    };
+    if read == 0 {
+        take_fds(&mut handed_off, &[]);
+        return Err(invalid("the old process hung up"));
+    }
+    while read < header.len() {
+        match (&stream).read(&mut header[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    if read < header.len() || header[0] != HANDOFF_VERSION {
+        take_fds(&mut handed_off, &[]);
+        return Err(invalid("unsupported handoff version"));
+    }
+    let mut names = vec![0; u32::from_be_bytes(header[1..5].try_into().unwrap()) as usize];
+    if let Err(err) = (&stream).read_exact(&mut names) {
+        take_fds(&mut handed_off, &[]);
+        return Err(err);
+    }
+    let names = String::from_utf8_lossy(&names).into_owned();
+    let names: Vec<&str> = names.split('\n').filter(|name| !name.is_empty()).collect();
+    take_fds(&mut handed_off, &names);
+    if handed_off.sockets.len() != names.len() {
+        return Err(invalid("fewer sockets handed over than named"));
+    }
+
+    (&stream).write_all(&[HANDOFF_TAKEN])?;
+    Ok(handed_off)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::os::unix::io::FromRawFd;
+    use std::thread;
+    use uuid::Uuid;
+
+    #[test]
+    fn hands_off_bound_sockets() {
+        let path = std::env::temp_dir().join(format!("handoff-{}.sock", Uuid::new_v4()));
+        let listener = HandoffListener::bind(&path).unwrap();
+        let dns = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dhcp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addrs = (dns.local_addr().unwrap(), dhcp.local_addr().unwrap());
+
+        let old = thread::spawn(move || {
+            listener
+                .hand_off(&[("dns", dns.as_raw_fd()), ("dhcp", dhcp.as_raw_fd())])
+                .unwrap();
+            // Packets sent before the old process exits aren't lost.
+            dns.send_to(b"queued", dns.local_addr().unwrap()).unwrap();
+        });
+        let mut handed_off = take_sockets(&path).unwrap();
+        old.join().unwrap();
+
+        assert_eq!(handed_off.names(), vec!["dhcp", "dns"]);
+        let dns = unsafe { UdpSocket::from_raw_fd(handed_off.take("dns").unwrap()) };
+        assert_eq!(dns.local_addr().unwrap(), addrs.0);
+        let mut buf = [0; 16];
+        assert_eq!(dns.recv(&mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"queued");
+        assert_eq!(handed_off.take("dns"), None);
+        assert_eq!(handed_off.names(), vec!["dhcp"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_other_versions() {
+        let path = std::env::temp_dir().join(format!("handoff-{}.sock", Uuid::new_v4()));
+        let listener = UnixListener::bind(&path).unwrap();
+        let old = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(&[HANDOFF_VERSION + 1, 0, 0, 0, 0])
+                .unwrap();
+        });
+        assert!(take_sockets(&path).is_err());
+        old.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -3,3 +3,27 @@
 //! Pipelines are abstractions used by graphgen to IO packets for a router through channels.
 mod runner;
 pub use self::runner::*;
+
+mod sharded;
+pub use self::sharded::*;
+
+mod handoff;
+pub use self::handoff::*;
+
+/// Includes a pipeline generated by `route_rs_graphgen::Build` in the crate's build script. With
+/// no arguments it includes `pipeline.rs` from `OUT_DIR`, the default output of `Build`:
+///
+/// ```ignore
+/// mod pipeline {
+///     route_rs_runtime::include_pipeline!();
+/// }
+/// ```
+#[macro_export]
+macro_rules! include_pipeline {
+    () => {
+        include!(concat!(env!("OUT_DIR"), "/pipeline.rs"));
+    };
+    ($file:expr) => {
+        include!(concat!(env!("OUT_DIR"), "/", $file));
+    };
+}
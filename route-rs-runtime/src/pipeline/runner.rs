@@ -1,3 +1,8 @@
+use crate::link::primitive::{InputChannelLink, OutputChannelLink};
+use crate::link::{LinkBuilder, PacketStream};
+use tokio::runtime;
+use tokio::task::JoinHandle;
+
 pub trait Runner {
     type Input: Sized;
     type Output: Sized;
@@ -7,3 +12,140 @@ pub trait Runner {
         output_channel: crossbeam::Sender<Self::Output>,
     ) -> ();
 }
+
+/// A pipeline with several input and output channels, such as one of each per interface of a
+/// router. Channels are given in the order their IO nodes are declared in the source graph.
+pub trait MultiRunner {
+    type Input: Sized;
+    type Output: Sized;
+
+    fn run(
+        input_channels: Vec<crossbeam::Receiver<Self::Input>>,
+        output_channels: Vec<crossbeam::Sender<Self::Output>>,
+    ) -> ();
+}
+
+/// Runs a link between channels, such as those of the interfaces of a router, returning once every
+/// input channel has closed and the link has drained. Each input channel feeds an ingressor of the
+/// link, and each egressor feeds the output channel at the same position, so there must be as many
+/// output channels as the link has egressors.
+pub fn run_with_channels<Input, Output, L>(
+    runtime: &mut runtime::Runtime,
+    link: L,
+    input_channels: Vec<crossbeam::Receiver<Input>>,
+    output_channels: Vec<crossbeam::Sender<Output>>,
+) where
+    Input: Send + 'static,
+    Output: Send + 'static,
+    L: LinkBuilder<Input, Output>,
+{
+    let in_streams: Vec<PacketStream<Input>> = input_channels
+        .into_iter()
+        .map(|channel| {
+            InputChannelLink::new()
+                .channel(channel)
+                .build_link()
+                .1
+                .remove(0)
+        })
+        .collect();
+    run_with_streams(runtime, link, in_streams, output_channels);
+}
+
+/// As `run_with_channels`, but with the ingressors of the link given as streams.
+pub(crate) fn run_with_streams<Input, Output, L>(
+    runtime: &mut runtime::Runtime,
+    link: L,
+    in_streams: Vec<PacketStream<Input>>,
+    output_channels: Vec<crossbeam::Sender<Output>>,
+) where
+    Input: Send + 'static,
+    Output: Send + 'static,
+    L: LinkBuilder<Input, Output>,
+{
+    let (mut all_runnables, egressors) = link.ingressors(in_streams).build_link();
+    assert_eq!(
+        egressors.len(),
+        output_channels.len(),
+        "Link has {} egressors, but was given {} output channels",
+        egressors.len(),
+        output_channels.len()
+    );
+    for (egressor, channel) in egressors.into_iter().zip(output_channels) {
+        let (mut runnables, _) = OutputChannelLink::new()
+            .ingressor(egressor)
+            .channel(channel)
+            .build_link();
+        all_runnables.append(&mut runnables);
+    }
+
+    runtime.block_on(async {
+        let handles: Vec<JoinHandle<()>> = all_runnables.into_iter().map(tokio::spawn).collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::{ForkLink, JoinLink};
+    use crate::utils::test::harness::initialize_runtime;
+    use crossbeam::crossbeam_channel;
+
+    #[test]
+    fn joins_inputs() {
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            (0..3).map(|_| crossbeam_channel::unbounded()).unzip();
+        for (index, sender) in senders.into_iter().enumerate() {
+            sender.send(index).unwrap();
+        }
+        let (output_channel, output) = crossbeam_channel::unbounded();
+
+        run_with_channels(
+            &mut initialize_runtime(),
+            JoinLink::new(),
+            receivers,
+            vec![output_channel],
+        );
+
+        let mut output: Vec<usize> = output.iter().collect();
+        output.sort();
+        assert_eq!(output, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn forks_to_outputs() {
+        let (input, input_channel) = crossbeam_channel::unbounded();
+        input.send(1).unwrap();
+        drop(input);
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            (0..2).map(|_| crossbeam_channel::unbounded()).unzip();
+
+        run_with_channels(
+            &mut initialize_runtime(),
+            ForkLink::new().num_egressors(2),
+            vec![input_channel],
+            senders,
+        );
+
+        for output in receivers {
+            assert_eq!(output.iter().collect::<Vec<i32>>(), vec![1]);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Link has 2 egressors, but was given 1 output channels")]
+    fn panics_on_missing_output_channels() {
+        let (_, input_channel) = crossbeam_channel::unbounded::<i32>();
+        let (output_channel, _) = crossbeam_channel::unbounded();
+
+        run_with_channels(
+            &mut initialize_runtime(),
+            ForkLink::new().num_egressors(2),
+            vec![input_channel],
+            vec![output_channel],
+        );
+    }
+}
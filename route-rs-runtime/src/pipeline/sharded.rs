@@ -0,0 +1,365 @@
+use crate::link::LinkBuilder;
+#[cfg(all(feature = "fanout", target_os = "linux"))]
+use crate::link::PacketStream;
+use crate::pipeline::run_with_channels;
+#[cfg(all(feature = "fanout", target_os = "linux"))]
+use crate::pipeline::runner::run_with_streams;
+use crossbeam::crossbeam_channel;
+#[cfg(all(feature = "fanout", target_os = "linux"))]
+use std::ffi::CStr;
+#[cfg(all(feature = "fanout", target_os = "linux"))]
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use tokio::runtime;
+
+/// Runs a copy of a pipeline on each of several cores, each in a single threaded Tokio runtime of
+/// its own, so packets never cross cores once they are in a shard.
+///
+/// Packets are spread over the shards by a flow hash, so every packet of a flow goes through the
+/// same shard and flows stay in order. Each shard feeds every output channel, so packets of
+/// different flows may leave in a different order than they came in.
+///
+/// With the `fanout` feature, `run_fanout` reads the interfaces directly: each shard has an
+/// `AF_PACKET` socket per interface in a fanout group, and the kernel hashes each frame to one of
+/// them, so frames never cross cores on their way in. Otherwise, `run` reads input channels on a
+/// dispatcher thread each, which hashes every packet and hands it to its shard over a channel.
+/// That should keep up as long as the pipeline does more per packet than hashing it.
+pub struct ShardedRuntime {
+    num_shards: Option<usize>,
+    pin_threads: bool,
+    queue_capacity: usize,
+}
+
+impl Default for ShardedRuntime {
+    fn default() -> Self {
+        ShardedRuntime::new()
+    }
+}
+
+impl ShardedRuntime {
+    pub fn new() -> Self {
+        ShardedRuntime {
+            num_shards: None,
+            pin_threads: true,
+            queue_capacity: 1024,
+        }
+    }
+
+    /// Defaults to the number of cores.
+    pub fn num_shards(self, num_shards: usize) -> Self {
+        assert!(num_shards > 0, "num_shards must be > 0");
+        ShardedRuntime {
+            num_shards: Some(num_shards),
+            pin_threads: self.pin_threads,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Whether the thread of each shard is pinned to a core of its own, which keeps its caches
+    /// warm. Pinning is only supported on Linux, and is skipped wherever it isn't allowed. Defaults
+    /// to true.
+    pub fn pin_threads(self, pin_threads: bool) -> Self {
+        ShardedRuntime {
+            num_shards: self.num_shards,
+            pin_threads,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Capacity of the channel from each dispatcher to each shard, defaults to 1024.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(queue_capacity > 0, "queue_capacity must be > 0");
+        ShardedRuntime {
+            num_shards: self.num_shards,
+            pin_threads: self.pin_threads,
+            queue_capacity,
+        }
+    }
+
+    /// Runs a shard built by `build_shard` for each shard index, returning once every input
+    /// channel has closed and every shard has drained. As with `run_with_channels`, each shard
+    /// takes an ingressor per input channel and must have an egressor per output channel.
+    pub fn run<Input, Output, L, B, H>(
+        self,
+        build_shard: B,
+        input_channels: Vec<crossbeam::Receiver<Input>>,
+        output_channels: Vec<crossbeam::Sender<Output>>,
+        flow_hash: H,
+    ) where
+        Input: Send + 'static,
+        Output: Send + 'static,
+        L: LinkBuilder<Input, Output>,
+        B: Fn(usize) -> L + Send + Sync + 'static,
+        H: Fn(&Input) -> u64 + Send + Sync + 'static,
+    {
+        let num_shards = self.num_shards.unwrap_or_else(num_cpus::get);
+        let flow_hash = Arc::new(flow_hash);
+
+        // shard_inputs[shard][input] is read by the shard, for the dispatcher of an input.
+        let mut shard_inputs: Vec<Vec<crossbeam::Receiver<Input>>> =
+            (0..num_shards).map(|_| vec![]).collect();
+        let mut dispatchers = vec![];
+        for input_channel in input_channels {
+            let mut senders = vec![];
+            for inputs in shard_inputs.iter_mut() {
+                let (sender, receiver) = crossbeam_channel::bounded(self.queue_capacity);
+                senders.push(sender);
+                inputs.push(receiver);
+            }
+            let flow_hash = Arc::clone(&flow_hash);
+            dispatchers.push(thread::spawn(move || {
+                for packet in input_channel.iter() {
+                    let shard = (flow_hash(&packet) % senders.len() as u64) as usize;
+                    // Only fails if the shard has stopped, and its packets with it.
+                    let _ = senders[shard].send(packet);
+                }
+            }));
+        }
+
+        let build_shard = Arc::new(build_shard);
+        let shards: Vec<_> = shard_inputs
+            .into_iter()
+            .enumerate()
+            .map(|(shard, inputs)| {
+                let build_shard = Arc::clone(&build_shard);
+                let outputs = output_channels.clone();
+                let pin_threads = self.pin_threads;
+                thread::spawn(move || {
+                    if pin_threads {
+                        pin_to_core(shard);
+                    }
+                    let mut runtime = runtime::Builder::new()
+                        .basic_scheduler()
+                        .enable_all()
+                        .build()
+                        .unwrap();
+                    run_with_channels(&mut runtime, build_shard(shard), inputs, outputs);
+                })
+            })
+            .collect();
+        drop(output_channels);
+
+        for dispatcher in dispatchers {
+            dispatcher.join().unwrap();
+        }
+        for shard in shards {
+            shard.join().unwrap();
+        }
+    }
+    /// Runs a shard built by `build_shard` for each shard index on frames read from `interfaces`,
+    /// returning once every shard has stopped. Each shard opens a socket on each interface, which
+    /// joins the fanout group `fanout_group` plus the position of the interface, and takes an
+    /// ingressor per interface in the same order. As with `run`, each shard must have an egressor
+    /// per output channel.
+    ///
+    /// Fanout groups are shared by every process on the host, so `fanout_group` must not be used by
+    /// anything else. Returns an error, before any shard is started, if a socket can't be opened.
+    #[cfg(all(feature = "fanout", target_os = "linux"))]
+    pub fn run_fanout<Output, L, B, I>(
+        self,
+        build_shard: B,
+        interfaces: &[I],
+        fanout_group: u16,
+        output_channels: Vec<crossbeam::Sender<Output>>,
+    ) -> io::Result<()>
+    where
+        Output: Send + 'static,
+        L: LinkBuilder<Vec<u8>, Output>,
+        B: Fn(usize) -> L + Send + Sync + 'static,
+        I: AsRef<CStr>,
+    {
+        let num_shards = self.num_shards.unwrap_or_else(num_cpus::get);
+
+        let mut shard_sockets = vec![];
+        for _ in 0..num_shards {
+            let mut sockets = vec![];
+            for (position, interface) in interfaces.iter().enumerate() {
+                let mut socket = afpacket::Socket::new()?;
+                socket.set_nonblocking(true)?;
+                let mut socket = socket.bind(interface)?;
+                socket.join_fanout(fanout_group.wrapping_add(position as u16))?;
+                sockets.push(socket);
+            }
+            shard_sockets.push(sockets);
+        }
+
+        let build_shard = Arc::new(build_shard);
+        let shards: Vec<_> = shard_sockets
+            .into_iter()
+            .enumerate()
+            .map(|(shard, sockets)| {
+                let build_shard = Arc::clone(&build_shard);
+                let outputs = output_channels.clone();
+                let pin_threads = self.pin_threads;
+                thread::spawn(move || {
+                    if pin_threads {
+                        pin_to_core(shard);
+                    }
+                    let mut runtime = runtime::Builder::new()
+                        .basic_scheduler()
+                        .enable_all()
+                        .build()
+                        .unwrap();
+                    // The sockets must be registered with the reactor of the shard's runtime.
+                    let in_streams = runtime.enter(|| {
+                        sockets
+                            .into_iter()
+                            .map(|socket| {
+                                let socket = afpacket::AsyncBoundSocket::from_socket(socket)
+                                    .expect("Failed to register socket with the shard's runtime");
+                                frame_stream(socket)
+                            })
+                            .collect()
+                    });
+                    run_with_streams(&mut runtime, build_shard(shard), in_streams, outputs);
+                })
+            })
+            .collect();
+        drop(output_channels);
+
+        for shard in shards {
+            shard.join().unwrap();
+        }
+        Ok(())
+    }
+}
+
+/// Largest frame a fanout socket reads, which covers jumbo frames.
+#[cfg(all(feature = "fanout", target_os = "linux"))]
+const MAX_FRAME_LEN: usize = 9216;
+
+/// The frames read from a socket, ending at the first error, as when its interface goes away.
+#[cfg(all(feature = "fanout", target_os = "linux"))]
+fn frame_stream(socket: afpacket::AsyncBoundSocket) -> PacketStream<Vec<u8>> {
+    let frames = futures::stream::unfold(
+        (socket, vec![0; MAX_FRAME_LEN]),
+        |(mut socket, mut buffer)| async move {
+            match socket.recv(&mut buffer).await {
+                Ok(len) => {
+                    let frame = buffer[..len].to_vec();
+                    Some((frame, (socket, buffer)))
+                }
+                Err(_) => None,
+            }
+        },
+    );
+    Box::new(Box::pin(frames))
+}
+
+/// Pins the calling thread to a core, counting round the cores this process may run on. Pinning is
+/// only an optimization, so it is skipped if the process can't change its affinity.
+#[cfg(target_os = "linux")]
+fn pin_to_core(shard: usize) {
+    unsafe {
+        let mut allowed: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut allowed) != 0 {
+            return;
+        }
+        let cores: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+            .filter(|core| libc::CPU_ISSET(*core, &allowed))
+            .collect();
+        if cores.is_empty() {
+            return;
+        }
+
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cores[shard % cores.len()], &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core(_shard: usize) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::Processor;
+    use std::collections::HashMap;
+
+    /// Tags each packet with the shard it went through.
+    struct Tag(usize);
+
+    impl Processor for Tag {
+        type Input = (u64, u32);
+        type Output = (usize, u64, u32);
+
+        fn process(&mut self, (flow, seq): Self::Input) -> Option<Self::Output> {
+            Some((self.0, flow, seq))
+        }
+    }
+
+    #[test]
+    fn keeps_flows_on_one_shard_in_order() {
+        let (input, input_channel) = crossbeam_channel::unbounded();
+        let (output_channel, output) = crossbeam_channel::unbounded();
+        for seq in 0..100 {
+            for flow in 0..8 {
+                input.send((flow, seq)).unwrap();
+            }
+        }
+        drop(input);
+
+        ShardedRuntime::new().num_shards(4).queue_capacity(10).run(
+            |shard| ProcessLink::new().processor(Tag(shard)),
+            vec![input_channel],
+            vec![output_channel],
+            |(flow, _)| *flow,
+        );
+
+        let output: Vec<(usize, u64, u32)> = output.iter().collect();
+        assert_eq!(output.len(), 800);
+        let mut flows: HashMap<u64, (usize, Vec<u32>)> = HashMap::new();
+        for (shard, flow, seq) in output {
+            let (flow_shard, seqs) = flows.entry(flow).or_insert((shard, vec![]));
+            assert_eq!(*flow_shard, shard);
+            seqs.push(seq);
+        }
+        for flow in 0..8 {
+            let (shard, seqs) = &flows[&flow];
+            assert_eq!(*shard, flow as usize % 4);
+            assert_eq!(*seqs, (0..100).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn shards_take_every_input() {
+        let (input_a, input_channel_a) = crossbeam_channel::unbounded();
+        let (input_b, input_channel_b) = crossbeam_channel::unbounded();
+        let (output_channel, output) = crossbeam_channel::unbounded();
+        for n in 0..50 {
+            input_a.send(n).unwrap();
+            input_b.send(n + 1000).unwrap();
+        }
+        drop(input_a);
+        drop(input_b);
+
+        ShardedRuntime::new().num_shards(3).pin_threads(false).run(
+            |_| crate::link::primitive::JoinLink::new(),
+            vec![input_channel_a, input_channel_b],
+            vec![output_channel],
+            |n: &i32| *n as u64,
+        );
+
+        let mut output: Vec<i32> = output.iter().collect();
+        output.sort();
+        assert_eq!(output, (0..50).chain(1000..1050).collect::<Vec<_>>());
+    }
+
+    #[cfg(all(feature = "fanout", target_os = "linux"))]
+    #[test]
+    fn fanout_reports_socket_errors_before_starting() {
+        let (output_channel, _output) = crossbeam_channel::unbounded::<Vec<u8>>();
+        let interfaces = [std::ffi::CString::new("route-rs-none").unwrap()];
+        let result = ShardedRuntime::new().num_shards(2).run_fanout(
+            |_| crate::link::primitive::JoinLink::new(),
+            &interfaces,
+            0x5253,
+            vec![output_channel],
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,746 @@
+use crate::processor::Processor;
+use crate::utils::clock;
+use route_rs_packets::{fold_checksum, sum_words, FlowKey, IpProtocol, Ipv4Packet};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+/// A connection a host on the LAN has told a peer to open to it, through an application protocol
+/// the NAT doesn't otherwise understand, such as the data connection of an FTP `PORT` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expectation {
+    /// The IP protocol number of the connection, 6 for TCP or 17 for UDP.
+    pub protocol: u8,
+    /// The peer the connection is expected from, or none if it may come from anywhere.
+    pub peer: Option<Ipv4Addr>,
+    pub external_port: u16,
+    pub internal: SocketAddrV4,
+    pub expires: Instant,
+}
+
+/// The connections ALGs expect, shared with the NAT, which lets the first connection matching an
+/// expectation in, to the host that expects it. Expectations not met within their lifetime are
+/// forgotten.
+#[derive(Debug)]
+pub struct Expectations {
+    expected: Mutex<Vec<Expectation>>,
+    ports: RangeInclusive<u16>,
+    lifetime: Duration,
+}
+
+impl Default for Expectations {
+    fn default() -> Self {
+        Expectations::new(Duration::from_secs(30))
+    }
+}
+
+impl Expectations {
+    /// Expectations given external ports from 1024 to 65535, and forgotten after `lifetime`.
+    pub fn new(lifetime: Duration) -> Self {
+        Expectations {
+            expected: Mutex::new(Vec::new()),
+            ports: 1024..=65535,
+            lifetime,
+        }
+    }
+
+    /// Expects a connection to a host's port, returning the external port the peer should
+    /// connect to, the host's own port if it is free. A connection already expected keeps its
+    /// port. None if every port is taken.
+    pub fn expect(
+        &self,
+        protocol: u8,
+        internal: SocketAddrV4,
+        peer: Option<Ipv4Addr>,
+    ) -> Option<u16> {
+        let now = clock::now();
+        let mut expected = self.expected.lock().unwrap();
+        expected.retain(|e| e.expires > now);
+        // A payload sent again, as a TCP retransmission, must be rewritten as it was the first time.
+        if let Some(e) = expected
+            .iter_mut()
+            .find(|e| e.protocol == protocol && e.internal == internal && e.peer == peer)
+        {
+            e.expires = now + self.lifetime;
+            return Some(e.external_port);
+        }
+        let taken = |port: u16| {
+            expected
+                .iter()
+                .any(|e| e.protocol == protocol && e.external_port == port)
+        };
+        let external_port = if self.ports.contains(&internal.port()) && !taken(internal.port()) {
+            internal.port()
+        } else {
+            self.ports.clone().find(|port| !taken(*port))?
+        };
+        expected.push(Expectation {
+            protocol,
+            peer,
+            external_port,
+            internal,
+            expires: now + self.lifetime,
+        });
+        Some(external_port)
+    }
+
+    /// The host a new connection from a peer to an external port was expected by, if any. The
+    /// expectation is met, so a second connection to the port isn't let in.
+    pub fn take(&self, protocol: u8, peer: Ipv4Addr, external_port: u16) -> Option<SocketAddrV4> {
+        let now = clock::now();
+        let mut expected = self.expected.lock().unwrap();
+        let index = expected.iter().position(|e| {
+            e.protocol == protocol
+                && e.external_port == external_port
+                && e.peer.is_none_or(|p| p == peer)
+                && e.expires > now
+        })?;
+        Some(expected.remove(index).internal)
+    }
+
+    /// Every expectation not yet met or forgotten.
+    pub fn expectations(&self) -> Vec<Expectation> {
+        let now = clock::now();
+        let expected = self.expected.lock().unwrap();
+        expected
+            .iter()
+            .filter(|e| e.expires > now)
+            .cloned()
+            .collect()
+    }
+}
+
+/// What an ALG is given to rewrite a payload with.
+pub struct AlgContext<'a> {
+    /// The address the NAT translates the LAN's addresses to.
+    pub external_addr: Ipv4Addr,
+    pub expectations: &'a Expectations,
+}
+
+/// An application layer gateway, which rewrites the addresses and ports of the LAN that an
+/// application protocol carries in its payloads to those the NAT translates them to, and expects
+/// the connections the payloads tell peers to open.
+pub trait Alg: Send {
+    fn name(&self) -> &'static str;
+
+    /// Whether the ALG rewrites the packets a host on the LAN sends on a flow.
+    fn handles(&self, flow: &FlowKey) -> bool;
+
+    /// The payload of a packet of a flow the ALG handles, rewritten, or none to leave it as it is.
+    fn rewrite(&mut self, flow: &FlowKey, payload: &[u8], context: &AlgContext) -> Option<Vec<u8>>;
+}
+
+/// Rewrites the `PORT` and `EPRT` commands FTP clients on the LAN send to servers, so the
+/// server opens the data connection to the NAT's address, and expects the data connection.
+#[derive(Default)]
+pub struct FtpAlg {}
+
+impl FtpAlg {
+    pub fn new() -> Self {
+        FtpAlg {}
+    }
+
+    /// The host and port of a `PORT h1,h2,h3,h4,p1,p2` command.
+    fn parse_port(args: &str) -> Option<SocketAddrV4> {
+        let fields = args
+            .split(',')
+            .map(|f| f.trim().parse::<u8>())
+            .collect::<Result<Vec<u8>, _>>()
+            .ok()?;
+        if fields.len() != 6 {
+            return None;
+        }
+        let addr = Ipv4Addr::new(fields[0], fields[1], fields[2], fields[3]);
+        let port = u16::from_be_bytes([fields[4], fields[5]]);
+        Some(SocketAddrV4::new(addr, port))
+    }
+
+    /// The host and port of an `EPRT |1|addr|port|` command, RFC 2428.
+    fn parse_eprt(args: &str) -> Option<SocketAddrV4> {
+        let fields: Vec<&str> = args.trim().split('|').collect();
+        if fields.len() != 5 || fields[1] != "1" {
+            return None;
+        }
+        Some(SocketAddrV4::new(
+            fields[2].parse().ok()?,
+            fields[3].parse().ok()?,
+        ))
+    }
+}
+
+impl Alg for FtpAlg {
+    fn name(&self) -> &'static str {
+        "ftp"
+    }
+
+    fn handles(&self, flow: &FlowKey) -> bool {
+        flow.protocol == PROTOCOL_TCP && flow.dest_port == 21
+    }
+
+    fn rewrite(&mut self, flow: &FlowKey, payload: &[u8], context: &AlgContext) -> Option<Vec<u8>> {
+        let command = std::str::from_utf8(payload).ok()?;
+        let line = command.trim_end_matches("\r\n");
+        let (verb, args) = line.split_at(line.find(' ')?);
+        let internal = match verb.to_ascii_uppercase().as_str() {
+            "PORT" => FtpAlg::parse_port(args)?,
+            "EPRT" => FtpAlg::parse_eprt(args)?,
+            _ => return None,
+        };
+        let peer = match flow.dest_addr {
+            IpAddr::V4(peer) => Some(peer),
+            IpAddr::V6(_) => None,
+        };
+        let port = context.expectations.expect(PROTOCOL_TCP, internal, peer)?;
+        let addr = context.external_addr;
+        let rewritten = match verb.to_ascii_uppercase().as_str() {
+            "PORT" => {
+                let [h1, h2, h3, h4] = addr.octets();
+                let [p1, p2] = port.to_be_bytes();
+                format!("{} {},{},{},{},{},{}\r\n", verb, h1, h2, h3, h4, p1, p2)
+            }
+            _ => format!("{} |1|{}|{}|\r\n", verb, addr, port),
+        };
+        Some(rewritten.into_bytes())
+    }
+}
+
+/// Rewrites the SIP messages phones on the LAN send, so the addresses in their headers, and the
+/// connection address and media ports of their SDP bodies, are the NAT's, and expects the media
+/// streams, from any peer, as the media may come from elsewhere than the SIP server.
+#[derive(Default)]
+pub struct SipAlg {}
+
+impl SipAlg {
+    pub fn new() -> Self {
+        SipAlg {}
+    }
+
+    fn rewrite_sdp(body: &str, internal: Ipv4Addr, context: &AlgContext) -> Option<String> {
+        let mut media_addr = internal;
+        let mut rewritten = String::with_capacity(body.len());
+        for line in body.split_inclusive("\r\n") {
+            let content = line.trim_end_matches("\r\n");
+            if let Some(addr) = content.strip_prefix("c=IN IP4 ") {
+                media_addr = addr.trim().parse().ok()?;
+                rewritten.push_str(&format!("c=IN IP4 {}\r\n", context.external_addr));
+            } else if let Some(media) = content.strip_prefix("m=") {
+                let mut fields = media.splitn(3, ' ');
+                let (kind, port, rest) = (fields.next()?, fields.next()?, fields.next()?);
+                let port: u16 = port.parse().ok()?;
+                let internal = SocketAddrV4::new(media_addr, port);
+                let port = context.expectations.expect(PROTOCOL_UDP, internal, None)?;
+                rewritten.push_str(&format!("m={} {} {}\r\n", kind, port, rest));
+            } else {
+                rewritten.push_str(line);
+            }
+        }
+        Some(rewritten)
+    }
+}
+
+impl Alg for SipAlg {
+    fn name(&self) -> &'static str {
+        "sip"
+    }
+
+    fn handles(&self, flow: &FlowKey) -> bool {
+        flow.protocol == PROTOCOL_UDP && flow.dest_port == 5060
+    }
+
+    fn rewrite(&mut self, flow: &FlowKey, payload: &[u8], context: &AlgContext) -> Option<Vec<u8>> {
+        let internal = match flow.src_addr {
+            IpAddr::V4(addr) => addr,
+            IpAddr::V6(_) => return None,
+        };
+        let message = std::str::from_utf8(payload).ok()?;
+        let split = message.find("\r\n\r\n")? + 4;
+        let (headers, body) = message.split_at(split);
+
+        let body = if body.is_empty() {
+            String::new()
+        } else {
+            SipAlg::rewrite_sdp(body, internal, context)?
+        };
+        let headers = replace_addr(headers, internal, context.external_addr);
+        let mut rewritten = String::with_capacity(headers.len() + body.len());
+        for line in headers.split_inclusive("\r\n") {
+            let (name, _) = line.split_at(line.find(':').unwrap_or(0));
+            if name.eq_ignore_ascii_case("Content-Length") {
+                rewritten.push_str(&format!("{}: {}\r\n", name, body.len()));
+            } else {
+                rewritten.push_str(line);
+            }
+        }
+        rewritten.push_str(&body);
+        Some(rewritten.into_bytes())
+    }
+}
+
+/// `text` with each mention of `from` replaced with `to`, leaving addresses `from` is only a part
+/// of, such as 10.0.0.10 for 10.0.0.1, as they are.
+fn replace_addr(text: &str, from: Ipv4Addr, to: Ipv4Addr) -> String {
+    let (from, to) = (from.to_string(), to.to_string());
+    let part_of_addr = |c: Option<char>| c.is_some_and(|c| c.is_ascii_digit() || c == '.');
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find(&from) {
+        let before = rest[..at]
+            .chars()
+            .last()
+            .or_else(|| replaced.chars().last());
+        let after = rest[at + from.len()..].chars().next();
+        replaced.push_str(&rest[..at]);
+        if part_of_addr(before) || part_of_addr(after) {
+            replaced.push_str(&from);
+        } else {
+            replaced.push_str(&to);
+        }
+        rest = &rest[at + from.len()..];
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+/// The TCP or UDP checksum of a segment, with the checksum field zeroed, RFC 793 section 3.1.
+pub(crate) fn transport_checksum(
+    src: Ipv4Addr,
+    dest: Ipv4Addr,
+    protocol: u8,
+    segment: &[u8],
+) -> u16 {
+    let mut sum = sum_words(&src.octets(), 0);
+    sum = sum_words(&dest.octets(), sum);
+    sum += u32::from(protocol) + segment.len() as u32;
+    fold_checksum(sum_words(segment, sum))
+}
+
+/// How the sequence numbers of a TCP flow are moved once an ALG has changed the length of its
+/// payloads, as `nf_nat_seq_adjust` does. Segments of the host up to the last one rewritten, at
+/// `correction_pos` of the host's own sequence numbers, are moved by `offset_before`, and those
+/// after it by `offset_after`, so a retransmission of a rewritten segment lands where the
+/// original did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SeqAdjust {
+    correction_pos: u32,
+    offset_before: i64,
+    offset_after: i64,
+    fin_sent: bool,
+    fin_received: bool,
+}
+
+impl SeqAdjust {
+    /// The offset for a sequence number of the host, before it is moved.
+    fn offset(&self, seq: u32) -> i64 {
+        if after(seq, self.correction_pos) {
+            self.offset_after
+        } else {
+            self.offset_before
+        }
+    }
+}
+
+/// Whether sequence number `a` comes after `b`, in the space that wraps around, RFC 1982.
+fn after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+const TCP_FIN: u8 = 0x01;
+const TCP_RST: u8 = 0x04;
+
+/// Runs ALGs over the traffic between the LAN and the NAT, on the LAN side, where addresses are
+/// still the hosts' own. Each packet a host sends on a flow an ALG handles is rewritten by it, and
+/// the connections it expects are added to `Expectations`, for the NAT to let in.
+///
+/// An ALG that changes the length of a TCP payload moves every sequence number after it, so the
+/// processor shifts the sequence numbers of the packets the host sends on the flow from then on,
+/// and the acknowledgment numbers of those the peer sends back, which must pass through it too.
+/// A retransmitted segment is rewritten again, but moved as the original was. Checksums are
+/// recomputed for the packets changed. What is kept for a flow is forgotten once it is reset, or
+/// closed from both ends.
+pub struct AlgProcessor {
+    algs: Vec<Box<dyn Alg>>,
+    external_addr: Ipv4Addr,
+    expectations: Arc<Expectations>,
+    /// How each TCP flow rewritten has had its sequence numbers moved, by its key as the host
+    /// sends it.
+    adjusts: HashMap<FlowKey, SeqAdjust>,
+}
+
+impl AlgProcessor {
+    pub fn new(external_addr: Ipv4Addr, expectations: Arc<Expectations>) -> Self {
+        AlgProcessor {
+            algs: Vec::new(),
+            external_addr,
+            expectations,
+            adjusts: HashMap::new(),
+        }
+    }
+
+    /// Adds an ALG, which rewrites the flows it handles that no ALG registered before it does.
+    pub fn register(mut self, alg: Box<dyn Alg>) -> Self {
+        self.algs.push(alg);
+        self
+    }
+
+    fn rewrite(&mut self, flow: &FlowKey, mut segment: Vec<u8>) -> Option<Vec<u8>> {
+        let header_len = match flow.protocol {
+            PROTOCOL_TCP if segment.len() >= 20 => usize::from(segment[12] >> 4) * 4,
+            PROTOCOL_UDP if segment.len() >= 8 => 8,
+            _ => return None,
+        };
+        if segment.len() < header_len {
+            return None;
+        }
+
+        let context = AlgContext {
+            external_addr: self.external_addr,
+            expectations: &self.expectations,
+        };
+        let payload = &segment[header_len..];
+        let rewritten = self
+            .algs
+            .iter_mut()
+            .find(|alg| alg.handles(flow))
+            .and_then(|alg| alg.rewrite(flow, payload, &context));
+        let mut changed = rewritten.is_some();
+        if let Some(rewritten) = rewritten {
+            if flow.protocol == PROTOCOL_TCP {
+                let grown = rewritten.len() as i64 - payload.len() as i64;
+                let seq = u32::from_be_bytes(segment[4..8].try_into().unwrap());
+                self.correct(flow, seq, grown);
+            }
+            segment.truncate(header_len);
+            segment.extend_from_slice(&rewritten);
+            if flow.protocol == PROTOCOL_UDP {
+                let len = segment.len() as u16;
+                segment[4..6].copy_from_slice(&len.to_be_bytes());
+            }
+        }
+
+        if flow.protocol == PROTOCOL_TCP {
+            if let Some(adjust) = self.adjusts.get(flow) {
+                let seq = u32::from_be_bytes(segment[4..8].try_into().unwrap());
+                let seq = (i64::from(seq) + adjust.offset(seq)) as u32;
+                segment[4..8].copy_from_slice(&seq.to_be_bytes());
+                changed = true;
+            }
+            if let Some(adjust) = self.adjusts.get(&flow.reverse()) {
+                // The peer acknowledges sequence numbers already moved, so which offset applies is
+                // told by where the acknowledgment was before the last correction.
+                let ack = u32::from_be_bytes(segment[8..12].try_into().unwrap());
+                let unmoved = (i64::from(ack) - adjust.offset_before) as u32;
+                let ack = (i64::from(ack) - adjust.offset(unmoved)) as u32;
+                segment[8..12].copy_from_slice(&ack.to_be_bytes());
+                changed = true;
+            }
+            self.track_close(flow, segment[13]);
+        }
+
+        if changed {
+            Some(segment)
+        } else {
+            None
+        }
+    }
+
+    /// Records that the host's segment at `seq` was rewritten `grown` bytes longer. A segment at or
+    /// before the last correction is a retransmission, and was already accounted for.
+    fn correct(&mut self, flow: &FlowKey, seq: u32, grown: i64) {
+        if grown == 0 {
+            return;
+        }
+        let adjust = self.adjusts.entry(*flow).or_insert(SeqAdjust {
+            correction_pos: seq,
+            offset_before: 0,
+            offset_after: 0,
+            fin_sent: false,
+            fin_received: false,
+        });
+        if adjust.offset_before == adjust.offset_after || after(seq, adjust.correction_pos) {
+            adjust.correction_pos = seq;
+            adjust.offset_before = adjust.offset_after;
+            adjust.offset_after += grown;
+        }
+    }
+
+    /// Forgets how a flow is moved once it is reset, or once both ends have sent a FIN and the
+    /// last of them has been acknowledged.
+    fn track_close(&mut self, flow: &FlowKey, flags: u8) {
+        if flags & TCP_RST != 0 {
+            self.adjusts.remove(flow);
+            self.adjusts.remove(&flow.reverse());
+            return;
+        }
+        for (key, from_host) in [(*flow, true), (flow.reverse(), false)].iter() {
+            let closed = match self.adjusts.get_mut(key) {
+                Some(adjust) if flags & TCP_FIN != 0 => {
+                    if *from_host {
+                        adjust.fin_sent = true;
+                    } else {
+                        adjust.fin_received = true;
+                    }
+                    false
+                }
+                Some(adjust) => adjust.fin_sent && adjust.fin_received,
+                None => false,
+            };
+            if closed {
+                self.adjusts.remove(key);
+            }
+        }
+    }
+}
+
+impl Processor for AlgProcessor {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, mut packet: Self::Input) -> Option<Self::Output> {
+        if packet.protocol() != IpProtocol::TCP && packet.protocol() != IpProtocol::UDP {
+            return Some(packet);
+        }
+        let flow = FlowKey::from_ipv4(&packet);
+        let mut segment = match self.rewrite(&flow, packet.payload().to_vec()) {
+            Some(segment) => segment,
+            None => return Some(packet),
+        };
+        let checksum_at = if flow.protocol == PROTOCOL_TCP { 16 } else { 6 };
+        segment[checksum_at..checksum_at + 2].copy_from_slice(&[0, 0]);
+        let checksum = transport_checksum(
+            packet.src_addr(),
+            packet.dest_addr(),
+            flow.protocol,
+            &segment,
+        );
+        segment[checksum_at..checksum_at + 2].copy_from_slice(&checksum.to_be_bytes());
+        packet.set_payload(&segment);
+        packet.set_checksum();
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{TcpSegment, UdpSegment};
+    use std::convert::TryFrom;
+
+    const EXTERNAL_ADDR: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 7);
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 20);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+
+    fn tcp(
+        src: Ipv4Addr,
+        dest: Ipv4Addr,
+        ports: (u16, u16),
+        seqs: (u32, u32),
+        payload: &str,
+    ) -> Ipv4Packet {
+        let mut segment = TcpSegment::empty();
+        segment.set_src_port(ports.0);
+        segment.set_dest_port(ports.1);
+        segment.set_sequence_number(seqs.0);
+        segment.set_acknowledgment_number(seqs.1);
+        segment.set_payload(payload.as_bytes());
+        let mut packet = Ipv4Packet::encap_tcp(segment);
+        packet.set_src_addr(src);
+        packet.set_dest_addr(dest);
+        packet
+    }
+
+    fn processor() -> (AlgProcessor, Arc<Expectations>) {
+        let expectations = Arc::new(Expectations::default());
+        let processor = AlgProcessor::new(EXTERNAL_ADDR, Arc::clone(&expectations))
+            .register(Box::new(FtpAlg::new()))
+            .register(Box::new(SipAlg::new()));
+        (processor, expectations)
+    }
+
+    #[test]
+    fn rewrites_ftp_port_commands() {
+        let (mut alg, expectations) = processor();
+
+        let port = tcp(
+            CLIENT,
+            SERVER,
+            (40000, 21),
+            (1000, 500),
+            "PORT 192,168,1,20,19,137\r\n",
+        );
+        let rewritten = TcpSegment::try_from(alg.process(port).unwrap()).unwrap();
+        assert_eq!(
+            rewritten.payload().as_ref(),
+            b"PORT 203,0,113,7,19,137\r\n".as_ref()
+        );
+        let data = SocketAddrV4::new(CLIENT, 5001);
+        assert_eq!(expectations.take(PROTOCOL_TCP, SERVER, 5001), Some(data));
+        assert_eq!(expectations.take(PROTOCOL_TCP, SERVER, 5001), None);
+
+        // The command is a byte shorter, so later sequence numbers, and the server's
+        // acknowledgments of them, are a byte apart.
+        let list = tcp(CLIENT, SERVER, (40000, 21), (1027, 560), "LIST\r\n");
+        let list = TcpSegment::try_from(alg.process(list).unwrap()).unwrap();
+        assert_eq!(list.sequence_number(), 1026);
+        let ack = tcp(SERVER, CLIENT, (21, 40000), (560, 1032), "");
+        let ack = TcpSegment::try_from(alg.process(ack).unwrap()).unwrap();
+        assert_eq!(ack.acknowledgment_number(), 1033);
+    }
+
+    #[test]
+    fn moves_retransmissions_as_the_original() {
+        let (mut alg, _) = processor();
+
+        let port = "PORT 192,168,1,20,19,137\r\n";
+        for _ in 0..2 {
+            let sent = tcp(CLIENT, SERVER, (40000, 21), (1000, 500), port);
+            let sent = TcpSegment::try_from(alg.process(sent).unwrap()).unwrap();
+            assert_eq!(sent.sequence_number(), 1000);
+            assert_eq!(
+                sent.payload().as_ref(),
+                b"PORT 203,0,113,7,19,137\r\n".as_ref()
+            );
+        }
+
+        // The command was only a byte shorter, however often it was sent.
+        let list = tcp(CLIENT, SERVER, (40000, 21), (1027, 560), "LIST\r\n");
+        let list = TcpSegment::try_from(alg.process(list).unwrap()).unwrap();
+        assert_eq!(list.sequence_number(), 1026);
+        // An acknowledgment of no more than the command itself is moved by the earlier offset.
+        let early_ack = tcp(SERVER, CLIENT, (21, 40000), (560, 1000), "");
+        let early_ack = TcpSegment::try_from(alg.process(early_ack).unwrap()).unwrap();
+        assert_eq!(early_ack.acknowledgment_number(), 1000);
+        let ack = tcp(SERVER, CLIENT, (21, 40000), (560, 1032), "");
+        let ack = TcpSegment::try_from(alg.process(ack).unwrap()).unwrap();
+        assert_eq!(ack.acknowledgment_number(), 1033);
+    }
+
+    fn with_flags(packet: Ipv4Packet, flags: u16) -> Ipv4Packet {
+        let (src, dest) = (packet.src_addr(), packet.dest_addr());
+        let mut segment = TcpSegment::try_from(packet).unwrap();
+        segment.set_control_bits(flags);
+        let mut packet = Ipv4Packet::encap_tcp(segment);
+        packet.set_src_addr(src);
+        packet.set_dest_addr(dest);
+        packet
+    }
+
+    #[test]
+    fn forgets_closed_flows() {
+        let (mut alg, _) = processor();
+        let port = "PORT 192,168,1,20,19,137\r\n";
+
+        alg.process(tcp(CLIENT, SERVER, (40000, 21), (1000, 500), port));
+        assert_eq!(alg.adjusts.len(), 1);
+        let fin = tcp(CLIENT, SERVER, (40000, 21), (1027, 500), "");
+        alg.process(with_flags(fin, u16::from(TCP_FIN)));
+        let fin = tcp(SERVER, CLIENT, (21, 40000), (500, 1027), "");
+        alg.process(with_flags(fin, u16::from(TCP_FIN)));
+        assert_eq!(alg.adjusts.len(), 1);
+        let last_ack = tcp(CLIENT, SERVER, (40000, 21), (1028, 501), "");
+        let last_ack = TcpSegment::try_from(alg.process(last_ack).unwrap()).unwrap();
+        assert_eq!(last_ack.sequence_number(), 1027);
+        assert!(alg.adjusts.is_empty());
+
+        alg.process(tcp(CLIENT, SERVER, (40001, 21), (1000, 500), port));
+        assert_eq!(alg.adjusts.len(), 1);
+        let rst = tcp(SERVER, CLIENT, (21, 40001), (500, 1026), "");
+        alg.process(with_flags(rst, u16::from(TCP_RST)));
+        assert!(alg.adjusts.is_empty());
+    }
+
+    #[test]
+    fn fixes_checksums() {
+        let (mut alg, _) = processor();
+
+        let eprt = tcp(
+            CLIENT,
+            SERVER,
+            (40000, 21),
+            (1, 1),
+            "EPRT |1|192.168.1.20|6000|\r\n",
+        );
+        let packet = alg.process(eprt).unwrap();
+        let (src, dest) = (packet.src_addr(), packet.dest_addr());
+        let mut segment = packet.payload().to_vec();
+        assert_eq!(&segment[20..], b"EPRT |1|203.0.113.7|6000|\r\n".as_ref());
+        let checksum = u16::from_be_bytes([segment[16], segment[17]]);
+        segment[16..18].copy_from_slice(&[0, 0]);
+        assert_eq!(
+            transport_checksum(src, dest, PROTOCOL_TCP, &segment),
+            checksum
+        );
+    }
+
+    #[test]
+    fn rewrites_sip_sdp() {
+        let (mut alg, expectations) = processor();
+
+        let invite = "INVITE sip:bob@example.com SIP/2.0\r\n\
+                      Via: SIP/2.0/UDP 192.168.1.20:5060\r\n\
+                      Content-Length: 53\r\n\r\n\
+                      v=0\r\n\
+                      c=IN IP4 192.168.1.20\r\n\
+                      m=audio 49170 RTP/AVP 0\r\n";
+        let mut segment = UdpSegment::empty();
+        segment.set_src_port(5060);
+        segment.set_dest_port(5060);
+        segment.set_payload(invite.as_bytes());
+        let mut packet = Ipv4Packet::encap_udp(segment);
+        packet.set_src_addr(CLIENT);
+        packet.set_dest_addr(SERVER);
+
+        let rewritten = UdpSegment::try_from(alg.process(packet).unwrap()).unwrap();
+        let payload = String::from_utf8(rewritten.payload().to_vec()).unwrap();
+        assert_eq!(
+            payload,
+            "INVITE sip:bob@example.com SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 203.0.113.7:5060\r\n\
+             Content-Length: 52\r\n\r\n\
+             v=0\r\n\
+             c=IN IP4 203.0.113.7\r\n\
+             m=audio 49170 RTP/AVP 0\r\n"
+        );
+        assert_eq!(rewritten.length() as usize, 8 + payload.len());
+        let media = SocketAddrV4::new(CLIENT, 49170);
+        assert_eq!(
+            expectations.take(PROTOCOL_UDP, Ipv4Addr::new(192, 0, 2, 9), 49170),
+            Some(media)
+        );
+    }
+
+    /// Upper-cases what hosts send to port 7.
+    struct Shout {}
+
+    impl Alg for Shout {
+        fn name(&self) -> &'static str {
+            "shout"
+        }
+
+        fn handles(&self, flow: &FlowKey) -> bool {
+            flow.dest_port == 7
+        }
+
+        fn rewrite(&mut self, _: &FlowKey, payload: &[u8], _: &AlgContext) -> Option<Vec<u8>> {
+            Some(payload.to_ascii_uppercase())
+        }
+    }
+
+    #[test]
+    fn runs_registered_algs() {
+        let (alg, _) = processor();
+        let mut alg = alg.register(Box::new(Shout {}));
+
+        let echo = tcp(CLIENT, SERVER, (40000, 7), (1, 1), "hello");
+        let echo = TcpSegment::try_from(alg.process(echo).unwrap()).unwrap();
+        assert_eq!(echo.payload().as_ref(), b"HELLO".as_ref());
+        let other = tcp(CLIENT, SERVER, (40000, 80), (1, 1), "hello");
+        let other = TcpSegment::try_from(alg.process(other).unwrap()).unwrap();
+        assert_eq!(other.payload().as_ref(), b"hello".as_ref());
+    }
+}
@@ -0,0 +1,320 @@
+use crate::config::Subnet;
+use crate::processor::{Processor, ProcessorContext};
+use crate::utils::clock;
+use route_rs_packets::{ArpFrame, ArpOp, MacAddr, ARP_ETHER_TYPE};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+fn ipv4_addr(bytes: &[u8]) -> Option<Ipv4Addr> {
+    let octets: [u8; 4] = bytes.try_into().ok()?;
+    Some(Ipv4Addr::from(octets))
+}
+
+/// Answers ARP requests for addresses in other subnets with the router's own MAC address, so hosts
+/// that think those addresses are on their link send their packets to the router, which routes
+/// them on. Requests it doesn't answer, for addresses outside the subnets or gratuitous, are
+/// dropped, so they can be sent down another path.
+pub struct ProxyArp {
+    mac: MacAddr,
+    subnets: Vec<Subnet>,
+}
+
+impl ProxyArp {
+    pub fn new(mac: MacAddr, subnets: Vec<Subnet>) -> Self {
+        ProxyArp { mac, subnets }
+    }
+}
+
+impl Processor for ProxyArp {
+    type Input = ArpFrame;
+    type Output = ArpFrame;
+
+    fn process(&mut self, request: Self::Input) -> Option<Self::Output> {
+        if request.opcode() != ArpOp::Request as u16 {
+            return None;
+        }
+        let sender = ipv4_addr(request.sender_protocol_addr())?;
+        let target = ipv4_addr(request.target_protocol_addr())?;
+        if sender == target {
+            return None;
+        }
+        if !self.subnets.iter().any(|s| s.contains(IpAddr::V4(target))) {
+            return None;
+        }
+        let requester = MacAddr::new(request.sender_hardware_addr().try_into().ok()?);
+
+        let mut reply = ArpFrame::new(6, 4);
+        reply
+            .set_hardware_type(request.hardware_type())
+            .set_protocol_type(request.protocol_type())
+            .set_opcode(ArpOp::Reply as u16)
+            .set_sender_hardware_addr(self.mac)
+            .set_sender_protocol_addr(IpAddr::V4(target))
+            .set_target_hardware_addr(requester)
+            .set_target_protocol_addr(IpAddr::V4(sender));
+        let mut frame = reply.frame();
+        frame.set_ether_type(ARP_ETHER_TYPE);
+        frame.set_src_mac(self.mac);
+        frame.set_dest_mac(requester);
+        ArpFrame::try_from(frame).ok()
+    }
+}
+
+/// The addresses the router has sent ARP requests for, and when, shared between what sends the
+/// requests and the `ArpFilter` checking the replies.
+#[derive(Default)]
+pub struct ArpRequests {
+    sent: Mutex<HashMap<Ipv4Addr, Instant>>,
+}
+
+impl ArpRequests {
+    pub fn new() -> Self {
+        ArpRequests {
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Notes that a request for `address` has been sent.
+    pub fn sent(&self, address: Ipv4Addr) {
+        self.sent.lock().unwrap().insert(address, clock::now());
+    }
+
+    /// Whether a request for `address` was sent within `timeout`, forgetting it if so, so each
+    /// request is answered once.
+    fn answer(&self, address: Ipv4Addr, timeout: Duration) -> bool {
+        let now = clock::now();
+        let mut sent = self.sent.lock().unwrap();
+        sent.retain(|_, at| now.saturating_duration_since(*at) <= timeout);
+        sent.remove(&address).is_some()
+    }
+}
+
+/// Drops ARP frames that would poison the router's ARP table: replies to requests it never sent,
+/// or sent more than `timeout` ago, gratuitous ARP, and frames from a sender that has sent more
+/// than `rate_limit` in the last second. Requests from senders within their rate limit pass.
+///
+/// The filter counts the frames it drops as `dropped` under its name in the metrics. Its rate
+/// limit can be tuned while it runs, as by the management API, through the handle `watch`
+/// returns, and applies from the next frame it sees.
+pub struct ArpFilter {
+    requests: Arc<ArpRequests>,
+    timeout: Duration,
+    rate_limit: u32,
+    /// When each sender's current second began, and the frames it has sent within it.
+    senders: HashMap<MacAddr, (Instant, u32)>,
+    dropped: Option<Arc<AtomicU64>>,
+    watched: Option<watch::Receiver<u32>>,
+}
+
+impl ArpFilter {
+    pub fn new(requests: Arc<ArpRequests>) -> Self {
+        ArpFilter {
+            requests,
+            timeout: Duration::from_secs(3),
+            rate_limit: 10,
+            senders: HashMap::new(),
+            dropped: None,
+            watched: None,
+        }
+    }
+
+    /// Changes timeout, how long after a request its reply is accepted, default value is 3
+    /// seconds.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        ArpFilter {
+            requests: self.requests,
+            timeout,
+            rate_limit: self.rate_limit,
+            senders: self.senders,
+            dropped: self.dropped,
+            watched: self.watched,
+        }
+    }
+
+    /// Changes rate_limit, the frames a sender may send each second, default value is 10.
+    pub fn rate_limit(self, rate_limit: u32) -> Self {
+        ArpFilter {
+            requests: self.requests,
+            timeout: self.timeout,
+            rate_limit,
+            senders: self.senders,
+            dropped: self.dropped,
+            watched: self.watched,
+        }
+    }
+
+    /// A handle to change the rate limit of the filter while it runs, starting from the one it has
+    /// been given so far. A rate limit sent through the handle replaces any set after this.
+    pub fn watch(self) -> (Self, watch::Sender<u32>) {
+        let (sender, watched) = watch::channel(self.rate_limit);
+        let filter = ArpFilter {
+            requests: self.requests,
+            timeout: self.timeout,
+            rate_limit: self.rate_limit,
+            senders: self.senders,
+            dropped: self.dropped,
+            watched: Some(watched),
+        };
+        (filter, sender)
+    }
+
+    /// Whether the sender is within its rate limit, counting this frame against it.
+    fn within_rate_limit(&mut self, sender: MacAddr) -> bool {
+        if let Some(watched) = &self.watched {
+            self.rate_limit = *watched.borrow();
+        }
+        let now = clock::now();
+        let second = Duration::from_secs(1);
+        // Forgets senders quiet for a second once there are enough to be worth sweeping.
+        if self.senders.len() >= 1024 {
+            self.senders
+                .retain(|_, (began, _)| now.saturating_duration_since(*began) < second);
+        }
+        let (began, sent) = self.senders.entry(sender).or_insert((now, 0));
+        if now.saturating_duration_since(*began) >= second {
+            *began = now;
+            *sent = 0;
+        }
+        *sent += 1;
+        *sent <= self.rate_limit
+    }
+
+    fn accept(&mut self, frame: &ArpFrame) -> bool {
+        let sender = match frame.sender_hardware_addr().try_into() {
+            Ok(bytes) => MacAddr::new(bytes),
+            Err(_) => return false,
+        };
+        let (sender_ip, target_ip) = match (
+            ipv4_addr(frame.sender_protocol_addr()),
+            ipv4_addr(frame.target_protocol_addr()),
+        ) {
+            (Some(sender_ip), Some(target_ip)) => (sender_ip, target_ip),
+            _ => return false,
+        };
+        if sender_ip == target_ip || !self.within_rate_limit(sender) {
+            return false;
+        }
+        match frame.opcode() {
+            op if op == ArpOp::Request as u16 => true,
+            op if op == ArpOp::Reply as u16 => self.requests.answer(sender_ip, self.timeout),
+            _ => false,
+        }
+    }
+}
+
+impl Processor for ArpFilter {
+    type Input = ArpFrame;
+    type Output = ArpFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        if self.accept(&frame) {
+            return Some(frame);
+        }
+        if let Some(dropped) = &self.dropped {
+            dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        None
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.dropped = Some(context.counter("dropped"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST_MAC: MacAddr = MacAddr {
+        bytes: [1, 2, 3, 4, 5, 6],
+    };
+    const ROUTER_MAC: MacAddr = MacAddr {
+        bytes: [0xa, 0xb, 0xc, 0xd, 0xe, 0xf],
+    };
+
+    fn arp(op: ArpOp, sender: [u8; 4], target: [u8; 4]) -> ArpFrame {
+        let mut frame = ArpFrame::new(6, 4);
+        frame
+            .set_hardware_type(1)
+            .set_protocol_type(0x0800)
+            .set_opcode(op as u16)
+            .set_sender_hardware_addr(HOST_MAC)
+            .set_sender_protocol_addr(IpAddr::from(sender))
+            .set_target_protocol_addr(IpAddr::from(target));
+        frame
+    }
+
+    #[test]
+    fn proxy_arp_answers_for_subnets() {
+        let mut proxy = ProxyArp::new(ROUTER_MAC, vec!["10.1.0.0/16".parse().unwrap()]);
+
+        let reply = proxy
+            .process(arp(ArpOp::Request, [10, 0, 0, 2], [10, 1, 2, 3]))
+            .unwrap();
+        assert_eq!(reply.opcode(), ArpOp::Reply as u16);
+        assert_eq!(reply.sender_hardware_addr(), ROUTER_MAC.bytes);
+        assert_eq!(reply.sender_protocol_addr(), [10, 1, 2, 3]);
+        assert_eq!(reply.target_hardware_addr(), HOST_MAC.bytes);
+        assert_eq!(reply.target_protocol_addr(), [10, 0, 0, 2]);
+        let frame = reply.frame();
+        assert_eq!(frame.dest_mac(), HOST_MAC);
+        assert_eq!(frame.src_mac(), ROUTER_MAC);
+
+        assert!(proxy
+            .process(arp(ArpOp::Request, [10, 0, 0, 2], [10, 2, 0, 1]))
+            .is_none());
+        assert!(proxy
+            .process(arp(ArpOp::Request, [10, 1, 2, 3], [10, 1, 2, 3]))
+            .is_none());
+    }
+
+    #[test]
+    fn filter_accepts_only_solicited_replies() {
+        let requests = Arc::new(ArpRequests::new());
+        let mut filter = ArpFilter::new(Arc::clone(&requests));
+
+        let reply = || arp(ArpOp::Reply, [10, 0, 0, 2], [10, 0, 0, 1]);
+        assert!(filter.process(reply()).is_none());
+        requests.sent(Ipv4Addr::new(10, 0, 0, 2));
+        assert!(filter.process(reply()).is_some());
+        assert!(filter.process(reply()).is_none());
+
+        // Gratuitous ARP is dropped, other requests pass.
+        assert!(filter
+            .process(arp(ArpOp::Request, [10, 0, 0, 2], [10, 0, 0, 2]))
+            .is_none());
+        assert!(filter
+            .process(arp(ArpOp::Request, [10, 0, 0, 2], [10, 0, 0, 1]))
+            .is_some());
+    }
+
+    #[test]
+    fn filter_rate_limits_senders() {
+        let mut filter = ArpFilter::new(Arc::new(ArpRequests::new())).rate_limit(3);
+        let passed = (0..5)
+            .filter_map(|_| filter.process(arp(ArpOp::Request, [10, 0, 0, 2], [10, 0, 0, 1])))
+            .count();
+        assert_eq!(passed, 3);
+    }
+
+    #[test]
+    fn watched_rate_limit_applies_to_the_next_frame() {
+        let (mut filter, rate_limit) = ArpFilter::new(Arc::new(ArpRequests::new()))
+            .rate_limit(3)
+            .watch();
+        let request = || arp(ArpOp::Request, [10, 0, 0, 2], [10, 0, 0, 1]);
+        assert!(filter.process(request()).is_some());
+
+        rate_limit.broadcast(1).unwrap();
+        assert!(filter.process(request()).is_none());
+        assert_eq!(filter.rate_limit, 1);
+
+        rate_limit.broadcast(10).unwrap();
+        assert!(filter.process(request()).is_some());
+    }
+}
@@ -0,0 +1,243 @@
+use crate::processor::Processor;
+use route_rs_packets::PacketData;
+use std::convert::TryInto;
+
+/// Length of the framing header placed in front of every compressed payload.
+///
+/// | byte 0  | byte 1    | bytes 2..=3                      |
+/// |---------|-----------|----------------------------------|
+/// | version | algorithm | uncompressed length (big endian) |
+pub const COMPRESSION_HEADER_LEN: usize = 4;
+
+/// Version of the framing header written by `Compress`, `Decompress` rejects anything else.
+pub const COMPRESSION_FRAME_VERSION: u8 = 1;
+
+/// The codec used for the body of a compressed frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    /// Body is stored as is, used when compressing would not make the payload smaller.
+    Stored,
+    Lz4,
+    Zstd,
+}
+
+impl CompressionAlgorithm {
+    fn to_byte(self) -> u8 {
+        match self {
+            CompressionAlgorithm::Stored => 0,
+            CompressionAlgorithm::Lz4 => 1,
+            CompressionAlgorithm::Zstd => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(CompressionAlgorithm::Stored),
+            1 => Some(CompressionAlgorithm::Lz4),
+            2 => Some(CompressionAlgorithm::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Compresses a payload and prepends the framing header, for carrying packets through a
+/// compressed tunnel to another route-rs instance, where `Decompress` undoes it.
+///
+/// If the compressed body would not be smaller than the input, the payload is stored uncompressed
+/// so the frame never grows by more than the header. Payloads longer than `u16::MAX` can not be
+/// described by the header and are dropped.
+///
+/// Compression is comparatively expensive, so this processor should run behind a `QueueLink` so it
+/// gets a task of its own rather than slowing down the links in front of it.
+pub struct Compress {
+    algorithm: CompressionAlgorithm,
+    zstd_level: i32,
+}
+
+impl Compress {
+    pub fn new(algorithm: CompressionAlgorithm) -> Self {
+        Compress {
+            algorithm,
+            zstd_level: zstd::DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+
+    /// Level passed to zstd, ignored by other algorithms.
+    pub fn zstd_level(self, level: i32) -> Self {
+        Compress {
+            algorithm: self.algorithm,
+            zstd_level: level,
+        }
+    }
+}
+
+impl Processor for Compress {
+    type Input = PacketData;
+    type Output = PacketData;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        if packet.len() > u16::MAX as usize {
+            return None;
+        }
+
+        let body = match self.algorithm {
+            CompressionAlgorithm::Stored => None,
+            CompressionAlgorithm::Lz4 => Some(lz4_flex::compress(&packet)),
+            CompressionAlgorithm::Zstd => zstd::block::compress(&packet, self.zstd_level).ok(),
+        };
+        let (algorithm, body) = match body {
+            Some(body) if body.len() < packet.len() => (self.algorithm, body),
+            _ => (CompressionAlgorithm::Stored, packet.clone()),
+        };
+
+        let mut frame = Vec::with_capacity(COMPRESSION_HEADER_LEN + body.len());
+        frame.push(COMPRESSION_FRAME_VERSION);
+        frame.push(algorithm.to_byte());
+        frame.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&body);
+        Some(frame)
+    }
+}
+
+/// Strips the framing header written by `Compress` and restores the original payload.
+///
+/// Frames with an unknown version or algorithm, and frames whose body does not decompress to the
+/// length given in the header, are dropped.
+#[derive(Default)]
+pub struct Decompress {}
+
+impl Decompress {
+    pub fn new() -> Self {
+        Decompress {}
+    }
+}
+
+impl Processor for Decompress {
+    type Input = PacketData;
+    type Output = PacketData;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        if packet.len() < COMPRESSION_HEADER_LEN || packet[0] != COMPRESSION_FRAME_VERSION {
+            return None;
+        }
+        let algorithm = CompressionAlgorithm::from_byte(packet[1])?;
+        let original_len = u16::from_be_bytes(packet[2..4].try_into().unwrap()) as usize;
+        let body = &packet[COMPRESSION_HEADER_LEN..];
+
+        let payload = match algorithm {
+            CompressionAlgorithm::Stored => Some(body.to_vec()),
+            CompressionAlgorithm::Lz4 => lz4_flex::decompress(body, original_len).ok(),
+            CompressionAlgorithm::Zstd => zstd::block::decompress(body, original_len).ok(),
+        }?;
+
+        if payload.len() == original_len {
+            Some(payload)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::{LinkBuilder, ProcessLinkBuilder};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    fn compressible_payload() -> PacketData {
+        b"route-rs route-rs route-rs route-rs route-rs route-rs route-rs".to_vec()
+    }
+
+    fn round_trip(algorithm: CompressionAlgorithm, payload: PacketData) -> PacketData {
+        let frame = Compress::new(algorithm).process(payload).unwrap();
+        Decompress::new().process(frame).unwrap()
+    }
+
+    #[test]
+    fn lz4_round_trip() {
+        let payload = compressible_payload();
+        let frame = Compress::new(CompressionAlgorithm::Lz4)
+            .process(payload.clone())
+            .unwrap();
+        assert_eq!(frame[0], COMPRESSION_FRAME_VERSION);
+        assert_eq!(frame[1], CompressionAlgorithm::Lz4.to_byte());
+        assert!(frame.len() < payload.len());
+        assert_eq!(Decompress::new().process(frame).unwrap(), payload);
+    }
+
+    #[test]
+    fn zstd_round_trip() {
+        let payload = compressible_payload();
+        let frame = Compress::new(CompressionAlgorithm::Zstd)
+            .zstd_level(1)
+            .process(payload.clone())
+            .unwrap();
+        assert_eq!(frame[1], CompressionAlgorithm::Zstd.to_byte());
+        assert_eq!(Decompress::new().process(frame).unwrap(), payload);
+    }
+
+    #[test]
+    fn incompressible_payload_is_stored() {
+        let payload = vec![0x1f, 0x8b, 0x08, 0x00];
+        let frame = Compress::new(CompressionAlgorithm::Lz4)
+            .process(payload.clone())
+            .unwrap();
+        assert_eq!(frame[1], CompressionAlgorithm::Stored.to_byte());
+        assert_eq!(frame.len(), COMPRESSION_HEADER_LEN + payload.len());
+        assert_eq!(
+            round_trip(CompressionAlgorithm::Lz4, payload.clone()),
+            payload
+        );
+    }
+
+    #[test]
+    fn empty_payload() {
+        assert_eq!(
+            round_trip(CompressionAlgorithm::Zstd, vec![]),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn oversized_payload_is_dropped() {
+        let payload = vec![0; u16::MAX as usize + 1];
+        assert_eq!(
+            Compress::new(CompressionAlgorithm::Lz4).process(payload),
+            None
+        );
+    }
+
+    #[test]
+    fn malformed_frames_are_dropped() {
+        let mut decompress = Decompress::new();
+        assert_eq!(decompress.process(vec![1, 0, 0]), None);
+        assert_eq!(decompress.process(vec![2, 0, 0, 0]), None);
+        assert_eq!(decompress.process(vec![1, 9, 0, 0]), None);
+        assert_eq!(decompress.process(vec![1, 0, 0, 5, 1, 2]), None);
+        assert_eq!(decompress.process(vec![1, 1, 0, 40, 0xff, 0xff]), None);
+    }
+
+    #[test]
+    fn compress_decompress_link() {
+        let packets = vec![compressible_payload(), vec![1, 2, 3], vec![]];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, mut egressors) = ProcessLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .processor(Compress::new(CompressionAlgorithm::Lz4))
+                .build_link();
+
+            let (mut decompress_runnables, decompress_egressors) = ProcessLink::new()
+                .ingressor(egressors.remove(0))
+                .processor(Decompress::new())
+                .build_link();
+            runnables.append(&mut decompress_runnables);
+
+            run_link((runnables, decompress_egressors)).await
+        });
+        assert_eq!(results[0], packets);
+    }
+}
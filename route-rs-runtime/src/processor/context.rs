@@ -0,0 +1,63 @@
+use crate::metrics::Registry;
+use crate::state::StateStore;
+use crate::utils::clock;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// What a processor is given by its link in `Processor::setup`: the router's shared state, its
+/// clock, and counters registered with its metrics, so a processor needn't be handed each of them
+/// through its constructor.
+#[derive(Debug, Clone)]
+pub struct ProcessorContext {
+    name: String,
+}
+
+impl ProcessorContext {
+    /// A context for the processor named `name`, which scopes the counters it registers.
+    pub fn new(name: &str) -> Self {
+        ProcessorContext {
+            name: String::from(name),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The router's shared state.
+    pub fn state(&self) -> &'static StateStore {
+        StateStore::global()
+    }
+
+    /// The time now, by the clock of the runtime, which stands still between timers under
+    /// `sim`. Processors should keep time with this rather than `Instant::now`.
+    pub fn now(&self) -> Instant {
+        clock::now()
+    }
+
+    /// A counter in the global metrics `Registry`, named `counter` under the name of the
+    /// processor, as in `nat.expired`.
+    pub fn counter(&self, counter: &str) -> Arc<AtomicU64> {
+        Registry::global().counter(&format!("{}.{}", self.name, counter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn scopes_counters_by_processor() {
+        let context = ProcessorContext::new("context-test");
+        context.counter("seen").fetch_add(2, Ordering::Relaxed);
+        ProcessorContext::new("context-other")
+            .counter("seen")
+            .fetch_add(1, Ordering::Relaxed);
+
+        let counters = Registry::global().counters();
+        assert!(counters.contains(&(String::from("context-test.seen"), 2)));
+        assert!(counters.contains(&(String::from("context-other.seen"), 1)));
+    }
+}
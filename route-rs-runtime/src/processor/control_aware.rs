@@ -0,0 +1,103 @@
+use crate::link::message::{ControlMsg, RouterMessage};
+use crate::processor::{Processor, ProcessorContext};
+
+/// Runs a processor on a stream of `RouterMessage`s. Packets are passed to the processor, and
+/// control messages are shown to its `control` hook, then forwarded as they are, so they keep
+/// their place among the packets around them.
+pub struct ControlAware<P: Processor> {
+    processor: P,
+}
+
+impl<P: Processor> ControlAware<P> {
+    pub fn new(processor: P) -> Self {
+        ControlAware { processor }
+    }
+}
+
+impl<P: Processor> Processor for ControlAware<P> {
+    type Input = RouterMessage<P::Input>;
+    type Output = RouterMessage<P::Output>;
+
+    fn process(&mut self, message: Self::Input) -> Option<Self::Output> {
+        match message {
+            RouterMessage::Data(packet) => self.processor.process(packet).map(RouterMessage::Data),
+            RouterMessage::Control(msg) => {
+                self.processor.control(&msg);
+                Some(RouterMessage::Control(msg))
+            }
+        }
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.processor.setup(context)
+    }
+
+    fn control(&mut self, msg: &ControlMsg) {
+        self.processor.control(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::{LinkBuilder, ProcessLinkBuilder};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    /// Passes on the number of distinct packets it has seen since it was last flushed.
+    struct Distinct {
+        seen: Vec<i32>,
+    }
+
+    impl Processor for Distinct {
+        type Input = i32;
+        type Output = usize;
+
+        fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+            if self.seen.contains(&packet) {
+                return None;
+            }
+            self.seen.push(packet);
+            Some(self.seen.len())
+        }
+
+        fn control(&mut self, msg: &ControlMsg) {
+            if *msg == ControlMsg::Flush {
+                self.seen.clear();
+            }
+        }
+    }
+
+    #[test]
+    fn forwards_control_messages_in_order() {
+        let messages = vec![
+            RouterMessage::Data(1),
+            RouterMessage::Data(2),
+            RouterMessage::Data(1),
+            RouterMessage::Control(ControlMsg::Flush),
+            RouterMessage::Data(1),
+            RouterMessage::Control(ControlMsg::Barrier(7)),
+        ];
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ProcessLink::new()
+                .ingressor(immediate_stream(messages))
+                .processor(ControlAware::new(Distinct { seen: vec![] }))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(
+            results[0],
+            vec![
+                RouterMessage::Data(1),
+                RouterMessage::Data(2),
+                RouterMessage::Control(ControlMsg::Flush),
+                RouterMessage::Data(1),
+                RouterMessage::Control(ControlMsg::Barrier(7)),
+            ]
+        );
+    }
+}
@@ -3,6 +3,7 @@ use rand::distributions::{Bernoulli, Distribution};
 use rand::rngs::StdRng;
 use rand::SeedableRng;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 /// DropProcessor
 /// Drops packets with weighted randomness.
@@ -10,6 +11,7 @@ pub struct Drop<A: Send + Clone> {
     phantom: PhantomData<A>,
     bernoulli: Bernoulli,
     rng: StdRng,
+    counter: Option<&'static AtomicU64>,
 }
 
 impl<A: Send + Clone> Drop<A> {
@@ -18,6 +20,7 @@ impl<A: Send + Clone> Drop<A> {
             phantom: PhantomData,
             bernoulli: Bernoulli::new(1.0).unwrap(),
             rng: StdRng::from_entropy(),
+            counter: None,
         }
     }
 
@@ -31,6 +34,7 @@ impl<A: Send + Clone> Drop<A> {
             phantom: self.phantom,
             bernoulli: Bernoulli::new(chance).unwrap(),
             rng: self.rng,
+            counter: self.counter,
         }
     }
 
@@ -39,8 +43,32 @@ impl<A: Send + Clone> Drop<A> {
             phantom: self.phantom,
             bernoulli: self.bernoulli,
             rng: StdRng::seed_from_u64(int_seed),
+            counter: self.counter,
         }
     }
+
+    /// Counts every dropped packet in `counter`. A static counter can be read from anywhere while
+    /// the router runs, which is how generated pipelines report their drops.
+    pub fn counter(self, counter: &'static AtomicU64) -> Self {
+        Drop {
+            phantom: self.phantom,
+            bernoulli: self.bernoulli,
+            rng: self.rng,
+            counter: Some(counter),
+        }
+    }
+
+    /// Decides whether the next packet is dropped, counting it if so, for links that do more with
+    /// the packets they drop than forget them.
+    pub(crate) fn drops_next(&mut self) -> bool {
+        if !self.bernoulli.sample(&mut self.rng) {
+            return false;
+        }
+        if let Some(counter) = self.counter {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        true
+    }
 }
 
 impl<A: Send + Clone> Processor for Drop<A> {
@@ -48,7 +76,7 @@ impl<A: Send + Clone> Processor for Drop<A> {
     type Output = A;
 
     fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
-        if self.bernoulli.sample(&mut self.rng) {
+        if self.drops_next() {
             None
         } else {
             Some(packet)
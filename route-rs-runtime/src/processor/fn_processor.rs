@@ -0,0 +1,89 @@
+use crate::processor::Processor;
+use std::marker::PhantomData;
+
+/// Processor that runs a closure on each packet, returning what it returns, for transformations
+/// and test fixtures too small to be worth a struct and impl of their own.
+///
+/// ```
+/// use route_rs_runtime::processor::{FnProcessor, Processor};
+///
+/// let mut double = FnProcessor::new(|packet: i32| Some(packet * 2));
+/// assert_eq!(double.process(21), Some(42));
+/// ```
+///
+/// A dynamic pipeline can place it by class name once it is registered with a factory building
+/// it, as can a graphgen pipeline, with a factory function named by `GenerateOptions::factory`.
+pub struct FnProcessor<I, O, F> {
+    process: F,
+    phantom: PhantomData<fn(I) -> O>,
+}
+
+impl<I, O, F: FnMut(I) -> Option<O>> FnProcessor<I, O, F> {
+    pub fn new(process: F) -> Self {
+        FnProcessor {
+            process,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<I, O, F> Processor for FnProcessor<I, O, F>
+where
+    I: Send + Clone,
+    O: Send + Clone,
+    F: FnMut(I) -> Option<O>,
+{
+    type Input = I;
+    type Output = O;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        (self.process)(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic::ProcessorRegistry;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::{LinkBuilder, ProcessLinkBuilder};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    fn runs_closure() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let mut seen = 0;
+            let link = ProcessLink::new()
+                .ingressor(immediate_stream(0..6))
+                .processor(FnProcessor::new(move |packet: i32| {
+                    seen += 1;
+                    if packet % 2 == 0 {
+                        Some(format!("{}/{}", packet, seen))
+                    } else {
+                        None
+                    }
+                }))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec!["0/1", "2/3", "4/5"]);
+    }
+
+    #[test]
+    fn registers_by_class_name() {
+        let mut registry = ProcessorRegistry::new();
+        registry.register_processor("AddN", |args| match args {
+            [n] => {
+                let n: i32 = n.parse().map_err(|_| format!("{} is not a number", n))?;
+                Ok(FnProcessor::new(move |packet: i32| Some(packet + n)))
+            }
+            _ => Err(String::from("AddN takes one argument")),
+        });
+
+        let mut add = registry.processor("AddN", &[String::from("3")]).unwrap();
+        assert_eq!(add.process(4), Some(7));
+    }
+}
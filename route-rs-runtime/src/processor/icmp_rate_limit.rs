@@ -0,0 +1,359 @@
+use crate::config::RateLimitConfig;
+use crate::processor::{Processor, ProcessorContext};
+use crate::utils::clock;
+use route_rs_packets::{IpProtocol, Ipv4Packet};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::watch;
+
+/// ICMP error types, RFC 792 and RFC 1812 section 4.3.2.
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMP_SOURCE_QUENCH: u8 = 4;
+const ICMP_REDIRECT: u8 = 5;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMP_PARAMETER_PROBLEM: u8 = 12;
+
+fn is_error(icmp_type: u8) -> bool {
+    matches!(
+        icmp_type,
+        ICMP_DEST_UNREACHABLE
+            | ICMP_SOURCE_QUENCH
+            | ICMP_REDIRECT
+            | ICMP_TIME_EXCEEDED
+            | ICMP_PARAMETER_PROBLEM
+    )
+}
+
+/// Whether `addr` can be the source of a packet from a single host, RFC 1812 section 4.2.2.11.
+fn is_unicast(addr: Ipv4Addr) -> bool {
+    !(addr.is_unspecified()
+        || addr.is_loopback()
+        || addr.is_broadcast()
+        || addr.is_multicast()
+        || addr.octets()[0] >= 240)
+}
+
+/// Whether an error must not be sent about the packet whose header, and the first 8 bytes of
+/// whose payload, are quoted in `quoted`, RFC 1812 section 4.3.2.7: an ICMP error itself, a
+/// fragment other than the first, or a packet not from, or not to, a single host.
+fn suppressed(quoted: &[u8]) -> bool {
+    if quoted.len() < 20 {
+        return true;
+    }
+    let header_len = usize::from(quoted[0] & 0x0f) * 4;
+    let fragment_offset = u16::from_be_bytes([quoted[6], quoted[7]]) & 0x1fff;
+    let protocol = quoted[9];
+    let src_addr = Ipv4Addr::new(quoted[12], quoted[13], quoted[14], quoted[15]);
+    let dest_addr = Ipv4Addr::new(quoted[16], quoted[17], quoted[18], quoted[19]);
+    if fragment_offset != 0 || !is_unicast(src_addr) || !is_unicast(dest_addr) {
+        return true;
+    }
+    protocol == 1 && quoted.get(header_len).copied().is_none_or(is_error)
+}
+
+/// Tokens for `burst` packets at once, refilled at `rate` a second.
+#[derive(Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: u64, now: Instant) -> Self {
+        TokenBucket {
+            tokens: burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: u64, burst: u64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate as f64).min(burst as f64);
+        self.updated = now;
+    }
+}
+
+/// The limits of an `IcmpRateLimiter`, as sent through the handle of `IcmpRateLimiter::watch` to
+/// change them while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcmpRateLimits {
+    pub per_source_rate: u64,
+    pub per_source_burst: u64,
+    pub global_rate: u64,
+    pub global_burst: u64,
+}
+
+/// Limits the ICMP errors the router sends, time exceeded, unreachable and the like, as RFC 1812
+/// section 4.3.2.8 requires, so it can't be used to reflect traffic at a victim, or be made to
+/// spend its uplink on errors.
+///
+/// The limiter takes the errors the router generates, and passes each only if both the host it
+/// is sent to and the router as a whole are within their limits, `per_source_limit` and
+/// `global_limit`, token buckets of so many packets a second, with bursts of so many. Errors that
+/// RFC 1812 section 4.3.2.7 says must not be sent at all, about ICMP errors, fragments other than
+/// the first, or packets not between two hosts, are dropped without counting against the limits.
+/// Packets other than ICMP errors pass untouched.
+///
+/// The limiter counts the errors it drops as `rate_limited` or `suppressed` under its name in the
+/// metrics. Its limits can be tuned while it runs, as by the management API, through the handle
+/// `watch` returns, and apply from the next error it sees.
+pub struct IcmpRateLimiter {
+    per_source_rate: u64,
+    per_source_burst: u64,
+    global_rate: u64,
+    global_burst: u64,
+    sources: HashMap<Ipv4Addr, TokenBucket>,
+    global: Option<TokenBucket>,
+    rate_limited: Option<Arc<AtomicU64>>,
+    suppressed: Option<Arc<AtomicU64>>,
+    watched: Option<watch::Receiver<IcmpRateLimits>>,
+}
+
+impl Default for IcmpRateLimiter {
+    fn default() -> Self {
+        IcmpRateLimiter::new()
+    }
+}
+
+impl IcmpRateLimiter {
+    pub fn new() -> Self {
+        IcmpRateLimiter {
+            per_source_rate: 10,
+            per_source_burst: 10,
+            global_rate: 100,
+            global_burst: 100,
+            sources: HashMap::new(),
+            global: None,
+            rate_limited: None,
+            suppressed: None,
+            watched: None,
+        }
+    }
+
+    /// Changes the errors sent to each host, `packets_per_second` after a first `burst`, default
+    /// value is 10 a second, with bursts of 10.
+    pub fn per_source_limit(self, packets_per_second: u64, burst: u64) -> Self {
+        assert!(burst > 0, "burst must be > 0");
+        IcmpRateLimiter {
+            per_source_rate: packets_per_second,
+            per_source_burst: burst,
+            global_rate: self.global_rate,
+            global_burst: self.global_burst,
+            sources: self.sources,
+            global: self.global,
+            rate_limited: self.rate_limited,
+            suppressed: self.suppressed,
+            watched: self.watched,
+        }
+    }
+
+    /// Changes the errors sent in all, `packets_per_second` after a first `burst`, default value
+    /// is 100 a second, with bursts of 100.
+    pub fn global_limit(self, packets_per_second: u64, burst: u64) -> Self {
+        assert!(burst > 0, "burst must be > 0");
+        IcmpRateLimiter {
+            per_source_rate: self.per_source_rate,
+            per_source_burst: self.per_source_burst,
+            global_rate: packets_per_second,
+            global_burst: burst,
+            sources: self.sources,
+            global: self.global,
+            rate_limited: self.rate_limited,
+            suppressed: self.suppressed,
+            watched: self.watched,
+        }
+    }
+
+    /// Takes the global limit from a rate limit of the `RouterConfig`, such as `icmp-errors`.
+    pub fn config(self, config: &RateLimitConfig) -> Self {
+        let burst = config.burst.unwrap_or(config.packets_per_second).max(1);
+        self.global_limit(config.packets_per_second, burst)
+    }
+
+    /// A handle to change the limits of the limiter while it runs, starting from those it has
+    /// been given so far. Limits sent through the handle replace any set after this.
+    pub fn watch(self) -> (Self, watch::Sender<IcmpRateLimits>) {
+        let (sender, watched) = watch::channel(self.limits());
+        let limiter = IcmpRateLimiter {
+            per_source_rate: self.per_source_rate,
+            per_source_burst: self.per_source_burst,
+            global_rate: self.global_rate,
+            global_burst: self.global_burst,
+            sources: self.sources,
+            global: self.global,
+            rate_limited: self.rate_limited,
+            suppressed: self.suppressed,
+            watched: Some(watched),
+        };
+        (limiter, sender)
+    }
+
+    pub fn limits(&self) -> IcmpRateLimits {
+        IcmpRateLimits {
+            per_source_rate: self.per_source_rate,
+            per_source_burst: self.per_source_burst,
+            global_rate: self.global_rate,
+            global_burst: self.global_burst,
+        }
+    }
+
+    /// Whether an error may be sent to `dest`, taking a token from its bucket and the global one
+    /// if so.
+    fn within_limits(&mut self, dest: Ipv4Addr) -> bool {
+        let now = clock::now();
+        if let Some(watched) = &self.watched {
+            let limits = *watched.borrow();
+            self.per_source_rate = limits.per_source_rate;
+            self.per_source_burst = limits.per_source_burst.max(1);
+            self.global_rate = limits.global_rate;
+            self.global_burst = limits.global_burst.max(1);
+        }
+        let (per_source_rate, per_source_burst) = (self.per_source_rate, self.per_source_burst);
+        let (global_rate, global_burst) = (self.global_rate, self.global_burst);
+        // Forgets hosts whose buckets are full again once there are enough to be worth sweeping.
+        if self.sources.len() >= 1024 {
+            self.sources.retain(|_, bucket| {
+                bucket.refill(per_source_rate, per_source_burst, now);
+                bucket.tokens < per_source_burst as f64
+            });
+        }
+
+        let global = self
+            .global
+            .get_or_insert_with(|| TokenBucket::new(global_burst, now));
+        global.refill(global_rate, global_burst, now);
+        let source = self
+            .sources
+            .entry(dest)
+            .or_insert_with(|| TokenBucket::new(per_source_burst, now));
+        source.refill(per_source_rate, per_source_burst, now);
+        if global.tokens < 1.0 || source.tokens < 1.0 {
+            return false;
+        }
+        global.tokens -= 1.0;
+        source.tokens -= 1.0;
+        true
+    }
+}
+
+impl Processor for IcmpRateLimiter {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let payload = packet.payload();
+        if packet.protocol() != IpProtocol::ICMP || payload.is_empty() || !is_error(payload[0]) {
+            return Some(packet);
+        }
+
+        let counter = if suppressed(payload.get(8..).unwrap_or(&[])) {
+            &self.suppressed
+        } else if !self.within_limits(packet.dest_addr()) {
+            &self.rate_limited
+        } else {
+            return Some(packet);
+        };
+        if let Some(counter) = counter {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        None
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.rate_limited = Some(context.counter("rate_limited"));
+        self.suppressed = Some(context.counter("suppressed"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+    /// A time exceeded error sent to `dest` about a UDP datagram it sent to `original_dest`.
+    fn time_exceeded(dest: Ipv4Addr, original_dest: Ipv4Addr) -> Ipv4Packet {
+        let mut original = Ipv4Packet::empty();
+        original.set_protocol(17);
+        original.set_src_addr(dest);
+        original.set_dest_addr(original_dest);
+        original.set_payload(&[0; 8]);
+
+        let mut message = vec![ICMP_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0];
+        message.extend_from_slice(&original.data[..28]);
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(1);
+        packet.set_src_addr(ROUTER);
+        packet.set_dest_addr(dest);
+        packet.set_payload(&message);
+        packet
+    }
+
+    #[test]
+    fn limits_each_host_and_the_router() {
+        let mut limiter = IcmpRateLimiter::new()
+            .per_source_limit(1, 2)
+            .global_limit(1, 3);
+        let target = Ipv4Addr::new(198, 51, 100, 7);
+        let hosts: Vec<Ipv4Addr> = (1..=3).map(|i| Ipv4Addr::new(10, 0, 0, i)).collect();
+
+        assert!(limiter.process(time_exceeded(hosts[0], target)).is_some());
+        assert!(limiter.process(time_exceeded(hosts[0], target)).is_some());
+        assert!(limiter.process(time_exceeded(hosts[0], target)).is_none());
+        assert!(limiter.process(time_exceeded(hosts[1], target)).is_some());
+        assert!(limiter.process(time_exceeded(hosts[2], target)).is_none());
+
+        // Echo replies are not errors, and aren't limited.
+        let mut reply = time_exceeded(hosts[0], target);
+        let mut message = reply.payload().to_vec();
+        message[0] = 0;
+        reply.set_payload(&message);
+        assert!(limiter.process(reply).is_some());
+    }
+
+    #[test]
+    fn suppresses_errors_about_broadcasts_and_errors() {
+        let mut limiter = IcmpRateLimiter::new();
+        let host = Ipv4Addr::new(10, 0, 0, 1);
+        assert!(limiter
+            .process(time_exceeded(host, Ipv4Addr::new(255, 255, 255, 255)))
+            .is_none());
+        assert!(limiter
+            .process(time_exceeded(host, Ipv4Addr::new(224, 0, 0, 251)))
+            .is_none());
+
+        // An error about an error.
+        let error = time_exceeded(host, Ipv4Addr::new(198, 51, 100, 7));
+        let mut message = vec![ICMP_DEST_UNREACHABLE, 1, 0, 0, 0, 0, 0, 0];
+        message.extend_from_slice(&error.data[..28]);
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(1);
+        packet.set_src_addr(ROUTER);
+        packet.set_dest_addr(host);
+        packet.set_payload(&message);
+        assert!(limiter.process(packet).is_none());
+        assert_eq!(limiter.sources.len(), 0);
+    }
+
+    #[test]
+    fn watched_limits_apply_to_the_next_error() {
+        let (mut limiter, limits) = IcmpRateLimiter::new().watch();
+        let target = Ipv4Addr::new(198, 51, 100, 7);
+        let host = |i| Ipv4Addr::new(10, 0, 0, i);
+        assert!(limiter.process(time_exceeded(host(1), target)).is_some());
+
+        // A burst of 1 leaves the global bucket a single token, and a rate of 0 never refills it.
+        let tightened = IcmpRateLimits {
+            global_rate: 0,
+            global_burst: 1,
+            ..limiter.limits()
+        };
+        limits.broadcast(tightened).unwrap();
+        assert!(limiter.process(time_exceeded(host(2), target)).is_some());
+        assert!(limiter.process(time_exceeded(host(3), target)).is_none());
+        assert_eq!(limiter.limits(), tightened);
+    }
+}
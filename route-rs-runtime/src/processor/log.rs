@@ -1,20 +1,131 @@
-use crate::processor::Processor;
-use std::fmt::Debug;
-use std::io::{BufWriter, Write};
+use crate::link::event::EventSink;
+use crate::processor::{Processor, ProcessorContext};
+use crate::utils::clock;
+use route_rs_packets::{EthernetFrame, Ipv4Packet, Ipv6Packet};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
 use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-/// Processor that logs incoming packets with Debug information, delimited with newlines.
-pub struct Log<A, W: Write> {
-    phantom: PhantomData<A>,
-    log_writer: BufWriter<W>,
+/// The header fields that are fields of their own in the events of `LogTarget::Tracing`.
+const TRACING_FIELDS: [&str; 9] = [
+    "src",
+    "dest",
+    "protocol",
+    "len",
+    "ttl",
+    "hop_limit",
+    "src_mac",
+    "dest_mac",
+    "ether_type",
+];
+
+/// The header fields of a packet a `LogProcessor` can log, by name.
+pub trait LogFields {
+    fn log_fields(&self) -> Vec<(&'static str, String)>;
 }
 
-impl<A, W: Write> Log<A, W> {
-    pub fn new(writer: W) -> Log<A, W> {
-        Log {
-            phantom: PhantomData,
-            log_writer: BufWriter::new(writer),
+impl LogFields for EthernetFrame {
+    fn log_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("src_mac", self.src_mac().to_string()),
+            ("dest_mac", self.dest_mac().to_string()),
+            ("ether_type", format!("{:#06x}", self.ether_type())),
+            ("len", self.data.len().to_string()),
+        ]
+    }
+}
+
+impl LogFields for Ipv4Packet {
+    fn log_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("src", self.src_addr().to_string()),
+            ("dest", self.dest_addr().to_string()),
+            ("protocol", format!("{:?}", self.protocol())),
+            ("ttl", self.ttl().to_string()),
+            ("len", self.total_len().to_string()),
+        ]
+    }
+}
+
+impl LogFields for Ipv6Packet {
+    fn log_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("src", self.src_addr().to_string()),
+            ("dest", self.dest_addr().to_string()),
+            ("protocol", format!("{:?}", self.next_header())),
+            ("hop_limit", self.hop_limit().to_string()),
+            ("len", (40 + usize::from(self.payload_length())).to_string()),
+        ]
+    }
+}
+
+/// A log file that is rotated once it grows past `max_bytes`: it is renamed with a `.1` suffix,
+/// older files move up a number, and all but the newest `keep` are removed.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    writer: BufWriter<File>,
+    written: u64,
+}
+
+impl RotatingFile {
+    /// Appends to the file at `path`, creating it if there is none.
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        assert!(max_bytes > 0, "max_bytes must be > 0");
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            max_bytes,
+            keep,
+            writer: BufWriter::new(file),
+            written,
+        })
+    }
+
+    /// The path of the `n`th newest rotated file.
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for n in (1..self.keep).rev() {
+                if self.rotated(n).exists() {
+                    fs::rename(self.rotated(n), self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.writer = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        Ok(())
+    }
+
+    /// Writes a line, rotating first if it would take the file past `max_bytes`. A line longer
+    /// than that gets a file of its own.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.max_bytes {
+            self.rotate()?;
         }
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.written += len;
+        Ok(())
     }
 }
 
@@ -23,73 +134,477 @@ impl<A, W: Write> Log<A, W> {
 /// the process of dropping will be ignored. Calling flush ensures that the buffer is empty and thus
 /// dropping will not even attempt file operations."
 /// https://doc.rust-lang.org/std/io/struct.BufWriter.html
-impl<A, W: Write> Drop for Log<A, W> {
+impl Drop for RotatingFile {
     fn drop(&mut self) {
-        self.log_writer.flush().unwrap();
+        let _ = self.writer.flush();
     }
 }
 
-impl<A: Send + Clone + Debug, W: Write> Processor for Log<A, W> {
+/// Where a `LogProcessor` sends its records.
+pub enum LogTarget {
+    /// An `INFO` event of the `route_rs::packet` target, for whatever `tracing` subscriber the
+    /// router has installed. The verdict, and the header fields `LogFields` gives, are fields of
+    /// the event, so subscribers can filter and index on them, and any others, such as
+    /// annotations, are put together as `key=value` text in its `other` field.
+    Tracing,
+    /// A line of each record, prefixed with the time it was logged.
+    File(RotatingFile),
+    /// A `LinkEvent::Activity` of each record, for whatever reads the sink's events, such as a
+    /// `SyslogExporter`.
+    Events(EventSink),
+}
+
+/// A record of a packet a `LogProcessor` logged, written out as `key=value` pairs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub verdict: Option<&'static str>,
+    pub fields: Vec<(&'static str, String)>,
+}
+
+impl LogRecord {
+    /// The value of the field of a name, if the record has it.
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verdict = self.verdict.map(|verdict| ("verdict", verdict.to_string()));
+        for (n, (key, value)) in verdict.iter().chain(&self.fields).enumerate() {
+            if n > 0 {
+                write!(f, " ")?;
+            }
+            if value.is_empty()
+                || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=')
+            {
+                write!(f, "{}={:?}", key, value)?;
+            } else {
+                write!(f, "{}={}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Logs what it sees of packets as structured records, and passes them on untouched.
+///
+/// Each record has the header fields of the packet, or those `select`ed, the annotations the
+/// processor was given, such as the interface or branch it is on, and the verdict of the branch,
+/// such as `dropped` after a firewall's reject branch. Logging every packet is more than any log
+/// keeps up with at real traffic rates, so the processor logs only one in `sample` packets, and
+/// no more than `max_rate` records a second, counting the sampled packets it skipped for the rate
+/// as `suppressed` under its name in the metrics, and those it logged as `logged`.
+pub struct LogProcessor<A> {
+    phantom: PhantomData<A>,
+    target: LogTarget,
+    select: Option<Vec<&'static str>>,
+    annotations: Vec<(&'static str, String)>,
+    verdict: Option<&'static str>,
+    sample: u64,
+    max_rate: Option<u64>,
+    seen: u64,
+    tokens: f64,
+    updated: Option<Instant>,
+    name: String,
+    logged: Option<Arc<AtomicU64>>,
+    suppressed: Option<Arc<AtomicU64>>,
+}
+
+impl<A> LogProcessor<A> {
+    pub fn new(target: LogTarget) -> Self {
+        LogProcessor {
+            phantom: PhantomData,
+            target,
+            select: None,
+            annotations: vec![],
+            verdict: None,
+            sample: 1,
+            max_rate: None,
+            seen: 0,
+            tokens: 0.0,
+            updated: None,
+            name: String::from("log"),
+            logged: None,
+            suppressed: None,
+        }
+    }
+
+    /// Logs only the named header fields, in the order the packet gives them, rather than all.
+    pub fn select(self, fields: &[&'static str]) -> Self {
+        LogProcessor {
+            phantom: PhantomData,
+            target: self.target,
+            select: Some(fields.to_vec()),
+            annotations: self.annotations,
+            verdict: self.verdict,
+            sample: self.sample,
+            max_rate: self.max_rate,
+            seen: self.seen,
+            tokens: self.tokens,
+            updated: self.updated,
+            name: self.name,
+            logged: self.logged,
+            suppressed: self.suppressed,
+        }
+    }
+
+    /// Adds a field to every record, after those of the packet.
+    pub fn annotate(self, key: &'static str, value: &str) -> Self {
+        let mut annotations = self.annotations;
+        annotations.push((key, String::from(value)));
+        LogProcessor {
+            phantom: PhantomData,
+            target: self.target,
+            select: self.select,
+            annotations,
+            verdict: self.verdict,
+            sample: self.sample,
+            max_rate: self.max_rate,
+            seen: self.seen,
+            tokens: self.tokens,
+            updated: self.updated,
+            name: self.name,
+            logged: self.logged,
+            suppressed: self.suppressed,
+        }
+    }
+
+    /// Leads every record with the verdict of the branch the processor is on.
+    pub fn verdict(self, verdict: &'static str) -> Self {
+        LogProcessor {
+            phantom: PhantomData,
+            target: self.target,
+            select: self.select,
+            annotations: self.annotations,
+            verdict: Some(verdict),
+            sample: self.sample,
+            max_rate: self.max_rate,
+            seen: self.seen,
+            tokens: self.tokens,
+            updated: self.updated,
+            name: self.name,
+            logged: self.logged,
+            suppressed: self.suppressed,
+        }
+    }
+
+    /// Logs the first of every `one_in` packets, default value is 1, every packet.
+    pub fn sample(self, one_in: u64) -> Self {
+        assert!(one_in > 0, "sample must be > 0");
+        LogProcessor {
+            phantom: PhantomData,
+            target: self.target,
+            select: self.select,
+            annotations: self.annotations,
+            verdict: self.verdict,
+            sample: one_in,
+            max_rate: self.max_rate,
+            seen: self.seen,
+            tokens: self.tokens,
+            updated: self.updated,
+            name: self.name,
+            logged: self.logged,
+            suppressed: self.suppressed,
+        }
+    }
+
+    /// Caps the records logged at `records_per_second`, in bursts of up to as many, by default
+    /// there is no cap.
+    pub fn max_rate(self, records_per_second: u64) -> Self {
+        assert!(records_per_second > 0, "max_rate must be > 0");
+        LogProcessor {
+            phantom: PhantomData,
+            target: self.target,
+            select: self.select,
+            annotations: self.annotations,
+            verdict: self.verdict,
+            sample: self.sample,
+            max_rate: Some(records_per_second),
+            seen: self.seen,
+            tokens: records_per_second as f64,
+            updated: self.updated,
+            name: self.name,
+            logged: self.logged,
+            suppressed: self.suppressed,
+        }
+    }
+
+    /// Whether the rate allows another record now, taking a token for it if so.
+    fn within_rate(&mut self) -> bool {
+        let rate = match self.max_rate {
+            Some(rate) => rate as f64,
+            None => return true,
+        };
+        let now = clock::now();
+        if let Some(updated) = self.updated {
+            let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate).min(rate);
+        }
+        self.updated = Some(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn write(&mut self, record: &LogRecord) {
+        match &mut self.target {
+            LogTarget::Tracing => {
+                let field = |key| record.field(key);
+                let other = LogRecord {
+                    verdict: None,
+                    fields: record
+                        .fields
+                        .iter()
+                        .filter(|(key, _)| !TRACING_FIELDS.contains(key))
+                        .cloned()
+                        .collect(),
+                };
+                let other = Some(other.to_string()).filter(|other| !other.is_empty());
+                tracing::info!(
+                    target: "route_rs::packet",
+                    processor = %self.name,
+                    verdict = record.verdict,
+                    src = field("src"),
+                    dest = field("dest"),
+                    protocol = field("protocol"),
+                    len = field("len"),
+                    ttl = field("ttl"),
+                    hop_limit = field("hop_limit"),
+                    src_mac = field("src_mac"),
+                    dest_mac = field("dest_mac"),
+                    ether_type = field("ether_type"),
+                    other = other.as_deref(),
+                    "packet"
+                )
+            }
+            LogTarget::Events(event_sink) => event_sink.activity(record.to_string()),
+            LogTarget::File(file) => {
+                let at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                // A log that can't be written is no reason to stop forwarding packets.
+                let _ = file.write_line(&format!(
+                    "ts={}.{:06} processor={} {}",
+                    at.as_secs(),
+                    at.subsec_micros(),
+                    self.name,
+                    record
+                ));
+            }
+        }
+    }
+}
+
+impl<A: LogFields + Send + Clone> Processor for LogProcessor<A> {
     type Input = A;
     type Output = A;
 
     fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
-        self.log_writer
-            .write_all(format!("{:?}\n", packet).as_ref())
-            .unwrap();
+        let sampled = self.seen.is_multiple_of(self.sample);
+        self.seen += 1;
+        if !sampled {
+            return Some(packet);
+        }
+        if !self.within_rate() {
+            if let Some(suppressed) = &self.suppressed {
+                suppressed.fetch_add(1, Ordering::Relaxed);
+            }
+            return Some(packet);
+        }
+
+        let mut fields = packet.log_fields();
+        if let Some(select) = &self.select {
+            fields.retain(|(key, _)| select.contains(key));
+        }
+        fields.extend(self.annotations.iter().cloned());
+        let record = LogRecord {
+            verdict: self.verdict,
+            fields,
+        };
+        self.write(&record);
+        if let Some(logged) = &self.logged {
+            logged.fetch_add(1, Ordering::Relaxed);
+        }
         Some(packet)
     }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.name = String::from(context.name());
+        self.logged = Some(context.counter("logged"));
+        self.suppressed = Some(context.counter("suppressed"));
+    }
 }
 
-// TODO: Find common testing abstraction for logging processors
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::File;
-    use std::fs::{create_dir_all, remove_file};
-    use std::io::Read;
-    use std::path::Path;
+    use crate::metrics::Registry;
+    use std::net::Ipv4Addr;
+    use std::sync::Mutex;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
     use uuid::Uuid;
 
-    fn test_log_processor(packets: Vec<i32>, expected_log: &str) {
-        let log_dir = Path::new("test_logs");
-        let log_filename = format!("{}.log", Uuid::new_v4());
-        let log_path = log_dir.join(log_filename);
-        create_dir_all(log_dir).unwrap();
-        let log_file = File::create(log_path.clone()).unwrap();
+    fn packet(n: u8) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, n));
+        packet.set_dest_addr(Ipv4Addr::new(192, 168, 0, 1));
+        packet
+    }
+
+    fn log_path() -> PathBuf {
+        std::env::temp_dir().join(format!("{}.log", Uuid::new_v4()))
+    }
 
-        let mut proc = Log::new(log_file);
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
 
-        let res_packets: Vec<i32> = packets
-            .clone()
-            .into_iter()
-            .map(|packet| proc.process(packet).unwrap())
-            .collect();
-        assert_eq!(res_packets, packets); // assert identity
+    #[test]
+    fn writes_records_to_a_rotating_file() {
+        let path = log_path();
+        let mut log =
+            LogProcessor::new(LogTarget::File(RotatingFile::open(&path, 250, 1).unwrap()))
+                .select(&["src", "dest"])
+                .annotate("branch", "wan uplink")
+                .verdict("accepted");
+        log.setup(&ProcessorContext::new("log-test-rotating"));
 
-        std::mem::drop(proc); // dropping to flush internal BufWriter
+        for n in 0..4 {
+            assert_eq!(log.process(packet(n)).unwrap().data, packet(n).data);
+        }
+        std::mem::drop(log); // dropping to flush internal BufWriter
 
-        let mut log_file = File::open(log_path.clone()).unwrap();
-        let mut contents = String::new();
-        log_file.read_to_string(&mut contents).unwrap();
-        assert_eq!(contents, expected_log);
-        remove_file(log_path).unwrap();
+        // Each line is about 100 bytes, so two fit in a file, and only one older file is kept.
+        let rotated = PathBuf::from(format!("{}.1", path.display()));
+        let (older, newer) = (lines(&rotated), lines(&path));
+        assert_eq!((older.len(), newer.len()), (2, 2));
+        assert!(newer[1].starts_with("ts="));
+        assert!(newer[1].ends_with(
+            " processor=log-test-rotating verdict=accepted src=10.0.0.3 dest=192.168.0.1 \
+             branch=\"wan uplink\""
+        ));
+        assert!(!PathBuf::from(format!("{}.2", path.display())).exists());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
     }
 
     #[test]
-    fn writes_nothing() {
-        test_log_processor(vec![], "");
+    fn samples_and_caps_the_rate() {
+        let path = log_path();
+        let mut log = LogProcessor::new(LogTarget::File(
+            RotatingFile::open(&path, 1 << 20, 0).unwrap(),
+        ))
+        .select(&["src"])
+        .sample(3)
+        .max_rate(2);
+        log.setup(&ProcessorContext::new("log-test-sampled"));
+
+        for n in 0..12 {
+            assert!(log.process(packet(n)).is_some());
+        }
+        std::mem::drop(log);
+
+        // Packets 0, 3, 6 and 9 are sampled, and only the first two fit in the rate.
+        let logged = lines(&path);
+        assert_eq!(logged.len(), 2);
+        assert!(logged[0].ends_with("src=10.0.0.0"));
+        assert!(logged[1].ends_with("src=10.0.0.3"));
+        let counters = Registry::global().counters();
+        assert!(counters.contains(&(String::from("log-test-sampled.logged"), 2)));
+        assert!(counters.contains(&(String::from("log-test-sampled.suppressed"), 2)));
+        fs::remove_file(&path).unwrap();
     }
 
-    #[test]
-    fn writes_packet() {
-        test_log_processor(vec![10], "10\n");
+    /// The target of an event, and its fields.
+    type Collected = (String, Vec<(String, String)>);
+
+    /// Keeps the fields of the events it is given.
+    #[derive(Default)]
+    struct Collector {
+        events: Mutex<Vec<Collected>>,
     }
 
-    #[test]
-    fn writes_stream_of_packets() {
-        test_log_processor((0..10).collect(), "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+    struct Fields<'a>(&'a mut Vec<(String, String)>);
+
+    impl<'a> Visit for Fields<'a> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0
+                .push((String::from(field.name()), String::from(value)));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .push((String::from(field.name()), format!("{:?}", value)));
+        }
+    }
+
+    impl Subscriber for &'static Collector {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &Attributes) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, _: &Record) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event) {
+            let mut fields = vec![];
+            event.record(&mut Fields(&mut fields));
+            self.events
+                .lock()
+                .unwrap()
+                .push((String::from(event.metadata().target()), fields));
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
     }
 
-    // TODO: Add tests for other impl Write's like network sockets
+    #[test]
+    fn emits_tracing_events() {
+        let collector: &'static Collector = Box::leak(Box::new(Collector::default()));
+        let mut log = LogProcessor::new(LogTarget::Tracing)
+            .select(&["dest", "ttl"])
+            .annotate("branch", "wan uplink")
+            .verdict("dropped");
+        log.setup(&ProcessorContext::new("firewall-log"));
+
+        tracing::subscriber::with_default(collector, || {
+            log.process(packet(1));
+        });
+
+        let events = collector.events.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![(
+                String::from("route_rs::packet"),
+                vec![
+                    (String::from("message"), String::from("packet")),
+                    (String::from("processor"), String::from("firewall-log")),
+                    (String::from("verdict"), String::from("dropped")),
+                    (String::from("dest"), String::from("192.168.0.1")),
+                    (String::from("ttl"), String::from("0")),
+                    (String::from("other"), String::from("branch=\"wan uplink\"")),
+                ]
+            )]
+        );
+    }
 }
@@ -7,27 +7,74 @@
 //! While there are many provided processors that can be used to implement a router, users of route-rs that need specifc functionality
 //! in their router most likely will implement their own custom processors, conforming to the laid out processor standard.
 
+mod context;
+pub use self::context::*;
+
+mod control_aware;
+pub use self::control_aware::*;
+
 mod identity;
 pub use self::identity::*;
 
 mod transform_from;
 pub use self::transform_from::*;
 
+mod fn_processor;
+pub use self::fn_processor::*;
+
 mod drop;
 pub use self::drop::*;
 
 mod dec_ip_hop;
 pub use self::dec_ip_hop::*;
 
+mod arp;
+pub use self::arp::*;
+
+mod alg;
+pub use self::alg::*;
+
+mod icmp_rate_limit;
+pub use self::icmp_rate_limit::*;
+
+mod pmtu;
+pub use self::pmtu::*;
+
+mod mss_clamp;
+pub use self::mss_clamp::*;
+
 mod log;
 pub use self::log::*;
 
-mod file_log;
-pub use self::file_log::*;
+mod sequence;
+pub use self::sequence::*;
+
+mod tunnel;
+pub use self::tunnel::*;
+
+mod vlan;
+pub use self::vlan::*;
+
+#[cfg(feature = "compression")]
+mod compress;
+#[cfg(feature = "compression")]
+pub use self::compress::*;
+
+use crate::link::message::ControlMsg;
 
 pub trait Processor {
     type Input: Send + Clone;
     type Output: Send + Clone;
 
     fn process(&mut self, packet: Self::Input) -> Option<Self::Output>;
+
+    /// Called once by the link running the processor as the link is built, before any packet is
+    /// processed. Processors that keep state shared with the rest of the router, count things, or
+    /// keep time take what they need from the context here.
+    fn setup(&mut self, _context: &ProcessorContext) {}
+
+    /// Called with each control message that reaches the processor, when it is run in a
+    /// `ControlAware`, before the message is forwarded. Processors that keep tables or caches empty
+    /// them on a `ControlMsg::Flush`.
+    fn control(&mut self, _msg: &ControlMsg) {}
 }
@@ -0,0 +1,176 @@
+use crate::processor::{PmtuCache, Processor, ProcessorContext, PMTU_CACHE_STATE};
+use route_rs_packets::{update_checksum, IpProtocol, Ipv4Packet};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_OPTION_MSS: u8 = 2;
+
+/// The bytes of IPv4 and TCP headers without options, which an MSS leaves out of the MTU.
+const TCP_IPV4_HEADERS: usize = 40;
+
+/// Lowers the maximum segment size offered by TCP SYNs to `mss`, so the connections through the
+/// router send segments that fit, as a router in front of a tunnel or PPPoE link must when ICMP
+/// errors are filtered somewhere along the path and path MTU discovery doesn't work.
+///
+/// With a `PmtuCache`, the MSS is lowered further to fit the path MTU the cache knows to the
+/// sender of the SYN, since the MSS it offers limits what is sent to it. The clamp uses the cache
+/// shared in the `StateStore` under `PMTU_CACHE_STATE` unless it is given one, or told not to use
+/// one with `pmtu(false)`. Only SYNs offering an MSS are changed, and their checksums updated.
+pub struct TcpMssClamp {
+    mss: u16,
+    use_pmtu: bool,
+    cache: Option<Arc<PmtuCache>>,
+}
+
+impl TcpMssClamp {
+    pub fn new(mss: u16) -> Self {
+        TcpMssClamp {
+            mss,
+            use_pmtu: true,
+            cache: None,
+        }
+    }
+
+    /// Lowers the MSS to fit the path MTUs of `cache`.
+    pub fn cache(self, cache: Arc<PmtuCache>) -> Self {
+        TcpMssClamp {
+            mss: self.mss,
+            use_pmtu: true,
+            cache: Some(cache),
+        }
+    }
+
+    /// Changes use_pmtu, whether the MSS is lowered to fit path MTUs, default value is true.
+    pub fn pmtu(self, use_pmtu: bool) -> Self {
+        TcpMssClamp {
+            mss: self.mss,
+            use_pmtu,
+            cache: self.cache,
+        }
+    }
+
+    fn limit(&self, packet: &Ipv4Packet) -> u16 {
+        let path_mss = self
+            .cache
+            .as_ref()
+            .filter(|_| self.use_pmtu)
+            .and_then(|cache| cache.mtu(IpAddr::V4(packet.src_addr())))
+            .map(|mtu| mtu.saturating_sub(TCP_IPV4_HEADERS) as u16);
+        path_mss.map_or(self.mss, |path_mss| path_mss.min(self.mss))
+    }
+}
+
+impl Processor for TcpMssClamp {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, mut packet: Self::Input) -> Option<Self::Output> {
+        let start = packet.payload_offset;
+        if packet.protocol() != IpProtocol::TCP
+            || packet.data.len() < start + 20
+            || packet.data[start + 13] & TCP_SYN == 0
+        {
+            return Some(packet);
+        }
+        let header_len =
+            (usize::from(packet.data[start + 12] >> 4) * 4).min(packet.data.len() - start);
+        let limit = self.limit(&packet);
+
+        let mut at = start + 20;
+        while at < start + header_len {
+            let kind = packet.data[at];
+            match kind {
+                0 => break,
+                1 => at += 1,
+                _ => {
+                    let len = usize::from(*packet.data.get(at + 1).unwrap_or(&0));
+                    if len < 2 || at + len > start + header_len {
+                        break;
+                    }
+                    if kind == TCP_OPTION_MSS && len == 4 {
+                        let mss = u16::from_be_bytes([packet.data[at + 2], packet.data[at + 3]]);
+                        if mss > limit {
+                            packet.data[at + 2..at + 4].copy_from_slice(&limit.to_be_bytes());
+                            let checksum = u16::from_be_bytes([
+                                packet.data[start + 16],
+                                packet.data[start + 17],
+                            ]);
+                            let checksum = update_checksum(checksum, mss, limit);
+                            packet.data[start + 16..start + 18]
+                                .copy_from_slice(&checksum.to_be_bytes());
+                        }
+                        break;
+                    }
+                    at += len;
+                }
+            }
+        }
+        Some(packet)
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        if self.use_pmtu && self.cache.is_none() {
+            self.cache = Some(
+                context
+                    .state()
+                    .get_or_insert_with(PMTU_CACHE_STATE, PmtuCache::new),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::internet_checksum;
+    use std::net::Ipv4Addr;
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+
+    /// A SYN-ACK from the server, offering `mss`, with its checksum over the segment alone, which
+    /// is enough to check it is kept up to date.
+    fn syn_ack(mss: u16) -> Ipv4Packet {
+        let mut segment = vec![0; 28];
+        segment[12] = 0x70;
+        segment[13] = TCP_SYN | 0x10;
+        segment[20..24].copy_from_slice(&[1, 1, 4, 2]);
+        segment[24..28].copy_from_slice(&[TCP_OPTION_MSS, 4, 0, 0]);
+        segment[26..28].copy_from_slice(&mss.to_be_bytes());
+        let checksum = internet_checksum(&segment);
+        segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(6);
+        packet.set_src_addr(SERVER);
+        packet.set_payload(&segment);
+        packet
+    }
+
+    fn offered(packet: &Ipv4Packet) -> u16 {
+        u16::from_be_bytes([packet.payload()[26], packet.payload()[27]])
+    }
+
+    #[test]
+    fn clamps_syns() {
+        let mut clamp = TcpMssClamp::new(1452).pmtu(false);
+        let clamped = clamp.process(syn_ack(1460)).unwrap();
+        assert_eq!(offered(&clamped), 1452);
+        assert_eq!(internet_checksum(&clamped.payload()), 0);
+
+        assert_eq!(offered(&clamp.process(syn_ack(1400)).unwrap()), 1400);
+        // Segments other than SYNs are left alone.
+        let mut ack = syn_ack(1460);
+        ack.data[20 + 13] = 0x10;
+        assert_eq!(offered(&clamp.process(ack).unwrap()), 1460);
+    }
+
+    #[test]
+    fn clamps_to_path_mtu() {
+        let cache = Arc::new(PmtuCache::new());
+        let mut clamp = TcpMssClamp::new(1452).cache(Arc::clone(&cache));
+        cache.update(IpAddr::V4(SERVER), 1400);
+        let clamped = clamp.process(syn_ack(1460)).unwrap();
+        assert_eq!(offered(&clamped), 1360);
+        assert_eq!(internet_checksum(&clamped.payload()), 0);
+    }
+}
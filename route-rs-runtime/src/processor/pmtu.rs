@@ -0,0 +1,218 @@
+use crate::processor::{Processor, ProcessorContext};
+use crate::utils::clock;
+use route_rs_packets::{
+    EthernetFrame, IpProtocol, Ipv4Packet, Ipv6Packet, ICMPV6_PACKET_TOO_BIG,
+    ICMP_DEST_UNREACHABLE, ICMP_FRAGMENTATION_NEEDED, IPV4_ETHER_TYPE, IPV6_ETHER_TYPE,
+    IPV6_MIN_MTU,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// The name the path MTU cache is shared under in the `StateStore`, unless processors are given
+/// their own `PmtuCache`.
+pub const PMTU_CACHE_STATE: &str = "pmtu-cache";
+
+/// The smallest MTU an IPv4 path may be said to have, RFC 791.
+const IPV4_MIN_MTU: usize = 68;
+
+/// The MTUs of the paths to destinations, as learned from the ICMP errors of the routers along
+/// them, each forgotten after `lifetime`, 10 minutes by default as RFC 1191 section 6.3
+/// suggests, so a path that has grown again is found.
+pub struct PmtuCache {
+    paths: RwLock<HashMap<IpAddr, (usize, Instant)>>,
+    lifetime: Duration,
+}
+
+impl Default for PmtuCache {
+    fn default() -> Self {
+        PmtuCache::new()
+    }
+}
+
+impl PmtuCache {
+    pub fn new() -> Self {
+        PmtuCache::with_lifetime(Duration::from_secs(600))
+    }
+
+    pub fn with_lifetime(lifetime: Duration) -> Self {
+        PmtuCache {
+            paths: RwLock::new(HashMap::new()),
+            lifetime,
+        }
+    }
+
+    /// The MTU of the path to `dest`, if one has been learned and hasn't expired.
+    pub fn mtu(&self, dest: IpAddr) -> Option<usize> {
+        let now = clock::now();
+        self.paths
+            .read()
+            .unwrap()
+            .get(&dest)
+            .filter(|(_, expires)| now < *expires)
+            .map(|(mtu, _)| *mtu)
+    }
+
+    /// Records that the path to `dest` has an MTU of `mtu`, for `lifetime` from now. An MTU below
+    /// the least the family allows is taken as that least, and one above what is already known is
+    /// ignored until what is known expires, as routers only ever report a path shrinking.
+    pub fn update(&self, dest: IpAddr, mtu: usize) {
+        let now = clock::now();
+        let min_mtu = match dest {
+            IpAddr::V4(_) => IPV4_MIN_MTU,
+            IpAddr::V6(_) => IPV6_MIN_MTU,
+        };
+        let mtu = mtu.max(min_mtu);
+        let mut paths = self.paths.write().unwrap();
+        // Forgets expired paths once there are enough to be worth sweeping.
+        if paths.len() >= 4096 {
+            paths.retain(|_, (_, expires)| now < *expires);
+        }
+        let entry = paths.entry(dest).or_insert((mtu, now));
+        if now >= entry.1 || mtu <= entry.0 {
+            *entry = (mtu, now + self.lifetime);
+        }
+    }
+
+    /// Learns from a Fragmentation Needed error, returning whether `packet` was one. The path is
+    /// to the destination of the packet it quotes.
+    pub fn learn_ipv4(&self, packet: &Ipv4Packet) -> bool {
+        let message = packet.payload();
+        if packet.protocol() != IpProtocol::ICMP
+            || message.len() < 8 + 20
+            || message[0] != ICMP_DEST_UNREACHABLE
+            || message[1] != ICMP_FRAGMENTATION_NEEDED
+        {
+            return false;
+        }
+        let mtu = u16::from_be_bytes([message[6], message[7]]);
+        let dest = Ipv4Addr::new(message[24], message[25], message[26], message[27]);
+        // Routers from before RFC 1191 leave the MTU 0, and there is nothing to learn.
+        if mtu != 0 {
+            self.update(IpAddr::V4(dest), usize::from(mtu));
+        }
+        true
+    }
+
+    /// Learns from a Packet Too Big error, returning whether `packet` was one. The path is to the
+    /// destination of the packet it quotes.
+    pub fn learn_ipv6(&self, packet: &Ipv6Packet) -> bool {
+        let message = packet.payload();
+        if packet.next_header() != IpProtocol::IPv6_ICMP
+            || message.len() < 8 + 40
+            || message[0] != ICMPV6_PACKET_TOO_BIG
+        {
+            return false;
+        }
+        let mtu = u32::from_be_bytes([message[4], message[5], message[6], message[7]]);
+        let mut dest = [0; 16];
+        dest.copy_from_slice(&message[8 + 24..8 + 40]);
+        self.update(IpAddr::V6(Ipv6Addr::from(dest)), mtu as usize);
+        true
+    }
+}
+
+/// Learns the MTUs of paths from the Fragmentation Needed and Packet Too Big errors received by
+/// the router, for its `PmtuCache`, passing every frame on, errors included, so they still reach
+/// whoever sent the packets they are about. It keeps its paths in the cache shared in the
+/// `StateStore` under `PMTU_CACHE_STATE`, unless given one.
+#[derive(Default)]
+pub struct PmtuLearn {
+    cache: Option<Arc<PmtuCache>>,
+}
+
+impl PmtuLearn {
+    pub fn new() -> Self {
+        PmtuLearn { cache: None }
+    }
+
+    pub fn cache(self, cache: Arc<PmtuCache>) -> Self {
+        PmtuLearn { cache: Some(cache) }
+    }
+}
+
+impl Processor for PmtuLearn {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        if let Some(cache) = &self.cache {
+            match frame.ether_type() {
+                IPV4_ETHER_TYPE => {
+                    if let Ok(packet) = Ipv4Packet::try_from(frame.clone()) {
+                        cache.learn_ipv4(&packet);
+                    }
+                }
+                IPV6_ETHER_TYPE => {
+                    if let Ok(packet) = Ipv6Packet::try_from(frame.clone()) {
+                        cache.learn_ipv6(&packet);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(frame)
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        if self.cache.is_none() {
+            self.cache = Some(
+                context
+                    .state()
+                    .get_or_insert_with(PMTU_CACHE_STATE, PmtuCache::new),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{icmp_fragmentation_needed, icmpv6_packet_too_big};
+
+    fn too_big_v4(dest: Ipv4Addr, mtu: u16) -> EthernetFrame {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 2));
+        packet.set_dest_addr(dest);
+        packet.set_payload(&[0; 8]);
+        let error = icmp_fragmentation_needed(&packet, mtu, Ipv4Addr::new(192, 0, 2, 1));
+        EthernetFrame::encap_ipv4(error)
+    }
+
+    #[test]
+    fn learns_from_errors() {
+        let cache = Arc::new(PmtuCache::new());
+        let mut learn = PmtuLearn::new().cache(Arc::clone(&cache));
+        let server = Ipv4Addr::new(198, 51, 100, 7);
+
+        let error = too_big_v4(server, 1400);
+        assert_eq!(learn.process(error.clone()), Some(error));
+        assert_eq!(cache.mtu(IpAddr::V4(server)), Some(1400));
+        // A larger MTU is ignored while the smaller is known, and a tiny one is raised.
+        learn.process(too_big_v4(server, 1480));
+        assert_eq!(cache.mtu(IpAddr::V4(server)), Some(1400));
+        learn.process(too_big_v4(server, 20));
+        assert_eq!(cache.mtu(IpAddr::V4(server)), Some(68));
+
+        let mut packet = Ipv6Packet::empty();
+        let server: Ipv6Addr = "2001:db8::7".parse().unwrap();
+        packet.set_dest_addr(server);
+        let error = icmpv6_packet_too_big(&packet, 1420, "2001:db8::1".parse().unwrap());
+        learn.process(EthernetFrame::encap_ipv6(error));
+        assert_eq!(cache.mtu(IpAddr::V6(server)), Some(1420));
+        assert_eq!(cache.mtu(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 8))), None);
+    }
+
+    #[test]
+    fn paths_expire() {
+        let cache = PmtuCache::with_lifetime(Duration::from_secs(0));
+        let server = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+        cache.update(server, 1400);
+        assert_eq!(cache.mtu(server), None);
+        // Once expired, a larger MTU is taken.
+        cache.update(server, 1500);
+        assert_eq!(cache.paths.read().unwrap()[&server].0, 1500);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::processor::Processor;
+use std::collections::BTreeSet;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Annotates every packet with a monotonically increasing sequence number, starting at 0.
+///
+/// Pair with a `GapDetector` further down the pipeline to measure loss and reordering between
+/// the two points.
+#[derive(Default)]
+pub struct SequenceStamp<A: Send + Clone> {
+    phantom: PhantomData<A>,
+    next_sequence: u64,
+}
+
+impl<A: Send + Clone> SequenceStamp<A> {
+    pub fn new() -> SequenceStamp<A> {
+        SequenceStamp {
+            phantom: PhantomData,
+            next_sequence: 0,
+        }
+    }
+}
+
+impl<A: Send + Clone> Processor for SequenceStamp<A> {
+    type Input = A;
+    type Output = (u64, A);
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        Some((sequence, packet))
+    }
+}
+
+/// Counters kept by a `GapDetector`. They are shared with whoever holds the handle returned by
+/// `GapDetector::stats`, so they can be read while the pipeline is running.
+#[derive(Default, Debug)]
+pub struct GapStats {
+    received: AtomicU64,
+    lost: AtomicU64,
+    reordered: AtomicU64,
+    duplicates: AtomicU64,
+}
+
+impl GapStats {
+    /// Packets that reached the detector.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Sequence numbers that were skipped and have not shown up since.
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    /// Packets that arrived after a packet with a higher sequence number.
+    pub fn reordered(&self) -> u64 {
+        self.reordered.load(Ordering::Relaxed)
+    }
+
+    /// Packets whose sequence number had already been received.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+}
+
+/// Consumes the sequence number written by a `SequenceStamp` and counts loss and reordering.
+///
+/// A jump forward in the sequence counts every skipped number as lost. When a late packet then
+/// arrives it is counted as reordered, and is no longer counted as lost. A packet whose sequence
+/// number was already received is counted as a duplicate. The annotation is stripped, so packets
+/// leave the detector as they entered the `SequenceStamp`.
+///
+/// Missing sequence numbers are remembered for the last `GAP_WINDOW` numbers only. A packet older
+/// than that is counted as reordered, but stays counted as lost.
+pub struct GapDetector<A: Send + Clone> {
+    phantom: PhantomData<A>,
+    expected_sequence: u64,
+    missing: BTreeSet<u64>,
+    stats: Arc<GapStats>,
+}
+
+/// How far behind the expected sequence number a `GapDetector` tracks missing packets.
+pub const GAP_WINDOW: u64 = 4096;
+
+impl<A: Send + Clone> GapDetector<A> {
+    pub fn new() -> GapDetector<A> {
+        GapDetector {
+            phantom: PhantomData,
+            expected_sequence: 0,
+            missing: BTreeSet::new(),
+            stats: Arc::new(GapStats::default()),
+        }
+    }
+
+    /// Handle to the counters of this detector.
+    pub fn stats(&self) -> Arc<GapStats> {
+        Arc::clone(&self.stats)
+    }
+}
+
+impl<A: Send + Clone> Default for GapDetector<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Send + Clone> Processor for GapDetector<A> {
+    type Input = (u64, A);
+    type Output = A;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let (sequence, packet) = packet;
+        self.stats.received.fetch_add(1, Ordering::Relaxed);
+
+        if sequence >= self.expected_sequence {
+            let skipped = sequence - self.expected_sequence;
+            self.stats.lost.fetch_add(skipped, Ordering::Relaxed);
+            let window_start = sequence.wrapping_add(1).saturating_sub(GAP_WINDOW);
+            self.missing
+                .extend(self.expected_sequence.max(window_start)..sequence);
+            if self.missing.range(..window_start).next().is_some() {
+                self.missing = self.missing.split_off(&window_start);
+            }
+            self.expected_sequence = sequence.wrapping_add(1);
+        } else if self.missing.remove(&sequence) {
+            // The late packet was counted as lost when the gap opened.
+            self.stats.reordered.fetch_add(1, Ordering::Relaxed);
+            self.stats.lost.fetch_sub(1, Ordering::Relaxed);
+        } else if sequence >= self.expected_sequence.saturating_sub(GAP_WINDOW) {
+            self.stats.duplicates.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.stats.reordered.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::{LinkBuilder, ProcessLinkBuilder};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    fn detect(sequences: Vec<u64>) -> Arc<GapStats> {
+        let mut detector = GapDetector::new();
+        let stats = detector.stats();
+        for sequence in sequences {
+            assert_eq!(detector.process((sequence, ())), Some(()));
+        }
+        stats
+    }
+
+    #[test]
+    fn stamps_in_order() {
+        let mut stamp = SequenceStamp::new();
+        assert_eq!(stamp.process('a'), Some((0, 'a')));
+        assert_eq!(stamp.process('b'), Some((1, 'b')));
+        assert_eq!(stamp.process('c'), Some((2, 'c')));
+    }
+
+    #[test]
+    fn no_gaps() {
+        let stats = detect(vec![0, 1, 2, 3]);
+        assert_eq!(stats.received(), 4);
+        assert_eq!(stats.lost(), 0);
+        assert_eq!(stats.reordered(), 0);
+    }
+
+    #[test]
+    fn counts_loss() {
+        let stats = detect(vec![0, 3, 4, 6]);
+        assert_eq!(stats.received(), 4);
+        assert_eq!(stats.lost(), 3);
+        assert_eq!(stats.reordered(), 0);
+    }
+
+    #[test]
+    fn late_packet_is_reordered_not_lost() {
+        let stats = detect(vec![0, 2, 1, 3]);
+        assert_eq!(stats.received(), 4);
+        assert_eq!(stats.lost(), 0);
+        assert_eq!(stats.reordered(), 1);
+    }
+
+    #[test]
+    fn duplicate_does_not_underflow_loss() {
+        let stats = detect(vec![0, 1, 1]);
+        assert_eq!(stats.lost(), 0);
+        assert_eq!(stats.reordered(), 0);
+        assert_eq!(stats.duplicates(), 1);
+    }
+
+    #[test]
+    fn duplicate_does_not_recover_loss() {
+        let stats = detect(vec![0, 2, 2]);
+        assert_eq!(stats.received(), 3);
+        assert_eq!(stats.lost(), 1);
+        assert_eq!(stats.reordered(), 0);
+        assert_eq!(stats.duplicates(), 1);
+    }
+
+    #[test]
+    fn late_packet_after_duplicate_is_reordered() {
+        let stats = detect(vec![0, 2, 2, 1, 1]);
+        assert_eq!(stats.lost(), 0);
+        assert_eq!(stats.reordered(), 1);
+        assert_eq!(stats.duplicates(), 2);
+    }
+
+    #[test]
+    fn measures_loss_through_link() {
+        let packets: Vec<i32> = (0..20).collect();
+        let detector = GapDetector::new();
+        let stats = detector.stats();
+
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, mut egressors) = ProcessLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .processor(SequenceStamp::new())
+                .build_link();
+
+            let (mut drop_runnables, mut drop_egressors) = ProcessLink::new()
+                .ingressor(egressors.remove(0))
+                .processor(DropOdd {})
+                .build_link();
+            runnables.append(&mut drop_runnables);
+
+            let (mut detector_runnables, detector_egressors) = ProcessLink::new()
+                .ingressor(drop_egressors.remove(0))
+                .processor(detector)
+                .build_link();
+            runnables.append(&mut detector_runnables);
+
+            run_link((runnables, detector_egressors)).await
+        });
+        assert_eq!(results[0].len(), 10);
+        assert_eq!(stats.received(), 10);
+        assert_eq!(stats.lost(), 9);
+    }
+
+    struct DropOdd {}
+
+    impl Processor for DropOdd {
+        type Input = (u64, i32);
+        type Output = (u64, i32);
+
+        fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+            if packet.1 % 2 == 0 {
+                Some(packet)
+            } else {
+                None
+            }
+        }
+    }
+}
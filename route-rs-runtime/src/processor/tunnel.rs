@@ -0,0 +1,255 @@
+use crate::processor::{Processor, ProcessorContext};
+use route_rs_packets::{
+    ErspanPacket, ErspanType, EthernetFrame, GenevePacket, GrePacket, Ipv4Packet,
+    TRANSPARENT_ETHER_BRIDGING,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The outer header of a tunnel, from the router to the far end of the tunnel.
+fn outer(mut packet: Ipv4Packet, local: Ipv4Addr, remote: Ipv4Addr) -> Ipv4Packet {
+    packet.set_src_addr(local);
+    packet.set_dest_addr(remote);
+    packet.set_checksum();
+    packet
+}
+
+/// The frame carried by a tunnel, which starts with its Ethernet header.
+fn inner_frame(inner: &[u8]) -> Option<EthernetFrame> {
+    EthernetFrame::from_buffer(inner.to_vec(), 0).ok()
+}
+
+/// Encapsulates Ethernet frames in Geneve, to the tunnel endpoint `remote`, of the virtual
+/// network `vni`. The UDP source port is taken from a hash of the frame's headers, so the flows of
+/// the overlay are spread over the paths between the endpoints.
+pub struct GeneveEncap {
+    vni: u32,
+    local: Ipv4Addr,
+    remote: Ipv4Addr,
+}
+
+impl GeneveEncap {
+    pub fn new(vni: u32, local: Ipv4Addr, remote: Ipv4Addr) -> Self {
+        GeneveEncap { vni, local, remote }
+    }
+}
+
+impl Processor for GeneveEncap {
+    type Input = EthernetFrame;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        let inner = &frame.data[frame.layer2_offset..];
+        // The Ethernet header and the IP and port headers of a packet without options.
+        let mut hasher = DefaultHasher::new();
+        inner[..inner.len().min(14 + 20 + 4)].hash(&mut hasher);
+        let src_port = 0xc000 | (hasher.finish() as u16 & 0x3fff);
+
+        let mut geneve = GenevePacket::new();
+        geneve
+            .set_vni(self.vni)
+            .set_src_port(src_port)
+            .set_inner(inner);
+        Some(outer(geneve.packet(), self.local, self.remote))
+    }
+}
+
+/// Terminates Geneve tunnels of the virtual networks `vnis`, passing on the Ethernet frames they
+/// carry. Anything else, other VNIs, control messages, and packets with critical options, none of
+/// which are understood, is dropped and counted as `not_terminated`.
+pub struct GeneveDecap {
+    vnis: HashSet<u32>,
+    not_terminated: Arc<AtomicU64>,
+}
+
+impl GeneveDecap {
+    pub fn new(vnis: Vec<u32>) -> Self {
+        GeneveDecap {
+            vnis: vnis.into_iter().collect(),
+            not_terminated: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Processor for GeneveDecap {
+    type Input = Ipv4Packet;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let frame = GenevePacket::try_from(packet).ok().and_then(|geneve| {
+            if !self.vnis.contains(&geneve.vni())
+                || geneve.oam()
+                || geneve.critical()
+                || geneve.protocol_type() != TRANSPARENT_ETHER_BRIDGING
+            {
+                return None;
+            }
+            inner_frame(geneve.inner())
+        });
+        if frame.is_none() {
+            self.not_terminated.fetch_add(1, Ordering::Relaxed);
+        }
+        frame
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.not_terminated = context.counter("not_terminated");
+    }
+}
+
+/// Encapsulates mirrored Ethernet frames in ERSPAN, of `session_id`, to the remote analyzer at
+/// `remote`. Each packet is numbered in the GRE sequence number, so the analyzer can tell how
+/// many it missed, and, of Type III, stamped with the time it was mirrored, in 100 microsecond
+/// units.
+pub struct ErspanEncap {
+    erspan_type: ErspanType,
+    session_id: u16,
+    local: Ipv4Addr,
+    remote: Ipv4Addr,
+    sequence: u32,
+}
+
+impl ErspanEncap {
+    pub fn new(
+        erspan_type: ErspanType,
+        session_id: u16,
+        local: Ipv4Addr,
+        remote: Ipv4Addr,
+    ) -> Self {
+        assert!(session_id < 1024, "ERSPAN session IDs are 10 bits");
+        ErspanEncap {
+            erspan_type,
+            session_id,
+            local,
+            remote,
+            sequence: 0,
+        }
+    }
+}
+
+impl Processor for ErspanEncap {
+    type Input = EthernetFrame;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        let mut erspan = ErspanPacket::new(self.erspan_type);
+        erspan
+            .set_session_id(self.session_id)
+            .set_mirrored(&frame.data[frame.layer2_offset..]);
+        if self.erspan_type == ErspanType::III {
+            let since_epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            erspan.set_timestamp((since_epoch.as_micros() / 100) as u32);
+        }
+        erspan.gre_mut().set_sequence(Some(self.sequence));
+        self.sequence = self.sequence.wrapping_add(1);
+        Some(outer(erspan.packet(), self.local, self.remote))
+    }
+}
+
+/// Passes on the frames mirrored in ERSPAN of Type II or III, of any session in `session_ids`,
+/// or of every session if it is empty, for a router that is itself the analyzer, or that relays
+/// mirrored traffic on to one. Anything else is dropped and counted as `not_terminated`.
+pub struct ErspanDecap {
+    session_ids: HashSet<u16>,
+    not_terminated: Arc<AtomicU64>,
+}
+
+impl ErspanDecap {
+    pub fn new(session_ids: Vec<u16>) -> Self {
+        ErspanDecap {
+            session_ids: session_ids.into_iter().collect(),
+            not_terminated: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Processor for ErspanDecap {
+    type Input = Ipv4Packet;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let frame = GrePacket::try_from(packet)
+            .and_then(ErspanPacket::try_from)
+            .ok()
+            .and_then(|erspan| {
+                if !self.session_ids.is_empty() && !self.session_ids.contains(&erspan.session_id())
+                {
+                    return None;
+                }
+                inner_frame(erspan.mirrored())
+            });
+        if frame.is_none() {
+            self.not_terminated.fetch_add(1, Ordering::Relaxed);
+        }
+        frame
+    }
+
+    fn setup(&mut self, context: &ProcessorContext) {
+        self.not_terminated = context.counter("not_terminated");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{IpProtocol, IPV4_ETHER_TYPE};
+
+    const LOCAL: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const REMOTE: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 2);
+
+    fn frame(payload: &[u8]) -> EthernetFrame {
+        let mut frame = EthernetFrame::empty();
+        frame.data[..12].copy_from_slice(&[2, 0, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1]);
+        frame.set_ether_type(IPV4_ETHER_TYPE);
+        frame.set_payload(payload);
+        frame
+    }
+
+    #[test]
+    fn geneve_round_trip() {
+        let mut encap = GeneveEncap::new(5001, LOCAL, REMOTE);
+        let mut decap = GeneveDecap::new(vec![5001]);
+
+        let mut packet = encap.process(frame(b"overlay")).unwrap();
+        assert_eq!((packet.src_addr(), packet.dest_addr()), (LOCAL, REMOTE));
+        assert!(packet.validate_checksum());
+        let geneve = GenevePacket::try_from(packet.clone()).unwrap();
+        assert!(geneve.src_port() >= 0xc000);
+        assert_eq!(geneve.vni(), 5001);
+        assert_eq!(decap.process(packet), Some(frame(b"overlay")));
+
+        let mut other = GeneveEncap::new(5002, LOCAL, REMOTE);
+        assert_eq!(decap.process(other.process(frame(b"x")).unwrap()), None);
+        assert_eq!(decap.not_terminated.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn erspan_mirrors_frames() {
+        let mut encap = ErspanEncap::new(ErspanType::III, 7, LOCAL, REMOTE);
+        let first = encap.process(frame(b"one")).unwrap();
+        let second = encap.process(frame(b"two")).unwrap();
+        assert_eq!(first.protocol(), IpProtocol::GREs);
+
+        let erspan = ErspanPacket::try_from(GrePacket::try_from(second.clone()).unwrap()).unwrap();
+        assert_eq!(erspan.gre().sequence(), Some(1));
+        assert_eq!(erspan.session_id(), 7);
+        assert!(erspan.timestamp().unwrap() > 0);
+
+        let mut decap = ErspanDecap::new(vec![7]);
+        assert_eq!(decap.process(first), Some(frame(b"one")));
+        let mut other = ErspanEncap::new(ErspanType::II, 8, LOCAL, REMOTE);
+        assert_eq!(decap.process(other.process(frame(b"x")).unwrap()), None);
+        assert_eq!(
+            ErspanDecap::new(vec![]).process(second),
+            Some(frame(b"two"))
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use crate::processor::Processor;
+use route_rs_packets::{EthernetFrame, VlanTag, VLAN_QINQ_TPID, VLAN_STAG_TPID};
+
+/// Pushes an 802.1ad S-tag of the service VLAN `vid` onto frames, outside any C-tag they carry,
+/// as a provider edge does to the frames of a customer. The tag has the 802.1ad TPID, unless
+/// configured otherwise for switches that want another, and priority 0 unless given.
+pub struct VlanPush {
+    tag: VlanTag,
+}
+
+impl VlanPush {
+    pub fn new(vid: u16) -> Self {
+        VlanPush {
+            tag: VlanTag::new(VLAN_STAG_TPID, vid),
+        }
+    }
+
+    /// Changes tpid, the TPID of the tag, default value is 0x88a8.
+    pub fn tpid(self, tpid: u16) -> Self {
+        VlanPush {
+            tag: VlanTag { tpid, ..self.tag },
+        }
+    }
+
+    /// Changes pcp, the priority of the tag, default value is 0.
+    pub fn pcp(self, pcp: u8) -> Self {
+        assert!(pcp < 8, "pcp must be < 8");
+        VlanPush {
+            tag: VlanTag { pcp, ..self.tag },
+        }
+    }
+}
+
+impl Processor for VlanPush {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, mut frame: Self::Input) -> Option<Self::Output> {
+        frame.push_vlan_tag(self.tag);
+        Some(frame)
+    }
+}
+
+/// Pops the S-tag off frames, as a provider edge does to frames going back to a customer. The
+/// outermost tag is popped if its TPID is one of `tpids`, 0x88a8 and 0x9100 by default, and
+/// frames without one are passed on as they are.
+pub struct VlanPop {
+    tpids: Vec<u16>,
+}
+
+impl Default for VlanPop {
+    fn default() -> Self {
+        VlanPop::new()
+    }
+}
+
+impl VlanPop {
+    pub fn new() -> Self {
+        VlanPop {
+            tpids: vec![VLAN_STAG_TPID, VLAN_QINQ_TPID],
+        }
+    }
+
+    /// Changes tpids, the TPIDs of the S-tags to pop, default value is 0x88a8 and 0x9100.
+    pub fn tpids(self, tpids: Vec<u16>) -> Self {
+        VlanPop { tpids }
+    }
+}
+
+impl Processor for VlanPop {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, mut frame: Self::Input) -> Option<Self::Output> {
+        frame.parse_vlan_tags(&self.tpids);
+        match frame.vlan_tags().first() {
+            Some(tag) if self.tpids.contains(&tag.tpid) => {
+                frame.pop_vlan_tag();
+            }
+            _ => {}
+        }
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{Ipv4Packet, IPV4_ETHER_TYPE, VLAN_CTAG_TPID};
+
+    #[test]
+    fn push_and_pop_s_tags() {
+        let mut customer = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
+        customer.push_vlan_tag(VlanTag::new(VLAN_CTAG_TPID, 10));
+
+        let mut push = VlanPush::new(200).pcp(3);
+        let provider = push.process(customer.clone()).unwrap();
+        let tags = provider.vlan_tags();
+        assert_eq!(tags[0].tpid, VLAN_STAG_TPID);
+        assert_eq!((tags[0].vid, tags[0].pcp), (200, 3));
+        assert_eq!(tags[1].vid, 10);
+        assert_eq!(provider.ether_type(), IPV4_ETHER_TYPE);
+
+        let mut pop = VlanPop::new();
+        assert_eq!(pop.process(provider).unwrap(), customer);
+        // The C-tag isn't an S-tag, so it stays.
+        assert_eq!(pop.process(customer.clone()).unwrap(), customer);
+    }
+
+    #[test]
+    fn configured_tpid() {
+        let customer = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
+        let provider = VlanPush::new(300)
+            .tpid(0x9200)
+            .process(customer.clone())
+            .unwrap();
+        // Reparsed as a switch would receive it, the tag isn't recognized without its TPID.
+        let received = EthernetFrame::from_buffer(provider.data, 0).unwrap();
+        assert_eq!(received.ether_type(), 0x9200);
+
+        let mut pop = VlanPop::new().tpids(vec![0x9200]);
+        assert_eq!(pop.process(received).unwrap(), customer);
+    }
+}
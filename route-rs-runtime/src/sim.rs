@@ -0,0 +1,171 @@
+//! Runs links on virtual time, so tests of links that keep time, such as rate limiters or flow
+//! timeouts, are reproducible and don't wait on the wall clock.
+//!
+//! The runtime is single threaded, so its tasks run one at a time in the order they were woken, and
+//! its clock stands still while any of them can make progress. Once every task is waiting, the
+//! clock jumps straight to the next timer due. Timers of the runtime, such as those under
+//! `PacketIntervalGenerator`, and links reading `utils::clock::now` all go by this clock.
+//! `VirtualIntervalGenerator` keeps its rate on it however short its period, and several of them
+//! can be phased to take their turns in a set order:
+//!
+//! ```
+//! use route_rs_runtime::sim;
+//! use std::time::Duration;
+//!
+//! let elapsed = sim::block_on(async {
+//!     let start = tokio::time::Instant::now();
+//!     tokio::time::delay_for(Duration::from_secs(3600)).await;
+//!     start.elapsed()
+//! });
+//! // The timer wheel has millisecond resolution.
+//! assert!(elapsed >= Duration::from_secs(3600) && elapsed < Duration::from_secs(3601));
+//! ```
+//!
+//! Tasks that wait on anything other than timers, such as sockets or threads, leave the clock
+//! standing, and run in whatever order the world outside hands them their input.
+
+use futures::Future;
+use std::time::Duration;
+use tokio::runtime::{self, Runtime};
+
+/// A single threaded runtime with its clock paused.
+pub fn runtime() -> Runtime {
+    let runtime = runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.enter(tokio::time::pause);
+    runtime
+}
+
+/// Runs a future to completion on a fresh `runtime`.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+/// Moves the clock forward by `duration`, firing the timers due in that time, without waiting for
+/// every task to be idle first.
+pub async fn advance(duration: Duration) {
+    tokio::time::advance(duration).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::{JoinLink, QueueLink};
+    use crate::link::{LinkBuilder, PacketStream, ProcessLinkBuilder};
+    use crate::processor::Identity;
+    use crate::utils::clock;
+    use crate::utils::test::harness::run_link;
+    use crate::utils::test::packet_generators::{
+        PacketIntervalGenerator, VirtualIntervalGenerator,
+    };
+    use std::time::Instant;
+
+    #[test]
+    fn interval_generators_run_on_virtual_time() {
+        let started = Instant::now();
+        let (results, elapsed) = block_on(async {
+            let start = clock::now();
+            let link = QueueLink::new()
+                .ingressor(Box::new(PacketIntervalGenerator::new(
+                    Duration::from_secs(3600),
+                    0..5,
+                )))
+                .processor(Identity::new())
+                .build_link();
+
+            let results = run_link(link).await;
+            (results, clock::now() - start)
+        });
+
+        assert_eq!(results[0], vec![0, 1, 2, 3, 4]);
+        assert!(elapsed >= Duration::from_secs(4 * 3600));
+        assert!(started.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn runs_are_reproducible() {
+        let run = || {
+            block_on(async {
+                let input_streams: Vec<PacketStream<i32>> = vec![
+                    Box::new(PacketIntervalGenerator::new(
+                        Duration::from_millis(30),
+                        0..10,
+                    )),
+                    Box::new(PacketIntervalGenerator::new(
+                        Duration::from_millis(20),
+                        100..110,
+                    )),
+                ];
+                let link = JoinLink::new().ingressors(input_streams).build_link();
+
+                run_link(link).await.remove(0)
+            })
+        };
+
+        let first = run();
+        assert_eq!(first.len(), 20);
+        for _ in 0..5 {
+            assert_eq!(run(), first);
+        }
+    }
+
+    #[test]
+    fn advance_fires_timers() {
+        block_on(async {
+            let start = clock::now();
+            advance(Duration::from_secs(90)).await;
+            assert_eq!(clock::now() - start, Duration::from_secs(90));
+        });
+    }
+
+    #[test]
+    fn virtual_intervals_keep_their_rate() {
+        let started = Instant::now();
+        let (results, elapsed) = block_on(async {
+            let start = clock::now();
+            let link = QueueLink::new()
+                .ingressor(Box::new(VirtualIntervalGenerator::new(
+                    Duration::from_micros(250),
+                    0..40_000,
+                )))
+                .processor(Identity::new())
+                .build_link();
+
+            let results = run_link(link).await;
+            (results, clock::now() - start)
+        });
+
+        assert_eq!(results[0].len(), 40_000);
+        // The last packet is due at 9.99975s, and sent on the next tick of the timer wheel.
+        assert!(elapsed >= Duration::from_millis(9999) && elapsed < Duration::from_millis(10_002));
+        assert!(started.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn phased_intervals_take_turns() {
+        let run = || {
+            block_on(async {
+                let input_streams: Vec<PacketStream<i32>> = vec![
+                    Box::new(
+                        VirtualIntervalGenerator::new(Duration::from_millis(10), 100..103)
+                            .phase(Duration::from_millis(5)),
+                    ),
+                    Box::new(VirtualIntervalGenerator::new(
+                        Duration::from_millis(10),
+                        0..3,
+                    )),
+                ];
+                let link = JoinLink::new().ingressors(input_streams).build_link();
+
+                run_link(link).await.remove(0)
+            })
+        };
+
+        for _ in 0..5 {
+            assert_eq!(run(), vec![0, 100, 1, 101, 2, 102]);
+        }
+    }
+}
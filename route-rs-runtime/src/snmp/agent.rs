@@ -0,0 +1,553 @@
+use crate::metrics::Registry;
+use crate::snmp::ber::{self, Message, Oid, Pdu, Value};
+use crate::utils::clock;
+use std::fs;
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+/// Responses are kept to what fits in one Ethernet frame, so GetBulkRequests are cut short
+/// rather than fragmented.
+const MAX_RESPONSE_LEN: usize = 1400;
+
+/// error-status of a response to a SetRequest, as nothing in the MIB is writable.
+const NO_ACCESS: i64 = 6;
+
+const SYSTEM: [u32; 7] = [1, 3, 6, 1, 2, 1, 1];
+const IF_NUMBER: [u32; 9] = [1, 3, 6, 1, 2, 1, 2, 1, 0];
+const IF_ENTRY: [u32; 9] = [1, 3, 6, 1, 2, 1, 2, 2, 1];
+const IF_X_ENTRY: [u32; 10] = [1, 3, 6, 1, 2, 1, 31, 1, 1, 1];
+
+/// ifType of an interface, and of a link, from the IANAifType-MIB.
+const ETHERNET_CSMACD: i64 = 6;
+const OTHER: i64 = 1;
+
+/// Counters of an interface, as the ifTable and ifXTable of the IF-MIB, RFC 2863, report them.
+/// The ifTable has 32 bit versions of those that have one, which wrap around.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterfaceCounters {
+    pub in_octets: u64,
+    pub in_packets: u64,
+    pub in_discards: u64,
+    pub in_errors: u64,
+    pub out_octets: u64,
+    pub out_packets: u64,
+    pub out_discards: u64,
+    pub out_errors: u64,
+}
+
+impl InterfaceCounters {
+    /// The counters the kernel keeps of the interface named `name`, read from
+    /// `/sys/class/net/<name>/statistics`.
+    pub fn from_sysfs(name: &str) -> io::Result<Self> {
+        let read = |counter: &str| -> io::Result<u64> {
+            let path = format!("/sys/class/net/{}/statistics/{}", name, counter);
+            fs::read_to_string(path)?
+                .trim()
+                .parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        };
+        Ok(InterfaceCounters {
+            in_octets: read("rx_bytes")?,
+            in_packets: read("rx_packets")?,
+            in_discards: read("rx_dropped")?,
+            in_errors: read("rx_errors")?,
+            out_octets: read("tx_bytes")?,
+            out_packets: read("tx_packets")?,
+            out_discards: read("tx_dropped")?,
+            out_errors: read("tx_errors")?,
+        })
+    }
+
+    /// The packets that left the named link `rx`, which receives from an interface, and `tx`,
+    /// which transmits to it, with the packets their event sinks dropped as discards. Octets and
+    /// errors aren't counted by links, so they are zero.
+    pub fn from_links(registry: &Registry, rx: &str, tx: &str) -> Self {
+        let counters = registry.counters();
+        let dropped = |link: &str| {
+            let name = format!("{}.dropped", link);
+            counters
+                .iter()
+                .find(|(counter, _)| *counter == name)
+                .map_or(0, |(_, count)| *count)
+        };
+        let packets = |link: &str| registry.get(link).map_or(0, |link| link.packets());
+        InterfaceCounters {
+            in_packets: packets(rx),
+            in_discards: dropped(rx),
+            out_packets: packets(tx),
+            out_discards: dropped(tx),
+            ..InterfaceCounters::default()
+        }
+    }
+}
+
+type CountersReader = Box<dyn Fn() -> InterfaceCounters + Send + Sync>;
+
+struct Interface {
+    name: String,
+    mtu: u16,
+    counters: CountersReader,
+}
+
+/// The MIB an `SnmpServer` answers from: the system group of the SNMPv2-MIB, and the ifTable and
+/// ifXTable of the IF-MIB, with a row for each registered interface, then one for each named link
+/// of the metrics `Registry`. Interfaces are numbered from 1 in the order they were registered,
+/// and links after them in the order they were, so an ifIndex stays put as long as the router
+/// registers the same. Counters are read as requests arrive.
+///
+/// A link's row counts the packets that left its egressors as ifOutUcastPkts, and those its event
+/// sink dropped as ifOutDiscards, and has no octets or incoming packets.
+///
+/// Clones share their interfaces, so the agent can be handed to the server while the router
+/// keeps registering.
+#[derive(Clone)]
+pub struct SnmpAgent {
+    community: Vec<u8>,
+    sys_descr: String,
+    sys_name: String,
+    started: Instant,
+    registry: &'static Registry,
+    interfaces: Arc<RwLock<Vec<Interface>>>,
+}
+
+impl SnmpAgent {
+    /// Answers requests that carry `community`, and ignores the rest.
+    pub fn new(community: &str) -> Self {
+        SnmpAgent {
+            community: community.as_bytes().to_vec(),
+            sys_descr: format!("route-rs {}", env!("CARGO_PKG_VERSION")),
+            sys_name: String::new(),
+            started: clock::now(),
+            registry: Registry::global(),
+            interfaces: Arc::new(RwLock::new(vec![])),
+        }
+    }
+
+    /// Changes sys_name, the name of the router, default value is empty.
+    pub fn sys_name(self, sys_name: &str) -> Self {
+        SnmpAgent {
+            community: self.community,
+            sys_descr: self.sys_descr,
+            sys_name: String::from(sys_name),
+            started: self.started,
+            registry: self.registry,
+            interfaces: self.interfaces,
+        }
+    }
+
+    /// Lists the links of another metrics registry rather than the global one.
+    pub fn registry(self, registry: &'static Registry) -> Self {
+        SnmpAgent {
+            community: self.community,
+            sys_descr: self.sys_descr,
+            sys_name: self.sys_name,
+            started: self.started,
+            registry,
+            interfaces: self.interfaces,
+        }
+    }
+
+    /// Registers an interface, replacing any registered before under the same name, in its
+    /// place. The reader is called for every request of the interface's counters, such as
+    /// `InterfaceCounters::from_sysfs`, or a sum of those and the drops an AF_PACKET socket
+    /// reports in its statistics.
+    pub fn register_interface<F>(&self, name: &str, mtu: u16, counters: F)
+    where
+        F: Fn() -> InterfaceCounters + Send + Sync + 'static,
+    {
+        let interface = Interface {
+            name: String::from(name),
+            mtu,
+            counters: Box::new(counters),
+        };
+        let mut interfaces = self.interfaces.write().unwrap();
+        match interfaces.iter_mut().find(|known| known.name == name) {
+            Some(known) => *known = interface,
+            None => interfaces.push(interface),
+        }
+    }
+
+    /// The response to an encoded request, or `None` if there should be none, as for a request
+    /// of another community or version, or one that isn't SNMP at all.
+    pub fn respond(&self, request: &[u8]) -> Option<Vec<u8>> {
+        let request = Message::decode(request)?;
+        if request.version != ber::VERSION_2C || request.community != self.community {
+            return None;
+        }
+        let pdu = request.pdu;
+        let (error_status, error_index, bindings) = match pdu.pdu_type {
+            ber::GET_REQUEST => (0, 0, self.get(&pdu.bindings)),
+            ber::GET_NEXT_REQUEST => (0, 0, self.get_next(&pdu.bindings)),
+            ber::GET_BULK_REQUEST => (
+                0,
+                0,
+                self.get_bulk(&pdu.bindings, pdu.error_status, pdu.error_index),
+            ),
+            ber::SET_REQUEST => (NO_ACCESS, 1, pdu.bindings),
+            _ => return None,
+        };
+        let response = Message {
+            version: request.version,
+            community: request.community,
+            pdu: Pdu {
+                pdu_type: ber::RESPONSE,
+                request_id: pdu.request_id,
+                error_status,
+                error_index,
+                bindings,
+            },
+        };
+        Some(response.encode())
+    }
+
+    fn get(&self, requested: &[(Oid, Value)]) -> Vec<(Oid, Value)> {
+        let mib = self.mib();
+        requested
+            .iter()
+            .map(|(oid, _)| {
+                let value = match mib.binary_search_by(|(known, _)| known.cmp(oid)) {
+                    Ok(index) => mib[index].1.clone(),
+                    // An object that exists, without the instance asked for.
+                    Err(_) if mib.iter().any(|(known, _)| known.starts_with(parent(oid))) => {
+                        Value::NoSuchInstance
+                    }
+                    Err(_) => Value::NoSuchObject,
+                };
+                (oid.clone(), value)
+            })
+            .collect()
+    }
+
+    fn get_next(&self, requested: &[(Oid, Value)]) -> Vec<(Oid, Value)> {
+        let mib = self.mib();
+        requested.iter().map(|(oid, _)| next(&mib, oid)).collect()
+    }
+
+    /// The next of each of the first `non_repeaters` OIDs, then the next `max_repetitions` of
+    /// each of the rest, a row at a time, RFC 3416 section 4.2.3. Repetitions that don't fit in a
+    /// response are left out.
+    fn get_bulk(
+        &self,
+        requested: &[(Oid, Value)],
+        non_repeaters: i64,
+        max_repetitions: i64,
+    ) -> Vec<(Oid, Value)> {
+        let mib = self.mib();
+        let non_repeaters = (non_repeaters.max(0) as usize).min(requested.len());
+        let mut bindings: Vec<(Oid, Value)> = requested[..non_repeaters]
+            .iter()
+            .map(|(oid, _)| next(&mib, oid))
+            .collect();
+        let mut len: usize = bindings
+            .iter()
+            .map(|(oid, value)| ber::encode_binding(oid, value).len())
+            .sum();
+
+        let mut row: Vec<Oid> = requested[non_repeaters..]
+            .iter()
+            .map(|(oid, _)| oid.clone())
+            .collect();
+        for _ in 0..max_repetitions.max(0) {
+            if row.is_empty() {
+                break;
+            }
+            let nexts: Vec<(Oid, Value)> = row.iter().map(|oid| next(&mib, oid)).collect();
+            let row_len: usize = nexts
+                .iter()
+                .map(|(oid, value)| ber::encode_binding(oid, value).len())
+                .sum();
+            if len + row_len > MAX_RESPONSE_LEN {
+                break;
+            }
+            len += row_len;
+            let ended = nexts.iter().all(|(_, value)| *value == Value::EndOfMibView);
+            row = nexts.iter().map(|(oid, _)| oid.clone()).collect();
+            bindings.extend(nexts);
+            if ended {
+                break;
+            }
+        }
+        bindings
+    }
+
+    /// Every object of the MIB with its value now, in OID order.
+    fn mib(&self) -> Vec<(Oid, Value)> {
+        let mut mib = vec![
+            (oid(&SYSTEM, &[1, 0]), string(&self.sys_descr)),
+            // zeroDotZero, as the router has no registered identity of its own.
+            (oid(&SYSTEM, &[2, 0]), Value::ObjectIdentifier(vec![0, 0])),
+            (
+                oid(&SYSTEM, &[3, 0]),
+                Value::TimeTicks(
+                    (clock::now().duration_since(self.started).as_millis() / 10) as u32,
+                ),
+            ),
+            (oid(&SYSTEM, &[5, 0]), string(&self.sys_name)),
+        ];
+
+        let interfaces = self.interfaces.read().unwrap();
+        let links = self.registry.links();
+        let counters = self.registry.counters();
+        let rows = interfaces
+            .iter()
+            .map(|interface| Row {
+                name: interface.name.clone(),
+                if_type: ETHERNET_CSMACD,
+                mtu: i64::from(interface.mtu),
+                counters: (interface.counters)(),
+            })
+            .chain(links.iter().map(|link| {
+                let dropped = format!("{}.dropped", link.name());
+                Row {
+                    name: String::from(link.name()),
+                    if_type: OTHER,
+                    mtu: 0,
+                    counters: InterfaceCounters {
+                        out_packets: link.packets(),
+                        out_discards: counters
+                            .iter()
+                            .find(|(name, _)| *name == dropped)
+                            .map_or(0, |(_, count)| *count),
+                        ..InterfaceCounters::default()
+                    },
+                }
+            }));
+        let mut if_number = 0;
+        for (if_index, row) in (1..).zip(rows) {
+            if_number += 1;
+            mib.extend(row.objects(if_index));
+        }
+        mib.push((IF_NUMBER.to_vec(), Value::Integer(if_number)));
+
+        mib.sort_by(|(a, _), (b, _)| a.cmp(b));
+        mib
+    }
+}
+
+/// An interface or link as the ifTable and ifXTable have it.
+struct Row {
+    name: String,
+    if_type: i64,
+    mtu: i64,
+    counters: InterfaceCounters,
+}
+
+impl Row {
+    fn objects(&self, if_index: u32) -> Vec<(Oid, Value)> {
+        let counters = &self.counters;
+        let entry = |column: u32, value: Value| (oid(&IF_ENTRY, &[column, if_index]), value);
+        let x_entry = |column: u32, value: Value| (oid(&IF_X_ENTRY, &[column, if_index]), value);
+        let counter32 = |n: u64| Value::Counter32(n as u32);
+        vec![
+            entry(1, Value::Integer(i64::from(if_index))),
+            entry(2, string(&self.name)),
+            entry(3, Value::Integer(self.if_type)),
+            entry(4, Value::Integer(self.mtu)),
+            // ifAdminStatus and ifOperStatus, up while the router runs.
+            entry(7, Value::Integer(1)),
+            entry(8, Value::Integer(1)),
+            entry(10, counter32(counters.in_octets)),
+            entry(11, counter32(counters.in_packets)),
+            entry(13, counter32(counters.in_discards)),
+            entry(14, counter32(counters.in_errors)),
+            entry(16, counter32(counters.out_octets)),
+            entry(17, counter32(counters.out_packets)),
+            entry(19, counter32(counters.out_discards)),
+            entry(20, counter32(counters.out_errors)),
+            x_entry(1, string(&self.name)),
+            x_entry(6, Value::Counter64(counters.in_octets)),
+            x_entry(7, Value::Counter64(counters.in_packets)),
+            x_entry(10, Value::Counter64(counters.out_octets)),
+            x_entry(11, Value::Counter64(counters.out_packets)),
+        ]
+    }
+}
+
+fn oid(prefix: &[u32], rest: &[u32]) -> Oid {
+    prefix.iter().chain(rest).cloned().collect()
+}
+
+fn string(s: &str) -> Value {
+    Value::OctetString(s.as_bytes().to_vec())
+}
+
+fn parent(oid: &[u32]) -> &[u32] {
+    &oid[..oid.len().saturating_sub(1)]
+}
+
+/// The first object of `mib` after `oid`, or the end of the MIB view.
+fn next(mib: &[(Oid, Value)], oid: &[u32]) -> (Oid, Value) {
+    match mib.iter().find(|(known, _)| known.as_slice() > oid) {
+        Some(object) => object.clone(),
+        None => (oid.to_vec(), Value::EndOfMibView),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static REGISTRY: Registry = Registry::new();
+    static WALKED_REGISTRY: Registry = Registry::new();
+
+    fn request(community: &str, pdu_type: u8, oids: &[&[u32]], error: (i64, i64)) -> Vec<u8> {
+        Message {
+            version: ber::VERSION_2C,
+            community: community.as_bytes().to_vec(),
+            pdu: Pdu {
+                pdu_type,
+                request_id: 42,
+                error_status: error.0,
+                error_index: error.1,
+                bindings: oids.iter().map(|oid| (oid.to_vec(), Value::Null)).collect(),
+            },
+        }
+        .encode()
+    }
+
+    fn response(agent: &SnmpAgent, request: Vec<u8>) -> Pdu {
+        let response = Message::decode(&agent.respond(&request).unwrap()).unwrap();
+        assert_eq!(response.pdu.pdu_type, ber::RESPONSE);
+        assert_eq!(response.pdu.request_id, 42);
+        response.pdu
+    }
+
+    fn agent(registry: &'static Registry) -> SnmpAgent {
+        let agent = SnmpAgent::new("public")
+            .sys_name("edge1")
+            .registry(registry);
+        agent.register_interface("eth0", 1500, || InterfaceCounters {
+            in_octets: (1 << 32) + 10,
+            in_packets: 7,
+            out_discards: 2,
+            ..InterfaceCounters::default()
+        });
+        agent
+    }
+
+    #[test]
+    fn answers_gets_of_interface_counters() {
+        let agent = agent(&REGISTRY);
+        let pdu = response(
+            &agent,
+            request(
+                "public",
+                ber::GET_REQUEST,
+                &[
+                    &[1, 3, 6, 1, 2, 1, 1, 5, 0],
+                    &[1, 3, 6, 1, 2, 1, 2, 2, 1, 2, 1],
+                    &[1, 3, 6, 1, 2, 1, 2, 2, 1, 10, 1],
+                    &[1, 3, 6, 1, 2, 1, 31, 1, 1, 1, 6, 1],
+                    &[1, 3, 6, 1, 2, 1, 2, 2, 1, 19, 1],
+                    &[1, 3, 6, 1, 2, 1, 2, 2, 1, 10, 99],
+                    &[1, 3, 6, 1, 4, 1, 1],
+                ],
+                (0, 0),
+            ),
+        );
+        let values: Vec<Value> = pdu.bindings.into_iter().map(|(_, value)| value).collect();
+        assert_eq!(
+            values,
+            vec![
+                string("edge1"),
+                string("eth0"),
+                // The 32 bit counter wraps, the 64 bit one doesn't.
+                Value::Counter32(10),
+                Value::Counter64((1 << 32) + 10),
+                Value::Counter32(2),
+                Value::NoSuchInstance,
+                Value::NoSuchObject,
+            ]
+        );
+
+        assert_eq!(
+            agent.respond(&request("private", ber::GET_REQUEST, &[&SYSTEM], (0, 0))),
+            None
+        );
+        let set = response(
+            &agent,
+            request("public", ber::SET_REQUEST, &[&IF_NUMBER], (0, 0)),
+        );
+        assert_eq!((set.error_status, set.error_index), (NO_ACCESS, 1));
+    }
+
+    #[test]
+    fn walks_interfaces_and_links_in_order() {
+        let agent = agent(&WALKED_REGISTRY);
+        WALKED_REGISTRY.register("classifier", 1);
+        agent.register_interface("eth1", 9000, InterfaceCounters::default);
+
+        // ifDescr, walked with GetNextRequests as snmpwalk does.
+        let if_descr = oid(&IF_ENTRY, &[2]);
+        let mut walked = if_descr.clone();
+        let mut descrs = vec![];
+        loop {
+            let pdu = response(
+                &agent,
+                request("public", ber::GET_NEXT_REQUEST, &[&walked], (0, 0)),
+            );
+            let (next, value) = pdu.bindings[0].clone();
+            if !next.starts_with(&if_descr) {
+                break;
+            }
+            descrs.push((next[next.len() - 1], value));
+            walked = next;
+        }
+        assert_eq!(
+            descrs,
+            vec![
+                (1, string("eth0")),
+                (2, string("eth1")),
+                (3, string("classifier")),
+            ]
+        );
+
+        let pdu = response(
+            &agent,
+            request("public", ber::GET_NEXT_REQUEST, &[&[1, 3, 6, 1, 9]], (0, 0)),
+        );
+        assert_eq!(pdu.bindings[0].1, Value::EndOfMibView);
+    }
+
+    #[test]
+    fn answers_bulk_requests_a_row_at_a_time() {
+        let agent = agent(&REGISTRY);
+        let pdu = response(
+            &agent,
+            request(
+                "public",
+                ber::GET_BULK_REQUEST,
+                &[
+                    &[1, 3, 6, 1, 2, 1, 1, 3],
+                    &[1, 3, 6, 1, 2, 1, 31, 1, 1, 1, 1],
+                    &[1, 3, 6, 1, 2, 1, 31, 1, 1, 1, 6],
+                ],
+                (1, 2),
+            ),
+        );
+        let oids: Vec<Oid> = pdu.bindings.iter().map(|(oid, _)| oid.clone()).collect();
+        assert_eq!(
+            oids,
+            vec![
+                oid(&SYSTEM, &[3, 0]),
+                oid(&IF_X_ENTRY, &[1, 1]),
+                oid(&IF_X_ENTRY, &[6, 1]),
+                oid(&IF_X_ENTRY, &[6, 1]),
+                oid(&IF_X_ENTRY, &[7, 1]),
+            ]
+        );
+
+        let pdu = response(
+            &agent,
+            request("public", ber::GET_BULK_REQUEST, &[&[1, 3]], (0, 1000)),
+        );
+        let len: usize = pdu
+            .bindings
+            .iter()
+            .map(|(oid, value)| ber::encode_binding(oid, value).len())
+            .sum();
+        assert!(len <= MAX_RESPONSE_LEN);
+        assert_eq!(pdu.bindings.last().unwrap().1, Value::EndOfMibView);
+    }
+}
@@ -0,0 +1,379 @@
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIME_TICKS: u8 = 0x43;
+const COUNTER64: u8 = 0x46;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const NO_SUCH_INSTANCE: u8 = 0x81;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+pub const GET_REQUEST: u8 = 0xa0;
+pub const GET_NEXT_REQUEST: u8 = 0xa1;
+pub const RESPONSE: u8 = 0xa2;
+pub const SET_REQUEST: u8 = 0xa3;
+pub const GET_BULK_REQUEST: u8 = 0xa5;
+
+/// The version field of a v2c message.
+pub const VERSION_2C: i64 = 1;
+
+/// An object identifier, compared arc by arc, which is the order a MIB is walked in.
+pub type Oid = Vec<u32>;
+
+/// The value of a variable binding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    Null,
+    ObjectIdentifier(Oid),
+    Counter32(u32),
+    Gauge32(u32),
+    TimeTicks(u32),
+    Counter64(u64),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+/// A PDU of any type, with the error fields a GetBulkRequest uses for its non-repeaters and
+/// max-repetitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pdu {
+    pub pdu_type: u8,
+    pub request_id: i64,
+    pub error_status: i64,
+    pub error_index: i64,
+    pub bindings: Vec<(Oid, Value)>,
+}
+
+/// An SNMP message, RFC 3416 section 3.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub version: i64,
+    pub community: Vec<u8>,
+    pub pdu: Pdu,
+}
+
+impl Message {
+    /// Decodes a message, or `None` if it is not one this agent understands.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader::new(bytes);
+        let mut message = reader.nested(SEQUENCE)?;
+        let version = message.integer()?;
+        let community = message.element(OCTET_STRING)?.to_vec();
+        let (pdu_type, pdu) = message.any()?;
+        let mut pdu = Reader::new(pdu);
+        let request_id = pdu.integer()?;
+        let error_status = pdu.integer()?;
+        let error_index = pdu.integer()?;
+        let mut list = pdu.nested(SEQUENCE)?;
+        let mut bindings = vec![];
+        while !list.is_empty() {
+            let mut binding = list.nested(SEQUENCE)?;
+            let oid = decode_oid(binding.element(OBJECT_IDENTIFIER)?)?;
+            let value = binding.value()?;
+            bindings.push((oid, value));
+        }
+        Some(Message {
+            version,
+            community,
+            pdu: Pdu {
+                pdu_type,
+                request_id,
+                error_status,
+                error_index,
+                bindings,
+            },
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bindings = vec![];
+        for (oid, value) in &self.pdu.bindings {
+            bindings.extend(encode_binding(oid, value));
+        }
+        let mut pdu = encode_integer(self.pdu.request_id);
+        pdu.extend(encode_integer(self.pdu.error_status));
+        pdu.extend(encode_integer(self.pdu.error_index));
+        pdu.extend(tlv(SEQUENCE, &bindings));
+
+        let mut message = encode_integer(self.version);
+        message.extend(tlv(OCTET_STRING, &self.community));
+        message.extend(tlv(self.pdu.pdu_type, &pdu));
+        tlv(SEQUENCE, &message)
+    }
+}
+
+/// A variable binding as it is encoded, to size a response before it is put together.
+pub fn encode_binding(oid: &[u32], value: &Value) -> Vec<u8> {
+    let mut binding = tlv(OBJECT_IDENTIFIER, &encode_oid(oid));
+    binding.extend(encode_value(value));
+    tlv(SEQUENCE, &binding)
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        encoded.push(len as u8);
+    } else {
+        let len_bytes: Vec<u8> = len
+            .to_be_bytes()
+            .iter()
+            .cloned()
+            .skip_while(|byte| *byte == 0)
+            .collect();
+        encoded.push(0x80 | len_bytes.len() as u8);
+        encoded.extend(len_bytes);
+    }
+    encoded.extend_from_slice(content);
+    encoded
+}
+
+fn encode_integer(n: i64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    // Leading bytes that only repeat the sign of the next are left out.
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    tlv(INTEGER, &bytes[start..])
+}
+
+fn encode_unsigned(tag: u8, n: u64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(7);
+    let mut content = vec![];
+    // A leading zero keeps the value from reading as negative.
+    if bytes[start] & 0x80 != 0 {
+        content.push(0);
+    }
+    content.extend_from_slice(&bytes[start..]);
+    tlv(tag, &content)
+}
+
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut content = vec![];
+    let first = oid.first().cloned().unwrap_or(0) * 40 + oid.get(1).cloned().unwrap_or(0);
+    for arc in std::iter::once(first).chain(oid.iter().skip(2).cloned()) {
+        let mut groups = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            groups.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        content.extend(groups.iter().rev());
+    }
+    content
+}
+
+fn decode_oid(content: &[u8]) -> Option<Oid> {
+    let mut arcs = vec![];
+    let mut arc: u32 = 0;
+    for byte in content {
+        arc = arc.checked_mul(128)? | u32::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    let first = *arcs.first()?;
+    let (top, second) = if first < 80 {
+        (first / 40, first % 40)
+    } else {
+        (2, first - 80)
+    };
+    Some(
+        [top, second]
+            .iter()
+            .chain(arcs.iter().skip(1))
+            .cloned()
+            .collect(),
+    )
+}
+
+fn encode_value(value: &Value) -> Vec<u8> {
+    match value {
+        Value::Integer(n) => encode_integer(*n),
+        Value::OctetString(bytes) => tlv(OCTET_STRING, bytes),
+        Value::Null => tlv(NULL, &[]),
+        Value::ObjectIdentifier(oid) => tlv(OBJECT_IDENTIFIER, &encode_oid(oid)),
+        Value::Counter32(n) => encode_unsigned(COUNTER32, u64::from(*n)),
+        Value::Gauge32(n) => encode_unsigned(GAUGE32, u64::from(*n)),
+        Value::TimeTicks(n) => encode_unsigned(TIME_TICKS, u64::from(*n)),
+        Value::Counter64(n) => encode_unsigned(COUNTER64, *n),
+        Value::NoSuchObject => tlv(NO_SUCH_OBJECT, &[]),
+        Value::NoSuchInstance => tlv(NO_SUCH_INSTANCE, &[]),
+        Value::EndOfMibView => tlv(END_OF_MIB_VIEW, &[]),
+    }
+}
+
+/// Reads elements one after the other from the content of a constructed one.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The tag and content of the next element.
+    fn any(&mut self) -> Option<(u8, &'a [u8])> {
+        let tag = *self.bytes.first()?;
+        let first_len = *self.bytes.get(1)?;
+        let (len, header_len) = if first_len < 0x80 {
+            (first_len as usize, 2)
+        } else {
+            let len_bytes = (first_len & 0x7f) as usize;
+            if len_bytes == 0 || len_bytes > 4 {
+                return None;
+            }
+            let len = self
+                .bytes
+                .get(2..2 + len_bytes)?
+                .iter()
+                .fold(0, |len, byte| len << 8 | *byte as usize);
+            (len, 2 + len_bytes)
+        };
+        let content = self.bytes.get(header_len..header_len + len)?;
+        self.bytes = &self.bytes[header_len + len..];
+        Some((tag, content))
+    }
+
+    fn element(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.any()? {
+            (found, content) if found == tag => Some(content),
+            _ => None,
+        }
+    }
+
+    fn nested(&mut self, tag: u8) -> Option<Reader<'a>> {
+        self.element(tag).map(Reader::new)
+    }
+
+    fn integer(&mut self) -> Option<i64> {
+        let content = self.element(INTEGER)?;
+        if content.is_empty() || content.len() > 8 {
+            return None;
+        }
+        let sign = if content[0] & 0x80 != 0 { -1 } else { 0 };
+        Some(
+            content
+                .iter()
+                .fold(sign, |n: i64, byte| n << 8 | i64::from(*byte)),
+        )
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        let (tag, content) = self.any()?;
+        let unsigned = || {
+            if content.len() > 9 {
+                return None;
+            }
+            Some(
+                content
+                    .iter()
+                    .fold(0u64, |n, byte| n << 8 | u64::from(*byte)),
+            )
+        };
+        Some(match tag {
+            INTEGER => Value::Integer(Reader::new(&tlv(INTEGER, content)).integer()?),
+            OCTET_STRING => Value::OctetString(content.to_vec()),
+            NULL => Value::Null,
+            OBJECT_IDENTIFIER => Value::ObjectIdentifier(decode_oid(content)?),
+            COUNTER32 => Value::Counter32(unsigned()? as u32),
+            GAUGE32 => Value::Gauge32(unsigned()? as u32),
+            TIME_TICKS => Value::TimeTicks(unsigned()? as u32),
+            COUNTER64 => Value::Counter64(unsigned()?),
+            NO_SUCH_OBJECT => Value::NoSuchObject,
+            NO_SUCH_INSTANCE => Value::NoSuchInstance,
+            END_OF_MIB_VIEW => Value::EndOfMibView,
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_a_get_request() {
+        // snmpget -v2c -c public <agent> sysUpTime.0
+        let request = [
+            0x30, 0x29, 0x02, 0x01, 0x01, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0,
+            0x1c, 0x02, 0x04, 0x12, 0x34, 0x56, 0x78, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30,
+            0x0e, 0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x03, 0x00, 0x05,
+            0x00,
+        ];
+        let message = Message::decode(&request).unwrap();
+        assert_eq!(message.version, VERSION_2C);
+        assert_eq!(message.community, b"public".to_vec());
+        assert_eq!(message.pdu.pdu_type, GET_REQUEST);
+        assert_eq!(message.pdu.request_id, 0x1234_5678);
+        assert_eq!(
+            message.pdu.bindings,
+            vec![(vec![1, 3, 6, 1, 2, 1, 1, 3, 0], Value::Null)]
+        );
+        assert_eq!(message.encode(), request.to_vec());
+
+        assert_eq!(Message::decode(&request[..20]), None);
+    }
+
+    #[test]
+    fn round_trips_values() {
+        let values = vec![
+            Value::Integer(0),
+            Value::Integer(127),
+            Value::Integer(128),
+            Value::Integer(-129),
+            Value::Integer(i64::MIN),
+            Value::OctetString(vec![b'x'; 300]),
+            Value::ObjectIdentifier(vec![1, 3, 6, 1, 4, 1, 2_000_000]),
+            Value::Counter32(u32::MAX),
+            Value::Gauge32(0),
+            Value::TimeTicks(360_000),
+            Value::Counter64(u64::MAX),
+            Value::EndOfMibView,
+        ];
+        let message = Message {
+            version: VERSION_2C,
+            community: b"private".to_vec(),
+            pdu: Pdu {
+                pdu_type: RESPONSE,
+                request_id: -1,
+                error_status: 0,
+                error_index: 0,
+                bindings: values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, value)| (vec![1, 3, 6, 1, index as u32], value))
+                    .collect(),
+            },
+        };
+        assert_eq!(Message::decode(&message.encode()), Some(message));
+
+        assert_eq!(encode_integer(128), vec![INTEGER, 2, 0x00, 0x80]);
+        assert_eq!(encode_integer(-128), vec![INTEGER, 1, 0x80]);
+        assert_eq!(
+            encode_unsigned(COUNTER32, 0x8000_0000),
+            vec![COUNTER32, 5, 0, 0x80, 0, 0, 0]
+        );
+        assert_eq!(
+            encode_oid(&[1, 3, 6, 1, 4, 1, 8072]),
+            vec![0x2b, 6, 1, 4, 1, 0xbf, 0x08]
+        );
+    }
+}
@@ -0,0 +1,28 @@
+//! # What is it for?
+//!
+//! Many networks are still monitored by polling SNMP, so a router can serve the counters of its
+//! interfaces and links to the same tools as the rest. A router opts in by building an
+//! `SnmpAgent` with a community, registering its interfaces with a reader of their counters, and
+//! serving it on a UDP address with an `SnmpServer`. Named links are listed from the metrics
+//! `Registry` without any registration.
+//!
+//! The agent speaks SNMP v2c, and answers GetRequests, GetNextRequests and GetBulkRequests of:
+//!
+//! - the system group of the SNMPv2-MIB, RFC 3418: sysDescr, sysObjectID, sysUpTime and sysName.
+//! - ifNumber and the ifTable of the IF-MIB, RFC 2863, with 32 bit counters.
+//! - the ifXTable of the IF-MIB, with ifName and the 64 bit octet and packet counters.
+//!
+//! Interface counters come from wherever the router has them: the kernel's, with
+//! `InterfaceCounters::from_sysfs`, the named links that receive from and transmit to the
+//! interface, with `InterfaceCounters::from_links`, or both. The packets an AF_PACKET socket
+//! drops before the router reads them are counted in its `afpacket::Statistics`, and belong with
+//! the discards of its interface. Nothing is writable, so SetRequests are refused.
+
+/// The subset of BER, X.690, that SNMP v2c messages are made of, RFC 3416 section 3.
+mod ber;
+
+mod agent;
+pub use self::agent::*;
+
+mod server;
+pub use self::server::*;
@@ -0,0 +1,103 @@
+use crate::snmp::SnmpAgent;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+/// Requests larger than this are dropped, the agent has no use for them.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+/// Serves an `SnmpAgent` over UDP, one datagram per request, as SNMP managers poll on port 161.
+///
+/// Communities are sent in the clear, so like the management API, the agent should be bound to a
+/// loopback or management network address, with a community of its own.
+pub struct SnmpServer {
+    agent: SnmpAgent,
+    socket: UdpSocket,
+}
+
+impl SnmpServer {
+    pub async fn bind(agent: SnmpAgent, addr: SocketAddr) -> io::Result<Self> {
+        Ok(SnmpServer {
+            agent,
+            socket: UdpSocket::bind(addr).await?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Answers requests until the runtime shuts down. Requests are answered one at a time, as the
+    /// agent only reads counters to answer them.
+    pub async fn serve(mut self) {
+        let mut buf = vec![0; MAX_REQUEST_LEN];
+        loop {
+            let (len, manager) = match self.socket.recv_from(&mut buf).await {
+                Ok(request) => request,
+                Err(_) => continue,
+            };
+            if let Some(response) = self.agent.respond(&buf[..len]) {
+                // The manager may have gone away, there is no one left to tell.
+                let _ = self.socket.send_to(&response, &manager).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Registry;
+    use crate::snmp::ber::{self, Message, Pdu, Value};
+    use crate::snmp::InterfaceCounters;
+    use crate::utils::test::harness::initialize_runtime;
+    use tokio::time::{timeout, Duration};
+
+    static REGISTRY: Registry = Registry::new();
+
+    #[test]
+    fn answers_requests_over_udp() {
+        let agent = SnmpAgent::new("public").registry(&REGISTRY);
+        agent.register_interface("eth0", 1500, || InterfaceCounters {
+            in_packets: 12,
+            ..InterfaceCounters::default()
+        });
+        let request = |community: &str| {
+            Message {
+                version: ber::VERSION_2C,
+                community: community.as_bytes().to_vec(),
+                pdu: Pdu {
+                    pdu_type: ber::GET_REQUEST,
+                    request_id: 7,
+                    error_status: 0,
+                    error_index: 0,
+                    bindings: vec![(vec![1, 3, 6, 1, 2, 1, 2, 2, 1, 11, 1], Value::Null)],
+                },
+            }
+            .encode()
+        };
+
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let server = SnmpServer::bind(agent, "127.0.0.1:0".parse().unwrap())
+                .await
+                .unwrap();
+            let addr = server.local_addr().unwrap();
+            tokio::spawn(server.serve());
+
+            let mut manager = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let mut buf = vec![0; 1500];
+            // Requests of another community go unanswered.
+            manager.send_to(&request("private"), &addr).await.unwrap();
+            manager.send_to(&request("public"), &addr).await.unwrap();
+            let (len, _) = timeout(Duration::from_secs(1), manager.recv_from(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+
+            let response = Message::decode(&buf[..len]).unwrap();
+            assert_eq!(response.pdu.request_id, 7);
+            assert_eq!(response.pdu.bindings[0].1, Value::Counter32(12));
+        });
+    }
+}
@@ -0,0 +1,101 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+type Entry = (String, Arc<dyn Any + Send + Sync>);
+
+/// State shared between the processors and links of a router, such as a NAT table or the
+/// `RouterConfig`, kept by name. Entries are typed, and whatever locking they need is up to the
+/// type, so a processor asks for an entry once, when it is set up, and holds on to the `Arc`.
+#[derive(Default)]
+pub struct StateStore {
+    entries: Mutex<Vec<Entry>>,
+}
+
+static GLOBAL_STATE: StateStore = StateStore::new();
+
+impl StateStore {
+    pub const fn new() -> Self {
+        StateStore {
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The store `ProcessorContext`s give processors.
+    pub fn global() -> &'static StateStore {
+        &GLOBAL_STATE
+    }
+
+    /// The entry of a name, or none if there is none, or it holds another type.
+    pub fn get<T: Any + Send + Sync>(&self, name: &str) -> Option<Arc<T>> {
+        let entries = self.entries.lock().unwrap();
+        let (_, entry) = entries.iter().find(|(n, _)| n == name)?;
+        Arc::clone(entry).downcast().ok()
+    }
+
+    /// Stores an entry under a name, replacing any stored before under the same.
+    pub fn insert<T: Any + Send + Sync>(&self, name: &str, value: T) -> Arc<T> {
+        let value = Arc::new(value);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|(n, _)| n != name);
+        entries.push((
+            String::from(name),
+            Arc::clone(&value) as Arc<dyn Any + Send + Sync>,
+        ));
+        value
+    }
+
+    /// The entry of a name, stored by calling `init` if there is none yet, so that the first of
+    /// several processors sharing an entry creates it. Panics if the entry holds another type.
+    pub fn get_or_insert_with<T, F>(&self, name: &str, init: F) -> Arc<T>
+    where
+        T: Any + Send + Sync,
+        F: FnOnce() -> T,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some((_, entry)) = entries.iter().find(|(n, _)| n == name) {
+            return Arc::clone(entry)
+                .downcast()
+                .unwrap_or_else(|_| panic!("State {} holds another type", name));
+        }
+        let value = Arc::new(init());
+        entries.push((
+            String::from(name),
+            Arc::clone(&value) as Arc<dyn Any + Send + Sync>,
+        ));
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn shares_entries_by_name() {
+        let store = StateStore::new();
+        let first = store.get_or_insert_with("hits", || AtomicUsize::new(0));
+        let second = store.get_or_insert_with("hits", || AtomicUsize::new(100));
+        first.fetch_add(1, Ordering::Relaxed);
+
+        assert_eq!(second.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            store
+                .get::<AtomicUsize>("hits")
+                .unwrap()
+                .load(Ordering::Relaxed),
+            1
+        );
+        assert!(store.get::<String>("hits").is_none());
+        assert!(store.get::<AtomicUsize>("misses").is_none());
+    }
+
+    #[test]
+    fn insert_replaces_entries() {
+        let store = StateStore::new();
+        store.insert("mtu", 1500u16);
+        store.insert("mtu", 9000u16);
+
+        assert_eq!(*store.get::<u16>("mtu").unwrap(), 9000);
+    }
+}
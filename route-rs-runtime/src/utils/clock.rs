@@ -0,0 +1,8 @@
+use std::time::Instant;
+
+/// The time links measure timeouts and uptimes by. It is the clock of the Tokio runtime running
+/// them, which is the system clock unless the runtime has paused it, as under the `sim` feature,
+/// where it only moves as timers fire. Outside of a runtime it is always the system clock.
+pub fn now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
@@ -2,3 +2,7 @@
 pub mod test;
 
 pub mod runner;
+
+pub mod clock;
+
+pub mod pcap;
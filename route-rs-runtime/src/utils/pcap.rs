@@ -0,0 +1,131 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Link-layer header types of pcap files, from the tcpdump.org registry.
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+
+/// The magic number of pcap files with microsecond timestamps, and of those with nanosecond ones.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+
+/// A packet of a pcap file, with when it was captured.
+pub type PcapRecord = (SystemTime, Vec<u8>);
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes `records` to a pcap file at `path`, each with when it was captured.
+pub fn write_pcap(path: &Path, link_type: u32, records: &[PcapRecord]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&PCAP_MAGIC.to_le_bytes())?;
+    file.write_all(&2u16.to_le_bytes())?;
+    file.write_all(&4u16.to_le_bytes())?;
+    file.write_all(&[0; 8])?;
+    file.write_all(&65535u32.to_le_bytes())?;
+    file.write_all(&link_type.to_le_bytes())?;
+    for (captured, data) in records {
+        let since_epoch = captured.duration_since(UNIX_EPOCH).unwrap_or_default();
+        file.write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        file.write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        file.write_all(&(data.len() as u32).to_le_bytes())?;
+        file.write_all(&(data.len() as u32).to_le_bytes())?;
+        file.write_all(data)?;
+    }
+    file.flush()
+}
+
+/// Reads the pcap file at `path`, as tcpdump and Wireshark write them, in either byte order and
+/// with either microsecond or nanosecond timestamps. Returns its link-layer header type, and its
+/// packets, as much of each as was captured.
+pub fn read_pcap(path: &Path) -> io::Result<(u32, Vec<PcapRecord>)> {
+    let mut data = vec![];
+    BufReader::new(File::open(path)?).read_to_end(&mut data)?;
+    if data.len() < 24 {
+        return Err(invalid("pcap file is too short for its header"));
+    }
+    let magic = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    let (big_endian, nanos) = match (magic, magic.swap_bytes()) {
+        (PCAP_MAGIC, _) => (false, false),
+        (PCAP_MAGIC_NANOS, _) => (false, true),
+        (_, PCAP_MAGIC) => (true, false),
+        (_, PCAP_MAGIC_NANOS) => (true, true),
+        _ => return Err(invalid("not a pcap file")),
+    };
+    let word = |at: usize| {
+        let bytes = data[at..at + 4].try_into().unwrap();
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+
+    let link_type = word(20);
+    let mut records = vec![];
+    let mut at = 24;
+    while at < data.len() {
+        if at + 16 > data.len() {
+            return Err(invalid("pcap file ends in a record header"));
+        }
+        let fraction = u64::from(word(at + 4));
+        let captured = UNIX_EPOCH
+            + Duration::from_secs(u64::from(word(at)))
+            + if nanos {
+                Duration::from_nanos(fraction)
+            } else {
+                Duration::from_micros(fraction)
+            };
+        let len = word(at + 8) as usize;
+        at += 16;
+        if at + len > data.len() {
+            return Err(invalid("pcap file ends in a packet"));
+        }
+        records.push((captured, data[at..at + len].to_vec()));
+        at += len;
+    }
+    Ok((link_type, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn reads_what_it_writes() {
+        let path = std::env::temp_dir().join(format!("pcap-{}.pcap", Uuid::new_v4()));
+        let captured = UNIX_EPOCH + Duration::from_micros(1_600_000_000_123_456);
+        let records = vec![(captured, vec![0x45, 0, 0, 20]), (captured, vec![])];
+        write_pcap(&path, LINKTYPE_IPV4, &records).unwrap();
+
+        assert_eq!(read_pcap(&path).unwrap(), (LINKTYPE_IPV4, records));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_big_endian_nanosecond_files() {
+        let path = std::env::temp_dir().join(format!("pcap-{}.pcap", Uuid::new_v4()));
+        let mut data = PCAP_MAGIC_NANOS.to_be_bytes().to_vec();
+        data.extend_from_slice(&[0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff]);
+        data.extend_from_slice(&LINKTYPE_ETHERNET.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 7, 0, 0, 0, 9, 0, 0, 0, 2, 0, 0, 0, 60, 0xaa, 0xbb]);
+        std::fs::write(&path, &data).unwrap();
+
+        let (link_type, records) = read_pcap(&path).unwrap();
+        assert_eq!(link_type, LINKTYPE_ETHERNET);
+        assert_eq!(
+            records,
+            vec![(UNIX_EPOCH + Duration::new(7, 9), vec![0xaa, 0xbb])]
+        );
+
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert!(read_pcap(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
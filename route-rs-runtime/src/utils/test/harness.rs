@@ -1,7 +1,9 @@
+use crate::link::event::{EventSink, LinkEvent};
 use crate::link::{Link, TokioRunnable};
-use crate::utils::test::packet_collectors::ExhaustiveCollector;
+use crate::utils::test::packet_collectors::{ExhaustiveCollector, TimedCollector};
 use crossbeam::crossbeam_channel;
 use std::fmt::Debug;
+use std::time::Duration;
 use tokio::runtime;
 
 /// The utils::test::harness module should be able to help Link authors abstract away the
@@ -63,6 +65,177 @@ pub async fn run_link<OutputPacket: Debug + Send + Clone + 'static>(
         .collect()
 }
 
+/// Runs a link as `run_link` does, but fails once `timeout` has passed without every egressor
+/// ending, showing what each sent until then, rather than hanging the test run if the link
+/// deadlocks. Its runnables are only waited for once every egressor has ended.
+pub async fn run_link_timeout<OutputPacket: Debug + Send + 'static>(
+    link: Link<OutputPacket>,
+    timeout: Duration,
+) -> Vec<Vec<OutputPacket>> {
+    let (runnables, egressors) = link;
+    let mut handles = vec![];
+    for runnable in runnables {
+        handles.push(tokio::spawn(runnable));
+    }
+    let collectors: Vec<_> = egressors
+        .into_iter()
+        .map(|egressor| tokio::spawn(TimedCollector::new(egressor).timeout(timeout)))
+        .collect();
+
+    let mut collections = vec![];
+    for collector in collectors {
+        collections.push(collector.await.unwrap());
+    }
+    if collections.iter().any(|collection| collection.timed_out()) {
+        let sent: Vec<String> = collections
+            .iter()
+            .enumerate()
+            .map(|(port, collection)| {
+                format!(
+                    "egressor {} ({:?}): {:?}",
+                    port, collection.stopped, collection.packets
+                )
+            })
+            .collect();
+        panic!("link timed out after {:?}\n{}", timeout, sent.join("\n"));
+    }
+    await_handles(handles).await;
+    collections
+        .into_iter()
+        .map(|collection| collection.packets)
+        .collect()
+}
+
+/// Runs a link as `run_link` does, for tests that need more of it: egressors named for what they
+/// carry, a timeout for each, after which whatever it sent so far is collected, and the events the
+/// link reported. The ingressors of the link are whatever streams it was built with, so several of
+/// them, from `spaced_stream` or the other generators, each keep timing of their own.
+///
+/// Egressors are named in order with `egressor`, or by their index if none are named. One whose
+/// timeout passes is marked as timed out in the results, and the link's runnables aren't waited
+/// for, since they may be stuck sending to it; otherwise they are, and their panics are the test's.
+pub struct LinkHarness {
+    egressors: Vec<(String, Option<Duration>)>,
+    timeout: Option<Duration>,
+    events: (
+        crossbeam_channel::Sender<LinkEvent>,
+        crossbeam_channel::Receiver<LinkEvent>,
+    ),
+}
+
+impl Default for LinkHarness {
+    fn default() -> Self {
+        LinkHarness::new()
+    }
+}
+
+impl LinkHarness {
+    pub fn new() -> Self {
+        LinkHarness {
+            egressors: vec![],
+            timeout: None,
+            events: crossbeam_channel::unbounded(),
+        }
+    }
+
+    /// Names the next egressor of the link.
+    pub fn egressor(mut self, name: &str) -> Self {
+        self.egressors.push((String::from(name), None));
+        self
+    }
+
+    /// Names the next egressor of the link, and collects from it for at most `timeout`.
+    pub fn egressor_timeout(mut self, name: &str, timeout: Duration) -> Self {
+        self.egressors.push((String::from(name), Some(timeout)));
+        self
+    }
+
+    /// Collects from each egressor without a timeout of its own for at most `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// An event sink for the link under test, whose events are returned with its packets.
+    pub fn event_sink(&self, link: &str) -> EventSink {
+        EventSink::new(link, self.events.0.clone())
+    }
+
+    pub async fn run<OutputPacket: Debug + Send + 'static>(
+        self,
+        link: Link<OutputPacket>,
+    ) -> LinkResults<OutputPacket> {
+        let (runnables, egressors) = link;
+        let names = if self.egressors.is_empty() {
+            (0..egressors.len())
+                .map(|i| (i.to_string(), None))
+                .collect()
+        } else {
+            assert_eq!(
+                self.egressors.len(),
+                egressors.len(),
+                "link has {} egressors, but {} were named",
+                egressors.len(),
+                self.egressors.len()
+            );
+            self.egressors
+        };
+
+        let mut handles = vec![];
+        for runnable in runnables {
+            handles.push(tokio::spawn(runnable));
+        }
+        let mut collectors = vec![];
+        for (egressor, (_, timeout)) in egressors.into_iter().zip(&names) {
+            let timeout = timeout.or(self.timeout);
+            let collector = match timeout {
+                Some(timeout) => TimedCollector::new(egressor).timeout(timeout),
+                None => TimedCollector::new(egressor),
+            };
+            collectors.push(tokio::spawn(collector));
+        }
+
+        let mut egressors = vec![];
+        for ((name, _), collector) in names.into_iter().zip(collectors) {
+            let collection = collector.await.unwrap();
+            let timed_out = collection.timed_out();
+            egressors.push((name, collection.packets, timed_out));
+        }
+        if egressors.iter().all(|(_, _, timed_out)| !timed_out) {
+            await_handles(handles).await;
+        }
+        LinkResults {
+            egressors,
+            events: self.events.1.try_iter().collect(),
+        }
+    }
+}
+
+/// The packets each egressor of a link sent, by name, and the events it reported.
+pub struct LinkResults<Packet> {
+    egressors: Vec<(String, Vec<Packet>, bool)>,
+    pub events: Vec<LinkEvent>,
+}
+
+impl<Packet> LinkResults<Packet> {
+    fn find(&self, name: &str) -> &(String, Vec<Packet>, bool) {
+        self.egressors
+            .iter()
+            .find(|(egressor, _, _)| egressor == name)
+            .unwrap_or_else(|| panic!("link has no egressor named {}", name))
+    }
+
+    /// The packets the egressor named `name` sent.
+    pub fn packets(&self, name: &str) -> &[Packet] {
+        &self.find(name).1
+    }
+
+    /// Whether the egressor named `name` was still open when its timeout passed.
+    pub fn timed_out(&self, name: &str) -> bool {
+        self.find(name).2
+    }
+}
+
 async fn spawn_runnables(runnables: Vec<TokioRunnable>) {
     let mut handles = vec![];
     for runnable in runnables {
@@ -76,3 +249,97 @@ async fn await_handles(handles: Vec<tokio::task::JoinHandle<()>>) {
         handle.await.unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::Even;
+    use crate::link::primitive::{ClassifyLink, JoinLink};
+    use crate::link::{LinkBuilder, PacketStream};
+    use crate::utils::test::packet_generators::{immediate_stream, spaced_stream};
+    use futures::prelude::*;
+
+    #[test]
+    fn names_egressors_and_collects_events() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let harness = LinkHarness::new().egressor("even").egressor("odd");
+            let link = ClassifyLink::new()
+                .ingressor(immediate_stream(0..6))
+                .classifier(Even::new())
+                .dispatcher(Box::new(|even| if even { 0 } else { 2 }))
+                .num_egressors(2)
+                .event_sink(harness.event_sink("parity"))
+                .build_link();
+
+            harness.run(link).await
+        });
+        assert_eq!(results.packets("even"), &[0, 2, 4]);
+        assert!(results.packets("odd").is_empty());
+        assert!(!results.timed_out("even"));
+        assert_eq!(results.events.len(), 3);
+    }
+
+    #[test]
+    fn interleaves_ingressors_by_their_timing() {
+        let millis = Duration::from_millis;
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = JoinLink::new()
+                .ingressors(vec![
+                    spaced_stream(vec![(millis(0), 0), (millis(40), 2)]),
+                    spaced_stream(vec![(millis(20), 1), (millis(40), 3)]),
+                ])
+                .build_link();
+
+            LinkHarness::new().run(link).await
+        });
+        assert_eq!(results.packets("0"), &[0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn times_out_egressors() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let never: PacketStream<i32> = Box::new(stream::pending());
+            let link = JoinLink::new()
+                .ingressors(vec![immediate_stream(vec![7]), never])
+                .build_link();
+
+            LinkHarness::new()
+                .egressor_timeout("joined", Duration::from_millis(50))
+                .run(link)
+                .await
+        });
+        assert_eq!(results.packets("joined"), &[7]);
+        assert!(results.timed_out("joined"));
+    }
+
+    #[test]
+    fn runs_links_that_end_within_timeout() {
+        let mut runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = JoinLink::new()
+                .ingressors(vec![
+                    immediate_stream(vec![1, 2]),
+                    immediate_stream(vec![3]),
+                ])
+                .build_link();
+            run_link_timeout(link, Duration::from_secs(10)).await
+        });
+        assert_eq!(results[0].len(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "egressor 0 (TimedOut): [7]")]
+    fn fails_links_that_hang_with_what_they_sent() {
+        let mut runtime = initialize_runtime();
+        runtime.block_on(async {
+            let never: PacketStream<i32> = Box::new(stream::pending());
+            let link = JoinLink::new()
+                .ingressors(vec![immediate_stream(vec![7]), never])
+                .build_link();
+            run_link_timeout(link, Duration::from_millis(50)).await
+        });
+    }
+}
@@ -1,3 +1,9 @@
 pub mod harness;
 pub mod packet_collectors;
 pub mod packet_generators;
+pub mod pcap_regression;
+
+/// Proptest strategies for packet streams, and invariants to check links against with them, behind
+/// the `proptest` feature outside the crate's own tests.
+#[cfg(any(test, feature = "proptest"))]
+pub mod properties;
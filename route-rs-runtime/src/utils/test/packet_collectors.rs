@@ -4,6 +4,7 @@ use futures::prelude::*;
 use futures::task::{Context, Poll};
 use std::fmt::Debug;
 use std::pin::Pin;
+use tokio::time::{delay_until, Delay, Duration, Instant};
 
 /// A structure that may be handed an input stream that it will exhaustively drain from until it
 /// recieves a None. Useful for testing purposes.
@@ -73,3 +74,171 @@ impl<T: Debug> Future for ExhaustiveCollector<T> {
         }
     }
 }
+
+/// Why a `TimedCollector` stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    /// The stream ended.
+    Ended,
+    /// The collector gathered as many packets as it was to.
+    Count,
+    /// The timeout passed before either.
+    TimedOut,
+}
+
+/// The packets a `TimedCollector` gathered, and why it stopped.
+#[derive(Debug)]
+pub struct TimedCollection<T> {
+    pub packets: Vec<T>,
+    pub stopped: Stopped,
+}
+
+impl<T: Debug> TimedCollection<T> {
+    pub fn timed_out(&self) -> bool {
+        self.stopped == Stopped::TimedOut
+    }
+
+    /// The packets gathered, panicking with them if the collector timed out, so a link that hangs
+    /// fails its test with what it sent before it did.
+    pub fn expect_complete(self) -> Vec<T> {
+        assert!(
+            !self.timed_out(),
+            "collector timed out after {} packets: {:?}",
+            self.packets.len(),
+            self.packets
+        );
+        self.packets
+    }
+}
+
+/// Timed Collector drains a stream as Exhaustive Collector does, but stops once its timeout has
+/// passed from when it is first polled, or once it has gathered `count` packets, and resolves to
+/// whatever it gathered, rather than waiting forever on a link that has deadlocked.
+pub struct TimedCollector<T> {
+    stream: PacketStream<T>,
+    timeout: Option<Duration>,
+    count: Option<usize>,
+    delay: Option<Delay>,
+    packets: Vec<T>,
+}
+
+impl<T> Unpin for TimedCollector<T> {}
+
+impl<T> TimedCollector<T> {
+    pub fn new(stream: PacketStream<T>) -> Self {
+        TimedCollector {
+            stream,
+            timeout: None,
+            count: None,
+            delay: None,
+            packets: vec![],
+        }
+    }
+
+    /// Stops collecting once `timeout` has passed.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        TimedCollector {
+            stream: self.stream,
+            timeout: Some(timeout),
+            count: self.count,
+            delay: self.delay,
+            packets: self.packets,
+        }
+    }
+
+    /// Stops collecting once `count` packets are gathered.
+    pub fn count(self, count: usize) -> Self {
+        TimedCollector {
+            stream: self.stream,
+            timeout: self.timeout,
+            count: Some(count),
+            delay: self.delay,
+            packets: self.packets,
+        }
+    }
+
+    fn stop(&mut self, stopped: Stopped) -> Poll<TimedCollection<T>> {
+        Poll::Ready(TimedCollection {
+            packets: std::mem::take(&mut self.packets),
+            stopped,
+        })
+    }
+}
+
+impl<T> Future for TimedCollector<T> {
+    type Output = TimedCollection<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let collector = Pin::into_inner(self);
+        if let Some(timeout) = collector.timeout {
+            collector
+                .delay
+                .get_or_insert_with(|| delay_until(Instant::now() + timeout));
+        }
+        loop {
+            if collector
+                .count
+                .is_some_and(|count| collector.packets.len() >= count)
+            {
+                return collector.stop(Stopped::Count);
+            }
+            // Checked for each packet, so a stream that is always ready can't outlast it.
+            if let Some(delay) = &collector.delay {
+                if delay.deadline() <= Instant::now() {
+                    return collector.stop(Stopped::TimedOut);
+                }
+            }
+            match Pin::new(&mut collector.stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => collector.packets.push(packet),
+                Poll::Ready(None) => return collector.stop(Stopped::Ended),
+                Poll::Pending => {
+                    if let Some(delay) = &mut collector.delay {
+                        ready!(Pin::new(delay).poll(cx));
+                        return collector.stop(Stopped::TimedOut);
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::initialize_runtime;
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    fn collects_until_the_stream_ends() {
+        let mut runtime = initialize_runtime();
+        let collection = runtime
+            .block_on(TimedCollector::new(immediate_stream(0..4)).timeout(Duration::from_secs(10)));
+        assert_eq!(collection.stopped, Stopped::Ended);
+        assert_eq!(collection.expect_complete(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn stops_at_count_of_an_endless_stream() {
+        let mut runtime = initialize_runtime();
+        let collection =
+            runtime.block_on(TimedCollector::new(Box::new(stream::repeat(7))).count(3));
+        assert_eq!(collection.stopped, Stopped::Count);
+        assert_eq!(collection.packets, vec![7, 7, 7]);
+    }
+
+    #[test]
+    fn returns_what_it_gathered_once_timed_out() {
+        let mut runtime = initialize_runtime();
+        let stuck: PacketStream<i32> = Box::new(stream::iter(0..2).chain(stream::pending()));
+        let collection =
+            runtime.block_on(TimedCollector::new(stuck).timeout(Duration::from_millis(20)));
+        assert!(collection.timed_out());
+        assert_eq!(collection.packets, vec![0, 1]);
+
+        let busy: PacketStream<i32> = Box::new(stream::repeat(1));
+        let collection =
+            runtime.block_on(TimedCollector::new(busy).timeout(Duration::from_millis(20)));
+        assert!(collection.timed_out());
+    }
+}
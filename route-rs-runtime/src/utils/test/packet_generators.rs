@@ -1,8 +1,12 @@
 use crate::link::PacketStream;
 use futures::prelude::*;
 use futures::task::{Context, Poll};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::iter::Peekable;
 use std::pin::Pin;
-use tokio::time::{interval, Duration, Interval};
+use tokio::time::{delay_until, interval, Delay, Duration, Instant, Interval};
 
 /// Immediately yields a collection of packets to be poll'd.
 /// Thin wrapper around iter_ok.
@@ -14,6 +18,17 @@ where
     Box::new(stream::iter(collection))
 }
 
+/// Yields each packet the given time after the one before, so each ingressor of a link under test
+/// can keep timing of its own, such as one ingressor sending a burst while another trickles.
+pub fn spaced_stream<T: Send + 'static>(packets: Vec<(Duration, T)>) -> PacketStream<T> {
+    Box::new(Box::pin(stream::iter(packets).then(
+        |(gap, packet)| async move {
+            tokio::time::delay_for(gap).await;
+            packet
+        },
+    )))
+}
+
 /*
     LinearIntervalGenerator
 
@@ -104,3 +119,386 @@ where
         }
     }
 }
+
+/// Yields one packet every `period` by the runtime clock, so under `sim` it runs on virtual time,
+/// and a test of a timed link covers hours of traffic at once, the same way each run.
+///
+/// Packet `k` is due `phase + k * period` after the first poll, and every packet due is yielded
+/// as soon as it is polled, rather than one per wake as with `PacketIntervalGenerator`. The timer
+/// wheel only wakes once a millisecond, so a period shorter than that sends each millisecond's
+/// packets together and keeps its rate, where waiting out a timer per packet would send one a
+/// millisecond. Generators with different phases take their turns in the order of their phases,
+/// rather than in whatever order their links happen to poll them.
+pub struct VirtualIntervalGenerator<Iterable: Iterator> {
+    period: Duration,
+    phase: Duration,
+    packets: Peekable<Iterable>,
+    start: Option<Instant>,
+    sent: u32,
+    delay: Option<Delay>,
+}
+
+impl<Iterable: Iterator> Unpin for VirtualIntervalGenerator<Iterable> {}
+
+impl<Iterable: Iterator> VirtualIntervalGenerator<Iterable> {
+    pub fn new(period: Duration, packets: Iterable) -> Self {
+        VirtualIntervalGenerator {
+            period,
+            phase: Duration::from_secs(0),
+            packets: packets.peekable(),
+            start: None,
+            sent: 0,
+            delay: None,
+        }
+    }
+
+    /// Delays every packet by `phase`, default value is none.
+    pub fn phase(self, phase: Duration) -> Self {
+        VirtualIntervalGenerator {
+            period: self.period,
+            phase,
+            packets: self.packets,
+            start: self.start,
+            sent: self.sent,
+            delay: self.delay,
+        }
+    }
+}
+
+impl<Iterable: Iterator> Stream for VirtualIntervalGenerator<Iterable> {
+    type Item = Iterable::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        if self.packets.peek().is_none() {
+            return Poll::Ready(None);
+        }
+        loop {
+            let due = start + self.phase + self.period * self.sent;
+            if due <= Instant::now() {
+                self.delay = None;
+                self.sent += 1;
+                return Poll::Ready(self.packets.next());
+            }
+            let delay = self.delay.get_or_insert_with(|| delay_until(due));
+            if delay.deadline() != due {
+                delay.reset(due);
+            }
+            ready!(Pin::new(delay).poll(cx));
+        }
+    }
+}
+
+/// When the packets of a flow are sent.
+#[derive(Clone, Copy, Debug)]
+pub enum Arrivals {
+    /// One packet every interval.
+    Constant(Duration),
+    /// Independent arrivals averaging one every interval, as many independent senders make.
+    Poisson(Duration),
+    /// Bursts lasting `on`, of a packet every `gap`, each followed by `off` of silence, as
+    /// voice with silence suppression, or video, sends.
+    OnOff {
+        on: Duration,
+        off: Duration,
+        gap: Duration,
+    },
+}
+
+/// The sizes of the packets of a flow, in bytes.
+#[derive(Clone, Copy, Debug)]
+pub enum PacketSizes {
+    Fixed(usize),
+    /// Sizes from `min` to `max`, inclusive, equally likely.
+    Uniform(usize, usize),
+    /// The simple IMIX, 7 packets of 40 bytes for every 4 of 576 and 1 of 1500, as IP packets
+    /// of the internet are, mostly ACKs and full size segments.
+    Imix,
+}
+
+/// A packet of generated traffic, with the flow it belongs to, its number in that flow, and its
+/// size in bytes, for a test to build a packet of, or for a link to account for directly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GeneratedPacket {
+    pub flow: usize,
+    pub seq: usize,
+    pub size: usize,
+}
+
+/// A kind of flow, such as bulk transfers or voice calls, for a `TrafficGenerator` to send
+/// `flows` of, each of `packets` packets.
+#[derive(Clone, Debug)]
+pub struct TrafficProfile {
+    arrivals: Arrivals,
+    sizes: PacketSizes,
+    flows: usize,
+    packets: usize,
+}
+
+impl TrafficProfile {
+    pub fn new(arrivals: Arrivals) -> Self {
+        TrafficProfile {
+            arrivals,
+            sizes: PacketSizes::Imix,
+            flows: 1,
+            packets: 100,
+        }
+    }
+
+    /// Changes sizes, the sizes of packets, default value is the IMIX.
+    pub fn sizes(self, sizes: PacketSizes) -> Self {
+        TrafficProfile {
+            arrivals: self.arrivals,
+            sizes,
+            flows: self.flows,
+            packets: self.packets,
+        }
+    }
+
+    /// Changes flows, the number of concurrent flows of the profile, default value is 1.
+    pub fn flows(self, flows: usize) -> Self {
+        TrafficProfile {
+            arrivals: self.arrivals,
+            sizes: self.sizes,
+            flows,
+            packets: self.packets,
+        }
+    }
+
+    /// Changes packets, the number of packets of each flow, default value is 100.
+    pub fn packets(self, packets: usize) -> Self {
+        TrafficProfile {
+            arrivals: self.arrivals,
+            sizes: self.sizes,
+            flows: self.flows,
+            packets,
+        }
+    }
+
+    fn size(&self, rng: &mut StdRng) -> usize {
+        match self.sizes {
+            PacketSizes::Fixed(size) => size,
+            PacketSizes::Uniform(min, max) => rng.gen_range(min, max + 1),
+            PacketSizes::Imix => match rng.gen_range(0, 12) {
+                0..=6 => 40,
+                7..=10 => 576,
+                _ => 1500,
+            },
+        }
+    }
+
+    /// When each packet of one flow is sent, from the start of the traffic. Flows start at a
+    /// random point of their first interval or burst, so those of one profile aren't in step.
+    fn offsets(&self, rng: &mut StdRng) -> Vec<Duration> {
+        match self.arrivals {
+            Arrivals::Constant(interval) => {
+                let phase = interval.mul_f64(rng.gen());
+                (0..self.packets)
+                    .map(|seq| phase + interval * seq as u32)
+                    .collect()
+            }
+            Arrivals::Poisson(mean) => {
+                let mut at = Duration::from_secs(0);
+                (0..self.packets)
+                    .map(|_| {
+                        // Exponential gaps, by inverting its distribution function.
+                        at += mean.mul_f64(-(1.0 - rng.gen::<f64>()).ln());
+                        at
+                    })
+                    .collect()
+            }
+            Arrivals::OnOff { on, off, gap } => {
+                let burst = ((on.as_nanos() / gap.as_nanos().max(1)) as usize).max(1);
+                let phase = (on + off).mul_f64(rng.gen());
+                (0..self.packets)
+                    .map(|seq| {
+                        phase + (on + off) * (seq / burst) as u32 + gap * (seq % burst) as u32
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Generates the traffic of a mix of `TrafficProfile`s, with rate limiters, queues and AQM in
+/// mind, which `immediate_stream` sends everything to at once. Flows are numbered in the order
+/// their profiles were added, and the traffic drawn from `seed`, so a test sees the same traffic
+/// each run.
+pub struct TrafficGenerator {
+    seed: u64,
+    profiles: Vec<TrafficProfile>,
+}
+
+impl TrafficGenerator {
+    pub fn new(seed: u64) -> Self {
+        TrafficGenerator {
+            seed,
+            profiles: vec![],
+        }
+    }
+
+    /// Adds the flows of `profile` to the mix.
+    pub fn profile(mut self, profile: TrafficProfile) -> Self {
+        self.profiles.push(profile);
+        self
+    }
+
+    /// Every packet of the traffic, with when it is sent from the start, in the order sent.
+    pub fn schedule(&self) -> Vec<(Duration, GeneratedPacket)> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut schedule = vec![];
+        let mut flow = 0;
+        for profile in &self.profiles {
+            for _ in 0..profile.flows {
+                for (seq, at) in profile.offsets(&mut rng).into_iter().enumerate() {
+                    let size = profile.size(&mut rng);
+                    schedule.push((at, GeneratedPacket { flow, seq, size }));
+                }
+                flow += 1;
+            }
+        }
+        // Stable, so packets sent at once stay in the order of their flows.
+        schedule.sort_by_key(|(at, _)| *at);
+        schedule
+    }
+
+    /// Sends the traffic as scheduled, from the first poll of the stream. Packets are sent as
+    /// soon as their time has passed, so those closer together than the timer can wake are sent
+    /// together, as a NIC's receive queue would hand them over, rather than late.
+    pub fn stream(&self) -> PacketStream<GeneratedPacket> {
+        Box::new(TrafficStream {
+            schedule: self.schedule().into_iter().collect(),
+            start: None,
+            delay: None,
+        })
+    }
+}
+
+struct TrafficStream {
+    schedule: VecDeque<(Duration, GeneratedPacket)>,
+    start: Option<Instant>,
+    delay: Option<Delay>,
+}
+
+impl Unpin for TrafficStream {}
+
+impl Stream for TrafficStream {
+    type Item = GeneratedPacket;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        loop {
+            let at = match self.schedule.front() {
+                Some((at, _)) => start + *at,
+                None => return Poll::Ready(None),
+            };
+            if at <= Instant::now() {
+                self.delay = None;
+                return Poll::Ready(self.schedule.pop_front().map(|(_, packet)| packet));
+            }
+            let delay = self.delay.get_or_insert_with(|| delay_until(at));
+            if delay.deadline() != at {
+                delay.reset(at);
+            }
+            ready!(Pin::new(delay).poll(cx));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::JoinLink;
+    use crate::link::LinkBuilder;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+
+    fn mix(seed: u64) -> TrafficGenerator {
+        TrafficGenerator::new(seed)
+            .profile(
+                TrafficProfile::new(Arrivals::Poisson(Duration::from_millis(1)))
+                    .flows(3)
+                    .packets(1000),
+            )
+            .profile(
+                TrafficProfile::new(Arrivals::OnOff {
+                    on: Duration::from_millis(20),
+                    off: Duration::from_millis(80),
+                    gap: Duration::from_millis(5),
+                })
+                .sizes(PacketSizes::Fixed(160))
+                .packets(8),
+            )
+    }
+
+    #[test]
+    fn same_seed_same_traffic() {
+        let schedule = mix(7).schedule();
+        assert_eq!(schedule, mix(7).schedule());
+        assert_ne!(schedule, mix(8).schedule());
+        assert_eq!(schedule.len(), 3008);
+        assert!(schedule.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    }
+
+    #[test]
+    fn profiles_shape_traffic() {
+        let schedule = mix(7).schedule();
+
+        let poisson: Vec<&(Duration, GeneratedPacket)> =
+            schedule.iter().filter(|(_, p)| p.flow == 0).collect();
+        // 1000 gaps averaging 1ms end close to 1s, well within 10% for any seed.
+        let end = poisson.last().unwrap().0.as_secs_f64();
+        assert!(end > 0.9 && end < 1.1, "{}", end);
+        let small = poisson.iter().filter(|(_, p)| p.size == 40).count();
+        assert!(small > 500 && small < 660, "{}", small);
+
+        let bursts: Vec<Duration> = schedule
+            .iter()
+            .filter(|(_, p)| p.flow == 3)
+            .map(|(at, p)| {
+                assert_eq!(p.size, 160);
+                *at
+            })
+            .collect();
+        // Bursts of 4 packets 5ms apart, the next 100ms after the first began.
+        assert_eq!(bursts[3] - bursts[0], Duration::from_millis(15));
+        assert_eq!(bursts[4] - bursts[0], Duration::from_millis(100));
+    }
+
+    #[test]
+    fn streams_on_schedule() {
+        let traffic = TrafficGenerator::new(1)
+            .profile(TrafficProfile::new(Arrivals::Constant(Duration::from_millis(10))).packets(5));
+        let last = traffic.schedule().last().unwrap().0;
+
+        let mut runtime = initialize_runtime();
+        let (results, elapsed) = runtime.block_on(async {
+            let started = Instant::now();
+            let link = JoinLink::new()
+                .ingressors(vec![traffic.stream()])
+                .build_link();
+            let results = run_link(link).await;
+            (results, started.elapsed())
+        });
+        assert_eq!(results[0].len(), 5);
+        assert!(elapsed >= last);
+    }
+
+    #[test]
+    fn virtual_intervals_send_what_is_due_together() {
+        let mut runtime = initialize_runtime();
+        let (results, elapsed) = runtime.block_on(async {
+            let started = Instant::now();
+            let link = JoinLink::new()
+                .ingressors(vec![Box::new(VirtualIntervalGenerator::new(
+                    Duration::from_micros(100),
+                    0..50,
+                )) as PacketStream<i32>])
+                .build_link();
+            let results = run_link(link).await;
+            (results, started.elapsed())
+        });
+        assert_eq!(results[0], (0..50).collect::<Vec<_>>());
+        // One timer per packet would take at least 50ms.
+        assert!(elapsed >= Duration::from_micros(4900) && elapsed < Duration::from_millis(40));
+    }
+}
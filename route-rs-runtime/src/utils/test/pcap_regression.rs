@@ -0,0 +1,410 @@
+use crate::link::primitive::Capture;
+use crate::link::LinkBuilder;
+use crate::utils::pcap::{read_pcap, write_pcap, LINKTYPE_ETHERNET, LINKTYPE_IPV4, LINKTYPE_IPV6};
+use crate::utils::test::harness::{initialize_runtime, run_link};
+use crate::utils::test::packet_generators::immediate_stream;
+use route_rs_packets::EthernetFrame;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// A field left out of comparisons with the goldens, for fields a link is free to set as it
+/// likes, or that change with every run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mask {
+    Ipv4Checksum,
+    Ipv4Ttl,
+    Ipv4Identification,
+    Ipv6HopLimit,
+    /// The checksum of a TCP, UDP, ICMP or ICMPv6 header following the IP header.
+    TransportChecksum,
+    /// `len` bytes at an `offset` from the start of the captured packet.
+    Bytes(usize, usize),
+}
+
+impl Mask {
+    /// The range of `packet`, captured with `link_type`, the mask covers, if it has the field.
+    fn range(self, link_type: u32, packet: &[u8]) -> Option<(usize, usize)> {
+        if let Mask::Bytes(offset, len) = self {
+            return Some((offset, len));
+        }
+        let ip = match link_type {
+            LINKTYPE_ETHERNET => {
+                let frame = EthernetFrame::from_buffer(packet.to_vec(), 0).ok()?;
+                frame.payload_offset
+            }
+            LINKTYPE_IPV4 | LINKTYPE_IPV6 => 0,
+            _ => return None,
+        };
+        let version = packet.get(ip)? >> 4;
+        let (transport, protocol) = match version {
+            4 => (
+                ip + usize::from(packet[ip] & 0x0f) * 4,
+                *packet.get(ip + 9)?,
+            ),
+            6 => (ip + 40, *packet.get(ip + 6)?),
+            _ => return None,
+        };
+        match (self, version) {
+            (Mask::Ipv4Checksum, 4) => Some((ip + 10, 2)),
+            (Mask::Ipv4Ttl, 4) => Some((ip + 8, 1)),
+            (Mask::Ipv4Identification, 4) => Some((ip + 4, 2)),
+            (Mask::Ipv6HopLimit, 6) => Some((ip + 7, 1)),
+            (Mask::TransportChecksum, _) => match protocol {
+                6 => Some((transport + 16, 2)),
+                17 => Some((transport + 6, 2)),
+                1 | 58 => Some((transport + 2, 2)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// How a packet a link sent differs from its golden.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PacketDiff {
+    /// The golden has a packet the link didn't send.
+    Missing { egressor: usize, index: usize },
+    /// The link sent a packet past the last of the golden.
+    Unexpected { egressor: usize, index: usize },
+    /// The packet's length, and its bytes that differ, with the link's value then the golden's,
+    /// after masking.
+    Differs {
+        egressor: usize,
+        index: usize,
+        len: (usize, usize),
+        bytes: Vec<(usize, u8, u8)>,
+    },
+}
+
+impl fmt::Display for PacketDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PacketDiff::Missing { egressor, index } => {
+                write!(f, "egressor {}, packet {}: missing", egressor, index)
+            }
+            PacketDiff::Unexpected { egressor, index } => {
+                write!(f, "egressor {}, packet {}: unexpected", egressor, index)
+            }
+            PacketDiff::Differs {
+                egressor,
+                index,
+                len,
+                bytes,
+            } => {
+                write!(f, "egressor {}, packet {}:", egressor, index)?;
+                if len.0 != len.1 {
+                    write!(f, " {} bytes, expected {};", len.0, len.1)?;
+                }
+                for (offset, actual, expected) in bytes {
+                    write!(
+                        f,
+                        " byte {} is {:#04x}, expected {:#04x};",
+                        offset, actual, expected
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Replays the packets of an input pcap through a link, and compares what leaves each of its
+/// egressors with a golden pcap of what should, packet by packet and byte by byte, but for the
+/// fields masked. This is how composites are tested against real captures, a capture of the
+/// traffic they should handle as input, and of what they should make of it as the goldens.
+///
+/// Goldens are written by `record`, to be checked by eye once, such as in Wireshark, and then
+/// kept with the test. A change to a link that changes what it sends fails `check` with a list of
+/// every packet that differs, and once the change is known to be right, `record` writes the new
+/// goldens. Packets are sent in the order of the input, as fast as the link takes them, so links
+/// that depend on time between packets are better tested otherwise.
+pub struct PcapRegression {
+    input: PathBuf,
+    goldens: Vec<PathBuf>,
+    masks: Vec<Mask>,
+}
+
+impl PcapRegression {
+    pub fn new<P: AsRef<Path>>(input: P) -> Self {
+        PcapRegression {
+            input: input.as_ref().to_path_buf(),
+            goldens: vec![],
+            masks: vec![],
+        }
+    }
+
+    /// Adds the golden of the next egressor of the link.
+    pub fn golden<P: AsRef<Path>>(mut self, golden: P) -> Self {
+        self.goldens.push(golden.as_ref().to_path_buf());
+        self
+    }
+
+    /// Leaves a field out of comparisons.
+    pub fn mask(mut self, mask: Mask) -> Self {
+        self.masks.push(mask);
+        self
+    }
+
+    /// Builds a link from `builder` with the packets of the input as its ingressor, runs it to the
+    /// end, and returns the packets of each egressor.
+    fn replay<I, O, B>(&self, builder: B) -> Vec<Vec<O>>
+    where
+        I: Capture + Send + 'static,
+        O: Capture + fmt::Debug + Send + Clone + 'static,
+        B: LinkBuilder<I, O>,
+    {
+        let (link_type, records) = read_pcap(&self.input)
+            .unwrap_or_else(|err| panic!("could not read {}: {}", self.input.display(), err));
+        assert_eq!(
+            link_type,
+            I::LINK_TYPE,
+            "{} has link type {}, but the link takes {}",
+            self.input.display(),
+            link_type,
+            I::LINK_TYPE
+        );
+        let packets: Vec<I> = records
+            .into_iter()
+            .enumerate()
+            .map(|(index, (_, data))| {
+                I::from_capture(data).unwrap_or_else(|| {
+                    panic!("packet {} of {} doesn't parse", index, self.input.display())
+                })
+            })
+            .collect();
+
+        let link = builder
+            .ingressors(vec![immediate_stream(packets)])
+            .build_link();
+        assert_eq!(
+            link.1.len(),
+            self.goldens.len(),
+            "link has {} egressors, but {} goldens were given",
+            link.1.len(),
+            self.goldens.len()
+        );
+        initialize_runtime().block_on(run_link(link))
+    }
+
+    /// Compares what the link built from `builder` sends with the goldens, returning how each
+    /// packet that doesn't match differs.
+    pub fn diff<I, O, B>(&self, builder: B) -> Vec<PacketDiff>
+    where
+        I: Capture + Send + 'static,
+        O: Capture + fmt::Debug + Send + Clone + 'static,
+        B: LinkBuilder<I, O>,
+    {
+        let mut diffs = vec![];
+        for (egressor, (packets, golden)) in
+            self.replay(builder).iter().zip(&self.goldens).enumerate()
+        {
+            let (link_type, expected) = read_pcap(golden)
+                .unwrap_or_else(|err| panic!("could not read {}: {}", golden.display(), err));
+            assert_eq!(
+                link_type,
+                O::LINK_TYPE,
+                "{} has link type {}, but the link sends {}",
+                golden.display(),
+                link_type,
+                O::LINK_TYPE
+            );
+            for index in 0..packets.len().max(expected.len()) {
+                match (packets.get(index), expected.get(index)) {
+                    (Some(packet), Some((_, expected))) => {
+                        let actual = self.masked(link_type, packet.capture());
+                        let expected = self.masked(link_type, expected);
+                        let bytes: Vec<(usize, u8, u8)> = actual
+                            .iter()
+                            .zip(&expected)
+                            .enumerate()
+                            .filter(|(_, (actual, expected))| actual != expected)
+                            .map(|(offset, (actual, expected))| (offset, *actual, *expected))
+                            .collect();
+                        if !bytes.is_empty() || actual.len() != expected.len() {
+                            diffs.push(PacketDiff::Differs {
+                                egressor,
+                                index,
+                                len: (actual.len(), expected.len()),
+                                bytes,
+                            });
+                        }
+                    }
+                    (None, _) => diffs.push(PacketDiff::Missing { egressor, index }),
+                    (_, None) => diffs.push(PacketDiff::Unexpected { egressor, index }),
+                }
+            }
+        }
+        diffs
+    }
+
+    /// Panics, listing how each packet differs, unless what the link built from `builder` sends
+    /// matches the goldens.
+    pub fn check<I, O, B>(&self, builder: B)
+    where
+        I: Capture + Send + 'static,
+        O: Capture + fmt::Debug + Send + Clone + 'static,
+        B: LinkBuilder<I, O>,
+    {
+        let diffs = self.diff(builder);
+        if !diffs.is_empty() {
+            let report: Vec<String> = diffs.iter().map(PacketDiff::to_string).collect();
+            panic!(
+                "{} packets differ from the goldens of {}:\n{}",
+                diffs.len(),
+                self.input.display(),
+                report.join("\n")
+            );
+        }
+    }
+
+    /// Writes what the link built from `builder` sends as the goldens.
+    pub fn record<I, O, B>(&self, builder: B)
+    where
+        I: Capture + Send + 'static,
+        O: Capture + fmt::Debug + Send + Clone + 'static,
+        B: LinkBuilder<I, O>,
+    {
+        for (packets, golden) in self.replay(builder).iter().zip(&self.goldens) {
+            // Goldens have no times of their own, so they only change when their packets do.
+            let records: Vec<_> = packets
+                .iter()
+                .map(|packet| (UNIX_EPOCH, packet.capture().to_vec()))
+                .collect();
+            write_pcap(golden, O::LINK_TYPE, &records)
+                .unwrap_or_else(|err| panic!("could not write {}: {}", golden.display(), err));
+        }
+    }
+
+    fn masked(&self, link_type: u32, packet: &[u8]) -> Vec<u8> {
+        let mut packet = packet.to_vec();
+        for mask in &self.masks {
+            if let Some((offset, len)) = mask.range(link_type, &packet) {
+                let end = (offset + len).min(packet.len());
+                for byte in packet.iter_mut().take(end).skip(offset) {
+                    *byte = 0;
+                }
+            }
+        }
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::DecIpv4HopLimit;
+    use route_rs_packets::Ipv4Packet;
+    use std::net::Ipv4Addr;
+    use uuid::Uuid;
+
+    fn packet(n: u8, ttl: u8) -> Vec<u8> {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, n));
+        packet.set_ttl(ttl);
+        packet.set_checksum();
+        packet.data
+    }
+
+    fn write(path: &Path, packets: Vec<Vec<u8>>) {
+        let records: Vec<_> = packets.into_iter().map(|data| (UNIX_EPOCH, data)).collect();
+        write_pcap(path, LINKTYPE_IPV4, &records).unwrap();
+    }
+
+    fn paths() -> (PathBuf, PathBuf) {
+        let name = Uuid::new_v4();
+        let directory = std::env::temp_dir();
+        (
+            directory.join(format!("input-{}.pcap", name)),
+            directory.join(format!("golden-{}.pcap", name)),
+        )
+    }
+
+    fn dec_ttl() -> ProcessLink<DecIpv4HopLimit> {
+        ProcessLink::new().processor(DecIpv4HopLimit::new())
+    }
+
+    #[test]
+    fn matches_goldens_but_for_masks() {
+        let (input, golden) = paths();
+        write(&input, vec![packet(1, 64), packet(2, 1)]);
+        // The hop limit is decremented without updating the checksum.
+        write(&golden, vec![packet(1, 63), packet(2, 0)]);
+
+        PcapRegression::new(&input)
+            .golden(&golden)
+            .mask(Mask::Ipv4Checksum)
+            .check(dec_ttl());
+
+        let diffs = PcapRegression::new(&input).golden(&golden).diff(dec_ttl());
+        assert_eq!(diffs.len(), 2);
+        for (index, diff) in diffs.into_iter().enumerate() {
+            match diff {
+                PacketDiff::Differs {
+                    egressor: 0,
+                    index: at,
+                    len: (20, 20),
+                    bytes,
+                } => {
+                    assert_eq!(at, index);
+                    assert!(!bytes.is_empty());
+                    assert!(bytes
+                        .iter()
+                        .all(|(offset, _, _)| *offset == 10 || *offset == 11));
+                }
+                diff => panic!("unexpected diff {:?}", diff),
+            }
+        }
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&golden).unwrap();
+    }
+
+    #[test]
+    fn reports_missing_and_unexpected_packets() {
+        let (input, golden) = paths();
+        write(&input, vec![packet(1, 64)]);
+        write(&golden, vec![packet(1, 63), packet(2, 63)]);
+        let regression = PcapRegression::new(&input)
+            .golden(&golden)
+            .mask(Mask::Ipv4Checksum)
+            .mask(Mask::Ipv4Ttl);
+        assert_eq!(
+            regression.diff(dec_ttl()),
+            vec![PacketDiff::Missing {
+                egressor: 0,
+                index: 1
+            }]
+        );
+
+        write(&input, vec![packet(1, 64), packet(2, 64), packet(3, 64)]);
+        let diffs = regression.diff(dec_ttl());
+        assert_eq!(
+            diffs,
+            vec![PacketDiff::Unexpected {
+                egressor: 0,
+                index: 2
+            }]
+        );
+        assert_eq!(diffs[0].to_string(), "egressor 0, packet 2: unexpected");
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&golden).unwrap();
+    }
+
+    #[test]
+    fn checks_what_it_records() {
+        let (input, golden) = paths();
+        write(&input, vec![packet(1, 64), packet(2, 5)]);
+        let regression = PcapRegression::new(&input).golden(&golden);
+        regression.record(dec_ttl());
+        regression.check(dec_ttl());
+
+        let (_, records) = read_pcap(&golden).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].1[8], 4);
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&golden).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+use crate::link::{LinkBuilder, PacketStream};
+use crate::utils::test::harness::{initialize_runtime, run_link};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::pin::Pin;
+
+/// The most times a scheduled stream is pending before one packet.
+const MAX_PAUSES: usize = 8;
+
+/// The packets of a stream, each with the number of times the stream is pending before sending it.
+/// A stream that is pending wakes itself at once, so the pauses shift where its packets fall among
+/// those of other streams, and where the link's tasks park, without taking any real time.
+pub type Schedule<T> = Vec<(usize, T)>;
+
+/// A packet of one flow, sent on one ingressor, numbered in the order it was sent there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowPacket {
+    pub ingressor: usize,
+    pub flow: u8,
+    pub seq: usize,
+}
+
+/// What a link must keep of the packets it is sent, whatever their interleaving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invariant {
+    /// Every packet sent leaves the link once, on one of its egressors.
+    Conservation,
+    /// The packets of each flow leave each egressor in the order they were sent on their ingressor.
+    FlowOrder,
+    /// No packet leaves one egressor more than once, and none leaves that wasn't sent.
+    NoDuplication,
+}
+
+/// Schedules of up to `max_len` packets from `packet`.
+pub fn schedule<T: std::fmt::Debug>(
+    packet: impl Strategy<Value = T>,
+    max_len: usize,
+) -> impl Strategy<Value = Schedule<T>> {
+    vec((0..=MAX_PAUSES, packet), 0..=max_len)
+}
+
+/// Schedules for 1 to `max_ingressors` ingressors, of up to `max_len` packets each, of up to
+/// `flows` flows, numbered as they are sent.
+pub fn flow_schedules(
+    max_ingressors: usize,
+    flows: u8,
+    max_len: usize,
+) -> impl Strategy<Value = Vec<Schedule<FlowPacket>>> {
+    vec(schedule(0..flows, max_len), 1..=max_ingressors).prop_map(|schedules| {
+        schedules
+            .into_iter()
+            .enumerate()
+            .map(|(ingressor, schedule)| {
+                schedule
+                    .into_iter()
+                    .enumerate()
+                    .map(|(seq, (pauses, flow))| {
+                        (
+                            pauses,
+                            FlowPacket {
+                                ingressor,
+                                flow,
+                                seq,
+                            },
+                        )
+                    })
+                    .collect()
+            })
+            .collect()
+    })
+}
+
+/// Sends the packets of `schedule`, pausing before each as it says.
+pub fn scheduled_stream<T: Send + 'static>(schedule: Schedule<T>) -> PacketStream<T> {
+    Box::new(ScheduledStream {
+        schedule: schedule.into_iter().collect(),
+    })
+}
+
+struct ScheduledStream<T> {
+    schedule: VecDeque<(usize, T)>,
+}
+
+impl<T> Unpin for ScheduledStream<T> {}
+
+impl<T> Stream for ScheduledStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        match self.schedule.front_mut() {
+            Some((pauses, _)) if *pauses > 0 => {
+                *pauses -= 1;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Some(_) => Poll::Ready(self.schedule.pop_front().map(|(_, packet)| packet)),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+/// Builds a link from `builder` with an ingressor for each of `schedules`, runs it to the end, and
+/// checks what left it against `invariants`, for use in a `proptest!`. The link must end once its
+/// ingressors have, as `run_link` expects.
+pub fn check_link<B>(
+    builder: B,
+    schedules: Vec<Schedule<FlowPacket>>,
+    invariants: &[Invariant],
+) -> Result<(), TestCaseError>
+where
+    B: LinkBuilder<FlowPacket, FlowPacket>,
+{
+    let sent: HashSet<FlowPacket> = schedules
+        .iter()
+        .flatten()
+        .map(|(_, packet)| *packet)
+        .collect();
+    let link = builder
+        .ingressors(schedules.into_iter().map(scheduled_stream).collect())
+        .build_link();
+    let mut runtime = initialize_runtime();
+    let egressors = runtime.block_on(run_link(link));
+
+    for invariant in invariants {
+        match invariant {
+            Invariant::Conservation => {
+                let mut left: Vec<FlowPacket> = egressors.iter().flatten().cloned().collect();
+                prop_assert_eq!(left.len(), sent.len(), "packets were lost or duplicated");
+                left.retain(|packet| !sent.contains(packet));
+                prop_assert!(left.is_empty(), "packets were never sent: {:?}", left);
+                let distinct: HashSet<&FlowPacket> = egressors.iter().flatten().collect();
+                prop_assert_eq!(distinct.len(), sent.len(), "packets were duplicated");
+            }
+            Invariant::FlowOrder => {
+                for (port, egressor) in egressors.iter().enumerate() {
+                    let mut last: HashMap<(usize, u8), usize> = HashMap::new();
+                    for packet in egressor {
+                        if let Some(seq) = last.insert((packet.ingressor, packet.flow), packet.seq)
+                        {
+                            prop_assert!(
+                                seq < packet.seq,
+                                "egressor {} sent {:?} after seq {}",
+                                port,
+                                packet,
+                                seq
+                            );
+                        }
+                    }
+                }
+            }
+            Invariant::NoDuplication => {
+                for (port, egressor) in egressors.iter().enumerate() {
+                    let mut seen = HashSet::new();
+                    for packet in egressor {
+                        prop_assert!(
+                            sent.contains(packet),
+                            "egressor {} sent {:?}, which was never sent",
+                            port,
+                            packet
+                        );
+                        prop_assert!(
+                            seen.insert(packet),
+                            "egressor {} sent {:?} twice",
+                            port,
+                            packet
+                        );
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::FnClassifier;
+    use crate::link::primitive::{ClassifyLink, ForkLink, JoinLink, QueueLink};
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::Identity;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn join_link_keeps_every_packet(schedules in flow_schedules(4, 4, 32)) {
+            check_link(
+                JoinLink::new().queue_capacity(2),
+                schedules,
+                &[Invariant::Conservation, Invariant::FlowOrder],
+            )?;
+        }
+
+        #[test]
+        fn queue_link_keeps_every_packet(schedules in flow_schedules(1, 4, 64)) {
+            check_link(
+                QueueLink::new().processor(Identity::new()).queue_capacity(1),
+                schedules,
+                &[Invariant::Conservation, Invariant::FlowOrder],
+            )?;
+        }
+
+        #[test]
+        fn classify_link_keeps_every_packet(schedules in flow_schedules(1, 6, 64)) {
+            check_link(
+                ClassifyLink::new()
+                    .classifier(FnClassifier::new(|packet: &FlowPacket| packet.flow % 3))
+                    .dispatcher(Box::new(usize::from))
+                    .num_egressors(3)
+                    .queue_capacity(1),
+                schedules,
+                &[Invariant::Conservation, Invariant::FlowOrder],
+            )?;
+        }
+
+        #[test]
+        fn fork_link_copies_every_packet_once(schedules in flow_schedules(1, 4, 64)) {
+            check_link(
+                ForkLink::new().num_egressors(3).queue_capacity(1),
+                schedules,
+                &[Invariant::NoDuplication, Invariant::FlowOrder],
+            )?;
+        }
+    }
+}